@@ -33,6 +33,7 @@ pub mod message_aggregator;
 pub mod mock_roles;
 pub mod sniffer;
 pub mod sniffer_error;
+pub mod sv1_miner;
 pub mod sv1_minerd;
 pub mod sv1_sniffer;
 pub mod template_provider;
@@ -111,6 +112,21 @@ pub async fn start_pool(
     supported_extensions: Vec<u16>,
     required_extensions: Vec<u16>,
 ) -> (PoolSv2, SocketAddr) {
+    let config = pool_config(
+        template_provider_config,
+        supported_extensions,
+        required_extensions,
+    );
+    start_pool_with_config(config).await
+}
+
+/// Builds the `PoolConfig` used by [`start_pool`], so tests can tweak optional settings before
+/// starting the pool with [`start_pool_with_config`].
+pub fn pool_config(
+    template_provider_config: TemplateProviderType,
+    supported_extensions: Vec<u16>,
+    required_extensions: Vec<u16>,
+) -> pool_sv2::config::PoolConfig {
     use pool_sv2::config::PoolConfig;
     let listening_address = get_available_address();
     let authority_public_key = Secp256k1PublicKey::try_from(
@@ -133,7 +149,7 @@ pub async fn start_pool(
     let authority_config =
         pool_sv2::config::AuthorityConfig::new(authority_public_key, authority_secret_key);
     let share_batch_size = 1;
-    let config = PoolConfig::new(
+    PoolConfig::new(
         connection_config,
         template_provider_config,
         authority_config,
@@ -143,7 +159,11 @@ pub async fn start_pool(
         1,
         supported_extensions,
         required_extensions,
-    );
+    )
+}
+
+pub async fn start_pool_with_config(config: pool_sv2::config::PoolConfig) -> (PoolSv2, SocketAddr) {
+    let listening_address = *config.listen_address();
     let pool = PoolSv2::new(config);
     let pool_clone = pool.clone();
     tokio::spawn(async move {
@@ -177,6 +197,20 @@ pub fn start_jdc(
     supported_extensions: Vec<u16>,
     required_extensions: Vec<u16>,
 ) -> (JobDeclaratorClient, SocketAddr) {
+    start_jdc_with_config(jdc_config(
+        pool,
+        template_provider_config,
+        supported_extensions,
+        required_extensions,
+    ))
+}
+
+pub fn jdc_config(
+    pool: &[(SocketAddr, SocketAddr)], // (pool_address, jds_address)
+    template_provider_config: TemplateProviderType,
+    supported_extensions: Vec<u16>,
+    required_extensions: Vec<u16>,
+) -> jd_client_sv2::config::JobDeclaratorClientConfig {
     use jd_client_sv2::config::{JobDeclaratorClientConfig, PoolConfig, ProtocolConfig, Upstream};
     let jdc_address = get_available_address();
     let max_supported_version = 2;
@@ -217,7 +251,7 @@ pub fn start_jdc(
     let shares_batch_size = 1;
     let user_identity = "IT-test".to_string();
     let jdc_signature = "JDC".to_string();
-    let jd_client_proxy = JobDeclaratorClientConfig::new(
+    JobDeclaratorClientConfig::new(
         jdc_address,
         protocol_config,
         user_identity,
@@ -231,8 +265,14 @@ pub fn start_jdc(
         None,
         supported_extensions,
         required_extensions,
-    );
-    let ret = jd_client_sv2::JobDeclaratorClient::new(jd_client_proxy);
+    )
+}
+
+pub fn start_jdc_with_config(
+    config: jd_client_sv2::config::JobDeclaratorClientConfig,
+) -> (JobDeclaratorClient, SocketAddr) {
+    let jdc_address = *config.listening_address();
+    let ret = jd_client_sv2::JobDeclaratorClient::new(config);
     let ret_clone = ret.clone();
     tokio::spawn(async move { ret_clone.start().await });
     (ret, jdc_address)
@@ -293,6 +333,53 @@ pub async fn start_sv2_translator(
     required_extensions: Vec<u16>,
     job_keepalive_interval_secs: Option<u16>,
 ) -> (TranslatorSv2, SocketAddr) {
+    let config = sv2_translator_config(
+        upstreams,
+        aggregate_channels,
+        supported_extensions,
+        required_extensions,
+        job_keepalive_interval_secs,
+    )
+    .await;
+    start_sv2_translator_with_config(config)
+}
+
+/// Builds the `TranslatorConfig` used by [`start_sv2_translator`], so tests can tweak optional
+/// settings before starting the translator with [`start_sv2_translator_with_config`].
+pub async fn sv2_translator_config(
+    upstreams: &[SocketAddr],
+    aggregate_channels: bool,
+    supported_extensions: Vec<u16>,
+    required_extensions: Vec<u16>,
+    job_keepalive_interval_secs: Option<u16>,
+) -> translator_sv2::config::TranslatorConfig {
+    let minerd_process = MinerdProcess::new(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)), false)
+        .await
+        .unwrap();
+    let min_individual_miner_hashrate = minerd_process.measure_hashrate().await.unwrap() as f32;
+
+    sv2_translator_config_with_hashrate(
+        upstreams,
+        aggregate_channels,
+        supported_extensions,
+        required_extensions,
+        job_keepalive_interval_secs,
+        min_individual_miner_hashrate,
+    )
+}
+
+/// Builds the `TranslatorConfig` used by [`sv2_translator_config`] for miners of the given
+/// hashrate, without measuring the hashrate of `minerd`.
+///
+/// Meant for tests driving the translator with an in-process [`sv1_miner::MockSv1Miner`].
+pub fn sv2_translator_config_with_hashrate(
+    upstreams: &[SocketAddr],
+    aggregate_channels: bool,
+    supported_extensions: Vec<u16>,
+    required_extensions: Vec<u16>,
+    job_keepalive_interval_secs: Option<u16>,
+    min_individual_miner_hashrate: f32,
+) -> translator_sv2::config::TranslatorConfig {
     let job_keepalive_interval_secs = job_keepalive_interval_secs.unwrap_or(60);
     let upstreams = upstreams
         .iter()
@@ -315,11 +402,6 @@ pub async fn start_sv2_translator(
     let listening_address = get_available_address();
     let listening_port = listening_address.port();
 
-    let minerd_process = MinerdProcess::new(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)), false)
-        .await
-        .unwrap();
-    let min_individual_miner_hashrate = minerd_process.measure_hashrate().await.unwrap() as f32;
-
     let downstream_difficulty_config = translator_sv2::config::DownstreamDifficultyConfig::new(
         min_individual_miner_hashrate,
         SHARES_PER_MINUTE,
@@ -329,7 +411,7 @@ pub async fn start_sv2_translator(
 
     let downstream_extranonce2_size = 4;
 
-    let config = translator_sv2::config::TranslatorConfig::new(
+    translator_sv2::config::TranslatorConfig::new(
         upstreams,
         listening_address.ip().to_string(),
         listening_port,
@@ -341,6 +423,18 @@ pub async fn start_sv2_translator(
         aggregate_channels,
         supported_extensions,
         required_extensions,
+    )
+}
+
+pub fn start_sv2_translator_with_config(
+    config: translator_sv2::config::TranslatorConfig,
+) -> (TranslatorSv2, SocketAddr) {
+    let listening_address = SocketAddr::new(
+        config
+            .downstream_address
+            .parse()
+            .expect("Invalid downstream address"),
+        config.downstream_port,
     );
     let translator_v2 = translator_sv2::TranslatorSv2::new(config);
     let clone_translator_v2 = translator_v2.clone();
@@ -0,0 +1,303 @@
+//! In-process SV1 mining client.
+//!
+//! [`MockSv1Miner`] connects to an SV1 server such as the translator, performs the
+//! `mining.subscribe` and `mining.authorize` handshake, and submits shares found by grinding the
+//! nonce of the latest job against the latest difficulty. Tests can exercise the share flow
+//! without spawning `minerd`, and decide how many shares are submitted and how fast.
+use corepc_node::serde_json::{self, Value};
+use std::{net::SocketAddr, time::Duration};
+use stratum_apps::{
+    network_helpers::sv1_connection::ConnectionSV1,
+    stratum_core::{
+        bitcoin::Target,
+        sv1_api::{client_to_server, json_rpc, server_to_client::Notify},
+    },
+};
+use tokio::net::TcpStream;
+use translator_sv2::utils::validate_sv1_share;
+
+/// Number of nonces tried for a share before giving up on the current job.
+const MAX_NONCES_PER_SHARE: u32 = 10_000_000;
+
+/// An SV1 mining client running in the test process.
+pub struct MockSv1Miner {
+    connection: ConnectionSV1,
+    user_name: String,
+    next_request_id: u64,
+    extranonce1: Vec<u8>,
+    extranonce2_size: usize,
+    // Session id the server handed out as the id of the `mining.notify` subscription
+    session_id: Option<String>,
+    difficulty: Option<f64>,
+    job: Option<Notify<'static>>,
+    next_nonce: u32,
+}
+
+impl MockSv1Miner {
+    /// Connects to the SV1 server at `upstream_address` and authorizes `user_name`.
+    ///
+    /// Retries the connection until the server listens.
+    pub async fn connect(upstream_address: SocketAddr, user_name: &str) -> Self {
+        Self::connect_with_session_id(upstream_address, user_name, None).await
+    }
+
+    /// Connects like [`MockSv1Miner::connect`], presenting `session_id` in `mining.subscribe` to
+    /// resume a previous session.
+    pub async fn connect_with_session_id(
+        upstream_address: SocketAddr,
+        user_name: &str,
+        session_id: Option<&str>,
+    ) -> Self {
+        Self::connect_inner(upstream_address, user_name, session_id, false).await
+    }
+
+    /// Connects like [`MockSv1Miner::connect`], sending `mining.extranonce.subscribe` before
+    /// `mining.authorize` so the server can move the miner onto another extranonce1 with
+    /// `mining.set_extranonce`.
+    pub async fn connect_with_extranonce_subscribe(
+        upstream_address: SocketAddr,
+        user_name: &str,
+    ) -> Self {
+        Self::connect_inner(upstream_address, user_name, None, true).await
+    }
+
+    // Connects and performs the handshake, subscribing to `mining.set_extranonce` if
+    // `extranonce_subscribe`.
+    async fn connect_inner(
+        upstream_address: SocketAddr,
+        user_name: &str,
+        session_id: Option<&str>,
+        extranonce_subscribe: bool,
+    ) -> Self {
+        let stream = loop {
+            match TcpStream::connect(upstream_address).await {
+                Ok(stream) => break stream,
+                Err(_) => {
+                    tracing::warn!(
+                        "MockSv1Miner: unable to connect to upstream, retrying after 1 second"
+                    );
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        };
+        let mut miner = Self {
+            connection: ConnectionSV1::new(stream).await,
+            user_name: user_name.to_string(),
+            next_request_id: 1,
+            extranonce1: Vec::new(),
+            extranonce2_size: 0,
+            session_id: None,
+            difficulty: None,
+            job: None,
+            next_nonce: 0,
+        };
+
+        let subscribe_params = match session_id {
+            Some(session_id) => serde_json::json!(["mock-sv1-miner/1.0", session_id]),
+            None => serde_json::json!(["mock-sv1-miner/1.0"]),
+        };
+        let subscribe = miner
+            .request("mining.subscribe", subscribe_params)
+            .await
+            .expect("mining.subscribe was rejected");
+        // [subscriptions, extranonce1, extranonce2_size]
+        miner.session_id = subscribe[0].as_array().and_then(|subscriptions| {
+            subscriptions
+                .iter()
+                .filter(|subscription| subscription[0] == "mining.notify")
+                .find_map(|subscription| subscription[1].as_str().map(str::to_string))
+        });
+        miner.extranonce1 = hex::decode(
+            subscribe[1]
+                .as_str()
+                .expect("mining.subscribe response without extranonce1"),
+        )
+        .expect("Invalid extranonce1");
+        miner.extranonce2_size = subscribe[2]
+            .as_u64()
+            .expect("mining.subscribe response without extranonce2 size")
+            as usize;
+
+        if extranonce_subscribe {
+            miner
+                .request("mining.extranonce.subscribe", serde_json::json!([]))
+                .await
+                .expect("mining.extranonce.subscribe was rejected");
+        }
+        let authorized = miner
+            .request(
+                "mining.authorize",
+                serde_json::json!([miner.user_name.clone(), "x"]),
+            )
+            .await
+            .expect("mining.authorize was rejected");
+        assert_eq!(authorized, Value::Bool(true), "mining.authorize failed");
+        miner
+    }
+
+    /// Finds a share for the latest job and submits it, returning whether it was accepted.
+    ///
+    /// Waits for a job and a difficulty first if none was received yet.
+    pub async fn submit_share(&mut self) -> bool {
+        self.receive_until(|miner| miner.job.is_some() && miner.difficulty.is_some())
+            .await;
+        let job = self.job.clone().unwrap();
+        let target = difficulty_to_target(self.difficulty.unwrap());
+        let extranonce2 = hex::encode(vec![0u8; self.extranonce2_size]);
+
+        for _ in 0..MAX_NONCES_PER_SHARE {
+            let nonce = self.next_nonce;
+            self.next_nonce = self.next_nonce.wrapping_add(1);
+            let params = serde_json::json!([
+                self.user_name.clone(),
+                job.job_id.clone(),
+                extranonce2.clone(),
+                format!("{:08x}", job.time.0),
+                format!("{nonce:08x}"),
+            ]);
+            let share = client_to_server::Submit::try_from(json_rpc::StandardRequest {
+                id: self.next_request_id,
+                method: "mining.submit".to_string(),
+                params: params.clone(),
+            })
+            .expect("Invalid mining.submit");
+            let meets_target =
+                validate_sv1_share(&share, target, self.extranonce1.clone(), None, job.clone())
+                    .unwrap_or(false);
+            if meets_target {
+                return matches!(
+                    self.request("mining.submit", params).await,
+                    Ok(Value::Bool(true))
+                );
+            }
+        }
+        panic!("MockSv1Miner: no share found for job {}", job.job_id);
+    }
+
+    /// Submits `count` shares, one every `interval`, and returns how many were accepted.
+    pub async fn submit_shares(&mut self, count: usize, interval: Duration) -> usize {
+        let mut accepted = 0;
+        for _ in 0..count {
+            if self.submit_share().await {
+                accepted += 1;
+            }
+            tokio::time::sleep(interval).await;
+        }
+        accepted
+    }
+
+    /// Waits for the server to change the difficulty and returns the new one.
+    ///
+    /// The difficulty set along with the first job is the one a change is awaited from.
+    pub async fn wait_for_difficulty_change(&mut self) -> f64 {
+        self.receive_until(|miner| miner.job.is_some() && miner.difficulty.is_some())
+            .await;
+        let difficulty = self.difficulty;
+        self.receive_until(|miner| miner.difficulty != difficulty)
+            .await;
+        self.difficulty.unwrap()
+    }
+
+    /// Returns the extranonce1 the server assigned in its `mining.subscribe` response.
+    pub fn extranonce1(&self) -> &[u8] {
+        &self.extranonce1
+    }
+
+    /// Returns the session id the server assigned in its `mining.subscribe` response.
+    pub fn session_id(&self) -> Option<&str> {
+        self.session_id.as_deref()
+    }
+
+    /// Closes the connection to the server.
+    pub fn disconnect(self) {
+        self.connection.sender().close();
+        self.connection.receiver().close();
+    }
+
+    /// Returns the difficulty last set by the server.
+    pub fn difficulty(&self) -> Option<f64> {
+        self.difficulty
+    }
+
+    // Sends a request and returns the result of its response, or its error, handling the
+    // notifications received in the meantime.
+    async fn request(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        let id = self.next_request_id;
+        self.next_request_id += 1;
+        let request = json_rpc::Message::StandardRequest(json_rpc::StandardRequest {
+            id,
+            method: method.to_string(),
+            params,
+        });
+        assert!(
+            self.connection.send(request).await,
+            "Failed to send {method}"
+        );
+        loop {
+            let message = self
+                .connection
+                .receive()
+                .await
+                .expect("SV1 connection closed");
+            match message {
+                json_rpc::Message::OkResponse(response) if response.id == id => {
+                    return Ok(response.result);
+                }
+                json_rpc::Message::ErrorResponse(response) if response.id == id => {
+                    return Err(format!("{:?}", response.error));
+                }
+                message => self.handle_notification(message),
+            }
+        }
+    }
+
+    // Handles the notifications received until `done` holds.
+    async fn receive_until(&mut self, done: impl Fn(&Self) -> bool) {
+        while !done(self) {
+            let message = self
+                .connection
+                .receive()
+                .await
+                .expect("SV1 connection closed");
+            self.handle_notification(message);
+        }
+    }
+
+    fn handle_notification(&mut self, message: json_rpc::Message) {
+        let json_rpc::Message::Notification(notification) = message else {
+            return;
+        };
+        match notification.method.as_str() {
+            "mining.notify" => {
+                self.job = Some(Notify::try_from(notification).expect("Invalid mining.notify"));
+            }
+            "mining.set_difficulty" => {
+                self.difficulty = notification.params[0].as_f64();
+            }
+            // The jobs notified before were for the previous extranonce1
+            "mining.set_extranonce" => {
+                if let Some(extranonce1) = notification.params[0].as_str() {
+                    self.extranonce1 = hex::decode(extranonce1).expect("Invalid extranonce1");
+                }
+                if let Some(extranonce2_size) = notification.params[1].as_u64() {
+                    self.extranonce2_size = extranonce2_size as usize;
+                }
+                self.job = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+// Converts an SV1 difficulty to the target a share must meet, difficulty 1 being `Target::MAX`.
+fn difficulty_to_target(difficulty: f64) -> Target {
+    let mut remaining = 65535.0 * 2f64.powi(208) / difficulty;
+    let mut be_bytes = [0u8; 32];
+    for (i, byte) in be_bytes.iter_mut().enumerate() {
+        let weight = 256f64.powi(31 - i as i32);
+        let value = (remaining / weight).floor().min(255.0);
+        *byte = value as u8;
+        remaining -= value * weight;
+    }
+    Target::from_be_bytes(be_bytes)
+}
@@ -57,6 +57,53 @@ fn get_available_port() -> u16 {
         }
     }
 }
+
+/// Polls the `/metrics` endpoint of a monitoring server until it contains `expected`, returning
+/// the body.
+pub async fn wait_for_metric(monitoring_addr: SocketAddr, expected: &str) -> String {
+    wait_for_endpoint(monitoring_addr, "/metrics", expected).await
+}
+
+/// Polls the `path` endpoint of a monitoring server until its body contains `expected`, returning
+/// the body.
+pub async fn wait_for_endpoint(monitoring_addr: SocketAddr, path: &str, expected: &str) -> String {
+    let url = format!("http://{monitoring_addr}{path}");
+    tokio::time::timeout(std::time::Duration::from_secs(60), async {
+        loop {
+            let request = minreq::get(url.clone());
+            if let Ok(Ok(response)) = tokio::task::spawn_blocking(move || request.send()).await {
+                if let Ok(body) = response.as_str() {
+                    if body.contains(expected) {
+                        return body.to_string();
+                    }
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("`{expected}` was not reported by `{path}` in time"))
+}
+
+/// Sends a `POST` request to the `path` admin endpoint of a monitoring server, with `admin_token`
+/// as bearer token if given, returning the status code and the body.
+pub async fn post_endpoint(
+    monitoring_addr: SocketAddr,
+    path: &str,
+    admin_token: Option<&str>,
+) -> (i32, String) {
+    let mut request = minreq::post(format!("http://{monitoring_addr}{path}"));
+    if let Some(admin_token) = admin_token {
+        request = request.with_header("Authorization", format!("Bearer {admin_token}"));
+    }
+    let response = tokio::task::spawn_blocking(move || request.send())
+        .await
+        .expect("POST request task panicked")
+        .unwrap_or_else(|e| panic!("POST `{path}` failed: {e}"));
+    let body = response.as_str().unwrap_or_default().to_string();
+    (response.status_code, body)
+}
+
 pub async fn wait_for_client(listen_socket: SocketAddr) -> tokio::net::TcpStream {
     let listener = tokio::net::TcpListener::bind(listen_socket)
         .await
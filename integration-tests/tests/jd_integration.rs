@@ -1,17 +1,22 @@
 // This file contains integration tests for the `JDC/S` module.
 use integration_tests_sv2::{
     interceptor::{MessageDirection, ReplaceMessage},
-    mock_roles::{MockDownstream, WithSetup},
+    mock_roles::{MockDownstream, MockUpstream, WithSetup},
     template_provider::DifficultyLevel,
+    utils::{get_available_address, wait_for_metric},
     *,
 };
-use stratum_apps::stratum_core::{
-    binary_sv2::{Seq064K, B032, U256},
-    common_messages_sv2::*,
-    job_declaration_sv2::{ProvideMissingTransactionsSuccess, PushSolution, *},
-    mining_sv2::*,
-    parsers_sv2::{self, AnyMessage, Mining},
-    template_distribution_sv2::*,
+use std::time::Duration;
+use stratum_apps::{
+    config_helpers::{AllUpstreamsFailedPolicy, CoinbaseRewardDescriptor},
+    stratum_core::{
+        binary_sv2::{Seq064K, B032, U256},
+        common_messages_sv2::*,
+        job_declaration_sv2::{ProvideMissingTransactionsSuccess, PushSolution, *},
+        mining_sv2::*,
+        parsers_sv2::{self, AnyMessage, Mining},
+        template_distribution_sv2::*,
+    },
 };
 
 // This test verifies that jd-server does not exit when a connected jd-client shuts down.
@@ -799,3 +804,673 @@ async fn jdc_require_standard_jobs_set_does_not_group_standard_channels() {
         );
     }
 }
+
+// This test verifies that a JDC configured with a larger `jdc_search_space_bytes` reserves the
+// extra bytes when opening its upstream extended channel and still serves downstream channels.
+#[tokio::test]
+async fn jdc_opens_upstream_channel_with_configured_search_space() {
+    start_tracing();
+    const JDC_SEARCH_SPACE_BYTES: usize = 8;
+    let (tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    tp.fund_wallet().unwrap();
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (_jds, jds_addr) = start_jds(tp.rpc_info());
+    let (pool_sniffer, pool_sniffer_addr) = start_sniffer("pool", pool_addr, false, vec![], None);
+
+    let config = jdc_config(
+        &[(pool_sniffer_addr, jds_addr)],
+        sv2_tp_config(tp_addr),
+        vec![],
+        vec![],
+    )
+    .with_jdc_search_space_bytes(JDC_SEARCH_SPACE_BYTES);
+    let (_jdc, jdc_addr) = start_jdc_with_config(config);
+
+    let (sniffer, sniffer_addr) = start_sniffer("downstream", jdc_addr, false, vec![], None);
+    let mock_downstream = MockDownstream::new(
+        sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let send_to_jdc = mock_downstream.start().await;
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+
+    send_to_jdc
+        .send(AnyMessage::Mining(Mining::OpenExtendedMiningChannel(
+            OpenExtendedMiningChannel {
+                request_id: 0,
+                user_identity: b"user_identity".to_vec().try_into().unwrap(),
+                nominal_hash_rate: 1000.0,
+                max_target: vec![0xff; 32].try_into().unwrap(),
+                min_extranonce_size: 0,
+            },
+        )))
+        .await
+        .unwrap();
+
+    pool_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+        )
+        .await;
+    let min_extranonce_size = loop {
+        match pool_sniffer.next_message_from_downstream() {
+            Some((_, AnyMessage::Mining(Mining::OpenExtendedMiningChannel(msg)))) => {
+                break msg.min_extranonce_size;
+            }
+            _ => continue,
+        };
+    };
+    assert_eq!(min_extranonce_size as usize, JDC_SEARCH_SPACE_BYTES);
+
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+}
+
+// This test verifies that the JDC falls back to the next upstream when the extranonce size
+// granted by the pool leaves no room beyond the configured `jdc_search_space_bytes`.
+#[tokio::test]
+async fn jdc_falls_back_when_search_space_exceeds_upstream_extranonce() {
+    start_tracing();
+    let (tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    tp.fund_wallet().unwrap();
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (_jds, jds_addr) = start_jds(tp.rpc_info());
+
+    // Grant exactly the 8 bytes the JDC reserves for itself, leaving nothing for downstreams.
+    let small_extranonce_replace = ReplaceMessage::new(
+        MessageDirection::ToDownstream,
+        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        AnyMessage::Mining(Mining::OpenExtendedMiningChannelSuccess(
+            OpenExtendedMiningChannelSuccess {
+                request_id: 1,
+                channel_id: 1,
+                target: vec![0xff; 32].try_into().unwrap(),
+                extranonce_size: 8,
+                extranonce_prefix: vec![0, 0, 0, 1].try_into().unwrap(),
+                group_channel_id: 0,
+            },
+        )),
+    );
+    let (pool_sniffer_a, pool_sniffer_a_addr) = start_sniffer(
+        "pool_a",
+        pool_addr,
+        false,
+        vec![small_extranonce_replace.into()],
+        None,
+    );
+    let (pool_sniffer_b, pool_sniffer_b_addr) =
+        start_sniffer("pool_b", pool_addr, false, vec![], None);
+
+    let config = jdc_config(
+        &[
+            (pool_sniffer_a_addr, jds_addr),
+            (pool_sniffer_b_addr, jds_addr),
+        ],
+        sv2_tp_config(tp_addr),
+        vec![],
+        vec![],
+    )
+    .with_jdc_search_space_bytes(8);
+    let (_jdc, jdc_addr) = start_jdc_with_config(config);
+
+    let mock_downstream = MockDownstream::new(
+        jdc_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let send_to_jdc = mock_downstream.start().await;
+    send_to_jdc
+        .send(AnyMessage::Mining(Mining::OpenExtendedMiningChannel(
+            OpenExtendedMiningChannel {
+                request_id: 0,
+                user_identity: b"user_identity".to_vec().try_into().unwrap(),
+                nominal_hash_rate: 1000.0,
+                max_target: vec![0xff; 32].try_into().unwrap(),
+                min_extranonce_size: 0,
+            },
+        )))
+        .await
+        .unwrap();
+
+    pool_sniffer_a
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+
+    // the JDC should reject the granted extranonce and fall back to the second upstream
+    pool_sniffer_b
+        .wait_for_message_type(MessageDirection::ToUpstream, MESSAGE_TYPE_SETUP_CONNECTION)
+        .await;
+}
+
+// This test verifies that the JDC refuses to start when `jdc_search_space_bytes` leaves no room
+// for the client search space within `MAX_EXTRANONCE_LEN`.
+#[tokio::test]
+async fn jdc_refuses_search_space_exceeding_max_extranonce_len() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let config = jdc_config(
+        &[(get_available_address(), get_available_address())],
+        sv2_tp_config(tp_addr),
+        vec![],
+        vec![],
+    )
+    .with_jdc_search_space_bytes(MAX_EXTRANONCE_LEN);
+    let jdc_addr = *config.listening_address();
+
+    let jdc = jd_client_sv2::JobDeclaratorClient::new(config);
+    tokio::time::timeout(Duration::from_secs(60), jdc.start())
+        .await
+        .expect("JDC did not refuse the invalid jdc_search_space_bytes");
+
+    assert!(tokio::net::TcpListener::bind(jdc_addr).await.is_ok());
+}
+
+// This test verifies that a JDC whose extended channel is rejected as not supported falls back to
+// the next upstream instead of requesting a standard channel, which cannot carry its declared
+// jobs, and that its shares are accepted there.
+#[tokio::test]
+async fn jdc_falls_back_when_extended_channels_not_supported() {
+    start_tracing();
+    let (tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    tp.fund_wallet().unwrap();
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (_jds, jds_addr) = start_jds(tp.rpc_info());
+
+    let extended_not_supported_replace = ReplaceMessage::new(
+        MessageDirection::ToDownstream,
+        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        AnyMessage::Mining(Mining::OpenMiningChannelError(OpenMiningChannelError {
+            request_id: 1,
+            error_code: "extended-channels-not-supported"
+                .to_string()
+                .try_into()
+                .unwrap(),
+        })),
+    );
+    let (pool_sniffer_a, pool_sniffer_a_addr) = start_sniffer(
+        "pool_a",
+        pool_addr,
+        false,
+        vec![extended_not_supported_replace.into()],
+        None,
+    );
+    let (pool_sniffer_b, pool_sniffer_b_addr) =
+        start_sniffer("pool_b", pool_addr, false, vec![], None);
+
+    let (_jdc, jdc_addr) = start_jdc(
+        &[
+            (pool_sniffer_a_addr, jds_addr),
+            (pool_sniffer_b_addr, jds_addr),
+        ],
+        sv2_tp_config(tp_addr),
+        vec![],
+        vec![],
+    );
+    let (_translator, tproxy_addr) =
+        start_sv2_translator(&[jdc_addr], false, vec![], vec![], None).await;
+    let (_minerd_process, _minerd_addr) = start_minerd(tproxy_addr, None, None, false).await;
+
+    pool_sniffer_a
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_MINING_CHANNEL_ERROR,
+        )
+        .await;
+    pool_sniffer_b
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
+        )
+        .await;
+    assert!(
+        pool_sniffer_a
+            .assert_message_not_present(
+                MessageDirection::ToUpstream,
+                MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL,
+            )
+            .await
+    );
+}
+
+// This test verifies that a JDC solo mining with a ranged descriptor pays every new block to the
+// next script derived from the descriptor.
+#[tokio::test]
+async fn jdc_solo_mining_derives_coinbase_output_per_block() {
+    start_tracing();
+    let (tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let descriptor = CoinbaseRewardDescriptor::from_descriptor(
+        "wpkh(xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8/0/*)",
+    )
+    .unwrap();
+    let index_file =
+        std::env::temp_dir().join(format!("jdc-solo-descriptor-index-{}", std::process::id()));
+    let _ = std::fs::remove_file(&index_file);
+
+    // no upstream is configured, so the JDC solo mines right away
+    let config = jdc_config(&[], sv2_tp_config(tp_addr), vec![], vec![])
+        .with_solo_coinbase_descriptor(descriptor.clone(), index_file.clone());
+    let (_jdc, jdc_addr) = start_jdc_with_config(config);
+    let (sniffer, sniffer_addr) = start_sniffer("0", jdc_addr, false, vec![], None);
+
+    let mock_downstream = MockDownstream::new(
+        sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let send_to_jdc = mock_downstream.start().await;
+    send_to_jdc
+        .send(AnyMessage::Mining(Mining::OpenExtendedMiningChannel(
+            OpenExtendedMiningChannel {
+                request_id: 0,
+                user_identity: b"user_identity".to_vec().try_into().unwrap(),
+                nominal_hash_rate: 1000.0,
+                max_target: vec![0xff; 32].try_into().unwrap(),
+                min_extranonce_size: 0,
+            },
+        )))
+        .await
+        .unwrap();
+
+    let first_script = descriptor.script_pubkey_at(0).unwrap();
+    let second_script = descriptor.script_pubkey_at(1).unwrap();
+    assert_ne!(first_script, second_script);
+
+    let pays_to = |job: &NewExtendedMiningJob, script: &[u8]| {
+        job.coinbase_tx_suffix
+            .to_vec()
+            .windows(script.len())
+            .any(|window| window == script)
+    };
+    let next_job = || async {
+        loop {
+            sniffer
+                .wait_for_message_type(
+                    MessageDirection::ToDownstream,
+                    MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+                )
+                .await;
+            if let Some((_, AnyMessage::Mining(Mining::NewExtendedMiningJob(job)))) =
+                sniffer.next_message_from_upstream()
+            {
+                return job;
+            }
+        }
+    };
+
+    let first_job = next_job().await;
+    assert!(pays_to(&first_job, first_script.as_bytes()));
+    sniffer.clean_queue(MessageDirection::ToDownstream);
+
+    // a new block makes the JDC move on to the next derived script
+    tp.generate_blocks(1);
+    let second_job = loop {
+        let job = next_job().await;
+        if !pays_to(&job, first_script.as_bytes()) {
+            break job;
+        }
+    };
+    assert!(pays_to(&second_job, second_script.as_bytes()));
+
+    let _ = std::fs::remove_file(index_file);
+}
+
+// Verifies that with `on_all_upstreams_failed = "solo"` the JDC serves solo mining jobs to its
+// downstreams once every upstream failed.
+#[tokio::test]
+async fn jdc_mines_solo_once_all_upstreams_failed() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    // nothing listens on these addresses
+    let unreachable_upstream = (get_available_address(), get_available_address());
+    let config = jdc_config(
+        &[unreachable_upstream],
+        sv2_tp_config(tp_addr),
+        vec![],
+        vec![],
+    )
+    .with_on_all_upstreams_failed(AllUpstreamsFailedPolicy::Solo, 1);
+    let (_jdc, jdc_addr) = start_jdc_with_config(config);
+    let (sniffer, sniffer_addr) = start_sniffer("0", jdc_addr, false, vec![], None);
+
+    let mock_downstream = MockDownstream::new(
+        sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let send_to_jdc = mock_downstream.start().await;
+    send_to_jdc
+        .send(AnyMessage::Mining(Mining::OpenExtendedMiningChannel(
+            OpenExtendedMiningChannel {
+                request_id: 0,
+                user_identity: b"user_identity".to_vec().try_into().unwrap(),
+                nominal_hash_rate: 1000.0,
+                max_target: vec![0xff; 32].try_into().unwrap(),
+                min_extranonce_size: 0,
+            },
+        )))
+        .await
+        .unwrap();
+
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+        )
+        .await;
+}
+
+// Verifies that `JobDeclaratorClient::ready` resolves once the JDC fell back to solo mining and
+// accepts downstreams, even though no upstream was ever connected.
+#[tokio::test]
+async fn jdc_ready_resolves_in_solo_fallback() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let unreachable_upstream = (get_available_address(), get_available_address());
+    let config = jdc_config(
+        &[unreachable_upstream],
+        sv2_tp_config(tp_addr),
+        vec![],
+        vec![],
+    )
+    .with_on_all_upstreams_failed(AllUpstreamsFailedPolicy::Solo, 1);
+    let (jdc, jdc_addr) = start_jdc_with_config(config);
+
+    tokio::time::timeout(Duration::from_secs(60), jdc.ready())
+        .await
+        .expect("JDC never became ready");
+    tokio::net::TcpStream::connect(jdc_addr)
+        .await
+        .expect("JDC ready but not accepting connections");
+}
+
+// Verifies how the main loop of the JDC reacts to an error of a single downstream: a downstream
+// asking for another protocol is disconnected and dropped by the channel manager, while the JDC
+// keeps serving the other downstreams.
+#[tokio::test]
+async fn jdc_drops_rejected_downstream_and_keeps_serving() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let unreachable_upstream = (get_available_address(), get_available_address());
+    let monitoring_addr = get_available_address();
+    let config = jdc_config(
+        &[unreachable_upstream],
+        sv2_tp_config(tp_addr),
+        vec![],
+        vec![],
+    )
+    .with_on_all_upstreams_failed(AllUpstreamsFailedPolicy::Solo, 1)
+    .with_monitoring(monitoring_addr, 1);
+    let (_jdc, jdc_addr) = start_jdc_with_config(config);
+
+    let (rejected_sniffer, rejected_sniffer_addr) =
+        start_sniffer("rejected", jdc_addr, false, vec![], None);
+    let rejected_downstream = MockDownstream::new(
+        rejected_sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::TemplateDistributionProtocol, 0),
+    );
+    let _send_to_jdc = rejected_downstream.start().await;
+    rejected_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_ERROR,
+        )
+        .await;
+
+    let (sniffer, sniffer_addr) = start_sniffer("accepted", jdc_addr, false, vec![], None);
+    let mock_downstream = MockDownstream::new(
+        sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let send_to_jdc = mock_downstream.start().await;
+    send_to_jdc
+        .send(AnyMessage::Mining(Mining::OpenExtendedMiningChannel(
+            OpenExtendedMiningChannel {
+                request_id: 0,
+                user_identity: b"user_identity".to_vec().try_into().unwrap(),
+                nominal_hash_rate: 1000.0,
+                max_target: vec![0xff; 32].try_into().unwrap(),
+                min_extranonce_size: 0,
+            },
+        )))
+        .await
+        .unwrap();
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+
+    // only the downstream still connected is left
+    let metrics = wait_for_metric(
+        monitoring_addr,
+        r#"sv2_client_open_channels{client_id="1"} 1"#,
+    )
+    .await;
+    assert!(metrics.contains("sv2_clients_total 1"));
+}
+
+// Verifies that with `on_all_upstreams_failed = "shutdown"` the JDC exits once every upstream
+// failed, without ever accepting downstreams.
+#[tokio::test]
+async fn jdc_shuts_down_once_all_upstreams_failed() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let unreachable_upstream = (get_available_address(), get_available_address());
+    let config = jdc_config(
+        &[unreachable_upstream],
+        sv2_tp_config(tp_addr),
+        vec![],
+        vec![],
+    )
+    .with_on_all_upstreams_failed(AllUpstreamsFailedPolicy::Shutdown, 1);
+    let jdc_addr = *config.listening_address();
+
+    let jdc = jd_client_sv2::JobDeclaratorClient::new(config);
+    tokio::time::timeout(Duration::from_secs(60), jdc.start())
+        .await
+        .expect("JDC did not shut down after all upstreams failed");
+
+    assert!(tokio::net::TcpListener::bind(jdc_addr).await.is_ok());
+}
+
+// Verifies that with `on_all_upstreams_failed = "wait_and_retry"` the JDC neither mines solo nor
+// exits once every upstream failed, and connects to the upstream as soon as it comes up.
+#[tokio::test]
+async fn jdc_waits_and_retries_once_all_upstreams_failed() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let pool_addr = get_available_address();
+    let jds_addr = get_available_address();
+    let config = jdc_config(
+        &[(pool_addr, jds_addr)],
+        sv2_tp_config(tp_addr),
+        vec![],
+        vec![],
+    )
+    .with_on_all_upstreams_failed(AllUpstreamsFailedPolicy::WaitAndRetry, 1);
+    let (_jdc, jdc_addr) = start_jdc_with_config(config);
+
+    // the JDC only accepts downstreams once an upstream is connected, or when mining solo
+    tokio::time::sleep(Duration::from_secs(10)).await;
+    assert!(tokio::net::TcpListener::bind(jdc_addr).await.is_ok());
+
+    // the JDS comes up first, so the pool is never reached while the JDS is still down
+    let _send_to_jdc_from_jds = MockUpstream::new(
+        jds_addr,
+        WithSetup::yes_with_defaults(Protocol::JobDeclarationProtocol, 0),
+    )
+    .start()
+    .await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let _send_to_jdc_from_pool = MockUpstream::new(
+        pool_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    )
+    .start()
+    .await;
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while tokio::net::TcpListener::bind(jdc_addr).await.is_ok() {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    })
+    .await
+    .expect("JDC did not connect to the upstream once it came up");
+}
+
+// Starts a relay between the JDC and the Template Provider, returning its address, the number of
+// connections it accepted and a `Notify` closing the first one. Only the first connection is
+// relayed if `relay_reconnections` is false, the next ones being closed right away.
+async fn start_template_provider_relay(
+    tp_addr: std::net::SocketAddr,
+    relay_reconnections: bool,
+) -> (
+    std::net::SocketAddr,
+    std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    std::sync::Arc<tokio::sync::Notify>,
+) {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::{net::TcpStream, sync::Notify};
+
+    let listener = tokio::net::TcpListener::bind(get_available_address())
+        .await
+        .unwrap();
+    let relay_addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let drop_first_connection = Arc::new(Notify::new());
+    {
+        let connections = connections.clone();
+        let drop_first_connection = drop_first_connection.clone();
+        tokio::spawn(async move {
+            while let Ok((mut jdc_stream, _)) = listener.accept().await {
+                let first = connections.fetch_add(1, Ordering::SeqCst) == 0;
+                if !first && !relay_reconnections {
+                    continue;
+                }
+                let drop_first_connection = drop_first_connection.clone();
+                tokio::spawn(async move {
+                    let mut tp_stream = TcpStream::connect(tp_addr).await.unwrap();
+                    let relay = tokio::io::copy_bidirectional(&mut jdc_stream, &mut tp_stream);
+                    tokio::select! {
+                        _ = relay => {}
+                        _ = drop_first_connection.notified(), if first => {}
+                    }
+                });
+            }
+        });
+    }
+    (relay_addr, connections, drop_first_connection)
+}
+
+// Verifies that a JDC set to reconnect to its template provider survives the connection to it
+// dropping: it reconnects, and a miner connecting afterwards still opens a channel and gets a job.
+#[tokio::test]
+async fn jdc_reconnects_to_dropped_template_provider() {
+    use std::sync::atomic::Ordering;
+
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (relay_addr, connections, drop_first_connection) =
+        start_template_provider_relay(tp_addr, true).await;
+    let unreachable_upstream = (get_available_address(), get_available_address());
+    let config = jdc_config(
+        &[unreachable_upstream],
+        sv2_tp_config(relay_addr),
+        vec![],
+        vec![],
+    )
+    .with_on_all_upstreams_failed(AllUpstreamsFailedPolicy::Solo, 1)
+    .with_template_provider_reconnect(3, 1);
+    let (jdc, jdc_addr) = start_jdc_with_config(config);
+    tokio::time::timeout(Duration::from_secs(60), jdc.ready())
+        .await
+        .expect("JDC never became ready");
+
+    drop_first_connection.notify_one();
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while connections.load(Ordering::SeqCst) < 2 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("JDC never reconnected to the template provider");
+
+    let (sniffer, sniffer_addr) = start_sniffer("0", jdc_addr, false, vec![], None);
+    let send_to_jdc = MockDownstream::new(
+        sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    )
+    .start()
+    .await;
+    send_to_jdc
+        .send(AnyMessage::Mining(Mining::OpenExtendedMiningChannel(
+            OpenExtendedMiningChannel {
+                request_id: 0,
+                user_identity: b"user_identity".to_vec().try_into().unwrap(),
+                nominal_hash_rate: 1000.0,
+                max_target: vec![0xff; 32].try_into().unwrap(),
+                min_extranonce_size: 0,
+            },
+        )))
+        .await
+        .unwrap();
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+        )
+        .await;
+}
+
+// Verifies that a JDC shutting down while it waits to reconnect to its template provider stops
+// reconnecting instead of sleeping through its backoff first.
+#[tokio::test]
+async fn jdc_stops_reconnecting_to_template_provider_on_shutdown() {
+    use std::sync::atomic::Ordering;
+
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (relay_addr, connections, drop_first_connection) =
+        start_template_provider_relay(tp_addr, false).await;
+    let unreachable_upstream = (get_available_address(), get_available_address());
+    let config = jdc_config(
+        &[unreachable_upstream],
+        sv2_tp_config(relay_addr),
+        vec![],
+        vec![],
+    )
+    .with_on_all_upstreams_failed(AllUpstreamsFailedPolicy::Solo, 1)
+    .with_template_provider_reconnect(3, 3);
+    let (jdc, _jdc_addr) = start_jdc_with_config(config);
+    tokio::time::timeout(Duration::from_secs(60), jdc.ready())
+        .await
+        .expect("JDC never became ready");
+
+    drop_first_connection.notify_one();
+    // the JDC is now waiting out the backoff before its first attempt
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    drop(jdc);
+
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
@@ -5,9 +5,16 @@ use integration_tests_sv2::{
     interceptor::{MessageDirection, ReplaceMessage},
     mock_roles::{MockDownstream, WithSetup},
     template_provider::DifficultyLevel,
+    utils::{get_available_address, wait_for_endpoint, wait_for_metric},
     *,
 };
+use std::time::Duration;
 use stratum_apps::stratum_core::{
+    bitcoin::{
+        block::{Header, Version},
+        hashes::Hash,
+        BlockHash, CompactTarget, TxMerkleNode,
+    },
     common_messages_sv2::{has_work_selection, Protocol, SetupConnection, *},
     mining_sv2::*,
     parsers_sv2::{self, AnyMessage, CommonMessages, Mining, TemplateDistribution},
@@ -417,6 +424,72 @@ async fn pool_reject_setup_connection_with_non_mining_protocol() {
     );
 }
 
+// This test checks how the main loop of the pool reacts to an error of a single downstream: a
+// downstream asking for another protocol is disconnected and dropped by the channel manager, while
+// the pool keeps serving the other downstreams.
+#[tokio::test]
+async fn pool_drops_rejected_downstream_and_keeps_serving() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let monitoring_addr = get_available_address();
+    let config =
+        pool_config(sv2_tp_config(tp_addr), vec![], vec![]).with_monitoring(monitoring_addr, 1);
+    let (_pool, pool_addr) = start_pool_with_config(config).await;
+
+    let (rejected_sniffer, rejected_sniffer_addr) =
+        start_sniffer("rejected", pool_addr, false, vec![], None);
+    let rejected_downstream = MockDownstream::new(
+        rejected_sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::TemplateDistributionProtocol, 0),
+    );
+    let _send_to_pool = rejected_downstream.start().await;
+    rejected_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_ERROR,
+        )
+        .await;
+
+    let (sniffer, sniffer_addr) = start_sniffer("accepted", pool_addr, false, vec![], None);
+    let mock_downstream = MockDownstream::new(
+        sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let send_to_pool = mock_downstream.start().await;
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+    send_to_pool
+        .send(AnyMessage::Mining(Mining::OpenExtendedMiningChannel(
+            OpenExtendedMiningChannel {
+                request_id: 0,
+                user_identity: b"user_identity".to_vec().try_into().unwrap(),
+                nominal_hash_rate: 1000.0,
+                max_target: vec![0xff; 32].try_into().unwrap(),
+                min_extranonce_size: 0,
+            },
+        )))
+        .await
+        .unwrap();
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+
+    // only the downstream still connected is left
+    let metrics = wait_for_metric(
+        monitoring_addr,
+        r#"sv2_client_open_channels{client_id="2"} 1"#,
+    )
+    .await;
+    assert!(metrics.contains("sv2_clients_total 1"));
+}
+
 // This test launches a Pool and leverages a MockDownstream to test the correct functionalities of
 // grouping extended channels.
 #[tokio::test]
@@ -915,3 +988,989 @@ async fn pool_require_standard_jobs_set_does_not_group_standard_channels() {
         );
     }
 }
+
+// This test checks that the pool rejects channels whose `nominal_hash_rate` is outside the
+// configured bounds with an `OpenMiningChannelError` carrying the `invalid-hashrate` code.
+#[tokio::test]
+async fn pool_rejects_out_of_range_nominal_hashrate() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let config = pool_config(sv2_tp_config(tp_addr), vec![], vec![]).with_nominal_hashrate_bounds(
+        Some(1_000.0),
+        Some(1_000_000.0),
+        false,
+    );
+    let (_pool, pool_addr) = start_pool_with_config(config).await;
+
+    let (sniffer, sniffer_addr) = start_sniffer("sniffer", pool_addr, false, vec![], None);
+
+    let mock_downstream = MockDownstream::new(
+        sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let send_to_pool = mock_downstream.start().await;
+
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+
+    for (request_id, nominal_hash_rate) in [(0u32, 1.0), (1, 1.0e30)] {
+        let open_extended_mining_channel = AnyMessage::Mining(Mining::OpenExtendedMiningChannel(
+            OpenExtendedMiningChannel {
+                request_id: request_id.into(),
+                user_identity: b"user_identity".to_vec().try_into().unwrap(),
+                nominal_hash_rate,
+                max_target: vec![0xff; 32].try_into().unwrap(),
+                min_extranonce_size: 0,
+            },
+        ));
+        send_to_pool
+            .send(open_extended_mining_channel)
+            .await
+            .unwrap();
+
+        sniffer
+            .wait_for_message_type(
+                MessageDirection::ToDownstream,
+                MESSAGE_TYPE_OPEN_MINING_CHANNEL_ERROR,
+            )
+            .await;
+        match sniffer.next_message_from_upstream() {
+            Some((_, AnyMessage::Mining(Mining::OpenMiningChannelError(msg)))) => {
+                assert_eq!(msg.request_id, request_id);
+                assert_eq!(msg.error_code.as_utf8_or_hex(), "invalid-hashrate");
+            }
+            msg => panic!("Expected OpenMiningChannelError message, found: {:?}", msg),
+        }
+    }
+}
+
+// This test checks that a downstream cannot open more channels than `max_channels_per_downstream`
+// allows, the channel beyond the cap being rejected with the `too-many-channels` code, and that
+// the channels open with the downstream are reported in `sv2_client_open_channels`.
+#[tokio::test]
+async fn pool_limits_channels_per_downstream() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let monitoring_addr = get_available_address();
+    let config = pool_config(sv2_tp_config(tp_addr), vec![], vec![])
+        .with_max_channels_per_downstream(2)
+        .with_monitoring(monitoring_addr, 1);
+    let (_pool, pool_addr) = start_pool_with_config(config).await;
+
+    let (sniffer, sniffer_addr) = start_sniffer("sniffer", pool_addr, false, vec![], None);
+
+    let mock_downstream = MockDownstream::new(
+        sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let send_to_pool = mock_downstream.start().await;
+
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+
+    for request_id in 0u32..3 {
+        let open_extended_mining_channel = AnyMessage::Mining(Mining::OpenExtendedMiningChannel(
+            OpenExtendedMiningChannel {
+                request_id: request_id.into(),
+                user_identity: b"user_identity".to_vec().try_into().unwrap(),
+                nominal_hash_rate: 1000.0,
+                max_target: vec![0xff; 32].try_into().unwrap(),
+                min_extranonce_size: 0,
+            },
+        ));
+        send_to_pool
+            .send(open_extended_mining_channel)
+            .await
+            .unwrap();
+
+        if request_id < 2 {
+            sniffer
+                .wait_for_message_type_and_clean_queue(
+                    MessageDirection::ToDownstream,
+                    MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+                )
+                .await;
+            continue;
+        }
+        sniffer
+            .wait_for_message_type(
+                MessageDirection::ToDownstream,
+                MESSAGE_TYPE_OPEN_MINING_CHANNEL_ERROR,
+            )
+            .await;
+        // the jobs of the channels opened before may still precede the error
+        loop {
+            match sniffer.next_message_from_upstream() {
+                Some((_, AnyMessage::Mining(Mining::OpenMiningChannelError(msg)))) => {
+                    assert_eq!(msg.request_id, request_id);
+                    assert_eq!(msg.error_code.as_utf8_or_hex(), "too-many-channels");
+                    break;
+                }
+                Some(_) => continue,
+                None => panic!("Expected OpenMiningChannelError message"),
+            }
+        }
+    }
+
+    wait_for_metric(
+        monitoring_addr,
+        r#"sv2_client_open_channels{client_id="1"} 2"#,
+    )
+    .await;
+}
+
+// This test checks that the pool refuses to start with `max_channels_per_downstream` set to 0,
+// which would reject every channel.
+#[tokio::test]
+async fn pool_refuses_zero_max_channels_per_downstream() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let config =
+        pool_config(sv2_tp_config(tp_addr), vec![], vec![]).with_max_channels_per_downstream(0);
+
+    let result = pool_sv2::PoolSv2::new(config).start().await;
+    assert!(matches!(
+        result,
+        Err(pool_sv2::error::PoolErrorKind::Configuration(_))
+    ));
+}
+
+// This test checks that the pool refuses to start with a lower nominal hashrate bound above the
+// upper one.
+#[tokio::test]
+async fn pool_refuses_inverted_nominal_hashrate_bounds() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let config = pool_config(sv2_tp_config(tp_addr), vec![], vec![]).with_nominal_hashrate_bounds(
+        Some(1_000_000.0),
+        Some(1_000.0),
+        false,
+    );
+
+    let result = pool_sv2::PoolSv2::new(config).start().await;
+    assert!(matches!(
+        result,
+        Err(pool_sv2::error::PoolErrorKind::Configuration(_))
+    ));
+}
+
+// This test checks that the pool refuses to start with an authentication error code too long to
+// be sent in a `SetupConnectionError`, instead of shutting down on the first rejected client.
+#[tokio::test]
+async fn pool_refuses_too_long_authentication_error_code() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let config = pool_config(sv2_tp_config(tp_addr), vec![], vec![])
+        .with_authentication_error_code("x".repeat(256));
+
+    let result = pool_sv2::PoolSv2::new(config).start().await;
+    assert!(matches!(
+        result,
+        Err(pool_sv2::error::PoolErrorKind::Configuration(_))
+    ));
+}
+
+// This test checks that with `clamp_hashrate` set, the pool opens channels with an out-of-range
+// `nominal_hash_rate` as if the client had requested the nearest bound.
+#[tokio::test]
+async fn pool_clamps_out_of_range_nominal_hashrate() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let config = pool_config(sv2_tp_config(tp_addr), vec![], vec![]).with_nominal_hashrate_bounds(
+        Some(1.0e12),
+        Some(1.0e15),
+        true,
+    );
+    let (_pool, pool_addr) = start_pool_with_config(config).await;
+
+    let (sniffer, sniffer_addr) = start_sniffer("sniffer", pool_addr, false, vec![], None);
+
+    let mock_downstream = MockDownstream::new(
+        sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let send_to_pool = mock_downstream.start().await;
+
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+
+    // the first channel requests exactly the lower bound, the second one a hashrate below it
+    let mut targets = Vec::new();
+    for (request_id, nominal_hash_rate) in [(0u32, 1.0e12), (1, 1.0)] {
+        let open_standard_mining_channel = AnyMessage::Mining(Mining::OpenStandardMiningChannel(
+            OpenStandardMiningChannel {
+                request_id: request_id.into(),
+                user_identity: b"user_identity".to_vec().try_into().unwrap(),
+                nominal_hash_rate,
+                max_target: vec![0xff; 32].try_into().unwrap(),
+            },
+        ));
+        send_to_pool
+            .send(open_standard_mining_channel)
+            .await
+            .unwrap();
+
+        sniffer
+            .wait_for_message_type(
+                MessageDirection::ToDownstream,
+                MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL_SUCCESS,
+            )
+            .await;
+        let target = loop {
+            match sniffer.next_message_from_upstream() {
+                Some((_, AnyMessage::Mining(Mining::OpenStandardMiningChannelSuccess(msg)))) => {
+                    break msg.target.inner_as_ref().to_vec();
+                }
+                _ => continue,
+            };
+        };
+        targets.push(target);
+    }
+
+    assert_eq!(
+        targets[0], targets[1],
+        "Clamped channel must get the target of the lower bound"
+    );
+}
+
+// This test checks that the pool tracks the `sequence_number` of the shares of each channel: a
+// skipped number is only counted, while a repeated or older number is answered with an
+// `invalid-sequence-number` error when `reject_out_of_sequence_shares` is set.
+#[tokio::test]
+async fn pool_detects_out_of_sequence_shares() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let monitoring_addr = get_available_address();
+    let config = pool_config(sv2_tp_config(tp_addr), vec![], vec![])
+        .with_reject_out_of_sequence_shares(true)
+        .with_monitoring(monitoring_addr, 1);
+    let (_pool, pool_addr) = start_pool_with_config(config).await;
+
+    let (sniffer, sniffer_addr) = start_sniffer("sniffer", pool_addr, false, vec![], None);
+
+    let mock_downstream = MockDownstream::new(
+        sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let send_to_pool = mock_downstream.start().await;
+
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+
+    send_to_pool
+        .send(AnyMessage::Mining(Mining::OpenStandardMiningChannel(
+            OpenStandardMiningChannel {
+                request_id: 0u32.into(),
+                user_identity: b"user_identity".to_vec().try_into().unwrap(),
+                nominal_hash_rate: 1000.0,
+                max_target: vec![0xff; 32].try_into().unwrap(),
+            },
+        )))
+        .await
+        .unwrap();
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+    let channel_id = loop {
+        match sniffer.next_message_from_upstream() {
+            Some((_, AnyMessage::Mining(Mining::OpenStandardMiningChannelSuccess(msg)))) => {
+                break msg.channel_id;
+            }
+            _ => continue,
+        };
+    };
+
+    // 1 -> 4 skips two numbers, 2 goes back and the second 4 repeats the last number. The job id
+    // is unknown, so every share is answered with an error.
+    let sequence_numbers = [0, 1, 4, 2, 4, 5];
+    for sequence_number in sequence_numbers {
+        send_to_pool
+            .send(AnyMessage::Mining(Mining::SubmitSharesStandard(
+                SubmitSharesStandard {
+                    channel_id,
+                    sequence_number,
+                    job_id: u32::MAX,
+                    nonce: 0,
+                    ntime: 0,
+                    version: 0x2000_0000,
+                },
+            )))
+            .await
+            .unwrap();
+    }
+
+    let mut errors = Vec::new();
+    tokio::time::timeout(Duration::from_secs(60), async {
+        while errors.len() < sequence_numbers.len() {
+            match sniffer.next_message_from_upstream() {
+                Some((_, AnyMessage::Mining(Mining::SubmitSharesError(msg)))) => {
+                    errors.push((msg.sequence_number, msg.error_code.as_utf8_or_hex()));
+                }
+                Some(_) => continue,
+                None => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+    })
+    .await
+    .expect("the pool did not answer every share");
+
+    let rejected_out_of_sequence: Vec<u32> = errors
+        .iter()
+        .filter(|(_, error_code)| error_code == "invalid-sequence-number")
+        .map(|(sequence_number, _)| *sequence_number)
+        .collect();
+    assert_eq!(rejected_out_of_sequence, vec![2, 4]);
+
+    let metrics = wait_for_metric(
+        monitoring_addr,
+        r#"sv2_client_share_sequence_violations_total{kind="gap"} 1"#,
+    )
+    .await;
+    assert!(metrics.contains(r#"sv2_client_share_sequence_violations_total{kind="duplicate"} 1"#));
+    assert!(metrics.contains(r#"sv2_client_share_sequence_violations_total{kind="regression"} 1"#));
+}
+
+// Checks that a share below the target of its channel is answered with `above-target` by a pool
+// validating shares strictly, and with the generic `difficulty-too-low` otherwise.
+#[tokio::test]
+async fn strict_pool_rejects_share_below_channel_target() {
+    start_tracing();
+    assert_eq!(below_target_share_error_code(true).await, "above-target");
+    assert_eq!(
+        below_target_share_error_code(false).await,
+        "difficulty-too-low"
+    );
+}
+
+// Submits a share below the target of its channel to a pool and returns the error code it is
+// answered with.
+async fn below_target_share_error_code(strict_share_validation: bool) -> String {
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let config = pool_config(sv2_tp_config(tp_addr), vec![], vec![])
+        .with_strict_share_validation(strict_share_validation);
+    let (_pool, pool_addr) = start_pool_with_config(config).await;
+
+    let (sniffer, sniffer_addr) = start_sniffer("sniffer", pool_addr, false, vec![], None);
+
+    let mock_downstream = MockDownstream::new(
+        sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let send_to_pool = mock_downstream.start().await;
+
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+
+    // Such a nominal hashrate gives the channel a target no single hash can be expected to meet.
+    send_to_pool
+        .send(AnyMessage::Mining(Mining::OpenStandardMiningChannel(
+            OpenStandardMiningChannel {
+                request_id: 0u32.into(),
+                user_identity: b"user_identity".to_vec().try_into().unwrap(),
+                nominal_hash_rate: 1e15,
+                max_target: vec![0xff; 32].try_into().unwrap(),
+            },
+        )))
+        .await
+        .unwrap();
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH,
+        )
+        .await;
+    let (mut channel_id, mut job, mut prev_hash) = (None, None, None);
+    while prev_hash.is_none() {
+        match sniffer.next_message_from_upstream() {
+            Some((_, AnyMessage::Mining(Mining::OpenStandardMiningChannelSuccess(msg)))) => {
+                channel_id = Some(msg.channel_id);
+            }
+            Some((_, AnyMessage::Mining(Mining::NewMiningJob(msg)))) => job = Some(msg),
+            Some((_, AnyMessage::Mining(Mining::SetNewPrevHash(msg)))) => prev_hash = Some(msg),
+            Some(_) => continue,
+            None => panic!("no SetNewPrevHash received"),
+        }
+    }
+    let (channel_id, job, prev_hash) = (channel_id.unwrap(), job.unwrap(), prev_hash.unwrap());
+
+    // The regtest network target is easy, so pick a nonce that does not find a block either.
+    let mut header = Header {
+        version: Version::from_consensus(job.version as i32),
+        prev_blockhash: BlockHash::from_byte_array(
+            prev_hash.prev_hash.to_vec().try_into().unwrap(),
+        ),
+        merkle_root: TxMerkleNode::from_byte_array(job.merkle_root.to_vec().try_into().unwrap()),
+        time: prev_hash.min_ntime,
+        bits: CompactTarget::from_consensus(prev_hash.nbits),
+        nonce: 0,
+    };
+    while header.validate_pow(header.target()).is_ok() {
+        header.nonce += 1;
+    }
+
+    send_to_pool
+        .send(AnyMessage::Mining(Mining::SubmitSharesStandard(
+            SubmitSharesStandard {
+                channel_id,
+                sequence_number: 0,
+                job_id: job.job_id,
+                nonce: header.nonce,
+                ntime: header.time,
+                version: job.version,
+            },
+        )))
+        .await
+        .unwrap();
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SUBMIT_SHARES_ERROR,
+        )
+        .await;
+    let error = loop {
+        match sniffer.next_message_from_upstream() {
+            Some((_, AnyMessage::Mining(Mining::SubmitSharesError(msg)))) => break msg,
+            Some(_) => continue,
+            None => panic!("no SubmitSharesError received"),
+        }
+    };
+    assert_eq!(error.sequence_number, 0);
+    error.error_code.as_utf8_or_hex()
+}
+
+// Verifies that `PoolSv2::ready` resolves once the pool accepts connections, so a client can
+// connect right away instead of sleeping.
+#[tokio::test]
+async fn pool_ready_resolves_once_listening() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let config = pool_config(sv2_tp_config(tp_addr), vec![], vec![]);
+    let pool_addr = *config.listen_address();
+    let pool = pool_sv2::PoolSv2::new(config);
+    let ready = pool.ready();
+    let pool_clone = pool.clone();
+    tokio::spawn(async move {
+        _ = pool_clone.start().await;
+    });
+
+    tokio::time::timeout(Duration::from_secs(60), ready)
+        .await
+        .expect("pool never became ready");
+    tokio::net::TcpStream::connect(pool_addr)
+        .await
+        .expect("pool ready but not accepting connections");
+}
+
+// Checks that a pool exporting its share accounting writes a dump with a row per worker: the
+// share of a mining device is counted as accepted, and a share with an unknown job id as
+// rejected.
+#[tokio::test]
+async fn pool_exports_share_accounting_per_worker() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let export_path =
+        std::env::temp_dir().join(format!("pool-share-export-{}.csv", std::process::id()));
+    let _ = std::fs::remove_file(&export_path);
+    let config = pool_config(sv2_tp_config(tp_addr), vec![], vec![]).with_share_export(
+        export_path.clone(),
+        pool_sv2::share_export::ShareExportFormat::Csv,
+        1,
+    );
+    let (_pool, pool_addr) = start_pool_with_config(config).await;
+
+    let (sniffer, sniffer_addr) = start_sniffer("sniffer", pool_addr, false, vec![], None);
+    start_mining_device_sv2(
+        sniffer_addr,
+        None,
+        None,
+        Some("alice".to_string()),
+        1,
+        None,
+        true,
+    );
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
+        )
+        .await;
+
+    let (sniffer_b, sniffer_addr_b) = start_sniffer("sniffer_b", pool_addr, false, vec![], None);
+    let mock_downstream = MockDownstream::new(
+        sniffer_addr_b,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let send_to_pool = mock_downstream.start().await;
+    sniffer_b
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+    send_to_pool
+        .send(AnyMessage::Mining(Mining::OpenStandardMiningChannel(
+            OpenStandardMiningChannel {
+                request_id: 0u32.into(),
+                user_identity: b"bob".to_vec().try_into().unwrap(),
+                nominal_hash_rate: 1000.0,
+                max_target: vec![0xff; 32].try_into().unwrap(),
+            },
+        )))
+        .await
+        .unwrap();
+    sniffer_b
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+    let channel_id = loop {
+        match sniffer_b.next_message_from_upstream() {
+            Some((_, AnyMessage::Mining(Mining::OpenStandardMiningChannelSuccess(msg)))) => {
+                break msg.channel_id;
+            }
+            _ => continue,
+        };
+    };
+    send_to_pool
+        .send(AnyMessage::Mining(Mining::SubmitSharesStandard(
+            SubmitSharesStandard {
+                channel_id,
+                sequence_number: 0,
+                job_id: u32::MAX,
+                nonce: 0,
+                ntime: 0,
+                version: 0x2000_0000,
+            },
+        )))
+        .await
+        .unwrap();
+
+    // Every dump only holds the shares since the previous one, so add up the rows of the dumps
+    // until both shares were seen.
+    let (mut alice_accepted, mut bob_rejected) = (0, 0);
+    let mut last_dump = String::new();
+    tokio::time::timeout(Duration::from_secs(60), async {
+        while alice_accepted == 0 || bob_rejected == 0 {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            let Ok(dump) = std::fs::read_to_string(&export_path) else {
+                continue;
+            };
+            if dump == last_dump {
+                continue;
+            }
+            let mut lines = dump.lines();
+            assert_eq!(
+                lines.next(),
+                Some(
+                    "user_identity,period_start,period_end,shares_accepted,shares_rejected,\
+                     share_work_sum,estimated_hashrate"
+                )
+            );
+            for row in lines {
+                let fields: Vec<&str> = row.split(',').collect();
+                assert_eq!(fields.len(), 7, "malformed row {row}");
+                let (accepted, rejected): (u64, u64) =
+                    (fields[3].parse().unwrap(), fields[4].parse().unwrap());
+                match fields[0] {
+                    "alice" => {
+                        alice_accepted += accepted;
+                        if accepted > 0 {
+                            assert!(fields[6].parse::<f64>().unwrap() > 0.0);
+                        }
+                    }
+                    "bob" => {
+                        assert_eq!(accepted, 0);
+                        bob_rejected += rejected;
+                    }
+                    other => panic!("unexpected worker {other}"),
+                }
+            }
+            last_dump = dump;
+        }
+    })
+    .await
+    .expect("no dump with the shares of both workers");
+    let _ = std::fs::remove_file(&export_path);
+}
+
+// This test checks that a pool with a downstream authenticator answers the `SetupConnection` of
+// an unknown downstream with a `SetupConnectionError` carrying the configured error code, while
+// known downstreams are still accepted.
+#[tokio::test]
+async fn pool_rejects_downstreams_unknown_to_its_authenticator() {
+    use pool_sv2::{authentication::DownstreamAuthenticator, PoolSv2};
+    use std::sync::Arc;
+
+    struct KnownDevices;
+
+    impl DownstreamAuthenticator for KnownDevices {
+        fn authenticate(&self, setup_connection: &SetupConnection<'_>) -> bool {
+            setup_connection.device_id.as_utf8_or_hex() == "rig-01"
+        }
+    }
+
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let config = pool_config(sv2_tp_config(tp_addr), vec![], vec![])
+        .with_authentication_error_code("unknown-device".to_string());
+    let pool_addr = *config.listen_address();
+    let pool = PoolSv2::new(config).with_downstream_authenticator(Arc::new(KnownDevices));
+    let ready = pool.ready();
+    let pool_clone = pool.clone();
+    tokio::spawn(async move {
+        _ = pool_clone.start().await;
+    });
+    ready.await;
+
+    for (device_id, expected) in [
+        ("stranger", MESSAGE_TYPE_SETUP_CONNECTION_ERROR),
+        ("rig-01", MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS),
+    ] {
+        let (sniffer, sniffer_addr) = start_sniffer(device_id, pool_addr, false, vec![], None);
+        let WithSetup::Yes(mut setup_connection) =
+            WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0)
+        else {
+            unreachable!()
+        };
+        setup_connection.device_id = device_id.as_bytes().to_vec().try_into().unwrap();
+        let _send_to_pool = MockDownstream::new(sniffer_addr, WithSetup::yes(setup_connection))
+            .start()
+            .await;
+
+        sniffer
+            .wait_for_message_type(MessageDirection::ToDownstream, expected)
+            .await;
+        if expected == MESSAGE_TYPE_SETUP_CONNECTION_ERROR {
+            match sniffer.next_message_from_upstream() {
+                Some((_, AnyMessage::Common(CommonMessages::SetupConnectionError(msg)))) => {
+                    assert_eq!(msg.error_code.as_utf8_or_hex(), "unknown-device");
+                }
+                msg => panic!("Expected SetupConnectionError message, found: {:?}", msg),
+            }
+        }
+    }
+}
+
+// This test checks that a pool set to reconnect to its template provider survives the connection
+// to it dropping: it reconnects, and a miner connecting afterwards still opens a channel and gets
+// a job.
+//
+// A relay between the pool and the Template Provider stands in for a template provider going away,
+// closing the first connection of the pool and relaying the next ones.
+#[tokio::test]
+async fn pool_reconnects_to_dropped_template_provider() {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::{net::TcpStream, sync::Notify};
+
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let listener = tokio::net::TcpListener::bind(get_available_address())
+        .await
+        .unwrap();
+    let relay_addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let drop_first_connection = Arc::new(Notify::new());
+    {
+        let connections = connections.clone();
+        let drop_first_connection = drop_first_connection.clone();
+        tokio::spawn(async move {
+            while let Ok((mut pool_stream, _)) = listener.accept().await {
+                let first = connections.fetch_add(1, Ordering::SeqCst) == 0;
+                let drop_first_connection = drop_first_connection.clone();
+                tokio::spawn(async move {
+                    let mut tp_stream = TcpStream::connect(tp_addr).await.unwrap();
+                    let relay = tokio::io::copy_bidirectional(&mut pool_stream, &mut tp_stream);
+                    tokio::select! {
+                        _ = relay => {}
+                        _ = drop_first_connection.notified(), if first => {}
+                    }
+                });
+            }
+        });
+    }
+
+    let config = pool_config(sv2_tp_config(relay_addr), vec![], vec![])
+        .with_template_provider_reconnect(3, 1);
+    let pool_addr = *config.listen_address();
+    let pool = pool_sv2::PoolSv2::new(config);
+    let ready = pool.ready();
+    let pool_clone = pool.clone();
+    tokio::spawn(async move {
+        _ = pool_clone.start().await;
+    });
+    ready.await;
+
+    drop_first_connection.notify_one();
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while connections.load(Ordering::SeqCst) < 2 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("pool never reconnected to the template provider");
+
+    let (sniffer, sniffer_addr) = start_sniffer("sniffer", pool_addr, false, vec![], None);
+    let send_to_pool = MockDownstream::new(
+        sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    )
+    .start()
+    .await;
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+
+    let open_extended_mining_channel = AnyMessage::Mining(Mining::OpenExtendedMiningChannel(
+        OpenExtendedMiningChannel {
+            request_id: 0.into(),
+            user_identity: b"user_identity".to_vec().try_into().unwrap(),
+            nominal_hash_rate: 1000.0,
+            max_target: vec![0xff; 32].try_into().unwrap(),
+            min_extranonce_size: 0,
+        },
+    ));
+    send_to_pool
+        .send(open_extended_mining_channel)
+        .await
+        .unwrap();
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+        )
+        .await;
+}
+
+// This test checks that a pool deferring downstreams until its upstream is ready refuses the
+// miners connecting while it reconnects to its template provider with a `temporarily-unavailable`
+// `SetupConnectionError`, and accepts them again once reconnected.
+//
+// A relay between the pool and the Template Provider closes the first connection of the pool and
+// drops the next ones until the template provider is back.
+#[tokio::test]
+async fn pool_refuses_downstreams_while_reconnecting_to_template_provider() {
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::{net::TcpStream, sync::Notify};
+
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let listener = tokio::net::TcpListener::bind(get_available_address())
+        .await
+        .unwrap();
+    let relay_addr = listener.local_addr().unwrap();
+    let tp_up = Arc::new(AtomicBool::new(true));
+    let relayed = Arc::new(AtomicUsize::new(0));
+    let dropped = Arc::new(AtomicUsize::new(0));
+    let drop_connections = Arc::new(Notify::new());
+    {
+        let tp_up = tp_up.clone();
+        let relayed = relayed.clone();
+        let dropped = dropped.clone();
+        let drop_connections = drop_connections.clone();
+        tokio::spawn(async move {
+            while let Ok((mut pool_stream, _)) = listener.accept().await {
+                if !tp_up.load(Ordering::SeqCst) {
+                    dropped.fetch_add(1, Ordering::SeqCst);
+                    continue;
+                }
+                relayed.fetch_add(1, Ordering::SeqCst);
+                let drop_connections = drop_connections.clone();
+                tokio::spawn(async move {
+                    let mut tp_stream = TcpStream::connect(tp_addr).await.unwrap();
+                    let relay = tokio::io::copy_bidirectional(&mut pool_stream, &mut tp_stream);
+                    tokio::select! {
+                        _ = relay => {}
+                        _ = drop_connections.notified() => {}
+                    }
+                });
+            }
+        });
+    }
+
+    let config = pool_config(sv2_tp_config(relay_addr), vec![], vec![])
+        .with_template_provider_reconnect(5, 1)
+        .with_defer_downstream_until_upstream_ready(true);
+    let pool_addr = *config.listen_address();
+    let pool = pool_sv2::PoolSv2::new(config);
+    let ready = pool.ready();
+    let pool_clone = pool.clone();
+    tokio::spawn(async move {
+        _ = pool_clone.start().await;
+    });
+    ready.await;
+
+    tp_up.store(false, Ordering::SeqCst);
+    drop_connections.notify_waiters();
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while dropped.load(Ordering::SeqCst) < 1 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("pool never tried to reconnect to the template provider");
+
+    let (sniffer, sniffer_addr) = start_sniffer("refused", pool_addr, false, vec![], None);
+    let _send_to_pool = MockDownstream::new(
+        sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    )
+    .start()
+    .await;
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_ERROR,
+        )
+        .await;
+    match sniffer.next_message_from_upstream() {
+        Some((_, AnyMessage::Common(CommonMessages::SetupConnectionError(msg)))) => {
+            assert_eq!(msg.error_code.as_utf8_or_hex(), "temporarily-unavailable");
+        }
+        msg => panic!("Expected SetupConnectionError message, found: {:?}", msg),
+    }
+
+    tp_up.store(true, Ordering::SeqCst);
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while relayed.load(Ordering::SeqCst) < 2 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("pool never reconnected to the template provider");
+    // leave the pool time to set the new connection up
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let (sniffer, sniffer_addr) = start_sniffer("accepted", pool_addr, false, vec![], None);
+    let _send_to_pool = MockDownstream::new(
+        sniffer_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    )
+    .start()
+    .await;
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+}
+
+// This test checks that a pool shutting down while it waits to reconnect to its template provider
+// stops reconnecting instead of sleeping through its backoff first.
+//
+// A relay between the pool and the Template Provider closes the first connection of the pool and
+// counts the next ones.
+#[tokio::test]
+async fn pool_stops_reconnecting_to_template_provider_on_shutdown() {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::net::TcpStream;
+
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let listener = tokio::net::TcpListener::bind(get_available_address())
+        .await
+        .unwrap();
+    let relay_addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let drop_first_connection = Arc::new(tokio::sync::Notify::new());
+    {
+        let connections = connections.clone();
+        let drop_first_connection = drop_first_connection.clone();
+        tokio::spawn(async move {
+            while let Ok((mut pool_stream, _)) = listener.accept().await {
+                if connections.fetch_add(1, Ordering::SeqCst) > 0 {
+                    continue;
+                }
+                let drop_first_connection = drop_first_connection.clone();
+                tokio::spawn(async move {
+                    let mut tp_stream = TcpStream::connect(tp_addr).await.unwrap();
+                    let relay = tokio::io::copy_bidirectional(&mut pool_stream, &mut tp_stream);
+                    tokio::select! {
+                        _ = relay => {}
+                        _ = drop_first_connection.notified() => {}
+                    }
+                });
+            }
+        });
+    }
+
+    let config = pool_config(sv2_tp_config(relay_addr), vec![], vec![])
+        .with_template_provider_reconnect(3, 3);
+    let pool = pool_sv2::PoolSv2::new(config);
+    let ready = pool.ready();
+    let pool_clone = pool.clone();
+    tokio::spawn(async move {
+        _ = pool_clone.start().await;
+    });
+    ready.await;
+
+    drop_first_connection.notify_one();
+    // the pool is now waiting out the backoff before its first attempt
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    drop(pool);
+
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
+
+// This test checks that a share meeting the network target is submitted to the Template Provider
+// with `SubmitSolution`, and that the pool reports the block as accepted once the Template
+// Provider announces it as the new chain tip.
+#[tokio::test]
+async fn pool_reports_found_block_accepted_by_template_provider() {
+    start_tracing();
+    let (tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (tp_sniffer, tp_sniffer_addr) = start_sniffer("tp", tp_addr, false, vec![], None);
+    let monitoring_addr = get_available_address();
+    let config = pool_config(sv2_tp_config(tp_sniffer_addr), vec![], vec![])
+        .with_monitoring(monitoring_addr, 1);
+    let (_pool, pool_addr) = start_pool_with_config(config).await;
+
+    // On regtest, the single share of the mining device meets the network target
+    start_mining_device_sv2(pool_addr, None, None, None, 1, None, true);
+    tp_sniffer
+        .wait_for_message_type(MessageDirection::ToUpstream, MESSAGE_TYPE_SUBMIT_SOLUTION)
+        .await;
+
+    let blocks =
+        wait_for_endpoint(monitoring_addr, "/api/v1/blocks", r#""status":"accepted""#).await;
+    assert!(blocks.contains(r#""submitted_total":1,"accepted_total":1"#));
+    let best_block_hash = tp.get_best_block_hash().unwrap();
+    assert!(blocks.contains(&format!(r#""block_hash":"{best_block_hash}""#)));
+}
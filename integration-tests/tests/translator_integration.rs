@@ -4,11 +4,15 @@ use integration_tests_sv2::{
     mock_roles::{MockUpstream, WithSetup},
     sv1_sniffer::SV1MessageFilter,
     template_provider::DifficultyLevel,
-    utils::get_available_address,
+    utils::{get_available_address, post_endpoint, wait_for_endpoint, wait_for_metric},
     *,
 };
-use stratum_apps::stratum_core::mining_sv2::*;
-use tokio::net::TcpListener;
+use stratum_apps::{config_helpers::AllUpstreamsFailedPolicy, stratum_core::mining_sv2::*};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use translator_sv2::config::{DownstreamGroupRule, SetupConnectionFlagsPolicy, UpstreamRouteRule};
 
 use std::{
     collections::{HashMap, HashSet},
@@ -17,8 +21,9 @@ use std::{
 use stratum_apps::stratum_core::{
     binary_sv2::{Seq0255, Sv2Option},
     common_messages_sv2::{
-        Protocol, SetupConnectionError, SetupConnectionSuccess, MESSAGE_TYPE_SETUP_CONNECTION,
-        MESSAGE_TYPE_SETUP_CONNECTION_ERROR, MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        Protocol, Reconnect, SetupConnectionError, SetupConnectionSuccess,
+        MESSAGE_TYPE_SETUP_CONNECTION, MESSAGE_TYPE_SETUP_CONNECTION_ERROR,
+        MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
     },
     mining_sv2::{
         CloseChannel, OpenMiningChannelError, MESSAGE_TYPE_CLOSE_CHANNEL,
@@ -79,6 +84,45 @@ async fn translate_sv1_to_sv2_successfully() {
         .await;
 }
 
+// This test drives the translator shutdown through `run_until` and checks that resolving the
+// shutdown future terminates the translator cleanly and releases its downstream listener.
+#[tokio::test]
+async fn translator_run_until_shuts_down_when_future_resolves() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (pool_translator_sniffer, pool_translator_sniffer_addr) =
+        start_sniffer("0", pool_addr, false, vec![], None);
+    let config =
+        sv2_translator_config(&[pool_translator_sniffer_addr], false, vec![], vec![], None).await;
+    let tproxy_addr = std::net::SocketAddr::new(
+        config.downstream_address.parse().unwrap(),
+        config.downstream_port,
+    );
+
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let translator = translator_sv2::TranslatorSv2::new(config);
+    let translator_handle = tokio::spawn(translator.run_until(shutdown_rx));
+
+    pool_translator_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+    // give the SV1 server time to bind its listener
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(TcpListener::bind(tproxy_addr).await.is_err());
+
+    shutdown_tx.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(10), translator_handle)
+        .await
+        .expect("translator did not shut down in time")
+        .expect("translator task panicked");
+
+    assert!(TcpListener::bind(tproxy_addr).await.is_ok());
+}
+
 // Demonstrates the scenario where TProxy falls back to the secondary pool
 // after the primary pool returns a `SetupConnection.Error`.
 #[tokio::test]
@@ -161,6 +205,89 @@ async fn test_translator_fallback_on_setup_connection_error() {
         .await;
 }
 
+// Demonstrates the scenario where TProxy falls back to the secondary pool
+// after the primary pool selects a protocol version outside the configured range.
+#[tokio::test]
+async fn test_translator_fallback_on_unsupported_protocol_version() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool_1, pool_addr_1) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (_pool_2, pool_addr_2) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+
+    // the translator only supports version 2
+    let setup_connection_success_replace = ReplaceMessage::new(
+        MessageDirection::ToDownstream,
+        MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        AnyMessage::Common(parsers_sv2::CommonMessages::SetupConnectionSuccess(
+            SetupConnectionSuccess {
+                used_version: 3,
+                flags: 0,
+            },
+        )),
+    );
+
+    let (pool_translator_sniffer_1, pool_translator_sniffer_addr_1) = start_sniffer(
+        "A",
+        pool_addr_1,
+        false,
+        vec![setup_connection_success_replace.into()],
+        None,
+    );
+
+    let (pool_translator_sniffer_2, pool_translator_sniffer_addr_2) =
+        start_sniffer("B", pool_addr_2, false, vec![], None);
+
+    let (_, tproxy_addr) = start_sv2_translator(
+        &[
+            pool_translator_sniffer_addr_1,
+            pool_translator_sniffer_addr_2,
+        ],
+        false,
+        vec![],
+        vec![],
+        None,
+    )
+    .await;
+
+    let (_minerd_process, _minerd_addr) = start_minerd(tproxy_addr, None, None, false).await;
+
+    pool_translator_sniffer_1
+        .wait_for_message_type(MessageDirection::ToUpstream, MESSAGE_TYPE_SETUP_CONNECTION)
+        .await;
+    pool_translator_sniffer_1
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+
+    pool_translator_sniffer_2
+        .wait_for_message_type(MessageDirection::ToUpstream, MESSAGE_TYPE_SETUP_CONNECTION)
+        .await;
+    pool_translator_sniffer_2
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+    pool_translator_sniffer_2
+        .wait_for_message_type(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+        )
+        .await;
+
+    // the primary pool must not be used to open a channel
+    assert!(
+        pool_translator_sniffer_1
+            .assert_message_not_present(
+                MessageDirection::ToUpstream,
+                MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+            )
+            .await
+    );
+}
+
 // Demonstrates the scenario where the primary pool returns an `OpenMiningChannel.Error`,
 // causing TProxy to fall back to the secondary pool.
 #[tokio::test]
@@ -250,311 +377,747 @@ async fn test_translator_fallback_on_open_mining_message_error() {
         .await;
 }
 
-// This test verifies that the translator sends keepalive jobs to downstream miners when no new
-// jobs are received from upstream, and that shares submitted for keepalive jobs are properly
-// received by the pool. Keepalive job_id(s) use the format `{original_job_id}#{counter}`.
+// Verifies that with warm standby enabled, the translator completes `SetupConnection` with the
+// standby upstream ahead of time, so when the primary fails the failover only opens a channel on
+// the existing connection instead of reconnecting from scratch, and that the former primary is
+// then kept as the new standby.
 #[tokio::test]
-async fn test_translator_keepalive_job_sent_and_share_received_by_pool() {
+async fn translator_fails_over_to_warm_standby_upstream() {
     start_tracing();
-    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::High);
-    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
-    let (pool_translator_sniffer, pool_translator_sniffer_addr) =
-        start_sniffer("0", pool_addr, false, vec![], None);
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool_1, pool_addr_1) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (_pool_2, pool_addr_2) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
 
-    // Start translator with a short keepalive interval (5 seconds)
-    let keepalive_interval_secs = 5_u16;
-    let (_, tproxy_addr) = start_sv2_translator(
-        &[pool_translator_sniffer_addr],
+    // the primary fails as soon as the translator tries to open a channel with it
+    let open_mining_channel_success_replace = ReplaceMessage::new(
+        MessageDirection::ToDownstream,
+        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        AnyMessage::Mining(parsers_sv2::Mining::OpenMiningChannelError(
+            OpenMiningChannelError {
+                request_id: 0,
+                error_code: "primary-down".to_string().try_into().unwrap(),
+            },
+        )),
+    );
+
+    let (primary_sniffer, primary_sniffer_addr) = start_sniffer(
+        "A",
+        pool_addr_1,
+        false,
+        vec![open_mining_channel_success_replace.into()],
+        None,
+    );
+    let (standby_sniffer, standby_sniffer_addr) =
+        start_sniffer("B", pool_addr_2, false, vec![], None);
+
+    let config = sv2_translator_config(
+        &[primary_sniffer_addr, standby_sniffer_addr],
         false,
         vec![],
         vec![],
-        Some(keepalive_interval_secs),
+        None,
     )
-    .await;
-    let (sv1_sniffer, sv1_sniffer_addr) = start_sv1_sniffer(tproxy_addr);
-    let (_minerd_process, _minerd_addr) = start_minerd(sv1_sniffer_addr, None, None, false).await;
-
-    sv1_sniffer
-        .wait_for_message(&["mining.notify"], MessageDirection::ToDownstream)
-        .await;
-
-    pool_translator_sniffer
-        .wait_for_message_type(
+    .await
+    .with_warm_standby(1);
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    // the standby is connected and set up before any miner shows up, the cold path would only
+    // dial it once the primary failed
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        standby_sniffer.wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        ),
+    )
+    .await
+    .expect("the standby upstream was not set up ahead of the failover");
+    assert!(
+        primary_sniffer
+            .assert_message_not_present(
+                MessageDirection::ToUpstream,
+                MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL
+            )
+            .await
+    );
+    standby_sniffer.clean_queue(MessageDirection::ToUpstream);
+    standby_sniffer.clean_queue(MessageDirection::ToDownstream);
+    primary_sniffer
+        .wait_for_message_type_and_clean_queue(
             MessageDirection::ToUpstream,
-            MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
+            MESSAGE_TYPE_SETUP_CONNECTION,
         )
         .await;
 
-    // Wait for keepalive interval plus some buffer time
-    tokio::time::sleep(std::time::Duration::from_secs(
-        keepalive_interval_secs as u64 + 3,
-    ))
-    .await;
+    let (_minerd_process, _minerd_addr) = start_minerd(tproxy_addr, None, None, false).await;
 
-    // Wait for a keepalive mining.notify message (job_id contains '#' delimiter)
-    sv1_sniffer
-        .wait_for_keepalive_notify(MessageDirection::ToDownstream)
+    primary_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_MINING_CHANNEL_ERROR,
+        )
         .await;
 
-    // Wait for the share submission success message
-    // This proves the keepalive job was valid and the share was properly mapped
-    pool_translator_sniffer
+    // the channel is opened on the standby connection, without setting it up again
+    standby_sniffer
         .wait_for_message_type(
             MessageDirection::ToDownstream,
-            MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
         )
         .await;
+    assert!(
+        standby_sniffer
+            .assert_message_not_present(MessageDirection::ToUpstream, MESSAGE_TYPE_SETUP_CONNECTION)
+            .await
+    );
+
+    // the former primary is connected again as the new standby
+    primary_sniffer
+        .wait_for_message_type(MessageDirection::ToUpstream, MESSAGE_TYPE_SETUP_CONNECTION)
+        .await;
 }
 
-// This test launches a tProxy in aggregated mode and leverages a MockUpstream to test the correct
-// functionalities of grouping extended channels.
+// Verifies that a shutdown requested while the warm standby is still being set up stops the
+// translator, instead of waiting for the standby upstream to answer.
 #[tokio::test]
-async fn aggregated_translator_correctly_deals_with_group_channels() {
+async fn translator_shuts_down_while_connecting_warm_standby() {
     start_tracing();
-    let (tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
-    tp.fund_wallet().unwrap();
-
-    // block SubmitSolution messages from arriving to TP
-    // so we avoid shares triggering chain tip updates
-    // which we want to do explicitly via generate_blocks()
-    let ignore_submit_solution =
-        IgnoreMessage::new(MessageDirection::ToUpstream, MESSAGE_TYPE_SUBMIT_SOLUTION);
-    let (_sniffer_pool_tp, sniffer_pool_tp_addr) = start_sniffer(
-        "0",
-        tp_addr,
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (primary_sniffer, primary_sniffer_addr) =
+        start_sniffer("A", pool_addr, false, vec![], None);
+    // the standby accepts the connection but never answers the handshake
+    let standby = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let standby_addr = standby.local_addr().unwrap();
+
+    let config = sv2_translator_config(
+        &[primary_sniffer_addr, standby_addr],
         false,
-        vec![ignore_submit_solution.into()],
+        vec![],
+        vec![],
         None,
-    );
+    )
+    .await
+    .with_warm_standby(1);
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let translator = translator_sv2::TranslatorSv2::new(config);
+    let translator_handle = tokio::spawn(translator.run_until(shutdown_rx));
 
-    let (_pool, pool_addr) = start_pool(sv2_tp_config(sniffer_pool_tp_addr), vec![], vec![]).await;
+    primary_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+    let (_standby_connection, _) = tokio::time::timeout(Duration::from_secs(10), standby.accept())
+        .await
+        .expect("the standby upstream was never dialed")
+        .unwrap();
 
-    // ignore SubmitSharesSuccess messages, so we can keep the assertion flow simple
-    let ignore_submit_shares_success = IgnoreMessage::new(
+    shutdown_tx.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(10), translator_handle)
+        .await
+        .expect("translator did not shut down in time")
+        .expect("translator task panicked");
+}
+
+// Verifies that the monitoring metrics carry the configured upstream name as the `upstream` label,
+// and that the active upstream label switches from the primary to the backup pool on failover.
+#[tokio::test]
+async fn translator_metrics_switch_upstream_label_on_failover() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool_1, pool_addr_1) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (_pool_2, pool_addr_2) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+
+    // the primary fails as soon as the translator tries to open a channel with it
+    let open_mining_channel_success_replace = ReplaceMessage::new(
         MessageDirection::ToDownstream,
-        MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
+        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        AnyMessage::Mining(parsers_sv2::Mining::OpenMiningChannelError(
+            OpenMiningChannelError {
+                request_id: 0,
+                error_code: "primary-down".to_string().try_into().unwrap(),
+            },
+        )),
     );
-    let (sniffer, sniffer_addr) = start_sniffer(
-        "0",
-        pool_addr,
+    let (primary_sniffer, primary_sniffer_addr) = start_sniffer(
+        "A",
+        pool_addr_1,
         false,
-        vec![ignore_submit_shares_success.into()],
+        vec![open_mining_channel_success_replace.into()],
         None,
     );
+    let (backup_sniffer, backup_sniffer_addr) =
+        start_sniffer("B", pool_addr_2, false, vec![], None);
 
-    // aggregated tProxy
-    let (_, tproxy_addr) = start_sv2_translator(&[sniffer_addr], true, vec![], vec![], None).await;
-
-    sniffer
-        .wait_for_message_type_and_clean_queue(
+    let monitoring_addr = get_available_address();
+    let mut config = sv2_translator_config(
+        &[primary_sniffer_addr, backup_sniffer_addr],
+        true,
+        vec![],
+        vec![],
+        None,
+    )
+    .await
+    .with_monitoring(monitoring_addr, 1);
+    config.upstreams = config
+        .upstreams
+        .into_iter()
+        .zip(["primary", "backup"])
+        .map(|(upstream, name)| upstream.with_name(name.to_string()))
+        .collect();
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    primary_sniffer
+        .wait_for_message_type(
             MessageDirection::ToDownstream,
             MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
         )
         .await;
+    wait_for_metric(
+        monitoring_addr,
+        r#"sv2_server_active{upstream="primary"} 1"#,
+    )
+    .await;
 
-    let mut minerd_vec = Vec::new();
-
-    // start the first minerd process, to trigger the first OpenExtendedMiningChannel message
-    let (minerd_process, _minerd_addr) = start_minerd(tproxy_addr, None, None, false).await;
-    minerd_vec.push(minerd_process);
+    let (_minerd_process, _minerd_addr) = start_minerd(tproxy_addr, None, None, false).await;
 
-    sniffer
-        .wait_for_message_type_and_clean_queue(
-            MessageDirection::ToUpstream,
-            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+    backup_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
         )
         .await;
-    sniffer
+    let metrics =
+        wait_for_metric(monitoring_addr, r#"sv2_server_active{upstream="backup"} 1"#).await;
+    assert!(!metrics.contains(r#"upstream="primary""#));
+}
+
+// Verifies that `/api/v1/server` reports the cause of a failover along with the upstream that was
+// left, once the primary rejects the opening of the channel.
+#[tokio::test]
+async fn translator_reports_failover_reason_in_monitoring() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool_1, pool_addr_1) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (_pool_2, pool_addr_2) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+
+    let open_mining_channel_success_replace = ReplaceMessage::new(
+        MessageDirection::ToDownstream,
+        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        AnyMessage::Mining(parsers_sv2::Mining::OpenMiningChannelError(
+            OpenMiningChannelError {
+                request_id: 0,
+                error_code: "primary-down".to_string().try_into().unwrap(),
+            },
+        )),
+    );
+    let (primary_sniffer, primary_sniffer_addr) = start_sniffer(
+        "A",
+        pool_addr_1,
+        false,
+        vec![open_mining_channel_success_replace.into()],
+        None,
+    );
+    let (backup_sniffer, backup_sniffer_addr) =
+        start_sniffer("B", pool_addr_2, false, vec![], None);
+
+    let monitoring_addr = get_available_address();
+    let mut config = sv2_translator_config(
+        &[primary_sniffer_addr, backup_sniffer_addr],
+        true,
+        vec![],
+        vec![],
+        None,
+    )
+    .await
+    .with_monitoring(monitoring_addr, 1);
+    config.upstreams = config
+        .upstreams
+        .into_iter()
+        .zip(["primary", "backup"])
+        .map(|(upstream, name)| upstream.with_name(name.to_string()))
+        .collect();
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    primary_sniffer
         .wait_for_message_type(
             MessageDirection::ToDownstream,
-            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
         )
         .await;
+    let server =
+        wait_for_endpoint(monitoring_addr, "/api/v1/server", r#""upstream":"primary""#).await;
+    assert!(server.contains(r#""last_failover":null"#));
 
-    // save the aggregated and group channel IDs
-    let (aggregated_channel_id, group_channel_id) = match sniffer.next_message_from_upstream() {
-        Some((
-            _,
-            AnyMessage::Mining(parsers_sv2::Mining::OpenExtendedMiningChannelSuccess(msg)),
-        )) => (msg.channel_id, msg.group_channel_id),
-        msg => panic!(
-            "Expected OpenExtendedMiningChannelSuccess message, found: {:?}",
-            msg
-        ),
-    };
+    let (_minerd_process, _minerd_addr) = start_minerd(tproxy_addr, None, None, false).await;
 
-    // wait for the expected NewExtendedMiningJob and SetNewPrevHash messages
-    // and clean the queue
-    sniffer
+    backup_sniffer
         .wait_for_message_type(
             MessageDirection::ToDownstream,
-            MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
         )
         .await;
-    sniffer
-        .wait_for_message_type_and_clean_queue(
+    let server = wait_for_endpoint(
+        monitoring_addr,
+        "/api/v1/server",
+        r#""reason":"open_mining_channel_error""#,
+    )
+    .await;
+    assert!(server.contains(r#""upstream":"primary","timestamp":"#));
+    let global = wait_for_endpoint(
+        monitoring_addr,
+        "/api/v1/global",
+        r#""reason":"open_mining_channel_error""#,
+    )
+    .await;
+    assert!(global.contains(r#""upstream":"backup""#));
+}
+
+// Verifies that `POST /api/v1/failover` moves the translator from a healthy primary to the backup
+// upstream, and that monitoring reports the failover as requested by an operator.
+#[tokio::test]
+async fn translator_fails_over_on_admin_request() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool_1, pool_addr_1) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (_pool_2, pool_addr_2) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (primary_sniffer, primary_sniffer_addr) =
+        start_sniffer("A", pool_addr_1, false, vec![], None);
+    let (backup_sniffer, backup_sniffer_addr) =
+        start_sniffer("B", pool_addr_2, false, vec![], None);
+
+    let monitoring_addr = get_available_address();
+    let mut config = sv2_translator_config(
+        &[primary_sniffer_addr, backup_sniffer_addr],
+        true,
+        vec![],
+        vec![],
+        None,
+    )
+    .await
+    .with_monitoring(monitoring_addr, 1)
+    .with_monitoring_admin_token("admin-token".to_string());
+    config.upstreams = config
+        .upstreams
+        .into_iter()
+        .zip(["primary", "backup"])
+        .map(|(upstream, name)| upstream.with_name(name.to_string()))
+        .collect();
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    let (_minerd_process, _minerd_addr) = start_minerd(tproxy_addr, None, None, false).await;
+    primary_sniffer
+        .wait_for_message_type(
             MessageDirection::ToDownstream,
-            MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
         )
         .await;
+    wait_for_endpoint(monitoring_addr, "/api/v1/server", r#""upstream":"primary""#).await;
 
-    // open a few more extended channels to be aggregated with the first one
-    const N_MINERDS: u32 = 5;
-    for _i in 0..N_MINERDS {
-        let (minerd_process, _minerd_addr) = start_minerd(tproxy_addr, None, None, false).await;
-        minerd_vec.push(minerd_process);
+    // the admin action is refused without the configured token
+    let (status, body) = post_endpoint(monitoring_addr, "/api/v1/failover", None).await;
+    assert_eq!(status, 401, "unexpected response: {body}");
+    let (status, body) =
+        post_endpoint(monitoring_addr, "/api/v1/failover", Some("admin-token")).await;
+    assert_eq!(status, 202, "unexpected response: {body}");
 
-        // wait a bit
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    backup_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+    let server = wait_for_endpoint(monitoring_addr, "/api/v1/server", r#""reason":"manual""#).await;
+    assert!(server.contains(r#""upstream":"primary","timestamp":"#));
+    let global =
+        wait_for_endpoint(monitoring_addr, "/api/v1/global", r#""upstream":"backup""#).await;
+    assert!(global.contains(r#""reason":"manual""#));
+}
 
-        // assert no furter OpenExtendedMiningChannel messages are sent
-        sniffer
-            .assert_message_not_present(
-                MessageDirection::ToUpstream,
-                MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
-            )
-            .await;
-    }
+// Verifies that with `upstream_max_lifetime_secs` the translator periodically rotates from one
+// healthy upstream to the next one and back to the first, moving the connected miner along
+// without disconnecting it or changing its extranonce1.
+#[tokio::test]
+async fn translator_rotates_upstreams_after_max_lifetime() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool_1, pool_addr_1) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (_pool_2, pool_addr_2) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (sniffer_a, sniffer_a_addr) = start_sniffer("A", pool_addr_1, false, vec![], None);
+    let (sniffer_b, sniffer_b_addr) = start_sniffer("B", pool_addr_2, false, vec![], None);
 
-    // wait for a SubmitSharesExtended message
-    sniffer
+    let monitoring_addr = get_available_address();
+    let config = sv2_translator_config_with_hashrate(
+        &[sniffer_a_addr, sniffer_b_addr],
+        true,
+        vec![],
+        vec![],
+        None,
+        10_000.0,
+    )
+    .with_monitoring(monitoring_addr, 1)
+    .with_upstream_max_lifetime_secs(10);
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    let mut miner =
+        sv1_miner::MockSv1Miner::connect_with_extranonce_subscribe(tproxy_addr, "user.rig01").await;
+    let extranonce1 = miner.extranonce1().to_vec();
+    assert!(miner.submit_shares(3, Duration::from_millis(500)).await > 0);
+    sniffer_a
         .wait_for_message_type(
             MessageDirection::ToUpstream,
             MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
         )
         .await;
+    sniffer_a.clean_queue(MessageDirection::ToUpstream);
+    sniffer_a.clean_queue(MessageDirection::ToDownstream);
 
-    let share_channel_id = match sniffer.next_message_from_downstream() {
-        Some((_, AnyMessage::Mining(parsers_sv2::Mining::SubmitSharesExtended(msg)))) => {
-            msg.channel_id
-        }
-        msg => panic!("Expected SubmitSharesExtended message, found: {:?}", msg),
-    };
-
-    assert_eq!(
-        aggregated_channel_id, share_channel_id,
-        "Share submitted to the correct channel ID"
-    );
-    assert_ne!(
-        share_channel_id, group_channel_id,
-        "Share NOT submitted to the group channel ID"
-    );
-
-    // wait for another share, so we can clean the queue
-    sniffer
-        .wait_for_message_type_and_clean_queue(
+    // first rotation, to the next upstream
+    sniffer_b
+        .wait_for_message_type(MessageDirection::ToUpstream, MESSAGE_TYPE_SETUP_CONNECTION)
+        .await;
+    sniffer_b
+        .wait_for_message_type(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+        )
+        .await;
+    wait_for_endpoint(monitoring_addr, "/api/v1/server", r#""reason":"rotation""#).await;
+    // The same connection keeps mining, the next upstream assigning the same extranonce prefix
+    assert!(miner.submit_shares(5, Duration::from_millis(500)).await > 0);
+    sniffer_b
+        .wait_for_message_type(
             MessageDirection::ToUpstream,
             MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
         )
         .await;
+    assert_eq!(miner.extranonce1(), extranonce1.as_slice());
 
-    // now let's force a mempool update, so we trigger a NewExtendedMiningJob message
-    // it's actually directed to the group channel Id, not the aggregated channel Id
-    // nevertheless, tProxy should still submit the share to the aggregated channel Id
-    tp.create_mempool_transaction().unwrap();
-
-    sniffer
+    // second rotation, wrapping around to the first upstream
+    sniffer_a
+        .wait_for_message_type(MessageDirection::ToUpstream, MESSAGE_TYPE_SETUP_CONNECTION)
+        .await;
+    sniffer_a
         .wait_for_message_type(
-            MessageDirection::ToDownstream,
-            MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
         )
         .await;
-    let new_extended_mining_job = match sniffer.next_message_from_upstream() {
-        Some((_, AnyMessage::Mining(parsers_sv2::Mining::NewExtendedMiningJob(msg)))) => msg,
-        msg => panic!("Expected NewExtendedMiningJob message, found: {:?}", msg),
-    };
-
-    // here we're actually asserting pool behavior, not tProxy
-    // but still good to have, to ensure the global sanity of the test
-    assert_ne!(new_extended_mining_job.channel_id, aggregated_channel_id);
-    assert_eq!(new_extended_mining_job.channel_id, group_channel_id);
-
-    loop {
-        sniffer
-            .wait_for_message_type(
-                MessageDirection::ToUpstream,
-                MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
-            )
-            .await;
-        let submit_shares_extended = match sniffer.next_message_from_downstream() {
-            Some((_, AnyMessage::Mining(parsers_sv2::Mining::SubmitSharesExtended(msg)))) => msg,
-            msg => panic!("Expected SubmitSharesExtended message, found: {:?}", msg),
-        };
-
-        // assert the share is submitted to the aggregated channel Id
-        assert_eq!(submit_shares_extended.channel_id, aggregated_channel_id);
-        assert_ne!(submit_shares_extended.channel_id, group_channel_id);
-
-        if submit_shares_extended.job_id == 2 {
-            break;
-        }
-    }
-
-    // now let's force a chain tip update, so we trigger a SetNewPrevHash + NewExtendedMiningJob
-    // message pair
-    tp.generate_blocks(1);
-
-    sniffer
+    assert!(miner.submit_shares(5, Duration::from_millis(500)).await > 0);
+    sniffer_a
         .wait_for_message_type(
-            MessageDirection::ToDownstream,
-            MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
         )
         .await;
-    let new_extended_mining_job = match sniffer.next_message_from_upstream() {
-        Some((_, AnyMessage::Mining(parsers_sv2::Mining::NewExtendedMiningJob(msg)))) => msg,
-        msg => panic!("Expected NewExtendedMiningJob message, found: {:?}", msg),
-    };
+}
 
-    // again, asserting pool behavior, not tProxy
-    // just to ensure the global sanity of the test
-    assert_ne!(new_extended_mining_job.channel_id, aggregated_channel_id);
-    assert_eq!(new_extended_mining_job.channel_id, group_channel_id);
+// Verifies that the SV1 server closes connections from a source IP beyond its per-IP cap while
+// keeping the admitted ones, and reports the rejections in `sv2_connections_rejected_total`.
+#[tokio::test]
+async fn translator_rejects_connections_beyond_per_ip_limit() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (pool_translator_sniffer, pool_translator_sniffer_addr) =
+        start_sniffer("0", pool_addr, false, vec![], None);
 
-    sniffer
+    let monitoring_addr = get_available_address();
+    let config =
+        sv2_translator_config(&[pool_translator_sniffer_addr], false, vec![], vec![], None)
+            .await
+            .with_monitoring(monitoring_addr, 1)
+            .with_connection_limits(2, 0);
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    pool_translator_sniffer
         .wait_for_message_type(
             MessageDirection::ToDownstream,
-            MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
         )
         .await;
-    let set_new_prev_hash = match sniffer.next_message_from_upstream() {
-        Some((_, AnyMessage::Mining(parsers_sv2::Mining::SetNewPrevHash(msg)))) => msg,
-        msg => panic!("Expected SetNewPrevHash message, found: {:?}", msg),
-    };
-
-    // again, asserting pool behavior, not tProxy
-    // just to ensure the global sanity of the test
-    assert_eq!(set_new_prev_hash.channel_id, group_channel_id);
-    assert_ne!(set_new_prev_hash.channel_id, aggregated_channel_id);
-
-    loop {
-        sniffer
-            .wait_for_message_type(
-                MessageDirection::ToUpstream,
-                MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
-            )
-            .await;
-        let submit_shares_extended = match sniffer.next_message_from_downstream() {
-            Some((_, AnyMessage::Mining(parsers_sv2::Mining::SubmitSharesExtended(msg)))) => msg,
-            msg => panic!("Expected SubmitSharesExtended message, found: {:?}", msg),
-        };
-
-        // assert the share is submitted to the aggregated channel Id
-        assert_eq!(submit_shares_extended.channel_id, aggregated_channel_id);
-        assert_ne!(submit_shares_extended.channel_id, group_channel_id);
 
-        if submit_shares_extended.job_id == 3 {
-            break;
+    // All connections come from 127.0.0.1; only the first two are admitted
+    let mut connections = Vec::new();
+    while connections.len() < 6 {
+        match tokio::net::TcpStream::connect(tproxy_addr).await {
+            Ok(stream) => connections.push(stream),
+            Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
         }
     }
+
+    wait_for_metric(
+        monitoring_addr,
+        r#"sv2_connections_rejected_total{reason="per_ip_limit"} 4"#,
+    )
+    .await;
 }
 
-// This test launches a tProxy in non-aggregated mode and leverages a MockUpstream to test the
-// correct functionalities of grouping extended channels.
+// Verifies how the main loop of tProxy reacts to an error of a single miner: a miner that
+// subscribes but never authorizes is disconnected once its grace period is over, while tProxy keeps
+// serving the other miners on the same upstream instead of falling back.
 #[tokio::test]
-async fn non_aggregated_translator_correctly_deals_with_group_channels() {
+async fn translator_disconnects_miner_without_falling_back() {
     start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (pool_translator_sniffer, pool_translator_sniffer_addr) =
+        start_sniffer("0", pool_addr, false, vec![], None);
 
-    let (tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let config =
+        sv2_translator_config(&[pool_translator_sniffer_addr], false, vec![], vec![], None)
+            .await
+            .with_authorize_grace_secs(2);
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    let mut miner = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig01").await;
+    assert!(miner.submit_share().await);
+    pool_translator_sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_SETUP_CONNECTION,
+        )
+        .await;
+
+    let mut idle_miner = tokio::net::TcpStream::connect(tproxy_addr).await.unwrap();
+    idle_miner
+        .write_all(b"{\"id\":1,\"method\":\"mining.subscribe\",\"params\":[\"idle/1.0\"]}\n")
+        .await
+        .unwrap();
+    let mut buf = [0u8; 1024];
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while idle_miner.read(&mut buf).await.is_ok_and(|read| read > 0) {}
+    })
+    .await
+    .expect("The miner that never authorized was not disconnected");
+
+    // the upstream was kept, and the other miner keeps mining on it
+    assert!(miner.submit_share().await);
+    assert!(
+        pool_translator_sniffer
+            .assert_message_not_present(MessageDirection::ToUpstream, MESSAGE_TYPE_SETUP_CONNECTION)
+            .await
+    );
+}
+
+// Verifies that tProxy re-opens its channel with a smaller extranonce size when the pool rejects
+// the configured one as too large, instead of falling back to another upstream.
+#[tokio::test]
+async fn translator_retries_open_channel_with_smaller_extranonce_size() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (pool_translator_sniffer, pool_translator_sniffer_addr) =
+        start_sniffer("0", pool_addr, false, vec![], None);
+
+    let mut config =
+        sv2_translator_config(&[pool_translator_sniffer_addr], false, vec![], vec![], None)
+            .await
+            .with_downstream_extranonce2_size_floor(8);
+    // the pool grants at most 16 bytes of rollable extranonce
+    config.downstream_extranonce2_size = 17;
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+    let (_minerd_process, _minerd_addr) = start_minerd(tproxy_addr, None, None, false).await;
+
+    pool_translator_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_MINING_CHANNEL_ERROR,
+        )
+        .await;
+    pool_translator_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+
+    let mut requested_extranonce_sizes = Vec::new();
+    while let Some((_, message)) = pool_translator_sniffer.next_message_from_downstream() {
+        if let AnyMessage::Mining(parsers_sv2::Mining::OpenExtendedMiningChannel(msg)) = message {
+            requested_extranonce_sizes.push(msg.min_extranonce_size);
+        }
+    }
+    assert_eq!(requested_extranonce_sizes, vec![17, 16]);
+
+    let granted_extranonce_size = loop {
+        match pool_translator_sniffer.next_message_from_upstream() {
+            Some((
+                _,
+                AnyMessage::Mining(parsers_sv2::Mining::OpenExtendedMiningChannelSuccess(msg)),
+            )) => break msg.extranonce_size,
+            Some(_) => continue,
+            None => panic!("Expected OpenExtendedMiningChannelSuccess message"),
+        }
+    };
+    assert_eq!(granted_extranonce_size, 16);
+}
+
+// Verifies that the job-staleness watchdog detects an upstream that keeps the connection alive but
+// stops sending jobs. The first pool's jobs and prevhashes are dropped by the sniffer, so its
+// channel goes silent right after being opened and the translator falls back to the second pool,
+// reporting the stale channel in its metrics.
+#[tokio::test]
+async fn translator_falls_back_when_upstream_jobs_go_stale() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool_1, pool_addr_1) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (_pool_2, pool_addr_2) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+
+    let ignore_new_extended_mining_job = IgnoreMessage::new(
+        MessageDirection::ToDownstream,
+        MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+    );
+    let ignore_set_new_prev_hash = IgnoreMessage::new(
+        MessageDirection::ToDownstream,
+        MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH,
+    );
+    let (silent_sniffer, silent_sniffer_addr) = start_sniffer(
+        "A",
+        pool_addr_1,
+        false,
+        vec![
+            ignore_new_extended_mining_job.into(),
+            ignore_set_new_prev_hash.into(),
+        ],
+        None,
+    );
+    let (fallback_sniffer, fallback_sniffer_addr) =
+        start_sniffer("B", pool_addr_2, false, vec![], None);
+
+    let monitoring_addr = get_available_address();
+    let config = sv2_translator_config(
+        &[silent_sniffer_addr, fallback_sniffer_addr],
+        false,
+        vec![],
+        vec![],
+        None,
+    )
+    .await
+    .with_job_staleness_watchdog(2, true)
+    .with_monitoring(monitoring_addr, 1);
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    let (_minerd_process, _minerd_addr) = start_minerd(tproxy_addr, None, None, false).await;
+
+    silent_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+
+    fallback_sniffer
+        .wait_for_message_type(MessageDirection::ToUpstream, MESSAGE_TYPE_SETUP_CONNECTION)
+        .await;
+    fallback_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+        )
+        .await;
+    fallback_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+        )
+        .await;
+
+    wait_for_metric(monitoring_addr, "sv2_server_stale_job_events_total 1").await;
+}
+
+// This test verifies that the translator sends keepalive jobs to downstream miners when no new
+// jobs are received from upstream, and that shares submitted for keepalive jobs are properly
+// received by the pool. Keepalive job_id(s) use the format `{original_job_id}#{counter}`.
+#[tokio::test]
+async fn test_translator_keepalive_job_sent_and_share_received_by_pool() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::High);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (pool_translator_sniffer, pool_translator_sniffer_addr) =
+        start_sniffer("0", pool_addr, false, vec![], None);
+
+    // Start translator with a short keepalive interval (5 seconds)
+    let keepalive_interval_secs = 5_u16;
+    let (_, tproxy_addr) = start_sv2_translator(
+        &[pool_translator_sniffer_addr],
+        false,
+        vec![],
+        vec![],
+        Some(keepalive_interval_secs),
+    )
+    .await;
+    let (sv1_sniffer, sv1_sniffer_addr) = start_sv1_sniffer(tproxy_addr);
+    let (_minerd_process, _minerd_addr) = start_minerd(sv1_sniffer_addr, None, None, false).await;
+
+    sv1_sniffer
+        .wait_for_message(&["mining.notify"], MessageDirection::ToDownstream)
+        .await;
+
+    pool_translator_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
+        )
+        .await;
+
+    // Wait for keepalive interval plus some buffer time
+    tokio::time::sleep(std::time::Duration::from_secs(
+        keepalive_interval_secs as u64 + 3,
+    ))
+    .await;
+
+    // Wait for a keepalive mining.notify message (job_id contains '#' delimiter)
+    sv1_sniffer
+        .wait_for_keepalive_notify(MessageDirection::ToDownstream)
+        .await;
+
+    // Wait for the share submission success message
+    // This proves the keepalive job was valid and the share was properly mapped
+    pool_translator_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
+        )
+        .await;
+}
+
+// This test verifies that a keepalive interval reloaded into a running translator takes effect
+// without reconnecting its miners. The initial interval is longer than the time the sniffer waits
+// for a keepalive job, so only the reloaded one can produce it.
+#[tokio::test]
+async fn translator_applies_reloaded_keepalive_interval() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::High);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let config = sv2_translator_config(&[pool_addr], false, vec![], vec![], Some(600)).await;
+    let (tproxy, tproxy_addr) = start_sv2_translator_with_config(config.clone());
+    let (sv1_sniffer, sv1_sniffer_addr) = start_sv1_sniffer(tproxy_addr);
+    let (_minerd_process, _minerd_addr) = start_minerd(sv1_sniffer_addr, None, None, false).await;
+
+    sv1_sniffer
+        .wait_for_message(&["mining.notify"], MessageDirection::ToDownstream)
+        .await;
+
+    let mut reloaded_config = config;
+    reloaded_config
+        .downstream_difficulty_config
+        .job_keepalive_interval_secs = 5;
+    tproxy.reload_config(&reloaded_config).unwrap();
+
+    sv1_sniffer
+        .wait_for_keepalive_notify(MessageDirection::ToDownstream)
+        .await;
+}
+
+// This test launches a tProxy in aggregated mode and leverages a MockUpstream to test the correct
+// functionalities of grouping extended channels.
+#[tokio::test]
+async fn aggregated_translator_correctly_deals_with_group_channels() {
+    start_tracing();
+    let (tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
     tp.fund_wallet().unwrap();
 
     // block SubmitSolution messages from arriving to TP
@@ -584,52 +1147,295 @@ async fn non_aggregated_translator_correctly_deals_with_group_channels() {
         vec![ignore_submit_shares_success.into()],
         None,
     );
-    let (_, tproxy_addr) = start_sv2_translator(&[sniffer_addr], false, vec![], vec![], None).await;
+
+    // aggregated tProxy
+    let (_, tproxy_addr) = start_sv2_translator(&[sniffer_addr], true, vec![], vec![], None).await;
+
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+
+    let mut minerd_vec = Vec::new();
+
+    // start the first minerd process, to trigger the first OpenExtendedMiningChannel message
+    let (minerd_process, _minerd_addr) = start_minerd(tproxy_addr, None, None, false).await;
+    minerd_vec.push(minerd_process);
 
     sniffer
         .wait_for_message_type_and_clean_queue(
             MessageDirection::ToUpstream,
-            MESSAGE_TYPE_SETUP_CONNECTION,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+        )
+        .await;
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+
+    // save the aggregated and group channel IDs
+    let (aggregated_channel_id, group_channel_id) = match sniffer.next_message_from_upstream() {
+        Some((
+            _,
+            AnyMessage::Mining(parsers_sv2::Mining::OpenExtendedMiningChannelSuccess(msg)),
+        )) => (msg.channel_id, msg.group_channel_id),
+        msg => panic!(
+            "Expected OpenExtendedMiningChannelSuccess message, found: {:?}",
+            msg
+        ),
+    };
+
+    // wait for the expected NewExtendedMiningJob and SetNewPrevHash messages
+    // and clean the queue
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
         )
         .await;
     sniffer
         .wait_for_message_type_and_clean_queue(
             MessageDirection::ToDownstream,
-            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+            MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH,
         )
         .await;
 
-    const N_EXTENDED_CHANNELS: u32 = 5;
-    const EXPECTED_GROUP_CHANNEL_ID: u32 = 1;
-    let mut minerd_vec = Vec::new();
-    let mut sv1_sniffers = Vec::new();
-    let mut channel_ids = Vec::new();
+    // open a few more extended channels to be aggregated with the first one
+    const N_MINERDS: u32 = 5;
+    for _i in 0..N_MINERDS {
+        let (minerd_process, _minerd_addr) = start_minerd(tproxy_addr, None, None, false).await;
+        minerd_vec.push(minerd_process);
 
-    for _i in 0..N_EXTENDED_CHANNELS {
-        let (sv1_sniffer, sv1_sniffer_addr) = start_sv1_sniffer(tproxy_addr);
-        sv1_sniffers.push(sv1_sniffer);
-        let (minerd, _minerd_addr) = start_minerd(sv1_sniffer_addr, None, None, false).await;
-        minerd_vec.push(minerd);
+        // wait a bit
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+        // assert no furter OpenExtendedMiningChannel messages are sent
         sniffer
-            .wait_for_message_type_and_clean_queue(
+            .assert_message_not_present(
                 MessageDirection::ToUpstream,
                 MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
             )
             .await;
-        sniffer
-            .wait_for_message_type(
-                MessageDirection::ToDownstream,
-                MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
-            )
-            .await;
-        let open_extended_mining_channel_success = match sniffer.next_message_from_upstream() {
-            Some((
-                _,
-                AnyMessage::Mining(parsers_sv2::Mining::OpenExtendedMiningChannelSuccess(msg)),
-            )) => msg,
-            msg => panic!(
-                "Expected OpenExtendedMiningChannelSuccess message, found: {:?}",
-                msg
+    }
+
+    // wait for a SubmitSharesExtended message
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
+        )
+        .await;
+
+    let share_channel_id = match sniffer.next_message_from_downstream() {
+        Some((_, AnyMessage::Mining(parsers_sv2::Mining::SubmitSharesExtended(msg)))) => {
+            msg.channel_id
+        }
+        msg => panic!("Expected SubmitSharesExtended message, found: {:?}", msg),
+    };
+
+    assert_eq!(
+        aggregated_channel_id, share_channel_id,
+        "Share submitted to the correct channel ID"
+    );
+    assert_ne!(
+        share_channel_id, group_channel_id,
+        "Share NOT submitted to the group channel ID"
+    );
+
+    // wait for another share, so we can clean the queue
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
+        )
+        .await;
+
+    // now let's force a mempool update, so we trigger a NewExtendedMiningJob message
+    // it's actually directed to the group channel Id, not the aggregated channel Id
+    // nevertheless, tProxy should still submit the share to the aggregated channel Id
+    tp.create_mempool_transaction().unwrap();
+
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+        )
+        .await;
+    let new_extended_mining_job = match sniffer.next_message_from_upstream() {
+        Some((_, AnyMessage::Mining(parsers_sv2::Mining::NewExtendedMiningJob(msg)))) => msg,
+        msg => panic!("Expected NewExtendedMiningJob message, found: {:?}", msg),
+    };
+
+    // here we're actually asserting pool behavior, not tProxy
+    // but still good to have, to ensure the global sanity of the test
+    assert_ne!(new_extended_mining_job.channel_id, aggregated_channel_id);
+    assert_eq!(new_extended_mining_job.channel_id, group_channel_id);
+
+    loop {
+        sniffer
+            .wait_for_message_type(
+                MessageDirection::ToUpstream,
+                MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
+            )
+            .await;
+        let submit_shares_extended = match sniffer.next_message_from_downstream() {
+            Some((_, AnyMessage::Mining(parsers_sv2::Mining::SubmitSharesExtended(msg)))) => msg,
+            msg => panic!("Expected SubmitSharesExtended message, found: {:?}", msg),
+        };
+
+        // assert the share is submitted to the aggregated channel Id
+        assert_eq!(submit_shares_extended.channel_id, aggregated_channel_id);
+        assert_ne!(submit_shares_extended.channel_id, group_channel_id);
+
+        if submit_shares_extended.job_id == 2 {
+            break;
+        }
+    }
+
+    // now let's force a chain tip update, so we trigger a SetNewPrevHash + NewExtendedMiningJob
+    // message pair
+    tp.generate_blocks(1);
+
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+        )
+        .await;
+    let new_extended_mining_job = match sniffer.next_message_from_upstream() {
+        Some((_, AnyMessage::Mining(parsers_sv2::Mining::NewExtendedMiningJob(msg)))) => msg,
+        msg => panic!("Expected NewExtendedMiningJob message, found: {:?}", msg),
+    };
+
+    // again, asserting pool behavior, not tProxy
+    // just to ensure the global sanity of the test
+    assert_ne!(new_extended_mining_job.channel_id, aggregated_channel_id);
+    assert_eq!(new_extended_mining_job.channel_id, group_channel_id);
+
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH,
+        )
+        .await;
+    let set_new_prev_hash = match sniffer.next_message_from_upstream() {
+        Some((_, AnyMessage::Mining(parsers_sv2::Mining::SetNewPrevHash(msg)))) => msg,
+        msg => panic!("Expected SetNewPrevHash message, found: {:?}", msg),
+    };
+
+    // again, asserting pool behavior, not tProxy
+    // just to ensure the global sanity of the test
+    assert_eq!(set_new_prev_hash.channel_id, group_channel_id);
+    assert_ne!(set_new_prev_hash.channel_id, aggregated_channel_id);
+
+    loop {
+        sniffer
+            .wait_for_message_type(
+                MessageDirection::ToUpstream,
+                MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
+            )
+            .await;
+        let submit_shares_extended = match sniffer.next_message_from_downstream() {
+            Some((_, AnyMessage::Mining(parsers_sv2::Mining::SubmitSharesExtended(msg)))) => msg,
+            msg => panic!("Expected SubmitSharesExtended message, found: {:?}", msg),
+        };
+
+        // assert the share is submitted to the aggregated channel Id
+        assert_eq!(submit_shares_extended.channel_id, aggregated_channel_id);
+        assert_ne!(submit_shares_extended.channel_id, group_channel_id);
+
+        if submit_shares_extended.job_id == 3 {
+            break;
+        }
+    }
+}
+
+// This test launches a tProxy in non-aggregated mode and leverages a MockUpstream to test the
+// correct functionalities of grouping extended channels.
+#[tokio::test]
+async fn non_aggregated_translator_correctly_deals_with_group_channels() {
+    start_tracing();
+
+    let (tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    tp.fund_wallet().unwrap();
+
+    // block SubmitSolution messages from arriving to TP
+    // so we avoid shares triggering chain tip updates
+    // which we want to do explicitly via generate_blocks()
+    let ignore_submit_solution =
+        IgnoreMessage::new(MessageDirection::ToUpstream, MESSAGE_TYPE_SUBMIT_SOLUTION);
+    let (_sniffer_pool_tp, sniffer_pool_tp_addr) = start_sniffer(
+        "0",
+        tp_addr,
+        false,
+        vec![ignore_submit_solution.into()],
+        None,
+    );
+
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(sniffer_pool_tp_addr), vec![], vec![]).await;
+
+    // ignore SubmitSharesSuccess messages, so we can keep the assertion flow simple
+    let ignore_submit_shares_success = IgnoreMessage::new(
+        MessageDirection::ToDownstream,
+        MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
+    );
+    let (sniffer, sniffer_addr) = start_sniffer(
+        "0",
+        pool_addr,
+        false,
+        vec![ignore_submit_shares_success.into()],
+        None,
+    );
+    let (_, tproxy_addr) = start_sv2_translator(&[sniffer_addr], false, vec![], vec![], None).await;
+
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_SETUP_CONNECTION,
+        )
+        .await;
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+
+    const N_EXTENDED_CHANNELS: u32 = 5;
+    const EXPECTED_GROUP_CHANNEL_ID: u32 = 1;
+    let mut minerd_vec = Vec::new();
+    let mut sv1_sniffers = Vec::new();
+    let mut channel_ids = Vec::new();
+
+    for _i in 0..N_EXTENDED_CHANNELS {
+        let (sv1_sniffer, sv1_sniffer_addr) = start_sv1_sniffer(tproxy_addr);
+        sv1_sniffers.push(sv1_sniffer);
+        let (minerd, _minerd_addr) = start_minerd(sv1_sniffer_addr, None, None, false).await;
+        minerd_vec.push(minerd);
+        sniffer
+            .wait_for_message_type_and_clean_queue(
+                MessageDirection::ToUpstream,
+                MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+            )
+            .await;
+        sniffer
+            .wait_for_message_type(
+                MessageDirection::ToDownstream,
+                MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+            )
+            .await;
+        let open_extended_mining_channel_success = match sniffer.next_message_from_upstream() {
+            Some((
+                _,
+                AnyMessage::Mining(parsers_sv2::Mining::OpenExtendedMiningChannelSuccess(msg)),
+            )) => msg,
+            msg => panic!(
+                "Expected OpenExtendedMiningChannelSuccess message, found: {:?}",
+                msg
             ),
         };
         let channel_id = open_extended_mining_channel_success.channel_id;
@@ -1583,128 +2389,857 @@ async fn translator_does_not_shutdown_on_missing_downstream_channel() {
         )
         .await;
 
-    let set_new_prev_hash =
-        AnyMessage::Mining(parsers_sv2::Mining::SetNewPrevHash(SetNewPrevHash {
-            channel_id: 0,
-            job_id: 1,
-            prev_hash: hex::decode(
-                "3ab7089cd2cd30f133552cfde82c4cb239cd3c2310306f9d825e088a1772cc39",
-            )
-            .unwrap()
-            .try_into()
-            .unwrap(),
-            min_ntime: 1766782170,
-            nbits: 0x207fffff,
-        }));
+    let set_new_prev_hash =
+        AnyMessage::Mining(parsers_sv2::Mining::SetNewPrevHash(SetNewPrevHash {
+            channel_id: 0,
+            job_id: 1,
+            prev_hash: hex::decode(
+                "3ab7089cd2cd30f133552cfde82c4cb239cd3c2310306f9d825e088a1772cc39",
+            )
+            .unwrap()
+            .try_into()
+            .unwrap(),
+            min_ntime: 1766782170,
+            nbits: 0x207fffff,
+        }));
+
+    send_to_tproxy_a.send(set_new_prev_hash).await.unwrap();
+    sniffer_a
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH,
+        )
+        .await;
+
+    // SetTarget message with channel id not present in downstream
+    let set_target = AnyMessage::Mining(parsers_sv2::Mining::SetTarget(SetTarget {
+        channel_id: 5,
+        maximum_target: [0; 32].into(),
+    }));
+    send_to_tproxy_a.send(set_target).await.unwrap();
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    assert!(TcpListener::bind(tproxy_addr).await.is_err());
+}
+
+/// This test verifies that in aggregated mode, a new downstream connection that arrives
+/// between a future NewExtendedMiningJob and its corresponding SetNewPrevHash will correctly
+/// receive the future job and be able to submit shares after SetNewPrevHash activates the job.
+///
+/// This is a regression test for the "Failed to set new prev hash: JobIdNotFound" error
+/// that occurred when new downstream channels were created while a future job was pending.
+///
+/// See: https://github.com/stratum-mining/sv2-apps/issues/223
+#[tokio::test]
+async fn aggregated_translator_handles_downstream_connecting_during_future_job() {
+    start_tracing();
+
+    let mock_upstream_addr = get_available_address();
+    let mock_upstream = MockUpstream::new(mock_upstream_addr, WithSetup::no());
+    let send_to_tproxy = mock_upstream.start().await;
+
+    // ignore SubmitSharesSuccess messages to simplify the test flow
+    let ignore_submit_shares_success = IgnoreMessage::new(
+        MessageDirection::ToDownstream,
+        MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
+    );
+    let (sniffer, sniffer_addr) = start_sniffer(
+        "future_job_test",
+        mock_upstream_addr,
+        false,
+        vec![ignore_submit_shares_success.into()],
+        None,
+    );
+
+    // Start translator in aggregated mode
+    let (_tproxy, tproxy_addr) =
+        start_sv2_translator(&[sniffer_addr], true, vec![], vec![], None).await;
+
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_SETUP_CONNECTION,
+        )
+        .await;
+
+    let setup_connection_success = AnyMessage::Common(CommonMessages::SetupConnectionSuccess(
+        SetupConnectionSuccess {
+            used_version: 2,
+            flags: 0,
+        },
+    ));
+    send_to_tproxy.send(setup_connection_success).await.unwrap();
+
+    // Keep references to minerd processes and SV1 sniffers so they don't get dropped
+    let mut minerd_vec = Vec::new();
+
+    // Start SV1 sniffer for the first miner
+    let (sv1_sniffer_1, sv1_sniffer_addr_1) = start_sv1_sniffer(tproxy_addr);
+
+    // Start the first minerd (through SV1 sniffer) to trigger OpenExtendedMiningChannel
+    let (minerd_process_1, _minerd_addr_1) =
+        start_minerd(sv1_sniffer_addr_1, None, None, false).await;
+    minerd_vec.push(minerd_process_1);
+
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+        )
+        .await;
+
+    let open_extended_mining_channel: OpenExtendedMiningChannel = loop {
+        match sniffer.next_message_from_downstream() {
+            Some((_, AnyMessage::Mining(parsers_sv2::Mining::OpenExtendedMiningChannel(msg)))) => {
+                break msg;
+            }
+            _ => continue,
+        };
+    };
+
+    // Send OpenExtendedMiningChannelSuccess for the aggregated channel
+    let open_extended_mining_channel_success = AnyMessage::Mining(
+        parsers_sv2::Mining::OpenExtendedMiningChannelSuccess(OpenExtendedMiningChannelSuccess {
+            request_id: open_extended_mining_channel.request_id,
+            channel_id: 2, // aggregated channel ID
+            target: hex::decode("0000137c578190689425e3ecf8449a1af39db0aed305d9206f45ac32fe8330fc")
+                .unwrap()
+                .try_into()
+                .unwrap(),
+            // full extranonce has a total of 12 bytes
+            extranonce_size: 8,
+            extranonce_prefix: vec![0x00, 0x01, 0x00, 0x00].try_into().unwrap(),
+            group_channel_id: 1,
+        }),
+    );
+    send_to_tproxy
+        .send(open_extended_mining_channel_success)
+        .await
+        .unwrap();
+
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+
+    // Send a FUTURE job (min_ntime: None) - this job is not active yet!
+    let future_job = AnyMessage::Mining(parsers_sv2::Mining::NewExtendedMiningJob(
+        NewExtendedMiningJob {
+            channel_id: 2,
+            job_id: 1,
+            min_ntime: Sv2Option::new(None), // This makes it a future job!
+            version: 0x20000000,
+            version_rolling_allowed: true,
+            merkle_path: Seq0255::new(vec![]).unwrap(),
+            coinbase_tx_prefix: hex::decode("02000000010000000000000000000000000000000000000000000000000000000000000000ffffffff265200162f5374726174756d2056322053524920506f6f6c2f2f0c").unwrap().try_into().unwrap(),
+            coinbase_tx_suffix: hex::decode("feffffff0200f2052a01000000160014ebe1b7dcc293ccaa0ee743a86f89df8258c208fc0000000000000000266a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf901000000").unwrap().try_into().unwrap(),
+        },
+    ));
+
+    send_to_tproxy.send(future_job).await.unwrap();
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+        )
+        .await;
+
+    // CRITICAL: Start a SECOND minerd BEFORE sending SetNewPrevHash
+    // This is the race condition we're testing - the new downstream connects
+    // while a future job is pending but not yet activated
+
+    // Start SV1 sniffer for the second miner
+    let (sv1_sniffer_2, sv1_sniffer_addr_2) = start_sv1_sniffer(tproxy_addr);
+
+    let (minerd_process_2, _minerd_addr_2) =
+        start_minerd(sv1_sniffer_addr_2, None, None, false).await;
+    minerd_vec.push(minerd_process_2);
+
+    // Give time for the second minerd to connect and the channel to be created
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+
+    // Now send SetNewPrevHash to activate the future job
+    // Without the fix, this would cause "Failed to set new prev hash: JobIdNotFound"
+    // because the second downstream's channel wouldn't have the future job
+    let set_new_prev_hash =
+        AnyMessage::Mining(parsers_sv2::Mining::SetNewPrevHash(SetNewPrevHash {
+            channel_id: 2,
+            job_id: 1,
+            prev_hash: hex::decode(
+                "3ab7089cd2cd30f133552cfde82c4cb239cd3c2310306f9d825e088a1772cc39",
+            )
+            .unwrap()
+            .try_into()
+            .unwrap(),
+            min_ntime: 1766782170,
+            nbits: 0x207fffff,
+        }));
+
+    send_to_tproxy.send(set_new_prev_hash).await.unwrap();
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH,
+        )
+        .await;
+
+    // Verify BOTH miners receive the mining.notify message
+    sv1_sniffer_1
+        .wait_for_message(&["mining.notify"], MessageDirection::ToDownstream)
+        .await;
+    sv1_sniffer_2
+        .wait_for_message(&["mining.notify"], MessageDirection::ToDownstream)
+        .await;
+
+    // Verify BOTH miners submit shares (mining.submit)
+    // This proves both miners are working correctly after the future job was activated
+    sv1_sniffer_1
+        .wait_for_message(&["mining.submit"], MessageDirection::ToUpstream)
+        .await;
+    sv1_sniffer_2
+        .wait_for_message(&["mining.submit"], MessageDirection::ToUpstream)
+        .await;
+}
+
+// This test drives a pool and a translator with the in-process SV1 miner instead of minerd. The
+// miner's shares must be forwarded to the pool by the translator and accepted by the pool.
+#[tokio::test]
+async fn translator_forwards_shares_of_in_process_sv1_miner() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (pool_translator_sniffer, pool_translator_sniffer_addr) =
+        start_sniffer("0", pool_addr, false, vec![], None);
+    let config = sv2_translator_config_with_hashrate(
+        &[pool_translator_sniffer_addr],
+        false,
+        vec![],
+        vec![],
+        None,
+        10_000.0,
+    );
+    let (_, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    let mut miner = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig01").await;
+    let accepted = miner.submit_shares(3, Duration::from_millis(500)).await;
+    assert!(accepted > 0, "no share was accepted by the translator");
+
+    pool_translator_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
+        )
+        .await;
+    pool_translator_sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
+        )
+        .await;
+}
+
+// Verifies that in aggregated mode with `idle_no_downstream_secs` set, the translator closes the
+// upstream channel once no miner has been connected for that long, and opens a new one for the
+// next miner connecting.
+#[tokio::test]
+async fn aggregated_translator_closes_upstream_channel_without_downstreams() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (sniffer, sniffer_addr) = start_sniffer("0", pool_addr, false, vec![], None);
+    let config =
+        sv2_translator_config_with_hashrate(&[sniffer_addr], true, vec![], vec![], None, 10_000.0)
+            .with_idle_no_downstream_secs(3);
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    let mut miner = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig01").await;
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+        )
+        .await;
+    sniffer
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+    assert!(miner.submit_share().await);
+    miner.disconnect();
+
+    sniffer
+        .wait_for_message_type(MessageDirection::ToUpstream, MESSAGE_TYPE_CLOSE_CHANNEL)
+        .await;
+
+    let mut miner = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig01").await;
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+        )
+        .await;
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+    assert!(miner.submit_share().await);
+}
+
+// Verifies that in aggregated mode with `max_downstreams_per_aggregated_channel` set, the
+// translator opens another upstream channel for the miner that would exceed it, and forwards the
+// shares of both miners.
+#[tokio::test]
+async fn aggregated_translator_opens_another_channel_beyond_max_downstreams() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (sniffer, sniffer_addr) = start_sniffer("0", pool_addr, false, vec![], None);
+    let config =
+        sv2_translator_config_with_hashrate(&[sniffer_addr], true, vec![], vec![], None, 10_000.0)
+            .with_max_downstreams_per_aggregated_channel(1);
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    let mut miners = Vec::new();
+    for worker_name in ["user.rig01", "user.rig02"] {
+        miners.push(sv1_miner::MockSv1Miner::connect(tproxy_addr, worker_name).await);
+        sniffer
+            .wait_for_message_type_and_clean_queue(
+                MessageDirection::ToUpstream,
+                MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+            )
+            .await;
+        sniffer
+            .wait_for_message_type_and_clean_queue(
+                MessageDirection::ToDownstream,
+                MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+            )
+            .await;
+    }
+
+    for miner in miners.iter_mut() {
+        assert!(miner.submit_share().await);
+    }
+}
+
+// Verifies that with `on_all_upstreams_failed = "shutdown"` the translator exits once every
+// upstream failed, without ever opening its SV1 listener.
+#[tokio::test]
+async fn translator_shuts_down_once_all_upstreams_failed() {
+    start_tracing();
+    // nothing listens on this address
+    let unreachable_upstream = get_available_address();
+    let config = sv2_translator_config_with_hashrate(
+        &[unreachable_upstream],
+        false,
+        vec![],
+        vec![],
+        None,
+        10_000.0,
+    )
+    .with_on_all_upstreams_failed(AllUpstreamsFailedPolicy::Shutdown, 1);
+    let tproxy_addr = std::net::SocketAddr::new(
+        config.downstream_address.parse().unwrap(),
+        config.downstream_port,
+    );
+
+    let translator = translator_sv2::TranslatorSv2::new(config);
+    tokio::time::timeout(
+        Duration::from_secs(60),
+        translator.run_until(std::future::pending::<()>()),
+    )
+    .await
+    .expect("translator did not shut down after all upstreams failed");
+
+    assert!(TcpListener::bind(tproxy_addr).await.is_ok());
+}
+
+// Verifies that with `on_all_upstreams_failed = "wait_and_retry"` the translator keeps running
+// once every upstream failed, and connects to the upstream as soon as it comes up.
+#[tokio::test]
+async fn translator_waits_and_retries_once_all_upstreams_failed() {
+    start_tracing();
+    let upstream_addr = get_available_address();
+    let config = sv2_translator_config_with_hashrate(
+        &[upstream_addr],
+        false,
+        vec![],
+        vec![],
+        None,
+        10_000.0,
+    )
+    .with_on_all_upstreams_failed(AllUpstreamsFailedPolicy::WaitAndRetry, 1);
+    let tproxy_addr = std::net::SocketAddr::new(
+        config.downstream_address.parse().unwrap(),
+        config.downstream_port,
+    );
+
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let translator = translator_sv2::TranslatorSv2::new(config);
+    let translator_handle = tokio::spawn(translator.run_until(shutdown_rx));
+
+    // every retry of the only upstream fails while it is down
+    tokio::time::sleep(Duration::from_secs(8)).await;
+    assert!(!translator_handle.is_finished());
+    assert!(TcpListener::bind(tproxy_addr).await.is_ok());
+
+    let mock_upstream = MockUpstream::new(
+        upstream_addr,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let _send_to_tproxy = mock_upstream.start().await;
+
+    // the SV1 server only listens once an upstream is connected
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while TcpListener::bind(tproxy_addr).await.is_ok() {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    })
+    .await
+    .expect("translator did not connect to the upstream once it came up");
+
+    shutdown_tx.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(10), translator_handle)
+        .await
+        .expect("translator did not shut down in time")
+        .expect("translator task panicked");
+}
+
+// Verifies that the translator refuses to start with `on_all_upstreams_failed = "solo"`, since it
+// cannot build jobs on its own.
+#[tokio::test]
+async fn translator_refuses_solo_policy() {
+    start_tracing();
+    let config = sv2_translator_config_with_hashrate(
+        &[get_available_address()],
+        false,
+        vec![],
+        vec![],
+        None,
+        10_000.0,
+    )
+    .with_on_all_upstreams_failed(AllUpstreamsFailedPolicy::Solo, 1);
+
+    let translator = translator_sv2::TranslatorSv2::new(config);
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        translator.run_until(std::future::pending::<()>()),
+    )
+    .await
+    .expect("translator started with the solo policy");
+}
+
+// Verifies that SV1 clients are labelled by the first `downstream_groups` rule matching the worker
+// name they authorize with, and that the monitoring summary counts them per group label.
+#[tokio::test]
+async fn translator_groups_downstreams_by_worker_name() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+
+    let monitoring_addr = get_available_address();
+    let config = sv2_translator_config(&[pool_addr], false, vec![], vec![], None)
+        .await
+        .with_monitoring(monitoring_addr, 1)
+        .with_downstream_groups(vec![
+            DownstreamGroupRule::new(r"\.rack(\d+)-", "rack$1".to_string()).unwrap(),
+            DownstreamGroupRule::new(r"\.s19", "s19".to_string()).unwrap(),
+        ]);
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    let mut miners = Vec::new();
+    for worker_name in [
+        "user.rack1-s19-01",
+        "user.rack1-s19-02",
+        "user.rack2-m50-01",
+        "user.s19-03",
+        "user.m50-02",
+    ] {
+        miners.push(sv1_miner::MockSv1Miner::connect(tproxy_addr, worker_name).await);
+    }
+
+    let metrics = wait_for_metric(monitoring_addr, r#"sv1_group_clients{group="rack1"} 2"#).await;
+    assert!(metrics.contains(r#"sv1_group_clients{group="rack2"} 1"#));
+    assert!(metrics.contains(r#"sv1_group_clients{group="s19"} 1"#));
+    assert_eq!(metrics.matches("sv1_group_clients{").count(), 3);
+}
+
+// Verifies that `TranslatorSv2::ready` resolves once the translator is connected to the pool and
+// accepts SV1 miners.
+#[tokio::test]
+async fn translator_ready_resolves_once_serving() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let config = sv2_translator_config(&[pool_addr], false, vec![], vec![], None).await;
+    let (tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    tokio::time::timeout(Duration::from_secs(60), tproxy.ready())
+        .await
+        .expect("translator never became ready");
+    let mut miner = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig01").await;
+    assert!(miner.submit_share().await);
+}
+
+// Verifies that with `upstream_routes`, a worker routed to another upstream than the primary one
+// is moved onto a channel opened there once it authorizes, if its miner subscribed to
+// `mining.set_extranonce`, or reconnects onto it otherwise, and that both upstreams accept the
+// shares of their workers.
+#[tokio::test]
+async fn translator_routes_workers_to_upstreams_by_name() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool_1, pool_addr_1) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (_pool_2, pool_addr_2) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let (sniffer_1, sniffer_addr_1) = start_sniffer("A", pool_addr_1, false, vec![], None);
+    let (sniffer_2, sniffer_addr_2) = start_sniffer("B", pool_addr_2, false, vec![], None);
+
+    let config = sv2_translator_config(
+        &[sniffer_addr_1, sniffer_addr_2],
+        false,
+        vec![],
+        vec![],
+        None,
+    )
+    .await
+    .with_user_identity_template("{worker}".to_string())
+    .with_upstream_routes(vec![
+        UpstreamRouteRule::new(r"\.pool1$", 0).unwrap(),
+        UpstreamRouteRule::new(r"\.pool2$", 1).unwrap(),
+    ]);
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    // the routed upstream is connected alongside the primary one
+    sniffer_2
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+
+    let mut miner_1 = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.pool1").await;
+    let mut miner_2 =
+        sv1_miner::MockSv1Miner::connect_with_extranonce_subscribe(tproxy_addr, "user.pool2").await;
+
+    // the second miner gets a channel on the upstream of its worker, and leaves the one it
+    // opened on the primary upstream
+    sniffer_2
+        .wait_for_message_type_and_clean_queue(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+    assert_eq!(
+        opened_channel_identities(&sniffer_2),
+        vec!["pool2".to_string()]
+    );
+    sniffer_1
+        .wait_for_message_type(MessageDirection::ToUpstream, MESSAGE_TYPE_CLOSE_CHANNEL)
+        .await;
+
+    assert!(miner_1.submit_share().await);
+    // the shares of the jobs notified before the move are rejected as stale
+    tokio::time::timeout(Duration::from_secs(60), async {
+        while !miner_2.submit_share().await {}
+    })
+    .await
+    .expect("no share accepted on the routed upstream");
+    sniffer_2
+        .wait_for_message_type(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
+        )
+        .await;
+
+    // a miner that cannot change its extranonce1 is disconnected once, and reconnects straight
+    // onto the upstream of its worker
+    let miner_3 = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig.pool2").await;
+    drop(miner_3);
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    sniffer_2.clean_queue(MessageDirection::ToUpstream);
+    let mut miner_3 = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig.pool2").await;
+    sniffer_2
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+    assert_eq!(
+        opened_channel_identities(&sniffer_2),
+        vec!["rig.pool2".to_string()]
+    );
+    assert!(miner_3.submit_share().await);
+}
+
+// Returns the user identities of the channels the translator opened through `sniffer` since its
+// queue was last cleaned.
+fn opened_channel_identities(sniffer: &sniffer::Sniffer<'_>) -> Vec<String> {
+    let mut user_identities = Vec::new();
+    while let Some((_, message)) = sniffer.next_message_from_downstream() {
+        if let AnyMessage::Mining(parsers_sv2::Mining::OpenExtendedMiningChannel(msg)) = message {
+            user_identities.push(msg.user_identity.as_utf8_or_hex());
+        }
+    }
+    user_identities
+}
+
+// Verifies that a worker reconnecting from the same IP address within `vardiff_retention_secs`
+// resumes the difficulty vardiff converged to before it disconnected, instead of the minimum one.
+#[tokio::test]
+async fn translator_restores_vardiff_state_on_reconnect() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    // the miner submits shares far faster than this hashrate accounts for, so vardiff raises its
+    // difficulty on its next cycle
+    let config =
+        sv2_translator_config_with_hashrate(&[pool_addr], false, vec![], vec![], Some(1), 1_000.0)
+            .with_vardiff_retention(300);
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    let mut miner = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig01").await;
+    miner.submit_share().await;
+    let initial_difficulty = miner.difficulty().unwrap();
+    tokio::time::timeout(Duration::from_secs(180), async {
+        while miner.difficulty() == Some(initial_difficulty) {
+            miner.submit_share().await;
+        }
+    })
+    .await
+    .expect("vardiff did not adjust the difficulty");
+    let converged_difficulty = miner.difficulty().unwrap();
+    assert!(converged_difficulty > initial_difficulty);
+    drop(miner);
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let mut miner = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig01").await;
+    miner.submit_share().await;
+    let restored_difficulty = miner.difficulty().unwrap();
+    assert!(
+        (restored_difficulty - converged_difficulty).abs() <= converged_difficulty * 1e-6,
+        "restored difficulty {restored_difficulty} instead of {converged_difficulty}"
+    );
+}
+
+// Verifies that a newly connected miner keeps its initial difficulty for `vardiff_warmup_secs`,
+// even across a vardiff cycle, and that vardiff adjusts it once the warmup is over.
+#[tokio::test]
+async fn translator_holds_initial_difficulty_during_vardiff_warmup() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    // the miner submits shares far faster than this hashrate accounts for, so vardiff would raise
+    // its difficulty on its first cycle
+    let mut config =
+        sv2_translator_config_with_hashrate(&[pool_addr], false, vec![], vec![], Some(1), 1_000.0);
+    config.downstream_difficulty_config.vardiff_warmup_secs = 90;
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    let mut miner = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig01").await;
+    miner.submit_share().await;
+    let initial_difficulty = miner.difficulty().unwrap();
+    // vardiff runs every 60 seconds, so one cycle falls within the warmup
+    let warmup_end = tokio::time::Instant::now() + Duration::from_secs(75);
+    while tokio::time::Instant::now() < warmup_end {
+        miner.submit_share().await;
+        assert_eq!(
+            miner.difficulty(),
+            Some(initial_difficulty),
+            "difficulty adjusted during the warmup"
+        );
+    }
+
+    tokio::time::timeout(Duration::from_secs(120), async {
+        while miner.difficulty() == Some(initial_difficulty) {
+            miner.submit_share().await;
+        }
+    })
+    .await
+    .expect("vardiff did not adjust the difficulty after the warmup");
+    assert!(miner.difficulty().unwrap() > initial_difficulty);
+}
+
+// Verifies that a miner presenting its session id in `mining.subscribe` within
+// `session_resumption_secs` resumes its still open channel with the same extranonce1, while a
+// miner without a session id gets a channel of its own.
+#[tokio::test]
+async fn translator_resumes_sv1_session_with_same_extranonce1() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let config = sv2_translator_config(&[pool_addr], false, vec![], vec![], None)
+        .await
+        .with_session_resumption(300);
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    let mut miner = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig01").await;
+    assert!(miner.submit_share().await);
+    let extranonce1 = miner.extranonce1().to_vec();
+    let session_id = miner
+        .session_id()
+        .expect("mining.subscribe response without session id")
+        .to_string();
+    miner.disconnect();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let mut miner = sv1_miner::MockSv1Miner::connect_with_session_id(
+        tproxy_addr,
+        "user.rig01",
+        Some(&session_id),
+    )
+    .await;
+    assert_eq!(miner.extranonce1(), extranonce1.as_slice());
+    assert_eq!(miner.session_id(), Some(session_id.as_str()));
+    assert!(miner.submit_share().await);
+
+    let other_miner = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig02").await;
+    assert_ne!(other_miner.extranonce1(), extranonce1.as_slice());
+}
+
+// Verifies that in aggregated mode a worker reconnecting from the same address within
+// `channel_affinity_secs`, presenting its session id, rejoins the channel it left with the same
+// extranonce1, while another worker gets a channel of its own.
+#[tokio::test]
+async fn translator_reconnecting_worker_rejoins_its_aggregated_channel() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let config = sv2_translator_config(&[pool_addr], true, vec![], vec![], None)
+        .await
+        .with_channel_affinity(300);
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+
+    let mut miner = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig01").await;
+    assert!(miner.submit_share().await);
+    let extranonce1 = miner.extranonce1().to_vec();
+    let session_id = miner
+        .session_id()
+        .expect("mining.subscribe response without session id")
+        .to_string();
+    miner.disconnect();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let other_miner = sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig02").await;
+    assert_ne!(other_miner.extranonce1(), extranonce1.as_slice());
+
+    let mut miner = sv1_miner::MockSv1Miner::connect_with_session_id(
+        tproxy_addr,
+        "user.rig01",
+        Some(&session_id),
+    )
+    .await;
+    assert_eq!(miner.extranonce1(), extranonce1.as_slice());
+    assert!(miner.submit_share().await);
+}
+
+// The upstream redirects the translator with a `Reconnect` to an endpoint of its allowlist, and
+// the translator moves over to it.
+#[tokio::test]
+async fn translator_follows_reconnect_to_allowed_endpoint() {
+    start_tracing();
+    let mock_upstream_addr_a = get_available_address();
+    let mock_upstream_a = MockUpstream::new(
+        mock_upstream_addr_a,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let send_to_tproxy_a = mock_upstream_a.start().await;
+    let (sniffer_a, sniffer_addr_a) = start_sniffer("A", mock_upstream_addr_a, false, vec![], None);
+
+    // the endpoint the upstream redirects to
+    let mock_upstream_addr_b = get_available_address();
+    let mock_upstream_b = MockUpstream::new(
+        mock_upstream_addr_b,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let _send_to_tproxy_b = mock_upstream_b.start().await;
+    let (sniffer_b, sniffer_addr_b) = start_sniffer("B", mock_upstream_addr_b, false, vec![], None);
+
+    let mut config = sv2_translator_config(&[sniffer_addr_a], true, vec![], vec![], None).await;
+    config.upstreams = config
+        .upstreams
+        .into_iter()
+        .map(|upstream| upstream.with_reconnect_allowlist(vec![sniffer_addr_b.to_string()]))
+        .collect();
+    let (_tproxy, _tproxy_addr) = start_sv2_translator_with_config(config);
 
-    send_to_tproxy_a.send(set_new_prev_hash).await.unwrap();
     sniffer_a
-        .wait_for_message_type_and_clean_queue(
+        .wait_for_message_type(
             MessageDirection::ToDownstream,
-            MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
         )
         .await;
 
-    // SetTarget message with channel id not present in downstream
-    let set_target = AnyMessage::Mining(parsers_sv2::Mining::SetTarget(SetTarget {
-        channel_id: 5,
-        maximum_target: [0; 32].into(),
+    let reconnect = AnyMessage::Common(CommonMessages::Reconnect(Reconnect {
+        new_host: sniffer_addr_b.ip().to_string().try_into().unwrap(),
+        new_port: sniffer_addr_b.port(),
     }));
-    send_to_tproxy_a.send(set_target).await.unwrap();
-
-    tokio::time::sleep(Duration::from_secs(1)).await;
+    send_to_tproxy_a.send(reconnect).await.unwrap();
 
-    assert!(TcpListener::bind(tproxy_addr).await.is_err());
+    sniffer_b
+        .wait_for_message_type(MessageDirection::ToUpstream, MESSAGE_TYPE_SETUP_CONNECTION)
+        .await;
+    sniffer_b
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
 }
 
-/// This test verifies that in aggregated mode, a new downstream connection that arrives
-/// between a future NewExtendedMiningJob and its corresponding SetNewPrevHash will correctly
-/// receive the future job and be able to submit shares after SetNewPrevHash activates the job.
-///
-/// This is a regression test for the "Failed to set new prev hash: JobIdNotFound" error
-/// that occurred when new downstream channels were created while a future job was pending.
-///
-/// See: https://github.com/stratum-mining/sv2-apps/issues/223
+// Verifies that in aggregated mode a `SetTarget` the upstream sends to the group channel, rather
+// than to the aggregated channel, reaches every SV1 miner as a `mining.set_difficulty`.
 #[tokio::test]
-async fn aggregated_translator_handles_downstream_connecting_during_future_job() {
+async fn aggregated_translator_applies_group_channel_set_target_to_all_miners() {
     start_tracing();
-
     let mock_upstream_addr = get_available_address();
-    let mock_upstream = MockUpstream::new(mock_upstream_addr, WithSetup::no());
-    let send_to_tproxy = mock_upstream.start().await;
-
-    // ignore SubmitSharesSuccess messages to simplify the test flow
-    let ignore_submit_shares_success = IgnoreMessage::new(
-        MessageDirection::ToDownstream,
-        MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
-    );
-    let (sniffer, sniffer_addr) = start_sniffer(
-        "future_job_test",
+    let mock_upstream = MockUpstream::new(
         mock_upstream_addr,
-        false,
-        vec![ignore_submit_shares_success.into()],
-        None,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
     );
+    let send_to_tproxy = mock_upstream.start().await;
+    let (sniffer, sniffer_addr) = start_sniffer("", mock_upstream_addr, false, vec![], None);
 
-    // Start translator in aggregated mode
-    let (_tproxy, tproxy_addr) =
-        start_sv2_translator(&[sniffer_addr], true, vec![], vec![], None).await;
-
-    sniffer
-        .wait_for_message_type_and_clean_queue(
-            MessageDirection::ToUpstream,
-            MESSAGE_TYPE_SETUP_CONNECTION,
-        )
-        .await;
-
-    let setup_connection_success = AnyMessage::Common(CommonMessages::SetupConnectionSuccess(
-        SetupConnectionSuccess {
-            used_version: 2,
-            flags: 0,
-        },
-    ));
-    send_to_tproxy.send(setup_connection_success).await.unwrap();
-
-    // Keep references to minerd processes and SV1 sniffers so they don't get dropped
-    let mut minerd_vec = Vec::new();
-
-    // Start SV1 sniffer for the first miner
-    let (sv1_sniffer_1, sv1_sniffer_addr_1) = start_sv1_sniffer(tproxy_addr);
+    // vardiff would keep the difficulty of each miner to itself, the upstream one only bounding it
+    let mut config =
+        sv2_translator_config_with_hashrate(&[sniffer_addr], true, vec![], vec![], None, 1_000.0);
+    config.downstream_difficulty_config.enable_vardiff = false;
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
 
-    // Start the first minerd (through SV1 sniffer) to trigger OpenExtendedMiningChannel
-    let (minerd_process_1, _minerd_addr_1) =
-        start_minerd(sv1_sniffer_addr_1, None, None, false).await;
-    minerd_vec.push(minerd_process_1);
+    const AGGREGATED_CHANNEL_ID: u32 = 1;
+    const GROUP_CHANNEL_ID: u32 = 100;
 
+    // the first miner makes the translator open the aggregated channel
+    let first_miner = tokio::spawn(sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig00"));
     sniffer
         .wait_for_message_type(
             MessageDirection::ToUpstream,
             MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
         )
         .await;
-
     let open_extended_mining_channel: OpenExtendedMiningChannel = loop {
-        match sniffer.next_message_from_downstream() {
-            Some((_, AnyMessage::Mining(parsers_sv2::Mining::OpenExtendedMiningChannel(msg)))) => {
-                break msg;
-            }
-            _ => continue,
-        };
+        if let Some((_, AnyMessage::Mining(parsers_sv2::Mining::OpenExtendedMiningChannel(msg)))) =
+            sniffer.next_message_from_downstream()
+        {
+            break msg;
+        }
     };
-
-    // Send OpenExtendedMiningChannelSuccess for the aggregated channel
     let open_extended_mining_channel_success = AnyMessage::Mining(
         parsers_sv2::Mining::OpenExtendedMiningChannelSuccess(OpenExtendedMiningChannelSuccess {
             request_id: open_extended_mining_channel.request_id,
-            channel_id: 2, // aggregated channel ID
+            channel_id: AGGREGATED_CHANNEL_ID,
             target: hex::decode("0000137c578190689425e3ecf8449a1af39db0aed305d9206f45ac32fe8330fc")
                 .unwrap()
                 .try_into()
                 .unwrap(),
-            // full extranonce has a total of 12 bytes
-            extranonce_size: 8,
-            extranonce_prefix: vec![0x00, 0x01, 0x00, 0x00].try_into().unwrap(),
-            group_channel_id: 1,
+            // full extranonce has a total of 8 bytes
+            extranonce_size: 6,
+            extranonce_prefix: vec![0x00, 0x01].try_into().unwrap(),
+            group_channel_id: GROUP_CHANNEL_ID,
         }),
     );
     send_to_tproxy
@@ -1712,55 +3247,21 @@ async fn aggregated_translator_handles_downstream_connecting_during_future_job()
         .await
         .unwrap();
 
-    sniffer
-        .wait_for_message_type_and_clean_queue(
-            MessageDirection::ToDownstream,
-            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
-        )
-        .await;
-
-    // Send a FUTURE job (min_ntime: None) - this job is not active yet!
-    let future_job = AnyMessage::Mining(parsers_sv2::Mining::NewExtendedMiningJob(
-        NewExtendedMiningJob {
-            channel_id: 2,
-            job_id: 1,
-            min_ntime: Sv2Option::new(None), // This makes it a future job!
-            version: 0x20000000,
-            version_rolling_allowed: true,
-            merkle_path: Seq0255::new(vec![]).unwrap(),
-            coinbase_tx_prefix: hex::decode("02000000010000000000000000000000000000000000000000000000000000000000000000ffffffff265200162f5374726174756d2056322053524920506f6f6c2f2f0c").unwrap().try_into().unwrap(),
-            coinbase_tx_suffix: hex::decode("feffffff0200f2052a01000000160014ebe1b7dcc293ccaa0ee743a86f89df8258c208fc0000000000000000266a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf901000000").unwrap().try_into().unwrap(),
-        },
-    ));
-
-    send_to_tproxy.send(future_job).await.unwrap();
-    sniffer
-        .wait_for_message_type_and_clean_queue(
-            MessageDirection::ToDownstream,
-            MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
-        )
-        .await;
-
-    // CRITICAL: Start a SECOND minerd BEFORE sending SetNewPrevHash
-    // This is the race condition we're testing - the new downstream connects
-    // while a future job is pending but not yet activated
-
-    // Start SV1 sniffer for the second miner
-    let (sv1_sniffer_2, sv1_sniffer_addr_2) = start_sv1_sniffer(tproxy_addr);
-
-    let (minerd_process_2, _minerd_addr_2) =
-        start_minerd(sv1_sniffer_addr_2, None, None, false).await;
-    minerd_vec.push(minerd_process_2);
-
-    // Give time for the second minerd to connect and the channel to be created
-    tokio::time::sleep(Duration::from_millis(1000)).await;
-
-    // Now send SetNewPrevHash to activate the future job
-    // Without the fix, this would cause "Failed to set new prev hash: JobIdNotFound"
-    // because the second downstream's channel wouldn't have the future job
+    let new_extended_mining_job = AnyMessage::Mining(parsers_sv2::Mining::NewExtendedMiningJob(NewExtendedMiningJob {
+        channel_id: GROUP_CHANNEL_ID,
+        job_id: 1,
+        min_ntime: Sv2Option::new(None),
+        version: 0x20000000,
+        version_rolling_allowed: true,
+        merkle_path: Seq0255::new(vec![]).unwrap(),
+        // scriptSig for a total of 8 bytes of extranonce
+        coinbase_tx_prefix: hex::decode("02000000010000000000000000000000000000000000000000000000000000000000000000ffffffff225200162f5374726174756d2056322053524920506f6f6c2f2f08").unwrap().try_into().unwrap(),
+        coinbase_tx_suffix: hex::decode("feffffff0200f2052a01000000160014ebe1b7dcc293ccaa0ee743a86f89df8258c208fc0000000000000000266a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf901000000").unwrap().try_into().unwrap(),
+    }));
+    send_to_tproxy.send(new_extended_mining_job).await.unwrap();
     let set_new_prev_hash =
         AnyMessage::Mining(parsers_sv2::Mining::SetNewPrevHash(SetNewPrevHash {
-            channel_id: 2,
+            channel_id: GROUP_CHANNEL_ID,
             job_id: 1,
             prev_hash: hex::decode(
                 "3ab7089cd2cd30f133552cfde82c4cb239cd3c2310306f9d825e088a1772cc39",
@@ -1771,29 +3272,143 @@ async fn aggregated_translator_handles_downstream_connecting_during_future_job()
             min_ntime: 1766782170,
             nbits: 0x207fffff,
         }));
-
     send_to_tproxy.send(set_new_prev_hash).await.unwrap();
-    sniffer
-        .wait_for_message_type_and_clean_queue(
+
+    let mut miners = vec![first_miner.await.unwrap()];
+    for worker_name in ["user.rig01", "user.rig02"] {
+        miners.push(sv1_miner::MockSv1Miner::connect(tproxy_addr, worker_name).await);
+    }
+
+    let set_target = AnyMessage::Mining(parsers_sv2::Mining::SetTarget(SetTarget {
+        channel_id: GROUP_CHANNEL_ID,
+        maximum_target: stratum_apps::stratum_core::bitcoin::Target::MAX
+            .to_le_bytes()
+            .into(),
+    }));
+    send_to_tproxy.send(set_target).await.unwrap();
+
+    for miner in miners.iter_mut() {
+        let difficulty =
+            tokio::time::timeout(Duration::from_secs(10), miner.wait_for_difficulty_change())
+                .await
+                .expect("miner did not receive the group channel difficulty");
+        assert!(
+            (difficulty - 1.0).abs() < 1e-6,
+            "miner got difficulty {difficulty} instead of 1"
+        );
+    }
+}
+
+// Verifies that the translator retries binding its SV1 listener while the address is still in
+// use, as on a fast restart, instead of failing its startup.
+#[tokio::test]
+async fn translator_binds_sv1_listener_once_its_address_is_released() {
+    start_tracing();
+    let (_tp, tp_addr) = start_template_provider(None, DifficultyLevel::Low);
+    let (_pool, pool_addr) = start_pool(sv2_tp_config(tp_addr), vec![], vec![]).await;
+    let config = sv2_translator_config(&[pool_addr], false, vec![], vec![], None)
+        .await
+        .with_bind_retry(100, 100);
+
+    // the previous process still holding the address
+    let held =
+        std::net::TcpListener::bind((config.downstream_address.as_str(), config.downstream_port))
+            .unwrap();
+    let (_tproxy, tproxy_addr) = start_sv2_translator_with_config(config);
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    drop(held);
+
+    let mut miner = tokio::time::timeout(
+        Duration::from_secs(30),
+        sv1_miner::MockSv1Miner::connect(tproxy_addr, "user.rig01"),
+    )
+    .await
+    .expect("translator did not bind its SV1 listener");
+    assert!(miner.submit_share().await);
+}
+
+// Verifies that with the `fail_over` policy, a `SetupConnectionSuccess` with flags the translator
+// does not know makes it fail over to the next upstream.
+#[tokio::test]
+async fn translator_fails_over_on_unknown_setup_connection_flags_when_asked_to() {
+    start_tracing();
+    let unknown_flag = 1 << 3;
+
+    let mock_upstream_addr_a = get_available_address();
+    let mock_upstream_a = MockUpstream::new(
+        mock_upstream_addr_a,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, unknown_flag),
+    );
+    let _send_to_tproxy_a = mock_upstream_a.start().await;
+    let (sniffer_a, sniffer_addr_a) = start_sniffer("", mock_upstream_addr_a, false, vec![], None);
+
+    let mock_upstream_addr_b = get_available_address();
+    let mock_upstream_b = MockUpstream::new(
+        mock_upstream_addr_b,
+        WithSetup::yes_with_defaults(Protocol::MiningProtocol, 0),
+    );
+    let _send_to_tproxy_b = mock_upstream_b.start().await;
+    let (sniffer_b, sniffer_addr_b) = start_sniffer("", mock_upstream_addr_b, false, vec![], None);
+
+    let config = sv2_translator_config(
+        &[sniffer_addr_a, sniffer_addr_b],
+        false,
+        vec![],
+        vec![],
+        None,
+    )
+    .await
+    .with_setup_connection_flags_policy(SetupConnectionFlagsPolicy::FailOver);
+    let (_tproxy, _tproxy_addr) = start_sv2_translator_with_config(config);
+
+    sniffer_a
+        .wait_for_message_type(
             MessageDirection::ToDownstream,
-            MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
         )
         .await;
-
-    // Verify BOTH miners receive the mining.notify message
-    sv1_sniffer_1
-        .wait_for_message(&["mining.notify"], MessageDirection::ToDownstream)
-        .await;
-    sv1_sniffer_2
-        .wait_for_message(&["mining.notify"], MessageDirection::ToDownstream)
+    sniffer_b
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
         .await;
+}
 
-    // Verify BOTH miners submit shares (mining.submit)
-    // This proves both miners are working correctly after the future job was activated
-    sv1_sniffer_1
-        .wait_for_message(&["mining.submit"], MessageDirection::ToUpstream)
+// Verifies that with the default policy, a `SetupConnectionSuccess` refusing version rolling,
+// which the spec allows, and setting flags the translator does not know is only warned about, and
+// channels are opened with the single upstream.
+#[tokio::test]
+async fn translator_proceeds_on_unexpected_setup_connection_flags_by_default() {
+    start_tracing();
+    let requires_fixed_version = 1 << 0;
+    let unknown_flag = 1 << 3;
+
+    let mock_upstream_addr = get_available_address();
+    let mock_upstream = MockUpstream::new(
+        mock_upstream_addr,
+        WithSetup::yes_with_defaults(
+            Protocol::MiningProtocol,
+            requires_fixed_version | unknown_flag,
+        ),
+    );
+    let _send_to_tproxy = mock_upstream.start().await;
+    let (sniffer, sniffer_addr) = start_sniffer("", mock_upstream_addr, false, vec![], None);
+
+    let (_tproxy, tproxy_addr) =
+        start_sv2_translator(&[sniffer_addr], false, vec![], vec![], None).await;
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
         .await;
-    sv1_sniffer_2
-        .wait_for_message(&["mining.submit"], MessageDirection::ToUpstream)
+
+    let (_minerd_process, _minerd_addr) = start_minerd(tproxy_addr, None, None, false).await;
+    sniffer
+        .wait_for_message_type(
+            MessageDirection::ToUpstream,
+            MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+        )
         .await;
 }
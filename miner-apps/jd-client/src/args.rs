@@ -36,6 +36,8 @@ pub fn process_cli_args() -> Result<JobDeclaratorClientConfig, JDCErrorKind> {
         .build()?;
 
     let mut config = settings.try_deserialize::<JobDeclaratorClientConfig>()?;
+    config.validate_coinbase_outputs()?;
+    config.validate_work_selection()?;
 
     config.set_log_file(args.log_file);
 
@@ -0,0 +1,214 @@
+//! BIP34 guard on the coinbase of the jobs built from templates.
+//!
+//! Since BIP34, a block is only valid if its coinbase `scriptSig` starts with the minimal push of
+//! the block height. The Template Provider puts that push at the start of the template coinbase
+//! prefix. The height of the chain tip is taken from the template its `SetNewPrevHash`
+//! activates, and every later template for that tip, as well as the coinbase of every custom
+//! job, has to commit to it. Jobs failing this check are never sent, since the blocks mined on
+//! them would be rejected by the network.
+
+use stratum_apps::stratum_core::{
+    bitcoin::script::Builder, mining_sv2::SetCustomMiningJob,
+    template_distribution_sv2::NewTemplate,
+};
+
+use crate::error::JDCErrorKind;
+
+const OP_1: u8 = 0x51;
+const OP_16: u8 = 0x60;
+
+/// Returns the block height the template coinbase prefix commits to.
+pub fn template_height(template: &NewTemplate<'_>) -> Result<u64, JDCErrorKind> {
+    let coinbase_prefix = template.coinbase_prefix.inner_as_ref();
+    let height = match coinbase_prefix.split_first() {
+        Some((&op, _)) if (OP_1..=OP_16).contains(&op) => u64::from(op - OP_1 + 1),
+        // a positive script number fits in 8 bytes with the sign bit unset
+        Some((&len @ 1..=8, rest)) => match rest.get(..len as usize) {
+            Some(bytes) if bytes[bytes.len() - 1] & 0x80 == 0 => bytes
+                .iter()
+                .rev()
+                .fold(0, |height, byte| (height << 8) | u64::from(*byte)),
+            _ => return Err(JDCErrorKind::MissingCoinbaseHeight),
+        },
+        _ => return Err(JDCErrorKind::MissingCoinbaseHeight),
+    };
+    // BIP34 only accepts the minimal encoding of the height
+    if coinbase_prefix.starts_with(&height_push(height)) {
+        Ok(height)
+    } else {
+        Err(JDCErrorKind::MissingCoinbaseHeight)
+    }
+}
+
+/// Checks that `template` commits to a block height, and to `chain_tip_height`, the height of the
+/// block mined on the current chain tip, unless it is a future template.
+pub fn check_template_height(
+    template: &NewTemplate<'_>,
+    chain_tip_height: Option<u64>,
+) -> Result<(), JDCErrorKind> {
+    let height = template_height(template)?;
+    match chain_tip_height {
+        Some(chain_tip_height) if !template.future_template && height != chain_tip_height => Err(
+            JDCErrorKind::TemplateHeightMismatch(height, chain_tip_height),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the coinbase of `custom_job` starts with the BIP34 push of `chain_tip_height`, the
+/// height of the block mined on the current chain tip, or of the height of the template it was
+/// built from while the chain tip is unknown.
+pub fn validate_custom_job_height<'a>(
+    template: &NewTemplate<'_>,
+    chain_tip_height: Option<u64>,
+    custom_job: SetCustomMiningJob<'a>,
+) -> Result<SetCustomMiningJob<'a>, JDCErrorKind> {
+    let height = match chain_tip_height {
+        Some(chain_tip_height) => chain_tip_height,
+        None => template_height(template)?,
+    };
+    if custom_job
+        .coinbase_prefix
+        .inner_as_ref()
+        .starts_with(&height_push(height))
+    {
+        Ok(custom_job)
+    } else {
+        Err(JDCErrorKind::CoinbaseHeightMismatch(height))
+    }
+}
+
+// `CScript() << height`, as built by Bitcoin Core.
+fn height_push(height: u64) -> Vec<u8> {
+    Builder::new()
+        .push_int(height as i64)
+        .into_script()
+        .into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use stratum_apps::stratum_core::{
+        binary_sv2::{Seq0255, U256},
+        bitcoin::{Amount, ScriptBuf, TxOut},
+        channels_sv2::server::jobs::factory::JobFactory,
+        template_distribution_sv2::SetNewPrevHash,
+    };
+
+    use super::*;
+
+    fn template(coinbase_prefix: Vec<u8>) -> NewTemplate<'static> {
+        NewTemplate {
+            template_id: 1,
+            future_template: false,
+            version: 0x2000_0000,
+            coinbase_tx_version: 2,
+            coinbase_prefix: coinbase_prefix.try_into().unwrap(),
+            coinbase_tx_input_sequence: u32::MAX,
+            coinbase_tx_value_remaining: 312_500_000,
+            coinbase_tx_outputs_count: 0,
+            coinbase_tx_outputs: Vec::new().try_into().unwrap(),
+            coinbase_tx_locktime: 0,
+            merkle_path: Seq0255::new(Vec::<U256>::new()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn custom_job_carries_template_height() {
+        let height = 840_000;
+        // OP_PUSHBYTES_3 followed by 840000 in little endian
+        let template = template(vec![0x03, 0x40, 0xd1, 0x0c]);
+        assert!(matches!(template_height(&template), Ok(h) if h == height));
+
+        let prev_hash = SetNewPrevHash {
+            template_id: 1,
+            prev_hash: [0u8; 32].into(),
+            header_timestamp: 1_713_571_767,
+            n_bits: 0x1703_4219,
+            target: [0xffu8; 32].into(),
+        };
+        let outputs = vec![TxOut {
+            value: Amount::from_sat(312_500_000),
+            script_pubkey: ScriptBuf::new(),
+        }];
+        let mut job_factory = JobFactory::new(true, "pool".to_string(), Some("miner".to_string()));
+        let custom_job = job_factory
+            .new_custom_job(
+                1,
+                1,
+                vec![0; 8].try_into().unwrap(),
+                prev_hash.into(),
+                template.clone(),
+                outputs,
+                16,
+            )
+            .unwrap();
+
+        let custom_job = validate_custom_job_height(&template, None, custom_job).unwrap();
+        let mut custom_job =
+            validate_custom_job_height(&template, Some(height), custom_job).unwrap();
+        assert!(custom_job
+            .coinbase_prefix
+            .inner_as_ref()
+            .starts_with(&[0x03, 0x40, 0xd1, 0x0c]));
+
+        // a job built on a template of another height than the chain tip is rejected
+        assert!(matches!(
+            validate_custom_job_height(&template, Some(height + 1), custom_job.clone()),
+            Err(JDCErrorKind::CoinbaseHeightMismatch(h)) if h == height + 1
+        ));
+
+        // a coinbase committing to the next height is rejected
+        let mut coinbase_prefix = custom_job.coinbase_prefix.inner_as_ref().to_vec();
+        coinbase_prefix[1] += 1;
+        custom_job.coinbase_prefix = coinbase_prefix.try_into().unwrap();
+        assert!(matches!(
+            validate_custom_job_height(&template, None, custom_job),
+            Err(JDCErrorKind::CoinbaseHeightMismatch(h)) if h == height
+        ));
+    }
+
+    #[test]
+    fn template_checked_against_chain_tip_height() {
+        // OP_PUSHBYTES_3 followed by 840000 in little endian
+        let mut template = template(vec![0x03, 0x40, 0xd1, 0x0c]);
+        assert!(check_template_height(&template, None).is_ok());
+        assert!(check_template_height(&template, Some(840_000)).is_ok());
+        assert!(matches!(
+            check_template_height(&template, Some(840_001)),
+            Err(JDCErrorKind::TemplateHeightMismatch(840_000, 840_001))
+        ));
+
+        // a future template is for the block after the chain tip
+        template.future_template = true;
+        assert!(check_template_height(&template, Some(839_999)).is_ok());
+        assert!(matches!(
+            check_template_height(&self::template(vec![0x00]), None),
+            Err(JDCErrorKind::MissingCoinbaseHeight)
+        ));
+    }
+
+    #[test]
+    fn template_without_minimal_height_push_is_rejected() {
+        // heights up to 16 are pushed with OP_1..OP_16
+        assert!(matches!(
+            template_height(&template(vec![0x5a, 0x00])),
+            Ok(10)
+        ));
+        for coinbase_prefix in [
+            vec![],
+            vec![0x00],
+            // non-minimal push of height 10
+            vec![0x01, 0x0a],
+            // negative number
+            vec![0x01, 0x81],
+            // truncated push
+            vec![0x03, 0x40, 0xd1],
+        ] {
+            assert!(matches!(
+                template_height(&template(coinbase_prefix)),
+                Err(JDCErrorKind::MissingCoinbaseHeight)
+            ));
+        }
+    }
+}
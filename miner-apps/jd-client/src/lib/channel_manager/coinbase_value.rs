@@ -0,0 +1,80 @@
+//! Guard on the value paid by the coinbase outputs of the jobs built from a template.
+//!
+//! A template leaves `coinbase_tx_value_remaining` (subsidy plus fees) for the coinbase outputs,
+//! and a block whose coinbase pays more is invalid. The first coinbase output is the change
+//! output: it gets what is left once the values set on the other outputs are deducted. Outputs
+//! asking for more than the template leaves are rejected before any job is built on them.
+
+use stratum_apps::stratum_core::bitcoin::{Amount, TxOut};
+
+use crate::error::JDCErrorKind;
+
+/// Sets the value of the change output of `outputs`, so that they pay exactly
+/// `coinbase_tx_value_remaining` satoshis.
+pub fn assign_coinbase_value(
+    outputs: &mut [TxOut],
+    coinbase_tx_value_remaining: u64,
+) -> Result<(), JDCErrorKind> {
+    let Some((change, fixed)) = outputs.split_first_mut() else {
+        return Err(JDCErrorKind::ChannelManagerHasBadCoinbaseOutputs);
+    };
+    let fixed_value = fixed
+        .iter()
+        .try_fold(Amount::ZERO, |total, output| {
+            total.checked_add(output.value)
+        })
+        .map(Amount::to_sat);
+    match fixed_value {
+        Some(fixed_value) if fixed_value <= coinbase_tx_value_remaining => {
+            change.value = Amount::from_sat(coinbase_tx_value_remaining - fixed_value);
+            Ok(())
+        }
+        _ => Err(JDCErrorKind::CoinbaseValueMismatch {
+            available: coinbase_tx_value_remaining,
+            fixed: fixed_value.unwrap_or(u64::MAX),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stratum_apps::stratum_core::bitcoin::ScriptBuf;
+
+    use super::*;
+
+    fn output(sats: u64) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(sats),
+            script_pubkey: ScriptBuf::new(),
+        }
+    }
+
+    #[test]
+    fn change_output_takes_the_remaining_value() {
+        let mut outputs = vec![output(0), output(1_000), output(2_000)];
+        assign_coinbase_value(&mut outputs, 312_500_000).unwrap();
+
+        assert_eq!(outputs[0].value, Amount::from_sat(312_497_000));
+        let total: u64 = outputs.iter().map(|output| output.value.to_sat()).sum();
+        assert_eq!(total, 312_500_000);
+    }
+
+    #[test]
+    fn outputs_paying_more_than_the_template_are_rejected() {
+        let mut outputs = vec![output(0), output(200_000_000), output(200_000_000)];
+        assert!(matches!(
+            assign_coinbase_value(&mut outputs, 312_500_000),
+            Err(JDCErrorKind::CoinbaseValueMismatch {
+                available: 312_500_000,
+                fixed: 400_000_000,
+            })
+        ));
+        // the outputs are left untouched
+        assert_eq!(outputs[0].value, Amount::ZERO);
+
+        assert!(matches!(
+            assign_coinbase_value(&mut [], 312_500_000),
+            Err(JDCErrorKind::ChannelManagerHasBadCoinbaseOutputs)
+        ));
+    }
+}
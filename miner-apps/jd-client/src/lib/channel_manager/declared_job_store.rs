@@ -0,0 +1,160 @@
+//! Store of the jobs declared by the JDC that the upstream did not acknowledge yet.
+//!
+//! Declared jobs are removed once the upstream accepts the matching `SetCustomMiningJob`. A job
+//! whose acknowledgement never arrives would otherwise stay in the store forever, so the store
+//! drops the jobs older than [`DECLARED_JOB_TTL`] and, when [`MAX_DECLARED_JOBS`] are already
+//! waiting, the oldest job to make room for a new one.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use stratum_apps::utils::types::RequestId;
+use tracing::debug;
+
+/// Largest number of declared jobs waiting for an acknowledgement.
+pub const MAX_DECLARED_JOBS: usize = 256;
+
+/// How long a declared job waits for an acknowledgement before it is dropped.
+pub const DECLARED_JOB_TTL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug)]
+struct StoredJob<J> {
+    // Insertion order, used to find the oldest job.
+    sequence: u64,
+    declared_at: Instant,
+    job: J,
+}
+
+/// Declared jobs keyed by the `request_id` they were declared with, bounded in size and age.
+#[derive(Debug)]
+pub struct DeclaredJobStore<J> {
+    jobs: HashMap<RequestId, StoredJob<J>>,
+    next_sequence: u64,
+}
+
+impl<J> Default for DeclaredJobStore<J> {
+    fn default() -> Self {
+        Self {
+            jobs: HashMap::new(),
+            next_sequence: 0,
+        }
+    }
+}
+
+impl<J> DeclaredJobStore<J> {
+    /// Stores a job declared with `request_id`, evicting the expired jobs and, if the store is
+    /// full, the oldest one.
+    pub fn insert(&mut self, request_id: RequestId, job: J) {
+        self.insert_at(request_id, job, Instant::now());
+    }
+
+    fn insert_at(&mut self, request_id: RequestId, job: J, now: Instant) {
+        self.jobs.retain(|id, stored| {
+            let expired = now.duration_since(stored.declared_at) >= DECLARED_JOB_TTL;
+            if expired {
+                debug!("Evicting declared job request_id={id}: not acknowledged in time");
+            }
+            !expired
+        });
+        if !self.jobs.contains_key(&request_id) && self.jobs.len() >= MAX_DECLARED_JOBS {
+            if let Some(oldest) = self
+                .jobs
+                .iter()
+                .min_by_key(|(_, stored)| stored.sequence)
+                .map(|(id, _)| *id)
+            {
+                debug!("Evicting declared job request_id={oldest}: store is full");
+                self.jobs.remove(&oldest);
+            }
+        }
+        self.jobs.insert(
+            request_id,
+            StoredJob {
+                sequence: self.next_sequence,
+                declared_at: now,
+                job,
+            },
+        );
+        self.next_sequence += 1;
+    }
+
+    /// Returns the job declared with `request_id`.
+    pub fn get(&self, request_id: &RequestId) -> Option<&J> {
+        self.jobs.get(request_id).map(|stored| &stored.job)
+    }
+
+    /// Returns the job declared with `request_id` for modification.
+    pub fn get_mut(&mut self, request_id: &RequestId) -> Option<&mut J> {
+        self.jobs.get_mut(request_id).map(|stored| &mut stored.job)
+    }
+
+    /// Removes and returns the job declared with `request_id`.
+    pub fn remove(&mut self, request_id: &RequestId) -> Option<J> {
+        self.jobs.remove(request_id).map(|stored| stored.job)
+    }
+
+    /// Keeps only the jobs for which `keep` returns `true`.
+    pub fn retain(&mut self, mut keep: impl FnMut(&RequestId, &J) -> bool) {
+        self.jobs.retain(|id, stored| keep(id, &stored.job));
+    }
+
+    /// Iterates over the stored jobs.
+    pub fn values(&self) -> impl Iterator<Item = &J> {
+        self.jobs.values().map(|stored| &stored.job)
+    }
+
+    /// Iterates over the stored jobs and their `request_id` for modification.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&RequestId, &mut J)> {
+        self.jobs
+            .iter_mut()
+            .map(|(id, stored)| (id, &mut stored.job))
+    }
+
+    /// Removes every stored job.
+    pub fn clear(&mut self) {
+        self.jobs.clear();
+    }
+
+    /// Returns the number of jobs waiting for an acknowledgement.
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unacknowledged_jobs_stay_bounded() {
+        let mut store = DeclaredJobStore::default();
+        let now = Instant::now();
+
+        for request_id in 0..(MAX_DECLARED_JOBS as u32 * 4) {
+            store.insert_at(request_id, request_id, now);
+            assert!(store.len() <= MAX_DECLARED_JOBS);
+        }
+
+        assert_eq!(store.len(), MAX_DECLARED_JOBS);
+        // the oldest declarations were evicted first
+        let first_kept = MAX_DECLARED_JOBS as u32 * 3;
+        assert_eq!(store.get(&(first_kept - 1)), None);
+        assert_eq!(store.get(&first_kept), Some(&first_kept));
+    }
+
+    #[test]
+    fn expired_jobs_are_evicted() {
+        let mut store = DeclaredJobStore::default();
+        let now = Instant::now();
+
+        store.insert_at(1, 1, now);
+        store.insert_at(2, 2, now + DECLARED_JOB_TTL / 2);
+        store.insert_at(3, 3, now + DECLARED_JOB_TTL);
+
+        assert_eq!(store.get(&1), None);
+        assert_eq!(store.get(&2), Some(&2));
+        assert_eq!(store.len(), 2);
+    }
+}
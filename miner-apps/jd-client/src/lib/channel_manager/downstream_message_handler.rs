@@ -3,7 +3,7 @@ use std::sync::atomic::Ordering;
 use stratum_apps::{
     stratum_core::{
         binary_sv2::Str0255,
-        bitcoin::{Amount, Target},
+        bitcoin::Target,
         channels_sv2::{
             client,
             outputs::deserialize_outputs,
@@ -30,7 +30,9 @@ use stratum_apps::{
 use tracing::{debug, error, info, warn};
 
 use crate::{
-    channel_manager::{ChannelManager, ChannelManagerChannel, FULL_EXTRANONCE_SIZE},
+    channel_manager::{
+        coinbase_value::assign_coinbase_value, ChannelManager, ChannelManagerChannel,
+    },
     error::{self, JDCError, JDCErrorKind},
     jd_mode::{get_jd_mode, JdMode},
     utils::create_close_channel_msg,
@@ -285,8 +287,14 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                         ));
                     };
 
-                    coinbase_outputs[0].value =
-                        Amount::from_sat(last_future_template.coinbase_tx_value_remaining);
+                    assign_coinbase_value(
+                        &mut coinbase_outputs,
+                        last_future_template.coinbase_tx_value_remaining,
+                    )
+                    .map_err(|e| {
+                        error!("Cannot open channel on the last future template: {e}");
+                        JDCError::disconnect(e, downstream_id)
+                    })?;
 
                     downstream.downstream_data.super_safe_lock(|data| {
                         let mut messages: Vec<RouteMessageTo> = vec![];
@@ -555,8 +563,8 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                             .upstream_channel
                             .as_ref()
                             .map(|channel| channel.get_full_extranonce_size())
-                            .unwrap_or(FULL_EXTRANONCE_SIZE); // Default to FULL_EXTRANONCE_SIZE if
-                                                              // upstream channel is not present (solo mining mode)
+                            .unwrap_or(self.full_extranonce_size()); // Default to the solo mining size if
+                                                                     // upstream channel is not present
 
                         let rollable_extranonce_size =
                             full_extranonce_size - extranonce_prefix.clone().to_vec().len();
@@ -636,8 +644,14 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                                 ))
                             }
                         };
-                        coinbase_outputs[0].value =
-                            Amount::from_sat(last_future_template.coinbase_tx_value_remaining);
+                        assign_coinbase_value(
+                            &mut coinbase_outputs,
+                            last_future_template.coinbase_tx_value_remaining,
+                        )
+                        .map_err(|e| {
+                            error!("Cannot open channel on the last future template: {e}");
+                            JDCError::disconnect(e, downstream_id)
+                        })?;
 
                         // create a future extended job based on the last future template
                         if let Err(e) = extended_channel
@@ -993,10 +1007,11 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                         is_downstream_share_valid = true;
                     }
                     Ok(ShareValidationResult::BlockFound(share_hash, template_id, coinbase)) => {
-                        info!("SubmitSharesStandard on downstream channel: 💰 Block Found!!! 💰{share_hash}");
+                        info!("SubmitSharesStandard on downstream channel: 💰 Block Found!!! 💰{share_hash} | channel_id: {}, template_id: {:?}", channel_id, template_id);
                         is_downstream_share_valid = true;
                         if let Some(template_id) = template_id {
-                            info!("SubmitSharesStandard: Propagating solution to the Template Provider.");
+                            info!("SubmitSharesStandard: 💰 Submitting block {share_hash} to the Template Provider, accepted once it becomes the chain tip.");
+                            self.found_blocks.record_submitted(share_hash.to_string(), Some(template_id));
                             let solution = SubmitSolution {
                                 template_id,
                                 version: msg.version,
@@ -1006,6 +1021,7 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                             };
 
                             messages.push(TemplateDistribution::SubmitSolution(solution.clone()).into());
+                            self.advance_solo_payout();
                         }
                         let share_accounting = standard_channel.get_share_accounting().clone();
                         let success = SubmitSharesSuccess {
@@ -1209,9 +1225,10 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                         is_downstream_share_valid = true;
                     }
                     Ok(ShareValidationResult::BlockFound(share_hash, template_id, coinbase)) => {
-                        info!("SubmitSharesExtended on downstream channel: 💰 Block Found!!! 💰{share_hash}");
+                        info!("SubmitSharesExtended on downstream channel: 💰 Block Found!!! 💰{share_hash} | channel_id: {}, template_id: {:?}", channel_id, template_id);
                         if let Some(template_id) = template_id {
-                            info!("SubmitSharesExtended: Propagating solution to the Template Provider.");
+                            info!("SubmitSharesExtended: 💰 Submitting block {share_hash} to the Template Provider, accepted once it becomes the chain tip.");
+                            self.found_blocks.record_submitted(share_hash.to_string(), Some(template_id));
                             let solution = SubmitSolution {
                                 template_id,
                                 version: msg.version,
@@ -1220,6 +1237,7 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                                 coinbase_tx: coinbase.try_into().map_err(JDCError::shutdown)?,
                             };
                             messages.push(TemplateDistribution::SubmitSolution(solution.clone()).into());
+                            self.advance_solo_payout();
                         }
                         let share_accounting = extended_channel.get_share_accounting().clone();
                         let success = SubmitSharesSuccess {
@@ -36,14 +36,12 @@ impl HandleExtensionsFromServerAsync for ChannelManager {
 
         info!("Extension negotiation success: supported={:?}", supported);
 
-        // Check if all of the proxy's required extensions are supported by the server
-        let missing_required: Vec<u16> = self
-            .channel_manager_data
-            .super_safe_lock(|data| data.required_extensions.clone())
-            .iter()
-            .filter(|ext| !supported.contains(ext))
-            .copied()
-            .collect();
+        // Store the negotiated extensions in the shared channel manager data, and check if all of
+        // the JDC's required extensions are supported by the server
+        let missing_required = self.channel_manager_data.super_safe_lock(|data| {
+            data.negotiated_extensions = supported.clone();
+            data.missing_required_extensions()
+        });
 
         if !missing_required.is_empty() {
             error!(
@@ -55,11 +53,6 @@ impl HandleExtensionsFromServerAsync for ChannelManager {
             ));
         }
 
-        // Store the negotiated extensions in the shared channel manager data
-        self.channel_manager_data.super_safe_lock(|data| {
-            data.negotiated_extensions = supported.clone();
-        });
-
         info!("Successfully negotiated extensions: {:?}", supported);
 
         Ok(())
@@ -1,3 +1,5 @@
+use std::sync::atomic::Ordering;
+
 use stratum_apps::{
     stratum_core::{
         binary_sv2::{self, Sv2DataType, B016M},
@@ -19,7 +21,7 @@ use stratum_apps::{
 use tracing::{debug, error, info, warn};
 
 use crate::{
-    channel_manager::ChannelManager,
+    channel_manager::{coinbase_height::validate_custom_job_height, ChannelManager},
     error::{self, JDCError, JDCErrorKind},
 };
 
@@ -158,6 +160,11 @@ impl HandleJobDeclarationMessagesFromServerAsync for ChannelManager {
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
 
+        if !self.upstream_work_selection.load(Ordering::Relaxed) {
+            info!("Work selection is disabled for the upstream, not sending the custom job");
+            return Ok(());
+        }
+
         let Some(last_declare_job) = self
             .channel_manager_data
             .super_safe_lock(|data| data.last_declare_job_store.get(&msg.request_id).cloned())
@@ -185,29 +192,39 @@ impl HandleJobDeclarationMessagesFromServerAsync for ChannelManager {
             }
         };
 
-        let Some(custom_job) = self
-            .channel_manager_data
-            .super_safe_lock(|channel_manager_data| {
-                let job_factory = channel_manager_data.job_factory.as_mut()?;
-                let upstream_channel = channel_manager_data.upstream_channel.as_ref()?;
-                let full_extranonce_size = upstream_channel.get_full_extranonce_size();
-                let custom_job = job_factory.new_custom_job(
-                    upstream_channel.get_channel_id(),
-                    msg.request_id,
-                    msg.new_mining_job_token,
-                    prevhash.into(),
-                    last_declare_job.template,
-                    outputs,
-                    full_extranonce_size,
-                );
-                Some(custom_job)
-            })
+        let template = last_declare_job.template;
+        let Some((custom_job, chain_tip_height)) =
+            self.channel_manager_data
+                .super_safe_lock(|channel_manager_data| {
+                    let chain_tip_height = channel_manager_data.chain_tip_height;
+                    let job_factory = channel_manager_data.job_factory.as_mut()?;
+                    let upstream_channel = channel_manager_data.upstream_channel.as_ref()?;
+                    let full_extranonce_size = upstream_channel.get_full_extranonce_size();
+                    let custom_job = job_factory.new_custom_job(
+                        upstream_channel.get_channel_id(),
+                        msg.request_id,
+                        msg.new_mining_job_token,
+                        prevhash.into(),
+                        template.clone(),
+                        outputs,
+                        full_extranonce_size,
+                    );
+                    Some((custom_job, chain_tip_height))
+                })
         else {
             return Err(JDCError::log(JDCErrorKind::FailedToCreateCustomJob));
         };
 
         let custom_job =
             custom_job.map_err(|_e| JDCError::log(JDCErrorKind::FailedToCreateCustomJob))?;
+        let custom_job = validate_custom_job_height(&template, chain_tip_height, custom_job)
+            .map_err(|e| {
+                error!(
+                    "Not sending custom job for request_id = {}: {e}",
+                    msg.request_id
+                );
+                JDCError::log(e)
+            })?;
 
         self.channel_manager_data.super_safe_lock(|data| {
             if let Some(value) = data.last_declare_job_store.get_mut(&msg.request_id) {
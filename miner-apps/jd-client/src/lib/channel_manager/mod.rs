@@ -10,11 +10,18 @@ use std::{
 use async_channel::{Receiver, Sender};
 use stratum_apps::{
     coinbase_output_constraints::coinbase_output_constraints_message,
+    config_helpers::CoinbaseRewardScript,
     custom_mutex::Mutex,
     key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
-    network_helpers::noise_stream::NoiseTcpStream,
+    monitoring::{FailoverEvent, FailoverReason, FoundBlocks},
+    network_helpers::{
+        connection_limiter::{ConnectionLimiter, ConnectionPermit},
+        message_counters::MessageCounters,
+        noise_stream::NoiseTcpStream,
+        slow_consumer::SlowConsumerPolicy,
+    },
     stratum_core::{
-        bitcoin::{Amount, Target, TxOut},
+        bitcoin::{Network, Target, TxOut},
         channels_sv2::{
             client::extended::ExtendedChannel,
             outputs::deserialize_outputs,
@@ -39,7 +46,7 @@ use stratum_apps::{
         },
         mining_sv2::{
             ExtendedExtranonce, OpenExtendedMiningChannel, SetCustomMiningJob, SetTarget,
-            UpdateChannel,
+            UpdateChannel, MAX_EXTRANONCE_LEN,
         },
         noise_sv2::Responder,
         parsers_sv2::{AnyMessage, JobDeclaration, Mining, TemplateDistribution, Tlv},
@@ -47,37 +54,48 @@ use stratum_apps::{
     },
     task_manager::TaskManager,
     utils::{
+        bind_retry::{bind_with_retry, BindRetry},
+        log_throttle::LogThrottle,
         protocol_message_type::{protocol_message_type, MessageType},
         types::{
-            ChannelId, DownstreamId, Message, RequestId, SharesBatchSize, SharesPerMinute,
-            Sv2Frame, TemplateId, UpstreamJobId, VardiffKey,
+            ChannelId, DownstreamId, Message, SharesBatchSize, SharesPerMinute, Sv2Frame,
+            TemplateId, UpstreamJobId, VardiffKey,
         },
     },
 };
-use tokio::{net::TcpListener, select, sync::broadcast};
+use tokio::{select, sync::broadcast};
 use tracing::{debug, error, info, warn};
 
 use crate::{
-    channel_manager::downstream_message_handler::RouteMessageTo,
+    channel_manager::{
+        coinbase_value::assign_coinbase_value, declared_job_store::DeclaredJobStore,
+        downstream_message_handler::RouteMessageTo, pending_custom_jobs::PendingCustomJobs,
+    },
     config::JobDeclaratorClientConfig,
     downstream::Downstream,
     error::{self, JDCError, JDCErrorKind, JDCResult},
+    solo_payout::SoloPayout,
     status::{handle_error, Status, StatusSender},
     utils::{
         AtomicUpstreamState, DownstreamChannelJobId, PendingChannelRequest, ShutdownMessage,
         UpstreamState,
     },
 };
+mod coinbase_height;
+mod coinbase_value;
+mod declared_job_store;
 mod downstream_message_handler;
 mod extensions_message_handler;
 mod jd_message_handler;
+mod pending_custom_jobs;
 mod template_message_handler;
 mod upstream_message_handler;
 
-pub const JDC_SEARCH_SPACE_BYTES: usize = 4;
+/// Default number of extranonce bytes the JDC reserves to split the search space among its
+/// downstreams, used when `jdc_search_space_bytes` is not configured.
+pub const DEFAULT_JDC_SEARCH_SPACE_BYTES: usize = 4;
 // These are only used for solo-mining, very similar to pool
-const CLIENT_SEARCH_SPACE_BYTES: usize = 16;
-pub const FULL_EXTRANONCE_SIZE: usize = JDC_SEARCH_SPACE_BYTES + CLIENT_SEARCH_SPACE_BYTES;
+pub const CLIENT_SEARCH_SPACE_BYTES: usize = 16;
 
 /// A `DeclaredJob` encapsulates all the relevant data associated with a single
 /// job declaration, including its template, optional messages, coinbase output,
@@ -109,6 +127,9 @@ pub struct ChannelManagerData {
     // Mapping of `downstream_id` → `Downstream` object,
     // used by the channel manager to locate and interact with downstream clients.
     pub downstream: HashMap<DownstreamId, Downstream>,
+    // Mapping of `downstream_id` → connection slot held by that downstream,
+    // released when the downstream is removed.
+    connection_permits: HashMap<DownstreamId, ConnectionPermit>,
     // Extranonce prefix factory for **extended downstream channels**.
     // Each new extended downstream receives a unique extranonce prefix.
     extranonce_prefix_factory_extended: ExtendedExtranonce,
@@ -127,6 +148,9 @@ pub struct ChannelManagerData {
     last_future_template: Option<NewTemplate<'static>>,
     // The last **new prevhash** received from the upstream.
     last_new_prev_hash: Option<SetNewPrevHashTdp<'static>>,
+    // Height of the block mined on the last **new prevhash**, read from the template it
+    // activated. Every template and custom job for that chain tip has to commit to it.
+    chain_tip_height: Option<u64>,
     // The most recent set of **allocation tokens** received from the JDS.
     allocate_tokens: Option<AllocateMiningJobTokenSuccess<'static>>,
     // Stores new templates as they arrive, mapped by their **template ID**.
@@ -134,7 +158,12 @@ pub struct ChannelManagerData {
     // Stores the last declared job, keyed by the `request_id` used when
     // declaring the job to the JDS.
     // This is later used to send a `SetCustomMiningJob`.
-    last_declare_job_store: HashMap<RequestId, DeclaredJob>,
+    // Jobs not acknowledged are evicted after a while, see `DeclaredJobStore`.
+    last_declare_job_store: DeclaredJobStore<DeclaredJob>,
+    // `SetCustomMiningJob` requests sent to the upstream and not acknowledged yet, keyed by the
+    // `request_id` of their entry in `last_declare_job_store`, and the ones deferred once too
+    // many are in flight.
+    pending_custom_jobs: PendingCustomJobs,
     // Maps a template ID → corresponding upstream job ID.
     template_id_to_upstream_job_id: HashMap<TemplateId, UpstreamJobId>,
     // Maps a downstream ID + channel_id + job ID → corresponding template ID.
@@ -162,15 +191,31 @@ pub struct ChannelManagerData {
 }
 
 impl ChannelManagerData {
+    /// Returns the number of declared jobs the upstream did not acknowledge yet.
+    pub fn pending_declared_jobs(&self) -> usize {
+        self.last_declare_job_store.len()
+    }
+
+    /// Returns the required extensions the upstream did not agree to.
+    pub fn missing_required_extensions(&self) -> Vec<u16> {
+        self.required_extensions
+            .iter()
+            .filter(|ext| !self.negotiated_extensions.contains(ext))
+            .copied()
+            .collect()
+    }
+
     /// Resets the internal state of the Channel Manager.
     ///
     /// This method is primarily used during **fallback scenarios** to clear and
     /// reinitialize all internal data structures. It ensures that the Channel Manager
     /// returns to a clean state, ready to handle fresh upstream or downstream connections.
-    pub fn reset(&mut self, coinbase_outputs: Vec<u8>) {
+    pub fn reset(&mut self, coinbase_outputs: Vec<u8>, jdc_search_space_bytes: usize) {
         self.downstream.clear();
+        self.connection_permits.clear();
         self.template_store.clear();
         self.last_declare_job_store.clear();
+        self.pending_custom_jobs.clear();
         self.template_id_to_upstream_job_id.clear();
         self.downstream_channel_id_and_job_id_to_template_id.clear();
         self.pending_downstream_requests.clear();
@@ -179,11 +224,11 @@ impl ChannelManagerData {
         self.request_id_factory = AtomicU32::new(0);
 
         let (range_0, range_1, range_2) = {
-            let range_1 = 0..JDC_SEARCH_SPACE_BYTES;
+            let range_1 = 0..jdc_search_space_bytes;
             (
                 0..range_1.start,
                 range_1.clone(),
-                range_1.end..FULL_EXTRANONCE_SIZE,
+                range_1.end..jdc_search_space_bytes + CLIENT_SEARCH_SPACE_BYTES,
             )
         };
         self.extranonce_prefix_factory_extended =
@@ -195,6 +240,7 @@ impl ChannelManagerData {
         self.allocate_tokens = None;
         self.upstream_channel = None;
         self.pool_tag_string = None;
+        self.negotiated_extensions.clear();
 
         self.coinbase_outputs = coinbase_outputs;
     }
@@ -249,6 +295,8 @@ pub struct ChannelManager {
     share_batch_size: SharesBatchSize,
     shares_per_minute: SharesPerMinute,
     user_identity: String,
+    /// Extranonce bytes reserved by the JDC to split the search space among its downstreams.
+    jdc_search_space_bytes: usize,
     /// This represent the current state of Upstream channel
     /// 1. NoChannel: No active upstream connection.
     /// 2. Pending: A channel request has been sent, awaiting response.
@@ -258,6 +306,41 @@ pub struct ChannelManager {
     /// When enabled, propagates upstream SetTarget to downstream miners and caps vardiff targets.
     /// Updated on upstream connect/failover based on the active upstream's config.
     propagate_upstream_target: Arc<AtomicBool>,
+    /// Whether the active upstream allows the JDC to select its own work. When disabled, no
+    /// custom job is sent to the upstream. Updated on upstream connect/failover.
+    upstream_work_selection: Arc<AtomicBool>,
+    /// Label of the upstream currently connected, reported as the `upstream` monitoring label.
+    /// `None` while solo mining.
+    active_upstream: Arc<Mutex<Option<String>>>,
+    /// Most recent fallback to another upstream or to solo mining, reported by monitoring.
+    last_failover: Arc<Mutex<Option<FailoverEvent>>>,
+    /// Throttle of the warnings logged for every rejected share.
+    log_throttle: Arc<LogThrottle>,
+    /// Per-IP connection cap and accept rate limiter of the downstream server.
+    pub connection_limiter: Arc<ConnectionLimiter>,
+    /// Derives a fresh coinbase output for every block mined in solo mode, when a solo coinbase
+    /// descriptor is configured.
+    solo_payout: Option<Arc<SoloPayout>>,
+    /// Coinbase output configured for solo mining, reported with the address of its descriptor.
+    pub(crate) coinbase_reward_script: CoinbaseRewardScript,
+    /// Network mined on, to report the addresses of the coinbase outputs. Only known with
+    /// Bitcoin Core IPC.
+    pub(crate) network: Option<Network>,
+    /// Largest SV2 frame, in bytes, accepted from downstreams.
+    max_frame_size: usize,
+    /// Whether the frames exchanged with downstreams are logged at trace level.
+    frame_trace: bool,
+    /// Counts of the messages exchanged, shared with the other connections of the JDC.
+    message_counters: Arc<MessageCounters>,
+    /// Blocks submitted to the template provider, and whether it accepted them.
+    pub(crate) found_blocks: Arc<FoundBlocks>,
+    /// When a downstream falling behind the messages sent to it is flagged as a slow consumer.
+    slow_consumer_policy: Option<SlowConsumerPolicy>,
+    /// Whether the downstream server waits for the first mining job token from the JDS before
+    /// accepting connections.
+    defer_downstream_until_upstream_ready: bool,
+    /// How binding the downstream server is retried while its address is in use.
+    bind_retry: BindRetry,
 }
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -275,15 +358,27 @@ impl ChannelManager {
         downstream_sender: broadcast::Sender<(DownstreamId, Mining<'static>, Option<Vec<Tlv>>)>,
         downstream_receiver: Receiver<(DownstreamId, Mining<'static>, Option<Vec<Tlv>>)>,
         coinbase_outputs: Vec<u8>,
+        solo_payout: Option<Arc<SoloPayout>>,
         supported_extensions: Vec<u16>,
         required_extensions: Vec<u16>,
+        message_counters: Arc<MessageCounters>,
+        log_throttle: Arc<LogThrottle>,
     ) -> JDCResult<Self, error::ChannelManager> {
+        let jdc_search_space_bytes = config.jdc_search_space_bytes();
+        if jdc_search_space_bytes == 0
+            || jdc_search_space_bytes + CLIENT_SEARCH_SPACE_BYTES > MAX_EXTRANONCE_LEN
+        {
+            return Err(JDCError::shutdown(
+                JDCErrorKind::InvalidJdcSearchSpaceBytes(jdc_search_space_bytes),
+            ));
+        }
+
         let (range_0, range_1, range_2) = {
-            let range_1 = 0..JDC_SEARCH_SPACE_BYTES;
+            let range_1 = 0..jdc_search_space_bytes;
             (
                 0..range_1.start,
                 range_1.clone(),
-                range_1.end..FULL_EXTRANONCE_SIZE,
+                range_1.end..jdc_search_space_bytes + CLIENT_SEARCH_SPACE_BYTES,
             )
         };
 
@@ -297,6 +392,7 @@ impl ChannelManager {
 
         let channel_manager_data = Arc::new(Mutex::new(ChannelManagerData {
             downstream: HashMap::new(),
+            connection_permits: HashMap::new(),
             extranonce_prefix_factory_extended,
             extranonce_prefix_factory_standard,
             downstream_id_factory: AtomicUsize::new(0),
@@ -304,9 +400,14 @@ impl ChannelManager {
             sequence_number_factory: AtomicU32::new(1),
             last_future_template: None,
             last_new_prev_hash: None,
+            chain_tip_height: None,
             allocate_tokens: None,
             template_store: HashMap::new(),
-            last_declare_job_store: HashMap::new(),
+            last_declare_job_store: DeclaredJobStore::default(),
+            pending_custom_jobs: PendingCustomJobs::new(
+                config.max_pending_custom_jobs(),
+                config.pending_custom_job_timeout(),
+            ),
             template_id_to_upstream_job_id: HashMap::new(),
             downstream_channel_id_and_job_id_to_template_id: HashMap::new(),
             coinbase_outputs,
@@ -338,13 +439,105 @@ impl ChannelManager {
             shares_per_minute: config.shares_per_minute(),
             miner_tag_string: config.jdc_signature().to_string(),
             user_identity: config.user_identity().to_string(),
+            jdc_search_space_bytes,
             upstream_state: AtomicUpstreamState::new(UpstreamState::SoloMining),
             propagate_upstream_target: Arc::new(AtomicBool::new(false)),
+            upstream_work_selection: Arc::new(AtomicBool::new(true)),
+            active_upstream: Arc::new(Mutex::new(None)),
+            last_failover: Arc::new(Mutex::new(None)),
+            log_throttle,
+            connection_limiter: Arc::new(
+                ConnectionLimiter::new(
+                    config.max_connections_per_ip(),
+                    config.max_accepts_per_sec(),
+                )
+                .with_churn_warning(config.churn_warning_per_minute()),
+            ),
+            solo_payout,
+            coinbase_reward_script: config.coinbase_reward_script.clone(),
+            network: config.template_provider_type().network(),
+            max_frame_size: config.max_frame_size(),
+            frame_trace: config.frame_trace(),
+            message_counters,
+            found_blocks: Arc::new(FoundBlocks::new()),
+            slow_consumer_policy: config.slow_consumer_policy(),
+            defer_downstream_until_upstream_ready: config.defer_downstream_until_upstream_ready(),
+            bind_retry: config.bind_retry(),
         };
 
         Ok(channel_manager)
     }
 
+    // What downstreams still wait for before the listener opens: the first template and prevhash,
+    // and the first mining job token when deferring downstreams until the upstream is ready.
+    // Checked again on every attempt, as the upstream state changes meanwhile, e.g. on a fallback
+    // to solo mining, which never gets a token.
+    fn awaited_before_accepting_downstreams(&self) -> Option<&'static str> {
+        let wait_for_token = self.defer_downstream_until_upstream_ready
+            && self.upstream_state.get() != UpstreamState::SoloMining;
+        self.channel_manager_data.super_safe_lock(|data| {
+            if data.last_future_template.is_none() || data.last_new_prev_hash.is_none() {
+                Some("initial template and prevhash from Template Provider")
+            } else if wait_for_token && data.allocate_tokens.is_none() {
+                Some("the first mining job token from the Job Declarator Server")
+            } else {
+                None
+            }
+        })
+    }
+
+    // Waits until downstreams may connect, returning `false` if the JDC shuts down meanwhile.
+    async fn wait_until_ready_for_downstreams(
+        &self,
+        shutdown_rx: &mut broadcast::Receiver<ShutdownMessage>,
+    ) -> bool {
+        while let Some(awaited) = self.awaited_before_accepting_downstreams() {
+            warn!("Waiting for {awaited}...");
+            select! {
+                message = shutdown_rx.recv() => {
+                    match message {
+                        Ok(ShutdownMessage::ShutdownAll) => {
+                            info!("Channel Manager: received shutdown while waiting for templates");
+                            return false;
+                        }
+                        Err(e) => {
+                            warn!(error = ?e, "shutdown channel closed unexpectedly");
+                            return false;
+                        }
+                        _ => {}
+                    }
+                }
+                _ = tokio::time::sleep(std::time::Duration::from_millis(100)) => {}
+            }
+        }
+        info!("Required template data received, ready to accept connections");
+        true
+    }
+
+    // Full extranonce size of downstream channels in solo mining mode, when there is no upstream
+    // channel to derive it from.
+    fn full_extranonce_size(&self) -> usize {
+        self.jdc_search_space_bytes + CLIENT_SEARCH_SPACE_BYTES
+    }
+
+    // Moves solo mining with a descriptor on to the next derived output once a block paying to
+    // the current one was found. The next future template picks it up.
+    fn advance_solo_payout(&self) {
+        let Some(solo_payout) = &self.solo_payout else {
+            return;
+        };
+        if self.upstream_state.get() != UpstreamState::SoloMining {
+            return;
+        }
+        match solo_payout.next_output() {
+            Ok(_) => info!(
+                "Block found, solo payout moves on to derived index {}",
+                solo_payout.next_index()
+            ),
+            Err(e) => error!("Failed to advance the solo payout index: {e:?}"),
+        }
+    }
+
     // Bootstraps a group channel with the given parameters.
     // Returns a `GroupChannel` if successful, otherwise returns `None`.
     //
@@ -359,9 +552,9 @@ impl ChannelManager {
                     data.upstream_channel
                         .as_ref()
                         .map(|channel| channel.get_full_extranonce_size())
-                        .unwrap_or(FULL_EXTRANONCE_SIZE), /* Default to FULL_EXTRANONCE_SIZE if
-                                                           * upstream channel is not present
-                                                           * (solo mining mode) */
+                        .unwrap_or(self.full_extranonce_size()), /* Default to the solo mining
+                                                                  * size if upstream channel is
+                                                                  * not present */
                     data.pool_tag_string.clone(),
                     data.last_future_template
                         .clone()
@@ -397,8 +590,13 @@ impl ChannelManager {
             }
         };
 
-        coinbase_outputs[0].value =
-            Amount::from_sat(last_future_template.coinbase_tx_value_remaining);
+        if let Err(e) = assign_coinbase_value(
+            &mut coinbase_outputs,
+            last_future_template.coinbase_tx_value_remaining,
+        ) {
+            error!("Cannot build a job on the last future template: {e}");
+            return None;
+        }
 
         if let Err(e) =
             group_channel.on_new_template(last_future_template, coinbase_outputs.clone())
@@ -437,41 +635,20 @@ impl ChannelManager {
     ) -> JDCResult<(), error::ChannelManager> {
         let mut shutdown_rx = notify_shutdown.subscribe();
 
-        // Wait for initial template and prevhash before accepting connections
-        loop {
-            let has_required_data = self.channel_manager_data.super_safe_lock(|data| {
-                data.last_future_template.is_some() && data.last_new_prev_hash.is_some()
-            });
-
-            if has_required_data {
-                info!("Required template data received, ready to accept connections");
-                break;
-            }
-
-            warn!("Waiting for initial template and prevhash from Template Provider...");
-            select! {
-                message = shutdown_rx.recv() => {
-                    match message {
-                        Ok(ShutdownMessage::ShutdownAll) => {
-                            info!("Channel Manager: received shutdown while waiting for templates");
-                            return Ok(());
-                        }
-                        Err(e) => {
-                            warn!(error = ?e, "shutdown channel closed unexpectedly");
-                            return Ok(());
-                        }
-                        _ => {}
-                    }
-                }
-                _ = tokio::time::sleep(std::time::Duration::from_millis(100)) => {}
-            }
+        if !self
+            .wait_until_ready_for_downstreams(&mut shutdown_rx)
+            .await
+        {
+            return Ok(());
         }
 
         info!("Starting downstream server at {listening_address}");
-        let server = TcpListener::bind(listening_address).await.map_err(|e| {
-            error!(error = ?e, "Failed to bind downstream server at {listening_address}");
-            JDCError::shutdown(e)
-        })?;
+        let server = bind_with_retry(listening_address, self.bind_retry)
+            .await
+            .map_err(|e| {
+                error!(error = ?e, "Failed to bind downstream server at {listening_address}");
+                JDCError::shutdown(e)
+            })?;
 
         let task_manager_clone = task_manager.clone();
         task_manager.spawn(async move {
@@ -502,6 +679,13 @@ impl ChannelManager {
                     res = server.accept() => {
                         match res {
                             Ok((stream, socket_address)) => {
+                                let connection_permit = match self.connection_limiter.try_acquire(socket_address.ip()) {
+                                    Ok(permit) => permit,
+                                    Err(reason) => {
+                                        warn!(%socket_address, %reason, "Rejecting downstream connection");
+                                        continue;
+                                    }
+                                };
                                 info!(%socket_address, "New downstream connection");
                                 let responder = match Responder::from_authority_kp(
                                     &authority_public_key.into_bytes(),
@@ -558,10 +742,15 @@ impl ChannelManager {
                                     status_sender.clone(),
                                     supported_extensions.clone(),
                                     required_extensions.clone(),
+                                    self.max_frame_size,
+                                    self.frame_trace,
+                                    self.message_counters.clone(),
+                                    self.slow_consumer_policy,
                                 );
 
                                 self.channel_manager_data.super_safe_lock(|data| {
                                     data.downstream.insert(downstream_id, downstream.clone());
+                                    data.connection_permits.insert(downstream_id, connection_permit);
                                 });
 
                                 downstream
@@ -632,13 +821,15 @@ impl ChannelManager {
                             Ok(ShutdownMessage::JobDeclaratorShutdownFallback((coinbase_outputs,tx))) => {
                                 info!("Channel Manager: Job declarator shutdown signal");
                                 self.upstream_state.set(UpstreamState::SoloMining);
-                                self.channel_manager_data.super_safe_lock(|data| data.reset(coinbase_outputs));
+                                self.active_upstream.super_safe_lock(|data| *data = None);
+                                self.channel_manager_data.super_safe_lock(|data| data.reset(coinbase_outputs, self.jdc_search_space_bytes));
                                 drop(tx);
                             }
                             Ok(ShutdownMessage::UpstreamShutdownFallback((coinbase_outputs,tx))) => {
                                 info!("Channel Manager: Upstream shutdown signal");
                                 self.upstream_state.set(UpstreamState::SoloMining);
-                                self.channel_manager_data.super_safe_lock(|data| data.reset(coinbase_outputs));
+                                self.active_upstream.super_safe_lock(|data| *data = None);
+                                self.channel_manager_data.super_safe_lock(|data| data.reset(coinbase_outputs, self.jdc_search_space_bytes));
                                 drop(tx);
                             }
                             Err(e) => {
@@ -699,6 +890,7 @@ impl ChannelManager {
     ) -> JDCResult<(), error::ChannelManager> {
         self.channel_manager_data.super_safe_lock(|cm_data| {
             cm_data.downstream.remove(&downstream_id);
+            cm_data.connection_permits.remove(&downstream_id);
             cm_data
                 .downstream_channel_id_and_job_id_to_template_id
                 .retain(|key, _| key.downstream_id != downstream_id);
@@ -837,7 +1029,7 @@ impl ChannelManager {
                                     .map_err(JDCError::shutdown)?;
                                 upstream_message.request_id = 1;
                                 upstream_message.min_extranonce_size +=
-                                    JDC_SEARCH_SPACE_BYTES as u16;
+                                    self.jdc_search_space_bytes as u16;
                                 let upstream_message =
                                     Mining::OpenExtendedMiningChannel(upstream_message)
                                         .into_static();
@@ -897,7 +1089,7 @@ impl ChannelManager {
                                     request_id: 1,
                                     nominal_hash_rate: downstream_channel_request.nominal_hash_rate,
                                     max_target: downstream_channel_request.max_target,
-                                    min_extranonce_size: JDC_SEARCH_SPACE_BYTES as u16,
+                                    min_extranonce_size: self.jdc_search_space_bytes as u16,
                                 };
 
                                 let message =
@@ -977,6 +1169,47 @@ impl ChannelManager {
             .store(enabled, Ordering::Relaxed);
     }
 
+    /// Sets whether custom jobs are sent to the upstream.
+    /// Called when connecting to an upstream or during failover.
+    pub fn set_upstream_work_selection(&self, enabled: bool) {
+        info!(
+            "Upstream work selection: {}",
+            if enabled {
+                "ENABLED — custom jobs are declared to the upstream"
+            } else {
+                "DISABLED — the JDC only mines the jobs of the upstream"
+            }
+        );
+        self.upstream_work_selection
+            .store(enabled, Ordering::Relaxed);
+    }
+
+    /// Sets the label of the upstream currently connected.
+    /// Called when connecting to an upstream or during failover.
+    pub fn set_active_upstream(&self, label: String) {
+        info!("Active upstream: {label}");
+        self.active_upstream
+            .super_safe_lock(|data| *data = Some(label));
+    }
+
+    /// Returns the label of the upstream currently connected, if any.
+    pub fn active_upstream(&self) -> Option<String> {
+        self.active_upstream.super_safe_lock(|data| data.clone())
+    }
+
+    /// Records a fallback away from the active upstream caused by `reason`.
+    /// Called before the fallback resets the active upstream.
+    pub fn record_failover(&self, reason: FailoverReason, detail: String) {
+        let event = FailoverEvent::new(reason, detail, self.active_upstream());
+        self.last_failover
+            .super_safe_lock(|data| *data = Some(event));
+    }
+
+    /// Returns the most recent fallback, if any happened.
+    pub fn last_failover(&self) -> Option<FailoverEvent> {
+        self.last_failover.super_safe_lock(|data| data.clone())
+    }
+
     /// Utility method to request for more token to JDS.
     pub async fn allocate_tokens(
         &self,
@@ -1308,3 +1541,230 @@ impl ChannelManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use async_channel::unbounded;
+    use stratum_apps::{
+        config_helpers::CoinbaseRewardScript,
+        monitoring::CoinbaseOutputsMonitoring,
+        stratum_core::{
+            binary_sv2::{Seq0255, U256},
+            bitcoin::{consensus::Encodable, Amount},
+        },
+        tp_type::TemplateProviderType,
+    };
+
+    use super::*;
+    use crate::config::{PoolConfig, ProtocolConfig};
+
+    async fn channel_manager(defer_downstream_until_upstream_ready: bool) -> ChannelManager {
+        let authority_public_key = Secp256k1PublicKey::try_from(
+            "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72".to_string(),
+        )
+        .unwrap();
+        let authority_secret_key = Secp256k1SecretKey::try_from(
+            "mkDLTBBRxdBv998612qipDYoTK3YUrqLe8uWw7gu3iXbSrn2n".to_string(),
+        )
+        .unwrap();
+        let coinbase_reward_script = CoinbaseRewardScript::from_descriptor(
+            "addr(tb1qpusf5256yxv50qt0pm0tue8k952fsu5lzsphft)",
+        )
+        .unwrap();
+        let config = JobDeclaratorClientConfig::new(
+            "127.0.0.1:34265".parse().unwrap(),
+            ProtocolConfig::new(2, 2, coinbase_reward_script),
+            "user".to_string(),
+            10.0,
+            1,
+            PoolConfig::new(authority_public_key, authority_secret_key),
+            3600,
+            TemplateProviderType::Sv2Tp {
+                address: "127.0.0.1:8442".to_string(),
+                public_key: None,
+            },
+            vec![],
+            "JDC".to_string(),
+            None,
+            vec![],
+            vec![],
+        )
+        .with_defer_downstream_until_upstream_ready(defer_downstream_until_upstream_ready);
+        let (upstream_sender, upstream_receiver) = unbounded();
+        let (jd_sender, jd_receiver) = unbounded();
+        let (tp_sender, tp_receiver) = unbounded();
+        let (downstream_sender, _) = broadcast::channel(10);
+        let (_, downstream_receiver) = unbounded();
+        ChannelManager::new(
+            config,
+            upstream_sender,
+            upstream_receiver,
+            jd_sender,
+            jd_receiver,
+            tp_sender,
+            tp_receiver,
+            downstream_sender,
+            downstream_receiver,
+            vec![],
+            None,
+            vec![],
+            vec![],
+            Arc::new(MessageCounters::new()),
+            Arc::new(LogThrottle::new(None)),
+        )
+        .await
+        .unwrap()
+    }
+
+    fn receive_first_template(channel_manager: &ChannelManager) {
+        channel_manager
+            .channel_manager_data
+            .super_safe_lock(|data| {
+                data.last_future_template = Some(NewTemplate {
+                    template_id: 1,
+                    future_template: true,
+                    version: 0x2000_0000,
+                    coinbase_tx_version: 2,
+                    coinbase_prefix: vec![0x03, 0x40, 0xd1, 0x0c].try_into().unwrap(),
+                    coinbase_tx_input_sequence: u32::MAX,
+                    coinbase_tx_value_remaining: 312_500_000,
+                    coinbase_tx_outputs_count: 0,
+                    coinbase_tx_outputs: Vec::new().try_into().unwrap(),
+                    coinbase_tx_locktime: 0,
+                    merkle_path: Seq0255::new(Vec::<U256>::new()).unwrap(),
+                });
+                data.last_new_prev_hash = Some(SetNewPrevHashTdp {
+                    template_id: 1,
+                    prev_hash: [0u8; 32].into(),
+                    header_timestamp: 1_713_571_767,
+                    n_bits: 0x1703_4219,
+                    target: [0xffu8; 32].into(),
+                });
+            });
+    }
+
+    fn receive_first_token(channel_manager: &ChannelManager) {
+        channel_manager
+            .channel_manager_data
+            .super_safe_lock(|data| {
+                data.allocate_tokens = Some(AllocateMiningJobTokenSuccess {
+                    request_id: 0,
+                    mining_job_token: vec![0; 8].try_into().unwrap(),
+                    coinbase_outputs: Vec::new().try_into().unwrap(),
+                });
+            });
+    }
+
+    // Spawns the wait for downstreams being allowed to connect.
+    fn wait_for_downstreams(
+        channel_manager: &Arc<ChannelManager>,
+        notify_shutdown: &broadcast::Sender<ShutdownMessage>,
+    ) -> tokio::task::JoinHandle<bool> {
+        let channel_manager = channel_manager.clone();
+        let mut shutdown_rx = notify_shutdown.subscribe();
+        tokio::spawn(async move {
+            channel_manager
+                .wait_until_ready_for_downstreams(&mut shutdown_rx)
+                .await
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn downstreams_wait_for_the_first_job_token_when_deferred() {
+        let channel_manager = Arc::new(channel_manager(true).await);
+        let (notify_shutdown, _) = broadcast::channel(10);
+        channel_manager.upstream_state.set(UpstreamState::Connected);
+        receive_first_template(&channel_manager);
+
+        let waiting = wait_for_downstreams(&channel_manager, &notify_shutdown);
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(!waiting.is_finished());
+
+        receive_first_token(&channel_manager);
+        let ready = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .expect("downstreams still wait once the token arrived");
+        assert!(ready.unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_fallback_to_solo_mining_stops_waiting_for_a_token() {
+        let channel_manager = Arc::new(channel_manager(true).await);
+        let (notify_shutdown, _) = broadcast::channel(10);
+        channel_manager.upstream_state.set(UpstreamState::Pending);
+        receive_first_template(&channel_manager);
+
+        let waiting = wait_for_downstreams(&channel_manager, &notify_shutdown);
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(!waiting.is_finished());
+
+        // the JDS never allocated a token, and the JDC fell back to solo mining meanwhile
+        channel_manager
+            .upstream_state
+            .set(UpstreamState::SoloMining);
+        let ready = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .expect("downstreams still wait for a token in solo mining");
+        assert!(ready.unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn downstreams_only_wait_for_the_first_template_by_default() {
+        let channel_manager = Arc::new(channel_manager(false).await);
+        let (notify_shutdown, _) = broadcast::channel(10);
+        channel_manager.upstream_state.set(UpstreamState::Connected);
+
+        let waiting = wait_for_downstreams(&channel_manager, &notify_shutdown);
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(!waiting.is_finished());
+
+        receive_first_template(&channel_manager);
+        let ready = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .expect("downstreams wait for a token without deferring");
+        assert!(ready.unwrap());
+    }
+
+    #[tokio::test]
+    async fn monitoring_reports_the_coinbase_outputs_in_use() {
+        let channel_manager = channel_manager(false).await;
+        let set_coinbase_outputs = |outputs: Vec<TxOut>| {
+            let mut encoded_outputs = vec![];
+            outputs.consensus_encode(&mut encoded_outputs).unwrap();
+            channel_manager
+                .channel_manager_data
+                .super_safe_lock(|data| data.coinbase_outputs = encoded_outputs);
+        };
+
+        // solo mining pays the configured output, whose address comes from its descriptor
+        set_coinbase_outputs(vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: channel_manager.coinbase_reward_script.script_pubkey(),
+        }]);
+        let outputs = channel_manager.get_coinbase_outputs().outputs;
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(
+            outputs[0].address.as_deref(),
+            Some("tb1qpusf5256yxv50qt0pm0tue8k952fsu5lzsphft")
+        );
+
+        // in JD mode, the outputs of the mining job token replace it
+        let pool_script = CoinbaseRewardScript::from_descriptor(
+            "wpkh(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+        )
+        .unwrap()
+        .script_pubkey();
+        set_coinbase_outputs(vec![TxOut {
+            value: Amount::from_sat(312_500_000),
+            script_pubkey: pool_script.clone(),
+        }]);
+        let outputs = channel_manager.get_coinbase_outputs().outputs;
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].script_pubkey, pool_script.to_hex_string());
+        assert_eq!(outputs[0].value, 312_500_000);
+        // the Sv2 template provider does not tell the network to derive an address from
+        assert_eq!(outputs[0].address, None);
+    }
+}
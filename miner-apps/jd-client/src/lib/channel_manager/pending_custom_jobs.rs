@@ -0,0 +1,185 @@
+//! Bound on the `SetCustomMiningJob` requests waiting for an acknowledgement from the upstream.
+//!
+//! In `CoinbaseOnly` mode a custom job is sent for every new template, so rapid template churn
+//! can put many requests in flight at once. Once the cap is reached, new jobs are deferred and
+//! sent in order as the upstream acknowledges the ones in flight. Deferred jobs are dropped when
+//! the chain tip changes, since the upstream would reject them. A job that is refused, or not
+//! acknowledged in time, frees its slot as well, so lost acknowledgements cannot stall custom
+//! jobs for good.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use stratum_apps::utils::types::RequestId;
+use tracing::{debug, warn};
+
+/// Custom jobs sent to the upstream and not acknowledged yet, plus the ones deferred until an
+/// acknowledgement arrives.
+#[derive(Debug)]
+pub struct PendingCustomJobs {
+    // `0` disables the cap.
+    max_in_flight: usize,
+    // Time after which an unacknowledged job no longer counts against the cap.
+    timeout: Duration,
+    // When each in-flight job was sent.
+    in_flight: HashMap<RequestId, Instant>,
+    deferred: VecDeque<RequestId>,
+}
+
+impl PendingCustomJobs {
+    /// Creates a store sending at most `max_in_flight` custom jobs at once, each holding its slot
+    /// for at most `timeout`. `0` disables the cap.
+    pub fn new(max_in_flight: usize, timeout: Duration) -> Self {
+        Self {
+            max_in_flight,
+            timeout,
+            in_flight: HashMap::new(),
+            deferred: VecDeque::new(),
+        }
+    }
+
+    /// Adds the custom job declared with `request_id`. Returns whether it can be sent right away,
+    /// otherwise it is deferred until an acknowledgement arrives.
+    pub fn push(&mut self, request_id: RequestId) -> bool {
+        self.expire();
+        if self.has_room() {
+            self.in_flight.insert(request_id, Instant::now());
+            return true;
+        }
+        debug!(
+            "Deferring custom job request_id={request_id}: {} already in flight",
+            self.in_flight.len()
+        );
+        self.deferred.push_back(request_id);
+        false
+    }
+
+    /// Marks the custom job sent with `request_id` as acknowledged, or refused, and returns the
+    /// deferred jobs to send now, oldest first.
+    ///
+    /// `is_declared` tells whether a job is still in the declared job store. In-flight jobs
+    /// evicted from it no longer count against the cap, and evicted deferred jobs are dropped.
+    pub fn acknowledge(
+        &mut self,
+        request_id: &RequestId,
+        mut is_declared: impl FnMut(&RequestId) -> bool,
+    ) -> Vec<RequestId> {
+        self.in_flight.remove(request_id);
+        self.in_flight.retain(|id, _| is_declared(id));
+        self.expire();
+
+        let mut ready = Vec::new();
+        while self.has_room() {
+            let Some(request_id) = self.deferred.pop_front() else {
+                break;
+            };
+            if is_declared(&request_id) {
+                self.in_flight.insert(request_id, Instant::now());
+                ready.push(request_id);
+            }
+        }
+        ready
+    }
+
+    /// Drops the deferred jobs, once they were built on an outdated chain tip.
+    pub fn clear_deferred(&mut self) {
+        if !self.deferred.is_empty() {
+            debug!("Dropping {} deferred custom jobs", self.deferred.len());
+            self.deferred.clear();
+        }
+    }
+
+    /// Forgets every in-flight and deferred job.
+    pub fn clear(&mut self) {
+        self.in_flight.clear();
+        self.deferred.clear();
+    }
+
+    /// Returns the number of custom jobs sent and not acknowledged yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    // Frees the slots of the jobs sent longer than the timeout ago.
+    fn expire(&mut self) {
+        let in_flight = self.in_flight.len();
+        self.in_flight
+            .retain(|_, sent_at| sent_at.elapsed() < self.timeout);
+        let expired = in_flight - self.in_flight.len();
+        if expired > 0 {
+            warn!(
+                "{expired} custom jobs were not acknowledged within {:?}, freeing their slots",
+                self.timeout
+            );
+        }
+    }
+
+    fn has_room(&self) -> bool {
+        self.max_in_flight == 0 || self.in_flight.len() < self.max_in_flight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn in_flight_jobs_stay_within_cap() {
+        let max_in_flight = 4;
+        let mut pending = PendingCustomJobs::new(max_in_flight, Duration::from_secs(60));
+
+        // rapid template churn: many jobs declared before any acknowledgement
+        let mut sent: VecDeque<RequestId> = VecDeque::new();
+        for request_id in 0..100 {
+            if pending.push(request_id) {
+                sent.push_back(request_id);
+            }
+            assert!(pending.in_flight() <= max_in_flight);
+        }
+        assert_eq!(sent.len(), max_in_flight);
+
+        // acknowledgements release the deferred jobs in order
+        let mut acknowledged = Vec::new();
+        while let Some(request_id) = sent.pop_front() {
+            acknowledged.push(request_id);
+            sent.extend(pending.acknowledge(&request_id, |_| true));
+            assert!(pending.in_flight() <= max_in_flight);
+        }
+        assert_eq!(acknowledged, (0..100).collect::<Vec<_>>());
+        assert_eq!(pending.in_flight(), 0);
+    }
+
+    #[test]
+    fn evicted_and_outdated_jobs_are_not_sent() {
+        let mut pending = PendingCustomJobs::new(1, Duration::from_secs(60));
+        assert!(pending.push(1));
+        assert!(!pending.push(2));
+        assert!(!pending.push(3));
+
+        // job 2 was evicted from the declared job store
+        assert_eq!(pending.acknowledge(&1, |id| *id != 2), vec![3]);
+
+        assert!(!pending.push(4));
+        pending.clear_deferred();
+        assert!(pending.acknowledge(&3, |_| true).is_empty());
+        assert_eq!(pending.in_flight(), 0);
+    }
+
+    #[test]
+    fn unacknowledged_jobs_free_their_slot_after_the_timeout() {
+        let mut pending = PendingCustomJobs::new(1, Duration::from_millis(10));
+        assert!(pending.push(1));
+        assert!(!pending.push(2));
+
+        // the acknowledgement of job 1 was lost
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(pending.push(3));
+        assert_eq!(pending.in_flight(), 1);
+
+        // job 3 was refused, the deferred job 2 is sent in its place
+        assert_eq!(pending.acknowledge(&3, |_| true), vec![2]);
+        assert_eq!(pending.in_flight(), 1);
+    }
+}
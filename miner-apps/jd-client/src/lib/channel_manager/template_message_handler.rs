@@ -2,7 +2,11 @@ use std::sync::atomic::Ordering;
 
 use stratum_apps::stratum_core::{
     binary_sv2::{Seq064K, U256},
-    bitcoin::{consensus, hashes::Hash, Amount, Transaction},
+    bitcoin::{
+        consensus::{self, Encodable},
+        hashes::Hash,
+        Transaction,
+    },
     channels_sv2::{chain_tip::ChainTip, outputs::deserialize_outputs},
     handlers_sv2::HandleTemplateDistributionMessagesFromServerAsync,
     job_declaration_sv2::DeclareMiningJob,
@@ -13,9 +17,15 @@ use stratum_apps::stratum_core::{
 use tracing::{error, info, warn};
 
 use crate::{
-    channel_manager::{downstream_message_handler::RouteMessageTo, ChannelManager, DeclaredJob},
+    channel_manager::{
+        coinbase_height::{check_template_height, template_height, validate_custom_job_height},
+        coinbase_value::assign_coinbase_value,
+        downstream_message_handler::RouteMessageTo,
+        ChannelManager, DeclaredJob,
+    },
     error::{self, JDCError, JDCErrorKind},
     jd_mode::{get_jd_mode, JdMode},
+    utils::UpstreamState,
 };
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -51,17 +61,55 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
 
-        let coinbase_outputs = self.channel_manager_data.super_safe_lock(|data| {
-            data.template_store
-                .insert(msg.template_id, msg.clone().into_static());
-            if msg.future_template {
-                data.last_future_template = Some(msg.clone().into_static());
+        // Solo mining with a descriptor pays every template to the current derived output. The
+        // index only advances once a block is found, so new blocks on the network do not burn
+        // indexes.
+        let solo_coinbase_outputs = match &self.solo_payout {
+            Some(solo_payout)
+                if msg.future_template
+                    && self.upstream_state.get() == UpstreamState::SoloMining =>
+            {
+                let output = solo_payout.peek_output().map_err(JDCError::shutdown)?;
+                info!(
+                    "Solo mining template {} pays to derived script {}",
+                    msg.template_id, output.script_pubkey
+                );
+                let mut encoded_outputs = vec![];
+                vec![output]
+                    .consensus_encode(&mut encoded_outputs)
+                    .map_err(|_| {
+                        JDCError::shutdown(JDCErrorKind::ChannelManagerHasBadCoinbaseOutputs)
+                    })?;
+                Some(encoded_outputs)
             }
-            data.coinbase_outputs.clone()
-        });
+            _ => None,
+        };
+
+        let (coinbase_outputs, chain_tip_height) =
+            self.channel_manager_data.super_safe_lock(|data| {
+                data.template_store
+                    .insert(msg.template_id, msg.clone().into_static());
+                if msg.future_template {
+                    data.last_future_template = Some(msg.clone().into_static());
+                }
+                if let Some(solo_coinbase_outputs) = solo_coinbase_outputs {
+                    data.coinbase_outputs = solo_coinbase_outputs;
+                }
+                (data.coinbase_outputs.clone(), data.chain_tip_height)
+            });
+        check_template_height(&msg, chain_tip_height).map_err(|e| {
+            error!("Not building jobs on template {}: {e}", msg.template_id);
+            JDCError::log(e)
+        })?;
 
         let mut coinbase_outputs = deserialize_outputs(coinbase_outputs)
             .map_err(|_| JDCError::shutdown(JDCErrorKind::ChannelManagerHasBadCoinbaseOutputs))?;
+        assign_coinbase_value(&mut coinbase_outputs, msg.coinbase_tx_value_remaining).map_err(
+            |e| {
+                error!("Not building jobs on template {}: {e}", msg.template_id);
+                JDCError::log(e)
+            },
+        )?;
 
         if get_jd_mode() == JdMode::FullTemplate {
             let tx_data_request =
@@ -78,7 +126,6 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
 
         let messages = self.channel_manager_data.super_safe_lock(|channel_manager_data| {
             let mut messages: Vec<RouteMessageTo> = Vec::new();
-            coinbase_outputs[0].value = Amount::from_sat(msg.coinbase_tx_value_remaining);
 
             for (downstream_id, downstream) in channel_manager_data.downstream.iter_mut() {
 
@@ -101,7 +148,7 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
                     let mut messages: Vec<RouteMessageTo> = vec![];
 
                     if let Some(upstream_channel) = channel_manager_data.upstream_channel.as_mut() {
-                        if !msg.future_template && get_jd_mode() == JdMode::CoinbaseOnly {
+                        if !msg.future_template && get_jd_mode() == JdMode::CoinbaseOnly && self.upstream_work_selection.load(Ordering::Relaxed) {
                                 if let (Some(token), Some(prevhash)) = (
                                     channel_manager_data.allocate_tokens.clone(),
                                     channel_manager_data.last_new_prev_hash.clone(),
@@ -109,7 +156,10 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
                                     let request_id = channel_manager_data.request_id_factory.fetch_add(1, Ordering::Relaxed);
                                     let job_factory = channel_manager_data.job_factory.as_mut().unwrap();
                                     let full_extranonce_size = upstream_channel.get_full_extranonce_size();
-                                    let custom_job = job_factory.new_custom_job(upstream_channel.get_channel_id(), request_id, token.clone().mining_job_token, prevhash.clone().into(), msg.clone(), coinbase_outputs.clone(), full_extranonce_size);
+                                    let custom_job = job_factory.new_custom_job(upstream_channel.get_channel_id(), request_id, token.clone().mining_job_token, prevhash.clone().into(), msg.clone(), coinbase_outputs.clone(), full_extranonce_size)
+                                        .map_err(|_| JDCErrorKind::FailedToCreateCustomJob)
+                                        .and_then(|custom_job| validate_custom_job_height(&msg, chain_tip_height, custom_job))
+                                        .inspect_err(|e| error!("Not sending custom job for template {}: {e}", msg.template_id));
 
                                     if let Ok(custom_job) = custom_job{
                                         let last_declare = DeclaredJob {
@@ -123,9 +173,11 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
                                         channel_manager_data
                                             .last_declare_job_store
                                             .insert(request_id, last_declare);
-                                        messages.push(
-                                            Mining::SetCustomMiningJob(custom_job).into()
-                                        );
+                                        if channel_manager_data.pending_custom_jobs.push(request_id) {
+                                            messages.push(
+                                                Mining::SetCustomMiningJob(custom_job).into()
+                                            );
+                                        }
                                     }
                                 }
                         }
@@ -298,8 +350,14 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
         };
 
         let mining_token = token.mining_job_token.clone();
-        deserialized_outputs[0].value =
-            Amount::from_sat(template_message.coinbase_tx_value_remaining);
+        assign_coinbase_value(
+            &mut deserialized_outputs,
+            template_message.coinbase_tx_value_remaining,
+        )
+        .map_err(|e| {
+            error!("Not declaring a job on template {}: {e}", msg.template_id);
+            JDCError::log(e)
+        })?;
         let reserialized_outputs = consensus::serialize(&deserialized_outputs);
 
         let tx_list: Vec<Transaction> = transactions_data
@@ -389,6 +447,10 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
 
+        if let Some(block_hash) = self.found_blocks.record_chain_tip(&msg.prev_hash.to_vec()) {
+            info!("💰 Block {block_hash} accepted: it is the new chain tip 💰");
+        }
+
         let coinbase_outputs = self
             .channel_manager_data
             .super_safe_lock(|data| data.coinbase_outputs.clone());
@@ -431,6 +493,19 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
 
         let messages = self.channel_manager_data.super_safe_lock(|channel_manager_data| {
             channel_manager_data.last_new_prev_hash = Some(msg.clone().into_static());
+            channel_manager_data.chain_tip_height = channel_manager_data
+                .template_store
+                .get(&msg.template_id)
+                .and_then(|template| {
+                    template_height(template)
+                        .inspect_err(|e| {
+                            error!(
+                                "Template {} activated by the new prevhash: {e}",
+                                template.template_id
+                            )
+                        })
+                        .ok()
+                });
             channel_manager_data.last_declare_job_store.iter_mut().for_each(|(_k, v)| {
                 if v.template.future_template && v.template.template_id == msg.template_id {
                     v.prev_hash = Some(msg.clone().into_static());
@@ -443,7 +518,11 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
             if let Some(ref mut upstream_channel) = channel_manager_data.upstream_channel {
                 _ = upstream_channel.on_chain_tip_update(msg.clone().into());
 
-                if get_jd_mode() == JdMode::CoinbaseOnly {
+                if get_jd_mode() == JdMode::CoinbaseOnly
+                    && self.upstream_work_selection.load(Ordering::Relaxed)
+                {
+                    // jobs still deferred were built on the previous chain tip
+                    channel_manager_data.pending_custom_jobs.clear_deferred();
                     if let (Some(job_factory), Some(token), Some(template)) = (
                         channel_manager_data.job_factory.as_mut(),
                         channel_manager_data.allocate_tokens.clone(),
@@ -458,15 +537,28 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
 
                         let full_extranonce_size = upstream_channel.get_full_extranonce_size();
 
-                        if let Ok(custom_job) = job_factory.new_custom_job(
-                            upstream_channel.get_channel_id(),
-                            request_id,
-                            token.clone().mining_job_token,
-                            chain_tip,
-                            template.clone(),
-                            outputs,
-                            full_extranonce_size,
-                        ) {
+                        let chain_tip_height = channel_manager_data.chain_tip_height;
+                        let custom_job = job_factory
+                            .new_custom_job(
+                                upstream_channel.get_channel_id(),
+                                request_id,
+                                token.clone().mining_job_token,
+                                chain_tip,
+                                template.clone(),
+                                outputs,
+                                full_extranonce_size,
+                            )
+                            .map_err(|_| JDCErrorKind::FailedToCreateCustomJob)
+                            .and_then(|custom_job| {
+                                validate_custom_job_height(&template, chain_tip_height, custom_job)
+                            })
+                            .inspect_err(|e| {
+                                error!(
+                                    "Not sending custom job for template {}: {e}",
+                                    template.template_id
+                                )
+                            });
+                        if let Ok(custom_job) = custom_job {
                             let last_declare = DeclaredJob {
                                 declare_mining_job: None,
                                 template: template.into_static(),
@@ -477,7 +569,9 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
                             };
 
                             channel_manager_data.last_declare_job_store.insert(request_id, last_declare);
-                            messages.push(Mining::SetCustomMiningJob(custom_job).into());
+                            if channel_manager_data.pending_custom_jobs.push(request_id) {
+                                messages.push(Mining::SetCustomMiningJob(custom_job).into());
+                            }
                         }
                     }
                 }
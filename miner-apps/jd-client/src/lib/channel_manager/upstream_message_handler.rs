@@ -18,8 +18,8 @@ use tracing::{debug, error, info, warn};
 
 use crate::{
     channel_manager::{
-        downstream_message_handler::RouteMessageTo, ChannelManager, DeclaredJob,
-        JDC_SEARCH_SPACE_BYTES,
+        coinbase_height::validate_custom_job_height, downstream_message_handler::RouteMessageTo,
+        ChannelManager, DeclaredJob,
     },
     error::{self, JDCError, JDCErrorKind},
     jd_mode::{get_jd_mode, JdMode},
@@ -43,7 +43,7 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
         SupportedChannelTypes::Extended
     }
     fn is_work_selection_enabled_for_server(&self, _server_id: Option<usize>) -> bool {
-        true
+        self.upstream_work_selection.load(Ordering::Relaxed)
     }
 
     // Handles an unexpected `OpenStandardMiningChannelSuccess` message from the upstream.
@@ -88,12 +88,45 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
 
+        // The upstream answers `RequestExtensions` before opening any channel, so a channel
+        // opening with required extensions missing means the upstream ignored the request.
+        let missing_required = self
+            .channel_manager_data
+            .super_safe_lock(|data| data.missing_required_extensions());
+        if !missing_required.is_empty() {
+            error!(
+                ?missing_required,
+                "Upstream opened a channel without agreeing to the required extensions"
+            );
+            return Err(JDCError::fallback(
+                JDCErrorKind::RequiredExtensionsNotSupported(missing_required),
+            ));
+        }
+
+        // The granted extranonce must leave room for the downstream search space as well
+        if self.jdc_search_space_bytes >= msg.extranonce_size as usize {
+            error!(
+                jdc_search_space_bytes = self.jdc_search_space_bytes,
+                extranonce_size = msg.extranonce_size,
+                "Upstream granted extranonce size cannot fit the JDC search space"
+            );
+            return Err(JDCError::fallback(
+                JDCErrorKind::JdcSearchSpaceExceedsUpstreamExtranonce(
+                    self.jdc_search_space_bytes,
+                    msg.extranonce_size as usize,
+                ),
+            ));
+        }
+
         let coinbase_outputs = self
             .channel_manager_data
             .super_safe_lock(|data| data.coinbase_outputs.clone());
 
-        let outputs = deserialize_outputs(coinbase_outputs)
-            .map_err(|_| JDCError::shutdown(JDCErrorKind::DeclaredJobHasBadCoinbaseOutputs))?;
+        // The configured outputs are validated at startup, so this is only a last resort
+        let outputs = deserialize_outputs(coinbase_outputs).map_err(|e| {
+            error!(error = ?e, "Failed to deserialize coinbase outputs");
+            JDCError::fallback(JDCErrorKind::DeclaredJobHasBadCoinbaseOutputs)
+        })?;
 
         let (channel_state, template, custom_job, close_channel) =
             self.channel_manager_data.super_safe_lock(|data| {
@@ -110,8 +143,8 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
 
                 let total_len = prefix_len + msg.extranonce_size as usize;
                 let range_0 = 0..prefix_len;
-                let range_1 = prefix_len..prefix_len + JDC_SEARCH_SPACE_BYTES;
-                let range_2 = prefix_len + JDC_SEARCH_SPACE_BYTES..total_len;
+                let range_1 = prefix_len..prefix_len + self.jdc_search_space_bytes;
+                let range_2 = prefix_len + self.jdc_search_space_bytes..total_len;
 
                 debug!(
                     prefix_len,
@@ -168,15 +201,31 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
 
                         let full_extranonce_size = extended_channel.get_full_extranonce_size();
 
-                        if let Ok(custom_job) = job_factory.new_custom_job(
-                            extended_channel.get_channel_id(),
-                            request_id,
-                            token.clone().mining_job_token,
-                            prevhash.clone().into(),
-                            template.clone(),
-                            outputs,
-                            full_extranonce_size,
-                        ) {
+                        let custom_job = job_factory
+                            .new_custom_job(
+                                extended_channel.get_channel_id(),
+                                request_id,
+                                token.clone().mining_job_token,
+                                prevhash.clone().into(),
+                                template.clone(),
+                                outputs,
+                                full_extranonce_size,
+                            )
+                            .map_err(|_| JDCErrorKind::FailedToCreateCustomJob)
+                            .and_then(|custom_job| {
+                                validate_custom_job_height(
+                                    &template,
+                                    data.chain_tip_height,
+                                    custom_job,
+                                )
+                            })
+                            .inspect_err(|e| {
+                                error!(
+                                    "Not sending custom job for template {}: {e}",
+                                    template.template_id
+                                )
+                            });
+                        if let Ok(custom_job) = custom_job {
                             let last_declare = DeclaredJob {
                                 declare_mining_job: None,
                                 template: template.into_static(),
@@ -187,7 +236,9 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
                             };
 
                             data.last_declare_job_store.insert(request_id, last_declare);
-                            Some(custom_job)
+                            data.pending_custom_jobs
+                                .push(request_id)
+                                .then_some(custom_job)
                         } else {
                             None
                         }
@@ -241,7 +292,9 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
                 }
             }
 
-            if get_jd_mode() == JdMode::CoinbaseOnly {
+            if get_jd_mode() == JdMode::CoinbaseOnly
+                && self.upstream_work_selection.load(Ordering::Relaxed)
+            {
                 if let Some(custom_job) = custom_job {
                     let set_custom_job = Mining::SetCustomMiningJob(custom_job);
                     let sv2_frame: Sv2Frame = AnyMessage::Mining(set_custom_job)
@@ -289,9 +342,10 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
 
     // Handles `OpenMiningChannelError` messages received from upstream.
     //
-    // Receiving this message is treated as malicious behavior, since JDC only supports
-    // extended channels. When encountered, we immediately trigger the fallback mechanism
-    // by transitioning the upstream state into a shutdown-fallback mode.
+    // The JDC declares its jobs with `SetCustomMiningJob`, which only extended channels carry, so
+    // it cannot retry with a standard channel. We immediately trigger the fallback mechanism by
+    // transitioning the upstream state into a shutdown-fallback mode, moving on to the next
+    // upstream.
     async fn handle_open_mining_channel_error(
         &mut self,
         _server_id: Option<usize>,
@@ -365,10 +419,19 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
                         if full_extranonce_size > MAX_EXTRANONCE_LEN {
                             return Err(JDCError::fallback(JDCErrorKind::ExtranonceSizeTooLarge));
                         }
+                        if self.jdc_search_space_bytes >= rollable_extranonce_size as usize {
+                            return Err(JDCError::fallback(
+                                JDCErrorKind::JdcSearchSpaceExceedsUpstreamExtranonce(
+                                    self.jdc_search_space_bytes,
+                                    rollable_extranonce_size as usize,
+                                ),
+                            ));
+                        }
 
                         let range_0 = 0..new_prefix_len;
-                        let range_1 = new_prefix_len..new_prefix_len + JDC_SEARCH_SPACE_BYTES;
-                        let range_2 = new_prefix_len + JDC_SEARCH_SPACE_BYTES..full_extranonce_size;
+                        let range_1 = new_prefix_len..new_prefix_len + self.jdc_search_space_bytes;
+                        let range_2 =
+                            new_prefix_len + self.jdc_search_space_bytes..full_extranonce_size;
 
                         debug!(
                             new_prefix_len,
@@ -482,7 +545,12 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
         msg: SubmitSharesError<'_>,
         _tlv_fields: Option<&[Tlv]>,
     ) -> Result<(), Self::Error> {
-        warn!("Received: {} ❌", msg);
+        if let Some(suppressed) = self
+            .log_throttle
+            .admit("submit_shares_error", &msg.error_code.as_utf8_or_hex())
+        {
+            warn!("Received: {} ❌{suppressed}", msg);
+        }
         Ok(())
     }
 
@@ -535,7 +603,7 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
         _tlv_fields: Option<&[Tlv]>,
     ) -> Result<(), Self::Error> {
         info!("Received: {} ✅", msg);
-        self.channel_manager_data.super_safe_lock(|data| {
+        let deferred_jobs = self.channel_manager_data.super_safe_lock(|data| {
             if let Some(last_declare_job) = data.last_declare_job_store.remove(&msg.request_id) {
                 let template_id = last_declare_job.template.template_id;
                 data.last_declare_job_store
@@ -560,14 +628,42 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
                     "No matching declare job found for custom job success"
                 );
             }
+
+            data.pending_custom_jobs
+                .acknowledge(&msg.request_id, |request_id| {
+                    data.last_declare_job_store.get(request_id).is_some()
+                })
+                .into_iter()
+                .filter_map(|request_id| {
+                    data.last_declare_job_store
+                        .get(&request_id)?
+                        .set_custom_mining_job
+                        .clone()
+                })
+                .collect::<Vec<_>>()
         });
+
+        for custom_job in deferred_jobs {
+            debug!(
+                request_id = custom_job.request_id,
+                "Sending deferred custom job"
+            );
+            let sv2_frame: Sv2Frame = AnyMessage::Mining(Mining::SetCustomMiningJob(custom_job))
+                .try_into()
+                .map_err(JDCError::shutdown)?;
+            self.channel_manager_channel
+                .upstream_sender
+                .send(sv2_frame)
+                .await
+                .map_err(|_e| JDCError::fallback(JDCErrorKind::ChannelErrorSender))?;
+        }
         Ok(())
     }
 
     // Handles a `SetCustomMiningJobError` from upstream.
     //
-    // Receiving this is treated as malicious behavior, so we immediately
-    // trigger the fallback mechanism.
+    // The refused job frees its slot among the pending custom jobs. Receiving this is treated as
+    // malicious behavior, so we immediately trigger the fallback mechanism.
     async fn handle_set_custom_mining_job_error(
         &mut self,
         _server_id: Option<usize>,
@@ -575,6 +671,13 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
         _tlv_fields: Option<&[Tlv]>,
     ) -> Result<(), Self::Error> {
         warn!("⚠️ Received: {} ❌", msg);
+        self.channel_manager_data.super_safe_lock(|data| {
+            data.last_declare_job_store.remove(&msg.request_id);
+            data.pending_custom_jobs
+                .acknowledge(&msg.request_id, |request_id| {
+                    data.last_declare_job_store.get(request_id).is_some()
+                });
+        });
         warn!("⚠️ Starting fallback mechanism.");
         Err(JDCError::fallback(JDCErrorKind::CustomJobError))
     }
@@ -3,15 +3,31 @@ use std::{
     net::SocketAddr,
     path::{Path, PathBuf},
     str::FromStr,
+    time::Duration,
 };
 use stratum_apps::{
-    config_helpers::{opt_path_from_toml, CoinbaseRewardScript},
+    config_helpers::{
+        authority_secret_key_from_toml, opt_path_from_toml, AllUpstreamsFailedPolicy,
+        CoinbaseRewardDescriptor, CoinbaseRewardScript,
+    },
     key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
-    stratum_core::bitcoin::{Amount, TxOut},
+    network_helpers::{noise_stream::DEFAULT_MAX_FRAME_SIZE, slow_consumer::SlowConsumerPolicy},
+    stratum_core::{
+        bitcoin::{
+            consensus::{encode::MAX_VEC_SIZE, Encodable},
+            Amount, TxOut,
+        },
+        channels_sv2::outputs::deserialize_outputs,
+    },
     tp_type::TemplateProviderType,
-    utils::types::{SharesBatchSize, SharesPerMinute},
+    utils::{
+        bind_retry::BindRetry,
+        types::{SharesBatchSize, SharesPerMinute},
+    },
 };
 
+use crate::{channel_manager::DEFAULT_JDC_SEARCH_SPACE_BYTES, error::JDCErrorKind};
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct JobDeclaratorClientConfig {
     // The address on which the JDC will listen for incoming connections when acting as an
@@ -23,7 +39,9 @@ pub struct JobDeclaratorClientConfig {
     min_supported_version: u16,
     // The public key used by this JDC for noise encryption.
     authority_public_key: Secp256k1PublicKey,
-    /// The secret key used by this JDC for noise encryption.
+    /// The secret key used by this JDC for noise encryption. Can be read from a file or an
+    /// environment variable instead, see [`authority_secret_key_from_toml`].
+    #[serde(flatten, deserialize_with = "authority_secret_key_from_toml")]
     authority_secret_key: Secp256k1SecretKey,
     /// The validity period (in seconds) for the certificate used in noise.
     cert_validity_sec: u64,
@@ -32,7 +50,8 @@ pub struct JobDeclaratorClientConfig {
     /// A list of upstream Job Declarator Servers (JDS) that this JDC can connect to.
     /// JDC can fallover between these upstreams.
     upstreams: Vec<Upstream>,
-    /// This is only used during solo-mining.
+    /// This is only used during solo-mining. In `FullTemplate` and `CoinbaseOnly` modes, the
+    /// coinbase outputs are the ones sent by the JDS in `AllocateMiningJobTokenSuccess`.
     pub coinbase_reward_script: CoinbaseRewardScript,
     /// A signature string identifying this JDC instance.
     jdc_signature: String,
@@ -57,12 +76,168 @@ pub struct JobDeclaratorClientConfig {
     monitoring_address: Option<SocketAddr>,
     #[serde(default = "default_monitoring_cache_refresh_secs")]
     monitoring_cache_refresh_secs: u64,
+    /// Age, in seconds, past which a monitoring request rebuilds the snapshot itself, bounding
+    /// the served staleness if the periodic refresh stalls. Unset disables it.
+    #[serde(default)]
+    monitoring_on_demand_refresh_secs: Option<u64>,
+    /// Bearer token the `POST /api/v1/failover` admin action of the monitoring server requires.
+    /// Unset keeps the action disabled.
+    #[serde(default)]
+    monitoring_admin_token: Option<String>,
+    /// Log the coinbase outputs paid at startup, and report the ones currently paid, such as the
+    /// outputs of the JDS in JD mode, on the monitoring server.
+    #[serde(default)]
+    report_coinbase_outputs: bool,
+    /// Number of extranonce bytes the JDC reserves to split the search space among its
+    /// downstreams.
+    #[serde(default = "default_jdc_search_space_bytes")]
+    jdc_search_space_bytes: usize,
+    /// Maximum number of concurrent downstream connections from a single IP address. `0`
+    /// disables the limit.
+    #[serde(default = "default_max_connections_per_ip")]
+    max_connections_per_ip: u32,
+    /// Maximum number of new downstream connections accepted per second, across all IP
+    /// addresses. `0` disables the limit.
+    #[serde(default = "default_max_accepts_per_sec")]
+    max_accepts_per_sec: u32,
+    /// Number of downstream connections closed within a minute above which a warning about
+    /// flapping miners is logged. `0` disables the warning.
+    #[serde(default)]
+    churn_warning_per_minute: u32,
+    /// Ranged descriptor the solo mining coinbase output is derived from, moving on to the next
+    /// index once a block is found. Takes precedence over `coinbase_reward_script` while solo
+    /// mining.
+    #[serde(default)]
+    solo_coinbase_descriptor: Option<CoinbaseRewardDescriptor>,
+    /// File storing the next unused index of `solo_coinbase_descriptor`.
+    #[serde(default = "default_solo_descriptor_index_file")]
+    solo_descriptor_index_file: PathBuf,
+    /// Number of running tasks above which a warning is logged. Tasks are still spawned past it.
+    /// Unset disables the soft cap.
+    #[serde(default)]
+    max_tasks: Option<usize>,
+    /// Number of attempts to reconnect to a `Sv2Tp` template provider after the connection to it
+    /// drops, while the last known job keeps being served. `0` shuts JDC down right away.
+    #[serde(default)]
+    template_provider_reconnect_attempts: u32,
+    /// Seconds to wait before the first reconnection to the template provider, doubled before
+    /// every next attempt.
+    #[serde(default = "default_template_provider_reconnect_backoff_secs")]
+    template_provider_reconnect_backoff_secs: u64,
+    /// What to do once every upstream failed: `solo`, `shutdown` or `wait_and_retry`.
+    #[serde(default = "default_on_all_upstreams_failed")]
+    on_all_upstreams_failed: AllUpstreamsFailedPolicy,
+    /// Seconds to wait before trying every upstream again with the `wait_and_retry` policy.
+    #[serde(default = "default_upstream_retry_interval_secs")]
+    upstream_retry_interval_secs: u64,
+    /// Largest SV2 frame, in bytes, accepted from upstreams, the template provider and
+    /// downstreams. A peer announcing a larger frame is disconnected before its payload is read.
+    #[serde(default = "default_max_frame_size")]
+    max_frame_size: usize,
+    /// Number of messages waiting to be sent to a downstream above which it is falling behind.
+    /// Unset disables slow consumer detection.
+    #[serde(default)]
+    slow_consumer_queue_threshold: Option<usize>,
+    /// Seconds the queue towards a downstream must stay above `slow_consumer_queue_threshold`
+    /// for the downstream to be flagged as a slow consumer.
+    #[serde(default = "default_slow_consumer_secs")]
+    slow_consumer_secs: u64,
+    /// Whether a downstream flagged as a slow consumer is disconnected instead of only being
+    /// reported.
+    #[serde(default)]
+    disconnect_slow_consumers: bool,
+    /// Whether to keep the downstream listener closed until the JDS allocated the first mining
+    /// job token, so miners only connect once jobs can be declared. Solo mining does not wait.
+    #[serde(default)]
+    defer_downstream_until_upstream_ready: bool,
+    /// Whether to log the message type and payload hex of every SV2 frame exchanged with the
+    /// pool, the JDS, the template provider and downstreams, at trace level.
+    #[serde(default)]
+    frame_trace: bool,
+    /// Maximum number of `SetCustomMiningJob` requests waiting for an acknowledgement from the
+    /// upstream in `CoinbaseOnly` mode. Newer custom jobs are deferred until acknowledgements
+    /// arrive. `0` disables the cap.
+    #[serde(default = "default_max_pending_custom_jobs")]
+    max_pending_custom_jobs: usize,
+    /// Seconds after which a `SetCustomMiningJob` the upstream did not acknowledge no longer
+    /// counts against `max_pending_custom_jobs`.
+    #[serde(default = "default_pending_custom_job_timeout_secs")]
+    pending_custom_job_timeout_secs: u64,
+    /// Seconds during which a repeated warning, such as a failed connection attempt or a rejected
+    /// share, is logged only once per upstream or error code. The next one is logged with the
+    /// number suppressed, or a summary once the window is over if none follows. Unset logs every
+    /// occurrence.
+    #[serde(default)]
+    log_throttle_window_secs: Option<u64>,
+    /// Number of times binding the downstream or monitoring listener is retried while its
+    /// address is still in use, e.g. by the previous process on a fast restart.
+    #[serde(default = "default_bind_retries")]
+    bind_retries: u32,
+    /// Milliseconds between two binds of a listener whose address is in use.
+    #[serde(default = "default_bind_retry_delay_ms")]
+    bind_retry_delay_ms: u64,
 }
 
 fn default_monitoring_cache_refresh_secs() -> u64 {
     15
 }
 
+fn default_work_selection() -> bool {
+    true
+}
+
+fn default_jdc_search_space_bytes() -> usize {
+    DEFAULT_JDC_SEARCH_SPACE_BYTES
+}
+
+fn default_max_connections_per_ip() -> u32 {
+    100
+}
+
+fn default_max_accepts_per_sec() -> u32 {
+    100
+}
+
+fn default_solo_descriptor_index_file() -> PathBuf {
+    PathBuf::from("jdc-solo-descriptor-index")
+}
+
+fn default_on_all_upstreams_failed() -> AllUpstreamsFailedPolicy {
+    AllUpstreamsFailedPolicy::Solo
+}
+
+fn default_upstream_retry_interval_secs() -> u64 {
+    30
+}
+
+fn default_template_provider_reconnect_backoff_secs() -> u64 {
+    2
+}
+
+fn default_max_frame_size() -> usize {
+    DEFAULT_MAX_FRAME_SIZE
+}
+
+fn default_slow_consumer_secs() -> u64 {
+    10
+}
+
+fn default_max_pending_custom_jobs() -> usize {
+    8
+}
+
+fn default_pending_custom_job_timeout_secs() -> u64 {
+    30
+}
+
+fn default_bind_retries() -> u32 {
+    5
+}
+
+fn default_bind_retry_delay_ms() -> u64 {
+    500
+}
+
 impl JobDeclaratorClientConfig {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
@@ -102,19 +277,323 @@ impl JobDeclaratorClientConfig {
             required_extensions,
             monitoring_address: None,
             monitoring_cache_refresh_secs: 15,
+            monitoring_on_demand_refresh_secs: None,
+            monitoring_admin_token: None,
+            report_coinbase_outputs: false,
+            jdc_search_space_bytes: DEFAULT_JDC_SEARCH_SPACE_BYTES,
+            max_connections_per_ip: default_max_connections_per_ip(),
+            max_accepts_per_sec: default_max_accepts_per_sec(),
+            churn_warning_per_minute: 0,
+            solo_coinbase_descriptor: None,
+            solo_descriptor_index_file: default_solo_descriptor_index_file(),
+            max_tasks: None,
+            template_provider_reconnect_attempts: 0,
+            template_provider_reconnect_backoff_secs:
+                default_template_provider_reconnect_backoff_secs(),
+            on_all_upstreams_failed: default_on_all_upstreams_failed(),
+            upstream_retry_interval_secs: default_upstream_retry_interval_secs(),
+            max_frame_size: default_max_frame_size(),
+            slow_consumer_queue_threshold: None,
+            slow_consumer_secs: default_slow_consumer_secs(),
+            disconnect_slow_consumers: false,
+            defer_downstream_until_upstream_ready: false,
+            frame_trace: false,
+            max_pending_custom_jobs: default_max_pending_custom_jobs(),
+            pending_custom_job_timeout_secs: default_pending_custom_job_timeout_secs(),
+            log_throttle_window_secs: None,
+            bind_retries: default_bind_retries(),
+            bind_retry_delay_ms: default_bind_retry_delay_ms(),
+        }
+    }
+
+    /// Sets the number of extranonce bytes the JDC reserves for its downstreams.
+    pub fn with_jdc_search_space_bytes(mut self, jdc_search_space_bytes: usize) -> Self {
+        self.jdc_search_space_bytes = jdc_search_space_bytes;
+        self
+    }
+
+    /// Returns the number of extranonce bytes the JDC reserves for its downstreams.
+    pub fn jdc_search_space_bytes(&self) -> usize {
+        self.jdc_search_space_bytes
+    }
+
+    /// Sets the per-IP connection cap and the accept rate limit of the downstream server. `0`
+    /// disables the corresponding limit.
+    pub fn with_connection_limits(
+        mut self,
+        max_connections_per_ip: u32,
+        max_accepts_per_sec: u32,
+    ) -> Self {
+        self.max_connections_per_ip = max_connections_per_ip;
+        self.max_accepts_per_sec = max_accepts_per_sec;
+        self
+    }
+
+    /// Returns the maximum number of concurrent downstream connections per IP address.
+    pub fn max_connections_per_ip(&self) -> u32 {
+        self.max_connections_per_ip
+    }
+
+    /// Sets the number of running tasks above which a warning is logged.
+    pub fn with_max_tasks(mut self, max_tasks: usize) -> Self {
+        self.max_tasks = Some(max_tasks);
+        self
+    }
+
+    /// Returns the soft cap on running tasks, if any.
+    pub fn max_tasks(&self) -> Option<usize> {
+        self.max_tasks
+    }
+
+    /// Sets how many times, and after how many seconds doubling between attempts, JDC reconnects
+    /// to a `Sv2Tp` template provider whose connection dropped. `0` attempts shut JDC down right
+    /// away.
+    pub fn with_template_provider_reconnect(mut self, attempts: u32, backoff_secs: u64) -> Self {
+        self.template_provider_reconnect_attempts = attempts;
+        self.template_provider_reconnect_backoff_secs = backoff_secs;
+        self
+    }
+
+    /// Returns the number of attempts to reconnect to the template provider.
+    pub fn template_provider_reconnect_attempts(&self) -> u32 {
+        self.template_provider_reconnect_attempts
+    }
+
+    /// Returns the wait before the first reconnection to the template provider.
+    pub fn template_provider_reconnect_backoff(&self) -> Duration {
+        Duration::from_secs(self.template_provider_reconnect_backoff_secs)
+    }
+
+    /// Returns the maximum number of new downstream connections accepted per second.
+    pub fn max_accepts_per_sec(&self) -> u32 {
+        self.max_accepts_per_sec
+    }
+
+    /// Sets the number of downstream connections closed within a minute above which a warning is
+    /// logged. `0` disables the warning.
+    pub fn with_churn_warning_per_minute(mut self, churn_warning_per_minute: u32) -> Self {
+        self.churn_warning_per_minute = churn_warning_per_minute;
+        self
+    }
+
+    /// Returns the number of downstream connections closed within a minute above which a warning is
+    /// logged.
+    pub fn churn_warning_per_minute(&self) -> u32 {
+        self.churn_warning_per_minute
+    }
+
+    /// Sets what to do once every upstream failed, and how long to wait before trying them all
+    /// again with [`AllUpstreamsFailedPolicy::WaitAndRetry`].
+    pub fn with_on_all_upstreams_failed(
+        mut self,
+        policy: AllUpstreamsFailedPolicy,
+        retry_interval_secs: u64,
+    ) -> Self {
+        self.on_all_upstreams_failed = policy;
+        self.upstream_retry_interval_secs = retry_interval_secs;
+        self
+    }
+
+    /// Returns what to do once every upstream failed.
+    pub fn on_all_upstreams_failed(&self) -> AllUpstreamsFailedPolicy {
+        self.on_all_upstreams_failed
+    }
+
+    /// Returns how long to wait before trying every upstream again.
+    pub fn upstream_retry_interval(&self) -> Duration {
+        Duration::from_secs(self.upstream_retry_interval_secs)
+    }
+
+    /// Sets the largest SV2 frame accepted from peers, in bytes.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Returns the largest SV2 frame accepted from peers, in bytes.
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Enables slow consumer detection, flagging downstreams whose queue stays above
+    /// `queue_threshold` messages for `secs` seconds, and whether flagged downstreams are
+    /// disconnected.
+    pub fn with_slow_consumer_detection(
+        mut self,
+        queue_threshold: usize,
+        secs: u64,
+        disconnect: bool,
+    ) -> Self {
+        self.slow_consumer_queue_threshold = Some(queue_threshold);
+        self.slow_consumer_secs = secs;
+        self.disconnect_slow_consumers = disconnect;
+        self
+    }
+
+    /// Returns when a downstream is flagged as a slow consumer, if the detection is enabled.
+    pub fn slow_consumer_policy(&self) -> Option<SlowConsumerPolicy> {
+        self.slow_consumer_queue_threshold
+            .map(|queue_threshold| SlowConsumerPolicy {
+                queue_threshold,
+                duration: Duration::from_secs(self.slow_consumer_secs),
+                disconnect: self.disconnect_slow_consumers,
+            })
+    }
+
+    /// Sets whether the downstream listener stays closed until the JDS allocated the first mining
+    /// job token.
+    pub fn with_defer_downstream_until_upstream_ready(mut self, defer: bool) -> Self {
+        self.defer_downstream_until_upstream_ready = defer;
+        self
+    }
+
+    /// Returns whether the downstream listener stays closed until the JDS allocated the first
+    /// mining job token.
+    pub fn defer_downstream_until_upstream_ready(&self) -> bool {
+        self.defer_downstream_until_upstream_ready
+    }
+
+    /// Sets whether the SV2 frames exchanged on every connection are logged at trace level.
+    pub fn with_frame_trace(mut self, frame_trace: bool) -> Self {
+        self.frame_trace = frame_trace;
+        self
+    }
+
+    /// Returns whether the SV2 frames exchanged on every connection are logged at trace level.
+    pub fn frame_trace(&self) -> bool {
+        self.frame_trace
+    }
+
+    /// Sets the maximum number of custom jobs waiting for an acknowledgement from the upstream.
+    /// `0` disables the cap.
+    pub fn with_max_pending_custom_jobs(mut self, max_pending_custom_jobs: usize) -> Self {
+        self.max_pending_custom_jobs = max_pending_custom_jobs;
+        self
+    }
+
+    /// Returns the maximum number of custom jobs waiting for an acknowledgement from the
+    /// upstream.
+    pub fn max_pending_custom_jobs(&self) -> usize {
+        self.max_pending_custom_jobs
+    }
+
+    /// Sets how long, in seconds, an unacknowledged custom job holds its slot.
+    pub fn with_pending_custom_job_timeout(mut self, pending_custom_job_timeout_secs: u64) -> Self {
+        self.pending_custom_job_timeout_secs = pending_custom_job_timeout_secs;
+        self
+    }
+
+    /// Returns how long an unacknowledged custom job holds its slot.
+    pub fn pending_custom_job_timeout(&self) -> Duration {
+        Duration::from_secs(self.pending_custom_job_timeout_secs)
+    }
+
+    /// Sets the window during which a repeated warning is logged only once.
+    pub fn with_log_throttle_window(mut self, log_throttle_window_secs: u64) -> Self {
+        self.log_throttle_window_secs = Some(log_throttle_window_secs);
+        self
+    }
+
+    /// Returns the window during which a repeated warning is logged only once, if throttled.
+    pub fn log_throttle_window(&self) -> Option<Duration> {
+        self.log_throttle_window_secs.map(Duration::from_secs)
+    }
+
+    /// Retries binding a listener whose address is in use `retries` times, `delay_ms`
+    /// milliseconds apart. No retries fail startup on the first bind failing.
+    pub fn with_bind_retry(mut self, retries: u32, delay_ms: u64) -> Self {
+        self.bind_retries = retries;
+        self.bind_retry_delay_ms = delay_ms;
+        self
+    }
+
+    /// Returns how binding a listener whose address is in use is retried.
+    pub fn bind_retry(&self) -> BindRetry {
+        BindRetry {
+            retries: self.bind_retries,
+            delay: Duration::from_millis(self.bind_retry_delay_ms),
         }
     }
 
+    /// Sets the ranged descriptor solo mining coinbase outputs are derived from, and the file
+    /// storing its next unused index.
+    pub fn with_solo_coinbase_descriptor(
+        mut self,
+        descriptor: CoinbaseRewardDescriptor,
+        index_file: PathBuf,
+    ) -> Self {
+        self.solo_coinbase_descriptor = Some(descriptor);
+        self.solo_descriptor_index_file = index_file;
+        self
+    }
+
+    /// Returns the ranged descriptor solo mining coinbase outputs are derived from, if any.
+    pub fn solo_coinbase_descriptor(&self) -> Option<&CoinbaseRewardDescriptor> {
+        self.solo_coinbase_descriptor.as_ref()
+    }
+
+    /// Returns the file storing the next unused index of the solo coinbase descriptor.
+    pub fn solo_descriptor_index_file(&self) -> &Path {
+        &self.solo_descriptor_index_file
+    }
+
     /// Returns the monitoring server bind address (if enabled)
     pub fn monitoring_address(&self) -> Option<SocketAddr> {
         self.monitoring_address
     }
 
+    /// Enables the monitoring server on `monitoring_address`.
+    pub fn with_monitoring(
+        mut self,
+        monitoring_address: SocketAddr,
+        monitoring_cache_refresh_secs: u64,
+    ) -> Self {
+        self.monitoring_address = Some(monitoring_address);
+        self.monitoring_cache_refresh_secs = monitoring_cache_refresh_secs;
+        self
+    }
+
     /// Returns the monitoring cache refresh interval in seconds.
     pub fn monitoring_cache_refresh_secs(&self) -> u64 {
         self.monitoring_cache_refresh_secs
     }
 
+    /// Makes monitoring requests rebuild a snapshot older than `max_staleness_secs` seconds.
+    pub fn with_monitoring_on_demand_refresh(mut self, max_staleness_secs: u64) -> Self {
+        self.monitoring_on_demand_refresh_secs = Some(max_staleness_secs);
+        self
+    }
+
+    /// Returns the snapshot age past which monitoring requests rebuild it, if enabled.
+    pub fn monitoring_on_demand_refresh(&self) -> Option<Duration> {
+        self.monitoring_on_demand_refresh_secs
+            .map(Duration::from_secs)
+    }
+
+    /// Enables the failover admin action of the monitoring server, guarded by `admin_token`.
+    pub fn with_monitoring_admin_token(mut self, admin_token: String) -> Self {
+        self.monitoring_admin_token = Some(admin_token);
+        self
+    }
+
+    /// Returns the bearer token of the failover admin action, `None` when it is disabled.
+    pub fn monitoring_admin_token(&self) -> Option<&str> {
+        self.monitoring_admin_token.as_deref()
+    }
+
+    /// Sets whether the coinbase outputs paid are logged at startup and reported on the
+    /// monitoring server.
+    pub fn with_report_coinbase_outputs(mut self, report_coinbase_outputs: bool) -> Self {
+        self.report_coinbase_outputs = report_coinbase_outputs;
+        self
+    }
+
+    /// Returns whether the coinbase outputs paid are logged at startup and reported on the
+    /// monitoring server.
+    pub fn report_coinbase_outputs(&self) -> bool {
+        self.report_coinbase_outputs
+    }
+
     /// Returns the listening address of the Job Declarator Client.
     pub fn listening_address(&self) -> &SocketAddr {
         &self.listening_address
@@ -169,6 +648,31 @@ impl JobDeclaratorClientConfig {
         }
     }
 
+    /// Checks that the coinbase output built from `coinbase_reward_script` can be encoded and
+    /// decoded back, as the channel manager does at runtime, and that its address belongs to the
+    /// network of the Bitcoin Core template provider, if one is configured.
+    pub fn validate_coinbase_outputs(&self) -> Result<(), JDCErrorKind> {
+        if let TemplateProviderType::BitcoinCoreIpc { network, .. } = &self.template_provider_type {
+            self.coinbase_reward_script
+                .check_network(network.network())
+                .map_err(JDCErrorKind::CoinbaseRewardNetwork)?;
+        }
+        validate_coinbase_outputs(vec![self.get_txout()])
+    }
+
+    /// Checks that every upstream grants the JDC work selection. Without it the pool expects
+    /// shares for its own jobs, which the JDC never mines.
+    pub fn validate_work_selection(&self) -> Result<(), JDCErrorKind> {
+        match self
+            .upstreams
+            .iter()
+            .find(|upstream| !upstream.work_selection)
+        {
+            Some(upstream) => Err(JDCErrorKind::WorkSelectionRequired(upstream.label())),
+            None => Ok(()),
+        }
+    }
+
     pub fn log_file(&self) -> Option<&Path> {
         self.log_file.as_deref()
     }
@@ -227,6 +731,22 @@ where
     Ok(ConfigJDCMode::from_str(&s).unwrap_or_default())
 }
 
+// Round-trips `outputs` through the consensus encoding the channel manager keeps them in.
+fn validate_coinbase_outputs(outputs: Vec<TxOut>) -> Result<(), JDCErrorKind> {
+    let mut encoded = vec![];
+    outputs
+        .consensus_encode(&mut encoded)
+        .map_err(|_| JDCErrorKind::InvalidCoinbaseOutputs)?;
+    // Consensus decoders refuse to read more than `MAX_VEC_SIZE` bytes at once
+    if encoded.len() > MAX_VEC_SIZE {
+        return Err(JDCErrorKind::InvalidCoinbaseOutputs);
+    }
+    match deserialize_outputs(encoded) {
+        Ok(decoded) if decoded == outputs => Ok(()),
+        _ => Err(JDCErrorKind::InvalidCoinbaseOutputs),
+    }
+}
+
 /// Represents pool specific encryption keys.
 pub struct PoolConfig {
     authority_public_key: Secp256k1PublicKey,
@@ -286,6 +806,18 @@ pub struct Upstream {
     /// and caps vardiff targets to prevent shares from being silently dropped
     #[serde(default)]
     pub propagate_upstream_target: bool,
+    /// Optional name used as the `upstream` label in monitoring metrics.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Endpoints (`host:port`, or `host` for any port) the pool may redirect the JDC to with
+    /// `Reconnect`. A `Reconnect` to any other endpoint is ignored.
+    #[serde(default)]
+    pub reconnect_allowlist: Vec<String>,
+    /// Whether the JDC asks the pool for work selection (`REQUIRES_WORK_SELECTION`) and sends it
+    /// the jobs it builds. The JDC only mines the jobs it declares, not the jobs of the pool, so
+    /// [`JobDeclaratorClientConfig::validate_work_selection`] rejects disabling it.
+    #[serde(default = "default_work_selection")]
+    pub work_selection: bool,
 }
 
 impl Upstream {
@@ -304,6 +836,85 @@ impl Upstream {
             jds_address,
             jds_port,
             propagate_upstream_target: false,
+            name: None,
+            reconnect_allowlist: Vec::new(),
+            work_selection: default_work_selection(),
         }
     }
+
+    /// Sets the name used to label this upstream in monitoring metrics.
+    pub fn with_name(mut self, name: String) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Sets the endpoints the pool may redirect the JDC to with `Reconnect`.
+    pub fn with_reconnect_allowlist(mut self, reconnect_allowlist: Vec<String>) -> Self {
+        self.reconnect_allowlist = reconnect_allowlist;
+        self
+    }
+
+    /// Sets whether the JDC asks the pool for work selection.
+    pub fn with_work_selection(mut self, work_selection: bool) -> Self {
+        self.work_selection = work_selection;
+        self
+    }
+
+    /// Returns the monitoring label of this upstream: its name, or the pool `address:port` if
+    /// unnamed.
+    pub fn label(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("{}:{}", self.pool_address, self.pool_port))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stratum_apps::stratum_core::bitcoin::ScriptBuf;
+
+    use super::*;
+
+    #[test]
+    fn malformed_coinbase_output_rejected_at_config_time() {
+        let script = CoinbaseRewardScript::from_descriptor(
+            "addr(tb1qpusf5256yxv50qt0pm0tue8k952fsu5lzsphft)",
+        )
+        .unwrap();
+        let valid = TxOut {
+            value: Amount::from_sat(0),
+            script_pubkey: script.script_pubkey().to_owned(),
+        };
+        assert!(validate_coinbase_outputs(vec![valid]).is_ok());
+
+        // a script too large to ever be decoded back
+        let malformed = TxOut {
+            value: Amount::from_sat(0),
+            script_pubkey: ScriptBuf::from_bytes(vec![0; MAX_VEC_SIZE]),
+        };
+        assert!(matches!(
+            validate_coinbase_outputs(vec![malformed]),
+            Err(JDCErrorKind::InvalidCoinbaseOutputs)
+        ));
+    }
+
+    #[test]
+    fn upstream_without_work_selection_rejected_at_config_time() {
+        let mut config = ext_config::Config::builder()
+            .add_source(ext_config::File::from_str(
+                include_str!("../../config-examples/mainnet/jdc-config-hosted-infra-example.toml"),
+                ext_config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize::<JobDeclaratorClientConfig>()
+            .unwrap();
+        assert!(config.validate_work_selection().is_ok());
+
+        config.upstreams[0].work_selection = false;
+        assert!(matches!(
+            config.validate_work_selection(),
+            Err(JDCErrorKind::WorkSelectionRequired(_))
+        ));
+    }
 }
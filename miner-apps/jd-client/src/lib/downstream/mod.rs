@@ -1,21 +1,31 @@
 use std::{
     collections::HashMap,
     sync::{atomic::AtomicU32, Arc},
+    time::Instant,
 };
 
 use async_channel::{unbounded, Receiver, Sender};
 use stratum_apps::{
     custom_mutex::Mutex,
-    network_helpers::noise_stream::NoiseTcpStream,
+    network_helpers::{
+        message_counters::MessageCounters,
+        noise_stream::NoiseTcpStream,
+        slow_consumer::{SlowConsumerDetector, SlowConsumerPolicy, SLOW_CONSUMER_CHECK_INTERVAL},
+    },
     stratum_core::{
-        channels_sv2::server::{
-            extended::ExtendedChannel,
-            group::GroupChannel,
-            jobs::{extended::ExtendedJob, job_store::DefaultJobStore, standard::StandardJob},
-            standard::StandardChannel,
+        binary_sv2::Sv2Option,
+        channels_sv2::{
+            chain_tip::ChainTip,
+            server::{
+                extended::ExtendedChannel,
+                group::GroupChannel,
+                jobs::{extended::ExtendedJob, job_store::DefaultJobStore, standard::StandardJob},
+                standard::StandardChannel,
+            },
         },
         common_messages_sv2::MESSAGE_TYPE_SETUP_CONNECTION,
         handlers_sv2::{HandleCommonMessagesFromClientAsync, HandleExtensionsFromClientAsync},
+        mining_sv2::{NewExtendedMiningJob, NewMiningJob, SetNewPrevHash},
         parsers_sv2::{parse_message_frame_with_tlvs, AnyMessage, Mining, Tlv},
     },
     task_manager::TaskManager,
@@ -58,6 +68,61 @@ pub struct DownstreamData {
     pub supported_extensions: Vec<u16>,
     /// Extensions that the JDC requires
     pub required_extensions: Vec<u16>,
+    /// Times this downstream lagged behind the channel manager broadcast and was re-synced
+    pub lagged_total: u64,
+}
+
+impl DownstreamData {
+    // Messages bringing the channels of this downstream back to their latest job: the active job
+    // is sent again as a future job, then activated by a `SetNewPrevHash` with the chain tip.
+    fn resync_messages(&self) -> Vec<Mining<'static>> {
+        let mut messages = vec![];
+        if !self.require_std_job && !self.group_channel.get_channel_ids().is_empty() {
+            if let (Some(job), Some(chain_tip)) = (
+                self.group_channel.get_active_job(),
+                self.group_channel.get_chain_tip(),
+            ) {
+                let job = NewExtendedMiningJob {
+                    min_ntime: Sv2Option::new(None),
+                    ..job.get_job_message().clone()
+                };
+                let prev_hash = set_new_prev_hash(job.channel_id, job.job_id, chain_tip);
+                messages.push(Mining::NewExtendedMiningJob(job));
+                messages.push(Mining::SetNewPrevHash(prev_hash));
+            }
+        }
+        if self.require_std_job {
+            for standard_channel in self.standard_channels.values() {
+                if let (Some(job), Some(chain_tip)) = (
+                    standard_channel.get_active_job(),
+                    standard_channel.get_chain_tip(),
+                ) {
+                    let job = NewMiningJob {
+                        min_ntime: Sv2Option::new(None),
+                        ..job.get_job_message().clone()
+                    };
+                    let prev_hash = set_new_prev_hash(job.channel_id, job.job_id, chain_tip);
+                    messages.push(Mining::NewMiningJob(job));
+                    messages.push(Mining::SetNewPrevHash(prev_hash));
+                }
+            }
+        }
+        messages
+    }
+}
+
+fn set_new_prev_hash(
+    channel_id: ChannelId,
+    job_id: u32,
+    chain_tip: &ChainTip,
+) -> SetNewPrevHash<'static> {
+    SetNewPrevHash {
+        channel_id,
+        job_id,
+        prev_hash: chain_tip.prev_hash(),
+        min_ntime: chain_tip.min_ntime(),
+        nbits: chain_tip.nbits(),
+    }
 }
 
 /// Communication layer for a downstream connection.
@@ -82,6 +147,10 @@ pub struct Downstream {
     pub downstream_data: Arc<Mutex<DownstreamData>>,
     downstream_channel: DownstreamChannel,
     pub downstream_id: DownstreamId,
+    /// When this downstream is flagged as a slow consumer, if the detection is enabled
+    slow_consumer_policy: Option<SlowConsumerPolicy>,
+    /// Flags this downstream as a slow consumer when the frames waiting to be sent to it pile up
+    pub slow_consumer: Arc<SlowConsumerDetector>,
 }
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -104,6 +173,10 @@ impl Downstream {
         status_sender: Sender<Status>,
         supported_extensions: Vec<u16>,
         required_extensions: Vec<u16>,
+        max_frame_size: usize,
+        frame_trace: bool,
+        message_counters: Arc<MessageCounters>,
+        slow_consumer_policy: Option<SlowConsumerPolicy>,
     ) -> Self {
         let (noise_stream_reader, noise_stream_writer) = noise_stream.into_split();
         let status_sender = StatusSender::Downstream {
@@ -118,6 +191,9 @@ impl Downstream {
             noise_stream_writer,
             outbound_rx,
             inbound_tx,
+            max_frame_size,
+            frame_trace,
+            message_counters,
             notify_shutdown,
             status_sender,
         );
@@ -138,12 +214,15 @@ impl Downstream {
             negotiated_extensions: vec![],
             supported_extensions,
             required_extensions,
+            lagged_total: 0,
         }));
 
         Downstream {
             downstream_channel,
             downstream_data,
             downstream_id,
+            slow_consumer_policy,
+            slow_consumer: Arc::new(SlowConsumerDetector::new()),
         }
     }
 
@@ -180,6 +259,7 @@ impl Downstream {
 
         let mut receiver = self.downstream_channel.channel_manager_receiver.subscribe();
         task_manager.spawn(async move {
+            let mut slow_consumer_check = tokio::time::interval(SLOW_CONSUMER_CHECK_INTERVAL);
             loop {
                 let self_clone_1 = self.clone();
                 let downstream_id = self_clone_1.downstream_id;
@@ -222,6 +302,13 @@ impl Downstream {
                             }
                         }
                     }
+                    _ = slow_consumer_check.tick(), if self.slow_consumer_policy.is_some() => {
+                        if let Err(e) = self.check_slow_consumer() {
+                            if handle_error(&status_sender, e).await {
+                                break;
+                            }
+                        }
+                    }
 
                 }
             }
@@ -229,6 +316,39 @@ impl Downstream {
         });
     }
 
+    /// Samples the number of frames waiting to be sent to the downstream, flagging it as a slow
+    /// consumer once the queue stayed above the configured threshold for long enough.
+    ///
+    /// A flagged downstream is disconnected if the policy says so.
+    pub fn check_slow_consumer(&self) -> JDCResult<(), error::Downstream> {
+        let Some(policy) = &self.slow_consumer_policy else {
+            return Ok(());
+        };
+        let queue_depth = self.send_queue_depth();
+        if !self
+            .slow_consumer
+            .observe(queue_depth, policy, Instant::now())
+        {
+            return Ok(());
+        }
+        warn!(
+            downstream_id = self.downstream_id,
+            queue_depth, "Slow consumer: frames queued for more than {:?}", policy.duration
+        );
+        if policy.disconnect {
+            return Err(JDCError::disconnect(
+                JDCErrorKind::SlowConsumer(queue_depth),
+                self.downstream_id,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the number of frames waiting to be sent to the downstream.
+    pub fn send_queue_depth(&self) -> usize {
+        self.downstream_channel.downstream_sender.len()
+    }
+
     // Performs the initial handshake with a downstream peer.
     async fn setup_connection_with_downstream(&mut self) -> JDCResult<(), error::Downstream> {
         let mut frame = self
@@ -256,6 +376,9 @@ impl Downstream {
     ) -> JDCResult<(), error::Downstream> {
         let (downstream_id, message, _tlv_fields) = match receiver.recv().await {
             Ok(msg) => msg,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                return self.resync_after_lag(skipped).await;
+            }
             Err(e) => {
                 warn!(?e, "Broadcast receive failed");
                 return Err(JDCError::shutdown(
@@ -272,6 +395,35 @@ impl Downstream {
             return Ok(());
         }
 
+        self.send_to_downstream(message).await
+    }
+
+    // Re-syncs this downstream after it lagged `skipped` messages behind the channel manager
+    // broadcast, instead of taking the whole client down.
+    //
+    // The skipped messages may have included a new job or prevhash, so every channel is sent its
+    // latest job and the prevhash activating it.
+    async fn resync_after_lag(&self, skipped: u64) -> JDCResult<(), error::Downstream> {
+        let (messages, lagged_total) = self.downstream_data.super_safe_lock(|data| {
+            data.lagged_total += 1;
+            (data.resync_messages(), data.lagged_total)
+        });
+        warn!(
+            skipped,
+            lagged_total,
+            "Downstream lagged behind the channel manager broadcast, re-syncing to the latest job"
+        );
+        for message in messages {
+            self.send_to_downstream(message).await?;
+        }
+        Ok(())
+    }
+
+    // Sends a mining message to the downstream peer.
+    async fn send_to_downstream(
+        &self,
+        message: Mining<'static>,
+    ) -> JDCResult<(), error::Downstream> {
         let message = AnyMessage::Mining(message);
         let sv2_frame: Sv2Frame = message.try_into().map_err(JDCError::shutdown)?;
 
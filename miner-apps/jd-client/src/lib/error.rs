@@ -15,8 +15,11 @@ use ext_config::ConfigError;
 use std::{
     fmt::{self, Formatter},
     marker::PhantomData,
+    net::SocketAddr,
 };
 use stratum_apps::{
+    config_helpers::CoinbaseOutputError,
+    monitoring::FailoverReason,
     network_helpers,
     stratum_core::{
         binary_sv2, bitcoin,
@@ -200,14 +203,37 @@ pub enum JDCErrorKind {
     FrameConversionError,
     /// Failed to create custom Job
     FailedToCreateCustomJob,
+    /// Template coinbase prefix does not start with a BIP34 block height push
+    MissingCoinbaseHeight,
+    /// Custom job coinbase does not commit to the chain tip block height
+    CoinbaseHeightMismatch(u64),
+    /// Template commits to another block height than the chain tip (template height, chain tip
+    /// height)
+    TemplateHeightMismatch(u64, u64),
     /// Allocate Mining job token coinbase output error
     AllocateMiningJobTokenSuccessCoinbaseOutputsError,
     /// Channel manager has bad coinbase outputs.
     ChannelManagerHasBadCoinbaseOutputs,
+    /// Coinbase outputs pay more than the coinbase value left by the template.
+    CoinbaseValueMismatch {
+        available: u64,
+        fixed: u64,
+    },
     /// Declared job has bad coinbase outputs.
     DeclaredJobHasBadCoinbaseOutputs,
+    /// Configured coinbase outputs do not survive a consensus encode/decode round trip.
+    InvalidCoinbaseOutputs,
     /// Extranonce size is too large
     ExtranonceSizeTooLarge,
+    /// Configured JDC search space bytes do not fit within `MAX_EXTRANONCE_LEN`
+    InvalidJdcSearchSpaceBytes(usize),
+    /// Configured JDC search space bytes leave no room within the extranonce size granted by
+    /// upstream
+    JdcSearchSpaceExceedsUpstreamExtranonce(usize, usize),
+    /// Could not derive the solo coinbase output from the configured descriptor
+    SoloPayoutDerivation(CoinbaseOutputError),
+    /// Configured coinbase reward address is not valid on the network mined on
+    CoinbaseRewardNetwork(CoinbaseOutputError),
     /// Could not create group channel
     FailedToCreateGroupChannel(GroupChannelError),
     ///Channel Errors
@@ -232,6 +258,15 @@ pub enum JDCErrorKind {
     FailedToSendCoinbaseOutputConstraints,
     /// Setup Connection Error
     SetupConnectionError,
+    /// Upstream selected a protocol version outside the supported range
+    UnsupportedProtocolVersion(u16),
+    /// Upstream accepted the `SetupConnection` with flags incompatible with the requested ones
+    IncompatibleSetupConnectionFlags {
+        requested: u32,
+        success: u32,
+    },
+    /// An upstream is configured without work selection, which the JDC cannot mine for
+    WorkSelectionRequired(String),
     /// Endpoint changed
     ChangeEndpoint,
     /// Received upstream message during solo mining
@@ -248,10 +283,42 @@ pub enum JDCErrorKind {
     CustomJobError,
     /// Could not initiate subsystem
     CouldNotInitiateSystem,
+    /// An operator requested a failover to the next upstream through the monitoring server
+    ManualFailover,
+    /// The pool redirected the JDC to another endpoint with `Reconnect`
+    UpstreamReconnect(SocketAddr),
+    /// Messages queued towards a downstream stayed above the slow consumer threshold (queue
+    /// depth)
+    SlowConsumer(usize),
 }
 
 impl std::error::Error for JDCErrorKind {}
 
+impl JDCErrorKind {
+    /// Returns the failover reason reported to monitoring when this error makes the JDC fall
+    /// back to the next upstream.
+    pub fn failover_reason(&self) -> FailoverReason {
+        use JDCErrorKind::*;
+        match self {
+            SetupConnectionError => FailoverReason::SetupConnectionError,
+            OpenMiningChannelError | OpenStandardMiningChannelError => {
+                FailoverReason::OpenMiningChannelError
+            }
+            CloseChannel => FailoverReason::CloseChannel,
+            Timeout => FailoverReason::Timeout,
+            ManualFailover => FailoverReason::Manual,
+            UpstreamReconnect(_) => FailoverReason::Reconnect,
+            NetworkHelpersError(_)
+            | CodecNoise(_)
+            | FramingSv2(_)
+            | Io(_)
+            | ChannelErrorReceiver(_)
+            | ChannelErrorSender => FailoverReason::ConnectionLost,
+            _ => FailoverReason::Other,
+        }
+    }
+}
+
 impl fmt::Display for JDCErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use JDCErrorKind::*;
@@ -313,6 +380,21 @@ impl fmt::Display for JDCErrorKind {
             FailedToCreateCustomJob => {
                 write!(f, "failed to create custom job")
             }
+            MissingCoinbaseHeight => {
+                write!(f, "Template coinbase prefix has no BIP34 block height push")
+            }
+            CoinbaseHeightMismatch(height) => {
+                write!(
+                    f,
+                    "Custom job coinbase does not start with the BIP34 push of height {height}"
+                )
+            }
+            TemplateHeightMismatch(height, chain_tip_height) => {
+                write!(
+                    f,
+                    "Template commits to block height {height} instead of {chain_tip_height}"
+                )
+            }
             AllocateMiningJobTokenSuccessCoinbaseOutputsError => {
                 write!(
                     f,
@@ -322,12 +404,43 @@ impl fmt::Display for JDCErrorKind {
             ChannelManagerHasBadCoinbaseOutputs => {
                 write!(f, "Channel Manager coinbase outputs are not deserializable")
             }
+            CoinbaseValueMismatch { available, fixed } => {
+                write!(
+                    f,
+                    "Coinbase outputs pay {fixed} sats, more than the {available} sats left by \
+                     the template"
+                )
+            }
             DeclaredJobHasBadCoinbaseOutputs => {
                 write!(f, "Declared job coinbase outputs are not deserializable")
             }
+            InvalidCoinbaseOutputs => {
+                write!(
+                    f,
+                    "Configured coinbase outputs cannot be encoded and decoded back"
+                )
+            }
             ExtranonceSizeTooLarge => {
                 write!(f, "Extranonce size too large")
             }
+            InvalidJdcSearchSpaceBytes(bytes) => {
+                write!(
+                    f,
+                    "Invalid jdc_search_space_bytes {bytes}: must be non-zero and leave room for client search space within MAX_EXTRANONCE_LEN"
+                )
+            }
+            JdcSearchSpaceExceedsUpstreamExtranonce(bytes, upstream_size) => {
+                write!(
+                    f,
+                    "jdc_search_space_bytes {bytes} leaves no room within the extranonce size {upstream_size} granted by upstream"
+                )
+            }
+            SoloPayoutDerivation(ref e) => {
+                write!(f, "Failed to derive solo coinbase output: {e}")
+            }
+            CoinbaseRewardNetwork(ref e) => {
+                write!(f, "Invalid coinbase_reward_script: {e}")
+            }
             FailedToCreateGroupChannel(ref e) => {
                 write!(f, "Failed to create group channel: {e:?}")
             }
@@ -376,6 +489,26 @@ impl fmt::Display for JDCErrorKind {
             SetupConnectionError => {
                 write!(f, "Failed to Setup connection")
             }
+            UnsupportedProtocolVersion(version) => {
+                write!(
+                    f,
+                    "Upstream selected unsupported protocol version {version}"
+                )
+            }
+            IncompatibleSetupConnectionFlags { requested, success } => {
+                write!(
+                    f,
+                    "Upstream setup flags {success:#b} are incompatible with the requested flags \
+                     {requested:#b}"
+                )
+            }
+            WorkSelectionRequired(ref upstream) => {
+                write!(
+                    f,
+                    "Upstream {upstream} disables work_selection, but the JDC only mines the jobs \
+                     it declares"
+                )
+            }
             ChangeEndpoint => {
                 write!(f, "Change endpoint")
             }
@@ -388,6 +521,14 @@ impl fmt::Display for JDCErrorKind {
             CloseChannel => write!(f, "channel closed by upstream"),
             CustomJobError => write!(f, "Custom job not acknowledged"),
             CouldNotInitiateSystem => write!(f, "Could not initiate subsystem"),
+            ManualFailover => write!(f, "Failover requested by an operator"),
+            UpstreamReconnect(addr) => write!(f, "Upstream asked to reconnect to {addr}"),
+            SlowConsumer(queue_depth) => {
+                write!(
+                    f,
+                    "Downstream is not keeping up, {queue_depth} messages queued"
+                )
+            }
         }
     }
 }
@@ -2,7 +2,12 @@ use std::sync::Arc;
 
 use async_channel::{Receiver, Sender};
 use stratum_apps::{
-    network_helpers::noise_stream::{NoiseTcpReadHalf, NoiseTcpWriteHalf},
+    network_helpers::{
+        frame_trace::{trace_inbound_frame, trace_outbound_frame},
+        message_counters::MessageCounters,
+        noise_stream::{NoiseTcpReadHalf, NoiseTcpWriteHalf},
+        Error,
+    },
     stratum_core::framing_sv2::framing::Frame,
     task_manager::TaskManager,
     utils::types::{Message, Sv2Frame},
@@ -25,14 +30,19 @@ pub fn spawn_io_tasks(
     mut writer: NoiseTcpWriteHalf<Message>,
     outbound_rx: Receiver<Sv2Frame>,
     inbound_tx: Sender<Sv2Frame>,
+    max_frame_size: usize,
+    frame_trace: bool,
+    message_counters: Arc<MessageCounters>,
     notify_shutdown: broadcast::Sender<ShutdownMessage>,
     status_sender: StatusSender,
 ) {
     let caller = std::panic::Location::caller();
+    reader.set_max_frame_size(max_frame_size);
     let inbound_tx_clone = inbound_tx.clone();
     let outbound_rx_clone = outbound_rx.clone();
     {
         let mut shutdown_rx = notify_shutdown.subscribe();
+        let message_counters = message_counters.clone();
         let status_sender = status_sender.clone();
         let status_type: StatusType = StatusType::from(&status_sender);
 
@@ -97,8 +107,12 @@ pub fn spawn_io_tasks(
                                         drop(frame);
                                         break;
                                     },
-                                    Frame::Sv2(sv2_frame) => {
+                                    Frame::Sv2(mut sv2_frame) => {
                                         trace!("Received inbound frame");
+                                        message_counters.count_inbound(&sv2_frame);
+                                        if frame_trace {
+                                            trace_inbound_frame(&mut sv2_frame);
+                                        }
                                         if let Err(e) = inbound_tx.send(sv2_frame).await {
                                             inbound_tx.close();
                                             error!(error=?e, "Failed to forward inbound frame");
@@ -107,6 +121,11 @@ pub fn spawn_io_tasks(
                                     },
                                 }
                             }
+                            Err(Error::FrameTooLarge { size, max }) => {
+                                error!(size, max, "Frame above the maximum frame size, closing the connection");
+                                inbound_tx.close();
+                                break;
+                            }
                             Err(e) => {
                                 error!(error=?e, "Reader error");
                                 inbound_tx.close();
@@ -186,6 +205,17 @@ pub fn spawn_io_tasks(
                         match res {
                             Ok(frame) => {
                                 trace!("Sending outbound frame");
+                                let frame = if frame_trace {
+                                    trace_outbound_frame(frame)
+                                } else {
+                                    Some(frame)
+                                };
+                                let Some(frame) = frame else {
+                                    error!("Failed to serialize traced outbound frame");
+                                    outbound_rx.close();
+                                    break;
+                                };
+                                message_counters.count_outbound(&frame);
                                 if let Err(e) = writer.write_frame(frame.into()).await {
                                     error!(error=?e, "Writer error");
                                     outbound_rx.close();
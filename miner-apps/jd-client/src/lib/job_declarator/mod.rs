@@ -4,7 +4,7 @@ use async_channel::{unbounded, Receiver, Sender};
 use stratum_apps::{
     custom_mutex::Mutex,
     key_utils::Secp256k1PublicKey,
-    network_helpers::noise_stream::NoiseTcpStream,
+    network_helpers::{message_counters::MessageCounters, noise_stream::NoiseTcpStream},
     stratum_core::{
         codec_sv2::HandshakeRole,
         framing_sv2,
@@ -67,6 +67,7 @@ impl JobDeclarator {
     /// - Establishes TCP connection.
     /// - Performs SV2 Noise handshake.
     /// - Spawns background IO tasks for reading/writing frames.
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         upstreams: &(SocketAddr, SocketAddr, Secp256k1PublicKey, bool),
         channel_manager_sender: Sender<JobDeclaration<'static>>,
@@ -75,6 +76,9 @@ impl JobDeclarator {
         mode: ConfigJDCMode,
         task_manager: Arc<TaskManager>,
         status_sender: Sender<Status>,
+        max_frame_size: usize,
+        frame_trace: bool,
+        message_counters: Arc<MessageCounters>,
     ) -> JDCResult<Self, error::JobDeclarator> {
         let (_, addr, pubkey, _) = upstreams;
         info!("Connecting to JD Server at {addr}");
@@ -103,6 +107,9 @@ impl JobDeclarator {
             noise_stream_writer,
             outbound_rx,
             inbound_tx,
+            max_frame_size,
+            frame_trace,
+            message_counters,
             notify_shutdown,
             status_sender,
         );
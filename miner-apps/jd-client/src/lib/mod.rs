@@ -1,13 +1,20 @@
-use std::{net::SocketAddr, sync::Arc, thread::JoinHandle, time::Duration};
+use std::{future::Future, net::SocketAddr, sync::Arc, thread::JoinHandle, time::Duration};
 
 use async_channel::{unbounded, Receiver, Sender};
 use bitcoin_core_sv2::CancellationToken;
 use stratum_apps::{
+    config_helpers::AllUpstreamsFailedPolicy,
     key_utils::Secp256k1PublicKey,
-    stratum_core::{bitcoin::consensus::Encodable, parsers_sv2::JobDeclaration},
+    monitoring::{CoinbaseOutputInfo, CoinbaseOutputsInfo, TemplateFees},
+    network_helpers::message_counters::MessageCounters,
+    ready_signal::ReadySignal,
+    stratum_core::{
+        bitcoin::{consensus::Encodable, Address},
+        parsers_sv2::JobDeclaration,
+    },
     task_manager::TaskManager,
     tp_type::TemplateProviderType,
-    utils::types::Sv2Frame,
+    utils::{log_throttle::LogThrottle, status_channel::next_status, types::Sv2Frame},
     SHUTDOWN_BROADCAST_CAPACITY,
 };
 use tokio::sync::{broadcast, mpsc};
@@ -19,6 +26,8 @@ use crate::{
     error::JDCErrorKind,
     jd_mode::{set_jd_mode, JdMode},
     job_declarator::JobDeclarator,
+    monitoring::ManualFailover,
+    solo_payout::SoloPayout,
     status::{State, Status},
     template_receiver::{
         bitcoin_core::{connect_to_bitcoin_core, BitcoinCoreSv2Config},
@@ -36,6 +45,7 @@ mod io_task;
 pub mod jd_mode;
 mod job_declarator;
 pub mod monitoring;
+mod solo_payout;
 mod status;
 mod template_receiver;
 mod upstream;
@@ -46,6 +56,9 @@ pub mod utils;
 pub struct JobDeclaratorClient {
     config: JobDeclaratorClientConfig,
     notify_shutdown: broadcast::Sender<ShutdownMessage>,
+    ready: ReadySignal,
+    message_counters: Arc<MessageCounters>,
+    log_throttle: Arc<LogThrottle>,
 }
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -54,12 +67,23 @@ impl JobDeclaratorClient {
     pub fn new(config: JobDeclaratorClientConfig) -> Self {
         let (notify_shutdown, _) =
             tokio::sync::broadcast::channel::<ShutdownMessage>(SHUTDOWN_BROADCAST_CAPACITY);
+        let log_throttle = Arc::new(LogThrottle::new(config.log_throttle_window()));
         Self {
             config,
             notify_shutdown,
+            ready: ReadySignal::new(),
+            message_counters: Arc::new(MessageCounters::new()),
+            log_throttle,
         }
     }
 
+    /// Returns a future resolving once the JDC is serving, with its downstream listener bound
+    /// after an upstream was connected or, with `on_all_upstreams_failed = "solo"`, after falling
+    /// back to solo mining.
+    pub fn ready(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.ready.wait()
+    }
+
     /// Starts the Job Declarator Client (JDC) main loop.
     pub async fn start(&self) {
         info!(
@@ -67,7 +91,54 @@ impl JobDeclaratorClient {
             self.config.user_identity()
         );
 
-        let miner_coinbase_outputs = vec![self.config.get_txout()];
+        let solo_payout = match self.config.solo_coinbase_descriptor() {
+            Some(descriptor) => match SoloPayout::load(
+                descriptor.clone(),
+                self.config.solo_descriptor_index_file().to_path_buf(),
+            ) {
+                Ok(solo_payout) => Some(Arc::new(solo_payout)),
+                Err(e) => {
+                    error!("Failed to load solo coinbase descriptor index: {e:?}");
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        // The initial outputs only size the coinbase output constraints; with a descriptor, the
+        // actual output is derived when the first template arrives.
+        let miner_coinbase_output = match &solo_payout {
+            Some(solo_payout) => match solo_payout.peek_output() {
+                Ok(output) => output,
+                Err(e) => {
+                    error!("Failed to derive solo coinbase output: {e:?}");
+                    return;
+                }
+            },
+            None => self.config.get_txout(),
+        };
+        // logged to let operators check the payout addresses before any block is found, with a
+        // descriptor the output of the next block. Monitoring reports the outputs in use later
+        // on, such as the ones of the JDS in JD mode.
+        let coinbase_outputs_info = self.config.report_coinbase_outputs().then(|| {
+            let network = self.config.template_provider_type().network();
+            let address = match &solo_payout {
+                Some(_) => network
+                    .and_then(|network| {
+                        Address::from_script(&miner_coinbase_output.script_pubkey, network).ok()
+                    })
+                    .map(|address| address.to_string()),
+                None => self.config.coinbase_reward_script.address(network),
+            };
+            CoinbaseOutputsInfo {
+                outputs: vec![CoinbaseOutputInfo::new(&miner_coinbase_output, address)],
+            }
+        });
+        for output in coinbase_outputs_info.iter().flat_map(|info| &info.outputs) {
+            info!("Coinbase output: {output}");
+        }
+
+        let miner_coinbase_outputs = vec![miner_coinbase_output];
         let mut encoded_outputs = vec![];
 
         miner_coinbase_outputs
@@ -76,7 +147,7 @@ impl JobDeclaratorClient {
 
         let notify_shutdown = self.notify_shutdown.clone();
         let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel::<()>(1);
-        let task_manager = Arc::new(TaskManager::new());
+        let task_manager = Arc::new(TaskManager::new().with_max_tasks(self.config.max_tasks()));
 
         let (status_sender, status_receiver) = async_channel::unbounded::<Status>();
 
@@ -98,7 +169,7 @@ impl JobDeclaratorClient {
 
         debug!("Channels initialized.");
 
-        let channel_manager = ChannelManager::new(
+        let channel_manager = match ChannelManager::new(
             self.config.clone(),
             channel_manager_to_upstream_sender.clone(),
             upstream_to_channel_manager_receiver.clone(),
@@ -109,11 +180,35 @@ impl JobDeclaratorClient {
             channel_manager_to_downstream_sender.clone(),
             downstream_to_channel_manager_receiver,
             encoded_outputs.clone(),
+            solo_payout,
             self.config.supported_extensions().to_vec(),
             self.config.required_extensions().to_vec(),
+            self.message_counters.clone(),
+            self.log_throttle.clone(),
         )
         .await
-        .unwrap();
+        {
+            Ok(channel_manager) => channel_manager,
+            Err(e) => {
+                error!("Failed to initialize Channel Manager: {e:?}");
+                return;
+            }
+        };
+
+        let manual_failover = Arc::new(ManualFailover::new(
+            channel_manager.clone(),
+            status_sender.clone(),
+        ));
+
+        // fees of the latest template, when templates come from Bitcoin Core IPC
+        let template_fees = match self.config.template_provider_type() {
+            TemplateProviderType::BitcoinCoreIpc {
+                network,
+                fee_threshold,
+                ..
+            } => Some(Arc::new(TemplateFees::new(network, *fee_threshold))),
+            TemplateProviderType::Sv2Tp { .. } => None,
+        };
 
         // Start monitoring server if configured
         if let Some(monitoring_addr) = self.config.monitoring_address() {
@@ -122,13 +217,36 @@ impl JobDeclaratorClient {
                 monitoring_addr
             );
 
-            let monitoring_server = stratum_apps::monitoring::MonitoringServer::new(
+            let mut monitoring_server = stratum_apps::monitoring::MonitoringServer::new(
                 monitoring_addr,
                 Some(Arc::new(channel_manager.clone())), // SV2 channels opened with servers
                 Some(Arc::new(channel_manager.clone())), // SV2 channels opened with clients
                 std::time::Duration::from_secs(self.config.monitoring_cache_refresh_secs()),
             )
-            .expect("Failed to initialize monitoring server");
+            .expect("Failed to initialize monitoring server")
+            .with_connections_monitoring(channel_manager.connection_limiter.clone())
+            .expect("Failed to add connections monitoring")
+            .with_tasks_monitoring(task_manager.clone())
+            .expect("Failed to add tasks monitoring")
+            .with_messages_monitoring(self.message_counters.clone())
+            .expect("Failed to add messages monitoring")
+            .with_found_blocks_monitoring(channel_manager.found_blocks.clone())
+            .with_bind_retry(self.config.bind_retry());
+            if let Some(max_staleness) = self.config.monitoring_on_demand_refresh() {
+                monitoring_server = monitoring_server.with_on_demand_refresh(max_staleness);
+            }
+            if let Some(admin_token) = self.config.monitoring_admin_token() {
+                monitoring_server = monitoring_server
+                    .with_failover_control(manual_failover.clone(), admin_token.to_string());
+            }
+            if let Some(template_fees) = &template_fees {
+                monitoring_server =
+                    monitoring_server.with_template_fees_monitoring(template_fees.clone());
+            }
+            if self.config.report_coinbase_outputs() {
+                monitoring_server = monitoring_server
+                    .with_coinbase_outputs_monitoring(Arc::new(channel_manager.clone()));
+            }
 
             // Create shutdown signal that waits for ShutdownAll
             let mut notify_shutdown_monitoring = notify_shutdown.subscribe();
@@ -149,6 +267,24 @@ impl JobDeclaratorClient {
             });
         }
 
+        // Summaries of throttled warnings are logged on a timer, so a burst that stops is still
+        // reported
+        let log_throttle = self.log_throttle.clone();
+        let mut notify_shutdown_log_throttle = notify_shutdown.subscribe();
+        task_manager.spawn(async move {
+            log_throttle
+                .run(async move {
+                    loop {
+                        match notify_shutdown_log_throttle.recv().await {
+                            Ok(ShutdownMessage::ShutdownAll) => break,
+                            Ok(_) => continue,
+                            Err(_) => break,
+                        }
+                    }
+                })
+                .await;
+        });
+
         let channel_manager_clone = channel_manager.clone();
         let mut bitcoin_core_sv2_join_handle: Option<JoinHandle<()>> = None;
 
@@ -165,9 +301,16 @@ impl JobDeclaratorClient {
                     notify_shutdown.clone(),
                     task_manager.clone(),
                     status_sender.clone(),
+                    self.config.max_frame_size(),
+                    self.config.frame_trace(),
+                    self.message_counters.clone(),
                 )
                 .await
-                .unwrap();
+                .unwrap()
+                .with_reconnect(
+                    self.config.template_provider_reconnect_attempts(),
+                    self.config.template_provider_reconnect_backoff(),
+                );
 
                 let notify_shutdown_cl = notify_shutdown.clone();
                 let status_sender_cl = status_sender.clone();
@@ -213,6 +356,9 @@ impl JobDeclaratorClient {
                     incoming_tdp_receiver,
                     outgoing_tdp_sender,
                     cancellation_token: CancellationToken::new(),
+                    template_fees: template_fees
+                        .clone()
+                        .expect("template fees are recorded with Bitcoin Core IPC"),
                 };
 
                 bitcoin_core_sv2_join_handle = Some(
@@ -255,8 +401,8 @@ impl JobDeclaratorClient {
 
         info!("Attempting to initialize upstream...");
 
-        match self
-            .initialize_jd(
+        let connected = tokio::select! {
+            result = self.connect_upstream(
                 &mut upstream_addresses,
                 channel_manager_to_upstream_receiver.clone(),
                 upstream_to_channel_manager_sender.clone(),
@@ -266,17 +412,25 @@ impl JobDeclaratorClient {
                 status_sender.clone(),
                 self.config.mode.clone(),
                 task_manager.clone(),
-            )
-            .await
-        {
-            Ok((upstream, job_declarator, upstream_idx)) => {
+            ) => Some(result),
+            _ = tokio::signal::ctrl_c() => None,
+        };
+
+        let mut shutdown_requested = false;
+        let mut active_upstream = None;
+        match connected {
+            Some(Ok((upstream, job_declarator, upstream_idx))) => {
+                active_upstream = Some(upstream_idx);
                 channel_manager_clone.set_propagate_upstream_target(
                     self.config.upstreams()[upstream_idx].propagate_upstream_target,
                 );
+                channel_manager_clone.set_upstream_work_selection(
+                    self.config.upstreams()[upstream_idx].work_selection,
+                );
+                channel_manager_clone
+                    .set_active_upstream(self.config.upstreams()[upstream_idx].label());
                 upstream
                     .start(
-                        self.config.min_supported_version(),
-                        self.config.max_supported_version(),
                         notify_shutdown.clone(),
                         shutdown_complete_tx.clone(),
                         status_sender.clone(),
@@ -298,41 +452,58 @@ impl JobDeclaratorClient {
                     .set(UpstreamState::NoChannel);
                 _ = channel_manager_clone.allocate_tokens(1).await;
             }
-            Err(e) => {
+            Some(Err(e))
+                if self.config.on_all_upstreams_failed() == AllUpstreamsFailedPolicy::Solo =>
+            {
                 tracing::error!("Failed to initialize upstream: {:?}", e);
                 set_jd_mode(jd_mode::JdMode::SoloMining);
             }
+            Some(Err(e)) => {
+                error!("Failed to initialize upstream, shutting down: {e:?}");
+                let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
+                shutdown_requested = true;
+            }
+            None => {
+                info!("Ctrl+C received while connecting to an upstream — initiating graceful shutdown...");
+                let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
+                shutdown_requested = true;
+            }
         };
 
-        _ = channel_manager_clone
-            .clone()
-            .start_downstream_server(
-                *self.config.authority_public_key(),
-                *self.config.authority_secret_key(),
-                self.config.cert_validity_sec(),
-                *self.config.listening_address(),
-                task_manager.clone(),
-                notify_shutdown.clone(),
-                status_sender.clone(),
-                downstream_to_channel_manager_sender.clone(),
-                channel_manager_to_downstream_sender.clone(),
-                self.config.supported_extensions().to_vec(),
-                self.config.required_extensions().to_vec(),
-            )
-            .await;
+        if !shutdown_requested {
+            let listening = channel_manager_clone
+                .clone()
+                .start_downstream_server(
+                    *self.config.authority_public_key(),
+                    *self.config.authority_secret_key(),
+                    self.config.cert_validity_sec(),
+                    *self.config.listening_address(),
+                    task_manager.clone(),
+                    notify_shutdown.clone(),
+                    status_sender.clone(),
+                    downstream_to_channel_manager_sender.clone(),
+                    channel_manager_to_downstream_sender.clone(),
+                    self.config.supported_extensions().to_vec(),
+                    self.config.required_extensions().to_vec(),
+                )
+                .await;
+            if listening.is_ok() {
+                self.ready.set_ready();
+            }
+        }
 
         info!("Spawning status listener task...");
         let notify_shutdown_clone = notify_shutdown.clone();
 
-        loop {
+        while !shutdown_requested {
             tokio::select! {
                 _ = tokio::signal::ctrl_c() => {
                     info!("Ctrl+C received — initiating graceful shutdown...");
                     let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
                     break;
                 }
-                message = status_receiver.recv() => {
-                    if let Ok(status) = message {
+                message = next_status(&status_receiver) => {
+                    if let Some(status) = message {
                         match status.state {
                             State::DownstreamShutdown{downstream_id,..} => {
                                 warn!("Downstream {downstream_id:?} disconnected — Channel manager.");
@@ -348,8 +519,19 @@ impl JobDeclaratorClient {
                                 let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
                                 break;
                             }
-                            State::UpstreamShutdownFallback(_) | State::JobDeclaratorShutdownFallback(_) => {
+                            State::UpstreamShutdownFallback(e) | State::JobDeclaratorShutdownFallback(e) => {
                                 warn!("Upstream/Job Declarator connection dropped — attempting reconnection...");
+                                channel_manager_clone.record_failover(e.failover_reason(), e.to_string());
+                                let redirect = match e {
+                                    JDCErrorKind::UpstreamReconnect(addr) => {
+                                        redirect_upstream(&mut upstream_addresses, active_upstream, addr)
+                                    }
+                                    _ => None,
+                                };
+                                let redirected = redirect.is_some();
+                                if redirected || matches!(e, JDCErrorKind::ManualFailover) {
+                                    drain_upstream_queues(&channel_manager_to_upstream_receiver, &channel_manager_to_jd_receiver).await;
+                                }
                                 let (tx, mut rx) = mpsc::channel::<()>(1);
                                 let _ = notify_shutdown_clone.send(ShutdownMessage::UpstreamShutdownFallback((encoded_outputs.clone(), tx)));
                                 set_jd_mode(JdMode::SoloMining);
@@ -364,8 +546,8 @@ impl JobDeclaratorClient {
 
                                 info!("Attempting to initialize Jd and upstream...");
 
-                                match self
-                                    .initialize_jd(
+                                let reconnected = tokio::select! {
+                                    result = self.connect_upstream(
                                         &mut upstream_addresses,
                                         channel_manager_to_upstream_receiver.clone(),
                                         upstream_to_channel_manager_sender.clone(),
@@ -375,17 +557,28 @@ impl JobDeclaratorClient {
                                         status_sender.clone(),
                                         self.config.mode.clone(),
                                         task_manager.clone(),
-                                    )
-                                    .await
-                                {
-                                    Ok((upstream, job_declarator, upstream_idx)) => {
+                                    ) => Some(result),
+                                    _ = tokio::signal::ctrl_c() => None,
+                                };
+                                // The redirect only applied to this reconnection
+                                if let Some((idx, addr)) = redirect {
+                                    upstream_addresses[idx].0 = addr;
+                                }
+
+                                match reconnected {
+                                    Some(Ok((upstream, job_declarator, upstream_idx))) => {
+                                        active_upstream = Some(upstream_idx);
                                         channel_manager_clone.set_propagate_upstream_target(
                                             self.config.upstreams()[upstream_idx].propagate_upstream_target,
                                         );
+                                        channel_manager_clone.set_upstream_work_selection(
+                                            self.config.upstreams()[upstream_idx].work_selection,
+                                        );
+                                        channel_manager_clone.set_active_upstream(
+                                            self.config.upstreams()[upstream_idx].label(),
+                                        );
                                         upstream
                                             .start(
-                                                self.config.min_supported_version(),
-                                                self.config.max_supported_version(),
                                                 notify_shutdown.clone(),
                                                 shutdown_complete_tx_fallback.clone(),
                                                 status_sender.clone(),
@@ -406,12 +599,22 @@ impl JobDeclaratorClient {
 
                                         _ = channel_manager_clone.allocate_tokens(1).await;
                                     }
-                                    Err(e) => {
+                                    Some(Err(e)) if self.config.on_all_upstreams_failed() == AllUpstreamsFailedPolicy::Solo => {
                                         tracing::error!("Failed to initialize upstream: {:?}", e);
                                         channel_manager_clone.upstream_state.set(UpstreamState::SoloMining);
                                         set_jd_mode(jd_mode::JdMode::SoloMining);
                                         info!("Fallback to solo mining mode");
                                     }
+                                    Some(Err(e)) => {
+                                        error!("Failed to initialize upstream, shutting down: {e:?}");
+                                        let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
+                                        break;
+                                    }
+                                    None => {
+                                        info!("Ctrl+C received while reconnecting — initiating graceful shutdown...");
+                                        let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
+                                        break;
+                                    }
                                 };
 
                                 _ = channel_manager_clone.clone()
@@ -429,8 +632,13 @@ impl JobDeclaratorClient {
                                         self.config.required_extensions().to_vec(),
                                     )
                                     .await;
+                                manual_failover.complete();
                                 }
                         }
+                    } else {
+                        warn!("Status channel closed — initiating graceful shutdown...");
+                        let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
+                        break;
                     }
                 }
             }
@@ -452,6 +660,53 @@ impl JobDeclaratorClient {
         info!("JD Client shutdown complete.");
     }
 
+    /// Initializes an upstream pool + JD connection pair with [`Self::initialize_jd`], applying
+    /// the configured [`AllUpstreamsFailedPolicy`] once every upstream failed.
+    ///
+    /// With [`AllUpstreamsFailedPolicy::WaitAndRetry`], every upstream is tried again after the
+    /// retry interval, so this only returns once an upstream is connected.
+    #[allow(clippy::too_many_arguments)]
+    async fn connect_upstream(
+        &self,
+        upstreams: &mut [(SocketAddr, SocketAddr, Secp256k1PublicKey, bool)],
+        channel_manager_to_upstream_receiver: Receiver<Sv2Frame>,
+        upstream_to_channel_manager_sender: Sender<Sv2Frame>,
+        channel_manager_to_jd_receiver: Receiver<JobDeclaration<'static>>,
+        jd_to_channel_manager_sender: Sender<JobDeclaration<'static>>,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+        status_sender: Sender<Status>,
+        mode: ConfigJDCMode,
+        task_manager: Arc<TaskManager>,
+    ) -> Result<(Upstream, JobDeclarator, usize), JDCErrorKind> {
+        loop {
+            let result = self
+                .initialize_jd(
+                    upstreams,
+                    channel_manager_to_upstream_receiver.clone(),
+                    upstream_to_channel_manager_sender.clone(),
+                    channel_manager_to_jd_receiver.clone(),
+                    jd_to_channel_manager_sender.clone(),
+                    notify_shutdown.clone(),
+                    status_sender.clone(),
+                    mode.clone(),
+                    task_manager.clone(),
+                )
+                .await;
+            if result.is_ok()
+                || self.config.on_all_upstreams_failed() != AllUpstreamsFailedPolicy::WaitAndRetry
+            {
+                return result;
+            }
+
+            let retry_interval = self.config.upstream_retry_interval();
+            warn!("All upstreams failed, trying them again in {retry_interval:?}");
+            tokio::time::sleep(retry_interval).await;
+            for upstream in upstreams.iter_mut() {
+                upstream.3 = false;
+            }
+        }
+    }
+
     /// Initializes an upstream pool + JD connection pair.
     #[allow(clippy::too_many_arguments)]
     pub async fn initialize_jd(
@@ -490,6 +745,8 @@ impl JobDeclaratorClient {
 
                 match try_initialize_single(
                     upstream_addr,
+                    self.config.upstreams()[i].reconnect_allowlist.clone(),
+                    self.config.upstreams()[i].work_selection,
                     upstream_to_channel_manager_sender.clone(),
                     channel_manager_to_upstream_receiver.clone(),
                     jd_to_channel_manager_sender.clone(),
@@ -499,6 +756,7 @@ impl JobDeclaratorClient {
                     mode.clone(),
                     task_manager.clone(),
                     &self.config,
+                    self.message_counters.clone(),
                 )
                 .await
                 {
@@ -512,10 +770,15 @@ impl JobDeclaratorClient {
                         rx.recv().await;
                         tracing::error!("All sparsed upstream and JDS connection is be terminated");
                         tokio::time::sleep(Duration::from_secs(1)).await;
-                        warn!(
-                            "Attempt {}/{} failed for {:?}: {:?}",
-                            attempt, MAX_RETRIES, upstream_addr, e
-                        );
+                        if let Some(suppressed) = self
+                            .log_throttle
+                            .admit("upstream_attempt_failed", &upstream_addr.0.to_string())
+                        {
+                            warn!(
+                                "Attempt {}/{} failed for {:?}: {:?}{suppressed}",
+                                attempt, MAX_RETRIES, upstream_addr, e
+                            );
+                        }
                         if attempt == MAX_RETRIES {
                             warn!(
                                 "Max retries reached for {:?}, moving to next upstream",
@@ -533,11 +796,57 @@ impl JobDeclaratorClient {
     }
 }
 
+// Points the pool address of the active upstream to the endpoint the pool redirected the JDC to
+// with `Reconnect`, so the failover connects to it first. The JDS of the upstream is kept.
+// Returns the index and the configured pool address of the redirected upstream, to restore once
+// the failover is done.
+fn redirect_upstream(
+    upstream_addresses: &mut [(SocketAddr, SocketAddr, Secp256k1PublicKey, bool)],
+    active_upstream: Option<usize>,
+    addr: SocketAddr,
+) -> Option<(usize, SocketAddr)> {
+    let idx = active_upstream?;
+    let upstream = upstream_addresses.get_mut(idx)?;
+    info!("Redirecting upstream from {} to {addr}", upstream.0);
+    let configured_addr = std::mem::replace(&mut upstream.0, addr);
+    upstream.3 = false;
+    Some((idx, configured_addr))
+}
+
+/// Longest wait for the frames queued to the upstream and the JDS before a manual failover or a
+/// redirect leaves them.
+const MANUAL_FAILOVER_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Waits until the upstream and job declarator tasks took every frame queued for them, so the
+// shares and declarations already sent by the channel manager reach the upstream being left.
+async fn drain_upstream_queues(
+    channel_manager_to_upstream_receiver: &Receiver<Sv2Frame>,
+    channel_manager_to_jd_receiver: &Receiver<JobDeclaration<'static>>,
+) {
+    let drained = tokio::time::timeout(MANUAL_FAILOVER_DRAIN_TIMEOUT, async {
+        while !channel_manager_to_upstream_receiver.is_empty()
+            || !channel_manager_to_jd_receiver.is_empty()
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    match drained {
+        Ok(()) => info!("Upstream queues drained, failing over"),
+        Err(_) => warn!(
+            "{} frames still queued for the upstream and the JDS after {MANUAL_FAILOVER_DRAIN_TIMEOUT:?}, failing over anyway",
+            channel_manager_to_upstream_receiver.len() + channel_manager_to_jd_receiver.len()
+        ),
+    }
+}
+
 // Attempts to initialize a single upstream (pool + JDS pair).
 #[allow(clippy::too_many_arguments)]
 #[cfg_attr(not(test), hotpath::measure)]
 async fn try_initialize_single(
     upstream_addr: &(SocketAddr, SocketAddr, Secp256k1PublicKey, bool),
+    reconnect_allowlist: Vec<String>,
+    work_selection: bool,
     upstream_to_channel_manager_sender: Sender<Sv2Frame>,
     channel_manager_to_upstream_receiver: Receiver<Sv2Frame>,
     jd_to_channel_manager_sender: Sender<JobDeclaration<'static>>,
@@ -547,16 +856,24 @@ async fn try_initialize_single(
     mode: ConfigJDCMode,
     task_manager: Arc<TaskManager>,
     config: &JobDeclaratorClientConfig,
+    message_counters: Arc<MessageCounters>,
 ) -> Result<(Upstream, JobDeclarator), JDCErrorKind> {
     info!("Upstream connection in-progress at initialize single");
     let upstream = Upstream::new(
         upstream_addr,
+        reconnect_allowlist,
+        work_selection,
         upstream_to_channel_manager_sender,
         channel_manager_to_upstream_receiver,
         notify_shutdown.clone(),
         task_manager.clone(),
         status_sender.clone(),
         config.required_extensions().to_vec(),
+        config.min_supported_version(),
+        config.max_supported_version(),
+        config.max_frame_size(),
+        config.frame_trace(),
+        message_counters.clone(),
     )
     .await
     .map_err(|error| error.kind)?;
@@ -571,6 +888,9 @@ async fn try_initialize_single(
         mode,
         task_manager.clone(),
         status_sender.clone(),
+        config.max_frame_size(),
+        config.frame_trace(),
+        message_counters,
     )
     .await
     .map_err(|error| error.kind)?;
@@ -1,17 +1,35 @@
 //! Monitoring integration for JD Client
 //!
-//! This module implements the ServerMonitoring and ClientsMonitoring traits on `ChannelManager`.
+//! This module implements the ServerMonitoring, ClientsMonitoring and CoinbaseOutputsMonitoring
+//! traits on `ChannelManager`.
 //! JDC has:
 //! - Server channels (upstream to pool)
 //! - Client channels (downstream miners connecting to JDC)
+//!
+//! It also provides [`ManualFailover`], the `POST /api/v1/failover` admin action.
 
+use async_channel::Sender;
 use hex;
-use stratum_apps::monitoring::{
-    client::{ClientInfo, ClientsMonitoring, ExtendedChannelInfo, StandardChannelInfo},
-    server::{ServerExtendedChannelInfo, ServerInfo, ServerMonitoring},
+use std::sync::atomic::{AtomicBool, Ordering};
+use stratum_apps::{
+    monitoring::{
+        client::{ClientInfo, ClientsMonitoring, ExtendedChannelInfo, StandardChannelInfo},
+        coinbase::{CoinbaseOutputInfo, CoinbaseOutputsInfo, CoinbaseOutputsMonitoring},
+        server::{ServerExtendedChannelInfo, ServerInfo, ServerMonitoring},
+        FailoverControl,
+    },
+    stratum_core::{
+        bitcoin::{Address, TxOut},
+        channels_sv2::outputs::deserialize_outputs,
+    },
 };
 
-use crate::{channel_manager::ChannelManager, downstream::Downstream};
+use crate::{
+    channel_manager::ChannelManager,
+    downstream::Downstream,
+    error::JDCErrorKind,
+    status::{State, Status},
+};
 
 impl ServerMonitoring for ChannelManager {
     fn get_server(&self) -> ServerInfo {
@@ -36,10 +54,13 @@ impl ServerMonitoring for ChannelManager {
 
                     extended_channels.push(ServerExtendedChannelInfo {
                         channel_id,
+                        // Group channels with the upstream are not tracked
+                        group_channel_id: None,
                         user_identity: user_identity.clone(),
                         nominal_hashrate: Some(upstream_channel.get_nominal_hashrate()),
                         target_hex: hex::encode(target.to_be_bytes()),
                         extranonce_prefix_hex: hex::encode(extranonce_prefix),
+                        current_job_id: upstream_channel.get_active_job().map(|job| job.0.job_id),
                         full_extranonce_size: upstream_channel.get_full_extranonce_size(),
                         rollable_extranonce_size: upstream_channel.get_rollable_extranonce_size(),
                         version_rolling: upstream_channel.is_version_rolling(),
@@ -47,17 +68,27 @@ impl ServerMonitoring for ChannelManager {
                         share_work_sum: share_accounting.get_share_work_sum(),
                         shares_submitted,
                         best_diff: share_accounting.get_best_diff(),
+                        // Downstream prefixes are not derived from the upstream channel
+                        extranonce_prefix_usage: None,
                     });
                 }
 
                 ServerInfo {
+                    upstream: self.active_upstream(),
                     extended_channels,
                     standard_channels,
+                    last_failover: self.last_failover(),
+                    pending_declared_jobs: Some(d.pending_declared_jobs()),
+                    stale_job_events: None,
                 }
             })
             .unwrap_or_else(|_| ServerInfo {
+                upstream: self.active_upstream(),
                 extended_channels: Vec::new(),
                 standard_channels: Vec::new(),
+                last_failover: self.last_failover(),
+                pending_declared_jobs: None,
+                stale_job_events: None,
             })
     }
 }
@@ -70,6 +101,12 @@ fn downstream_to_client_info(client: &Downstream) -> Option<ClientInfo> {
         .safe_lock(|dd| {
             let mut extended_channels = Vec::new();
             let mut standard_channels = Vec::new();
+            let group_channel_id = |channel_id| {
+                dd.group_channel
+                    .get_channel_ids()
+                    .contains(&channel_id)
+                    .then(|| dd.group_channel.get_group_channel_id())
+            };
 
             for (_channel_id, extended_channel) in dd.extended_channels.iter() {
                 let channel_id = extended_channel.get_channel_id();
@@ -80,11 +117,15 @@ fn downstream_to_client_info(client: &Downstream) -> Option<ClientInfo> {
 
                 extended_channels.push(ExtendedChannelInfo {
                     channel_id,
+                    group_channel_id: group_channel_id(channel_id),
                     user_identity: user_identity.clone(),
                     nominal_hashrate: extended_channel.get_nominal_hashrate(),
                     target_hex: hex::encode(target.to_be_bytes()),
                     requested_max_target_hex: hex::encode(requested_max_target.to_be_bytes()),
                     extranonce_prefix_hex: hex::encode(extended_channel.get_extranonce_prefix()),
+                    current_job_id: extended_channel
+                        .get_active_job()
+                        .map(|job| job.get_job_id()),
                     full_extranonce_size: extended_channel.get_full_extranonce_size(),
                     rollable_extranonce_size: extended_channel.get_rollable_extranonce_size(),
                     expected_shares_per_minute: extended_channel.get_shares_per_minute(),
@@ -107,11 +148,15 @@ fn downstream_to_client_info(client: &Downstream) -> Option<ClientInfo> {
 
                 standard_channels.push(StandardChannelInfo {
                     channel_id,
+                    group_channel_id: group_channel_id(channel_id),
                     user_identity: user_identity.clone(),
                     nominal_hashrate: standard_channel.get_nominal_hashrate(),
                     target_hex: hex::encode(target.to_be_bytes()),
                     requested_max_target_hex: hex::encode(requested_max_target.to_be_bytes()),
                     extranonce_prefix_hex: hex::encode(standard_channel.get_extranonce_prefix()),
+                    current_job_id: standard_channel
+                        .get_active_job()
+                        .map(|job| job.get_job_id()),
                     expected_shares_per_minute: standard_channel.get_shares_per_minute(),
                     shares_accepted: share_accounting.get_shares_accepted(),
                     share_work_sum: share_accounting.get_share_work_sum(),
@@ -127,6 +172,9 @@ fn downstream_to_client_info(client: &Downstream) -> Option<ClientInfo> {
                 client_id: client.downstream_id,
                 extended_channels,
                 standard_channels,
+                send_queue_depth: client.send_queue_depth(),
+                slow_consumer: client.slow_consumer.is_slow_consumer(),
+                lagged_total: dd.lagged_total,
             }
         })
         .ok()
@@ -157,3 +205,77 @@ impl ClientsMonitoring for ChannelManager {
             .unwrap_or(None)
     }
 }
+
+impl CoinbaseOutputsMonitoring for ChannelManager {
+    // The outputs of the coinbase currently built: the ones of the mining job token in JD mode,
+    // else the solo mining one, derived for the next block when a descriptor is configured.
+    fn get_coinbase_outputs(&self) -> CoinbaseOutputsInfo {
+        let coinbase_outputs = self
+            .channel_manager_data
+            .safe_lock(|data| data.coinbase_outputs.clone())
+            .unwrap_or_default();
+        let outputs = deserialize_outputs(coinbase_outputs)
+            .unwrap_or_default()
+            .iter()
+            .map(|output| CoinbaseOutputInfo::new(output, coinbase_output_address(self, output)))
+            .collect();
+        CoinbaseOutputsInfo { outputs }
+    }
+}
+
+// The address paid by `output`, also known without the network when it is the configured output
+// given as an addr() descriptor.
+fn coinbase_output_address(channel_manager: &ChannelManager, output: &TxOut) -> Option<String> {
+    if output.script_pubkey == channel_manager.coinbase_reward_script.script_pubkey() {
+        return channel_manager
+            .coinbase_reward_script
+            .address(channel_manager.network);
+    }
+    Address::from_script(&output.script_pubkey, channel_manager.network?)
+        .ok()
+        .map(|address| address.to_string())
+}
+
+/// Fails over to the next upstream on demand, through `POST /api/v1/failover`.
+///
+/// The request is reported to the status loop as an [`State::UpstreamShutdownFallback`], which
+/// drains the frames queued for the upstream and the JDS before falling back like for any
+/// upstream failure.
+pub struct ManualFailover {
+    channel_manager: ChannelManager,
+    status_sender: Sender<Status>,
+    // Set from the request until the status loop completed the failover
+    pending: AtomicBool,
+}
+
+impl ManualFailover {
+    pub fn new(channel_manager: ChannelManager, status_sender: Sender<Status>) -> Self {
+        Self {
+            channel_manager,
+            status_sender,
+            pending: AtomicBool::new(false),
+        }
+    }
+
+    /// Accepts new failover requests again. Called once the status loop handled a fallback.
+    pub fn complete(&self) {
+        self.pending.store(false, Ordering::Relaxed);
+    }
+}
+
+impl FailoverControl for ManualFailover {
+    fn request_failover(&self) -> Result<(), String> {
+        if self.channel_manager.active_upstream().is_none() {
+            return Err("No upstream connected".to_string());
+        }
+        if self.pending.swap(true, Ordering::Relaxed) {
+            return Err("A failover is already in progress".to_string());
+        }
+
+        let state = State::UpstreamShutdownFallback(JDCErrorKind::ManualFailover);
+        self.status_sender.try_send(Status { state }).map_err(|e| {
+            self.pending.store(false, Ordering::Relaxed);
+            format!("Failed to request the failover: {e}")
+        })
+    }
+}
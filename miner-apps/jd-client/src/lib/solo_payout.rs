@@ -0,0 +1,171 @@
+//! Solo mining coinbase outputs derived from a ranged descriptor.
+//!
+//! When a [`CoinbaseRewardDescriptor`] is configured, solo mining pays to the current index of the
+//! descriptor until a block is found there, then moves on to the next one, so found blocks never
+//! reuse an address. The next index is persisted to a small file as soon as a block is found, so
+//! an index is not reused across restarts either.
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use stratum_apps::{
+    config_helpers::CoinbaseRewardDescriptor,
+    custom_mutex::Mutex,
+    stratum_core::bitcoin::{Amount, TxOut},
+};
+
+use crate::error::JDCErrorKind;
+
+/// Derives the solo mining coinbase output of each block from a ranged descriptor.
+#[derive(Debug)]
+pub struct SoloPayout {
+    descriptor: CoinbaseRewardDescriptor,
+    index_file: PathBuf,
+    next_index: Mutex<u32>,
+}
+
+impl SoloPayout {
+    /// Creates a [`SoloPayout`] resuming from the index stored in `index_file`, or from index `0`
+    /// if the file does not exist yet.
+    pub fn load(
+        descriptor: CoinbaseRewardDescriptor,
+        index_file: PathBuf,
+    ) -> Result<Self, JDCErrorKind> {
+        let next_index = match fs::read_to_string(&index_file) {
+            Ok(contents) => contents.trim().parse()?,
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            descriptor,
+            index_file,
+            next_index: Mutex::new(next_index),
+        })
+    }
+
+    /// Returns the output paying to the current index, without consuming it.
+    pub fn peek_output(&self) -> Result<TxOut, JDCErrorKind> {
+        let index = self.next_index();
+        self.output_at(index)
+    }
+
+    /// Consumes the current index, once a block paying to it was found, and returns its output.
+    ///
+    /// The following index is persisted before returning, so the output is never paid to again,
+    /// even if the JDC restarts right after.
+    pub fn next_output(&self) -> Result<TxOut, JDCErrorKind> {
+        self.next_index.super_safe_lock(|next_index| {
+            let output = self.output_at(*next_index)?;
+            let following = *next_index + 1;
+            persist_index(&self.index_file, following)?;
+            *next_index = following;
+            Ok(output)
+        })
+    }
+
+    /// Returns the next index that will be derived.
+    pub fn next_index(&self) -> u32 {
+        self.next_index.super_safe_lock(|next_index| *next_index)
+    }
+
+    fn output_at(&self, index: u32) -> Result<TxOut, JDCErrorKind> {
+        let script_pubkey = self
+            .descriptor
+            .script_pubkey_at(index)
+            .map_err(JDCErrorKind::SoloPayoutDerivation)?;
+        Ok(TxOut {
+            value: Amount::from_sat(0),
+            script_pubkey,
+        })
+    }
+}
+
+// Writes the index to a temporary file first, so a crash mid-write cannot leave a truncated index
+// behind.
+fn persist_index(index_file: &Path, index: u32) -> Result<(), JDCErrorKind> {
+    let tmp_file = index_file.with_extension("tmp");
+    fs::write(&tmp_file, index.to_string())?;
+    fs::rename(&tmp_file, index_file)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIPTOR: &str = "wpkh(xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8/0/*)";
+
+    fn index_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("jdc-{name}-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn consecutive_solo_jobs_pay_distinct_derived_scripts() {
+        let descriptor = CoinbaseRewardDescriptor::from_descriptor(DESCRIPTOR).unwrap();
+        let index_file = index_file("solo-payout-distinct");
+        let payout = SoloPayout::load(descriptor.clone(), index_file.clone()).unwrap();
+
+        assert_eq!(
+            payout.peek_output().unwrap().script_pubkey,
+            descriptor.script_pubkey_at(0).unwrap()
+        );
+        let first = payout.next_output().unwrap();
+        let second = payout.next_output().unwrap();
+        assert_eq!(first.script_pubkey, descriptor.script_pubkey_at(0).unwrap());
+        assert_eq!(
+            second.script_pubkey,
+            descriptor.script_pubkey_at(1).unwrap()
+        );
+        assert_ne!(first.script_pubkey, second.script_pubkey);
+
+        fs::remove_file(index_file).unwrap();
+    }
+
+    #[test]
+    fn templates_keep_the_index_until_a_block_is_found() {
+        let descriptor = CoinbaseRewardDescriptor::from_descriptor(DESCRIPTOR).unwrap();
+        let index_file = index_file("solo-payout-peek");
+        let payout = SoloPayout::load(descriptor.clone(), index_file.clone()).unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(
+                payout.peek_output().unwrap().script_pubkey,
+                descriptor.script_pubkey_at(0).unwrap()
+            );
+        }
+        assert_eq!(payout.next_index(), 0);
+        assert!(!index_file.exists());
+
+        payout.next_output().unwrap();
+        assert_eq!(
+            payout.peek_output().unwrap().script_pubkey,
+            descriptor.script_pubkey_at(1).unwrap()
+        );
+
+        fs::remove_file(index_file).unwrap();
+    }
+
+    #[test]
+    fn next_index_survives_a_restart() {
+        let descriptor = CoinbaseRewardDescriptor::from_descriptor(DESCRIPTOR).unwrap();
+        let index_file = index_file("solo-payout-restart");
+
+        let payout = SoloPayout::load(descriptor.clone(), index_file.clone()).unwrap();
+        payout.next_output().unwrap();
+        payout.next_output().unwrap();
+        drop(payout);
+
+        let payout = SoloPayout::load(descriptor.clone(), index_file.clone()).unwrap();
+        assert_eq!(payout.next_index(), 2);
+        assert_eq!(
+            payout.next_output().unwrap().script_pubkey,
+            descriptor.script_pubkey_at(2).unwrap()
+        );
+
+        fs::remove_file(index_file).unwrap();
+    }
+}
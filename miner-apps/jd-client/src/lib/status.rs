@@ -116,75 +116,158 @@ pub struct Status {
     pub state: State,
 }
 
-#[cfg_attr(not(test), hotpath::measure)]
-async fn send_status<O>(sender: &StatusSender, error: JDCError<O>) -> bool {
-    use Action::*;
-
-    match error.action {
-        Log => {
-            warn!("Log-only error from {:?}: {:?}", sender, error.kind);
-            false
-        }
+/// Maps an error reported by `sender` to the [`State`] sent to the main loop, if any, and to
+/// whether the task of `sender` stops.
+///
+/// | Action       | Reported by        | State                           | Main loop   | Stops |
+/// |--------------|--------------------|---------------------------------|-------------|-------|
+/// | `Log`        | any                | none                            | -           | no    |
+/// | `Disconnect` | `Downstream`       | `DownstreamShutdown`            | disconnects | yes   |
+/// | `Disconnect` | others             | `DownstreamShutdown`            | disconnects | no    |
+/// | `Fallback`   | `Upstream`         | `UpstreamShutdownFallback`      | fails over  | yes   |
+/// | `Fallback`   | `JobDeclarator`    | `JobDeclaratorShutdownFallback` | fails over  | yes   |
+/// | `Fallback`   | others             | `UpstreamShutdownFallback`      | fails over  | no    |
+/// | `Shutdown`   | `TemplateReceiver` | `TemplateReceiverShutdown`      | shuts down  | yes   |
+/// | `Shutdown`   | others             | `ChannelManagerShutdown`        | shuts down  | yes   |
+///
+/// A task only stops on the errors it cannot outlive. The others keep running until the main
+/// loop tells them to stop, as part of the fallback or of the shutdown.
+///
+/// The action is picked where the error is raised, after what failed:
+///
+/// - `Log`: a benign race or a job that cannot be built for now (`Timeout`, `TemplateNotFound`,
+///   `TokenNotFound`, `LastDeclareJobNotFound`, `FailedToCreateCustomJob`, `TxDataError`,
+///   `UnexpectedMessage`).
+/// - `Disconnect`: a single downstream misbehaved or cannot be served (`SetupConnectionError` of
+///   the downstream, `DownstreamNotFound`, `LastNewPrevhashNotFound`, a group channel that cannot
+///   be bootstrapped for it, or a failed send to it).
+/// - `Fallback`: the pool or the JDS failed or refused the client (`SetupConnectionError`,
+///   `OpenMiningChannelError`, `ExtranonceSizeTooLarge`, `CloseChannel`, `CustomJobError`,
+///   `DeclareMiningJobError`, `DeclaredJobHasBadCoinbaseOutputs`, `UpstreamReconnect`, or a failed
+///   send to them).
+/// - `Shutdown`: the client itself cannot go on (`CouldNotInitiateSystem`,
+///   `ChannelManagerHasBadCoinbaseOutputs`, `BitcoinCoreSv2CancellationTokenActivated`, an invalid
+///   configuration, a template provider refusing the connection, or a closed channel between its
+///   own tasks).
+fn error_to_state(
+    sender: &StatusSender,
+    action: Action,
+    kind: JDCErrorKind,
+) -> (Option<State>, bool) {
+    use StatusSender::*;
 
-        Disconnect(downstream_id) => {
+    match action {
+        Action::Log => (None, false),
+        Action::Disconnect(downstream_id) => {
             let state = State::DownstreamShutdown {
                 downstream_id,
-                reason: error.kind,
+                reason: kind,
             };
-
-            if let Err(e) = sender.send(Status { state }).await {
-                tracing::error!(
-                    "Failed to send downstream shutdown status from {:?}: {:?}",
-                    sender,
-                    e
-                );
-                std::process::abort();
-            }
-            matches!(sender, StatusSender::Downstream { .. })
+            let stop = match sender {
+                Downstream { .. } => true,
+                TemplateReceiver(_) | ChannelManager(_) | Upstream(_) | JobDeclarator(_) => false,
+            };
+            (Some(state), stop)
         }
-
-        Fallback => {
-            let state = State::UpstreamShutdownFallback(error.kind);
-
-            if let Err(e) = sender.send(Status { state }).await {
-                tracing::error!("Failed to send fallback status from {:?}: {:?}", sender, e);
-                std::process::abort();
+        Action::Fallback => match sender {
+            Upstream(_) => (Some(State::UpstreamShutdownFallback(kind)), true),
+            JobDeclarator(_) => (Some(State::JobDeclaratorShutdownFallback(kind)), true),
+            Downstream { .. } | TemplateReceiver(_) | ChannelManager(_) => {
+                (Some(State::UpstreamShutdownFallback(kind)), false)
             }
-            matches!(sender, StatusSender::Upstream { .. })
-        }
-
-        Shutdown => {
+        },
+        Action::Shutdown => {
             let state = match sender {
-                StatusSender::ChannelManager(_) => {
-                    warn!(
-                        "Channel Manager shutdown requested due to error: {:?}",
-                        error.kind
-                    );
-                    State::ChannelManagerShutdown(error.kind)
-                }
-                StatusSender::TemplateReceiver(_) => {
-                    warn!(
-                        "Template Receiver shutdown requested due to error: {:?}",
-                        error.kind
-                    );
-                    State::TemplateReceiverShutdown(error.kind)
-                }
-                _ => {
-                    tracing::error!("Shutdown action received from invalid sender: {:?}", sender);
-                    State::ChannelManagerShutdown(error.kind)
+                TemplateReceiver(_) => State::TemplateReceiverShutdown(kind),
+                Downstream { .. } | ChannelManager(_) | Upstream(_) | JobDeclarator(_) => {
+                    State::ChannelManagerShutdown(kind)
                 }
             };
+            (Some(state), true)
+        }
+    }
+}
 
-            if let Err(e) = sender.send(Status { state }).await {
-                tracing::error!("Failed to send shutdown status from {:?}: {:?}", sender, e);
-                std::process::abort();
-            }
-            true
+#[cfg_attr(not(test), hotpath::measure)]
+async fn send_status<O>(sender: &StatusSender, error: JDCError<O>) -> bool {
+    match error.action {
+        Action::Log => warn!("Log-only error from {:?}: {:?}", sender, error.kind),
+        Action::Shutdown => warn!(
+            "Shutdown requested by {:?} due to error: {:?}",
+            sender, error.kind
+        ),
+        Action::Disconnect(_) | Action::Fallback => {}
+    }
+
+    let (state, stop) = error_to_state(sender, error.action, error.kind);
+    if let Some(state) = state {
+        if let Err(e) = sender.send(Status { state }).await {
+            tracing::error!("Failed to send status from {:?}: {:?}", sender, e);
+            std::process::abort();
         }
     }
+    stop
 }
 
 #[cfg_attr(not(test), hotpath::measure)]
 pub async fn handle_error<O>(sender: &StatusSender, e: JDCError<O>) -> bool {
     send_status(sender, e).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error;
+
+    #[tokio::test]
+    async fn test_handle_error_reports_state_to_main_loop() {
+        let (tx, rx) = async_channel::unbounded();
+
+        // a job declarator that drops fails over, and its task stops
+        let job_declarator = StatusSender::JobDeclarator(tx.clone());
+        let error = JDCError::<error::JobDeclarator>::fallback(JDCErrorKind::SetupConnectionError);
+        assert!(handle_error(&job_declarator, error).await);
+        assert!(matches!(
+            rx.try_recv().unwrap().state,
+            State::JobDeclaratorShutdownFallback(JDCErrorKind::SetupConnectionError)
+        ));
+
+        // a channel the pool refuses fails over, the channel manager keeps running
+        let channel_manager = StatusSender::ChannelManager(tx.clone());
+        let error =
+            JDCError::<error::ChannelManager>::fallback(JDCErrorKind::OpenMiningChannelError);
+        assert!(!handle_error(&channel_manager, error).await);
+        assert!(matches!(
+            rx.try_recv().unwrap().state,
+            State::UpstreamShutdownFallback(JDCErrorKind::OpenMiningChannelError)
+        ));
+
+        // a downstream the channel manager fails to serve is disconnected, the channel manager
+        // keeps running
+        let error =
+            JDCError::<error::ChannelManager>::disconnect(JDCErrorKind::DownstreamNotFound(5), 5);
+        assert!(!handle_error(&channel_manager, error).await);
+        assert!(matches!(
+            rx.try_recv().unwrap().state,
+            State::DownstreamShutdown {
+                downstream_id: 5,
+                reason: JDCErrorKind::DownstreamNotFound(5),
+            }
+        ));
+
+        // log-only errors do not reach the main loop
+        let error = JDCError::<error::ChannelManager>::log(JDCErrorKind::Timeout);
+        assert!(!handle_error(&channel_manager, error).await);
+        assert!(rx.is_empty());
+
+        // a template provider refusing the connection shuts the client down
+        let template_receiver = StatusSender::TemplateReceiver(tx);
+        let error =
+            JDCError::<error::TemplateProvider>::shutdown(JDCErrorKind::SetupConnectionError);
+        assert!(handle_error(&template_receiver, error).await);
+        assert!(matches!(
+            rx.try_recv().unwrap().state,
+            State::TemplateReceiverShutdown(JDCErrorKind::SetupConnectionError)
+        ));
+    }
+}
@@ -1,15 +1,19 @@
-use stratum_apps::stratum_core::{
-    common_messages_sv2::{
-        ChannelEndpointChanged, Reconnect, SetupConnectionError, SetupConnectionSuccess,
+use stratum_apps::{
+    network_helpers::reconnect::resolve_reconnect,
+    stratum_core::{
+        common_messages_sv2::{
+            ChannelEndpointChanged, Reconnect, SetupConnectionError, SetupConnectionSuccess,
+        },
+        handlers_sv2::HandleCommonMessagesFromServerAsync,
+        parsers_sv2::Tlv,
     },
-    handlers_sv2::HandleCommonMessagesFromServerAsync,
-    parsers_sv2::Tlv,
 };
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
 use crate::{
     error::{self, JDCError, JDCErrorKind},
     upstream::Upstream,
+    utils::check_setup_connection_success_flags,
 };
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -31,6 +35,22 @@ impl HandleCommonMessagesFromServerAsync for Upstream {
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
 
+        if !self.supports_version(msg.used_version) {
+            error!(
+                "Upstream selected unsupported protocol version {}",
+                msg.used_version
+            );
+            return Err(JDCError::fallback(
+                JDCErrorKind::UnsupportedProtocolVersion(msg.used_version),
+            ));
+        }
+        check_setup_connection_success_flags(self.setup_connection_flags(), msg.flags).map_err(
+            |e| {
+                error!("{e}");
+                JDCError::fallback(e)
+            },
+        )?;
+
         Ok(())
     }
 
@@ -51,7 +71,19 @@ impl HandleCommonMessagesFromServerAsync for Upstream {
         _tlv_fields: Option<&[Tlv]>,
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
-        Ok(())
+        match resolve_reconnect(&msg, self.address, &self.reconnect_allowlist).await {
+            Ok(addr) => {
+                info!(
+                    "Upstream {} redirects to {addr}, reconnecting",
+                    self.address
+                );
+                Err(JDCError::fallback(JDCErrorKind::UpstreamReconnect(addr)))
+            }
+            Err(e) => {
+                warn!("Ignoring Reconnect from upstream {}: {e}", self.address);
+                Ok(())
+            }
+        }
     }
 
     async fn handle_setup_connection_error(
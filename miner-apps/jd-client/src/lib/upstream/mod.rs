@@ -15,7 +15,7 @@ use async_channel::{unbounded, Receiver, Sender};
 use stratum_apps::{
     custom_mutex::Mutex,
     key_utils::Secp256k1PublicKey,
-    network_helpers::noise_stream::NoiseTcpStream,
+    network_helpers::{message_counters::MessageCounters, noise_stream::NoiseTcpStream},
     stratum_core::{
         binary_sv2::Seq064K, codec_sv2::HandshakeRole, extensions_sv2::RequestExtensions,
         framing_sv2, handlers_sv2::HandleCommonMessagesFromServerAsync, noise_sv2::Initiator,
@@ -37,7 +37,7 @@ use crate::{
     error::{self, JDCError, JDCErrorKind, JDCResult},
     io_task::spawn_io_tasks,
     status::{handle_error, Status, StatusSender},
-    utils::{get_setup_connection_message, ShutdownMessage},
+    utils::{get_setup_connection_message, mining_setup_connection_flags, ShutdownMessage},
 };
 
 mod message_handler;
@@ -69,8 +69,16 @@ pub struct Upstream {
     upstream_channel: UpstreamChannel,
     /// Protocol extensions that the JDC requires
     required_extensions: Vec<u16>,
+    /// Lowest protocol version requested in `SetupConnection`
+    min_supported_version: u16,
+    /// Highest protocol version requested in `SetupConnection`
+    max_supported_version: u16,
     /// Upstream address
     address: SocketAddr,
+    /// Endpoints the upstream may redirect the JDC to with `Reconnect`
+    reconnect_allowlist: Vec<String>,
+    /// Whether work selection is requested in `SetupConnection`
+    work_selection: bool,
 }
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -79,14 +87,22 @@ impl Upstream {
     ///
     /// - Establishes TCP + Noise connection
     /// - Spawns IO tasks to handle inbound/outbound traffic
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         upstreams: &(SocketAddr, SocketAddr, Secp256k1PublicKey, bool),
+        reconnect_allowlist: Vec<String>,
+        work_selection: bool,
         channel_manager_sender: Sender<Sv2Frame>,
         channel_manager_receiver: Receiver<Sv2Frame>,
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
         task_manager: Arc<TaskManager>,
         status_sender: Sender<Status>,
         required_extensions: Vec<u16>,
+        min_supported_version: u16,
+        max_supported_version: u16,
+        max_frame_size: usize,
+        frame_trace: bool,
+        message_counters: Arc<MessageCounters>,
     ) -> JDCResult<Self, error::Upstream> {
         let (addr, _, pubkey, _) = upstreams;
         let stream = tokio::time::timeout(
@@ -115,6 +131,9 @@ impl Upstream {
             noise_stream_writer,
             outbound_rx,
             inbound_tx,
+            max_frame_size,
+            frame_trace,
+            message_counters,
             notify_shutdown,
             status_sender,
         );
@@ -131,22 +150,26 @@ impl Upstream {
             upstream_data,
             upstream_channel,
             required_extensions,
+            min_supported_version,
+            max_supported_version,
             address: *addr,
+            reconnect_allowlist,
+            work_selection,
         })
     }
 
     /// Perform `SetupConnection` handshake with upstream.
     ///
     /// Sends [`SetupConnection`] and awaits response.
-    pub async fn setup_connection(
-        &mut self,
-        min_version: u16,
-        max_version: u16,
-    ) -> JDCResult<(), error::Upstream> {
+    pub async fn setup_connection(&mut self) -> JDCResult<(), error::Upstream> {
         info!("Upstream: initiating SV2 handshake...");
-        let setup_connection =
-            get_setup_connection_message(min_version, max_version, &self.address)
-                .map_err(JDCError::shutdown)?;
+        let setup_connection = get_setup_connection_message(
+            self.min_supported_version,
+            self.max_supported_version,
+            &self.address,
+            self.work_selection,
+        )
+        .map_err(JDCError::shutdown)?;
         debug!(?setup_connection, "Prepared `SetupConnection` message");
         let sv2_frame: Sv2Frame = Message::Common(setup_connection.into())
             .try_into()
@@ -191,6 +214,16 @@ impl Upstream {
         Ok(())
     }
 
+    /// Returns the flags of the `SetupConnection` sent to the upstream.
+    pub fn setup_connection_flags(&self) -> u32 {
+        mining_setup_connection_flags(self.work_selection)
+    }
+
+    /// Returns whether `version` lies within the protocol version range requested from upstream.
+    pub fn supports_version(&self, version: u16) -> bool {
+        (self.min_supported_version..=self.max_supported_version).contains(&version)
+    }
+
     /// Send `RequestExtensions` message to upstream.
     /// The supported extensions are stored for potential retry if the server requires additional
     /// extensions.
@@ -243,8 +276,6 @@ impl Upstream {
     /// This function spawns an async task and returns immediately.
     pub async fn start(
         mut self,
-        min_version: u16,
-        max_version: u16,
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
         shutdown_complete_tx: mpsc::Sender<()>,
         status_sender: Sender<Status>,
@@ -253,8 +284,9 @@ impl Upstream {
         let status_sender = StatusSender::Upstream(status_sender);
         let mut shutdown_rx = notify_shutdown.subscribe();
 
-        if let Err(e) = self.setup_connection(min_version, max_version).await {
+        if let Err(e) = self.setup_connection().await {
             error!(error = ?e, "Upstream: connection setup failed.");
+            handle_error(&status_sender, e).await;
             return;
         }
 
@@ -50,18 +50,56 @@ pub enum ShutdownMessage {
     UpstreamShutdown(tokio::sync::mpsc::Sender<()>),
 }
 
-/// Constructs a `SetupConnection` message for the mining protocol.
+/// `SetupConnection` flag of the mining protocol asking the pool for work selection.
+const REQUIRES_WORK_SELECTION: u32 = 1 << 1;
+/// `SetupConnection` flag of the mining protocol announcing that the version field is rolled.
+const REQUIRES_VERSION_ROLLING: u32 = 1 << 2;
+/// `SetupConnection.Success` flag of the mining protocol: the pool does not accept any change of
+/// the version field.
+const REQUIRES_FIXED_VERSION: u32 = 1 << 0;
+
+/// Returns the flags of the `SetupConnection` sent to the pool, asking for work selection only
+/// if `work_selection` is set.
+pub fn mining_setup_connection_flags(work_selection: bool) -> u32 {
+    if work_selection {
+        REQUIRES_VERSION_ROLLING | REQUIRES_WORK_SELECTION
+    } else {
+        REQUIRES_VERSION_ROLLING
+    }
+}
+
+/// Checks that the flags of the `SetupConnection.Success` of the pool are compatible with the
+/// `requested_flags` of the `SetupConnection` sent to it.
+pub fn check_setup_connection_success_flags(
+    requested_flags: u32,
+    success_flags: u32,
+) -> Result<(), JDCErrorKind> {
+    // the JDC rolls the version field of every job it builds
+    if requested_flags & REQUIRES_VERSION_ROLLING != 0
+        && success_flags & REQUIRES_FIXED_VERSION != 0
+    {
+        return Err(JDCErrorKind::IncompatibleSetupConnectionFlags {
+            requested: requested_flags,
+            success: success_flags,
+        });
+    }
+    Ok(())
+}
+
+/// Constructs a `SetupConnection` message for the mining protocol, asking for work selection if
+/// `work_selection` is set.
 pub fn get_setup_connection_message(
     min_version: u16,
     max_version: u16,
     address: &SocketAddr,
+    work_selection: bool,
 ) -> Result<SetupConnection<'static>, JDCErrorKind> {
     let endpoint_host = address.ip().to_string().into_bytes().try_into()?;
     let vendor = String::new().try_into()?;
     let hardware_version = String::new().try_into()?;
     let firmware = String::new().try_into()?;
     let device_id = String::new().try_into()?;
-    let flags = 0b0000_0000_0000_0000_0000_0000_0000_0110;
+    let flags = mining_setup_connection_flags(work_selection);
     Ok(SetupConnection {
         protocol: Protocol::MiningProtocol,
         min_version,
@@ -304,3 +342,33 @@ impl From<(DownstreamId, ChannelId, JobId)> for DownstreamChannelJobId {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use stratum_apps::stratum_core::common_messages_sv2::has_work_selection;
+
+    use super::*;
+
+    #[test]
+    fn setup_connection_flags_follow_the_work_selection_preference() {
+        let address: SocketAddr = "127.0.0.1:34254".parse().unwrap();
+        for work_selection in [true, false] {
+            let setup_connection =
+                get_setup_connection_message(2, 2, &address, work_selection).unwrap();
+            assert_eq!(has_work_selection(setup_connection.flags), work_selection);
+            assert_ne!(setup_connection.flags & REQUIRES_VERSION_ROLLING, 0);
+        }
+    }
+
+    #[test]
+    fn fixed_version_pools_are_incompatible() {
+        for work_selection in [true, false] {
+            let requested = mining_setup_connection_flags(work_selection);
+            assert!(check_setup_connection_success_flags(requested, 0).is_ok());
+            assert!(matches!(
+                check_setup_connection_success_flags(requested, REQUIRES_FIXED_VERSION),
+                Err(JDCErrorKind::IncompatibleSetupConnectionFlags { .. })
+            ));
+        }
+    }
+}
@@ -4,7 +4,7 @@
 //! and the `from_args` function to parse them from the command line.
 use clap::Parser;
 use ext_config::{Config, File, FileFormat};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tracing::error;
 use translator_sv2::{config::TranslatorConfig, error::TproxyErrorKind};
 
@@ -28,13 +28,24 @@ pub struct Args {
 }
 
 /// Process CLI args, if any.
+///
+/// Returns the configuration along with the path it was loaded from, to load it again on reload.
 #[allow(clippy::result_large_err)]
-pub fn process_cli_args() -> Result<TranslatorConfig, TproxyErrorKind> {
+pub fn process_cli_args() -> Result<(TranslatorConfig, PathBuf), TproxyErrorKind> {
     // Parse CLI arguments
     let args = Args::parse();
 
-    // Build configuration from the provided file path
-    let config_path = args.config_path.to_str().ok_or_else(|| {
+    let mut config = load_config(&args.config_path)?;
+
+    config.set_log_dir(args.log_file);
+
+    Ok((config, args.config_path))
+}
+
+/// Loads the configuration from the TOML file at `config_path`.
+#[allow(clippy::result_large_err)]
+pub fn load_config(config_path: &Path) -> Result<TranslatorConfig, TproxyErrorKind> {
+    let config_path = config_path.to_str().ok_or_else(|| {
         error!("Invalid configuration path.");
         TproxyErrorKind::BadCliArgs
     })?;
@@ -44,9 +55,7 @@ pub fn process_cli_args() -> Result<TranslatorConfig, TproxyErrorKind> {
         .build()?;
 
     // Deserialize settings into TranslatorConfig
-    let mut config = settings.try_deserialize::<TranslatorConfig>()?;
-
-    config.set_log_dir(args.log_file);
+    let config = settings.try_deserialize::<TranslatorConfig>()?;
 
     Ok(config)
 }
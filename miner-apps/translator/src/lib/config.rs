@@ -10,14 +10,27 @@
 //! - Downstream interface address and port ([`DownstreamConfig`])
 //! - Supported protocol versions
 //! - Downstream difficulty adjustment parameters ([`DownstreamDifficultyConfig`])
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use serde::Deserialize;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
 use std::net::SocketAddr;
 use stratum_apps::{
-    config_helpers::opt_path_from_toml,
+    config_helpers::{opt_path_from_toml, AllUpstreamsFailedPolicy},
     key_utils::Secp256k1PublicKey,
-    utils::types::{Hashrate, SharesPerMinute},
+    network_helpers::{noise_stream::DEFAULT_MAX_FRAME_SIZE, slow_consumer::SlowConsumerPolicy},
+    utils::{
+        bind_retry::BindRetry,
+        types::{Hashrate, SharesPerMinute},
+    },
+};
+
+use crate::{
+    error::TproxyErrorKind, io_task::IoTimeouts,
+    sv2::channel_manager::extranonce_factory::DEFAULT_EXTRANONCE_USAGE_WARNING_THRESHOLD,
 };
 
 /// Configuration for the Translator.
@@ -35,8 +48,8 @@ pub struct TranslatorConfig {
     /// The size of the extranonce2 field for downstream mining connections.
     pub downstream_extranonce2_size: u16,
     /// The user identity/username to use when connecting to the pool.
-    /// This will be appended with a counter for each mining channel (e.g., username.miner1,
-    /// username.miner2).
+    /// Each mining channel's identity is built from it with `user_identity_template`
+    /// (by default username.miner1, username.miner2, ...).
     pub user_identity: String,
     /// Configuration settings for managing difficulty on the downstream connection.
     pub downstream_difficulty_config: DownstreamDifficultyConfig,
@@ -57,12 +70,452 @@ pub struct TranslatorConfig {
     monitoring_address: Option<SocketAddr>,
     #[serde(default = "default_monitoring_cache_refresh_secs")]
     monitoring_cache_refresh_secs: u64,
+    /// Age, in seconds, past which a monitoring request rebuilds the snapshot itself, bounding
+    /// the served staleness if the periodic refresh stalls. Unset disables it.
+    #[serde(default)]
+    monitoring_on_demand_refresh_secs: Option<u64>,
+    /// Bearer token the `POST /api/v1/failover` admin action of the monitoring server requires.
+    /// Unset keeps the action disabled.
+    #[serde(default)]
+    monitoring_admin_token: Option<String>,
+    /// Whether to keep a second upstream connected with `SetupConnection` already completed,
+    /// so that failover skips the connection, the Noise handshake and `SetupConnection`. No
+    /// channel is opened on it before it is promoted, so failover still waits for the channels to
+    /// open.
+    #[serde(default)]
+    warm_standby: bool,
+    /// Index into `upstreams` of the upstream kept as warm standby. Once it was promoted, the
+    /// next upstream in the list is kept as standby instead.
+    #[serde(default = "default_standby_upstream_index")]
+    standby_upstream_index: usize,
+    /// Seconds without a `NewExtendedMiningJob`/`SetNewPrevHash` on an upstream channel after
+    /// which the channel is reported as stale. Unset disables the job-staleness watchdog.
+    #[serde(default)]
+    job_staleness_secs: Option<u64>,
+    /// Whether a stale upstream channel triggers fallback to the next upstream instead of only
+    /// being logged.
+    #[serde(default)]
+    job_staleness_fallback: bool,
+    /// Template of the per-miner user identity. Supports `{user}` (the configured
+    /// `user_identity`), `{worker}` (the worker name the SV1 miner authorized with) and `{id}`
+    /// (a per-miner counter).
+    ///
+    /// With `{worker}`, the upstream channel of a miner is opened once it sends
+    /// `mining.authorize`, so its `user_identity` carries the worker name. A miner that does not
+    /// authorize within 2 seconds of its first message gets its channel with `{worker}` rendered
+    /// as `miner{id}`.
+    #[serde(default = "default_user_identity_template")]
+    user_identity_template: String,
+    /// Milliseconds between receiving a job from upstream and sending its `mining.notify` to the
+    /// last downstream above which a warning is logged and the alarm counter is incremented.
+    #[serde(default = "default_job_propagation_alarm_ms")]
+    job_propagation_alarm_ms: u64,
+    /// Maximum number of concurrent SV1 connections from a single IP address. `0` disables the
+    /// limit.
+    #[serde(default = "default_max_connections_per_ip")]
+    max_connections_per_ip: u32,
+    /// Maximum number of new SV1 connections accepted per second, across all IP addresses.
+    /// `0` disables the limit.
+    #[serde(default = "default_max_accepts_per_sec")]
+    max_accepts_per_sec: u32,
+    /// Number of SV1 connections closed within a minute above which a warning about flapping
+    /// miners is logged. `0` disables the warning.
+    #[serde(default)]
+    churn_warning_per_minute: u32,
+    /// Smallest extranonce2 size to retry opening a channel with when the upstream rejects
+    /// `downstream_extranonce2_size` as too large. The size is lowered one byte at a time.
+    #[serde(default = "default_downstream_extranonce2_size_floor")]
+    downstream_extranonce2_size_floor: u16,
+    /// Number of channels whose latest `SetNewPrevHash` the SV1 server may retain before it logs
+    /// a warning and prunes the entries of closed channels.
+    #[serde(default = "default_max_retained_prevhashes")]
+    max_retained_prevhashes: usize,
+    /// Number of jobs per channel the SV1 server holds while waiting for the `SetNewPrevHash` of
+    /// the channel. The oldest job is dropped when the channel holds more. 0 drops such jobs
+    /// right away.
+    #[serde(default = "default_max_pending_jobs_per_channel")]
+    max_pending_jobs_per_channel: usize,
+    /// Seconds a job waits for the `SetNewPrevHash` of its channel before it is dropped.
+    #[serde(default = "default_pending_job_timeout_secs")]
+    pending_job_timeout_secs: u64,
+    /// Number of notified jobs per channel, keepalive jobs included, the SV1 server keeps valid
+    /// for share validation. The oldest jobs are evicted when the channel holds more, except the
+    /// upstream job the latest job is built on.
+    #[serde(default = "default_max_valid_jobs_per_channel")]
+    max_valid_jobs_per_channel: usize,
+    /// Largest number of keepalive `mining.notify` sent per second. Keepalive jobs are spread
+    /// over the keepalive check interval and never sent faster than this. 0 only spreads them.
+    #[serde(default = "default_max_keepalive_notifies_per_sec")]
+    max_keepalive_notifies_per_sec: u32,
+    /// Whether to open the upstream channel with the extranonce2 size a SV1 miner suggests in its
+    /// `mining.configure`, when larger than `downstream_extranonce2_size`.
+    #[serde(default)]
+    forward_miner_extranonce2_size: bool,
+    /// Whether to request the extensions negotiated with an upstream on the previous connection
+    /// right away when reconnecting to it. SV2 has no session resumption, so `SetupConnection` is
+    /// still sent on every connection.
+    #[serde(default)]
+    reuse_negotiated_extensions: bool,
+    /// Number of running tasks above which a warning is logged. Tasks are still spawned past it.
+    /// Unset disables the soft cap.
+    #[serde(default)]
+    max_tasks: Option<usize>,
+    /// What to do when the upstream rejects an `UpdateChannel` sent by the translator.
+    #[serde(default)]
+    update_channel_error_action: UpdateChannelErrorAction,
+    /// What to do when the flags of the `SetupConnectionSuccess` of the upstream may not suit the
+    /// translator.
+    #[serde(default)]
+    setup_connection_flags_policy: SetupConnectionFlagsPolicy,
+    /// Largest accepted lead, in seconds, of the time of the first job received from an upstream
+    /// over the local clock. Job times in the past are accepted. Keepalive jobs are never given a
+    /// time further ahead of the local clock than this either.
+    #[serde(default = "default_max_clock_skew_secs")]
+    max_clock_skew_secs: u64,
+    /// Whether to shut down instead of only warning when the clock skew exceeds
+    /// `max_clock_skew_secs`.
+    #[serde(default)]
+    refuse_clock_skew: bool,
+    /// What to do once every upstream failed: `shutdown` or `wait_and_retry`. The translator
+    /// cannot mine solo.
+    #[serde(default = "default_on_all_upstreams_failed")]
+    on_all_upstreams_failed: AllUpstreamsFailedPolicy,
+    /// Seconds to wait before trying every upstream again with the `wait_and_retry` policy.
+    #[serde(default = "default_upstream_retry_interval_secs")]
+    upstream_retry_interval_secs: u64,
+    /// Seconds the translator stays connected to an upstream before rotating to the next one. The
+    /// next upstream is connected first, then the shares queued for the current one are drained
+    /// and the miners are moved onto the next one without being disconnected. 0 disables the
+    /// rotation.
+    #[serde(default)]
+    upstream_max_lifetime_secs: u64,
+    /// Rules deriving the monitoring group label of a downstream from the worker name it
+    /// authorizes with. The first matching rule wins; downstreams matching none have no label.
+    #[serde(default)]
+    downstream_groups: Vec<DownstreamGroupRule>,
+    /// Rules opening the channel of a downstream on another upstream than the primary one,
+    /// from the worker name it authorizes with. The first matching rule wins; downstreams
+    /// matching none use the primary upstream. Only supported in non-aggregated mode.
+    ///
+    /// Channels are opened on the primary upstream on `mining.subscribe` and moved on
+    /// `mining.authorize`, so miners need `mining.extranonce.subscribe`. Other miners are
+    /// disconnected once, and reconnect onto the routed upstream.
+    #[serde(default)]
+    upstream_routes: Vec<UpstreamRouteRule>,
+    /// Seconds the vardiff state of a disconnected SV1 miner is kept, keyed by its worker name
+    /// and IP address, to resume its difficulty if it reconnects in time. 0 disables it.
+    #[serde(default)]
+    vardiff_retention_secs: u64,
+    /// Seconds the channel of a disconnected SV1 miner is kept open, so a `mining.subscribe`
+    /// presenting its session id from the same IP address resumes it with the same extranonce1.
+    /// 0 disables it.
+    #[serde(default)]
+    session_resumption_secs: u64,
+    /// Seconds the channel of a disconnected SV1 miner is kept for its worker, so a
+    /// `mining.subscribe` presenting its session id from the same IP address rejoins it with the
+    /// same extranonce1, provided the same worker authorizes. 0 disables it. Requires
+    /// `aggregate_channels`.
+    #[serde(default)]
+    channel_affinity_secs: u64,
+    /// Seconds without any connected SV1 miner after which the aggregated upstream channel is
+    /// closed, to be reopened by the next miner connecting. 0 keeps it open. Requires
+    /// `aggregate_channels`.
+    #[serde(default)]
+    idle_no_downstream_secs: u64,
+    /// Number of SV1 miners sharing an aggregated upstream channel above which another aggregated
+    /// channel is opened upstream for the next miners. Unset shares a single channel. Requires
+    /// `aggregate_channels`.
+    #[serde(default)]
+    max_downstreams_per_aggregated_channel: Option<usize>,
+    /// Largest SV2 frame, in bytes, accepted from upstreams. An upstream announcing a larger
+    /// frame is disconnected before its payload is read.
+    #[serde(default = "default_max_frame_size")]
+    max_frame_size: usize,
+    /// Fraction of the extranonce prefixes of an upstream channel allocated to downstream
+    /// channels above which a warning is logged, e.g. 0.8 for 80%.
+    #[serde(default = "default_extranonce_usage_warning_threshold")]
+    extranonce_usage_warning_threshold: f64,
+    /// Number of messages waiting to be sent to a SV1 miner above which it is falling behind.
+    /// Unset disables slow consumer detection.
+    #[serde(default)]
+    slow_consumer_queue_threshold: Option<usize>,
+    /// Seconds the queue towards a miner must stay above `slow_consumer_queue_threshold` for the
+    /// miner to be flagged as a slow consumer.
+    #[serde(default = "default_slow_consumer_secs")]
+    slow_consumer_secs: u64,
+    /// Whether a miner flagged as a slow consumer is disconnected instead of only being reported.
+    #[serde(default)]
+    disconnect_slow_consumers: bool,
+    /// Seconds past the ntime of a job that SV1 miners may roll the ntime of their shares to.
+    /// Shares with an ntime before the job ntime or past this window are rejected.
+    #[serde(default = "default_ntime_roll_window_secs")]
+    ntime_roll_window_secs: u32,
+    /// Whether to log the message type and payload hex of every SV2 frame exchanged with the
+    /// upstream, at trace level.
+    #[serde(default)]
+    frame_trace: bool,
+    /// Seconds a SV1 miner may take to send `mining.authorize` after its `mining.subscribe`
+    /// before it is disconnected. 0 disables the check.
+    #[serde(default)]
+    authorize_grace_secs: u64,
+    /// Seconds a channel opened for a SV1 miner may take to be accepted by the upstream, counted
+    /// from when its request is sent, not while it waits for a slot under
+    /// `max_in_flight_open_channels`. A later `OpenExtendedMiningChannelSuccess` still gives its
+    /// channel to the miner if it has none by then, and is closed otherwise.
+    #[serde(default = "default_open_channel_timeout_secs")]
+    open_channel_timeout_secs: u64,
+    /// Largest number of `OpenExtendedMiningChannel` requests waiting for an answer of the
+    /// upstream at once. Further requests are queued until an answer, a timeout or a disconnection
+    /// frees a slot, a miner holding at most one place in the queue. Unset sends every request
+    /// right away.
+    #[serde(default)]
+    max_in_flight_open_channels: Option<usize>,
+    /// What to do with a SV1 miner whose channel was refused by the upstream or not accepted
+    /// within `open_channel_timeout_secs`: `disconnect` it, or `retry` opening its channel. A
+    /// channel the primary upstream keeps refusing makes the translator fall back to the next
+    /// upstream instead of disconnecting the miner.
+    #[serde(default)]
+    open_channel_failure_action: OpenChannelFailureAction,
+    /// Times the channel of a SV1 miner is requested again with the `retry` action before the
+    /// miner is disconnected.
+    #[serde(default = "default_open_channel_retries")]
+    open_channel_retries: u32,
+    /// Seconds without receiving a frame from the upstream after which its connection is closed
+    /// and the translator falls back to the next upstream. Unset leaves reads unbounded.
+    #[serde(default)]
+    upstream_read_timeout_secs: Option<u64>,
+    /// Seconds writing a frame to the upstream may take before its connection is closed and the
+    /// translator falls back to the next upstream. Unset leaves writes unbounded.
+    #[serde(default)]
+    upstream_write_timeout_secs: Option<u64>,
+    /// Seconds during which a repeated warning, such as a failed connection attempt or a rejected
+    /// share, is logged only once per upstream or error code. The next one is logged with the
+    /// number suppressed, or a summary once the window is over if none follows. Unset logs every
+    /// occurrence.
+    #[serde(default)]
+    log_throttle_window_secs: Option<u64>,
+    /// Number of times binding the SV1 or monitoring listener is retried while its address is
+    /// still in use, e.g. by the previous process on a fast restart.
+    #[serde(default = "default_bind_retries")]
+    bind_retries: u32,
+    /// Milliseconds between two binds of a listener whose address is in use.
+    #[serde(default = "default_bind_retry_delay_ms")]
+    bind_retry_delay_ms: u64,
 }
 
 fn default_monitoring_cache_refresh_secs() -> u64 {
     15
 }
 
+fn default_standby_upstream_index() -> usize {
+    1
+}
+
+fn default_user_identity_template() -> String {
+    DEFAULT_USER_IDENTITY_TEMPLATE.to_string()
+}
+
+fn default_job_propagation_alarm_ms() -> u64 {
+    1000
+}
+
+fn default_max_connections_per_ip() -> u32 {
+    100
+}
+
+fn default_max_accepts_per_sec() -> u32 {
+    100
+}
+
+fn default_downstream_extranonce2_size_floor() -> u16 {
+    2
+}
+
+fn default_max_retained_prevhashes() -> usize {
+    10_000
+}
+
+fn default_max_pending_jobs_per_channel() -> usize {
+    8
+}
+
+fn default_pending_job_timeout_secs() -> u64 {
+    10
+}
+
+fn default_max_valid_jobs_per_channel() -> usize {
+    64
+}
+
+fn default_max_keepalive_notifies_per_sec() -> u32 {
+    100
+}
+
+fn default_max_clock_skew_secs() -> u64 {
+    600
+}
+
+fn default_on_all_upstreams_failed() -> AllUpstreamsFailedPolicy {
+    AllUpstreamsFailedPolicy::Shutdown
+}
+
+fn default_upstream_retry_interval_secs() -> u64 {
+    30
+}
+
+fn default_max_frame_size() -> usize {
+    DEFAULT_MAX_FRAME_SIZE
+}
+
+fn default_extranonce_usage_warning_threshold() -> f64 {
+    DEFAULT_EXTRANONCE_USAGE_WARNING_THRESHOLD
+}
+
+fn default_slow_consumer_secs() -> u64 {
+    10
+}
+
+fn default_open_channel_timeout_secs() -> u64 {
+    60
+}
+
+fn default_open_channel_retries() -> u32 {
+    3
+}
+
+fn default_bind_retries() -> u32 {
+    5
+}
+
+fn default_bind_retry_delay_ms() -> u64 {
+    500
+}
+
+fn default_ntime_roll_window_secs() -> u32 {
+    // Bitcoin rejects blocks with a time more than two hours ahead of the network time
+    7200
+}
+
+/// Reaction of the translator to an `UpdateChannelError` from the upstream.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateChannelErrorAction {
+    /// Re-send the update once, with the nominal hashrate reverted to the value the channel had
+    /// before the update, or without restricting the maximum target, depending on the error.
+    #[default]
+    RevertAndRetry,
+    /// Only log the error, leaving the channel on the target the upstream last set.
+    Ignore,
+}
+
+/// What the translator does when the flags of the `SetupConnectionSuccess` of the upstream may
+/// not suit it.
+///
+/// `REQUIRES_EXTENDED_CHANNELS` is always accepted, as the translator only opens extended
+/// channels. `REQUIRES_FIXED_VERSION`, which the spec allows, takes away the version rolling the
+/// translator asked for, and flags the translator does not know may change what the upstream
+/// expects from it.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SetupConnectionFlagsPolicy {
+    /// Proceed, only logging the flags at debug level.
+    Ignore,
+    /// Proceed, logging a warning.
+    #[default]
+    Warn,
+    /// Fail over to the next upstream.
+    FailOver,
+}
+
+/// What the translator does with a SV1 miner whose channel could not be opened.
+///
+/// Until its channel is open, the handshake messages of the miner are queued. Retrying keeps
+/// them queued for the next channel, disconnecting answers the queued requests with an error.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OpenChannelFailureAction {
+    /// Disconnect the miner right away.
+    #[default]
+    Disconnect,
+    /// Request its channel again, up to `open_channel_retries` times, before disconnecting it.
+    Retry,
+}
+
+/// Rule labelling the downstreams whose authorized worker name matches `pattern`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct DownstreamGroupRule {
+    /// Regular expression matched against the worker name of `mining.authorize`.
+    #[serde(deserialize_with = "regex_from_toml")]
+    pattern: Regex,
+    /// Group label of the matching downstreams. It may reference capture groups of `pattern`,
+    /// as `$1` or `${name}`.
+    label: String,
+}
+
+impl DownstreamGroupRule {
+    /// Creates a rule labelling the worker names matching `pattern` with `label`.
+    pub fn new(pattern: &str, label: String) -> Result<Self, regex::Error> {
+        Ok(Self {
+            pattern: Regex::new(pattern)?,
+            label,
+        })
+    }
+
+    /// Returns the label of `worker_name`, or `None` if it does not match the rule.
+    pub fn label_for(&self, worker_name: &str) -> Option<String> {
+        let captures = self.pattern.captures(worker_name)?;
+        let mut label = String::new();
+        captures.expand(&self.label, &mut label);
+        Some(label)
+    }
+}
+
+/// Rule opening the channel of the downstreams whose authorized worker name matches `pattern` on
+/// the upstream at index `upstream` of the `upstreams` list.
+#[derive(Debug, Deserialize, Clone)]
+pub struct UpstreamRouteRule {
+    /// Regular expression matched against the worker name of `mining.authorize`.
+    #[serde(deserialize_with = "regex_from_toml")]
+    pattern: Regex,
+    /// Index into the `upstreams` list of the upstream the matching downstreams are routed to.
+    upstream: usize,
+}
+
+impl UpstreamRouteRule {
+    /// Creates a rule routing the worker names matching `pattern` to the upstream at index
+    /// `upstream`.
+    pub fn new(pattern: &str, upstream: usize) -> Result<Self, regex::Error> {
+        Ok(Self {
+            pattern: Regex::new(pattern)?,
+            upstream,
+        })
+    }
+
+    /// Returns the index of the upstream the rule routes to.
+    pub fn upstream(&self) -> usize {
+        self.upstream
+    }
+
+    /// Returns whether `worker_name` matches the rule.
+    pub fn matches(&self, worker_name: &str) -> bool {
+        self.pattern.is_match(worker_name)
+    }
+}
+
+fn regex_from_toml<'de, D>(deserializer: D) -> Result<Regex, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(serde::de::Error::custom)
+}
+
+/// Default user identity template, yielding `username.miner1`, `username.miner2`, ...
+pub const DEFAULT_USER_IDENTITY_TEMPLATE: &str = "{user}.miner{id}";
+
+/// Maximum length in bytes of a user identity, as carried by a `Str0255`.
+pub const MAX_USER_IDENTITY_LEN: usize = 255;
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Upstream {
     /// The address of the upstream server.
@@ -71,6 +524,13 @@ pub struct Upstream {
     pub port: u16,
     /// The Secp256k1 public key used to authenticate the upstream authority.
     pub authority_pubkey: Secp256k1PublicKey,
+    /// Optional name used as the `upstream` label in monitoring metrics.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Endpoints this upstream may redirect the translator to with `Reconnect`, as `host:port`,
+    /// or `host` to allow any of its ports. A `Reconnect` to another endpoint is ignored.
+    #[serde(default)]
+    pub reconnect_allowlist: Vec<String>,
 }
 
 impl Upstream {
@@ -80,8 +540,29 @@ impl Upstream {
             address,
             port,
             authority_pubkey,
+            name: None,
+            reconnect_allowlist: Vec::new(),
         }
     }
+
+    /// Sets the name used to label this upstream in monitoring metrics.
+    pub fn with_name(mut self, name: String) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Sets the endpoints this upstream may redirect the translator to with `Reconnect`.
+    pub fn with_reconnect_allowlist(mut self, reconnect_allowlist: Vec<String>) -> Self {
+        self.reconnect_allowlist = reconnect_allowlist;
+        self
+    }
+
+    /// Returns the monitoring label of this upstream: its name, or `address:port` if unnamed.
+    pub fn label(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("{}:{}", self.address, self.port))
+    }
 }
 
 impl TranslatorConfig {
@@ -116,168 +597,1013 @@ impl TranslatorConfig {
             log_file: None,
             monitoring_address: None,
             monitoring_cache_refresh_secs: 15,
+            monitoring_on_demand_refresh_secs: None,
+            monitoring_admin_token: None,
+            warm_standby: false,
+            standby_upstream_index: default_standby_upstream_index(),
+            job_staleness_secs: None,
+            job_staleness_fallback: false,
+            user_identity_template: default_user_identity_template(),
+            job_propagation_alarm_ms: default_job_propagation_alarm_ms(),
+            max_connections_per_ip: default_max_connections_per_ip(),
+            max_accepts_per_sec: default_max_accepts_per_sec(),
+            churn_warning_per_minute: 0,
+            downstream_extranonce2_size_floor: default_downstream_extranonce2_size_floor(),
+            max_retained_prevhashes: default_max_retained_prevhashes(),
+            max_pending_jobs_per_channel: default_max_pending_jobs_per_channel(),
+            pending_job_timeout_secs: default_pending_job_timeout_secs(),
+            max_valid_jobs_per_channel: default_max_valid_jobs_per_channel(),
+            max_keepalive_notifies_per_sec: default_max_keepalive_notifies_per_sec(),
+            forward_miner_extranonce2_size: false,
+            reuse_negotiated_extensions: false,
+            max_tasks: None,
+            update_channel_error_action: UpdateChannelErrorAction::default(),
+            setup_connection_flags_policy: SetupConnectionFlagsPolicy::default(),
+            max_clock_skew_secs: default_max_clock_skew_secs(),
+            refuse_clock_skew: false,
+            on_all_upstreams_failed: default_on_all_upstreams_failed(),
+            upstream_retry_interval_secs: default_upstream_retry_interval_secs(),
+            upstream_max_lifetime_secs: 0,
+            downstream_groups: Vec::new(),
+            upstream_routes: Vec::new(),
+            vardiff_retention_secs: 0,
+            session_resumption_secs: 0,
+            channel_affinity_secs: 0,
+            idle_no_downstream_secs: 0,
+            max_downstreams_per_aggregated_channel: None,
+            max_frame_size: default_max_frame_size(),
+            extranonce_usage_warning_threshold: default_extranonce_usage_warning_threshold(),
+            slow_consumer_queue_threshold: None,
+            slow_consumer_secs: default_slow_consumer_secs(),
+            disconnect_slow_consumers: false,
+            ntime_roll_window_secs: default_ntime_roll_window_secs(),
+            frame_trace: false,
+            authorize_grace_secs: 0,
+            open_channel_timeout_secs: default_open_channel_timeout_secs(),
+            max_in_flight_open_channels: None,
+            open_channel_failure_action: OpenChannelFailureAction::default(),
+            open_channel_retries: default_open_channel_retries(),
+            upstream_read_timeout_secs: None,
+            upstream_write_timeout_secs: None,
+            log_throttle_window_secs: None,
+            bind_retries: default_bind_retries(),
+            bind_retry_delay_ms: default_bind_retry_delay_ms(),
         }
     }
 
-    /// Returns the monitoring server bind address (if enabled)
-    pub fn monitoring_address(&self) -> Option<SocketAddr> {
-        self.monitoring_address
+    /// Enables warm standby, keeping `upstreams[standby_upstream_index]` connected and idle.
+    pub fn with_warm_standby(mut self, standby_upstream_index: usize) -> Self {
+        self.warm_standby = true;
+        self.standby_upstream_index = standby_upstream_index;
+        self
     }
 
-    /// Returns the monitoring cache refresh interval in seconds.
-    pub fn monitoring_cache_refresh_secs(&self) -> u64 {
-        self.monitoring_cache_refresh_secs
+    /// Returns the index of the warm-standby upstream, if warm standby is enabled.
+    pub fn warm_standby_upstream_index(&self) -> Option<usize> {
+        self.warm_standby.then_some(self.standby_upstream_index)
     }
 
-    pub fn set_log_dir(&mut self, log_dir: Option<PathBuf>) {
-        if let Some(dir) = log_dir {
-            self.log_file = Some(dir);
-        }
+    /// Enables the job-staleness watchdog with the given timeout.
+    pub fn with_job_staleness_watchdog(mut self, job_staleness_secs: u64, fallback: bool) -> Self {
+        self.job_staleness_secs = Some(job_staleness_secs);
+        self.job_staleness_fallback = fallback;
+        self
     }
-    pub fn log_dir(&self) -> Option<&Path> {
-        self.log_file.as_deref()
+
+    /// Returns the job-staleness timeout, if the watchdog is enabled.
+    pub fn job_staleness_timeout(&self) -> Option<Duration> {
+        self.job_staleness_secs.map(Duration::from_secs)
     }
-}
 
-/// Configuration settings for managing difficulty adjustments on the downstream connection.
-#[derive(Debug, Deserialize, Clone)]
-pub struct DownstreamDifficultyConfig {
-    /// The minimum hashrate expected from an individual miner on the downstream connection.
-    pub min_individual_miner_hashrate: Hashrate,
-    /// The target number of shares per minute for difficulty adjustment.
-    pub shares_per_minute: SharesPerMinute,
-    /// Whether to enable variable difficulty adjustment mechanism.
-    /// If false, difficulty will be managed by upstream (useful with JDC).
-    pub enable_vardiff: bool,
-    /// Interval in seconds for sending keepalive jobs to downstream miners.
-    /// The translator will send periodic mining.notify messages with updated time
-    /// to prevent SV1 miners from timing out when the upstream doesn't send new jobs
-    /// frequently enough (e.g., due to low Bitcoin mempool activity).
-    /// Set to 0 to disable keepalive jobs.
-    pub job_keepalive_interval_secs: u16,
-}
+    /// Returns whether a stale upstream channel triggers fallback.
+    pub fn job_staleness_fallback(&self) -> bool {
+        self.job_staleness_fallback
+    }
 
-impl DownstreamDifficultyConfig {
-    /// Creates a new `DownstreamDifficultyConfig` instance.
-    pub fn new(
-        min_individual_miner_hashrate: Hashrate,
-        shares_per_minute: SharesPerMinute,
-        enable_vardiff: bool,
-        job_keepalive_interval_secs: u16,
+    /// Sets the job propagation latency above which an alarm is raised.
+    pub fn with_job_propagation_alarm(mut self, job_propagation_alarm_ms: u64) -> Self {
+        self.job_propagation_alarm_ms = job_propagation_alarm_ms;
+        self
+    }
+
+    /// Returns the job propagation latency above which an alarm is raised.
+    pub fn job_propagation_alarm_threshold(&self) -> Duration {
+        Duration::from_millis(self.job_propagation_alarm_ms)
+    }
+
+    /// Sets the per-IP connection cap and the accept rate limit of the SV1 server. `0` disables
+    /// the corresponding limit.
+    pub fn with_connection_limits(
+        mut self,
+        max_connections_per_ip: u32,
+        max_accepts_per_sec: u32,
     ) -> Self {
-        Self {
-            min_individual_miner_hashrate,
-            shares_per_minute,
-            enable_vardiff,
-            job_keepalive_interval_secs,
-        }
+        self.max_connections_per_ip = max_connections_per_ip;
+        self.max_accepts_per_sec = max_accepts_per_sec;
+        self
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::str::FromStr;
+    /// Returns the maximum number of concurrent SV1 connections per IP address.
+    pub fn max_connections_per_ip(&self) -> u32 {
+        self.max_connections_per_ip
+    }
 
-    fn create_test_upstream() -> Upstream {
-        // Use a valid base58-encoded public key from the key-utils test cases
-        let pubkey_str = "9bDuixKmZqAJnrmP746n8zU1wyAQRrus7th9dxnkPg6RzQvCnan";
-        let pubkey = Secp256k1PublicKey::from_str(pubkey_str).unwrap();
-        Upstream::new("127.0.0.1".to_string(), 4444, pubkey)
+    /// Returns the maximum number of new SV1 connections accepted per second.
+    pub fn max_accepts_per_sec(&self) -> u32 {
+        self.max_accepts_per_sec
     }
 
-    fn create_test_difficulty_config() -> DownstreamDifficultyConfig {
-        DownstreamDifficultyConfig::new(100.0, 5.0, true, 60)
+    /// Sets the number of SV1 connections closed within a minute above which a warning is
+    /// logged. `0` disables the warning.
+    pub fn with_churn_warning_per_minute(mut self, churn_warning_per_minute: u32) -> Self {
+        self.churn_warning_per_minute = churn_warning_per_minute;
+        self
     }
 
-    #[test]
-    fn test_upstream_creation() {
-        let upstream = create_test_upstream();
-        assert_eq!(upstream.address, "127.0.0.1");
-        assert_eq!(upstream.port, 4444);
+    /// Returns the number of SV1 connections closed within a minute above which a warning is
+    /// logged.
+    pub fn churn_warning_per_minute(&self) -> u32 {
+        self.churn_warning_per_minute
     }
 
-    #[test]
-    fn test_downstream_difficulty_config_creation() {
-        let config = create_test_difficulty_config();
-        assert_eq!(config.min_individual_miner_hashrate, 100.0);
-        assert_eq!(config.shares_per_minute, 5.0);
-        assert!(config.enable_vardiff);
+    /// Sets the number of running tasks above which a warning is logged.
+    pub fn with_max_tasks(mut self, max_tasks: usize) -> Self {
+        self.max_tasks = Some(max_tasks);
+        self
     }
 
-    #[test]
-    fn test_translator_config_creation() {
-        let upstreams = vec![create_test_upstream()];
-        let difficulty_config = create_test_difficulty_config();
+    /// Returns the soft cap on running tasks, if any.
+    pub fn max_tasks(&self) -> Option<usize> {
+        self.max_tasks
+    }
 
-        let config = TranslatorConfig::new(
-            upstreams,
-            "0.0.0.0".to_string(),
-            3333,
-            difficulty_config,
-            2,
-            1,
-            4,
-            "test_user".to_string(),
-            true,
-            vec![],
-            vec![],
-        );
+    /// Sets what to do when the upstream rejects an `UpdateChannel`.
+    pub fn with_update_channel_error_action(mut self, action: UpdateChannelErrorAction) -> Self {
+        self.update_channel_error_action = action;
+        self
+    }
 
-        assert_eq!(config.upstreams.len(), 1);
-        assert_eq!(config.downstream_address, "0.0.0.0");
-        assert_eq!(config.downstream_port, 3333);
-        assert_eq!(config.max_supported_version, 2);
-        assert_eq!(config.min_supported_version, 1);
-        assert_eq!(config.downstream_extranonce2_size, 4);
-        assert_eq!(config.user_identity, "test_user");
-        assert!(config.aggregate_channels);
-        assert!(config.supported_extensions.is_empty());
-        assert!(config.required_extensions.is_empty());
-        assert!(config.log_file.is_none());
+    /// Returns what to do when the upstream rejects an `UpdateChannel`.
+    pub fn update_channel_error_action(&self) -> UpdateChannelErrorAction {
+        self.update_channel_error_action
     }
 
-    #[test]
-    fn test_translator_config_log_dir() {
-        let upstreams = vec![create_test_upstream()];
-        let difficulty_config = create_test_difficulty_config();
+    /// Sets how strictly the flags of the `SetupConnectionSuccess` of the upstream are checked.
+    pub fn with_setup_connection_flags_policy(
+        mut self,
+        policy: SetupConnectionFlagsPolicy,
+    ) -> Self {
+        self.setup_connection_flags_policy = policy;
+        self
+    }
 
-        let mut config = TranslatorConfig::new(
-            upstreams,
-            "0.0.0.0".to_string(),
-            3333,
-            difficulty_config,
-            2,
-            1,
-            4,
-            "test_user".to_string(),
-            false,
-            vec![],
-            vec![],
-        );
+    /// Returns how strictly the flags of the `SetupConnectionSuccess` of the upstream are checked.
+    pub fn setup_connection_flags_policy(&self) -> SetupConnectionFlagsPolicy {
+        self.setup_connection_flags_policy
+    }
 
-        assert!(config.log_dir().is_none());
+    /// Sets the largest accepted clock skew, and whether exceeding it shuts the translator down.
+    pub fn with_max_clock_skew(mut self, max_clock_skew_secs: u64, refuse: bool) -> Self {
+        self.max_clock_skew_secs = max_clock_skew_secs;
+        self.refuse_clock_skew = refuse;
+        self
+    }
 
-        let log_path = PathBuf::from("/tmp/logs");
-        config.set_log_dir(Some(log_path.clone()));
-        assert_eq!(config.log_dir(), Some(log_path.as_path()));
+    /// Returns the largest accepted difference between upstream job times and the local clock.
+    pub fn max_clock_skew(&self) -> Duration {
+        Duration::from_secs(self.max_clock_skew_secs)
+    }
 
-        config.set_log_dir(None);
-        assert_eq!(config.log_dir(), Some(log_path.as_path())); // Should remain unchanged
+    /// Returns whether a clock skew above [`Self::max_clock_skew`] shuts the translator down.
+    pub fn refuse_clock_skew(&self) -> bool {
+        self.refuse_clock_skew
     }
 
-    #[test]
-    fn test_multiple_upstreams() {
-        let upstream1 = create_test_upstream();
-        let mut upstream2 = create_test_upstream();
-        upstream2.address = "192.168.1.1".to_string();
-        upstream2.port = 5555;
+    /// Sets what to do once every upstream failed, and how long to wait before trying them all
+    /// again with [`AllUpstreamsFailedPolicy::WaitAndRetry`].
+    pub fn with_on_all_upstreams_failed(
+        mut self,
+        policy: AllUpstreamsFailedPolicy,
+        retry_interval_secs: u64,
+    ) -> Self {
+        self.on_all_upstreams_failed = policy;
+        self.upstream_retry_interval_secs = retry_interval_secs;
+        self
+    }
 
-        let upstreams = vec![upstream1, upstream2];
-        let difficulty_config = create_test_difficulty_config();
+    /// Returns what to do once every upstream failed.
+    pub fn on_all_upstreams_failed(&self) -> AllUpstreamsFailedPolicy {
+        self.on_all_upstreams_failed
+    }
 
-        let config = TranslatorConfig::new(
-            upstreams,
+    /// Returns how long to wait before trying every upstream again.
+    pub fn upstream_retry_interval(&self) -> Duration {
+        Duration::from_secs(self.upstream_retry_interval_secs)
+    }
+
+    /// Sets how long, in seconds, the translator stays connected to an upstream before rotating
+    /// to the next one.
+    pub fn with_upstream_max_lifetime_secs(mut self, upstream_max_lifetime_secs: u64) -> Self {
+        self.upstream_max_lifetime_secs = upstream_max_lifetime_secs;
+        self
+    }
+
+    /// Returns how long the translator stays connected to an upstream before rotating to the
+    /// next one, if it rotates.
+    pub fn upstream_max_lifetime(&self) -> Option<Duration> {
+        (self.upstream_max_lifetime_secs > 0)
+            .then_some(Duration::from_secs(self.upstream_max_lifetime_secs))
+    }
+
+    /// Sets the smallest extranonce2 size to retry opening a channel with when the upstream
+    /// rejects the configured one as too large.
+    pub fn with_downstream_extranonce2_size_floor(
+        mut self,
+        downstream_extranonce2_size_floor: u16,
+    ) -> Self {
+        self.downstream_extranonce2_size_floor = downstream_extranonce2_size_floor;
+        self
+    }
+
+    /// Returns the smallest extranonce2 size to retry opening a channel with.
+    pub fn downstream_extranonce2_size_floor(&self) -> u16 {
+        self.downstream_extranonce2_size_floor
+    }
+
+    /// Sets the number of channel prevhashes the SV1 server may retain before pruning.
+    pub fn with_max_retained_prevhashes(mut self, max_retained_prevhashes: usize) -> Self {
+        self.max_retained_prevhashes = max_retained_prevhashes;
+        self
+    }
+
+    /// Returns the number of channel prevhashes the SV1 server may retain before pruning.
+    pub fn max_retained_prevhashes(&self) -> usize {
+        self.max_retained_prevhashes
+    }
+
+    /// Sets how many jobs per channel, and for how long, the SV1 server holds while waiting for
+    /// the prevhash of their channel.
+    pub fn with_pending_jobs(mut self, max_per_channel: usize, timeout_secs: u64) -> Self {
+        self.max_pending_jobs_per_channel = max_per_channel;
+        self.pending_job_timeout_secs = timeout_secs;
+        self
+    }
+
+    /// Returns the number of jobs per channel held while waiting for the channel's prevhash.
+    pub fn max_pending_jobs_per_channel(&self) -> usize {
+        self.max_pending_jobs_per_channel
+    }
+
+    /// Returns how long a job waits for the prevhash of its channel before it is dropped.
+    pub fn pending_job_timeout(&self) -> Duration {
+        Duration::from_secs(self.pending_job_timeout_secs)
+    }
+
+    /// Sets the number of notified jobs per channel kept valid for share validation.
+    pub fn with_max_valid_jobs_per_channel(mut self, max_valid_jobs_per_channel: usize) -> Self {
+        self.max_valid_jobs_per_channel = max_valid_jobs_per_channel;
+        self
+    }
+
+    /// Returns the number of notified jobs per channel kept valid for share validation.
+    pub fn max_valid_jobs_per_channel(&self) -> usize {
+        self.max_valid_jobs_per_channel
+    }
+
+    /// Sets the largest number of keepalive `mining.notify` sent per second, 0 for no limit.
+    pub fn with_max_keepalive_notifies_per_sec(mut self, max_per_sec: u32) -> Self {
+        self.max_keepalive_notifies_per_sec = max_per_sec;
+        self
+    }
+
+    /// Returns the largest number of keepalive `mining.notify` sent per second, 0 for no limit.
+    pub fn max_keepalive_notifies_per_sec(&self) -> u32 {
+        self.max_keepalive_notifies_per_sec
+    }
+
+    /// Sets whether to honor the extranonce2 size suggested by SV1 miners.
+    pub fn with_forward_miner_extranonce2_size(mut self, forward: bool) -> Self {
+        self.forward_miner_extranonce2_size = forward;
+        self
+    }
+
+    /// Returns whether to honor the extranonce2 size suggested by SV1 miners.
+    pub fn forward_miner_extranonce2_size(&self) -> bool {
+        self.forward_miner_extranonce2_size
+    }
+
+    /// Sets whether reconnecting to an upstream requests the extensions negotiated with it last
+    /// time.
+    pub fn with_reuse_negotiated_extensions(mut self, reuse: bool) -> Self {
+        self.reuse_negotiated_extensions = reuse;
+        self
+    }
+
+    /// Returns whether reconnecting to an upstream requests the extensions negotiated with it
+    /// last time.
+    pub fn reuse_negotiated_extensions(&self) -> bool {
+        self.reuse_negotiated_extensions
+    }
+
+    /// Sets how many seconds past the job ntime SV1 miners may roll the ntime of their shares.
+    pub fn with_ntime_roll_window_secs(mut self, ntime_roll_window_secs: u32) -> Self {
+        self.ntime_roll_window_secs = ntime_roll_window_secs;
+        self
+    }
+
+    /// Returns how many seconds past the job ntime SV1 miners may roll the ntime of their shares.
+    pub fn ntime_roll_window_secs(&self) -> u32 {
+        self.ntime_roll_window_secs
+    }
+
+    /// Sets whether the SV2 frames exchanged with the upstream are logged at trace level.
+    pub fn with_frame_trace(mut self, frame_trace: bool) -> Self {
+        self.frame_trace = frame_trace;
+        self
+    }
+
+    /// Returns whether the SV2 frames exchanged with the upstream are logged at trace level.
+    pub fn frame_trace(&self) -> bool {
+        self.frame_trace
+    }
+
+    /// Sets how many seconds a channel opened for a SV1 miner may take to be accepted.
+    pub fn with_open_channel_timeout(mut self, open_channel_timeout_secs: u64) -> Self {
+        self.open_channel_timeout_secs = open_channel_timeout_secs;
+        self
+    }
+
+    /// Returns how long a channel opened for a SV1 miner may take to be accepted.
+    pub fn open_channel_timeout(&self) -> Duration {
+        Duration::from_secs(self.open_channel_timeout_secs)
+    }
+
+    /// Sets how many `OpenExtendedMiningChannel` requests may wait for an answer of the upstream
+    /// at once.
+    pub fn with_max_in_flight_open_channels(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight_open_channels = Some(max_in_flight);
+        self
+    }
+
+    /// Returns how many `OpenExtendedMiningChannel` requests may wait for an answer of the
+    /// upstream at once, if limited. A limit of `0` is treated as `1`.
+    pub fn max_in_flight_open_channels(&self) -> Option<usize> {
+        self.max_in_flight_open_channels.map(|max| max.max(1))
+    }
+
+    /// Sets what to do with a SV1 miner whose channel failed to open, and how many times its
+    /// channel is requested again with [`OpenChannelFailureAction::Retry`].
+    pub fn with_open_channel_failure_action(
+        mut self,
+        action: OpenChannelFailureAction,
+        retries: u32,
+    ) -> Self {
+        self.open_channel_failure_action = action;
+        self.open_channel_retries = retries;
+        self
+    }
+
+    /// Returns what to do with a SV1 miner whose channel failed to open.
+    pub fn open_channel_failure_action(&self) -> OpenChannelFailureAction {
+        self.open_channel_failure_action
+    }
+
+    /// Returns how many times the channel of a SV1 miner is requested again before it is
+    /// disconnected.
+    pub fn open_channel_retries(&self) -> u32 {
+        self.open_channel_retries
+    }
+
+    /// Sets the read and write timeouts of the upstream connection, in seconds.
+    pub fn with_upstream_io_timeouts(
+        mut self,
+        read_timeout_secs: Option<u64>,
+        write_timeout_secs: Option<u64>,
+    ) -> Self {
+        self.upstream_read_timeout_secs = read_timeout_secs;
+        self.upstream_write_timeout_secs = write_timeout_secs;
+        self
+    }
+
+    /// Returns the read and write timeouts of the upstream connection.
+    pub fn upstream_io_timeouts(&self) -> IoTimeouts {
+        IoTimeouts {
+            read: self.upstream_read_timeout_secs.map(Duration::from_secs),
+            write: self.upstream_write_timeout_secs.map(Duration::from_secs),
+        }
+    }
+
+    /// Sets the window during which a repeated warning is logged only once.
+    pub fn with_log_throttle_window(mut self, log_throttle_window_secs: u64) -> Self {
+        self.log_throttle_window_secs = Some(log_throttle_window_secs);
+        self
+    }
+
+    /// Returns the window during which a repeated warning is logged only once, if throttled.
+    pub fn log_throttle_window(&self) -> Option<Duration> {
+        self.log_throttle_window_secs.map(Duration::from_secs)
+    }
+
+    /// Retries binding a listener whose address is in use `retries` times, `delay_ms`
+    /// milliseconds apart. No retries fail startup on the first bind failing.
+    pub fn with_bind_retry(mut self, retries: u32, delay_ms: u64) -> Self {
+        self.bind_retries = retries;
+        self.bind_retry_delay_ms = delay_ms;
+        self
+    }
+
+    /// Returns how binding a listener whose address is in use is retried.
+    pub fn bind_retry(&self) -> BindRetry {
+        BindRetry {
+            retries: self.bind_retries,
+            delay: Duration::from_millis(self.bind_retry_delay_ms),
+        }
+    }
+
+    /// Sets the template used to build each miner's user identity.
+    pub fn with_user_identity_template(mut self, user_identity_template: String) -> Self {
+        self.user_identity_template = user_identity_template;
+        self
+    }
+
+    /// Returns whether the user identity template references the SV1 worker name.
+    pub fn user_identity_template_uses_worker(&self) -> bool {
+        self.user_identity_template.contains("{worker}")
+    }
+
+    /// Renders the user identity of a miner from `user_identity_template`.
+    ///
+    /// `{worker}` is the part of `authorized_worker_name` after the first `.` (the whole name if
+    /// it has none). Before the miner authorized, it falls back to `miner{id}`.
+    pub fn render_user_identity(
+        &self,
+        authorized_worker_name: Option<&str>,
+        miner_id: u32,
+    ) -> Result<String, TproxyErrorKind> {
+        let worker = match authorized_worker_name {
+            Some(name) => name
+                .split_once('.')
+                .map_or(name, |(_, worker)| worker)
+                .to_string(),
+            None => format!("miner{miner_id}"),
+        };
+        let user_identity = self
+            .user_identity_template
+            .replace("{user}", &self.user_identity)
+            .replace("{worker}", &worker)
+            .replace("{id}", &miner_id.to_string());
+        if user_identity.len() > MAX_USER_IDENTITY_LEN {
+            return Err(TproxyErrorKind::UserIdentityTooLong(user_identity));
+        }
+        Ok(user_identity)
+    }
+
+    /// Sets the rules deriving the monitoring group label of each downstream.
+    pub fn with_downstream_groups(mut self, downstream_groups: Vec<DownstreamGroupRule>) -> Self {
+        self.downstream_groups = downstream_groups;
+        self
+    }
+
+    /// Returns the group label of a downstream authorized as `worker_name`, from the first
+    /// matching `downstream_groups` rule.
+    pub fn downstream_group_label(&self, worker_name: &str) -> Option<String> {
+        self.downstream_groups
+            .iter()
+            .find_map(|rule| rule.label_for(worker_name))
+    }
+
+    /// Sets the rules routing downstreams to upstreams by worker name.
+    pub fn with_upstream_routes(mut self, upstream_routes: Vec<UpstreamRouteRule>) -> Self {
+        self.upstream_routes = upstream_routes;
+        self
+    }
+
+    /// Returns the rules routing downstreams to upstreams by worker name.
+    pub fn upstream_routes(&self) -> &[UpstreamRouteRule] {
+        &self.upstream_routes
+    }
+
+    /// Returns the index of the upstream a downstream authorized as `worker_name` is routed to,
+    /// from the first matching `upstream_routes` rule.
+    pub fn routed_upstream_index(&self, worker_name: &str) -> Option<usize> {
+        self.upstream_routes
+            .iter()
+            .find(|rule| rule.matches(worker_name))
+            .map(UpstreamRouteRule::upstream)
+    }
+
+    /// Sets how long, in seconds, the vardiff state of a disconnected SV1 miner is kept.
+    pub fn with_vardiff_retention(mut self, vardiff_retention_secs: u64) -> Self {
+        self.vardiff_retention_secs = vardiff_retention_secs;
+        self
+    }
+
+    /// Returns how long the vardiff state of a disconnected SV1 miner is kept, if it is.
+    pub fn vardiff_retention(&self) -> Option<Duration> {
+        (self.vardiff_retention_secs > 0)
+            .then_some(Duration::from_secs(self.vardiff_retention_secs))
+    }
+
+    /// Sets how long, in seconds, the channel of a disconnected SV1 miner is kept to resume its
+    /// session.
+    pub fn with_session_resumption(mut self, session_resumption_secs: u64) -> Self {
+        self.session_resumption_secs = session_resumption_secs;
+        self
+    }
+
+    /// Returns how long the channel of a disconnected SV1 miner is kept, if it is.
+    pub fn session_resumption(&self) -> Option<Duration> {
+        (self.session_resumption_secs > 0)
+            .then_some(Duration::from_secs(self.session_resumption_secs))
+    }
+
+    /// Sets how long, in seconds, the channel of a disconnected SV1 miner is kept for its worker
+    /// to rejoin it.
+    pub fn with_channel_affinity(mut self, channel_affinity_secs: u64) -> Self {
+        self.channel_affinity_secs = channel_affinity_secs;
+        self
+    }
+
+    /// Returns how long the channel of a disconnected SV1 miner is kept for its worker, if it is.
+    pub fn channel_affinity(&self) -> Option<Duration> {
+        (self.channel_affinity_secs > 0).then_some(Duration::from_secs(self.channel_affinity_secs))
+    }
+
+    /// Sets how long, in seconds, the aggregated upstream channel stays open without any
+    /// connected SV1 miner.
+    pub fn with_idle_no_downstream_secs(mut self, idle_no_downstream_secs: u64) -> Self {
+        self.idle_no_downstream_secs = idle_no_downstream_secs;
+        self
+    }
+
+    /// Returns how long the aggregated upstream channel stays open without any connected SV1
+    /// miner, if it is closed at all.
+    pub fn idle_no_downstream(&self) -> Option<Duration> {
+        (self.idle_no_downstream_secs > 0)
+            .then_some(Duration::from_secs(self.idle_no_downstream_secs))
+    }
+
+    /// Sets how many SV1 miners share an aggregated upstream channel before another one is
+    /// opened.
+    pub fn with_max_downstreams_per_aggregated_channel(
+        mut self,
+        max_downstreams_per_aggregated_channel: usize,
+    ) -> Self {
+        self.max_downstreams_per_aggregated_channel = Some(max_downstreams_per_aggregated_channel);
+        self
+    }
+
+    /// Returns how many SV1 miners share an aggregated upstream channel, if it is capped.
+    pub fn max_downstreams_per_aggregated_channel(&self) -> Option<usize> {
+        self.max_downstreams_per_aggregated_channel
+    }
+
+    /// Sets the largest SV2 frame accepted from upstreams, in bytes.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Returns the largest SV2 frame accepted from upstreams, in bytes.
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Sets the fraction of the extranonce prefixes of an upstream channel above which a
+    /// warning is logged.
+    pub fn with_extranonce_usage_warning_threshold(mut self, threshold: f64) -> Self {
+        self.extranonce_usage_warning_threshold = threshold;
+        self
+    }
+
+    /// Returns the fraction of the extranonce prefixes of an upstream channel above which a
+    /// warning is logged.
+    pub fn extranonce_usage_warning_threshold(&self) -> f64 {
+        self.extranonce_usage_warning_threshold
+    }
+
+    /// Enables slow consumer detection, flagging miners whose queue stays above `queue_threshold`
+    /// messages for `secs` seconds, and whether flagged miners are disconnected.
+    pub fn with_slow_consumer_detection(
+        mut self,
+        queue_threshold: usize,
+        secs: u64,
+        disconnect: bool,
+    ) -> Self {
+        self.slow_consumer_queue_threshold = Some(queue_threshold);
+        self.slow_consumer_secs = secs;
+        self.disconnect_slow_consumers = disconnect;
+        self
+    }
+
+    /// Returns when a miner is flagged as a slow consumer, if the detection is enabled.
+    pub fn slow_consumer_policy(&self) -> Option<SlowConsumerPolicy> {
+        self.slow_consumer_queue_threshold
+            .map(|queue_threshold| SlowConsumerPolicy {
+                queue_threshold,
+                duration: Duration::from_secs(self.slow_consumer_secs),
+                disconnect: self.disconnect_slow_consumers,
+            })
+    }
+
+    /// Disconnects SV1 miners that do not send `mining.authorize` within `secs` seconds of their
+    /// `mining.subscribe`. 0 disables the check.
+    pub fn with_authorize_grace_secs(mut self, secs: u64) -> Self {
+        self.authorize_grace_secs = secs;
+        self
+    }
+
+    /// Returns how long a SV1 miner may take to authorize after subscribing, if it is limited.
+    pub fn authorize_grace(&self) -> Option<Duration> {
+        (self.authorize_grace_secs > 0).then(|| Duration::from_secs(self.authorize_grace_secs))
+    }
+
+    /// Returns the monitoring server bind address (if enabled)
+    pub fn monitoring_address(&self) -> Option<SocketAddr> {
+        self.monitoring_address
+    }
+
+    /// Enables the monitoring server on `monitoring_address`.
+    pub fn with_monitoring(
+        mut self,
+        monitoring_address: SocketAddr,
+        monitoring_cache_refresh_secs: u64,
+    ) -> Self {
+        self.monitoring_address = Some(monitoring_address);
+        self.monitoring_cache_refresh_secs = monitoring_cache_refresh_secs;
+        self
+    }
+
+    /// Returns the monitoring cache refresh interval in seconds.
+    pub fn monitoring_cache_refresh_secs(&self) -> u64 {
+        self.monitoring_cache_refresh_secs
+    }
+
+    /// Makes monitoring requests rebuild a snapshot older than `max_staleness_secs` seconds.
+    pub fn with_monitoring_on_demand_refresh(mut self, max_staleness_secs: u64) -> Self {
+        self.monitoring_on_demand_refresh_secs = Some(max_staleness_secs);
+        self
+    }
+
+    /// Returns the snapshot age past which monitoring requests rebuild it, if enabled.
+    pub fn monitoring_on_demand_refresh(&self) -> Option<Duration> {
+        self.monitoring_on_demand_refresh_secs
+            .map(Duration::from_secs)
+    }
+
+    /// Enables the failover admin action of the monitoring server, guarded by `admin_token`.
+    pub fn with_monitoring_admin_token(mut self, admin_token: String) -> Self {
+        self.monitoring_admin_token = Some(admin_token);
+        self
+    }
+
+    /// Returns the bearer token of the failover admin action, `None` when it is disabled.
+    pub fn monitoring_admin_token(&self) -> Option<&str> {
+        self.monitoring_admin_token.as_deref()
+    }
+
+    pub fn set_log_dir(&mut self, log_dir: Option<PathBuf>) {
+        if let Some(dir) = log_dir {
+            self.log_file = Some(dir);
+        }
+    }
+    pub fn log_dir(&self) -> Option<&Path> {
+        self.log_file.as_deref()
+    }
+
+    /// Checks the values the translator cannot run with, at startup and on every reload.
+    pub fn validate(&self) -> Result<(), TproxyErrorKind> {
+        let invalid = |reason: String| Err(TproxyErrorKind::InvalidConfig(reason));
+        let difficulty_config = &self.downstream_difficulty_config;
+        let spm = difficulty_config.shares_per_minute;
+        if spm.is_nan() || spm <= 0.0 {
+            return invalid(format!("shares_per_minute must be positive, got {spm}"));
+        }
+        let hashrate = difficulty_config.min_individual_miner_hashrate;
+        if hashrate.is_nan() || hashrate <= 0.0 {
+            return invalid(format!(
+                "min_individual_miner_hashrate must be positive, got {hashrate}"
+            ));
+        }
+        if self.monitoring_cache_refresh_secs == 0 {
+            return invalid("monitoring_cache_refresh_secs must be at least 1".to_string());
+        }
+        if let Some(max_staleness) = self.monitoring_on_demand_refresh_secs {
+            if max_staleness <= self.monitoring_cache_refresh_secs {
+                return invalid(format!(
+                    "monitoring_on_demand_refresh_secs ({max_staleness}) must be above monitoring_cache_refresh_secs ({})",
+                    self.monitoring_cache_refresh_secs
+                ));
+            }
+        }
+        if self.on_all_upstreams_failed == AllUpstreamsFailedPolicy::Solo {
+            return invalid(
+                "on_all_upstreams_failed = \"solo\" is not supported by the translator, use \"shutdown\" or \"wait_and_retry\"".to_string(),
+            );
+        }
+        if self.aggregate_channels && !self.upstream_routes().is_empty() {
+            return invalid("upstream_routes requires aggregate_channels = false".to_string());
+        }
+        if !self.aggregate_channels && self.channel_affinity().is_some() {
+            return invalid("channel_affinity_secs requires aggregate_channels = true".to_string());
+        }
+        match self.max_downstreams_per_aggregated_channel {
+            Some(0) => {
+                return invalid(
+                    "max_downstreams_per_aggregated_channel must be at least 1".to_string(),
+                );
+            }
+            Some(_) if !self.aggregate_channels => {
+                return invalid(
+                    "max_downstreams_per_aggregated_channel requires aggregate_channels = true"
+                        .to_string(),
+                );
+            }
+            _ => {}
+        }
+        if let Some(route) = self
+            .upstream_routes()
+            .iter()
+            .find(|route| route.upstream() >= self.upstreams.len())
+        {
+            return invalid(format!(
+                "upstream_routes refers to upstream {}, but only {} upstreams are configured",
+                route.upstream(),
+                self.upstreams.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Configuration settings for managing difficulty adjustments on the downstream connection.
+#[derive(Debug, Deserialize, Clone)]
+pub struct DownstreamDifficultyConfig {
+    /// The minimum hashrate expected from an individual miner on the downstream connection.
+    pub min_individual_miner_hashrate: Hashrate,
+    /// The target number of shares per minute for difficulty adjustment.
+    pub shares_per_minute: SharesPerMinute,
+    /// Whether to enable variable difficulty adjustment mechanism.
+    /// If false, difficulty will be managed by upstream (useful with JDC).
+    pub enable_vardiff: bool,
+    /// Interval in seconds for sending keepalive jobs to downstream miners.
+    /// The translator will send periodic mining.notify messages with updated time
+    /// to prevent SV1 miners from timing out when the upstream doesn't send new jobs
+    /// frequently enough (e.g., due to low Bitcoin mempool activity).
+    /// Set to 0 to disable keepalive jobs.
+    pub job_keepalive_interval_secs: u16,
+    /// Grid the difficulty sent to the miners in `mining.set_difficulty` is snapped to.
+    #[serde(default)]
+    pub difficulty_quantization: DifficultyQuantization,
+    /// Seconds a newly connected miner is held at its initial difficulty before vardiff starts
+    /// adjusting it, giving the miner time to ramp up. 0 disables the warmup.
+    #[serde(default)]
+    pub vardiff_warmup_secs: u64,
+}
+
+impl DownstreamDifficultyConfig {
+    /// Creates a new `DownstreamDifficultyConfig` instance.
+    pub fn new(
+        min_individual_miner_hashrate: Hashrate,
+        shares_per_minute: SharesPerMinute,
+        enable_vardiff: bool,
+        job_keepalive_interval_secs: u16,
+    ) -> Self {
+        Self {
+            min_individual_miner_hashrate,
+            shares_per_minute,
+            enable_vardiff,
+            job_keepalive_interval_secs,
+            difficulty_quantization: DifficultyQuantization::default(),
+            vardiff_warmup_secs: 0,
+        }
+    }
+
+    /// Sets the grid the difficulty sent to the miners is snapped to.
+    pub fn with_difficulty_quantization(mut self, quantization: DifficultyQuantization) -> Self {
+        self.difficulty_quantization = quantization;
+        self
+    }
+
+    /// Sets how long a newly connected miner is held at its initial difficulty, 0 disabling it.
+    pub fn with_vardiff_warmup_secs(mut self, vardiff_warmup_secs: u64) -> Self {
+        self.vardiff_warmup_secs = vardiff_warmup_secs;
+        self
+    }
+
+    /// Returns how long a newly connected miner is held at its initial difficulty.
+    pub fn vardiff_warmup(&self) -> Duration {
+        Duration::from_secs(self.vardiff_warmup_secs)
+    }
+}
+
+/// Grid the difficulty of a `mining.set_difficulty` is snapped to, configured as `"none"`,
+/// `"power_of_two"` or a step value.
+///
+/// The difficulty is always rounded up, so the miner never works on an easier target than the
+/// channel requires.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(try_from = "DifficultyQuantizationConfig")]
+pub enum DifficultyQuantization {
+    /// Send the difficulty of the target unchanged.
+    #[default]
+    None,
+    /// Round the difficulty up to the next power of two.
+    PowerOfTwo,
+    /// Round the difficulty up to the next multiple of the step.
+    Step(f64),
+}
+
+impl DifficultyQuantization {
+    /// Snaps `difficulty` to the grid, never below it.
+    pub fn quantize(&self, difficulty: f64) -> f64 {
+        if !difficulty.is_finite() || difficulty <= 0.0 {
+            return difficulty;
+        }
+        let (snapped, next) = match *self {
+            DifficultyQuantization::None => return difficulty,
+            DifficultyQuantization::PowerOfTwo => {
+                let snapped = difficulty.log2().ceil().exp2();
+                (snapped, snapped * 2.0)
+            }
+            DifficultyQuantization::Step(step) => {
+                let snapped = (difficulty / step).ceil() * step;
+                (snapped, snapped + step)
+            }
+        };
+        // rounding errors must not make the difficulty easier
+        if snapped < difficulty {
+            next
+        } else {
+            snapped
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DifficultyQuantizationConfig {
+    Mode(String),
+    Step(f64),
+}
+
+impl TryFrom<DifficultyQuantizationConfig> for DifficultyQuantization {
+    type Error = String;
+
+    fn try_from(config: DifficultyQuantizationConfig) -> Result<Self, Self::Error> {
+        match config {
+            DifficultyQuantizationConfig::Mode(mode) => match mode.as_str() {
+                "none" => Ok(DifficultyQuantization::None),
+                "power_of_two" => Ok(DifficultyQuantization::PowerOfTwo),
+                _ => Err(format!(
+                    "invalid difficulty_quantization `{mode}`, expected `none`, `power_of_two` \
+                     or a step value"
+                )),
+            },
+            DifficultyQuantizationConfig::Step(step) if step.is_finite() && step > 0.0 => {
+                Ok(DifficultyQuantization::Step(step))
+            }
+            DifficultyQuantizationConfig::Step(step) => Err(format!(
+                "difficulty_quantization step must be a positive number, got {step}"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn create_test_upstream() -> Upstream {
+        // Use a valid base58-encoded public key from the key-utils test cases
+        let pubkey_str = "9bDuixKmZqAJnrmP746n8zU1wyAQRrus7th9dxnkPg6RzQvCnan";
+        let pubkey = Secp256k1PublicKey::from_str(pubkey_str).unwrap();
+        Upstream::new("127.0.0.1".to_string(), 4444, pubkey)
+    }
+
+    fn create_test_difficulty_config() -> DownstreamDifficultyConfig {
+        DownstreamDifficultyConfig::new(100.0, 5.0, true, 60)
+    }
+
+    #[test]
+    fn test_upstream_creation() {
+        let upstream = create_test_upstream();
+        assert_eq!(upstream.address, "127.0.0.1");
+        assert_eq!(upstream.port, 4444);
+    }
+
+    #[test]
+    fn test_upstream_label() {
+        let upstream = create_test_upstream();
+        assert_eq!(upstream.label(), "127.0.0.1:4444");
+        assert_eq!(upstream.with_name("primary".to_string()).label(), "primary");
+    }
+
+    #[test]
+    fn test_downstream_difficulty_config_creation() {
+        let config = create_test_difficulty_config();
+        assert_eq!(config.min_individual_miner_hashrate, 100.0);
+        assert_eq!(config.shares_per_minute, 5.0);
+        assert!(config.enable_vardiff);
+        assert_eq!(config.difficulty_quantization, DifficultyQuantization::None);
+        assert_eq!(config.vardiff_warmup(), Duration::ZERO);
+        let config = config.with_vardiff_warmup_secs(120);
+        assert_eq!(config.vardiff_warmup(), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn test_difficulty_quantization() {
+        let none = DifficultyQuantization::None;
+        assert_eq!(none.quantize(1000.5), 1000.5);
+        assert_eq!(none.quantize(0.001), 0.001);
+
+        let power_of_two = DifficultyQuantization::PowerOfTwo;
+        assert_eq!(power_of_two.quantize(1000.5), 1024.0);
+        assert_eq!(power_of_two.quantize(1024.0), 1024.0);
+        assert_eq!(power_of_two.quantize(1025.0), 2048.0);
+        assert_eq!(power_of_two.quantize(0.3), 0.5);
+
+        let step = DifficultyQuantization::Step(500.0);
+        assert_eq!(step.quantize(1000.5), 1500.0);
+        assert_eq!(step.quantize(1000.0), 1000.0);
+        assert_eq!(step.quantize(0.3), 500.0);
+        assert!(DifficultyQuantization::Step(0.1).quantize(0.3) >= 0.3);
+    }
+
+    #[test]
+    fn test_translator_config_creation() {
+        let upstreams = vec![create_test_upstream()];
+        let difficulty_config = create_test_difficulty_config();
+
+        let config = TranslatorConfig::new(
+            upstreams,
+            "0.0.0.0".to_string(),
+            3333,
+            difficulty_config,
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            true,
+            vec![],
+            vec![],
+        );
+
+        assert_eq!(config.upstreams.len(), 1);
+        assert_eq!(config.downstream_address, "0.0.0.0");
+        assert_eq!(config.downstream_port, 3333);
+        assert_eq!(config.max_supported_version, 2);
+        assert_eq!(config.min_supported_version, 1);
+        assert_eq!(config.downstream_extranonce2_size, 4);
+        assert_eq!(config.user_identity, "test_user");
+        assert!(config.aggregate_channels);
+        assert!(config.supported_extensions.is_empty());
+        assert!(config.required_extensions.is_empty());
+        assert!(config.log_file.is_none());
+    }
+
+    #[test]
+    fn test_translator_config_log_dir() {
+        let upstreams = vec![create_test_upstream()];
+        let difficulty_config = create_test_difficulty_config();
+
+        let mut config = TranslatorConfig::new(
+            upstreams,
+            "0.0.0.0".to_string(),
+            3333,
+            difficulty_config,
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            false,
+            vec![],
+            vec![],
+        );
+
+        assert!(config.log_dir().is_none());
+
+        let log_path = PathBuf::from("/tmp/logs");
+        config.set_log_dir(Some(log_path.clone()));
+        assert_eq!(config.log_dir(), Some(log_path.as_path()));
+
+        config.set_log_dir(None);
+        assert_eq!(config.log_dir(), Some(log_path.as_path())); // Should remain unchanged
+    }
+
+    #[test]
+    fn test_multiple_upstreams() {
+        let upstream1 = create_test_upstream();
+        let mut upstream2 = create_test_upstream();
+        upstream2.address = "192.168.1.1".to_string();
+        upstream2.port = 5555;
+
+        let upstreams = vec![upstream1, upstream2];
+        let difficulty_config = create_test_difficulty_config();
+
+        let config = TranslatorConfig::new(
+            upstreams,
             "0.0.0.0".to_string(),
             3333,
             difficulty_config,
@@ -320,4 +1646,474 @@ mod tests {
         assert!(!config.downstream_difficulty_config.enable_vardiff);
         assert!(!config.aggregate_channels);
     }
+
+    #[test]
+    fn test_warm_standby_config() {
+        let upstreams = vec![create_test_upstream(), create_test_upstream()];
+        let config = TranslatorConfig::new(
+            upstreams,
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            true,
+            vec![],
+            vec![],
+        );
+
+        assert_eq!(config.warm_standby_upstream_index(), None);
+
+        let config = config.with_warm_standby(1);
+        assert_eq!(config.warm_standby_upstream_index(), Some(1));
+    }
+
+    #[test]
+    fn test_job_staleness_config() {
+        let config = TranslatorConfig::new(
+            vec![create_test_upstream()],
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            true,
+            vec![],
+            vec![],
+        );
+
+        assert!(config.job_staleness_timeout().is_none());
+        assert!(!config.job_staleness_fallback());
+
+        let config = config.with_job_staleness_watchdog(90, true);
+        assert_eq!(
+            config.job_staleness_timeout(),
+            Some(Duration::from_secs(90))
+        );
+        assert!(config.job_staleness_fallback());
+    }
+
+    #[test]
+    fn test_job_propagation_alarm_config() {
+        let config = TranslatorConfig::new(
+            vec![create_test_upstream()],
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            true,
+            vec![],
+            vec![],
+        );
+
+        assert_eq!(
+            config.job_propagation_alarm_threshold(),
+            Duration::from_secs(1)
+        );
+
+        let config = config.with_job_propagation_alarm(250);
+        assert_eq!(
+            config.job_propagation_alarm_threshold(),
+            Duration::from_millis(250)
+        );
+    }
+
+    #[test]
+    fn test_connection_limits_config() {
+        let config = TranslatorConfig::new(
+            vec![create_test_upstream()],
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            true,
+            vec![],
+            vec![],
+        );
+
+        assert_eq!(config.max_connections_per_ip(), 100);
+        assert_eq!(config.max_accepts_per_sec(), 100);
+
+        let config = config.with_connection_limits(4, 0);
+        assert_eq!(config.max_connections_per_ip(), 4);
+        assert_eq!(config.max_accepts_per_sec(), 0);
+    }
+
+    #[test]
+    fn test_downstream_extranonce2_size_floor_config() {
+        let config = TranslatorConfig::new(
+            vec![create_test_upstream()],
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            true,
+            vec![],
+            vec![],
+        );
+
+        assert_eq!(config.downstream_extranonce2_size_floor(), 2);
+
+        let config = config.with_downstream_extranonce2_size_floor(4);
+        assert_eq!(config.downstream_extranonce2_size_floor(), 4);
+    }
+
+    #[test]
+    fn test_max_retained_prevhashes_config() {
+        let config = TranslatorConfig::new(
+            vec![create_test_upstream()],
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            true,
+            vec![],
+            vec![],
+        );
+
+        assert_eq!(config.max_retained_prevhashes(), 10_000);
+
+        let config = config.with_max_retained_prevhashes(64);
+        assert_eq!(config.max_retained_prevhashes(), 64);
+    }
+
+    #[test]
+    fn test_forward_miner_extranonce2_size_config() {
+        let config = TranslatorConfig::new(
+            vec![create_test_upstream()],
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            true,
+            vec![],
+            vec![],
+        );
+
+        assert!(!config.forward_miner_extranonce2_size());
+
+        let config = config.with_forward_miner_extranonce2_size(true);
+        assert!(config.forward_miner_extranonce2_size());
+    }
+
+    #[test]
+    fn test_max_tasks_config() {
+        let config = TranslatorConfig::new(
+            vec![create_test_upstream()],
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            true,
+            vec![],
+            vec![],
+        );
+
+        assert_eq!(config.max_tasks(), None);
+
+        let config = config.with_max_tasks(5_000);
+        assert_eq!(config.max_tasks(), Some(5_000));
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let config = |difficulty_config| {
+            TranslatorConfig::new(
+                vec![create_test_upstream()],
+                "0.0.0.0".to_string(),
+                3333,
+                difficulty_config,
+                2,
+                1,
+                4,
+                "test_user".to_string(),
+                true,
+                vec![],
+                vec![],
+            )
+        };
+
+        assert!(config(create_test_difficulty_config()).validate().is_ok());
+        assert!(matches!(
+            config(DownstreamDifficultyConfig::new(100.0, 0.0, true, 60)).validate(),
+            Err(TproxyErrorKind::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            config(DownstreamDifficultyConfig::new(0.0, 5.0, true, 60)).validate(),
+            Err(TproxyErrorKind::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            config(create_test_difficulty_config())
+                .with_monitoring("127.0.0.1:9090".parse().unwrap(), 0)
+                .validate(),
+            Err(TproxyErrorKind::InvalidConfig(_))
+        ));
+        // the on-demand refresh must only kick in once the periodic refresh is late
+        assert!(matches!(
+            config(create_test_difficulty_config())
+                .with_monitoring("127.0.0.1:9090".parse().unwrap(), 15)
+                .with_monitoring_on_demand_refresh(15)
+                .validate(),
+            Err(TproxyErrorKind::InvalidConfig(_))
+        ));
+        assert!(config(create_test_difficulty_config())
+            .with_monitoring("127.0.0.1:9090".parse().unwrap(), 15)
+            .with_monitoring_on_demand_refresh(60)
+            .validate()
+            .is_ok());
+        assert!(matches!(
+            config(create_test_difficulty_config())
+                .with_max_downstreams_per_aggregated_channel(0)
+                .validate(),
+            Err(TproxyErrorKind::InvalidConfig(_))
+        ));
+        assert!(config(create_test_difficulty_config())
+            .with_max_downstreams_per_aggregated_channel(100)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_update_channel_error_action_config() {
+        let config = TranslatorConfig::new(
+            vec![create_test_upstream()],
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            true,
+            vec![],
+            vec![],
+        );
+
+        assert_eq!(
+            config.update_channel_error_action(),
+            UpdateChannelErrorAction::RevertAndRetry
+        );
+
+        let config = config.with_update_channel_error_action(UpdateChannelErrorAction::Ignore);
+        assert_eq!(
+            config.update_channel_error_action(),
+            UpdateChannelErrorAction::Ignore
+        );
+
+        assert_eq!(
+            config.setup_connection_flags_policy(),
+            SetupConnectionFlagsPolicy::Warn
+        );
+        let config = config.with_setup_connection_flags_policy(SetupConnectionFlagsPolicy::Strict);
+        assert_eq!(
+            config.setup_connection_flags_policy(),
+            SetupConnectionFlagsPolicy::Strict
+        );
+
+        assert_eq!(
+            config.open_channel_failure_action(),
+            OpenChannelFailureAction::Disconnect
+        );
+        assert_eq!(config.open_channel_retries(), 3);
+        let config = config.with_open_channel_failure_action(OpenChannelFailureAction::Retry, 5);
+        assert_eq!(
+            config.open_channel_failure_action(),
+            OpenChannelFailureAction::Retry
+        );
+        assert_eq!(config.open_channel_retries(), 5);
+    }
+
+    #[test]
+    fn test_max_clock_skew_config() {
+        let config = TranslatorConfig::new(
+            vec![create_test_upstream()],
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            true,
+            vec![],
+            vec![],
+        );
+
+        assert_eq!(config.max_clock_skew(), Duration::from_secs(600));
+        assert!(!config.refuse_clock_skew());
+
+        let config = config.with_max_clock_skew(60, true);
+        assert_eq!(config.max_clock_skew(), Duration::from_secs(60));
+        assert!(config.refuse_clock_skew());
+    }
+
+    #[test]
+    fn test_on_all_upstreams_failed_config() {
+        let config = TranslatorConfig::new(
+            vec![create_test_upstream()],
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            true,
+            vec![],
+            vec![],
+        );
+
+        assert_eq!(
+            config.on_all_upstreams_failed(),
+            AllUpstreamsFailedPolicy::Shutdown
+        );
+        assert_eq!(config.upstream_retry_interval(), Duration::from_secs(30));
+
+        let config = config.with_on_all_upstreams_failed(AllUpstreamsFailedPolicy::WaitAndRetry, 5);
+        assert_eq!(
+            config.on_all_upstreams_failed(),
+            AllUpstreamsFailedPolicy::WaitAndRetry
+        );
+        assert_eq!(config.upstream_retry_interval(), Duration::from_secs(5));
+
+        assert_eq!(config.upstream_max_lifetime(), None);
+        let config = config.with_upstream_max_lifetime_secs(3600);
+        assert_eq!(
+            config.upstream_max_lifetime(),
+            Some(Duration::from_secs(3600))
+        );
+
+        assert_eq!(config.idle_no_downstream(), None);
+        let config = config.with_idle_no_downstream_secs(300);
+        assert_eq!(config.idle_no_downstream(), Some(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn test_downstream_group_label() {
+        let config = TranslatorConfig::new(
+            vec![create_test_upstream()],
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            true,
+            vec![],
+            vec![],
+        );
+        assert_eq!(config.downstream_group_label("farm.rack1-s19-01"), None);
+
+        let config = config.with_downstream_groups(vec![
+            DownstreamGroupRule::new(r"\.rack(\d+)-", "rack$1".to_string()).unwrap(),
+            DownstreamGroupRule::new(r"s19", "s19".to_string()).unwrap(),
+        ]);
+        // the first matching rule wins
+        assert_eq!(
+            config.downstream_group_label("farm.rack1-s19-01"),
+            Some("rack1".to_string())
+        );
+        assert_eq!(
+            config.downstream_group_label("farm.s19-02"),
+            Some("s19".to_string())
+        );
+        assert_eq!(config.downstream_group_label("farm.m50-01"), None);
+        assert!(DownstreamGroupRule::new("rack(", "rack".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_routed_upstream_index() {
+        let config = TranslatorConfig::new(
+            vec![create_test_upstream(), create_test_upstream()],
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            false,
+            vec![],
+            vec![],
+        );
+        assert_eq!(config.routed_upstream_index("user.pool2"), None);
+
+        let config = config.with_upstream_routes(vec![
+            UpstreamRouteRule::new(r"\.pool1$", 0).unwrap(),
+            UpstreamRouteRule::new(r"\.pool\d$", 1).unwrap(),
+        ]);
+        // the first matching rule wins
+        assert_eq!(config.routed_upstream_index("user.pool1"), Some(0));
+        assert_eq!(config.routed_upstream_index("user.pool2"), Some(1));
+        assert_eq!(config.routed_upstream_index("user.rig01"), None);
+        assert!(UpstreamRouteRule::new("pool(", 1).is_err());
+    }
+
+    #[test]
+    fn test_render_user_identity() {
+        let config = TranslatorConfig::new(
+            vec![create_test_upstream()],
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            false,
+            vec![],
+            vec![],
+        );
+
+        // the default template keeps the `user.minerN` naming
+        assert!(!config.user_identity_template_uses_worker());
+        assert_eq!(
+            config.render_user_identity(Some("user.rig01"), 3).unwrap(),
+            "test_user.miner3"
+        );
+
+        let config = config.with_user_identity_template("{user}.{worker}-{id}".to_string());
+        assert!(config.user_identity_template_uses_worker());
+        assert_eq!(
+            config.render_user_identity(Some("user.rig01"), 3).unwrap(),
+            "test_user.rig01-3"
+        );
+        assert_eq!(
+            config.render_user_identity(Some("rig01"), 3).unwrap(),
+            "test_user.rig01-3"
+        );
+        assert_eq!(
+            config.render_user_identity(None, 3).unwrap(),
+            "test_user.miner3-3"
+        );
+
+        let long_worker = format!("user.{}", "w".repeat(MAX_USER_IDENTITY_LEN));
+        assert!(matches!(
+            config.render_user_identity(Some(&long_worker), 3),
+            Err(TproxyErrorKind::UserIdentityTooLong(_))
+        ));
+    }
 }
@@ -12,9 +12,11 @@ use ext_config::ConfigError;
 use std::{
     fmt::{self, Formatter},
     marker::PhantomData,
+    net::SocketAddr,
     sync::PoisonError,
 };
 use stratum_apps::{
+    monitoring::FailoverReason,
     stratum_core::{
         binary_sv2,
         channels_sv2::client::error::GroupChannelError,
@@ -24,9 +26,12 @@ use stratum_apps::{
         parsers_sv2::{self, ParserError, TlvError},
         sv1_api::server_to_client::SetDifficulty,
     },
-    utils::types::{
-        CanDisconnect, CanFallback, CanShutdown, ChannelId, DownstreamId, ExtensionType,
-        MessageType,
+    utils::{
+        coinbase_split::CoinbaseSplitError,
+        types::{
+            CanDisconnect, CanFallback, CanShutdown, ChannelId, DownstreamId, ExtensionType,
+            MessageType,
+        },
     },
 };
 use tokio::sync::broadcast;
@@ -142,6 +147,8 @@ pub enum TproxyErrorKind {
     BadSerdeJson(serde_json::Error),
     /// Errors on bad `config` TOML deserialize.
     BadConfigDeserialize(ConfigError),
+    /// Configuration values that cannot be run with, described by a string.
+    InvalidConfig(String),
     /// Errors from `binary_sv2` crate.
     BinarySv2(binary_sv2::Error),
     /// Errors on bad noise handshake.
@@ -202,10 +209,76 @@ pub enum TproxyErrorKind {
     FailedToAddChannelIdToGroupChannel(GroupChannelError),
     /// Aggregated channel was closed
     AggregatedChannelClosed,
+    /// Upstream closed the extended channel serving a downstream
+    ChannelClosedByUpstream(ChannelId),
+    /// No new job or prevhash received on an upstream channel within the staleness timeout
+    StaleUpstreamJobs(ChannelId),
+    /// Upstream selected a protocol version outside the supported range
+    UnsupportedProtocolVersion(u16),
+    /// Upstream set `SetupConnectionSuccess` flags the translator cannot work with
+    IncompatibleSetupConnectionFlags { requested: u32, success: u32 },
+    /// Rendered user identity exceeds the maximum length
+    UserIdentityTooLong(String),
+    /// The channel opened for a downstream has a smaller extranonce2 than the miner suggested
+    /// (suggested size, granted size)
+    Extranonce2SizeNotGranted(usize, usize),
+    /// The channel of a downstream could not be opened (attempts made)
+    DownstreamChannelNotOpened(u32),
+    /// Time of the upstream jobs is ahead of the local clock by more than the accepted skew
+    /// (job time minus local time, in seconds)
+    ClockSkew(i64),
+    /// An operator requested a failover to the next upstream through the monitoring server
+    ManualFailover,
+    /// The upstream connection reached `upstream_max_lifetime_secs` and is rotated to the next
+    /// upstream
+    UpstreamMaxLifetime,
+    /// Messages queued towards a downstream stayed above the slow consumer threshold (queue
+    /// depth)
+    SlowConsumer(usize),
+    /// The upstream sent an extranonce prefix the channel cannot use (channel id)
+    InvalidExtranoncePrefix(ChannelId),
+    /// The extranonce1 of a downstream changed, or has to change for its worker to be rerouted,
+    /// and its miner cannot be told with `mining.set_extranonce`
+    ExtranonceChangeNotSupported,
+    /// Reading or writing a frame on the upstream connection exceeded its timeout
+    UpstreamIoTimeout,
+    /// A downstream subscribed and did not send `mining.authorize` within the grace period
+    AuthorizeGraceExpired,
+    /// The upstream redirected the translator to another endpoint with `Reconnect`
+    UpstreamReconnect(SocketAddr),
+    /// The coinbase of an upstream job is not split where the extranonce of the channel goes
+    InvalidCoinbaseSplit(CoinbaseSplitError),
+    /// A downstream rejoined the channel of a worker and authorized another one
+    /// (worker of the channel, worker authorized)
+    RejoinedWorkerMismatch(String, String),
 }
 
 impl std::error::Error for TproxyErrorKind {}
 
+impl TproxyErrorKind {
+    /// Returns the failover reason reported to monitoring when this error makes the translator
+    /// fall back to the next upstream.
+    pub fn failover_reason(&self) -> FailoverReason {
+        use TproxyErrorKind::*;
+        match self {
+            SetupConnectionError => FailoverReason::SetupConnectionError,
+            OpenMiningChannelError => FailoverReason::OpenMiningChannelError,
+            AggregatedChannelClosed | ChannelClosedByUpstream(_) => FailoverReason::CloseChannel,
+            StaleUpstreamJobs(_) | UpstreamIoTimeout => FailoverReason::Timeout,
+            ManualFailover => FailoverReason::Manual,
+            UpstreamMaxLifetime => FailoverReason::Rotation,
+            UpstreamReconnect(_) => FailoverReason::Reconnect,
+            NetworkHelpersError(_)
+            | CodecNoise(_)
+            | FramingSv2(_)
+            | Io(_)
+            | ChannelErrorReceiver(_)
+            | ChannelErrorSender => FailoverReason::ConnectionLost,
+            _ => FailoverReason::Other,
+        }
+    }
+}
+
 impl fmt::Display for TproxyErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use TproxyErrorKind::*;
@@ -214,6 +287,7 @@ impl fmt::Display for TproxyErrorKind {
             BadCliArgs => write!(f, "Bad CLI arg input"),
             BadSerdeJson(ref e) => write!(f, "Bad serde json: `{e:?}`"),
             BadConfigDeserialize(ref e) => write!(f, "Bad `config` TOML deserialize: `{e:?}`"),
+            InvalidConfig(ref e) => write!(f, "Invalid configuration: {e}"),
             BinarySv2(ref e) => write!(f, "Binary SV2 error: `{e:?}`"),
             CodecNoise(ref e) => write!(f, "Noise error: `{e:?}"),
             FramingSv2(ref e) => write!(f, "Framing SV2 error: `{e:?}`"),
@@ -278,6 +352,77 @@ impl fmt::Display for TproxyErrorKind {
                 write!(f, "Failed to add channel id to group channel: {e:?}")
             }
             AggregatedChannelClosed => write!(f, "Aggregated channel was closed"),
+            UnsupportedProtocolVersion(version) => {
+                write!(
+                    f,
+                    "Upstream selected unsupported protocol version {version}"
+                )
+            }
+            IncompatibleSetupConnectionFlags { requested, success } => {
+                write!(
+                    f,
+                    "Upstream setup flags {success:#b} are incompatible with the requested flags \
+                     {requested:#b}"
+                )
+            }
+            ChannelClosedByUpstream(channel_id) => {
+                write!(f, "Channel {channel_id} was closed by upstream")
+            }
+            StaleUpstreamJobs(channel_id) => {
+                write!(
+                    f,
+                    "No new jobs received from upstream on channel {channel_id}"
+                )
+            }
+            UserIdentityTooLong(user_identity) => {
+                write!(f, "User identity exceeds 255 bytes: {user_identity}")
+            }
+            Extranonce2SizeNotGranted(suggested, granted) => {
+                write!(
+                    f,
+                    "Miner expects an extranonce2 size of {suggested} bytes, but only {granted} were granted"
+                )
+            }
+            DownstreamChannelNotOpened(attempts) => {
+                write!(
+                    f,
+                    "Upstream did not open a mining channel for the miner after {attempts} attempts"
+                )
+            }
+            ClockSkew(skew) => {
+                write!(f, "Upstream job time is {skew}s ahead of the local clock")
+            }
+            ManualFailover => write!(f, "Failover requested by an operator"),
+            UpstreamMaxLifetime => {
+                write!(f, "Upstream connection reached its maximum lifetime")
+            }
+            SlowConsumer(queue_depth) => {
+                write!(
+                    f,
+                    "Downstream is not keeping up, {queue_depth} messages queued"
+                )
+            }
+            InvalidExtranoncePrefix(channel_id) => {
+                write!(f, "Invalid extranonce prefix for channel {channel_id}")
+            }
+            ExtranonceChangeNotSupported => {
+                write!(f, "Miner cannot be sent mining.set_extranonce")
+            }
+            InvalidCoinbaseSplit(ref e) => write!(f, "Invalid coinbase split of upstream job: {e}"),
+            UpstreamIoTimeout => write!(f, "Upstream connection I/O timed out"),
+            UpstreamReconnect(addr) => write!(f, "Upstream asked to reconnect to {addr}"),
+            AuthorizeGraceExpired => {
+                write!(
+                    f,
+                    "Miner did not send mining.authorize within the grace period"
+                )
+            }
+            RejoinedWorkerMismatch(retained, authorized) => {
+                write!(
+                    f,
+                    "Miner rejoined the channel of worker {retained} but authorized {authorized}"
+                )
+            }
         }
     }
 }
@@ -0,0 +1,167 @@
+//! ## Hot-Reloadable Configuration
+//!
+//! Holds the configuration fields that can be changed on a running translator, without
+//! reconnecting its upstream or its miners:
+//! - `downstream_difficulty_config.min_individual_miner_hashrate`, the starting hashrate of miners
+//!   connecting after the reload
+//! - `downstream_difficulty_config.shares_per_minute`, used from the next vardiff cycle on
+//! - `downstream_difficulty_config.job_keepalive_interval_secs`, where 0 pauses keepalive jobs
+//! - `monitoring_cache_refresh_secs`, used from the next monitoring snapshot refresh on
+//!
+//! Every other field of a reloaded configuration is ignored until the translator restarts.
+use std::sync::{
+    atomic::{AtomicU16, AtomicU32, Ordering},
+    Arc,
+};
+
+use stratum_apps::{
+    custom_mutex::Mutex,
+    monitoring::SnapshotCache,
+    utils::types::{Hashrate, SharesPerMinute},
+};
+use tokio::sync::Notify;
+use tracing::info;
+
+use crate::{config::TranslatorConfig, error::TproxyErrorKind};
+
+/// Current values of the hot-reloadable configuration fields.
+pub struct HotReloadableConfig {
+    min_individual_miner_hashrate: AtomicU32,
+    shares_per_minute: AtomicU32,
+    job_keepalive_interval_secs: AtomicU16,
+    monitoring_cache: Mutex<Option<Arc<SnapshotCache>>>,
+    reloaded: Notify,
+}
+
+impl std::fmt::Debug for HotReloadableConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HotReloadableConfig")
+            .field(
+                "min_individual_miner_hashrate",
+                &self.min_individual_miner_hashrate(),
+            )
+            .field("shares_per_minute", &self.shares_per_minute())
+            .field(
+                "job_keepalive_interval_secs",
+                &self.job_keepalive_interval_secs(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl HotReloadableConfig {
+    /// Creates the holder with the values of `config`.
+    pub fn new(config: &TranslatorConfig) -> Self {
+        let difficulty_config = &config.downstream_difficulty_config;
+        Self {
+            min_individual_miner_hashrate: AtomicU32::new(
+                difficulty_config.min_individual_miner_hashrate.to_bits(),
+            ),
+            shares_per_minute: AtomicU32::new(difficulty_config.shares_per_minute.to_bits()),
+            job_keepalive_interval_secs: AtomicU16::new(
+                difficulty_config.job_keepalive_interval_secs,
+            ),
+            monitoring_cache: Mutex::new(None),
+            reloaded: Notify::new(),
+        }
+    }
+
+    /// Applies the hot-reloadable fields of `config` and wakes the loops waiting on a reload.
+    ///
+    /// Nothing is applied if `config` fails [`TranslatorConfig::validate`].
+    pub fn reload(&self, config: &TranslatorConfig) -> Result<(), TproxyErrorKind> {
+        config.validate()?;
+        let difficulty_config = &config.downstream_difficulty_config;
+        self.min_individual_miner_hashrate.store(
+            difficulty_config.min_individual_miner_hashrate.to_bits(),
+            Ordering::Relaxed,
+        );
+        self.shares_per_minute.store(
+            difficulty_config.shares_per_minute.to_bits(),
+            Ordering::Relaxed,
+        );
+        self.job_keepalive_interval_secs.store(
+            difficulty_config.job_keepalive_interval_secs,
+            Ordering::Relaxed,
+        );
+        self.monitoring_cache.super_safe_lock(|cache| {
+            if let Some(cache) = cache {
+                cache.set_refresh_interval(std::time::Duration::from_secs(
+                    config.monitoring_cache_refresh_secs(),
+                ));
+            }
+        });
+        info!(
+            min_individual_miner_hashrate = difficulty_config.min_individual_miner_hashrate,
+            shares_per_minute = difficulty_config.shares_per_minute,
+            job_keepalive_interval_secs = difficulty_config.job_keepalive_interval_secs,
+            monitoring_cache_refresh_secs = config.monitoring_cache_refresh_secs(),
+            "Reloaded configuration"
+        );
+        self.reloaded.notify_waiters();
+        Ok(())
+    }
+
+    /// Sets the snapshot cache of the monitoring server, whose refresh interval follows reloads.
+    pub(crate) fn set_monitoring_cache(&self, cache: Arc<SnapshotCache>) {
+        self.monitoring_cache
+            .super_safe_lock(|monitoring_cache| *monitoring_cache = Some(cache));
+    }
+
+    /// Returns the expected hashrate of a newly connected miner.
+    pub fn min_individual_miner_hashrate(&self) -> Hashrate {
+        Hashrate::from_bits(self.min_individual_miner_hashrate.load(Ordering::Relaxed))
+    }
+
+    /// Returns the share rate vardiff aims for.
+    pub fn shares_per_minute(&self) -> SharesPerMinute {
+        SharesPerMinute::from_bits(self.shares_per_minute.load(Ordering::Relaxed))
+    }
+
+    /// Returns the keepalive job interval, 0 when keepalive jobs are disabled.
+    pub fn job_keepalive_interval_secs(&self) -> u16 {
+        self.job_keepalive_interval_secs.load(Ordering::Relaxed)
+    }
+
+    /// Waits for the next reload.
+    pub(crate) async fn reloaded(&self) {
+        self.reloaded.notified().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DownstreamDifficultyConfig, Upstream};
+    use stratum_apps::key_utils::Secp256k1PublicKey;
+
+    fn config(shares_per_minute: SharesPerMinute) -> TranslatorConfig {
+        let pubkey: Secp256k1PublicKey = "9bDuixKmZqAJnrmP746n8zU1wyAQRrus7th9dxnkPg6RzQvCnan"
+            .parse()
+            .unwrap();
+        TranslatorConfig::new(
+            vec![Upstream::new("127.0.0.1".to_string(), 4444, pubkey)],
+            "0.0.0.0".to_string(),
+            3333,
+            DownstreamDifficultyConfig::new(100.0, shares_per_minute, true, 60),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            true,
+            vec![],
+            vec![],
+        )
+    }
+
+    #[test]
+    fn invalid_reload_keeps_the_current_values() {
+        let hot_config = HotReloadableConfig::new(&config(5.0));
+
+        assert!(hot_config.reload(&config(0.0)).is_err());
+        assert_eq!(hot_config.shares_per_minute(), 5.0);
+
+        hot_config.reload(&config(10.0)).unwrap();
+        assert_eq!(hot_config.shares_per_minute(), 10.0);
+    }
+}
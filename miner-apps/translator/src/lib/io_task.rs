@@ -1,8 +1,20 @@
-use std::sync::Arc;
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use async_channel::{Receiver, Sender};
 use stratum_apps::{
-    network_helpers::noise_stream::{NoiseTcpReadHalf, NoiseTcpWriteHalf},
+    network_helpers::{
+        frame_trace::{trace_inbound_frame, trace_outbound_frame},
+        message_counters::MessageCounters,
+        noise_stream::{NoiseTcpReadHalf, NoiseTcpWriteHalf},
+        Error,
+    },
     stratum_core::framing_sv2::framing::Frame,
     task_manager::TaskManager,
     utils::types::{Message, Sv2Frame},
@@ -12,6 +24,28 @@ use tracing::{error, trace, warn, Instrument as _};
 
 use crate::utils::ShutdownMessage;
 
+/// Longest time reading or writing a frame on the upstream connection may take. `None` leaves
+/// the operation unbounded, a stuck socket is then only detected by TCP keepalive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoTimeouts {
+    /// Longest time without receiving a frame.
+    pub read: Option<Duration>,
+    /// Longest time writing a single frame may take.
+    pub write: Option<Duration>,
+}
+
+// Runs `io`, giving up once it takes longer than `timeout`.
+async fn with_timeout<T>(timeout: Option<Duration>, io: impl Future<Output = T>) -> Option<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, io).await.ok(),
+        None => Some(io.await),
+    }
+}
+
+/// Spawns the tasks reading and writing the frames of a connection.
+///
+/// Returns a flag set when a read or a write exceeded its timeout in `io_timeouts`, before the
+/// connection is closed.
 #[cfg_attr(not(test), hotpath::measure)]
 #[track_caller]
 #[allow(clippy::too_many_arguments)]
@@ -21,13 +55,21 @@ pub fn spawn_io_tasks(
     mut writer: NoiseTcpWriteHalf<Message>,
     outbound_rx: Receiver<Sv2Frame>,
     inbound_tx: Sender<Sv2Frame>,
+    max_frame_size: usize,
+    frame_trace: bool,
+    message_counters: Arc<MessageCounters>,
+    io_timeouts: IoTimeouts,
     notify_shutdown: broadcast::Sender<ShutdownMessage>,
-) {
+) -> Arc<AtomicBool> {
+    let timed_out = Arc::new(AtomicBool::new(false));
     let caller = std::panic::Location::caller();
+    reader.set_max_frame_size(max_frame_size);
     let inbound_tx_clone = inbound_tx.clone();
     let outbound_rx_clone = outbound_rx.clone();
     {
         let mut shutdown_rx = notify_shutdown.subscribe();
+        let message_counters = message_counters.clone();
+        let timed_out = timed_out.clone();
         task_manager.spawn(
             async move {
                 trace!("Reader task started");
@@ -40,7 +82,13 @@ pub fn spawn_io_tasks(
                                 break;
                             }
                         }
-                        res = reader.read_frame() => {
+                        res = with_timeout(io_timeouts.read, reader.read_frame()) => {
+                            let Some(res) = res else {
+                                error!(timeout = ?io_timeouts.read, "No frame read within the read timeout, closing the connection");
+                                timed_out.store(true, Ordering::SeqCst);
+                                inbound_tx.close();
+                                break;
+                            };
                             match res {
                                 Ok(frame) => {
                                     match frame {
@@ -49,8 +97,12 @@ pub fn spawn_io_tasks(
                                             drop(frame);
                                             break;
                                         },
-                                        Frame::Sv2(sv2_frame) => {
+                                        Frame::Sv2(mut sv2_frame) => {
                                             trace!("Received inbound frame");
+                                            message_counters.count_inbound(&sv2_frame);
+                                            if frame_trace {
+                                                trace_inbound_frame(&mut sv2_frame);
+                                            }
                                             if let Err(e) = inbound_tx.send(sv2_frame).await {
                                                 inbound_tx.close();
                                                 error!(error=?e, "Failed to forward inbound frame");
@@ -59,6 +111,11 @@ pub fn spawn_io_tasks(
                                         },
                                     }
                                 }
+                                Err(Error::FrameTooLarge { size, max }) => {
+                                    error!(size, max, "Frame above the maximum frame size, closing the connection");
+                                    inbound_tx.close();
+                                    break;
+                                }
                                 Err(e) => {
                                     error!(error=?e, "Reader error");
                                     inbound_tx.close();
@@ -83,6 +140,7 @@ pub fn spawn_io_tasks(
 
     {
         let mut shutdown_rx = notify_shutdown.subscribe();
+        let timed_out = timed_out.clone();
 
         task_manager.spawn(
             async move {
@@ -100,10 +158,30 @@ pub fn spawn_io_tasks(
                             match res {
                                 Ok(frame) => {
                                     trace!("Sending outbound frame");
-                                    if let Err(e) = writer.write_frame(frame.into()).await {
-                                        error!(error=?e, "Writer error");
+                                    let frame = if frame_trace {
+                                        trace_outbound_frame(frame)
+                                    } else {
+                                        Some(frame)
+                                    };
+                                    let Some(frame) = frame else {
+                                        error!("Failed to serialize traced outbound frame");
                                         outbound_rx.close();
                                         break;
+                                    };
+                                    message_counters.count_outbound(&frame);
+                                    match with_timeout(io_timeouts.write, writer.write_frame(frame.into())).await {
+                                        Some(Ok(())) => {}
+                                        Some(Err(e)) => {
+                                            error!(error=?e, "Writer error");
+                                            outbound_rx.close();
+                                            break;
+                                        }
+                                        None => {
+                                            error!(timeout = ?io_timeouts.write, "Frame not written within the write timeout, closing the connection");
+                                            timed_out.store(true, Ordering::SeqCst);
+                                            outbound_rx.close();
+                                            break;
+                                        }
                                     }
                                 }
                                 Err(_) => {
@@ -127,4 +205,5 @@ pub fn spawn_io_tasks(
             )),
         );
     }
+    timed_out
 }
@@ -7,37 +7,60 @@
 //! applications (proxies or pool servers).
 //!
 //! The central component is the `TranslatorSv2` struct, which encapsulates the state and
-//! provides the `start` method as the main entry point for running the translator service.
+//! provides the `start` and `run_until` methods as the main entry points for running the
+//! translator service.
 //! It relies on several sub-modules (`config`, `downstream_sv1`, `upstream_sv2`, `proxy`, `status`,
 //! etc.) for specialized functionalities.
 #![allow(clippy::module_inception)]
 use async_channel::{unbounded, Receiver, Sender};
+use dashmap::DashMap;
 use std::{
+    future::Future,
     net::SocketAddr,
     sync::{Arc, OnceLock},
     time::Duration,
 };
 use stratum_apps::{
-    task_manager::TaskManager, utils::types::Sv2Frame, SHUTDOWN_BROADCAST_CAPACITY,
+    config_helpers::AllUpstreamsFailedPolicy,
+    network_helpers::message_counters::MessageCounters,
+    ready_signal::ReadySignal,
+    stratum_core::{
+        binary_sv2::Str0255,
+        mining_sv2::CloseChannel,
+        parsers_sv2::{AnyMessage, Mining},
+    },
+    task_manager::TaskManager,
+    utils::{
+        log_throttle::LogThrottle,
+        status_channel::next_status,
+        types::{ChannelId, Sv2Frame},
+    },
+    SHUTDOWN_BROADCAST_CAPACITY,
+};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time::Instant,
 };
-use tokio::sync::{broadcast, mpsc};
 use tracing::{debug, error, info, warn};
 
 pub use stratum_apps::stratum_core::sv1_api::server_to_client;
 
-use config::TranslatorConfig;
+use config::{SetupConnectionFlagsPolicy, TranslatorConfig};
 
 use crate::{
     error::TproxyErrorKind,
+    hot_reload::HotReloadableConfig,
+    monitoring::ManualFailover,
     status::{State, Status},
     sv1::sv1_server::sv1_server::Sv1Server,
-    sv2::{ChannelManager, Upstream},
+    sv2::{channel_manager::upstream_router::UpstreamRouter, ChannelManager, Upstream},
     utils::{ShutdownMessage, UpstreamEntry},
 };
 
 pub mod config;
 pub mod error;
-mod io_task;
+pub mod hot_reload;
+pub mod io_task;
 mod monitoring;
 pub mod status;
 pub mod sv1;
@@ -49,6 +72,10 @@ pub mod utils;
 #[derive(Clone, Debug)]
 pub struct TranslatorSv2 {
     config: TranslatorConfig,
+    hot_config: Arc<HotReloadableConfig>,
+    ready: ReadySignal,
+    message_counters: Arc<MessageCounters>,
+    log_throttle: Arc<LogThrottle>,
 }
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -58,15 +85,58 @@ impl TranslatorSv2 {
     /// Initializes the translator with the given configuration and sets up
     /// the reconnect wait time.
     pub fn new(config: TranslatorConfig) -> Self {
-        Self { config }
+        let hot_config = Arc::new(HotReloadableConfig::new(&config));
+        let log_throttle = Arc::new(LogThrottle::new(config.log_throttle_window()));
+        Self {
+            config,
+            hot_config,
+            ready: ReadySignal::new(),
+            message_counters: Arc::new(MessageCounters::new()),
+            log_throttle,
+        }
+    }
+
+    /// Returns a future resolving once the translator is serving, with an upstream connected
+    /// and the SV1 listener bound.
+    ///
+    /// Take it before [`Self::start`] or [`Self::run_until`], which consume the translator.
+    pub fn ready(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.ready.wait()
+    }
+
+    /// Applies the hot-reloadable fields of `config` to the running translator.
+    ///
+    /// Only the fields listed in [`hot_reload`] take effect, without reconnecting the upstream
+    /// or the miners. Every other field is ignored until the translator restarts. A `config`
+    /// failing [`TranslatorConfig::validate`] is rejected, keeping the current values.
+    pub fn reload_config(&self, config: &TranslatorConfig) -> Result<(), TproxyErrorKind> {
+        self.hot_config.reload(config)
     }
 
     /// Starts the translator.
     ///
     /// This method starts the main event loop, which handles connections,
-    /// protocol translation, job management, and status reporting.
+    /// protocol translation, job management, and status reporting. It runs until
+    /// Ctrl+C is received or a fatal status is reported.
     pub async fn start(self) {
+        self.run_until(tokio::signal::ctrl_c()).await
+    }
+
+    /// Runs the translator until `shutdown` resolves.
+    ///
+    /// Behaves like [`TranslatorSv2::start`], but additionally exits when the given future
+    /// completes, sending `ShutdownMessage::ShutdownAll` to every subsystem and waiting for
+    /// them to shut down gracefully.
+    pub async fn run_until<F>(self, shutdown: F)
+    where
+        F: Future,
+    {
         info!("Starting Translator Proxy...");
+        if let Err(e) = self.config.validate() {
+            error!("{e}");
+            return;
+        }
+        tokio::pin!(shutdown);
         // only initialized once
         TPROXY_MODE
             .set(self.config.aggregate_channels.into())
@@ -78,7 +148,7 @@ impl TranslatorSv2 {
         let (notify_shutdown, _) =
             broadcast::channel::<ShutdownMessage>(SHUTDOWN_BROADCAST_CAPACITY);
         let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel::<()>(1);
-        let task_manager = Arc::new(TaskManager::new());
+        let task_manager = Arc::new(TaskManager::new().with_max_tasks(self.config.max_tasks()));
         let (status_sender, status_receiver) = async_channel::unbounded::<Status>();
 
         let (channel_manager_to_upstream_sender, channel_manager_to_upstream_receiver) =
@@ -100,6 +170,9 @@ impl TranslatorSv2 {
                 addr: SocketAddr::new(u.address.parse().unwrap(), u.port),
                 authority_pubkey: u.authority_pubkey,
                 tried_or_flagged: false,
+                label: u.label(),
+                negotiated_extensions: None,
+                reconnect_allowlist: u.reconnect_allowlist.clone(),
             })
             .collect::<Vec<_>>();
 
@@ -108,17 +181,28 @@ impl TranslatorSv2 {
             self.config.downstream_port,
         );
 
-        let sv1_server = Arc::new(Sv1Server::new(
-            downstream_addr,
-            channel_manager_to_sv1_server_receiver,
-            sv1_server_to_channel_manager_sender,
-            self.config.clone(),
+        let upstream_router = Arc::new(UpstreamRouter::default().with_extensions(
+            self.config.supported_extensions.clone(),
+            self.config.required_extensions.clone(),
         ));
+        let aggregated_channel_ids = Arc::new(DashMap::new());
+
+        let sv1_server = Arc::new(
+            Sv1Server::new(
+                downstream_addr,
+                channel_manager_to_sv1_server_receiver,
+                sv1_server_to_channel_manager_sender,
+                self.config.clone(),
+            )
+            .with_hot_config(self.hot_config.clone())
+            .with_upstream_router(upstream_router.clone())
+            .with_aggregated_channel_ids(aggregated_channel_ids.clone()),
+        );
 
         info!("Initializing upstream connection...");
 
-        if let Err(e) = self
-            .initialize_upstream(
+        let active_upstream = tokio::select! {
+            result = self.connect_upstream(
                 &mut upstream_addresses,
                 channel_manager_to_upstream_receiver.clone(),
                 upstream_to_channel_manager_sender.clone(),
@@ -128,22 +212,97 @@ impl TranslatorSv2 {
                 task_manager.clone(),
                 sv1_server.clone(),
                 self.config.required_extensions.clone(),
+            ) => match result {
+                Ok(label) => label,
+                Err(e) => {
+                    error!("Failed to initialize any upstream connection: {e:?}");
+                    return;
+                }
+            },
+            _ = &mut shutdown => {
+                info!("Shutdown signal received while connecting to an upstream");
+                return;
+            }
+        };
+
+        let mut warm_standby = tokio::select! {
+            standby = self.connect_warm_standby(
+                &mut upstream_addresses,
+                &active_upstream,
+                channel_manager_to_upstream_receiver.clone(),
+                upstream_to_channel_manager_sender.clone(),
+                notify_shutdown.clone(),
+                shutdown_complete_tx.clone(),
+                task_manager.clone(),
+            ) => standby,
+            _ = &mut shutdown => {
+                // the active upstream already runs, so its tasks are stopped
+                info!("Shutdown signal received while connecting the warm standby");
+                let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
+                task_manager.join_all().await;
+                return;
+            }
+        };
+
+        let channel_manager: Arc<ChannelManager> = Arc::new(
+            ChannelManager::new(
+                channel_manager_to_upstream_sender,
+                upstream_to_channel_manager_receiver,
+                channel_manager_to_sv1_server_sender.clone(),
+                sv1_server_to_channel_manager_receiver,
+                status_sender.clone(),
+                self.config.supported_extensions.clone(),
+                self.config.required_extensions.clone(),
+                self.config.job_staleness_timeout(),
+                self.config.job_staleness_fallback(),
+                self.config.downstream_extranonce2_size_floor() as usize,
+                self.config.update_channel_error_action(),
             )
-            .await
-        {
-            error!("Failed to initialize any upstream connection: {e:?}");
-            return;
-        }
+            .with_clock_skew_check(
+                self.config.max_clock_skew(),
+                self.config.refuse_clock_skew(),
+            )
+            .with_extranonce_usage_warning_threshold(
+                self.config.extranonce_usage_warning_threshold(),
+            )
+            .with_upstream_router(upstream_router.clone())
+            .with_log_throttle(self.log_throttle.clone())
+            .with_aggregated_channels(
+                aggregated_channel_ids,
+                self.config.max_downstreams_per_aggregated_channel(),
+            ),
+        );
+        set_primary_upstream(&upstream_router, &upstream_addresses, &active_upstream);
+        channel_manager.set_active_upstream(active_upstream);
 
-        let channel_manager: Arc<ChannelManager> = Arc::new(ChannelManager::new(
-            channel_manager_to_upstream_sender,
-            upstream_to_channel_manager_receiver,
-            channel_manager_to_sv1_server_sender.clone(),
-            sv1_server_to_channel_manager_receiver,
-            status_sender.clone(),
-            self.config.supported_extensions.clone(),
-            self.config.required_extensions.clone(),
-        ));
+        let mut routed_upstreams: Vec<usize> = self
+            .config
+            .upstream_routes()
+            .iter()
+            .map(|route| route.upstream())
+            .collect();
+        routed_upstreams.sort_unstable();
+        routed_upstreams.dedup();
+        for index in routed_upstreams {
+            let translator = self.clone();
+            let upstream_router = upstream_router.clone();
+            let upstream_to_channel_manager_sender = upstream_to_channel_manager_sender.clone();
+            let notify_shutdown = notify_shutdown.clone();
+            let shutdown_complete_tx = shutdown_complete_tx.clone();
+            let task_manager_clone = task_manager.clone();
+            task_manager.spawn(async move {
+                translator
+                    .run_routed_upstream(
+                        index,
+                        upstream_router,
+                        upstream_to_channel_manager_sender,
+                        notify_shutdown,
+                        shutdown_complete_tx,
+                        task_manager_clone,
+                    )
+                    .await;
+            });
+        }
 
         info!("Launching ChannelManager tasks...");
         channel_manager
@@ -156,6 +315,11 @@ impl TranslatorSv2 {
             )
             .await;
 
+        let manual_failover = Arc::new(ManualFailover::new(
+            channel_manager.clone(),
+            status_sender.clone(),
+        ));
+
         // Start monitoring server if configured
         if let Some(monitoring_addr) = self.config.monitoring_address() {
             info!(
@@ -163,7 +327,7 @@ impl TranslatorSv2 {
                 monitoring_addr
             );
 
-            let monitoring_server = stratum_apps::monitoring::MonitoringServer::new(
+            let mut monitoring_server = stratum_apps::monitoring::MonitoringServer::new(
                 monitoring_addr,
                 Some(channel_manager.clone()), // SV2 channels opened with servers
                 None,                          /* no SV2 channels opened with clients (SV1
@@ -172,7 +336,23 @@ impl TranslatorSv2 {
             )
             .expect("Failed to initialize monitoring server")
             .with_sv1_monitoring(sv1_server.clone()) // SV1 client connections
-            .expect("Failed to add SV1 monitoring");
+            .expect("Failed to add SV1 monitoring")
+            .with_connections_monitoring(sv1_server.connection_limiter.clone())
+            .expect("Failed to add connections monitoring")
+            .with_tasks_monitoring(task_manager.clone())
+            .expect("Failed to add tasks monitoring")
+            .with_messages_monitoring(self.message_counters.clone())
+            .expect("Failed to add messages monitoring")
+            .with_bind_retry(self.config.bind_retry());
+            if let Some(max_staleness) = self.config.monitoring_on_demand_refresh() {
+                monitoring_server = monitoring_server.with_on_demand_refresh(max_staleness);
+            }
+            if let Some(admin_token) = self.config.monitoring_admin_token() {
+                monitoring_server = monitoring_server
+                    .with_failover_control(manual_failover.clone(), admin_token.to_string());
+            }
+            self.hot_config
+                .set_monitoring_cache(monitoring_server.snapshot_cache());
 
             // Create shutdown signal that waits for ShutdownAll
             let mut notify_shutdown_monitoring = notify_shutdown.subscribe();
@@ -193,15 +373,111 @@ impl TranslatorSv2 {
             });
         }
 
+        // Summaries of throttled warnings are logged on a timer, so a burst that stops is still
+        // reported
+        let log_throttle = self.log_throttle.clone();
+        let mut notify_shutdown_log_throttle = notify_shutdown.subscribe();
+        task_manager.spawn(async move {
+            log_throttle
+                .run(async move {
+                    loop {
+                        match notify_shutdown_log_throttle.recv().await {
+                            Ok(ShutdownMessage::ShutdownAll) => break,
+                            Ok(_) => continue,
+                            Err(_) => break,
+                        }
+                    }
+                })
+                .await;
+        });
+
+        self.ready.set_ready();
+
+        // The connected upstream is rotated once its lifetime is over, if it has a maximum one
+        let upstream_max_lifetime = self.config.upstream_max_lifetime();
+        let next_rotation_deadline =
+            move || upstream_max_lifetime.map(|lifetime| Instant::now() + lifetime);
+        let mut rotation_deadline = next_rotation_deadline();
+        // Whether the warm standby was promoted, or could not be connected, since it was last
+        // connected
+        let mut refill_warm_standby = false;
+
         loop {
             tokio::select! {
-                _ = tokio::signal::ctrl_c() => {
-                    info!("Ctrl+C received — initiating graceful shutdown...");
+                _ = &mut shutdown => {
+                    info!("Shutdown signal received — initiating graceful shutdown...");
                     let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
                     break;
                 }
-                message = status_receiver.recv() => {
-                    if let Ok(status) = message {
+                _ = tokio::time::sleep_until(rotation_deadline.unwrap_or_else(Instant::now)),
+                    if rotation_deadline.is_some() => {
+                    info!("Upstream connection reached its maximum lifetime — rotating upstreams");
+                    rotation_deadline = next_rotation_deadline();
+                    // The next upstream is set up before the active one is left, which is kept
+                    // until the next rotation if none can be
+                    rotate_upstreams(&mut upstream_addresses, &channel_manager);
+                    let next_upstream = match warm_standby.take() {
+                        Some(standby) => {
+                            refill_warm_standby = true;
+                            Some(standby)
+                        }
+                        None => tokio::select! {
+                            next_upstream = self.connect_rotation_upstream(
+                                &mut upstream_addresses,
+                                channel_manager_to_upstream_receiver.clone(),
+                                upstream_to_channel_manager_sender.clone(),
+                                notify_shutdown.clone(),
+                                shutdown_complete_tx.clone(),
+                                task_manager.clone(),
+                            ) => next_upstream,
+                            _ = &mut shutdown => {
+                                info!("Shutdown signal received while rotating upstreams — initiating graceful shutdown...");
+                                let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
+                                break;
+                            }
+                        },
+                    };
+                    let Some(next_upstream) = next_upstream else {
+                        warn!("No other upstream could be connected, keeping the active one until the next rotation");
+                        continue;
+                    };
+
+                    let reason = TproxyErrorKind::UpstreamMaxLifetime;
+                    channel_manager.record_failover(reason.failover_reason(), reason.to_string());
+                    if self.config.reuse_negotiated_extensions() {
+                        remember_negotiated_extensions(&mut upstream_addresses, &channel_manager);
+                    }
+                    drain_upstream_queue(&channel_manager_to_upstream_receiver).await;
+                    // The SV1 server keeps the downstreams connected while the channel manager and
+                    // the upstream being left reset
+                    let (tx, mut rx) = mpsc::channel(1);
+                    let _ = notify_shutdown.send(ShutdownMessage::UpstreamRotation{tx});
+                    rx.recv().await;
+                    info!("Rotation signal acknowledged");
+
+                    let label = next_upstream.label().to_string();
+                    if let Err(e) = next_upstream.activate(
+                        notify_shutdown.clone(),
+                        shutdown_complete_tx.clone(),
+                        status_sender.clone(),
+                        task_manager.clone(),
+                    ) {
+                        // The fallback reconnects an upstream, restarting the SV1 server
+                        let _ = status_sender
+                            .send(Status { state: State::UpstreamShutdown(e.kind) })
+                            .await;
+                        continue;
+                    }
+                    info!("Rotated to upstream {label}");
+                    set_primary_upstream(&upstream_router, &upstream_addresses, &label);
+                    channel_manager.set_active_upstream(label);
+                    // The downstreams move onto the channels opened for them on the new upstream
+                    if let Err(e) = sv1_server.reopen_rotated_channels().await {
+                        warn!("Failed to reopen the channels of the downstreams after the rotation: {e:?}");
+                    }
+                }
+                message = next_status(&status_receiver) => {
+                    if let Some(status) = message {
                         match status.state {
                             State::DownstreamShutdown{downstream_id,..} => {
                                 warn!("Downstream {downstream_id:?} disconnected — notifying SV1 server.");
@@ -219,34 +495,125 @@ impl TranslatorSv2 {
                             }
                             State::UpstreamShutdown(msg) => {
                                 warn!("Upstream connection dropped: {msg:?} — attempting reconnection...");
+                                channel_manager.record_failover(msg.failover_reason(), msg.to_string());
+                                if self.config.reuse_negotiated_extensions() {
+                                    remember_negotiated_extensions(&mut upstream_addresses, &channel_manager);
+                                }
+                                let redirect = match msg {
+                                    TproxyErrorKind::UpstreamReconnect(addr) => {
+                                        redirect_upstream(&mut upstream_addresses, &channel_manager, addr)
+                                    }
+                                    _ => None,
+                                };
+                                let redirected = redirect.is_some();
+                                if redirected || matches!(msg, TproxyErrorKind::ManualFailover) {
+                                    drain_upstream_queue(&channel_manager_to_upstream_receiver).await;
+                                }
                                 let (tx, mut rx) = mpsc::channel(1);
                                 let _ = notify_shutdown.send(ShutdownMessage::UpstreamFallback{tx});
                                 // via this we wait for all subsystem to acknowledge the fallback
                                 rx.recv().await;
                                 info!("Fallback signal acknowledged");
 
-                                if let Err(e) = self.initialize_upstream(
-                                    &mut upstream_addresses,
-                                    channel_manager_to_upstream_receiver.clone(),
-                                    upstream_to_channel_manager_sender.clone(),
-                                    notify_shutdown.clone(),
-                                    status_sender.clone(),
-                                    shutdown_complete_tx.clone(),
-                                    task_manager.clone(),
-                                    sv1_server.clone(),
-                                    self.config.required_extensions.clone(),
-                                ).await {
-                                    error!("Couldn't perform fallback, shutting system down: {e:?}");
-                                    let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
-                                    break;
+                                // A redirected translator reconnects to the endpoint it was sent to
+                                let standby = if redirected { None } else { warm_standby.take() };
+                                refill_warm_standby = true;
+                                let failed_over_to_standby = match standby {
+                                    Some(standby) => match self.activate_warm_standby(
+                                        standby,
+                                        notify_shutdown.clone(),
+                                        status_sender.clone(),
+                                        shutdown_complete_tx.clone(),
+                                        task_manager.clone(),
+                                        sv1_server.clone(),
+                                    ).await {
+                                        Ok(label) => Some(label),
+                                        Err(e) => {
+                                            warn!("Warm standby activation failed: {e:?} — falling back to cold reconnect.");
+                                            None
+                                        }
+                                    },
+                                    None => None,
+                                };
+
+                                if let Some(label) = failed_over_to_standby {
+                                    info!("Failed over to warm standby upstream.");
+                                    set_primary_upstream(&upstream_router, &upstream_addresses, &label);
+                                    channel_manager.set_active_upstream(label);
+                                    rotation_deadline = next_rotation_deadline();
                                 } else {
-                                    info!("Upstream restarted successfully.");
+                                    let reconnected = tokio::select! {
+                                        result = self.connect_upstream(
+                                            &mut upstream_addresses,
+                                            channel_manager_to_upstream_receiver.clone(),
+                                            upstream_to_channel_manager_sender.clone(),
+                                            notify_shutdown.clone(),
+                                            status_sender.clone(),
+                                            shutdown_complete_tx.clone(),
+                                            task_manager.clone(),
+                                            sv1_server.clone(),
+                                            self.config.required_extensions.clone(),
+                                        ) => result.map(Some),
+                                        _ = &mut shutdown => Ok(None),
+                                    };
+                                    match reconnected {
+                                        Ok(Some(label)) => {
+                                            info!("Upstream restarted successfully.");
+                                            set_primary_upstream(&upstream_router, &upstream_addresses, &label);
+                                            channel_manager.set_active_upstream(label);
+                                            rotation_deadline = next_rotation_deadline();
+                                        }
+                                        Ok(None) => {
+                                            info!("Shutdown signal received while reconnecting — initiating graceful shutdown...");
+                                            let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
+                                            break;
+                                        }
+                                        Err(e) => {
+                                            error!("Couldn't perform fallback, shutting system down: {e:?}");
+                                            let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
+                                            break;
+                                        }
+                                    }
                                 }
+                                // The redirect only applied to this reconnection
+                                if let Some((label, addr)) = redirect {
+                                    restore_upstream_address(&mut upstream_addresses, &label, addr);
+                                }
+                                manual_failover.complete();
                             }
                         }
+                    } else {
+                        warn!("Status channel closed — initiating graceful shutdown...");
+                        let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
+                        break;
                     }
                 }
             }
+
+            // Once the standby was promoted, or if it could not be connected, another upstream is
+            // kept ready in its place
+            if std::mem::take(&mut refill_warm_standby) && warm_standby.is_none() {
+                let active = channel_manager
+                    .active_upstream
+                    .super_safe_lock(|data| data.clone())
+                    .unwrap_or_default();
+                warm_standby = tokio::select! {
+                    standby = self.connect_warm_standby(
+                        &mut upstream_addresses,
+                        &active,
+                        channel_manager_to_upstream_receiver.clone(),
+                        upstream_to_channel_manager_sender.clone(),
+                        notify_shutdown.clone(),
+                        shutdown_complete_tx.clone(),
+                        task_manager.clone(),
+                    ) => standby,
+                    _ = &mut shutdown => {
+                        info!("Shutdown signal received while connecting the warm standby — initiating graceful shutdown...");
+                        let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
+                        break;
+                    }
+                };
+            }
         }
 
         drop(shutdown_complete_tx);
@@ -266,6 +633,55 @@ impl TranslatorSv2 {
         info!("TranslatorSv2 shutdown complete.");
     }
 
+    /// Connects an upstream with [`Self::initialize_upstream`], applying the configured
+    /// [`AllUpstreamsFailedPolicy`] once every upstream failed.
+    ///
+    /// With [`AllUpstreamsFailedPolicy::WaitAndRetry`], every upstream is tried again after the
+    /// retry interval, so this only returns once an upstream is connected.
+    #[allow(clippy::too_many_arguments)]
+    async fn connect_upstream(
+        &self,
+        upstreams: &mut [UpstreamEntry],
+        channel_manager_to_upstream_receiver: Receiver<Sv2Frame>,
+        upstream_to_channel_manager_sender: Sender<Sv2Frame>,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+        status_sender: Sender<Status>,
+        shutdown_complete_tx: mpsc::Sender<()>,
+        task_manager: Arc<TaskManager>,
+        sv1_server_instance: Arc<Sv1Server>,
+        required_extensions: Vec<u16>,
+    ) -> Result<String, TproxyErrorKind> {
+        loop {
+            let result = self
+                .initialize_upstream(
+                    upstreams,
+                    channel_manager_to_upstream_receiver.clone(),
+                    upstream_to_channel_manager_sender.clone(),
+                    notify_shutdown.clone(),
+                    status_sender.clone(),
+                    shutdown_complete_tx.clone(),
+                    task_manager.clone(),
+                    sv1_server_instance.clone(),
+                    required_extensions.clone(),
+                )
+                .await;
+            let all_upstreams_failed =
+                matches!(result, Err(TproxyErrorKind::CouldNotInitiateSystem));
+            if !all_upstreams_failed
+                || self.config.on_all_upstreams_failed() != AllUpstreamsFailedPolicy::WaitAndRetry
+            {
+                return result;
+            }
+
+            let retry_interval = self.config.upstream_retry_interval();
+            warn!("All upstreams failed, trying them again in {retry_interval:?}");
+            tokio::time::sleep(retry_interval).await;
+            for upstream_entry in upstreams.iter_mut() {
+                upstream_entry.tried_or_flagged = false;
+            }
+        }
+    }
+
     /// Initializes the upstream connection list, handling retries, fallbacks, and flagging.
     ///
     /// Upstreams are tried sequentially, each receiving a fixed number of retries before we
@@ -276,6 +692,8 @@ impl TranslatorSv2 {
     ///  `false` means "never tried", while `true` means "already connected or marked as
     /// malicious". Once an upstream is flagged we skip it on future loops
     /// to avoid hammering known-bad endpoints during failover.
+    ///
+    /// Returns the monitoring label of the upstream that was connected.
     #[allow(clippy::too_many_arguments)]
     pub async fn initialize_upstream(
         &self,
@@ -288,7 +706,7 @@ impl TranslatorSv2 {
         task_manager: Arc<TaskManager>,
         sv1_server_instance: Arc<Sv1Server>,
         required_extensions: Vec<u16>,
-    ) -> Result<(), TproxyErrorKind> {
+    ) -> Result<String, TproxyErrorKind> {
         const MAX_RETRIES: usize = 3;
         let upstream_len = upstreams.len();
         for (i, upstream_entry) in upstreams.iter_mut().enumerate() {
@@ -319,11 +737,21 @@ impl TranslatorSv2 {
                     status_sender.clone(),
                     shutdown_complete_tx.clone(),
                     task_manager.clone(),
-                    required_extensions.clone(),
+                    upstream_entry.extensions_to_request(
+                        &required_extensions,
+                        self.config.reuse_negotiated_extensions(),
+                    ),
+                    self.config.min_supported_version,
+                    self.config.max_supported_version,
+                    self.config.max_frame_size(),
+                    self.config.frame_trace(),
+                    self.message_counters.clone(),
+                    self.config.upstream_io_timeouts(),
+                    self.config.setup_connection_flags_policy(),
                 )
                 .await
                 {
-                    Ok(pair) => {
+                    Ok(()) => {
                         // starting sv1 server instance
                         if let Err(e) = sv1_server_instance
                             .start(
@@ -339,13 +767,18 @@ impl TranslatorSv2 {
                         }
 
                         upstream_entry.tried_or_flagged = true;
-                        return Ok(pair);
+                        return Ok(upstream_entry.label.clone());
                     }
                     Err(e) => {
-                        warn!(
-                            "Attempt {}/{} failed for {:?}: {:?}",
-                            attempt, MAX_RETRIES, upstream_entry.addr, e
-                        );
+                        if let Some(suppressed) = self
+                            .log_throttle
+                            .admit("upstream_attempt_failed", &upstream_entry.addr.to_string())
+                        {
+                            warn!(
+                                "Attempt {}/{} failed for {:?}: {:?}{suppressed}",
+                                attempt, MAX_RETRIES, upstream_entry.addr, e
+                            );
+                        }
                         if attempt == MAX_RETRIES {
                             warn!(
                                 "Max retries reached for {:?}, moving to next upstream",
@@ -361,6 +794,493 @@ impl TranslatorSv2 {
         tracing::error!("All upstreams failed after {} retries each", MAX_RETRIES);
         Err(TproxyErrorKind::CouldNotInitiateSystem)
     }
+
+    /// Connects the configured warm-standby upstream and completes its SV2 setup.
+    ///
+    /// When the configured upstream is the `active` one, which it is once it was promoted, the
+    /// next upstream in the list is kept as standby instead. The returned upstream stays idle
+    /// (no upstream task is running) until it gets promoted by [`Self::activate_warm_standby`].
+    /// Its entry is flagged so the cold failover path does not dial it a second time. Returns
+    /// `None` when warm standby is disabled, there is no other upstream than the active one, or
+    /// the standby could not be set up.
+    #[allow(clippy::too_many_arguments)]
+    async fn connect_warm_standby(
+        &self,
+        upstreams: &mut [UpstreamEntry],
+        active: &str,
+        channel_manager_to_upstream_receiver: Receiver<Sv2Frame>,
+        upstream_to_channel_manager_sender: Sender<Sv2Frame>,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+        shutdown_complete_tx: mpsc::Sender<()>,
+        task_manager: Arc<TaskManager>,
+    ) -> Option<Upstream> {
+        let index = self.config.warm_standby_upstream_index()?;
+        if index >= upstreams.len() {
+            warn!("Warm standby upstream index {index} is out of range, warm standby disabled");
+            return None;
+        }
+        let Some(index) = standby_candidate(upstreams, index, active) else {
+            warn!("No upstream other than the active one to keep as warm standby");
+            return None;
+        };
+        let upstream_entry = &mut upstreams[index];
+
+        info!("Connecting warm standby upstream {:?}", upstream_entry.addr);
+        let upstream = self
+            .connect_idle_upstream(
+                upstream_entry,
+                self.config.required_extensions.clone(),
+                channel_manager_to_upstream_receiver,
+                upstream_to_channel_manager_sender,
+                notify_shutdown,
+                shutdown_complete_tx,
+                task_manager,
+            )
+            .await?;
+
+        upstream_entry.tried_or_flagged = true;
+        info!("Warm standby upstream {:?} ready", upstream_entry.addr);
+        Some(upstream)
+    }
+
+    /// Connects the upstream a rotation moves to and completes its SV2 setup, so the active
+    /// upstream is only left once the next one is ready.
+    ///
+    /// The upstreams [`rotate_upstreams`] made eligible are tried once each, in order, and
+    /// flagged. The returned upstream stays idle until it is activated. Returns `None` when none
+    /// could be set up.
+    #[allow(clippy::too_many_arguments)]
+    async fn connect_rotation_upstream(
+        &self,
+        upstreams: &mut [UpstreamEntry],
+        channel_manager_to_upstream_receiver: Receiver<Sv2Frame>,
+        upstream_to_channel_manager_sender: Sender<Sv2Frame>,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+        shutdown_complete_tx: mpsc::Sender<()>,
+        task_manager: Arc<TaskManager>,
+    ) -> Option<Upstream> {
+        for upstream_entry in upstreams.iter_mut().filter(|u| !u.tried_or_flagged) {
+            upstream_entry.tried_or_flagged = true;
+            info!("Connecting upstream {:?} to rotate to", upstream_entry.addr);
+            let required_extensions = upstream_entry.extensions_to_request(
+                &self.config.required_extensions,
+                self.config.reuse_negotiated_extensions(),
+            );
+            let upstream = self
+                .connect_idle_upstream(
+                    upstream_entry,
+                    required_extensions,
+                    channel_manager_to_upstream_receiver.clone(),
+                    upstream_to_channel_manager_sender.clone(),
+                    notify_shutdown.clone(),
+                    shutdown_complete_tx.clone(),
+                    task_manager.clone(),
+                )
+                .await;
+            if upstream.is_some() {
+                return upstream;
+            }
+        }
+        None
+    }
+
+    // Connects `upstream_entry` and completes its SV2 setup, without starting its upstream task.
+    #[allow(clippy::too_many_arguments)]
+    async fn connect_idle_upstream(
+        &self,
+        upstream_entry: &UpstreamEntry,
+        required_extensions: Vec<u16>,
+        channel_manager_to_upstream_receiver: Receiver<Sv2Frame>,
+        upstream_to_channel_manager_sender: Sender<Sv2Frame>,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+        shutdown_complete_tx: mpsc::Sender<()>,
+        task_manager: Arc<TaskManager>,
+    ) -> Option<Upstream> {
+        let mut upstream = match Upstream::new(
+            upstream_entry,
+            upstream_to_channel_manager_sender,
+            channel_manager_to_upstream_receiver,
+            notify_shutdown,
+            shutdown_complete_tx,
+            task_manager,
+            required_extensions,
+            self.config.min_supported_version,
+            self.config.max_supported_version,
+            self.config.max_frame_size(),
+            self.config.frame_trace(),
+            self.message_counters.clone(),
+            self.config.upstream_io_timeouts(),
+        )
+        .await
+        {
+            Ok(upstream) => upstream
+                .with_setup_connection_flags_policy(self.config.setup_connection_flags_policy()),
+            Err(e) => {
+                warn!(
+                    "Failed to connect upstream {:?}: {e:?}",
+                    upstream_entry.addr
+                );
+                return None;
+            }
+        };
+
+        if let Err(e) = upstream.setup_connection().await {
+            warn!("Failed to set up upstream {:?}: {e:?}", upstream_entry.addr);
+            return None;
+        }
+        Some(upstream)
+    }
+
+    /// Promotes a warm-standby upstream to be the active upstream and restarts the SV1 server.
+    ///
+    /// Returns the monitoring label of the promoted upstream.
+    async fn activate_warm_standby(
+        &self,
+        standby: Upstream,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+        status_sender: Sender<Status>,
+        shutdown_complete_tx: mpsc::Sender<()>,
+        task_manager: Arc<TaskManager>,
+        sv1_server_instance: Arc<Sv1Server>,
+    ) -> Result<String, TproxyErrorKind> {
+        info!("Promoting warm standby upstream {}", standby.address());
+        let label = standby.label().to_string();
+        standby
+            .activate(
+                notify_shutdown.clone(),
+                shutdown_complete_tx.clone(),
+                status_sender.clone(),
+                task_manager.clone(),
+            )
+            .map_err(|e| e.kind)?;
+
+        sv1_server_instance
+            .start(
+                notify_shutdown,
+                shutdown_complete_tx,
+                status_sender,
+                task_manager,
+            )
+            .await
+            .map_err(|e| e.kind)?;
+        Ok(label)
+    }
+
+    /// Keeps the routed upstream at `index` of the `upstreams` list connected, for the channels
+    /// the `upstream_routes` rules send to it.
+    ///
+    /// The frames it sends reach the channel manager through `upstream_to_channel_manager_sender`
+    /// once [`UpstreamRouter::remap_inbound`] gave them local channel IDs. When the connection
+    /// ends, the channel manager gets a `CloseChannel` for each channel that was open on it, and
+    /// the upstream is connected again after the retry interval. No connection is made while the
+    /// primary connection uses the same upstream, since its channels are opened there. The
+    /// connection is kept when the primary upstream fails over, and negotiates the same required
+    /// extensions.
+    async fn run_routed_upstream(
+        self,
+        index: usize,
+        upstream_router: Arc<UpstreamRouter>,
+        upstream_to_channel_manager_sender: Sender<Sv2Frame>,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+        shutdown_complete_tx: mpsc::Sender<()>,
+        task_manager: Arc<TaskManager>,
+    ) {
+        let upstream = &self.config.upstreams[index];
+        let entry = UpstreamEntry {
+            addr: SocketAddr::new(upstream.address.parse().unwrap(), upstream.port),
+            authority_pubkey: upstream.authority_pubkey,
+            tried_or_flagged: false,
+            label: upstream.label(),
+            negotiated_extensions: None,
+            reconnect_allowlist: upstream.reconnect_allowlist.clone(),
+        };
+        let mut shutdown_rx = notify_shutdown.subscribe();
+
+        loop {
+            if !upstream_router.is_primary_upstream(index) {
+                let (inbound_tx, inbound_rx) = unbounded();
+                let (outbound_tx, outbound_rx) = unbounded();
+                // The upstream task reports its failures here instead of failing over the
+                // primary upstream
+                let (status_tx, status_rx) = unbounded::<Status>();
+                let started = match Upstream::new(
+                    &entry,
+                    inbound_tx,
+                    outbound_rx,
+                    notify_shutdown.clone(),
+                    shutdown_complete_tx.clone(),
+                    task_manager.clone(),
+                    self.config.required_extensions.clone(),
+                    self.config.min_supported_version,
+                    self.config.max_supported_version,
+                    self.config.max_frame_size(),
+                    self.config.frame_trace(),
+                    self.message_counters.clone(),
+                    self.config.upstream_io_timeouts(),
+                )
+                .await
+                {
+                    Ok(upstream) => upstream
+                        .with_setup_connection_flags_policy(
+                            self.config.setup_connection_flags_policy(),
+                        )
+                        .with_keep_on_fallback(true)
+                        .start(
+                            notify_shutdown.clone(),
+                            shutdown_complete_tx.clone(),
+                            status_tx,
+                            task_manager.clone(),
+                        )
+                        .await
+                        .map_err(|e| e.kind),
+                    Err(e) => Err(e.kind),
+                };
+
+                match started {
+                    Ok(()) => {
+                        info!("Routed upstream {} connected: {:?}", index, entry.addr);
+                        upstream_router.connect_upstream(index, outbound_tx);
+                        let mut closed_channels = Vec::new();
+                        let exit = forward_routed_upstream(
+                            index,
+                            &upstream_router,
+                            inbound_rx,
+                            status_rx,
+                            &upstream_to_channel_manager_sender,
+                            &mut shutdown_rx,
+                            &mut closed_channels,
+                        )
+                        .await;
+                        closed_channels.extend(upstream_router.disconnect_upstream(index));
+                        warn!("Routed upstream {} disconnected", index);
+                        match exit {
+                            RoutedUpstreamExit::Shutdown => return,
+                            RoutedUpstreamExit::Disconnected => {
+                                close_routed_channels(
+                                    closed_channels,
+                                    &upstream_to_channel_manager_sender,
+                                )
+                                .await
+                            }
+                        }
+                    }
+                    Err(e) => warn!("Failed to connect routed upstream {}: {:?}", index, e),
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(self.config.upstream_retry_interval()) => {}
+                message = shutdown_rx.recv() => match message {
+                    Ok(ShutdownMessage::ShutdownAll) | Err(_) => return,
+                    Ok(
+                        ShutdownMessage::UpstreamFallback { tx }
+                        | ShutdownMessage::UpstreamRotation { tx },
+                    ) => drop(tx),
+                    Ok(_) => {}
+                },
+            }
+        }
+    }
+}
+
+/// Why [`forward_routed_upstream`] stopped forwarding the frames of a routed upstream.
+enum RoutedUpstreamExit {
+    /// The connection to the routed upstream ended.
+    Disconnected,
+    /// The translator is shutting down.
+    Shutdown,
+}
+
+// Forwards the frames of the routed upstream at `index` to the channel manager until its upstream
+// task exits, which drops the sender of `inbound_rx`. The channels that were open on it when it is
+// disconnected early are added to `closed_channels`.
+async fn forward_routed_upstream(
+    index: usize,
+    upstream_router: &UpstreamRouter,
+    inbound_rx: Receiver<Sv2Frame>,
+    status_rx: Receiver<Status>,
+    upstream_to_channel_manager_sender: &Sender<Sv2Frame>,
+    shutdown_rx: &mut broadcast::Receiver<ShutdownMessage>,
+    closed_channels: &mut Vec<ChannelId>,
+) -> RoutedUpstreamExit {
+    let mut exit = RoutedUpstreamExit::Disconnected;
+    loop {
+        tokio::select! {
+            frame = inbound_rx.recv() => {
+                let Ok(frame) = frame else {
+                    return exit;
+                };
+                let frame = match upstream_router.remap_inbound(index, frame) {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        // Dropping its sender makes the upstream task exit
+                        warn!("Disconnecting routed upstream {}: {:?}", index, e);
+                        closed_channels.extend(upstream_router.disconnect_upstream(index));
+                        continue;
+                    }
+                };
+                if upstream_to_channel_manager_sender.send(frame).await.is_err() {
+                    // Closing its queue makes the upstream task exit
+                    closed_channels.extend(upstream_router.disconnect_upstream(index));
+                    exit = RoutedUpstreamExit::Shutdown;
+                }
+            }
+            Ok(status) = status_rx.recv() => {
+                warn!("Routed upstream {} failed: {:?}", index, status.state);
+                closed_channels.extend(upstream_router.disconnect_upstream(index));
+            }
+            message = shutdown_rx.recv() => match message {
+                Ok(ShutdownMessage::ShutdownAll) | Err(_) => exit = RoutedUpstreamExit::Shutdown,
+                // The connection is kept, the router closes the channels that were open on it
+                Ok(
+                    ShutdownMessage::UpstreamFallback { tx }
+                    | ShutdownMessage::UpstreamRotation { tx },
+                ) => drop(tx),
+                Ok(_) => {}
+            },
+        }
+    }
+}
+
+// Records the upstream the primary connection uses, so the channels routed to it are opened there.
+fn set_primary_upstream(
+    upstream_router: &UpstreamRouter,
+    upstream_addresses: &[UpstreamEntry],
+    label: &str,
+) {
+    if let Some(index) = upstream_addresses.iter().position(|u| u.label == label) {
+        upstream_router.set_primary_upstream(index);
+    }
+}
+
+// Returns the index of the upstream to keep as warm standby: the `configured` one, or the first
+// one after it that is not the `active` upstream.
+fn standby_candidate(
+    upstreams: &[UpstreamEntry],
+    configured: usize,
+    active: &str,
+) -> Option<usize> {
+    (0..upstreams.len())
+        .map(|offset| (configured + offset) % upstreams.len())
+        .find(|&index| upstreams[index].label != active)
+}
+
+// Closes the channels of a routed upstream that disconnected, disconnecting their downstreams.
+async fn close_routed_channels(
+    channel_ids: Vec<ChannelId>,
+    upstream_to_channel_manager_sender: &Sender<Sv2Frame>,
+) {
+    for channel_id in channel_ids {
+        let close_channel = AnyMessage::Mining(Mining::CloseChannel(CloseChannel {
+            channel_id,
+            reason_code: Str0255::try_from("routed upstream disconnected".to_string()).unwrap(),
+        }));
+        let Ok(frame) = Sv2Frame::try_from(close_channel) else {
+            continue;
+        };
+        if upstream_to_channel_manager_sender
+            .send(frame)
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+// Caches the extensions negotiated with the upstream being left on its entry, so they are
+// requested right away if it is connected to again.
+fn remember_negotiated_extensions(
+    upstream_addresses: &mut [UpstreamEntry],
+    channel_manager: &ChannelManager,
+) {
+    let Some(label) = channel_manager
+        .active_upstream
+        .super_safe_lock(|data| data.clone())
+    else {
+        return;
+    };
+    let negotiated = channel_manager
+        .negotiated_extensions
+        .super_safe_lock(|data| data.clone());
+    if let Some(entry) = upstream_addresses.iter_mut().find(|u| u.label == label) {
+        entry.remember_negotiated_extensions(negotiated);
+    }
+}
+
+// Points the upstream being left at the endpoint it redirected the translator to with
+// `Reconnect`, so the failover connects to it first. The redirect keeps the authority key and
+// the label of the upstream. Returns the label and the configured address of the redirected
+// upstream, to restore once the failover is done.
+fn redirect_upstream(
+    upstream_addresses: &mut [UpstreamEntry],
+    channel_manager: &ChannelManager,
+    addr: SocketAddr,
+) -> Option<(String, SocketAddr)> {
+    let label = channel_manager
+        .active_upstream
+        .super_safe_lock(|data| data.clone())?;
+    let entry = upstream_addresses.iter_mut().find(|u| u.label == label)?;
+    info!("Redirecting upstream {label} from {} to {addr}", entry.addr);
+    let configured_addr = std::mem::replace(&mut entry.addr, addr);
+    entry.tried_or_flagged = false;
+    Some((label, configured_addr))
+}
+
+// Points the upstream `label` back at its configured address after a redirect, so later
+// failovers and rotations only connect to configured upstreams.
+fn restore_upstream_address(
+    upstream_addresses: &mut [UpstreamEntry],
+    label: &str,
+    addr: SocketAddr,
+) {
+    if let Some(entry) = upstream_addresses.iter_mut().find(|u| u.label == label) {
+        entry.addr = addr;
+    }
+}
+
+// Makes the upstreams after the active one eligible again, so a rotation connects to the next
+// one. When the active upstream is the last one, every upstream is, so the rotation wraps around
+// to the first one and only comes back to the active one if all the others fail.
+fn rotate_upstreams(upstream_addresses: &mut [UpstreamEntry], channel_manager: &ChannelManager) {
+    let Some(label) = channel_manager
+        .active_upstream
+        .super_safe_lock(|data| data.clone())
+    else {
+        return;
+    };
+    let Some(active) = upstream_addresses.iter().position(|u| u.label == label) else {
+        return;
+    };
+    let wraps_around = active + 1 == upstream_addresses.len();
+    for (index, entry) in upstream_addresses.iter_mut().enumerate() {
+        if index > active || wraps_around {
+            entry.tried_or_flagged = false;
+        }
+    }
+}
+
+/// Longest wait for the frames queued to the upstream before a manual failover, a redirect or a
+/// rotation leaves it.
+const MANUAL_FAILOVER_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Waits until the upstream task took every frame queued for it, so the shares already forwarded
+// by the channel manager reach the upstream being left.
+async fn drain_upstream_queue(channel_manager_to_upstream_receiver: &Receiver<Sv2Frame>) {
+    let drained = tokio::time::timeout(MANUAL_FAILOVER_DRAIN_TIMEOUT, async {
+        while !channel_manager_to_upstream_receiver.is_empty() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    match drained {
+        Ok(()) => info!("Upstream queue drained, failing over"),
+        Err(_) => warn!(
+            "{} frames still queued for the upstream after {MANUAL_FAILOVER_DRAIN_TIMEOUT:?}, failing over anyway",
+            channel_manager_to_upstream_receiver.len()
+        ),
+    }
 }
 
 // Attempts to initialize a single upstream.
@@ -375,6 +1295,13 @@ async fn try_initialize_upstream(
     shutdown_complete_tx: mpsc::Sender<()>,
     task_manager: Arc<TaskManager>,
     required_extensions: Vec<u16>,
+    min_supported_version: u16,
+    max_supported_version: u16,
+    max_frame_size: usize,
+    frame_trace: bool,
+    message_counters: Arc<MessageCounters>,
+    io_timeouts: IoTimeouts,
+    setup_connection_flags_policy: SetupConnectionFlagsPolicy,
 ) -> Result<(), TproxyErrorKind> {
     let upstream = Upstream::new(
         upstream_addr,
@@ -384,8 +1311,15 @@ async fn try_initialize_upstream(
         shutdown_complete_tx.clone(),
         task_manager.clone(),
         required_extensions,
+        min_supported_version,
+        max_supported_version,
+        max_frame_size,
+        frame_trace,
+        message_counters,
+        io_timeouts,
     )
-    .await?;
+    .await?
+    .with_setup_connection_flags_policy(setup_connection_flags_policy);
 
     upstream
         .start(
@@ -3,14 +3,35 @@
 //! This module implements the ServerMonitoring trait on `ChannelManager`.
 //! tProxy has server channels (upstream to pool) but no SV2 clients
 //! (SV1 clients are handled separately in sv1_monitoring.rs).
+//! It also provides [`ManualFailover`], the `POST /api/v1/failover` admin action.
 
-use stratum_apps::monitoring::server::{ServerExtendedChannelInfo, ServerInfo, ServerMonitoring};
+use async_channel::Sender;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use stratum_apps::monitoring::{
+    server::{ServerExtendedChannelInfo, ServerInfo, ServerMonitoring},
+    FailoverControl,
+};
 
 use crate::{
-    sv2::channel_manager::ChannelManager, tproxy_mode, utils::AGGREGATED_CHANNEL_ID,
-    vardiff_enabled, TproxyMode,
+    error::TproxyErrorKind,
+    status::{State, Status},
+    sv2::channel_manager::ChannelManager,
+    tproxy_mode, vardiff_enabled, TproxyMode,
 };
 
+impl ChannelManager {
+    // Returns the id of the upstream group channel containing `channel_id`, if any.
+    fn group_channel_id(&self, channel_id: u32) -> Option<u32> {
+        self.group_channels
+            .iter()
+            .find(|group_channel| group_channel.get_channel_ids().contains(&channel_id))
+            .map(|group_channel| *group_channel.key())
+    }
+}
+
 impl ServerMonitoring for ChannelManager {
     fn get_server(&self) -> ServerInfo {
         let mut extended_channels = Vec::new();
@@ -19,11 +40,14 @@ impl ServerMonitoring for ChannelManager {
 
         match tproxy_mode() {
             TproxyMode::Aggregated => {
-                // In Aggregated mode: one shared channel to the server
-                // stored under AGGREGATED_CHANNEL_ID
-                if let Some(aggregated_extended_channel) =
-                    self.extended_channels.get(&AGGREGATED_CHANNEL_ID)
-                {
+                // In Aggregated mode: the channels shared with the server, stored under
+                // AGGREGATED_CHANNEL_ID and the IDs below it
+                for aggregated_channel_id in self.aggregated_channels() {
+                    let Some(aggregated_extended_channel) =
+                        self.extended_channels.get(&aggregated_channel_id)
+                    else {
+                        continue;
+                    };
                     let channel_id = aggregated_extended_channel.get_channel_id();
                     let target = aggregated_extended_channel.get_target();
                     let extranonce_prefix = aggregated_extended_channel.get_extranonce_prefix();
@@ -40,6 +64,7 @@ impl ServerMonitoring for ChannelManager {
 
                     extended_channels.push(ServerExtendedChannelInfo {
                         channel_id,
+                        group_channel_id: self.group_channel_id(channel_id),
                         user_identity: user_identity.clone(),
                         nominal_hashrate: if report_hashrate {
                             Some(aggregated_extended_channel.get_nominal_hashrate())
@@ -48,6 +73,9 @@ impl ServerMonitoring for ChannelManager {
                         },
                         target_hex: hex::encode(target.to_be_bytes()),
                         extranonce_prefix_hex: hex::encode(extranonce_prefix),
+                        current_job_id: aggregated_extended_channel
+                            .get_active_job()
+                            .map(|job| job.0.job_id),
                         full_extranonce_size: aggregated_extended_channel
                             .get_full_extranonce_size(),
                         rollable_extranonce_size: aggregated_extended_channel
@@ -57,6 +85,10 @@ impl ServerMonitoring for ChannelManager {
                         share_work_sum: share_accounting.get_share_work_sum(),
                         shares_submitted,
                         best_diff: share_accounting.get_best_diff(),
+                        extranonce_prefix_usage: self
+                            .extranonce_factories
+                            .get(&aggregated_channel_id)
+                            .map(|factory| factory.usage()),
                     });
                 }
             }
@@ -82,6 +114,7 @@ impl ServerMonitoring for ChannelManager {
 
                     extended_channels.push(ServerExtendedChannelInfo {
                         channel_id,
+                        group_channel_id: self.group_channel_id(channel_id),
                         user_identity: user_identity.clone(),
                         nominal_hashrate: if report_hashrate {
                             Some(extended_channel.get_nominal_hashrate())
@@ -90,6 +123,7 @@ impl ServerMonitoring for ChannelManager {
                         },
                         target_hex: hex::encode(target.to_be_bytes()),
                         extranonce_prefix_hex: hex::encode(extranonce_prefix),
+                        current_job_id: extended_channel.get_active_job().map(|job| job.0.job_id),
                         full_extranonce_size: extended_channel.get_full_extranonce_size(),
                         rollable_extranonce_size: extended_channel.get_rollable_extranonce_size(),
                         version_rolling: extended_channel.is_version_rolling(),
@@ -97,14 +131,69 @@ impl ServerMonitoring for ChannelManager {
                         share_work_sum: share_accounting.get_share_work_sum(),
                         shares_submitted,
                         best_diff: share_accounting.get_best_diff(),
+                        extranonce_prefix_usage: self
+                            .extranonce_factories
+                            .get(&channel_id)
+                            .map(|factory| factory.usage()),
                     });
                 }
             }
         }
 
         ServerInfo {
+            upstream: self.active_upstream.super_safe_lock(|data| data.clone()),
             extended_channels,
             standard_channels,
+            last_failover: self.last_failover.super_safe_lock(|data| data.clone()),
+            pending_declared_jobs: None,
+            stale_job_events: Some(self.stale_job_events.load(Ordering::Relaxed)),
         }
     }
 }
+
+/// Fails over to the next upstream on demand, through `POST /api/v1/failover`.
+///
+/// The request is reported to the status loop as an [`State::UpstreamShutdown`], which drains the
+/// frames queued for the upstream before falling back like for any upstream failure.
+pub struct ManualFailover {
+    channel_manager: Arc<ChannelManager>,
+    status_sender: Sender<Status>,
+    // Set from the request until the status loop completed the failover
+    pending: AtomicBool,
+}
+
+impl ManualFailover {
+    pub fn new(channel_manager: Arc<ChannelManager>, status_sender: Sender<Status>) -> Self {
+        Self {
+            channel_manager,
+            status_sender,
+            pending: AtomicBool::new(false),
+        }
+    }
+
+    /// Accepts new failover requests again. Called once the status loop handled a fallback.
+    pub fn complete(&self) {
+        self.pending.store(false, Ordering::Relaxed);
+    }
+}
+
+impl FailoverControl for ManualFailover {
+    fn request_failover(&self) -> Result<(), String> {
+        if self
+            .channel_manager
+            .active_upstream
+            .super_safe_lock(|data| data.is_none())
+        {
+            return Err("No upstream connected".to_string());
+        }
+        if self.pending.swap(true, Ordering::Relaxed) {
+            return Err("A failover is already in progress".to_string());
+        }
+
+        let state = State::UpstreamShutdown(TproxyErrorKind::ManualFailover);
+        self.status_sender.try_send(Status { state }).map_err(|e| {
+            self.pending.store(false, Ordering::Relaxed);
+            format!("Failed to request the failover: {e}")
+        })
+    }
+}
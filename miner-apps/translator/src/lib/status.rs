@@ -80,72 +80,181 @@ pub struct Status {
     pub state: State,
 }
 
-#[cfg_attr(not(test), hotpath::measure)]
-async fn send_status<O>(sender: &StatusSender, error: TproxyError<O>) -> bool {
-    use Action::*;
-
-    match error.action {
-        Log => {
-            warn!("Log-only error from {:?}: {:?}", sender, error.kind);
-            false
-        }
+/// Maps an error reported by `sender` to the [`State`] sent to the main loop, if any, and to
+/// whether the task of `sender` stops.
+///
+/// | Action       | Reported by  | State                    | Main loop   | Stops |
+/// |--------------|--------------|--------------------------|-------------|-------|
+/// | `Log`        | any          | none                     | -           | no    |
+/// | `Disconnect` | `Downstream` | `DownstreamShutdown`     | disconnects | yes   |
+/// | `Disconnect` | others       | `DownstreamShutdown`     | disconnects | no    |
+/// | `Fallback`   | `Upstream`   | `UpstreamShutdown`       | fails over  | yes   |
+/// | `Fallback`   | others       | `UpstreamShutdown`       | fails over  | no    |
+/// | `Shutdown`   | `Sv1Server`  | `Sv1ServerShutdown`      | shuts down  | yes   |
+/// | `Shutdown`   | others       | `ChannelManagerShutdown` | shuts down  | yes   |
+///
+/// A task only stops on the errors it cannot outlive. The others keep running until the main
+/// loop tells them to stop, as part of the fallback or of the shutdown.
+///
+/// The action is picked where the error is raised, after what failed:
+///
+/// - `Log`: a benign race, such as a message for a downstream or channel already gone
+///   (`DownstreamNotFound`, `DownstreamNotFoundWithChannelId`, `PendingChannelNotFound`), or no
+///   miner left to receive a message broadcast to the downstreams.
+/// - `Disconnect`: a single SV1 miner misbehaved or its connection failed (`SlowConsumer`,
+///   `AuthorizeGraceExpired`, `ExtranonceChangeNotSupported`, `RejoinedWorkerMismatch`,
+///   `DownstreamChannelNotOpened`, an `SV1Error` translating its share).
+/// - `Fallback`: the upstream failed or misbehaved (`SetupConnectionError`,
+///   `UnsupportedProtocolVersion`, `UnexpectedMessage`, `RequiredExtensionsNotSupported`,
+///   `StaleUpstreamJobs`, `UpstreamReconnect`, `UpstreamMaxLifetime`, `ManualFailover`, an
+///   `OpenMiningChannelError` once the primary upstream keeps refusing a channel, or a failed send
+///   to the upstream).
+/// - `Shutdown`: the translator itself cannot go on (`CouldNotInitiateSystem`, `ClockSkew`, a
+///   closed channel between its own tasks, or a message of its own it fails to serialize).
+fn error_to_state(
+    sender: &StatusSender,
+    action: Action,
+    kind: TproxyErrorKind,
+) -> (Option<State>, bool) {
+    use StatusSender::*;
 
-        Disconnect(downstream_id) => {
+    match action {
+        Action::Log => (None, false),
+        Action::Disconnect(downstream_id) => {
             let state = State::DownstreamShutdown {
                 downstream_id,
-                reason: error.kind,
+                reason: kind,
             };
-
-            if let Err(e) = sender.send(Status { state }).await {
-                tracing::error!(
-                    "Failed to send downstream shutdown status from {:?}: {:?}",
-                    sender,
-                    e
-                );
-                std::process::abort();
-            }
-            matches!(sender, StatusSender::Downstream { .. })
+            let stop = match sender {
+                Downstream { .. } => true,
+                Sv1Server(_) | ChannelManager(_) | Upstream(_) => false,
+            };
+            (Some(state), stop)
         }
-
-        Fallback => {
-            let state = State::UpstreamShutdown(error.kind);
-
-            if let Err(e) = sender.send(Status { state }).await {
-                tracing::error!("Failed to send fallback status from {:?}: {:?}", sender, e);
-                std::process::abort();
-            }
-            matches!(sender, StatusSender::Upstream { .. })
+        Action::Fallback => {
+            let stop = match sender {
+                Upstream(_) => true,
+                Downstream { .. } | Sv1Server(_) | ChannelManager(_) => false,
+            };
+            (Some(State::UpstreamShutdown(kind)), stop)
         }
-
-        Shutdown => {
+        Action::Shutdown => {
             let state = match sender {
-                StatusSender::ChannelManager(_) => {
-                    warn!(
-                        "Channel Manager shutdown requested due to error: {:?}",
-                        error.kind
-                    );
-                    State::ChannelManagerShutdown(error.kind)
-                }
-                StatusSender::Sv1Server(_) => {
-                    warn!(
-                        "Sv1Server shutdown requested due to error: {:?}",
-                        error.kind
-                    );
-                    State::Sv1ServerShutdown(error.kind)
+                Sv1Server(_) => State::Sv1ServerShutdown(kind),
+                Downstream { .. } | ChannelManager(_) | Upstream(_) => {
+                    State::ChannelManagerShutdown(kind)
                 }
-                _ => State::ChannelManagerShutdown(error.kind),
             };
+            (Some(state), true)
+        }
+    }
+}
 
-            if let Err(e) = sender.send(Status { state }).await {
-                tracing::error!("Failed to send shutdown status from {:?}: {:?}", sender, e);
-                std::process::abort();
-            }
-            true
+#[cfg_attr(not(test), hotpath::measure)]
+async fn send_status<O>(sender: &StatusSender, error: TproxyError<O>) -> bool {
+    match error.action {
+        Action::Log => warn!("Log-only error from {:?}: {:?}", sender, error.kind),
+        Action::Shutdown => warn!(
+            "Shutdown requested by {:?} due to error: {:?}",
+            sender, error.kind
+        ),
+        Action::Disconnect(_) | Action::Fallback => {}
+    }
+
+    let (state, stop) = error_to_state(sender, error.action, error.kind);
+    if let Some(state) = state {
+        if let Err(e) = sender.send(Status { state }).await {
+            tracing::error!("Failed to send status from {:?}: {:?}", sender, e);
+            std::process::abort();
         }
     }
+    stop
 }
 
 #[cfg_attr(not(test), hotpath::measure)]
 pub async fn handle_error<O>(sender: &StatusSender, e: TproxyError<O>) -> bool {
     send_status(sender, e).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error;
+
+    #[tokio::test]
+    async fn test_handle_error_reports_state_to_main_loop() {
+        let (tx, rx) = async_channel::unbounded();
+
+        // an upstream that drops fails over, and its task stops
+        let upstream = StatusSender::Upstream(tx.clone());
+        let error = TproxyError::<error::Upstream>::fallback(TproxyErrorKind::ManualFailover);
+        assert!(handle_error(&upstream, error).await);
+        assert!(matches!(
+            rx.try_recv().unwrap().state,
+            State::UpstreamShutdown(TproxyErrorKind::ManualFailover)
+        ));
+
+        // a channel the primary upstream keeps refusing fails over, the SV1 server keeps running
+        let sv1_server = StatusSender::Sv1Server(tx.clone());
+        let error =
+            TproxyError::<error::Sv1Server>::fallback(TproxyErrorKind::OpenMiningChannelError);
+        assert!(!handle_error(&sv1_server, error).await);
+        assert!(matches!(
+            rx.try_recv().unwrap().state,
+            State::UpstreamShutdown(TproxyErrorKind::OpenMiningChannelError)
+        ));
+
+        // a miner that never authorizes is disconnected, and its task stops
+        let downstream = StatusSender::Downstream {
+            downstream_id: 5,
+            tx: tx.clone(),
+        };
+        let error =
+            TproxyError::<error::Downstream>::disconnect(TproxyErrorKind::AuthorizeGraceExpired, 5);
+        assert!(handle_error(&downstream, error).await);
+        assert!(matches!(
+            rx.try_recv().unwrap().state,
+            State::DownstreamShutdown {
+                downstream_id: 5,
+                reason: TproxyErrorKind::AuthorizeGraceExpired,
+            }
+        ));
+
+        // a downstream the SV1 server fails to serve is disconnected, the server keeps running
+        let error = TproxyError::<error::Sv1Server>::disconnect(
+            TproxyErrorKind::DownstreamChannelNotOpened(3),
+            5,
+        );
+        assert!(!handle_error(&sv1_server, error).await);
+        assert!(matches!(
+            rx.try_recv().unwrap().state,
+            State::DownstreamShutdown {
+                downstream_id: 5,
+                reason: TproxyErrorKind::DownstreamChannelNotOpened(3),
+            }
+        ));
+
+        // log-only errors do not reach the main loop
+        let error = TproxyError::<error::Sv1Server>::log(TproxyErrorKind::ChannelErrorSender);
+        assert!(!handle_error(&sv1_server, error).await);
+        assert!(rx.is_empty());
+
+        // a fatal SV1 server error is reported as such
+        let error = TproxyError::<error::Sv1Server>::shutdown(TproxyErrorKind::ChannelErrorSender);
+        assert!(handle_error(&sv1_server, error).await);
+        assert!(matches!(
+            rx.try_recv().unwrap().state,
+            State::Sv1ServerShutdown(TproxyErrorKind::ChannelErrorSender)
+        ));
+
+        // a fatal channel manager error shuts the translator down
+        let channel_manager = StatusSender::ChannelManager(tx);
+        let error =
+            TproxyError::<error::ChannelManager>::shutdown(TproxyErrorKind::ChannelErrorSender);
+        assert!(handle_error(&channel_manager, error).await);
+        assert!(matches!(
+            rx.try_recv().unwrap().state,
+            State::ChannelManagerShutdown(TproxyErrorKind::ChannelErrorSender)
+        ));
+    }
+}
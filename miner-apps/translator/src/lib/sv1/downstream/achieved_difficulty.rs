@@ -0,0 +1,101 @@
+//! Difficulty achieved by the shares of a SV1 miner.
+//!
+//! The hashrate derived from the target assigned to a miner is only accurate when the miner
+//! submits every share meeting that target. A miner working at a higher difficulty of its own
+//! submits fewer shares, each of a higher difficulty, and is underestimated. Tracking the
+//! difficulty its shares actually achieved, along with how often it submits them, gives a
+//! hashrate estimate that does not depend on the assigned target.
+use std::time::Instant;
+
+use stratum_apps::utils::types::Hashrate;
+
+/// Weight of the latest share in the moving averages.
+const EWMA_WEIGHT: f64 = 0.1;
+
+/// Exponentially weighted moving averages of the difficulty achieved by the shares of a miner
+/// and of the time between them.
+#[derive(Debug, Default)]
+pub struct AchievedDifficulty {
+    // Average of the inverse of the share difficulties. The difficulty of a single share is
+    // unbounded, so it is averaged harmonically.
+    inverse_difficulty: Option<f64>,
+    // Average time between two shares, in seconds
+    share_interval: Option<f64>,
+    last_share_at: Option<Instant>,
+}
+
+impl AchievedDifficulty {
+    /// Records a share that achieved `difficulty`, submitted at `now`.
+    pub fn record_share(&mut self, difficulty: f64, now: Instant) {
+        if !difficulty.is_finite() || difficulty <= 0.0 {
+            return;
+        }
+        self.inverse_difficulty = Some(ewma(self.inverse_difficulty, difficulty.recip()));
+        if let Some(last_share_at) = self.last_share_at {
+            let interval = now.saturating_duration_since(last_share_at).as_secs_f64();
+            self.share_interval = Some(ewma(self.share_interval, interval));
+        }
+        self.last_share_at = Some(now);
+    }
+
+    /// Returns the average difficulty achieved by the shares, once a share was recorded.
+    pub fn difficulty(&self) -> Option<f64> {
+        self.inverse_difficulty.map(f64::recip)
+    }
+
+    /// Returns the hashrate estimated from the achieved difficulty and the share rate, once two
+    /// shares were recorded.
+    pub fn hashrate(&self) -> Option<Hashrate> {
+        let share_interval = self.share_interval.filter(|interval| *interval > 0.0)?;
+        // Shares meeting a difficulty D achieve a harmonic mean difficulty of 2D, and each took
+        // D * 2^32 hashes on average
+        let work_difficulty = self.difficulty()? / 2.0;
+        Some((work_difficulty * 2f64.powi(32) / share_interval) as Hashrate)
+    }
+}
+
+fn ewma(average: Option<f64>, sample: f64) -> f64 {
+    match average {
+        Some(average) => average + EWMA_WEIGHT * (sample - average),
+        None => sample,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn achieved_difficulty_converges_to_the_share_difficulty() {
+        let mut achieved = AchievedDifficulty::default();
+        assert_eq!(achieved.difficulty(), None);
+        assert_eq!(achieved.hashrate(), None);
+
+        let start = Instant::now();
+        achieved.record_share(1000.0, start);
+        assert_eq!(achieved.difficulty(), Some(1000.0));
+        assert_eq!(achieved.hashrate(), None);
+
+        // the miner moves to shares of difficulty 4000, one every 10 seconds
+        for i in 1..=100 {
+            achieved.record_share(4000.0, start + Duration::from_secs(10 * i));
+        }
+        let difficulty = achieved.difficulty().unwrap();
+        assert!((difficulty - 4000.0).abs() < 1.0, "{difficulty}");
+        let expected_hashrate = 2000.0 * 2f64.powi(32) / 10.0;
+        let hashrate = achieved.hashrate().unwrap() as f64;
+        assert!(
+            (hashrate - expected_hashrate).abs() / expected_hashrate < 1e-3,
+            "{hashrate}"
+        );
+    }
+
+    #[test]
+    fn invalid_difficulties_are_ignored() {
+        let mut achieved = AchievedDifficulty::default();
+        achieved.record_share(0.0, Instant::now());
+        achieved.record_share(f64::INFINITY, Instant::now());
+        assert_eq!(achieved.difficulty(), None);
+    }
+}
@@ -1,8 +1,13 @@
-use std::time::Instant;
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    time::Instant,
+};
 use stratum_apps::{
     stratum_core::{
         bitcoin::Target,
         sv1_api::{
+            client_to_server::Submit,
             json_rpc,
             utils::{Extranonce, HexU32Be},
         },
@@ -11,11 +16,37 @@ use stratum_apps::{
 };
 use tracing::debug;
 
-use super::SubmitShareWithChannelId;
+use super::{achieved_difficulty::AchievedDifficulty, ShareRejection, SubmitShareWithChannelId};
+use crate::utils::AGGREGATED_CHANNEL_ID;
+
+/// The fields identifying a share submitted on a job.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SubmittedShare {
+    pub job_id: String,
+    pub extranonce2: Vec<u8>,
+    pub ntime: u32,
+    pub nonce: u32,
+    pub version_bits: Option<u32>,
+}
+
+impl From<&Submit<'static>> for SubmittedShare {
+    fn from(share: &Submit<'static>) -> Self {
+        SubmittedShare {
+            job_id: share.job_id.clone(),
+            extranonce2: share.extra_nonce2.0.as_ref().to_vec(),
+            ntime: share.time.0,
+            nonce: share.nonce.0,
+            version_bits: share.version_bits.as_ref().map(|bits| bits.0),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct DownstreamData {
     pub channel_id: Option<ChannelId>,
+    // In aggregated mode, the aggregated channel the channel of the miner was opened on, whose
+    // jobs and targets the miner follows
+    pub aggregated_channel_id: ChannelId,
     pub extranonce1: Extranonce<'static>,
     pub extranonce2_len: usize,
     pub target: Target,
@@ -25,24 +56,75 @@ pub struct DownstreamData {
     pub last_job_version_field: Option<u32>,
     pub authorized_worker_name: String,
     pub user_identity: String,
+    // IP address the miner connected from, which together with the authorized worker name
+    // identifies the miner across reconnections
+    pub peer_ip: Option<IpAddr>,
+    // Monitoring group label derived from the authorized worker name by the `downstream_groups`
+    // rules
+    pub group_label: Option<String>,
+    // Workers authorized after the first one on this connection, with their user identity. They
+    // share the channel opened for the connection.
+    pub additional_workers: HashMap<String, String>,
+    // Per-miner counter used to render the user identity
+    pub miner_id: u32,
     pub cached_set_difficulty: Option<json_rpc::Message>,
     pub cached_notify: Option<json_rpc::Message>,
+    // Whether the miner was sent a `mining.set_difficulty`, which has to precede its first
+    // `mining.notify`
+    pub difficulty_sent: bool,
     pub pending_target: Option<Target>,
     pub pending_hashrate: Option<Hashrate>,
     // Queue of Sv1 handshake messages received while waiting for SV2 channel to open
     pub queued_sv1_handshake_messages: Vec<json_rpc::Message>,
+    // Channel openings for this downstream that were refused or timed out
+    pub failed_open_channel_attempts: u32,
+    // Whether a channel is being opened on the upstream the authorized worker is routed to, for
+    // the miner to move onto it
+    pub reopening_channel: bool,
+    // Whether the miner waits for a channel on the upstream the translator rotated to, its
+    // channel, if any, being on the upstream it left
+    pub rotating_upstream: bool,
+    // Worker whose retained channel the miner rejoined on `mining.subscribe`, which it has to
+    // authorize first
+    pub rejoined_worker_name: Option<String>,
+    // Since when the channel waits for `mining.authorize` to be requested, for the user identity
+    // template to render the worker name
+    pub awaiting_worker_name_since: Option<Instant>,
     // Stores pending shares to be sent to the sv1_server
     pub pending_share: Option<SubmitShareWithChannelId>,
+    // Reason the last submitted share was rejected, answered to the miner as a submit error
+    pub share_rejection: Option<ShareRejection>,
+    // Shares accepted on the jobs that are still valid, used to reject duplicates
+    pub submitted_shares: HashSet<SubmittedShare>,
+    // Extranonce2 size the miner suggested in its `mining.configure`
+    pub suggested_extranonce2_size: Option<usize>,
+    // Whether the miner subscribed to `mining.set_extranonce` with `mining.extranonce.subscribe`
+    pub extranonce_subscribed: bool,
+    // Jobs notified before the last extranonce1 change, whose shares are rejected as stale
+    pub stale_job_ids: HashSet<String>,
+    // When the miner sent its first `mining.subscribe`, used to disconnect miners that do not
+    // authorize within the grace period
+    pub subscribed_at: Option<Instant>,
+    // Whether the miner sent a `mining.authorize`
+    pub authorize_received: bool,
     // Tracks the upstream target for this downstream, used for vardiff target comparison
     pub upstream_target: Option<Target>,
     // Timestamp of when the last job was received by this downstream, used for keepalive check
     pub last_job_received_time: Option<Instant>,
+    // Number of times this downstream lagged behind the SV1 server broadcast and was re-synced
+    pub lagged_total: u64,
+    // Difficulty achieved by the accepted shares of the miner, for a hashrate estimate that does
+    // not depend on its target
+    pub achieved_difficulty: AchievedDifficulty,
+    // When the miner connected, used to hold its initial difficulty during the vardiff warmup
+    pub connected_at: Instant,
 }
 
 impl DownstreamData {
     pub fn new(hashrate: Option<Hashrate>, target: Target) -> Self {
         DownstreamData {
             channel_id: None,
+            aggregated_channel_id: AGGREGATED_CHANNEL_ID,
             extranonce1: vec![0; 8]
                 .try_into()
                 .expect("8-byte extranonce is always valid"),
@@ -54,17 +136,50 @@ impl DownstreamData {
             last_job_version_field: None,
             authorized_worker_name: String::new(),
             user_identity: String::new(),
+            peer_ip: None,
+            group_label: None,
+            additional_workers: HashMap::new(),
+            miner_id: 0,
             cached_set_difficulty: None,
             cached_notify: None,
+            difficulty_sent: false,
             pending_target: None,
             pending_hashrate: None,
             queued_sv1_handshake_messages: Vec::new(),
+            failed_open_channel_attempts: 0,
+            reopening_channel: false,
+            rotating_upstream: false,
+            rejoined_worker_name: None,
+            awaiting_worker_name_since: None,
             pending_share: None,
+            share_rejection: None,
+            submitted_shares: HashSet::new(),
+            suggested_extranonce2_size: None,
+            extranonce_subscribed: false,
+            stale_job_ids: HashSet::new(),
+            subscribed_at: None,
+            authorize_received: false,
             upstream_target: None,
             last_job_received_time: None,
+            lagged_total: 0,
+            achieved_difficulty: AchievedDifficulty::default(),
+            connected_at: Instant::now(),
         }
     }
 
+    /// Returns whether `worker_name` was authorized on this connection.
+    pub fn is_worker_authorized(&self, worker_name: &str) -> bool {
+        self.authorized_worker_name == worker_name
+            || self.additional_workers.contains_key(worker_name)
+    }
+
+    /// Returns the user identity the shares of `worker_name` are submitted with.
+    pub fn worker_user_identity(&self, worker_name: &str) -> &str {
+        self.additional_workers
+            .get(worker_name)
+            .unwrap_or(&self.user_identity)
+    }
+
     pub fn set_pending_target(&mut self, new_target: Target, downstream_id: DownstreamId) {
         self.pending_target = Some(new_target);
         debug!("Downstream {downstream_id}: Set pending target");
@@ -1,19 +1,28 @@
 use crate::{
+    config::DifficultyQuantization,
     error::{self, TproxyError, TproxyErrorKind, TproxyResult},
+    is_aggregated,
     status::{handle_error, StatusSender},
-    sv1::downstream::{channel::DownstreamChannelState, data::DownstreamData},
-    utils::{ShutdownMessage, AGGREGATED_CHANNEL_ID},
+    sv1::{
+        downstream::{channel::DownstreamChannelState, data::DownstreamData},
+        sv1_server::job_propagation::JobPropagationTracker,
+    },
+    utils::{build_sv1_set_difficulty, ShutdownMessage},
 };
 use async_channel::{Receiver, Sender};
+use dashmap::DashMap;
 use std::{
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::Instant,
+    time::{Duration, Instant},
 };
 use stratum_apps::{
     custom_mutex::Mutex,
+    network_helpers::slow_consumer::{
+        SlowConsumerDetector, SlowConsumerPolicy, SLOW_CONSUMER_CHECK_INTERVAL,
+    },
     stratum_core::{
         bitcoin::Target,
         sv1_api::{
@@ -25,7 +34,10 @@ use stratum_apps::{
     utils::types::{ChannelId, DownstreamId, Hashrate},
 };
 use tokio::sync::{broadcast, mpsc};
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, field, info, info_span, warn, Instrument as _, Span};
+
+// How often a downstream checks that its miner authorized within the grace period.
+const AUTHORIZE_GRACE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Represents a downstream SV1 miner connection.
 ///
@@ -49,6 +61,18 @@ pub struct Downstream {
     pub sv1_handshake_complete: Arc<AtomicBool>,
     // Flag to indicate we're processing queued Sv1 handshake message responses
     pub processing_queued_sv1_handshake_responses: Arc<AtomicBool>,
+    // Shared with the SV1 server to time the propagation of each job to its downstreams
+    pub job_propagation: Arc<JobPropagationTracker>,
+    // Shared with the SV1 server to re-sync this downstream to the latest job after it lagged
+    // behind the server broadcast
+    pub valid_sv1_jobs: Arc<DashMap<ChannelId, Vec<server_to_client::Notify<'static>>>>,
+    // Flags the miner as a slow consumer when the messages waiting to be sent to it pile up
+    pub slow_consumer: Arc<SlowConsumerDetector>,
+    // Grid the difficulty sent to the miner when re-syncing it is snapped to
+    pub difficulty_quantization: DifficultyQuantization,
+    // Span enclosing the handling of this connection, with the `downstream_id`, `peer_addr` and
+    // `worker_name` fields to filter the logs of a miner. Closed once the downstream is dropped.
+    pub span: Span,
 }
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -67,6 +91,8 @@ impl Downstream {
         )>,
         target: Target,
         hashrate: Option<Hashrate>,
+        job_propagation: Arc<JobPropagationTracker>,
+        valid_sv1_jobs: Arc<DashMap<ChannelId, Vec<server_to_client::Notify<'static>>>>,
     ) -> Self {
         let downstream_data = Arc::new(Mutex::new(DownstreamData::new(hashrate, target)));
         let downstream_channel_state = DownstreamChannelState::new(
@@ -81,9 +107,25 @@ impl Downstream {
             downstream_channel_state,
             sv1_handshake_complete: Arc::new(AtomicBool::new(false)),
             processing_queued_sv1_handshake_responses: Arc::new(AtomicBool::new(false)),
+            job_propagation,
+            valid_sv1_jobs,
+            slow_consumer: Arc::new(SlowConsumerDetector::new()),
+            difficulty_quantization: DifficultyQuantization::default(),
+            span: info_span!(
+                "downstream",
+                downstream_id,
+                peer_addr = field::Empty,
+                worker_name = field::Empty
+            ),
         }
     }
 
+    /// Sets the grid the difficulty sent to the miner is snapped to.
+    pub fn with_difficulty_quantization(mut self, quantization: DifficultyQuantization) -> Self {
+        self.difficulty_quantization = quantization;
+        self
+    }
+
     /// Spawns and runs the main task loop for this downstream connection.
     ///
     /// This method creates an async task that handles all communication for this
@@ -91,6 +133,8 @@ impl Downstream {
     /// - Shutdown signals (global, targeted, or all-downstream)
     /// - Messages from the miner (subscribe, authorize, submit)
     /// - Messages from the SV1 server (notify, set_difficulty, etc.)
+    /// - Periodic checks of the queue towards the miner, when `slow_consumer_policy` is set
+    /// - Periodic checks that the miner authorized in time, when `authorize_grace` is set
     ///
     /// The task will continue running until a shutdown signal is received or
     /// an unrecoverable error occurs. It ensures graceful cleanup of resources
@@ -101,6 +145,8 @@ impl Downstream {
         shutdown_complete_tx: mpsc::Sender<()>,
         status_sender: StatusSender,
         task_manager: Arc<TaskManager>,
+        slow_consumer_policy: Option<SlowConsumerPolicy>,
+        authorize_grace: Option<Duration>,
     ) {
         let mut sv1_server_receiver = self
             .downstream_channel_state
@@ -108,7 +154,10 @@ impl Downstream {
             .subscribe();
         let mut shutdown_rx = notify_shutdown.subscribe();
         let downstream_id = self.downstream_id;
-        task_manager.spawn(async move {
+        let span = self.span.clone();
+        let task = async move {
+            let mut slow_consumer_check = tokio::time::interval(SLOW_CONSUMER_CHECK_INTERVAL);
+            let mut authorize_grace_check = tokio::time::interval(AUTHORIZE_GRACE_CHECK_INTERVAL);
             loop {
                 tokio::select! {
                     msg = shutdown_rx.recv() => {
@@ -156,6 +205,26 @@ impl Downstream {
                         }
                     }
 
+                    _ = slow_consumer_check.tick(), if slow_consumer_policy.is_some() => {
+                        if let Some(policy) = &slow_consumer_policy {
+                            if let Err(e) = self.check_slow_consumer(policy) {
+                                if handle_error(&status_sender, e).await {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+
+                    _ = authorize_grace_check.tick(), if authorize_grace.is_some() => {
+                        if let Some(grace) = authorize_grace {
+                            if let Err(e) = self.check_authorize_grace(grace) {
+                                if handle_error(&status_sender, e).await {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+
                     else => {
                         warn!("Downstream {downstream_id}: all channels closed; exiting task");
                         break;
@@ -166,7 +235,8 @@ impl Downstream {
             warn!("Downstream {downstream_id}: unified task shutting down");
             self.downstream_channel_state.drop();
             drop(shutdown_complete_tx);
-        });
+        };
+        task_manager.spawn(task.instrument(span));
     }
 
     /// Handles messages received from the SV1 server.
@@ -185,6 +255,8 @@ impl Downstream {
     ///   complete
     /// - On handshake completion: sends cached messages in correct order (set_difficulty first,
     ///   then notify)
+    /// - If this downstream lagged behind the broadcast, re-syncs it to the latest job instead of
+    ///   disconnecting it
     pub async fn handle_sv1_server_message(
         &self,
         sv1_server_receiver: &mut broadcast::Receiver<(
@@ -195,12 +267,19 @@ impl Downstream {
     ) -> TproxyResult<(), error::Downstream> {
         match sv1_server_receiver.recv().await {
             Ok((channel_id, downstream_id, message)) => {
-                let my_channel_id = self.downstream_data.super_safe_lock(|d| d.channel_id);
+                let (my_channel_id, my_aggregated_channel_id, rotating_upstream) =
+                    self.downstream_data.super_safe_lock(|d| {
+                        (d.channel_id, d.aggregated_channel_id, d.rotating_upstream)
+                    });
                 let my_downstream_id = self.downstream_id;
                 let handshake_complete = self.sv1_handshake_complete.load(Ordering::SeqCst);
+                // Until the miner moved onto the upstream the translator rotated to, its channel ID
+                // is one of the upstream left, which the next upstream may give to another channel,
+                // so it only gets the messages sent to it
                 let id_matches = (my_channel_id == Some(channel_id)
-                    || channel_id == AGGREGATED_CHANNEL_ID)
-                    && (downstream_id.is_none() || downstream_id == Some(my_downstream_id));
+                    || channel_id == my_aggregated_channel_id)
+                    && ((downstream_id.is_none() && !rotating_upstream)
+                        || downstream_id == Some(my_downstream_id));
                 if !id_matches {
                     return Ok(()); // Message not intended for this downstream
                 }
@@ -228,37 +307,31 @@ impl Downstream {
                             "mining.notify" => {
                                 let (pending_set_difficulty, notify_opt) =
                                     self.downstream_data.super_safe_lock(|d| {
-                                        let cached_set_difficulty = d.cached_set_difficulty.take();
+                                        let set_difficulty =
+                                            self.take_set_difficulty_before_notify(d)?;
 
                                         // Prepare the notify message and update state
-                                        let notify_result = server_to_client::Notify::try_from(
+                                        let notify = server_to_client::Notify::try_from(
                                             notification.clone(),
-                                        );
-                                        if let Ok(mut notify) = notify_result {
-                                            if cached_set_difficulty.is_some() {
-                                                notify.clean_jobs = true;
-                                            }
-                                            d.last_job_version_field = Some(notify.version.0);
-
-                                            // Update target and hashrate if we're sending
-                                            // set_difficulty
-                                            if cached_set_difficulty.is_some() {
-                                                if let Some(new_target) = d.pending_target.take() {
-                                                    d.target = new_target;
-                                                }
-                                                if let Some(new_hashrate) =
-                                                    d.pending_hashrate.take()
-                                                {
-                                                    d.hashrate = Some(new_hashrate);
+                                        )
+                                        .ok()
+                                        .map(
+                                            |mut notify| {
+                                                if set_difficulty.is_some() {
+                                                    notify.clean_jobs = true;
                                                 }
-                                            }
-                                            // Update last job received time for keepalive tracking
-                                            d.last_job_received_time = Some(Instant::now());
-                                            (cached_set_difficulty, Some(notify))
-                                        } else {
-                                            (cached_set_difficulty, None)
-                                        }
-                                    });
+                                                d.last_job_version_field = Some(notify.version.0);
+                                                // Update last job received time for keepalive
+                                                // tracking
+                                                d.last_job_received_time = Some(Instant::now());
+                                                notify
+                                            },
+                                        );
+                                        TproxyResult::<_, error::Downstream>::Ok((
+                                            set_difficulty,
+                                            notify,
+                                        ))
+                                    })?;
 
                                 if let Some(set_difficulty_msg) = &pending_set_difficulty {
                                     debug!("Down: Sending pending mining.set_difficulty before mining.notify");
@@ -277,6 +350,7 @@ impl Downstream {
 
                                 if let Some(notify) = notify_opt {
                                     debug!("Down: Sending mining.notify");
+                                    let job_id = notify.job_id.clone();
                                     self.downstream_channel_state
                                         .downstream_sv1_sender
                                         .send(notify.into())
@@ -285,6 +359,11 @@ impl Downstream {
                                             error!("Down: Failed to send mining.notify to downstream: {:?}", e);
                                             TproxyError::disconnect(TproxyErrorKind::ChannelErrorSender, downstream_id.unwrap_or(0))
                                         })?;
+                                    self.job_propagation.notify_sent(
+                                        channel_id,
+                                        &job_id,
+                                        my_downstream_id,
+                                    );
                                 }
                                 return Ok(());
                             }
@@ -351,6 +430,9 @@ impl Downstream {
                     debug!("Down: SV1 handshake not complete, skipping non-notification message");
                 }
             }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                return self.resync_after_lag(skipped).await;
+            }
             Err(e) => {
                 let downstream_id = self.downstream_id;
                 error!(
@@ -364,6 +446,124 @@ impl Downstream {
         Ok(())
     }
 
+    /// Re-syncs this downstream after it lagged `skipped` messages behind the SV1 server
+    /// broadcast.
+    ///
+    /// The skipped messages may have included a `mining.set_difficulty` or a new prevhash, so the
+    /// miner is sent its current difficulty followed by the latest job with `clean_jobs` set.
+    /// Before the handshake completes the latest job only replaces the cached notify.
+    async fn resync_after_lag(&self, skipped: u64) -> TproxyResult<(), error::Downstream> {
+        let downstream_id = self.downstream_id;
+        let (channel_id, aggregated_channel_id, lagged_total) =
+            self.downstream_data.super_safe_lock(|d| {
+                d.lagged_total += 1;
+                (d.channel_id, d.aggregated_channel_id, d.lagged_total)
+            });
+        warn!(
+            "Downstream {downstream_id}: lagged {skipped} messages behind the SV1 server \
+             ({lagged_total} times so far), re-syncing to the latest job"
+        );
+
+        let job_key = if is_aggregated() {
+            Some(aggregated_channel_id)
+        } else {
+            channel_id
+        };
+        let Some(mut last_job) = job_key.and_then(|key| {
+            self.valid_sv1_jobs
+                .get(&key)
+                .and_then(|jobs| jobs.last().cloned())
+        }) else {
+            debug!("Downstream {downstream_id}: no job to re-sync to yet");
+            return Ok(());
+        };
+        last_job.clean_jobs = true;
+
+        if !self.sv1_handshake_complete.load(Ordering::SeqCst) {
+            self.downstream_data.super_safe_lock(|d| {
+                d.last_job_version_field = Some(last_job.version.0);
+                d.cached_notify = Some(last_job.into());
+            });
+            return Ok(());
+        }
+
+        let target = self.downstream_data.super_safe_lock(|d| {
+            d.cached_set_difficulty = None;
+            d.difficulty_sent = true;
+            if let Some(new_target) = d.pending_target.take() {
+                d.target = new_target;
+            }
+            if let Some(new_hashrate) = d.pending_hashrate.take() {
+                d.hashrate = Some(new_hashrate);
+            }
+            d.last_job_version_field = Some(last_job.version.0);
+            d.last_job_received_time = Some(Instant::now());
+            d.target
+        });
+        let set_difficulty = build_sv1_set_difficulty(target, self.difficulty_quantization)
+            .map_err(|e| TproxyError::disconnect(e, downstream_id))?;
+        for message in [set_difficulty, last_job.into()] {
+            self.downstream_channel_state
+                .downstream_sv1_sender
+                .send(message)
+                .await
+                .map_err(|e| {
+                    error!("Down: Failed to re-sync downstream {downstream_id}: {e:?}");
+                    TproxyError::disconnect(TproxyErrorKind::ChannelErrorSender, downstream_id)
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Samples the number of messages waiting to be sent to the miner, flagging it as a slow
+    /// consumer once the queue stayed above the threshold of `policy` for long enough.
+    ///
+    /// A flagged miner is disconnected if `policy` says so.
+    pub fn check_slow_consumer(
+        &self,
+        policy: &SlowConsumerPolicy,
+    ) -> TproxyResult<(), error::Downstream> {
+        let queue_depth = self.downstream_channel_state.downstream_sv1_sender.len();
+        if !self
+            .slow_consumer
+            .observe(queue_depth, policy, Instant::now())
+        {
+            return Ok(());
+        }
+        warn!(
+            "Downstream {}: slow consumer, {queue_depth} messages queued for more than {:?}",
+            self.downstream_id, policy.duration
+        );
+        if policy.disconnect {
+            return Err(TproxyError::disconnect(
+                TproxyErrorKind::SlowConsumer(queue_depth),
+                self.downstream_id,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that a miner which subscribed sent `mining.authorize` within `grace`, disconnecting
+    /// it otherwise.
+    pub fn check_authorize_grace(&self, grace: Duration) -> TproxyResult<(), error::Downstream> {
+        let expired = self.downstream_data.super_safe_lock(|d| {
+            !d.authorize_received
+                && d.subscribed_at
+                    .is_some_and(|subscribed_at| subscribed_at.elapsed() >= grace)
+        });
+        if !expired {
+            return Ok(());
+        }
+        warn!(
+            "Downstream {}: no mining.authorize within {:?} of mining.subscribe",
+            self.downstream_id, grace
+        );
+        Err(TproxyError::disconnect(
+            TproxyErrorKind::AuthorizeGraceExpired,
+            self.downstream_id,
+        ))
+    }
+
     /// Handles messages received from the downstream SV1 miner.
     ///
     /// This method processes SV1 protocol messages sent by the miner, including:
@@ -391,6 +591,18 @@ impl Downstream {
             }
         };
 
+        if let Message::StandardRequest(request) = &message {
+            match request.method.as_str() {
+                "mining.subscribe" => self.downstream_data.super_safe_lock(|d| {
+                    d.subscribed_at.get_or_insert_with(Instant::now);
+                }),
+                "mining.authorize" => self
+                    .downstream_data
+                    .super_safe_lock(|d| d.authorize_received = true),
+                _ => {}
+            }
+        }
+
         self.downstream_channel_state
             .sv1_server_sender
             .send((downstream_id, message))
@@ -403,23 +615,20 @@ impl Downstream {
     /// Handles SV1 handshake completion after mining.authorize.
     ///
     /// This method is called when the downstream completes the SV1 handshake
-    /// (subscribe + authorize). It sends any cached messages in the correct order:
-    /// set_difficulty first, then notify.
+    /// (subscribe + authorize). It sends the difficulty of the miner, cached or current, then the
+    /// cached notify, so the miner never gets a job before its difficulty.
     pub async fn handle_sv1_handshake_completion(&self) -> TproxyResult<(), error::Downstream> {
-        let (cached_set_difficulty, cached_notify, downstream_id) =
-            self.downstream_data.super_safe_lock(|d| {
-                self.sv1_handshake_complete
-                    .store(true, std::sync::atomic::Ordering::SeqCst);
-                (
-                    d.cached_set_difficulty.take(),
-                    d.cached_notify.take(),
-                    self.downstream_id,
-                )
-            });
+        let downstream_id = self.downstream_id;
+        let (set_difficulty, cached_notify) = self.downstream_data.super_safe_lock(|d| {
+            self.sv1_handshake_complete
+                .store(true, std::sync::atomic::Ordering::SeqCst);
+            let set_difficulty = self.take_set_difficulty_before_notify(d)?;
+            TproxyResult::<_, error::Downstream>::Ok((set_difficulty, d.cached_notify.take()))
+        })?;
         debug!("Down: SV1 handshake completed for downstream");
 
         // Send cached messages in correct order: set_difficulty first, then notify
-        if let Some(set_difficulty_msg) = cached_set_difficulty {
+        if let Some(set_difficulty_msg) = set_difficulty {
             debug!("Down: Sending cached mining.set_difficulty after handshake completion");
             self.downstream_channel_state
                 .downstream_sv1_sender
@@ -432,16 +641,6 @@ impl Downstream {
                     );
                     TproxyError::disconnect(TproxyErrorKind::ChannelErrorSender, downstream_id)
                 })?;
-
-            // Update target and hashrate after sending set_difficulty
-            self.downstream_data.super_safe_lock(|d| {
-                if let Some(new_target) = d.pending_target.take() {
-                    d.target = new_target;
-                }
-                if let Some(new_hashrate) = d.pending_hashrate.take() {
-                    d.hashrate = Some(new_hashrate);
-                }
-            });
         }
 
         if let Some(notify_msg) = cached_notify {
@@ -465,4 +664,31 @@ impl Downstream {
 
         Ok(())
     }
+
+    // Takes the `mining.set_difficulty` to send right before a `mining.notify`: the cached one, or
+    // the current difficulty if the miner never received one, as some miners mishandle a notify
+    // arriving first. Applies the pending target and hashrate a cached message carries.
+    fn take_set_difficulty_before_notify(
+        &self,
+        d: &mut DownstreamData,
+    ) -> TproxyResult<Option<json_rpc::Message>, error::Downstream> {
+        let set_difficulty = match d.cached_set_difficulty.take() {
+            Some(set_difficulty) => {
+                if let Some(new_target) = d.pending_target.take() {
+                    d.target = new_target;
+                }
+                if let Some(new_hashrate) = d.pending_hashrate.take() {
+                    d.hashrate = Some(new_hashrate);
+                }
+                set_difficulty
+            }
+            None if !d.difficulty_sent => {
+                build_sv1_set_difficulty(d.target, self.difficulty_quantization)
+                    .map_err(|e| TproxyError::disconnect(e, self.downstream_id))?
+            }
+            None => return Ok(None),
+        };
+        d.difficulty_sent = true;
+        Ok(Some(set_difficulty))
+    }
 }
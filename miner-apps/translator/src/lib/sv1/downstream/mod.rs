@@ -1,9 +1,10 @@
+pub(super) mod achieved_difficulty;
 pub(super) mod channel;
 pub(super) mod data;
 pub mod downstream;
 
 use stratum_apps::{
-    stratum_core::sv1_api::{client_to_server::Submit, utils::HexU32Be},
+    stratum_core::sv1_api::{client_to_server::Submit, json_rpc, utils::HexU32Be},
     utils::types::{ChannelId, DownstreamId},
 };
 
@@ -43,3 +44,58 @@ pub struct SubmitShareWithChannelId {
     /// The version field from the job, used for validation
     pub job_version: Option<u32>,
 }
+
+/// Reason a `mining.submit` was rejected by the translator without being forwarded upstream.
+///
+/// Each reason maps to the error code stratum pools commonly use for it, so miner software can
+/// report rejected shares by reason.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShareRejection {
+    /// The share could not be validated for a reason without a dedicated code.
+    Other,
+    /// The share references a job that is unknown or stale.
+    JobNotFound,
+    /// The same share was already submitted.
+    Duplicate,
+    /// The share hash does not meet the downstream target.
+    LowDifficulty,
+    /// The share ntime is outside the window allowed for its job.
+    NtimeOutOfRange,
+}
+
+impl ShareRejection {
+    /// Returns the stratum error code of the rejection.
+    pub fn code(&self) -> i32 {
+        match self {
+            ShareRejection::Other => 20,
+            ShareRejection::JobNotFound => 21,
+            ShareRejection::Duplicate => 22,
+            ShareRejection::LowDifficulty => 23,
+            ShareRejection::NtimeOutOfRange => 20,
+        }
+    }
+
+    /// Returns the error message of the rejection.
+    pub fn message(&self) -> &'static str {
+        match self {
+            ShareRejection::Other => "Other/Unknown",
+            ShareRejection::JobNotFound => "Job not found",
+            ShareRejection::Duplicate => "Duplicate share",
+            ShareRejection::LowDifficulty => "Low difficulty share",
+            ShareRejection::NtimeOutOfRange => "Ntime out of range",
+        }
+    }
+
+    /// Builds the error response to the `mining.submit` request with the given `id`.
+    pub fn response(&self, id: u64) -> json_rpc::Message {
+        json_rpc::Message::ErrorResponse(json_rpc::Response {
+            id,
+            error: Some(json_rpc::JsonRpcError {
+                code: self.code(),
+                message: self.message().to_string(),
+                data: None,
+            }),
+            result: serde_json::Value::Null,
+        })
+    }
+}
@@ -0,0 +1,138 @@
+//! Affinity of SV1 workers to their channel in aggregated mode.
+//!
+//! In aggregated mode, each miner mines on its own channel carved out of the aggregated one, with
+//! its own extranonce1. A reconnecting ASIC would otherwise get a new channel and extranonce
+//! region every time. The channel of a disconnected worker is kept for a while, keyed like a
+//! retained session by the session id handed out to its miner and its IP address. A
+//! `mining.subscribe` presenting that session id from the same address in time rejoins the
+//! channel, and the miner has to authorize the same worker first.
+use std::{net::IpAddr, time::Duration};
+
+use dashmap::DashMap;
+use stratum_apps::utils::types::ChannelId;
+
+use crate::sv1::sv1_server::session_resumption::{session_id, RetainedSession};
+
+/// Channels of disconnected workers, kept for a fixed time after they disconnect.
+#[derive(Debug)]
+pub struct ChannelAffinity {
+    ttl: Duration,
+    // Worker and channel of each session id and IP address
+    retained: DashMap<(String, IpAddr), (String, RetainedSession)>,
+}
+
+impl ChannelAffinity {
+    /// Creates a store keeping each channel for `ttl` after its worker disconnected.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            retained: DashMap::new(),
+        }
+    }
+
+    /// Keeps the channel of `worker_name`, disconnecting from the IP address of `session`, under
+    /// the session id of its extranonce1.
+    pub fn retain(&self, worker_name: String, session: RetainedSession) {
+        self.retained.insert(
+            (session_id(&session.extranonce1), session.peer_ip),
+            (worker_name, session),
+        );
+    }
+
+    /// Takes the channel of session `session_id` rejoined from `peer_ip`, with the worker it was
+    /// retained for, if it disconnected less than the retention time ago.
+    pub fn rejoin(&self, session_id: &str, peer_ip: IpAddr) -> Option<(String, RetainedSession)> {
+        let (_, retained) = self
+            .retained
+            .remove_if(&(session_id.to_string(), peer_ip), |_, (_, session)| {
+                session.retained_for() < self.ttl
+            })?;
+        Some(retained)
+    }
+
+    /// Removes and returns the channels retained for longer than the retention time.
+    pub fn take_expired(&self) -> Vec<RetainedSession> {
+        let expired: Vec<(String, IpAddr)> = self
+            .retained
+            .iter()
+            .filter(|retained| retained.value().1.retained_for() >= self.ttl)
+            .map(|retained| retained.key().clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|key| self.retained.remove(&key))
+            .map(|(_, (_, session))| session)
+            .collect()
+    }
+
+    /// Returns whether `channel_id` is retained for a worker.
+    pub fn is_retained(&self, channel_id: ChannelId) -> bool {
+        self.retained
+            .iter()
+            .any(|retained| retained.value().1.channel_id == channel_id)
+    }
+
+    /// Drops the channel `channel_id`, e.g. once it was closed by the upstream.
+    pub fn remove_channel(&self, channel_id: ChannelId) {
+        self.retained
+            .retain(|_, (_, session)| session.channel_id != channel_id);
+    }
+
+    /// Drops every retained channel.
+    pub fn clear(&self) {
+        self.retained.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn session(channel_id: ChannelId, ip: IpAddr) -> RetainedSession {
+        let extranonce1 = vec![0, 0, 0, channel_id as u8].try_into().unwrap();
+        RetainedSession::new(channel_id, extranonce1, 4, None, ip)
+    }
+
+    #[test]
+    fn worker_rejoins_its_channel_from_same_ip_once() {
+        let affinity = ChannelAffinity::new(Duration::from_secs(60));
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        affinity.retain("alice".to_string(), session(7, ip));
+        assert!(affinity.is_retained(7));
+
+        assert!(affinity
+            .rejoin("00000007", IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+            .is_none());
+        assert!(affinity.rejoin("00000008", ip).is_none());
+        let (worker_name, rejoined) = affinity.rejoin("00000007", ip).unwrap();
+        assert_eq!(worker_name, "alice");
+        assert_eq!(rejoined.channel_id, 7);
+        assert!(affinity.rejoin("00000007", ip).is_none());
+        assert!(!affinity.is_retained(7));
+    }
+
+    #[test]
+    fn channels_of_a_worker_are_retained_by_session() {
+        let affinity = ChannelAffinity::new(Duration::from_secs(60));
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        affinity.retain("alice".to_string(), session(7, ip));
+        affinity.retain("alice".to_string(), session(8, ip));
+        assert_eq!(affinity.rejoin("00000008", ip).unwrap().1.channel_id, 8);
+        assert_eq!(affinity.rejoin("00000007", ip).unwrap().1.channel_id, 7);
+    }
+
+    #[test]
+    fn expired_channel_is_not_rejoined() {
+        let affinity = ChannelAffinity::new(Duration::from_millis(10));
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        affinity.retain("alice".to_string(), session(7, ip));
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(affinity.rejoin("00000007", ip).is_none());
+        let expired = affinity.take_expired();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].channel_id, 7);
+        assert!(affinity.take_expired().is_empty());
+    }
+}
@@ -1,4 +1,7 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeSet, HashSet},
+    sync::Arc,
+};
 
 use crate::{is_aggregated, is_non_aggregated, sv1::sv1_server::sv1_server::PendingTargetUpdate};
 
@@ -8,7 +11,6 @@ use stratum_apps::{
         channels_sv2::{target::hash_rate_to_target, Vardiff},
         mining_sv2::{SetTarget, UpdateChannel},
         parsers_sv2::Mining,
-        stratum_translation::sv2_to_sv1::build_sv1_set_difficulty_from_sv2_target,
     },
     utils::types::{ChannelId, DownstreamId, Hashrate},
 };
@@ -51,9 +53,13 @@ impl Sv1Server {
     ///    - If new_target < upstream_target: wait for SetTarget response before sending
     ///      set_difficulty
     /// 4. Handle aggregated vs non-aggregated modes for UpdateChannel messages
+    ///
+    /// Downstreams connected for less than `vardiff_warmup_secs` keep their initial difficulty.
     async fn handle_vardiff_updates(&self) {
         let mut immediate_updates = Vec::new();
         let mut all_updates = Vec::new(); // All updates will generate UpdateChannel messages
+        let shares_per_minute = self.hot_config.shares_per_minute();
+        let warmup = self.config.downstream_difficulty_config.vardiff_warmup();
 
         for vardiff_key_pair in self.vardiff.iter() {
             let downstream_id = vardiff_key_pair.key();
@@ -62,7 +68,7 @@ impl Sv1Server {
             let Some(downstream) = self.downstreams.get(downstream_id) else {
                 continue;
             };
-            let (channel_id, hashrate, target, upstream_target) =
+            let (channel_id, hashrate, target, upstream_target, connected_at, rotating_upstream) =
                 downstream.downstream_data.super_safe_lock(|data| {
                     // It's safe to unwrap hashrate because we know that
                     // the downstream has a hashrate (we are
@@ -72,21 +78,47 @@ impl Sv1Server {
                         data.hashrate.unwrap(),
                         data.target,
                         data.upstream_target,
+                        data.connected_at,
+                        data.rotating_upstream,
                     )
                 });
 
+            // The difficulty follows the shares again once the miner moved onto the upstream the
+            // translator rotated to
+            if rotating_upstream {
+                debug!(
+                    "Downstream {} is moving to another upstream, keeping its difficulty",
+                    downstream_id
+                );
+                continue;
+            }
+
+            if connected_at.elapsed() < warmup {
+                debug!(
+                    "Downstream {} is still warming up, keeping its initial difficulty",
+                    downstream_id
+                );
+                // The shares of the ramp up are not accounted for once the warmup ends
+                if let Err(e) = vardiff.super_safe_lock(|state| state.reset_counter()) {
+                    warn!(
+                        "Failed to reset vardiff counter of downstream {}: {:?}",
+                        downstream_id, e
+                    );
+                }
+                continue;
+            }
+
             let Some(channel_id) = channel_id else {
                 error!("Channel id is none for downstream_id: {}", downstream_id);
                 continue;
             };
-            let new_hashrate_opt = vardiff.super_safe_lock(|state| {
-                state.try_vardiff(hashrate, &target, self.shares_per_minute)
-            });
+            let new_hashrate_opt = vardiff
+                .super_safe_lock(|state| state.try_vardiff(hashrate, &target, shares_per_minute));
 
             if let Ok(Some(new_hashrate)) = new_hashrate_opt {
                 // Calculate new target based on new hashrate
                 let new_target: Target =
-                    match hash_rate_to_target(new_hashrate as f64, self.shares_per_minute as f64) {
+                    match hash_rate_to_target(new_hashrate as f64, shares_per_minute as f64) {
                         Ok(target) => target,
                         Err(e) => {
                             error!(
@@ -152,7 +184,7 @@ impl Sv1Server {
         // Process immediate set_difficulty updates (for new_target >= upstream_target)
         for (channel_id, downstream_id, target) in immediate_updates {
             // Send set_difficulty message immediately
-            if let Ok(set_difficulty_msg) = build_sv1_set_difficulty_from_sv2_target(target) {
+            if let Ok(set_difficulty_msg) = self.build_set_difficulty(target) {
                 if let Err(e) = self
                     .sv1_server_channel_state
                     .sv1_server_to_downstream_sender
@@ -177,7 +209,8 @@ impl Sv1Server {
     ///
     /// Always sends UpdateChannel to keep upstream informed about target changes.
     /// Handles both aggregated and non-aggregated modes:
-    /// - Aggregated: Send single UpdateChannel with minimum target and sum of hashrates
+    /// - Aggregated: Send single UpdateChannel per aggregated channel with minimum target and sum
+    ///   of hashrates
     /// - Non-aggregated: Send individual UpdateChannel for each downstream
     async fn send_update_channel_messages(
         &self,
@@ -188,7 +221,7 @@ impl Sv1Server {
     ) {
         if is_aggregated() {
             // Aggregated mode: Send single UpdateChannel with minimum target and total hashrate of
-            // ALL downstreams
+            // ALL downstreams of each updated aggregated channel
             self.send_aggregated_update_channel(all_updates).await;
         } else {
             // Non-aggregated mode: Send individual UpdateChannel for each downstream
@@ -201,59 +234,75 @@ impl Sv1Server {
         all_updates: Vec<(DownstreamId, ChannelId, Target, Hashrate)>,
     ) {
         // Nothing to do if we received no updates
-        let Some((_, channel_id, _, _)) = all_updates.first() else {
-            return;
-        };
-
-        if self.downstreams.is_empty() {
+        if all_updates.is_empty() || self.downstreams.is_empty() {
             return;
         }
 
-        let mut min_target: Option<Target> = None;
-        let mut total_hashrate: Hashrate = 0.0;
+        // Only the aggregated channels of the updated downstreams change
+        let aggregated_channel_ids: BTreeSet<ChannelId> = all_updates
+            .iter()
+            .filter_map(|(downstream_id, _, _, _)| {
+                self.downstreams.get(downstream_id).map(|downstream| {
+                    downstream
+                        .downstream_data
+                        .super_safe_lock(|d| d.aggregated_channel_id)
+                })
+            })
+            .collect();
+
+        for aggregated_channel_id in aggregated_channel_ids {
+            let mut min_target: Option<Target> = None;
+            let mut total_hashrate: Hashrate = 0.0;
+            let mut downstream_count = 0;
 
-        for downstream in self.downstreams.iter() {
-            let downstream = downstream.value();
-            downstream.downstream_data.super_safe_lock(|d| {
-                let target = *d.pending_target.as_ref().unwrap_or(&d.target);
-                let hashrate = d
-                    .pending_hashrate
-                    .unwrap_or_else(|| d.hashrate.expect("vardiff implies hashrate"));
+            for downstream in self.downstreams.iter() {
+                let downstream = downstream.value();
+                downstream.downstream_data.super_safe_lock(|d| {
+                    if d.aggregated_channel_id != aggregated_channel_id {
+                        return;
+                    }
+                    let target = *d.pending_target.as_ref().unwrap_or(&d.target);
+                    let hashrate = d
+                        .pending_hashrate
+                        .unwrap_or_else(|| d.hashrate.expect("vardiff implies hashrate"));
 
-                min_target = Some(match min_target {
-                    Some(current) => current.min(target),
-                    None => target,
-                });
+                    min_target = Some(match min_target {
+                        Some(current) => current.min(target),
+                        None => target,
+                    });
 
-                total_hashrate += hashrate;
-            });
-        }
+                    total_hashrate += hashrate;
+                    downstream_count += 1;
+                });
+            }
 
-        let min_target = min_target.expect("at least one downstream must exist");
-        let downstream_count = self.downstreams.len();
+            let Some(min_target) = min_target else {
+                continue;
+            };
 
-        let update_channel = UpdateChannel {
-            channel_id: *channel_id,
-            nominal_hash_rate: total_hashrate,
-            maximum_target: min_target.to_le_bytes().into(),
-        };
+            let update_channel = UpdateChannel {
+                channel_id: aggregated_channel_id,
+                nominal_hash_rate: total_hashrate,
+                maximum_target: min_target.to_le_bytes().into(),
+            };
 
-        debug!(
-            "Sending aggregated UpdateChannel: channel_id={}, total_hashrate={}, min_target={:?}, downstreams={}, vardiff_updates={}",
-            channel_id,
-            total_hashrate,
-            min_target,
-            downstream_count,
-            all_updates.len()
-        );
+            debug!(
+                "Sending aggregated UpdateChannel: channel_id={}, total_hashrate={}, min_target={:?}, downstreams={}, vardiff_updates={}",
+                aggregated_channel_id,
+                total_hashrate,
+                min_target,
+                downstream_count,
+                all_updates.len()
+            );
 
-        if let Err(e) = self
-            .sv1_server_channel_state
-            .channel_manager_sender
-            .send((Mining::UpdateChannel(update_channel), None))
-            .await
-        {
-            error!("Failed to send aggregated UpdateChannel: {:?}", e);
+            if let Err(e) = self
+                .sv1_server_channel_state
+                .channel_manager_sender
+                .send((Mining::UpdateChannel(update_channel), None))
+                .await
+            {
+                error!("Failed to send aggregated UpdateChannel: {:?}", e);
+            }
         }
     }
 
@@ -311,24 +360,35 @@ impl Sv1Server {
     }
 
     /// Handles SetTarget in aggregated mode.
-    /// Updates all downstreams and processes all pending set_difficulty messages.
+    /// Updates all downstreams of the aggregated channel `channel_id` and processes their pending
+    /// set_difficulty messages.
     async fn handle_aggregated_set_target(
         &self,
         new_upstream_target: Target,
         channel_id: ChannelId,
     ) {
-        debug!("Aggregated mode: Updating upstream target for all downstreams");
+        debug!(
+            "Aggregated mode: Updating upstream target for all downstreams of channel {}",
+            channel_id
+        );
 
+        let mut downstream_ids = HashSet::new();
         for downstream in self.downstreams.iter() {
             let downstream = downstream.value();
             downstream.downstream_data.super_safe_lock(|d| {
-                d.set_upstream_target(new_upstream_target, downstream.downstream_id);
+                if d.aggregated_channel_id == channel_id {
+                    d.set_upstream_target(new_upstream_target, downstream.downstream_id);
+                    downstream_ids.insert(downstream.downstream_id);
+                }
             });
         }
 
         // Process ALL pending difficulty updates that can now be sent downstream
-        let applicable_updates =
-            self.get_pending_difficulty_updates(new_upstream_target, None, channel_id);
+        let applicable_updates = self.get_pending_difficulty_updates(
+            new_upstream_target,
+            Some(&downstream_ids),
+            channel_id,
+        );
 
         self.send_pending_set_difficulty_messages_to_downstream(applicable_updates)
             .await;
@@ -367,7 +427,7 @@ impl Sv1Server {
 
         let applicable_updates = self.get_pending_difficulty_updates(
             new_upstream_target,
-            Some(downstream_id),
+            Some(&HashSet::from([downstream_id])),
             channel_id,
         );
 
@@ -376,12 +436,12 @@ impl Sv1Server {
     }
 
     /// Gets pending updates that can now be applied based on the new upstream target.
-    /// If downstream_id is provided, only returns updates for that specific downstream.
+    /// If downstream_ids is provided, only returns updates for those specific downstreams.
     /// Logs a warning if the upstream target is higher than any requested target.
     fn get_pending_difficulty_updates(
         &self,
         new_upstream_target: Target,
-        downstream_id: Option<DownstreamId>,
+        downstream_ids: Option<&HashSet<DownstreamId>>,
         channel_id: ChannelId,
     ) -> Vec<PendingTargetUpdate> {
         let mut applicable_updates = Vec::new();
@@ -389,9 +449,9 @@ impl Sv1Server {
         self.pending_target_updates.super_safe_lock(|data| {
             data.retain(|pending_update| {
                 // Check if we should process this update
-                let should_process = match downstream_id {
-                    Some(downstream_id) => pending_update.downstream_id == downstream_id,
-                    None => true, // Process all in aggregated mode
+                let should_process = match downstream_ids {
+                    Some(downstream_ids) => downstream_ids.contains(&pending_update.downstream_id),
+                    None => true, // Process all
                 };
 
                 if !should_process {
@@ -434,17 +494,16 @@ impl Sv1Server {
                 continue;
             };
 
-            let set_difficulty_msg =
-                match build_sv1_set_difficulty_from_sv2_target(update.new_target) {
-                    Ok(msg) => msg,
-                    Err(e) => {
-                        error!(
-                            "Failed to build SetDifficulty for downstream {}: {:?}",
-                            update.downstream_id, e
-                        );
-                        continue;
-                    }
-                };
+            let set_difficulty_msg = match self.build_set_difficulty(update.new_target) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    error!(
+                        "Failed to build SetDifficulty for downstream {}: {:?}",
+                        update.downstream_id, e
+                    );
+                    continue;
+                }
+            };
 
             if let Err(e) = self
                 .sv1_server_channel_state
@@ -461,73 +520,100 @@ impl Sv1Server {
         }
     }
 
-    /// Sends an UpdateChannel message for aggregated mode when downstream state changes
-    /// (e.g., disconnect). Calculates total hashrate and minimum target among all remaining
-    /// downstreams.
-    pub async fn send_update_channel_on_downstream_state_change(&self) {
+    /// Sends an UpdateChannel message for the aggregated channel `aggregated_channel_id` when
+    /// the state of one of its downstreams changes (e.g., disconnect). The aggregated hashrate is
+    /// recomputed from the remaining downstreams on every call, so it never drifts from the set
+    /// of connected miners.
+    pub async fn send_update_channel_on_downstream_state_change(
+        &self,
+        aggregated_channel_id: ChannelId,
+    ) {
         if is_non_aggregated() {
             return;
         }
 
-        let is_empty = self.downstreams.is_empty();
+        let update = self.aggregated_update_channel(aggregated_channel_id);
 
-        let snapshot = if is_empty {
-            AggregatedSnapshot::NoDownstreams
-        } else {
-            let mut total_hashrate: Hashrate = 0.0;
-            let mut min_target: Option<Target> = None;
+        if let Err(e) = self
+            .sv1_server_channel_state
+            .channel_manager_sender
+            .send((Mining::UpdateChannel(update), None))
+            .await
+        {
+            error!(
+                "Failed to send UpdateChannel after downstream state change: {:?}",
+                e
+            );
+        }
+    }
 
-            for downstream in self.downstreams.iter() {
-                let downstream = downstream.value();
-                downstream.downstream_data.super_safe_lock(|d| {
-                    let hashrate = d.pending_hashrate.unwrap_or_else(|| {
-                        d.hashrate
-                            .expect("vardiff implies downstream must have a hashrate")
-                    });
+    /// Builds the UpdateChannel of the aggregated channel `aggregated_channel_id` from all its
+    /// current downstreams.
+    ///
+    /// The nominal hashrate is the sum of each downstream's pending or current hashrate,
+    /// falling back to the configured `min_individual_miner_hashrate` when none is known.
+    /// The maximum target is the minimum downstream target when vardiff is enabled, and left
+    /// unrestricted otherwise, matching how the channel was opened.
+    pub(crate) fn aggregated_update_channel(
+        &self,
+        aggregated_channel_id: ChannelId,
+    ) -> UpdateChannel<'static> {
+        let configured_hashrate = self.hot_config.min_individual_miner_hashrate();
+        let mut total_hashrate: Hashrate = 0.0;
+        let mut min_target: Option<Target> = None;
 
-                    let target = *d.pending_target.as_ref().unwrap_or(&d.target);
+        for downstream in self.downstreams.iter() {
+            let downstream = downstream.value();
+            downstream.downstream_data.super_safe_lock(|d| {
+                if d.aggregated_channel_id != aggregated_channel_id {
+                    return;
+                }
+                let hashrate = d
+                    .pending_hashrate
+                    .or(d.hashrate)
+                    .unwrap_or(configured_hashrate);
 
-                    total_hashrate += hashrate;
-                    min_target = Some(match min_target {
-                        Some(current) => current.min(target),
-                        None => target,
-                    });
+                let target = *d.pending_target.as_ref().unwrap_or(&d.target);
+
+                total_hashrate += hashrate;
+                min_target = Some(match min_target {
+                    Some(current) => current.min(target),
+                    None => target,
                 });
-            }
+            });
+        }
 
-            AggregatedSnapshot::Active {
+        let snapshot = match min_target {
+            Some(min_target) => AggregatedSnapshot::Active {
                 total_hashrate,
-                min_target: min_target.expect("downstreams is non-empty"),
-            }
+                min_target,
+            },
+            None => AggregatedSnapshot::NoDownstreams,
         };
 
-        let update = match snapshot {
+        match snapshot {
             AggregatedSnapshot::Active {
                 total_hashrate,
                 min_target,
-            } => UpdateChannel {
-                channel_id: 0, // ChannelManager will rewrite to upstream extended channel id
-                nominal_hash_rate: total_hashrate,
-                maximum_target: min_target.to_le_bytes().into(),
-            },
+            } => {
+                let maximum_target = if self.config.downstream_difficulty_config.enable_vardiff {
+                    min_target.to_le_bytes()
+                } else {
+                    [0xFF; 32]
+                };
+                UpdateChannel {
+                    // ChannelManager will rewrite to upstream extended channel id
+                    channel_id: aggregated_channel_id,
+                    nominal_hash_rate: total_hashrate,
+                    maximum_target: maximum_target.into(),
+                }
+            }
 
             AggregatedSnapshot::NoDownstreams => UpdateChannel {
-                channel_id: 0,
+                channel_id: aggregated_channel_id,
                 nominal_hash_rate: 0.0,
                 maximum_target: [0xFF; 32].into(),
             },
-        };
-
-        if let Err(e) = self
-            .sv1_server_channel_state
-            .channel_manager_sender
-            .send((Mining::UpdateChannel(update), None))
-            .await
-        {
-            error!(
-                "Failed to send UpdateChannel after downstream state change: {:?}",
-                e
-            );
         }
     }
 }
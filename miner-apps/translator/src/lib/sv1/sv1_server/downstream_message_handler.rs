@@ -1,15 +1,25 @@
-use stratum_apps::stratum_core::sv1_api::{
-    client_to_server, json_rpc,
-    server_to_client::{self, Notify},
-    utils::{Extranonce, HexU32Be},
-    IsServer,
+use std::{collections::HashSet, sync::atomic::Ordering, time::Instant};
+
+use stratum_apps::{
+    stratum_core::sv1_api::{
+        client_to_server, json_rpc, server_to_client,
+        utils::{Extranonce, HexU32Be},
+        IsServer,
+    },
+    utils::types::DownstreamId,
 };
 use tracing::{debug, info, warn};
 
 use crate::{
     error, is_aggregated,
-    sv1::{downstream::SubmitShareWithChannelId, Sv1Server},
-    utils::{validate_sv1_share, AGGREGATED_CHANNEL_ID},
+    sv1::{
+        downstream::{
+            data::SubmittedShare, downstream::Downstream, ShareRejection, SubmitShareWithChannelId,
+        },
+        sv1_server::session_resumption::session_id,
+        Sv1Server,
+    },
+    utils::{share_meets_target, sv1_share_hash},
 };
 
 // Implements `IsServer` for `Sv1Server` to handle the Sv1 messages.
@@ -73,9 +83,17 @@ impl IsServer<'static> for Sv1Server {
             downstream_id.to_string(),
         );
 
+        // The channel is opened before `mining.subscribe` is answered, so the extranonce1 the
+        // session id is derived from is known. Miners present it back to resume the session.
+        let downstream = self
+            .downstreams
+            .get(&downstream_id)
+            .expect("Downstream should exist");
         let notify_sub = (
             "mining.notify".to_string(),
-            "ae6812eb4cd7735a302a8a9dd95cf71f".to_string(),
+            downstream
+                .downstream_data
+                .super_safe_lock(|data| session_id(&data.extranonce1)),
         );
 
         vec![set_difficulty_sub, notify_sub]
@@ -104,76 +122,19 @@ impl IsServer<'static> for Sv1Server {
             .get(&downstream_id)
             .expect("Downstream should exist");
 
-        let job_id = &request.job_id;
-
-        let Some(channel_id) = downstream
+        let result = self.validate_submit(downstream_id, &downstream, request);
+        downstream
             .downstream_data
-            .super_safe_lock(|data| data.channel_id)
-        else {
-            return false;
-        };
-
-        let channel_id = if is_aggregated() {
-            AGGREGATED_CHANNEL_ID
-        } else {
-            channel_id
-        };
-
-        let find_job =
-            |jobs: &[Notify<'static>]| jobs.iter().find(|j| j.job_id == *job_id).cloned();
-
-        let job = self
-            .valid_sv1_jobs
-            .get(&channel_id)
-            .and_then(|jobs| find_job(jobs.as_ref()));
-
-        let Some(job) = job else {
-            return false;
-        };
-
-        downstream.downstream_data.super_safe_lock(|data| {
-            let channel_id = match data.channel_id {
-                Some(id) => id,
-                None => {
-                    error!(
-                        "Cannot submit share: channel_id is None \
-                         (waiting for OpenExtendedMiningChannelSuccess)"
-                    );
-                    return false;
+            .super_safe_lock(|data| match result {
+                Ok(share) => {
+                    data.pending_share = Some(share);
+                    true
                 }
-            };
-
-            info!(
-                "Received mining.submit from SV1 downstream for channel id: {}",
-                channel_id
-            );
-
-            let is_valid = validate_sv1_share(
-                request,
-                data.target,
-                data.extranonce1.clone().into(),
-                data.version_rolling_mask.clone(),
-                job,
-            )
-            .unwrap_or(false);
-
-            if !is_valid {
-                error!("Invalid share for channel id: {}", channel_id);
-                return false;
-            }
-
-            data.pending_share = Some(SubmitShareWithChannelId {
-                channel_id,
-                downstream_id,
-                share: request.clone(),
-                extranonce: data.extranonce1.clone().into(),
-                extranonce2_len: data.extranonce2_len,
-                version_rolling_mask: data.version_rolling_mask.clone(),
-                job_version: data.last_job_version_field,
-            });
-
-            true
-        })
+                Err(rejection) => {
+                    data.share_rejection = Some(rejection);
+                    false
+                }
+            })
     }
 
     /// Indicates to the server that the client supports the mining.set_extranonce method.
@@ -188,10 +149,14 @@ impl IsServer<'static> for Sv1Server {
             .expect("Downstream should exist");
         downstream
             .downstream_data
-            .super_safe_lock(|data| data.authorized_worker_name == *name)
+            .super_safe_lock(|data| data.is_worker_authorized(name))
     }
 
     /// Authorizes a Downstream role.
+    ///
+    /// A connection can authorize several workers. The first one names the connection and its
+    /// channel, the others share that channel since they mine on the same extranonce1 and jobs.
+    /// In non-aggregated mode, the shares of each worker carry its own user identity.
     fn authorize(&mut self, client_id: Option<usize>, name: &str) {
         let downstream_id = client_id.expect("Downstream id should exist");
         let downstream = self
@@ -199,16 +164,41 @@ impl IsServer<'static> for Sv1Server {
             .get(&downstream_id)
             .expect("Downstream should exist");
 
-        let is_authorized = self.is_authorized(client_id, name);
         downstream.downstream_data.super_safe_lock(|data| {
-            if !is_authorized {
+            // A channel opened without the worker name, for a miner not authorizing in time,
+            // rendered a template using it with `miner{id}`.
+            let user_identity = if self.config.user_identity_template_uses_worker() {
+                self.config
+                    .render_user_identity(Some(name), data.miner_id)
+                    .unwrap_or_else(|e| {
+                        warn!(
+                            "Down: Failed to render user identity for downstream {}: {}",
+                            downstream_id, e
+                        );
+                        name.to_string()
+                    })
+            } else {
+                name.to_string()
+            };
+
+            if data.authorized_worker_name.is_empty() || data.authorized_worker_name == name {
                 data.authorized_worker_name = name.to_string();
+                downstream.span.record("worker_name", name);
+                data.user_identity = user_identity;
+                debug!(
+                    "Down: Set user_identity to '{}' for downstream {}",
+                    data.user_identity, downstream_id
+                );
+                data.group_label = self.config.downstream_group_label(name);
+                self.restore_vardiff(downstream_id, data);
+            } else {
+                info!(
+                    "Down: Worker '{}' authorized on downstream {}, sharing its channel",
+                    name, downstream_id
+                );
+                data.additional_workers
+                    .insert(name.to_string(), user_identity);
             }
-            data.user_identity = name.to_string();
-            debug!(
-                "Down: Set user_identity to '{}' for downstream {}",
-                data.user_identity, downstream_id
-            );
         });
     }
 
@@ -304,3 +294,144 @@ impl IsServer<'static> for Sv1Server {
         )
     }
 }
+
+impl Sv1Server {
+    // Checks a `mining.submit` against the jobs of the downstream channel and its target,
+    // returning the share to forward upstream or the reason it was rejected.
+    fn validate_submit(
+        &self,
+        downstream_id: DownstreamId,
+        downstream: &Downstream,
+        request: &client_to_server::Submit<'static>,
+    ) -> Result<SubmitShareWithChannelId, ShareRejection> {
+        let job_id = &request.job_id;
+
+        let (channel_id, rotating_upstream) = downstream
+            .downstream_data
+            .super_safe_lock(|data| (data.channel_id, data.rotating_upstream));
+        let Some(channel_id) = channel_id else {
+            error!(
+                "Cannot submit share: channel_id is None \
+                 (waiting for OpenExtendedMiningChannelSuccess)"
+            );
+            return Err(ShareRejection::Other);
+        };
+        // The channel was closed along with the upstream the translator rotated away from
+        if rotating_upstream {
+            warn!(
+                "Rejecting share for job {} on channel id: {} of the upstream left",
+                job_id, channel_id
+            );
+            return Err(ShareRejection::JobNotFound);
+        }
+
+        let job_channel_id = if is_aggregated() {
+            downstream
+                .downstream_data
+                .super_safe_lock(|data| data.aggregated_channel_id)
+        } else {
+            channel_id
+        };
+
+        let (job, valid_job_ids) = self
+            .valid_sv1_jobs
+            .get(&job_channel_id)
+            .map(|jobs| {
+                let job = jobs.iter().find(|j| j.job_id == *job_id).cloned();
+                let valid_job_ids: HashSet<String> =
+                    jobs.iter().map(|j| j.job_id.clone()).collect();
+                (job, valid_job_ids)
+            })
+            .unwrap_or_default();
+
+        let Some(job) = job else {
+            warn!(
+                "Rejecting share for unknown or stale job {} on channel id: {}",
+                job_id, channel_id
+            );
+            return Err(ShareRejection::JobNotFound);
+        };
+
+        // The upstream only knows the original job of a keepalive, and would reject the share as
+        // stale once that job was evicted
+        if let Some(original_job_id) = Self::extract_original_job_id(job_id) {
+            if !valid_job_ids.contains(&original_job_id) {
+                warn!(
+                    "Rejecting share for keepalive job {} of evicted job {} on channel id: {}",
+                    job_id, original_job_id, channel_id
+                );
+                self.keepalive_shares_orphaned
+                    .fetch_add(1, Ordering::Relaxed);
+                return Err(ShareRejection::JobNotFound);
+            }
+        }
+
+        // The job ntime sent in `mining.notify` is the earliest ntime a share may use
+        let min_ntime = job.time.0;
+        let max_ntime = min_ntime.saturating_add(self.config.ntime_roll_window_secs());
+        if !(min_ntime..=max_ntime).contains(&request.time.0) {
+            warn!(
+                "Rejecting share with ntime {} outside [{}, {}] on channel id: {}",
+                request.time.0, min_ntime, max_ntime, channel_id
+            );
+            return Err(ShareRejection::NtimeOutOfRange);
+        }
+
+        downstream.downstream_data.super_safe_lock(|data| {
+            info!(
+                "Received mining.submit from SV1 downstream for channel id: {}",
+                channel_id
+            );
+
+            // Shares of jobs that are no longer valid would be rejected as stale anyway
+            data.submitted_shares
+                .retain(|share| valid_job_ids.contains(&share.job_id));
+            data.stale_job_ids.retain(|id| valid_job_ids.contains(id));
+            // The miner mined the jobs notified before its extranonce1 changed on the old one
+            if data.stale_job_ids.contains(job_id) {
+                warn!(
+                    "Rejecting share for job {} notified before extranonce change, channel id: {}",
+                    job_id, channel_id
+                );
+                return Err(ShareRejection::JobNotFound);
+            }
+            let submitted_share = SubmittedShare::from(request);
+            if data.submitted_shares.contains(&submitted_share) {
+                warn!("Rejecting duplicate share for channel id: {}", channel_id);
+                return Err(ShareRejection::Duplicate);
+            }
+
+            let share_hash = sv1_share_hash(
+                request,
+                data.extranonce1.clone().into(),
+                data.version_rolling_mask.clone(),
+                job,
+            )
+            .map_err(|e| {
+                error!(
+                    "Failed to validate share for channel id: {}: {:?}",
+                    channel_id, e
+                );
+                ShareRejection::Other
+            })?;
+
+            if !share_meets_target(share_hash, data.target) {
+                error!("Invalid share for channel id: {}", channel_id);
+                return Err(ShareRejection::LowDifficulty);
+            }
+            data.achieved_difficulty
+                .record_share(share_hash.difficulty_float(), Instant::now());
+
+            data.submitted_shares.insert(submitted_share);
+            Ok(SubmitShareWithChannelId {
+                channel_id,
+                downstream_id,
+                share: request.clone(),
+                extranonce: data.extranonce1.clone().into(),
+                extranonce2_len: data.extranonce2_len,
+                version_rolling_mask: data.version_rolling_mask.clone(),
+                job_version: data.last_job_version_field,
+            })
+        })
+    }
+}
@@ -0,0 +1,165 @@
+//! Job propagation latency tracking.
+//!
+//! Measures the time from the SV1 server receiving a `NewExtendedMiningJob` from upstream until
+//! the derived `mining.notify` was sent to the last downstream expecting it. A growing latency is
+//! a sign of contention between the upstream and downstream tasks.
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use stratum_apps::{
+    custom_mutex::Mutex,
+    monitoring::sv1::{LatencyHistogram, JOB_PROPAGATION_LATENCY_BUCKETS},
+    utils::types::{ChannelId, DownstreamId},
+};
+use tracing::{debug, warn};
+
+/// Latencies kept for the next Prometheus scrape. The oldest are dropped beyond it, e.g. when
+/// nothing scrapes the metrics.
+const MAX_UNSCRAPED_LATENCIES: usize = 1024;
+
+/// A job whose `mining.notify` has not been sent to every downstream yet.
+#[derive(Debug)]
+struct PendingJob {
+    job_id: String,
+    received_at: Instant,
+    remaining: HashSet<DownstreamId>,
+    delivered: usize,
+}
+
+/// Tracks in-flight jobs per upstream channel and records their propagation latency.
+#[derive(Debug)]
+pub struct JobPropagationTracker {
+    alarm_threshold: Duration,
+    pending: DashMap<ChannelId, PendingJob>,
+    latency: Mutex<LatencyHistogram>,
+    unscraped_latencies: Mutex<VecDeque<f64>>,
+    alarms: AtomicU64,
+}
+
+impl JobPropagationTracker {
+    /// Creates a tracker raising an alarm for jobs slower than `alarm_threshold`.
+    pub fn new(alarm_threshold: Duration) -> Self {
+        Self {
+            alarm_threshold,
+            pending: DashMap::new(),
+            latency: Mutex::new(LatencyHistogram::new(JOB_PROPAGATION_LATENCY_BUCKETS)),
+            unscraped_latencies: Mutex::new(VecDeque::new()),
+            alarms: AtomicU64::new(0),
+        }
+    }
+
+    /// Starts timing `job_id`, received at `received_at` on `channel_id`, until its notify was
+    /// sent to all of `downstreams`.
+    ///
+    /// A job of the same channel still in flight is dropped without being recorded, since the
+    /// downstreams that did not get it yet will only be sent the newer one.
+    pub fn job_received(
+        &self,
+        channel_id: ChannelId,
+        job_id: String,
+        received_at: Instant,
+        downstreams: HashSet<DownstreamId>,
+    ) {
+        if downstreams.is_empty() {
+            self.pending.remove(&channel_id);
+            return;
+        }
+        let job = PendingJob {
+            job_id,
+            received_at,
+            remaining: downstreams,
+            delivered: 0,
+        };
+        if let Some(previous) = self.pending.insert(channel_id, job) {
+            debug!(
+                "Job {} on channel {} superseded before reaching {} downstreams",
+                previous.job_id,
+                channel_id,
+                previous.remaining.len()
+            );
+        }
+    }
+
+    /// Records that the notify for `job_id` was sent to `downstream_id`.
+    pub fn notify_sent(&self, channel_id: ChannelId, job_id: &str, downstream_id: DownstreamId) {
+        if let Some(mut job) = self.pending.get_mut(&channel_id) {
+            if job.job_id == job_id && job.remaining.remove(&downstream_id) {
+                job.delivered += 1;
+            }
+        }
+        self.complete_if_done(channel_id);
+    }
+
+    /// Stops waiting for `downstream_id`, which disconnected.
+    pub fn downstream_removed(&self, downstream_id: DownstreamId) {
+        let mut channel_ids = Vec::new();
+        for mut job in self.pending.iter_mut() {
+            if job.remaining.remove(&downstream_id) {
+                channel_ids.push(*job.key());
+            }
+        }
+        for channel_id in channel_ids {
+            self.complete_if_done(channel_id);
+        }
+    }
+
+    /// Drops every job in flight, e.g. when the upstream connection is replaced.
+    pub fn clear(&self) {
+        self.pending.clear();
+    }
+
+    /// Returns the job propagation latency histogram.
+    pub fn latency(&self) -> LatencyHistogram {
+        self.latency.super_safe_lock(|latency| latency.clone())
+    }
+
+    /// Takes the latencies, in seconds, recorded since the last call.
+    pub fn take_latencies(&self) -> Vec<f64> {
+        self.unscraped_latencies
+            .super_safe_lock(|latencies| latencies.drain(..).collect())
+    }
+
+    /// Returns the number of jobs that exceeded the alarm threshold.
+    pub fn alarms_total(&self) -> u64 {
+        self.alarms.load(Ordering::Relaxed)
+    }
+
+    fn complete_if_done(&self, channel_id: ChannelId) {
+        let Some((_, job)) = self
+            .pending
+            .remove_if(&channel_id, |_, job| job.remaining.is_empty())
+        else {
+            return;
+        };
+        // Every downstream disconnected before getting the job, so nothing was propagated
+        if job.delivered == 0 {
+            return;
+        }
+
+        let latency = job.received_at.elapsed();
+        self.latency
+            .super_safe_lock(|histogram| histogram.observe(latency.as_secs_f64()));
+        self.unscraped_latencies.super_safe_lock(|latencies| {
+            if latencies.len() == MAX_UNSCRAPED_LATENCIES {
+                latencies.pop_front();
+            }
+            latencies.push_back(latency.as_secs_f64());
+        });
+        if latency > self.alarm_threshold {
+            self.alarms.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Job {} on channel {} took {:?} to reach {} downstreams, above the {:?} alarm threshold",
+                job.job_id, channel_id, latency, job.delivered, self.alarm_threshold
+            );
+        } else {
+            debug!(
+                "Job {} on channel {} reached {} downstreams in {:?}",
+                job.job_id, channel_id, job.delivered, latency
+            );
+        }
+    }
+}
@@ -1,7 +1,12 @@
 pub(super) mod channel;
+pub mod channel_affinity;
 mod difficulty_manager;
 pub mod downstream_message_handler;
+pub mod job_propagation;
+pub mod pending_jobs;
+pub mod session_resumption;
 pub mod sv1_server;
+pub mod vardiff_retention;
 
 /// Delimiter used to separate original job ID from keepalive mutation counter.
 /// Format: `{original_job_id}#{counter}`
@@ -0,0 +1,177 @@
+//! Jobs received for a channel before its `SetNewPrevHash`.
+//!
+//! A `NewExtendedMiningJob` can reach the SV1 server before the prevhash of its channel, e.g.
+//! right after the channel opened. Such a job cannot be turned into a `mining.notify` yet, so it
+//! is held for a short time and sent once the prevhash arrives. A channel whose prevhash never
+//! arrives points to a problem upstream: its jobs are dropped after the timeout, or once the
+//! queue of the channel is full, and counted.
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use stratum_apps::{stratum_core::mining_sv2::NewExtendedMiningJob, utils::types::ChannelId};
+use tracing::warn;
+
+/// Jobs waiting for the prevhash of their channel, bounded per channel and in time.
+#[derive(Debug)]
+pub struct PendingJobs {
+    max_per_channel: usize,
+    timeout: Duration,
+    jobs: DashMap<ChannelId, VecDeque<(NewExtendedMiningJob<'static>, Instant)>>,
+    dropped: AtomicU64,
+}
+
+impl PendingJobs {
+    /// Creates a buffer holding up to `max_per_channel` jobs per channel for `timeout` each.
+    /// With `max_per_channel` 0, jobs without a prevhash are dropped right away.
+    pub fn new(max_per_channel: usize, timeout: Duration) -> Self {
+        Self {
+            max_per_channel,
+            timeout,
+            jobs: DashMap::new(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Holds `job`, received at `received_at`, until the prevhash of its channel arrives.
+    ///
+    /// Expired jobs of every channel are dropped first; the oldest job of the channel is dropped
+    /// when its queue is full.
+    pub fn push(&self, job: NewExtendedMiningJob<'static>, received_at: Instant) {
+        self.drop_expired();
+        let channel_id = job.channel_id;
+        if self.max_per_channel == 0 {
+            self.count_dropped(channel_id, 1);
+            return;
+        }
+        let mut queue = self.jobs.entry(channel_id).or_default();
+        if queue.len() >= self.max_per_channel {
+            queue.pop_front();
+            self.count_dropped(channel_id, 1);
+        }
+        queue.push_back((job, received_at));
+    }
+
+    /// Takes the unexpired jobs of `channel_id`, oldest first, once its prevhash arrived.
+    pub fn take(&self, channel_id: ChannelId) -> Vec<(NewExtendedMiningJob<'static>, Instant)> {
+        let Some((_, queue)) = self.jobs.remove(&channel_id) else {
+            return Vec::new();
+        };
+        let total = queue.len();
+        let jobs: Vec<_> = queue
+            .into_iter()
+            .filter(|(_, received_at)| received_at.elapsed() < self.timeout)
+            .collect();
+        if jobs.len() < total {
+            self.count_dropped(channel_id, (total - jobs.len()) as u64);
+        }
+        jobs
+    }
+
+    /// Drops the jobs of `channel_id` without counting them, e.g. once the channel closed.
+    pub fn remove_channel(&self, channel_id: ChannelId) {
+        self.jobs.remove(&channel_id);
+    }
+
+    /// Drops every held job without counting them.
+    pub fn clear(&self) {
+        self.jobs.clear();
+    }
+
+    /// Returns the number of jobs currently held.
+    pub fn len(&self) -> usize {
+        self.jobs.iter().map(|queue| queue.len()).sum()
+    }
+
+    /// Returns whether no job is currently held.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of jobs dropped for lack of a prevhash.
+    pub fn dropped_total(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    // Drops the jobs held for longer than the timeout.
+    fn drop_expired(&self) {
+        let mut expired = Vec::new();
+        self.jobs.retain(|channel_id, queue| {
+            let total = queue.len();
+            queue.retain(|(_, received_at)| received_at.elapsed() < self.timeout);
+            if queue.len() < total {
+                expired.push((*channel_id, (total - queue.len()) as u64));
+            }
+            !queue.is_empty()
+        });
+        for (channel_id, count) in expired {
+            self.count_dropped(channel_id, count);
+        }
+    }
+
+    fn count_dropped(&self, channel_id: ChannelId, count: u64) {
+        self.dropped.fetch_add(count, Ordering::Relaxed);
+        warn!("Dropped {count} job(s) of channel {channel_id}: no SetNewPrevHash received for it");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stratum_apps::stratum_core::binary_sv2::{Seq0255, Sv2Option};
+
+    fn job(channel_id: ChannelId, job_id: u32) -> NewExtendedMiningJob<'static> {
+        NewExtendedMiningJob {
+            channel_id,
+            job_id,
+            min_ntime: Sv2Option::new(None),
+            version: 0x20000000,
+            version_rolling_allowed: true,
+            merkle_path: Seq0255::new(vec![]).unwrap(),
+            coinbase_tx_prefix: vec![0x02; 8].try_into().unwrap(),
+            coinbase_tx_suffix: vec![0xfe; 8].try_into().unwrap(),
+        }
+    }
+
+    #[test]
+    fn drops_oldest_job_when_channel_queue_is_full() {
+        let pending_jobs = PendingJobs::new(2, Duration::from_secs(60));
+        for job_id in 1..=3 {
+            pending_jobs.push(job(1, job_id), Instant::now());
+        }
+        pending_jobs.push(job(2, 1), Instant::now());
+        assert_eq!(pending_jobs.len(), 3);
+        assert_eq!(pending_jobs.dropped_total(), 1);
+
+        let job_ids: Vec<u32> = pending_jobs
+            .take(1)
+            .into_iter()
+            .map(|(job, _)| job.job_id)
+            .collect();
+        assert_eq!(job_ids, vec![2, 3]);
+        assert!(pending_jobs.take(1).is_empty());
+        assert_eq!(pending_jobs.len(), 1);
+    }
+
+    #[test]
+    fn expired_jobs_are_dropped() {
+        let pending_jobs = PendingJobs::new(8, Duration::from_millis(10));
+        pending_jobs.push(job(1, 1), Instant::now());
+        pending_jobs.push(job(2, 1), Instant::now());
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(pending_jobs.take(1).is_empty());
+        assert_eq!(pending_jobs.dropped_total(), 1);
+        pending_jobs.push(job(3, 1), Instant::now());
+        assert_eq!(pending_jobs.dropped_total(), 2);
+        assert_eq!(pending_jobs.len(), 1);
+
+        let pending_jobs = PendingJobs::new(0, Duration::from_secs(60));
+        pending_jobs.push(job(1, 1), Instant::now());
+        assert!(pending_jobs.is_empty());
+        assert_eq!(pending_jobs.dropped_total(), 1);
+    }
+}
@@ -0,0 +1,184 @@
+//! SV1 sessions retained across reconnections.
+//!
+//! A miner reconnecting after a short outage would otherwise get a fresh extranonce1, so the work
+//! it had in flight no longer matches any job. The translator hands out the extranonce1 of each
+//! connection as its session id, and keeps the channel of a disconnected miner open for a while.
+//! A `mining.subscribe` carrying that session id from the same IP address in time takes the
+//! channel over, with its extranonce1, instead of opening a new one.
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use stratum_apps::{
+    stratum_core::{
+        bitcoin::Target,
+        sv1_api::{client_to_server, json_rpc, utils::Extranonce},
+    },
+    utils::types::ChannelId,
+};
+
+/// Channel of a disconnected miner, kept open to resume its session.
+#[derive(Debug, Clone)]
+pub struct RetainedSession {
+    pub channel_id: ChannelId,
+    pub extranonce1: Extranonce<'static>,
+    pub extranonce2_len: usize,
+    pub upstream_target: Option<Target>,
+    pub peer_ip: IpAddr,
+    retained_at: Instant,
+}
+
+impl RetainedSession {
+    pub fn new(
+        channel_id: ChannelId,
+        extranonce1: Extranonce<'static>,
+        extranonce2_len: usize,
+        upstream_target: Option<Target>,
+        peer_ip: IpAddr,
+    ) -> Self {
+        Self {
+            channel_id,
+            extranonce1,
+            extranonce2_len,
+            upstream_target,
+            peer_ip,
+            retained_at: Instant::now(),
+        }
+    }
+
+    /// Returns how long ago the channel was retained.
+    pub fn retained_for(&self) -> Duration {
+        self.retained_at.elapsed()
+    }
+}
+
+/// Returns the session id handed out to a miner mining on `extranonce1`.
+pub fn session_id(extranonce1: &Extranonce<'_>) -> String {
+    hex::encode(extranonce1)
+}
+
+/// Returns the session id a `mining.subscribe` request presents, as the extranonce1 its miner
+/// mined on before.
+pub fn presented_session_id(message: &json_rpc::Message) -> Option<String> {
+    let json_rpc::Message::StandardRequest(request) = message else {
+        return None;
+    };
+    if request.method != "mining.subscribe" {
+        return None;
+    }
+    let subscribe = client_to_server::Subscribe::try_from(request.clone()).ok()?;
+    Some(session_id(subscribe.extranonce1.as_ref()?))
+}
+
+/// Sessions of disconnected miners, kept for a fixed time after they disconnect.
+#[derive(Debug)]
+pub struct SessionResumption {
+    ttl: Duration,
+    retained: DashMap<String, RetainedSession>,
+}
+
+impl SessionResumption {
+    /// Creates a store keeping each session for `ttl` after its miner disconnected.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            retained: DashMap::new(),
+        }
+    }
+
+    /// Keeps `session` under the session id of its extranonce1.
+    pub fn retain(&self, session: RetainedSession) {
+        self.retained
+            .insert(session_id(&session.extranonce1), session);
+    }
+
+    /// Takes the session `session_id` resumed from `peer_ip`, if its miner disconnected less than
+    /// the retention time ago from the same address.
+    ///
+    /// A session presented from another address stays retained for its own miner.
+    pub fn resume(&self, session_id: &str, peer_ip: IpAddr) -> Option<RetainedSession> {
+        let (_, session) = self.retained.remove_if(session_id, |_, session| {
+            session.peer_ip == peer_ip && session.retained_at.elapsed() < self.ttl
+        })?;
+        Some(session)
+    }
+
+    /// Removes and returns the sessions retained for longer than the retention time, whose
+    /// channels can be closed.
+    pub fn take_expired(&self) -> Vec<RetainedSession> {
+        let expired: Vec<String> = self
+            .retained
+            .iter()
+            .filter(|session| session.retained_at.elapsed() >= self.ttl)
+            .map(|session| session.key().clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|session_id| self.retained.remove(&session_id))
+            .map(|(_, session)| session)
+            .collect()
+    }
+
+    /// Returns whether `channel_id` is the channel of a retained session.
+    pub fn is_retained(&self, channel_id: ChannelId) -> bool {
+        self.retained
+            .iter()
+            .any(|session| session.channel_id == channel_id)
+    }
+
+    /// Drops the session of `channel_id`, e.g. once the channel was closed by the upstream.
+    pub fn remove_channel(&self, channel_id: ChannelId) {
+        self.retained
+            .retain(|_, session| session.channel_id != channel_id);
+    }
+
+    /// Drops every retained session.
+    pub fn clear(&self) {
+        self.retained.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn session(channel_id: ChannelId, ip: IpAddr) -> RetainedSession {
+        let extranonce1: Extranonce<'static> = vec![0, 0, 0, channel_id as u8].try_into().unwrap();
+        RetainedSession::new(channel_id, extranonce1, 4, None, ip)
+    }
+
+    #[test]
+    fn resumes_session_from_same_ip_once() {
+        let resumption = SessionResumption::new(Duration::from_secs(60));
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        resumption.retain(session(7, ip));
+        assert!(resumption.is_retained(7));
+
+        assert!(resumption
+            .resume("00000007", IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+            .is_none());
+        assert!(resumption.resume("00000008", ip).is_none());
+        let resumed = resumption.resume("00000007", ip).unwrap();
+        assert_eq!(resumed.channel_id, 7);
+        assert_eq!(resumed.extranonce2_len, 4);
+        assert!(resumption.resume("00000007", ip).is_none());
+        assert!(!resumption.is_retained(7));
+    }
+
+    #[test]
+    fn expired_session_is_not_resumed() {
+        let resumption = SessionResumption::new(Duration::from_millis(10));
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        resumption.retain(session(7, ip));
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(resumption.resume("00000007", ip).is_none());
+        let expired = resumption.take_expired();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].channel_id, 7);
+        assert!(resumption.take_expired().is_empty());
+    }
+}
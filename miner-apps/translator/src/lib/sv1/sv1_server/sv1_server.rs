@@ -1,52 +1,79 @@
 use crate::{
-    config::TranslatorConfig,
+    config::{OpenChannelFailureAction, TranslatorConfig},
     error::{self, TproxyError, TproxyErrorKind, TproxyResult},
+    hot_reload::HotReloadableConfig,
     is_aggregated, is_non_aggregated,
     status::{handle_error, Status, StatusSender},
     sv1::{
-        downstream::downstream::Downstream,
-        sv1_server::{channel::Sv1ServerChannelState, KEEPALIVE_JOB_ID_DELIMITER},
+        downstream::{data::DownstreamData, downstream::Downstream},
+        sv1_server::{
+            channel::Sv1ServerChannelState,
+            channel_affinity::ChannelAffinity,
+            job_propagation::JobPropagationTracker,
+            pending_jobs::PendingJobs,
+            session_resumption::{
+                presented_session_id, session_id, RetainedSession, SessionResumption,
+            },
+            vardiff_retention::VardiffRetention,
+            KEEPALIVE_JOB_ID_DELIMITER,
+        },
+    },
+    sv2::channel_manager::upstream_router::UpstreamRouter,
+    utils::{
+        authorized_worker_name, build_sv1_set_difficulty, is_aggregated_channel_id,
+        take_suggested_extranonce2_size, ShutdownMessage, AGGREGATED_CHANNEL_ID,
     },
-    utils::{ShutdownMessage, AGGREGATED_CHANNEL_ID},
 };
 use async_channel::{Receiver, Sender};
 use dashmap::DashMap;
 use std::{
-    collections::HashMap,
-    net::SocketAddr,
+    collections::{HashMap, HashSet, VecDeque},
+    net::{IpAddr, SocketAddr},
     sync::{
-        atomic::{AtomicU32, AtomicUsize, Ordering},
+        atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use stratum_apps::{
     custom_mutex::Mutex,
-    network_helpers::sv1_connection::ConnectionSV1,
+    network_helpers::{
+        connection_limiter::{ConnectionLimiter, ConnectionPermit},
+        sv1_connection::ConnectionSV1,
+    },
     stratum_core::{
         binary_sv2::Str0255,
         bitcoin::Target,
         channels_sv2::{target::hash_rate_to_target, Vardiff, VardiffState},
         extensions_sv2::UserIdentity,
-        mining_sv2::{CloseChannel, SetNewPrevHash, SetTarget},
+        mining_sv2::{CloseChannel, NewExtendedMiningJob, SetNewPrevHash, SetTarget},
         parsers_sv2::{Mining, Tlv, TlvField},
         stratum_translation::{
+            error::StratumTranslationError,
             sv1_to_sv2::{
                 build_sv2_open_extended_mining_channel,
                 build_sv2_submit_shares_extended_from_sv1_submit,
             },
-            sv2_to_sv1::{build_sv1_notify_from_sv2, build_sv1_set_difficulty_from_sv2_target},
+            sv2_to_sv1::build_sv1_notify_from_sv2,
+        },
+        sv1_api::{
+            json_rpc, server_to_client,
+            utils::{Extranonce, HexU32Be},
+            IsServer,
         },
-        sv1_api::{json_rpc, server_to_client, utils::HexU32Be, IsServer},
     },
     task_manager::TaskManager,
-    utils::types::{ChannelId, DownstreamId, Hashrate, RequestId, SharesPerMinute},
-};
-use tokio::{
-    net::TcpListener,
-    sync::{broadcast, mpsc},
+    utils::{
+        bind_retry::bind_with_retry,
+        types::{ChannelId, DownstreamId, Hashrate, RequestId},
+    },
 };
-use tracing::{debug, error, info, trace, warn};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{debug, error, info, trace, warn, Instrument as _};
+
+// Longest wait for `mining.authorize` before the channel of a miner is opened without its worker
+// name, for miners that only authorize once `mining.subscribe` is answered.
+const WORKER_NAME_WAIT: Duration = Duration::from_secs(2);
 
 /// SV1 server that handles connections from SV1 miners.
 ///
@@ -61,25 +88,66 @@ use tracing::{debug, error, info, trace, warn};
 #[derive(Clone)]
 pub struct Sv1Server {
     pub(crate) sv1_server_channel_state: Sv1ServerChannelState,
-    pub(crate) shares_per_minute: SharesPerMinute,
+    /// Configuration fields that can change while the server runs
+    pub(crate) hot_config: Arc<HotReloadableConfig>,
     pub(crate) listener_addr: SocketAddr,
     pub(crate) config: TranslatorConfig,
     pub(crate) sequence_counter: Arc<AtomicU32>,
     pub(crate) miner_counter: Arc<AtomicU32>,
     pub(crate) keepalive_job_id_counter: Arc<AtomicU32>,
+    /// Number of jobs whose keepalive time reached the future block time cap
+    pub(crate) keepalive_time_capped: Arc<AtomicU64>,
+    /// Original upstream job id of the job whose keepalive time is capped, for each channel whose
+    /// keepalives are capped
+    pub(crate) keepalive_capped_jobs: Arc<DashMap<ChannelId, String>>,
+    /// Number of shares rejected for a keepalive job whose original job is no longer valid
+    pub(crate) keepalive_shares_orphaned: Arc<AtomicU64>,
+    /// Routes the channels of downstreams to upstreams by worker name, shared with the channel
+    /// manager
+    pub(crate) upstream_router: Arc<UpstreamRouter>,
+    /// In aggregated mode, the aggregated channel each downstream channel was opened on, shared
+    /// with the channel manager
+    pub(crate) aggregated_channel_ids: Arc<DashMap<ChannelId, ChannelId>>,
+    /// Latency of jobs from upstream until their `mining.notify` reached every downstream
+    pub(crate) job_propagation: Arc<JobPropagationTracker>,
+    /// Per-IP connection cap and accept rate limiter of the listener
+    pub(crate) connection_limiter: Arc<ConnectionLimiter>,
+    /// Connection slots held by the downstreams, released when they disconnect
+    pub(crate) connection_permits: Arc<DashMap<DownstreamId, ConnectionPermit>>,
     pub(crate) downstream_id_factory: Arc<AtomicUsize>,
     pub(crate) request_id_factory: Arc<AtomicU32>,
     pub(crate) downstreams: Arc<DashMap<DownstreamId, Downstream>>,
-    pub(crate) request_id_to_downstream_id: Arc<DashMap<RequestId, DownstreamId>>,
+    /// Downstream each pending `OpenExtendedMiningChannel` was sent for, and when
+    pub(crate) request_id_to_downstream_id: Arc<DashMap<RequestId, (DownstreamId, Instant)>>,
+    /// Downstreams waiting for a free slot under `max_in_flight_open_channels` to request their
+    /// channel, in arrival order, with the worker the channel is opened for
+    pub(crate) queued_open_channel_requests: Arc<Mutex<VecDeque<(DownstreamId, Option<String>)>>>,
+    /// Downstream each `OpenExtendedMiningChannel` unanswered within the timeout was sent for,
+    /// to attach the channel if it still opens
+    pub(crate) late_open_channel_requests: Arc<DashMap<RequestId, DownstreamId>>,
     pub(crate) vardiff: Arc<DashMap<DownstreamId, Arc<Mutex<VardiffState>>>>,
+    /// Vardiff states of disconnected miners, kept to resume their difficulty on reconnection
+    pub(crate) vardiff_retention: Option<Arc<VardiffRetention>>,
+    /// Channels of disconnected miners, kept open to resume their session on reconnection
+    pub(crate) session_resumption: Option<Arc<SessionResumption>>,
+    /// Channels of disconnected miners, kept for their worker to rejoin them on reconnection
+    pub(crate) channel_affinity: Option<Arc<ChannelAffinity>>,
+    /// Worker of each miner disconnected to be rerouted to another upstream, by its IP address,
+    /// whose next connection opens its channel on the upstream of the worker
+    pub(crate) rerouted_workers: Arc<DashMap<IpAddr, String>>,
     /// HashMap to store the SetNewPrevHash for each channel
     /// Used in both aggregated and non-aggregated mode
     pub(crate) prevhashes: Arc<DashMap<ChannelId, SetNewPrevHash<'static>>>,
+    /// Jobs received before the SetNewPrevHash of their channel
+    pub(crate) pending_jobs: Arc<PendingJobs>,
     /// Tracks pending target updates that are waiting for SetTarget response from upstream
     pub(crate) pending_target_updates: Arc<Mutex<Vec<PendingTargetUpdate>>>,
-    /// Valid Sv1 jobs storage, containing only a single shared entry (AGGREGATED_CHANNEL_ID) in
-    /// case of channels aggregation (aggregated mode)
+    /// Valid Sv1 jobs storage, containing one shared entry per aggregated channel
+    /// (AGGREGATED_CHANNEL_ID and the IDs below it) in case of channels aggregation (aggregated
+    /// mode)
     pub(crate) valid_sv1_jobs: Arc<DashMap<ChannelId, Vec<server_to_client::Notify<'static>>>>,
+    /// Since when no downstream is connected while the aggregated channel is open
+    pub(crate) idle_since: Arc<Mutex<Option<Instant>>>,
 }
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -105,28 +173,93 @@ impl Sv1Server {
         channel_manager_sender: Sender<(Mining<'static>, Option<Vec<Tlv>>)>,
         config: TranslatorConfig,
     ) -> Self {
-        let shares_per_minute = config.downstream_difficulty_config.shares_per_minute;
+        let hot_config = Arc::new(HotReloadableConfig::new(&config));
         let sv1_server_channel_state =
             Sv1ServerChannelState::new(channel_manager_receiver, channel_manager_sender);
+        let job_propagation = Arc::new(JobPropagationTracker::new(
+            config.job_propagation_alarm_threshold(),
+        ));
+        let connection_limiter = Arc::new(
+            ConnectionLimiter::new(
+                config.max_connections_per_ip(),
+                config.max_accepts_per_sec(),
+            )
+            .with_churn_warning(config.churn_warning_per_minute()),
+        );
+        let vardiff_retention = config
+            .vardiff_retention()
+            .filter(|_| config.downstream_difficulty_config.enable_vardiff)
+            .map(|ttl| Arc::new(VardiffRetention::new(ttl)));
+        let session_resumption = config
+            .session_resumption()
+            .map(|ttl| Arc::new(SessionResumption::new(ttl)));
+        let channel_affinity = config
+            .channel_affinity()
+            .filter(|_| config.aggregate_channels)
+            .map(|ttl| Arc::new(ChannelAffinity::new(ttl)));
+        let pending_jobs = Arc::new(PendingJobs::new(
+            config.max_pending_jobs_per_channel(),
+            config.pending_job_timeout(),
+        ));
         Self {
             sv1_server_channel_state,
             config,
             listener_addr,
-            shares_per_minute,
+            hot_config,
             miner_counter: Arc::new(AtomicU32::new(0)),
             sequence_counter: Arc::new(AtomicU32::new(1)),
             keepalive_job_id_counter: Arc::new(AtomicU32::new(0)),
+            keepalive_time_capped: Arc::new(AtomicU64::new(0)),
+            keepalive_capped_jobs: Arc::new(DashMap::new()),
+            keepalive_shares_orphaned: Arc::new(AtomicU64::new(0)),
+            upstream_router: Arc::new(UpstreamRouter::default()),
+            aggregated_channel_ids: Arc::new(DashMap::new()),
+            job_propagation,
+            connection_limiter,
+            connection_permits: Arc::new(DashMap::new()),
             downstream_id_factory: Arc::new(AtomicUsize::new(1)),
             request_id_factory: Arc::new(AtomicU32::new(1)),
             downstreams: Arc::new(DashMap::new()),
             request_id_to_downstream_id: Arc::new(DashMap::new()),
+            queued_open_channel_requests: Arc::new(Mutex::new(VecDeque::new())),
+            late_open_channel_requests: Arc::new(DashMap::new()),
             vardiff: Arc::new(DashMap::new()),
+            vardiff_retention,
+            session_resumption,
+            channel_affinity,
+            rerouted_workers: Arc::new(DashMap::new()),
             prevhashes: Arc::new(DashMap::new()),
+            pending_jobs,
             pending_target_updates: Arc::new(Mutex::new(Vec::new())),
             valid_sv1_jobs: Arc::new(DashMap::new()),
+            idle_since: Arc::new(Mutex::new(None)),
         }
     }
 
+    /// Makes the server follow `hot_config`, the configuration fields reloaded while it runs,
+    /// instead of the values of its own configuration.
+    pub fn with_hot_config(mut self, hot_config: Arc<HotReloadableConfig>) -> Self {
+        self.hot_config = hot_config;
+        self
+    }
+
+    /// Sets the router sending the channels of routed downstreams to their upstream, shared with
+    /// the channel manager.
+    pub fn with_upstream_router(mut self, upstream_router: Arc<UpstreamRouter>) -> Self {
+        self.upstream_router = upstream_router;
+        self
+    }
+
+    /// Sets the map of the aggregated channel each downstream channel was opened on, shared with
+    /// the channel manager.
+    pub fn with_aggregated_channel_ids(
+        mut self,
+        aggregated_channel_ids: Arc<DashMap<ChannelId, ChannelId>>,
+    ) -> Self {
+        self.aggregated_channel_ids = aggregated_channel_ids;
+        self
+    }
+
     /// Starts the SV1 server and begins accepting connections.
     ///
     /// This method:
@@ -159,37 +292,36 @@ impl Sv1Server {
         info!("Starting SV1 server on {}", self.listener_addr);
         let mut shutdown_rx_main = notify_shutdown.subscribe();
 
-        // get the first target for the first set difficulty message
-        let first_target: Target = hash_rate_to_target(
-            self.config
-                .downstream_difficulty_config
-                .min_individual_miner_hashrate as f64,
-            self.config.downstream_difficulty_config.shares_per_minute as f64,
-        )
-        .unwrap();
-
         let vardiff_future = self.clone().spawn_vardiff_loop();
 
         let keepalive_future = self.clone().spawn_job_keepalive_loop();
 
-        let listener = TcpListener::bind(self.listener_addr).await.map_err(|e| {
-            error!("Failed to bind to {}: {}", self.listener_addr, e);
-            TproxyError::shutdown(e)
-        })?;
+        let session_expiry_future = self.clone().spawn_session_expiry_loop();
+
+        let listener = bind_with_retry(self.listener_addr, self.config.bind_retry())
+            .await
+            .map_err(|e| {
+                error!("Failed to bind to {}: {}", self.listener_addr, e);
+                TproxyError::shutdown(e)
+            })?;
 
         info!("Translator Proxy: listening on {}", self.listener_addr);
 
         let sv1_status_sender = StatusSender::Sv1Server(status_sender.clone());
         let task_manager_clone = task_manager.clone();
         let vardiff_enabled = self.config.downstream_difficulty_config.enable_vardiff;
-        let keepalive_enabled = self
+        let session_expiry_enabled =
+            self.session_resumption.is_some() || self.channel_affinity.is_some();
+        let idle_no_downstream = self
             .config
-            .downstream_difficulty_config
-            .job_keepalive_interval_secs
-            > 0;
+            .idle_no_downstream()
+            .filter(|_| self.config.aggregate_channels);
         task_manager_clone.spawn(async move {
             tokio::pin!(vardiff_future);
             tokio::pin!(keepalive_future);
+            tokio::pin!(session_expiry_future);
+            let mut open_channel_timeout_ticker = tokio::time::interval(Duration::from_secs(1));
+            let mut idle_ticker = tokio::time::interval(Duration::from_secs(1));
             loop {
                 tokio::select! {
                     message = shutdown_rx_main.recv() => {
@@ -200,55 +332,65 @@ impl Sv1Server {
                                 break;
                             }
                             Ok(ShutdownMessage::DownstreamShutdown(downstream_id)) => {
-                                if self.config.downstream_difficulty_config.enable_vardiff {
-                                    // Only remove from vardiff map if vardiff is enabled
-                                    self.vardiff.remove(&downstream_id);
-                                }
-                                let current_downstream = self.downstreams.remove(&downstream_id);
-
-                                if let Some((downstream_id, downstream)) = current_downstream {
-                                    info!("🔌 Downstream: {downstream_id} disconnected and removed from sv1 server downstreams");
-                                    // In aggregated mode, send UpdateChannel to reflect the new state (only if vardiff enabled)
-                                    if self.config.downstream_difficulty_config.enable_vardiff {
-                                        self.send_update_channel_on_downstream_state_change().await;
-                                    }
-
-                                    let channel_id = downstream.downstream_data.super_safe_lock(|d| d.channel_id);
-                                    if let Some(channel_id) = channel_id {
-                                        self.prevhashes.remove(&channel_id);
-                                        if is_non_aggregated() {
-                                            info!("Sending CloseChannel message: {channel_id} for downstream: {downstream_id}");
-                                            let reason_code =  Str0255::try_from("downstream disconnected".to_string()).unwrap();
-                                            _ = self.sv1_server_channel_state
-                                                .channel_manager_sender
-                                                .send((Mining::CloseChannel(CloseChannel {
-                                                    channel_id,
-                                                    reason_code,
-                                                }), None))
-                                                .await;
-                                        }
-                                    }
-                                }
+                                self.remove_downstream(downstream_id).await;
                             }
                             Ok(ShutdownMessage::UpstreamFallback {tx}) => {
                                 if self.config.downstream_difficulty_config.enable_vardiff {
                                     self.vardiff.clear();
                                 }
+                                if let Some(vardiff_retention) = &self.vardiff_retention {
+                                    vardiff_retention.clear();
+                                }
+                                if let Some(session_resumption) = &self.session_resumption {
+                                    session_resumption.clear();
+                                }
+                                if let Some(channel_affinity) = &self.channel_affinity {
+                                    channel_affinity.clear();
+                                }
+                                self.rerouted_workers.clear();
+                                self.request_id_to_downstream_id.clear();
+                                self.queued_open_channel_requests
+                                    .super_safe_lock(|queue| queue.clear());
+                                self.late_open_channel_requests.clear();
                                 self.prevhashes.clear();
+                                self.pending_jobs.clear();
+                                self.keepalive_capped_jobs.clear();
                                 self.downstreams.clear();
+                                self.connection_permits.clear();
+                                self.job_propagation.clear();
                                 info!("Fallback in processing stopping sv1 server");
                                 drop(tx);
                                 break;
                             }
+                            Ok(ShutdownMessage::UpstreamRotation {tx}) => {
+                                info!("Upstream rotation in progress, keeping downstreams connected");
+                                self.leave_rotated_upstream();
+                                drop(tx);
+                            }
                             _ => {}
                         }
                     }
                     result = listener.accept() => {
                         match result {
                             Ok((stream, addr)) => {
+                                let connection_permit = match self.connection_limiter.try_acquire(addr.ip()) {
+                                    Ok(permit) => permit,
+                                    Err(reason) => {
+                                        warn!("Rejecting SV1 downstream connection from {}: {}", addr, reason);
+                                        continue;
+                                    }
+                                };
                                 info!("New SV1 downstream connection from {}", addr);
                                 let connection = ConnectionSV1::new(stream).await;
                                 let downstream_id = self.downstream_id_factory.fetch_add(1, Ordering::Relaxed);
+                                self.connection_permits.insert(downstream_id, connection_permit);
+                                // the starting difficulty follows configuration reloads
+                                let min_individual_miner_hashrate = self.hot_config.min_individual_miner_hashrate();
+                                let first_target: Target = hash_rate_to_target(
+                                    min_individual_miner_hashrate as f64,
+                                    self.hot_config.shares_per_minute() as f64,
+                                )
+                                .unwrap();
                                 let downstream = Downstream::new(
                                     downstream_id,
                                     connection.sender().clone(),
@@ -256,8 +398,15 @@ impl Sv1Server {
                                     self.sv1_server_channel_state.downstream_to_sv1_server_sender.clone(),
                                     self.sv1_server_channel_state.sv1_server_to_downstream_sender.clone(),
                                     first_target,
-                                    Some(self.config.downstream_difficulty_config.min_individual_miner_hashrate),
+                                    Some(min_individual_miner_hashrate),
+                                    self.job_propagation.clone(),
+                                    self.valid_sv1_jobs.clone(),
+                                )
+                                .with_difficulty_quantization(
+                                    self.config.downstream_difficulty_config.difficulty_quantization,
                                 );
+                                downstream.downstream_data.super_safe_lock(|d| d.peer_ip = Some(addr.ip()));
+                                downstream.span.record("peer_addr", tracing::field::display(addr));
                                 // vardiff initialization (only if enabled)
                                 self.downstreams.insert(downstream_id, downstream.clone());
                                 // Insert vardiff state for this downstream only if vardiff is enabled
@@ -277,6 +426,8 @@ impl Sv1Server {
                                     shutdown_complete_tx.clone(),
                                     status_sender,
                                     task_manager.clone(),
+                                    self.config.slow_consumer_policy(),
+                                    self.config.authorize_grace(),
                                 );
                             }
                             Err(e) => {
@@ -292,9 +443,7 @@ impl Sv1Server {
                             }
                         }
                     }
-                    res = self.handle_upstream_message(
-                        first_target,
-                    ) => {
+                    res = self.handle_upstream_message() => {
                         if let Err(e) = res {
                             if handle_error(&sv1_status_sender, e).await {
                                 self.sv1_server_channel_state.drop();
@@ -302,8 +451,56 @@ impl Sv1Server {
                             }
                         }
                     }
+                    _ = open_channel_timeout_ticker.tick() => {
+                        let mut stop = false;
+                        for downstream_id in self.take_expired_worker_name_waits() {
+                            info!(
+                                "Downstream {} did not authorize in time, opening its channel",
+                                downstream_id
+                            );
+                            if let Err(e) =
+                                self.handle_open_channel_request(downstream_id, None).await
+                            {
+                                stop = handle_error(&sv1_status_sender, e).await;
+                                if stop {
+                                    break;
+                                }
+                            }
+                        }
+                        for downstream_id in self.take_timed_out_open_channel_requests() {
+                            if stop {
+                                break;
+                            }
+                            warn!("Channel of downstream {} was not opened in time", downstream_id);
+                            if let Err(e) =
+                                self.handle_open_channel_failure(downstream_id, false).await
+                            {
+                                stop = handle_error(&sv1_status_sender, e).await;
+                                if stop {
+                                    break;
+                                }
+                            }
+                        }
+                        if !stop {
+                            // slots of the requests that timed out or whose downstream left
+                            if let Err(e) = self.send_queued_open_channel_requests().await {
+                                stop = handle_error(&sv1_status_sender, e).await;
+                            }
+                        }
+                        if stop {
+                            self.sv1_server_channel_state.drop();
+                            break;
+                        }
+                    }
+                    _ = idle_ticker.tick(), if idle_no_downstream.is_some() => {
+                        let idle = idle_no_downstream.unwrap_or_default();
+                        if self.aggregated_channel_idle(idle) {
+                            self.close_idle_aggregated_channel().await;
+                        }
+                    }
                     _ = &mut vardiff_future, if vardiff_enabled => {}
-                    _ = &mut keepalive_future, if keepalive_enabled => {}
+                    _ = &mut keepalive_future => {}
+                    _ = &mut session_expiry_future, if session_expiry_enabled => {}
                 }
             }
             drop(shutdown_complete_tx);
@@ -332,81 +529,201 @@ impl Sv1Server {
             .await
             .map_err(TproxyError::shutdown)?;
 
-        let downstream = self.downstreams.get(&downstream_id);
+        // The handling of the message is logged within the connection span of its downstream
+        let Some(downstream) = self
+            .downstreams
+            .get(&downstream_id)
+            .map(|downstream| downstream.value().clone())
+        else {
+            return Ok(());
+        };
+        let span = downstream.span.clone();
+        self.handle_message_from_downstream(downstream_id, downstream, downstream_message)
+            .instrument(span)
+            .await
+    }
 
-        if let Some(downstream) = downstream {
-            let channel_id = downstream
+    // Handles a message of `downstream`, queuing it until the channel of the downstream is open.
+    async fn handle_message_from_downstream(
+        &self,
+        downstream_id: DownstreamId,
+        downstream: Downstream,
+        mut downstream_message: json_rpc::Message,
+    ) -> TproxyResult<(), error::Sv1Server> {
+        // The handler of `mining.extranonce.subscribe` does not know the downstream
+        if matches!(
+            &downstream_message,
+            json_rpc::Message::StandardRequest(request)
+                if request.method == "mining.extranonce.subscribe"
+        ) {
+            downstream
+                .downstream_data
+                .super_safe_lock(|data| data.extranonce_subscribed = true);
+        }
+        let channel_id = downstream
+            .downstream_data
+            .super_safe_lock(|data| data.channel_id);
+        if channel_id.is_none() {
+            // The channel is opened on the first message, so only a size suggested by the
+            // first `mining.configure` is taken into account
+            if self.config.forward_miner_extranonce2_size() {
+                if let Some(size) = take_suggested_extranonce2_size(&mut downstream_message) {
+                    debug!(
+                        "Down: Downstream {} suggested an extranonce2 size of {} bytes",
+                        downstream_id, size
+                    );
+                    downstream
+                        .downstream_data
+                        .super_safe_lock(|d| d.suggested_extranonce2_size = Some(size));
+                }
+            }
+            let is_first_message = downstream
                 .downstream_data
-                .super_safe_lock(|data| data.channel_id);
-            if channel_id.is_none() {
-                let is_first_message = downstream
+                .super_safe_lock(|d| d.queued_sv1_handshake_messages.is_empty());
+            let resumed_session = self.take_resumed_session(&downstream, &downstream_message);
+            let rejoined_channel = resumed_session
+                .is_none()
+                .then(|| self.take_rejoined_channel(&downstream, &downstream_message))
+                .flatten();
+            debug!("Down: Queuing Sv1 message until channel is established");
+            downstream.downstream_data.super_safe_lock(|data| {
+                data.queued_sv1_handshake_messages
+                    .push(downstream_message.clone())
+            });
+            // A resumed session brings its own channel, any channel still opening for the
+            // downstream is released once it opens
+            if let Some(session) = resumed_session {
+                return self
+                    .resume_session(&downstream, downstream_id, session)
+                    .await;
+            }
+            // The worker the channel was retained for is checked on `mining.authorize`
+            if let Some((worker_name, session)) = rejoined_channel {
+                info!(
+                    "Downstream {} rejoined channel {} of worker {}",
+                    downstream_id, session.channel_id, worker_name
+                );
+                downstream
                     .downstream_data
-                    .super_safe_lock(|d| d.queued_sv1_handshake_messages.is_empty());
-                if is_first_message {
-                    self.handle_open_channel_request(downstream_id).await?;
+                    .super_safe_lock(|d| d.rejoined_worker_name = Some(worker_name));
+                return self
+                    .take_over_channel(&downstream, downstream_id, session)
+                    .await;
+            }
+            // The channel is opened before the worker name is known, on the upstream the worker
+            // of a miner reconnecting after being rerouted is routed to. A user identity template
+            // using the worker name waits for `mining.authorize` instead.
+            if is_first_message {
+                let worker_name = self.take_rerouted_worker(&downstream);
+                if worker_name.is_none() && self.config.user_identity_template_uses_worker() {
+                    debug!(
+                        "Down: Waiting for mining.authorize to open the channel of downstream {}",
+                        downstream_id
+                    );
+                    downstream
+                        .downstream_data
+                        .super_safe_lock(|d| d.awaiting_worker_name_since = Some(Instant::now()));
+                } else {
+                    self.handle_open_channel_request(downstream_id, worker_name.as_deref())
+                        .await?;
                     debug!(
                         "Down: Sent OpenChannel request for downstream {}",
                         downstream_id
                     );
+                    return Ok(());
+                }
+            }
+            if let Some(worker_name) = authorized_worker_name(&downstream_message) {
+                let awaiting_worker_name = downstream
+                    .downstream_data
+                    .super_safe_lock(|d| d.awaiting_worker_name_since.take().is_some());
+                if awaiting_worker_name {
+                    self.handle_open_channel_request(downstream_id, Some(worker_name))
+                        .await?;
                 }
-                debug!("Down: Queuing Sv1 message until channel is established");
-                downstream.downstream_data.super_safe_lock(|data| {
-                    data.queued_sv1_handshake_messages
-                        .push(downstream_message.clone())
-                });
-                return Ok(());
             }
+            return Ok(());
+        }
 
-            let response = self
-                .clone()
-                .handle_message(Some(downstream_id), downstream_message.clone());
+        // A miner rejoining the channel of a worker has to authorize that worker first
+        if let Some(worker_name) = authorized_worker_name(&downstream_message) {
+            let rejoined_worker_name = downstream
+                .downstream_data
+                .super_safe_lock(|d| d.rejoined_worker_name.take());
+            if let Some(rejoined_worker_name) =
+                rejoined_worker_name.filter(|rejoined| rejoined != worker_name)
+            {
+                return Err(TproxyError::disconnect(
+                    TproxyErrorKind::RejoinedWorkerMismatch(
+                        rejoined_worker_name,
+                        worker_name.to_string(),
+                    ),
+                    downstream_id,
+                ));
+            }
+        }
 
-            match response {
-                Ok(Some(response_msg)) => {
-                    debug!(
-                        "Down: Sending Sv1 message to downstream: {:?}",
-                        response_msg
-                    );
-                    downstream
-                        .downstream_channel_state
-                        .downstream_sv1_sender
-                        .send(response_msg.into())
-                        .await
-                        .map_err(|error| {
-                            error!("Down: Failed to send message to downstream: {error:?}");
-                            TproxyError::disconnect(
-                                TproxyErrorKind::ChannelErrorSender,
-                                downstream_id,
-                            )
-                        })?;
-
-                    // Check if this was an authorize message and handle sv1 handshake completion
-                    if let json_rpc::Message::StandardRequest(request) = &downstream_message {
-                        if request.method == "mining.authorize" {
-                            info!("Down: Handling mining.authorize after handshake completion");
-                            if let Err(e) = downstream.handle_sv1_handshake_completion().await {
-                                error!("Down: Failed to handle handshake completion: {:?}", e);
-                                return Err(TproxyError::disconnect(e, downstream_id));
-                            }
+        let response = self
+            .clone()
+            .handle_message(Some(downstream_id), downstream_message.clone());
+
+        // A share rejected locally is answered with the error code of its rejection reason
+        let share_rejection = downstream
+            .downstream_data
+            .super_safe_lock(|d| d.share_rejection.take());
+        let response = match (response, share_rejection) {
+            (Ok(Some(json_rpc::Message::OkResponse(response))), Some(rejection)) => {
+                Ok(Some(rejection.response(response.id)))
+            }
+            (response, _) => response,
+        };
+
+        match response {
+            Ok(Some(response_msg)) => {
+                debug!(
+                    "Down: Sending Sv1 message to downstream: {:?}",
+                    response_msg
+                );
+                downstream
+                    .downstream_channel_state
+                    .downstream_sv1_sender
+                    .send(response_msg.into())
+                    .await
+                    .map_err(|error| {
+                        error!("Down: Failed to send message to downstream: {error:?}");
+                        TproxyError::disconnect(TproxyErrorKind::ChannelErrorSender, downstream_id)
+                    })?;
+
+                // Check if this was the first authorize message and handle sv1 handshake
+                // completion, later ones authorize additional workers
+                if let json_rpc::Message::StandardRequest(request) = &downstream_message {
+                    if request.method == "mining.authorize"
+                        && !downstream.sv1_handshake_complete.load(Ordering::SeqCst)
+                    {
+                        info!("Down: Handling mining.authorize after handshake completion");
+                        if let Err(e) = downstream.handle_sv1_handshake_completion().await {
+                            error!("Down: Failed to handle handshake completion: {:?}", e);
+                            return Err(TproxyError::disconnect(e, downstream_id));
                         }
+                        self.reroute_worker(&downstream).await?;
                     }
                 }
-                Ok(None) => {
-                    // Message was handled but no response needed
-                }
-                Err(e) => {
-                    error!("Down: Error handling downstream message: {:?}", e);
-                    return Err(TproxyError::disconnect(e, downstream_id));
-                }
             }
-
-            // Check if there's a pending share to send to the Sv1Server
-            let pending_share = downstream
-                .downstream_data
-                .super_safe_lock(|d| d.pending_share.take());
-            if let Some(share) = pending_share {
-                self.handle_submit_shares(share).await?;
+            Ok(None) => {
+                // Message was handled but no response needed
             }
+            Err(e) => {
+                error!("Down: Error handling downstream message: {:?}", e);
+                return Err(TproxyError::disconnect(e, downstream_id));
+            }
+        }
+
+        // Check if there's a pending share to send to the Sv1Server
+        let pending_share = downstream
+            .downstream_data
+            .super_safe_lock(|d| d.pending_share.take());
+        if let Some(share) = pending_share {
+            self.handle_submit_shares(share).await?;
         }
 
         Ok(())
@@ -461,7 +778,7 @@ impl Sv1Server {
             job_version,
             message.version_rolling_mask,
         )
-        .map_err(|_| TproxyError::shutdown(TproxyErrorKind::SV1Error))?;
+        .map_err(|_| TproxyError::disconnect(TproxyErrorKind::SV1Error, message.downstream_id))?;
 
         // Only add TLV fields with user identity in non-aggregated mode
         let tlv_fields = if is_non_aggregated() {
@@ -470,7 +787,7 @@ impl Sv1Server {
                 .get(&message.downstream_id)
                 .unwrap()
                 .downstream_data
-                .super_safe_lock(|d| d.user_identity.clone());
+                .super_safe_lock(|d| d.worker_user_identity(&share.user_name).to_string());
             UserIdentity::new(&user_identity_string)
                 .unwrap()
                 .to_tlv()
@@ -492,20 +809,22 @@ impl Sv1Server {
         Ok(())
     }
 
-    /// Handles channel opening requests from downstream when they send their first message.
+    /// Handles channel opening requests from downstream when they send their first message, or
+    /// authorize `worker_name` with channel affinity. A channel opened for `worker_name` goes to
+    /// the upstream the routing rules send it to.
+    ///
+    /// While `max_in_flight_open_channels` requests wait for an answer, the request is queued
+    /// behind the others, replacing any request still queued for the downstream.
     async fn handle_open_channel_request(
         &self,
         downstream_id: DownstreamId,
+        worker_name: Option<&str>,
     ) -> TproxyResult<(), error::Sv1Server> {
         info!(
             "SV1 server: opening extended mining channel for downstream {} after first message",
             downstream_id
         );
 
-        let request_id = self.request_id_factory.fetch_add(1, Ordering::Relaxed);
-        self.request_id_to_downstream_id
-            .insert(request_id, downstream_id);
-
         if !self.downstreams.contains_key(&downstream_id) {
             error!(
                 "Downstream {} not found when attempting to open channel",
@@ -517,22 +836,382 @@ impl Sv1Server {
             ));
         }
 
-        self.open_extended_mining_channel(request_id, downstream_id)
+        if self.config.max_in_flight_open_channels().is_none() {
+            return self
+                .send_open_channel_request(downstream_id, worker_name)
+                .await;
+        }
+        self.queued_open_channel_requests.super_safe_lock(|queue| {
+            queue.retain(|(id, _)| *id != downstream_id);
+            queue.push_back((downstream_id, worker_name.map(str::to_string)));
+        });
+        self.send_queued_open_channel_requests().await
+    }
+
+    // Sends queued channel requests while fewer than `max_in_flight_open_channels` requests wait
+    // for an answer. Requests of downstreams that left since are dropped.
+    async fn send_queued_open_channel_requests(&self) -> TproxyResult<(), error::Sv1Server> {
+        let Some(max_in_flight) = self.config.max_in_flight_open_channels() else {
+            return Ok(());
+        };
+        while self.request_id_to_downstream_id.len() < max_in_flight {
+            let Some((downstream_id, worker_name)) = self
+                .queued_open_channel_requests
+                .super_safe_lock(|queue| queue.pop_front())
+            else {
+                break;
+            };
+            if self.downstreams.contains_key(&downstream_id) {
+                self.send_open_channel_request(downstream_id, worker_name.as_deref())
+                    .await?;
+            }
+        }
+        let queued = self
+            .queued_open_channel_requests
+            .super_safe_lock(|queue| queue.len());
+        if queued > 0 {
+            debug!(
+                "{} channel requests queued behind {} in flight",
+                queued, max_in_flight
+            );
+        }
+        Ok(())
+    }
+
+    // Requests the channel of a downstream from the channel manager. The open channel timeout of
+    // the request starts now.
+    async fn send_open_channel_request(
+        &self,
+        downstream_id: DownstreamId,
+        worker_name: Option<&str>,
+    ) -> TproxyResult<(), error::Sv1Server> {
+        let request_id = self.request_id_factory.fetch_add(1, Ordering::Relaxed);
+        self.request_id_to_downstream_id
+            .insert(request_id, (downstream_id, Instant::now()));
+
+        if let Some(upstream) = worker_name.and_then(|name| self.config.routed_upstream_index(name))
+        {
+            info!(
+                "SV1 server: routing the channel of downstream {} to upstream {}",
+                downstream_id, upstream
+            );
+            self.upstream_router
+                .set_request_upstream(request_id, upstream);
+        }
+
+        self.open_extended_mining_channel(request_id, downstream_id, worker_name)
             .await?;
 
         Ok(())
     }
 
+    // Takes the downstreams whose channel waited longer than `WORKER_NAME_WAIT` for
+    // `mining.authorize`.
+    fn take_expired_worker_name_waits(&self) -> Vec<DownstreamId> {
+        self.downstreams
+            .iter()
+            .filter_map(|downstream| {
+                downstream
+                    .downstream_data
+                    .super_safe_lock(|d| {
+                        d.awaiting_worker_name_since
+                            .take_if(|since| since.elapsed() >= WORKER_NAME_WAIT)
+                    })
+                    .map(|_| *downstream.key())
+            })
+            .collect()
+    }
+
+    // Removes the channel requests unanswered for longer than `open_channel_timeout`, returning
+    // the downstreams they were sent for. A channel still opened for one of them is attached to
+    // its downstream if it has none by then.
+    fn take_timed_out_open_channel_requests(&self) -> Vec<DownstreamId> {
+        let open_channel_timeout = self.config.open_channel_timeout();
+        let mut timed_out = Vec::new();
+        self.request_id_to_downstream_id
+            .retain(|request_id, (downstream_id, sent_at)| {
+                let expired = sent_at.elapsed() >= open_channel_timeout;
+                if expired {
+                    self.late_open_channel_requests
+                        .insert(*request_id, *downstream_id);
+                    timed_out.push(*downstream_id);
+                }
+                !expired
+            });
+        timed_out
+    }
+
+    /// Handles a downstream whose channel was refused by the upstream or timed out, as set by
+    /// the configured [`OpenChannelFailureAction`].
+    ///
+    /// With [`OpenChannelFailureAction::Retry`], the channel is requested again while retries are
+    /// left, keeping the queued handshake messages of the downstream. Otherwise its queued
+    /// requests are answered with an error and the downstream is disconnected, or, when
+    /// `fall_back`, the translator falls back to the next upstream. A downstream whose channel
+    /// was on the upstream the translator rotated away from is handled the same way, as it has
+    /// no channel left to keep.
+    pub async fn handle_open_channel_failure(
+        &self,
+        downstream_id: DownstreamId,
+        fall_back: bool,
+    ) -> TproxyResult<(), error::Sv1Server> {
+        let Some(downstream) = self
+            .downstreams
+            .get(&downstream_id)
+            .map(|downstream| downstream.value().clone())
+        else {
+            return Ok(());
+        };
+        let Some((failed_attempts, worker_name, rotated)) =
+            downstream.downstream_data.super_safe_lock(|d| {
+                // The downstream resumed a session or rejoined a channel in the meantime, or
+                // keeps the channel it has if it could not be rerouted
+                if d.channel_id.is_some() && !d.rotating_upstream {
+                    if std::mem::take(&mut d.reopening_channel) {
+                        warn!(
+                            "Channel of downstream {} was not opened on the upstream of its \
+                             worker, keeping its current channel",
+                            downstream_id
+                        );
+                    }
+                    return None;
+                }
+                d.failed_open_channel_attempts += 1;
+                let worker_name = match d.channel_id {
+                    Some(_) => Some(d.authorized_worker_name.clone()).filter(|n| !n.is_empty()),
+                    None => d
+                        .queued_sv1_handshake_messages
+                        .iter()
+                        .find_map(|message| authorized_worker_name(message).map(str::to_string)),
+                };
+                Some((
+                    d.failed_open_channel_attempts,
+                    worker_name,
+                    d.rotating_upstream,
+                ))
+            })
+        else {
+            return Ok(());
+        };
+
+        let retries = self.config.open_channel_retries();
+        if self.config.open_channel_failure_action() == OpenChannelFailureAction::Retry
+            && failed_attempts <= retries
+        {
+            warn!(
+                "Channel of downstream {} was not opened, requesting it again ({}/{})",
+                downstream_id, failed_attempts, retries
+            );
+            // The channel goes to the upstream of the worker, once its name is known
+            let worker_name = worker_name.filter(|_| !self.config.upstream_routes().is_empty());
+            return self
+                .handle_open_channel_request(downstream_id, worker_name.as_deref())
+                .await;
+        }
+
+        if fall_back {
+            return Err(TproxyError::fallback(
+                TproxyErrorKind::OpenMiningChannelError,
+            ));
+        }
+        let error = TproxyErrorKind::DownstreamChannelNotOpened(failed_attempts);
+        if rotated {
+            // Its channel was closed along with the upstream left
+            downstream.downstream_data.super_safe_lock(|d| {
+                d.rotating_upstream = false;
+                d.channel_id = None;
+            });
+            // In aggregated mode, another downstream opens the channel the others share
+            self.reopen_rotated_channels().await?;
+        }
+        Err(self
+            .reject_queued_handshake(&downstream, downstream_id, error)
+            .await)
+    }
+
+    // Takes the retained session whose id a `mining.subscribe` presents, if the miner reconnected
+    // from the address the session was retained for.
+    fn take_resumed_session(
+        &self,
+        downstream: &Downstream,
+        message: &json_rpc::Message,
+    ) -> Option<RetainedSession> {
+        let session_resumption = self.session_resumption.as_ref()?;
+        let presented_session_id = presented_session_id(message)?;
+        let peer_ip = downstream.downstream_data.super_safe_lock(|d| d.peer_ip)?;
+        let session = session_resumption.resume(&presented_session_id, peer_ip);
+        if session.is_none() {
+            debug!(
+                "Down: Downstream {} presented session id {} that cannot be resumed",
+                downstream.downstream_id, presented_session_id
+            );
+        }
+        session
+    }
+
+    // Takes the channel retained under the session id a `mining.subscribe` presents, with the
+    // worker it was retained for, if the miner reconnected from the address it disconnected from.
+    fn take_rejoined_channel(
+        &self,
+        downstream: &Downstream,
+        message: &json_rpc::Message,
+    ) -> Option<(String, RetainedSession)> {
+        let channel_affinity = self.channel_affinity.as_ref()?;
+        let presented_session_id = presented_session_id(message)?;
+        let peer_ip = downstream.downstream_data.super_safe_lock(|d| d.peer_ip)?;
+        channel_affinity.rejoin(&presented_session_id, peer_ip)
+    }
+
+    // Takes the worker name of a miner that was disconnected to be rerouted, if it reconnected
+    // from the same address.
+    fn take_rerouted_worker(&self, downstream: &Downstream) -> Option<String> {
+        let peer_ip = downstream.downstream_data.super_safe_lock(|d| d.peer_ip)?;
+        self.rerouted_workers
+            .remove(&peer_ip)
+            .map(|(_, worker_name)| worker_name)
+    }
+
+    /// Resumes a retained session on the downstream whose `mining.subscribe` presented its id.
+    async fn resume_session(
+        &self,
+        downstream: &Downstream,
+        downstream_id: DownstreamId,
+        session: RetainedSession,
+    ) -> TproxyResult<(), error::Sv1Server> {
+        info!(
+            "Downstream {} resumed session {} on channel {}",
+            downstream_id,
+            session_id(&session.extranonce1),
+            session.channel_id
+        );
+        self.take_over_channel(downstream, downstream_id, session)
+            .await
+    }
+
+    // Moves the retained channel of `session` onto `downstream`.
+    //
+    // The downstream takes the channel over with its extranonce1, and gets the last job of the
+    // channel as its first job, since the upstream sends no new one for an open channel.
+    async fn take_over_channel(
+        &self,
+        downstream: &Downstream,
+        downstream_id: DownstreamId,
+        session: RetainedSession,
+    ) -> TproxyResult<(), error::Sv1Server> {
+        self.attach_channel(
+            downstream,
+            downstream_id,
+            session.channel_id,
+            session.extranonce1,
+            session.extranonce2_len,
+            session.upstream_target,
+        )
+        .await?;
+        if let Some(last_job) = self.get_last_job(Some(session.channel_id)) {
+            self.sv1_server_channel_state
+                .sv1_server_to_downstream_sender
+                .send((session.channel_id, Some(downstream_id), last_job.into()))
+                .map_err(|_| TproxyError::log(TproxyErrorKind::ChannelErrorSender))?;
+        }
+        Ok(())
+    }
+
+    // Sets `downstream` up on channel `channel_id`, answers the Sv1 handshake messages it queued
+    // while waiting for a channel and sends its initial difficulty.
+    async fn attach_channel(
+        &self,
+        downstream: &Downstream,
+        downstream_id: DownstreamId,
+        channel_id: ChannelId,
+        extranonce1: Extranonce<'static>,
+        extranonce2_len: usize,
+        upstream_target: Option<Target>,
+    ) -> TproxyResult<(), error::Sv1Server> {
+        let aggregated_channel_id = self.aggregated_channel_of(channel_id);
+        downstream
+            .downstream_data
+            .safe_lock(|d| {
+                d.extranonce1 = extranonce1;
+                d.extranonce2_len = extranonce2_len;
+                d.channel_id = Some(channel_id);
+                d.aggregated_channel_id = aggregated_channel_id;
+                // Set the initial upstream target from OpenExtendedMiningChannelSuccess, or the
+                // one of the resumed channel
+                if let Some(upstream_target) = upstream_target {
+                    d.set_upstream_target(upstream_target, downstream_id);
+                }
+            })
+            .map_err(TproxyError::shutdown)?;
+
+        let suggested_extranonce2_size = downstream
+            .downstream_data
+            .super_safe_lock(|d| d.suggested_extranonce2_size);
+        if let Some(suggested) = suggested_extranonce2_size {
+            if extranonce2_len < suggested {
+                let error = TproxyErrorKind::Extranonce2SizeNotGranted(suggested, extranonce2_len);
+                return Err(self
+                    .reject_queued_handshake(downstream, downstream_id, error)
+                    .await);
+            }
+        }
+
+        // Process all queued messages now that channel is established
+        if let Ok(queued_messages) = downstream.downstream_data.safe_lock(|d| {
+            let messages = d.queued_sv1_handshake_messages.clone();
+            d.queued_sv1_handshake_messages.clear();
+            messages
+        }) {
+            if !queued_messages.is_empty() {
+                info!(
+                    "Processing {} queued Sv1 messages for downstream {}",
+                    queued_messages.len(),
+                    downstream_id
+                );
+
+                // Set flag to indicate we're processing queued responses
+                downstream
+                    .processing_queued_sv1_handshake_responses
+                    .store(true, Ordering::SeqCst);
+
+                for message in queued_messages {
+                    if let Ok(Some(response_msg)) =
+                        self.clone().handle_message(Some(downstream_id), message)
+                    {
+                        self.sv1_server_channel_state
+                            .sv1_server_to_downstream_sender
+                            .send((channel_id, Some(downstream_id), response_msg.into()))
+                            .map_err(|_| TproxyError::log(TproxyErrorKind::ChannelErrorSender))?;
+                    }
+                }
+            }
+        }
+
+        // The initial target, unless the vardiff state of the miner was restored
+        // while processing its queued `mining.authorize`
+        let target = downstream.downstream_data.super_safe_lock(|d| d.target);
+        let set_difficulty = self.build_set_difficulty(target).map_err(|_| {
+            TproxyError::shutdown(TproxyErrorKind::General(
+                "Failed to generate set_difficulty".into(),
+            ))
+        })?;
+        // send the set_difficulty message to the downstream
+        self.sv1_server_channel_state
+            .sv1_server_to_downstream_sender
+            .send((channel_id, None, set_difficulty))
+            .map_err(|_| TproxyError::log(TproxyErrorKind::ChannelErrorSender))?;
+        Ok(())
+    }
+
     /// Handles messages received from the upstream SV2 server via the channel manager.
     ///
     /// This method processes various SV2 messages including:
     /// - OpenExtendedMiningChannelSuccess: Sets up downstream connections
     /// - NewExtendedMiningJob: Converts to SV1 notify messages
     /// - SetNewPrevHash: Updates block template information
+    /// - SetExtranoncePrefix: Sends the new extranonce1 to the miner with `mining.set_extranonce`
     /// - Channel error messages (TODO: implement proper handling)
     ///
     /// # Arguments
-    /// * `first_target` - Initial difficulty target for new connections
     /// * `notify_shutdown` - Broadcast channel for shutdown coordination
     /// * `shutdown_complete_tx` - Channel to signal shutdown completion
     /// * `status_sender` - Channel for sending status updates
@@ -541,10 +1220,7 @@ impl Sv1Server {
     /// # Returns
     /// * `Ok(())` - Message processed successfully
     /// * `Err(TproxyError)` - Error processing the message
-    pub async fn handle_upstream_message(
-        &self,
-        first_target: Target,
-    ) -> TproxyResult<(), error::Sv1Server> {
+    pub async fn handle_upstream_message(&self) -> TproxyResult<(), error::Sv1Server> {
         let (message, _tlv_fields) = self
             .sv1_server_channel_state
             .channel_manager_receiver
@@ -558,9 +1234,29 @@ impl Sv1Server {
                     "Received OpenExtendedMiningChannelSuccess for channel id: {}",
                     m.channel_id
                 );
-                let downstream_id = self.request_id_to_downstream_id.remove(&m.request_id);
+                // A channel opened after the timeout still goes to the downstream it was requested
+                // for, if that one is waiting for a channel
+                let downstream_id = self
+                    .request_id_to_downstream_id
+                    .remove(&m.request_id)
+                    .map(|(_, (downstream_id, _))| downstream_id)
+                    .or_else(|| {
+                        let (_, downstream_id) =
+                            self.late_open_channel_requests.remove(&m.request_id)?;
+                        warn!(
+                            "Channel {} for request {} opened after the timeout",
+                            m.channel_id, m.request_id
+                        );
+                        Some(downstream_id)
+                    });
 
-                let Some((_, downstream_id)) = downstream_id else {
+                self.send_queued_open_channel_requests().await?;
+
+                let Some(downstream_id) = downstream_id else {
+                    // No downstream will ever use the channel, unless it is the shared one
+                    if is_non_aggregated() {
+                        self.close_channel(m.channel_id).await;
+                    }
                     return Err(TproxyError::log(TproxyErrorKind::DownstreamNotFound(
                         m.request_id,
                     )));
@@ -573,109 +1269,108 @@ impl Sv1Server {
                         .to_vec()
                         .try_into()
                         .map_err(TproxyError::fallback)?;
-                    downstream
-                        .downstream_data
-                        .safe_lock(|d| {
-                            d.extranonce1 = extranonce1;
-                            d.extranonce2_len = m.extranonce_size.into();
-                            d.channel_id = Some(m.channel_id);
-                            // Set the initial upstream target from OpenExtendedMiningChannelSuccess
-                            d.set_upstream_target(initial_target, downstream_id);
-                        })
-                        .map_err(TproxyError::shutdown)?;
-
-                    // Process all queued messages now that channel is established
-                    if let Ok(queued_messages) = downstream.downstream_data.safe_lock(|d| {
-                        let messages = d.queued_sv1_handshake_messages.clone();
-                        d.queued_sv1_handshake_messages.clear();
-                        messages
-                    }) {
-                        if !queued_messages.is_empty() {
-                            info!(
-                                "Processing {} queued Sv1 messages for downstream {}",
-                                queued_messages.len(),
-                                downstream_id
-                            );
-
-                            // Set flag to indicate we're processing queued responses
-                            downstream
-                                .processing_queued_sv1_handshake_responses
-                                .store(true, Ordering::SeqCst);
-
-                            for message in queued_messages {
-                                if let Ok(Some(response_msg)) =
-                                    self.clone().handle_message(Some(downstream_id), message)
-                                {
-                                    self.sv1_server_channel_state
-                                        .sv1_server_to_downstream_sender
-                                        .send((
-                                            m.channel_id,
-                                            Some(downstream_id),
-                                            response_msg.into(),
-                                        ))
-                                        .map_err(|_| {
-                                            TproxyError::shutdown(
-                                                TproxyErrorKind::ChannelErrorSender,
-                                            )
-                                        })?;
-                                }
-                            }
+                    let (has_channel, rerouted, rotated) =
+                        downstream.downstream_data.super_safe_lock(|d| {
+                            (
+                                d.channel_id.is_some(),
+                                std::mem::take(&mut d.reopening_channel),
+                                std::mem::take(&mut d.rotating_upstream),
+                            )
+                        });
+                    let result = if has_channel && (rerouted || rotated) {
+                        self.move_to_channel(
+                            &downstream,
+                            downstream_id,
+                            m.channel_id,
+                            extranonce1,
+                            m.extranonce_size.into(),
+                            initial_target,
+                            rotated,
+                        )
+                        .await
+                    } else if has_channel {
+                        // The downstream resumed a retained session, or got the channel of an
+                        // earlier request, while this channel was opening
+                        info!(
+                            "Downstream {} already has a channel, releasing channel {} opened for it",
+                            downstream_id, m.channel_id
+                        );
+                        self.close_channel(m.channel_id).await;
+                        Ok(())
+                    } else {
+                        match self
+                            .attach_channel(
+                                &downstream,
+                                downstream_id,
+                                m.channel_id,
+                                extranonce1,
+                                m.extranonce_size.into(),
+                                Some(initial_target),
+                            )
+                            .await
+                        {
+                            // A `mining.authorize` queued while the channel was opening was just
+                            // handled
+                            Ok(()) => self.reroute_worker(&downstream).await,
+                            Err(e) => Err(e),
                         }
+                    };
+                    // In aggregated mode, the downstreams still waiting after a rotation share the
+                    // aggregated channel that just opened
+                    if rotated {
+                        self.reopen_rotated_channels().await?;
                     }
-
-                    let set_difficulty = build_sv1_set_difficulty_from_sv2_target(first_target)
-                        .map_err(|_| {
-                            TproxyError::shutdown(TproxyErrorKind::General(
-                                "Failed to generate set_difficulty".into(),
-                            ))
-                        })?;
-                    // send the set_difficulty message to the downstream
-                    self.sv1_server_channel_state
-                        .sv1_server_to_downstream_sender
-                        .send((m.channel_id, None, set_difficulty))
-                        .map_err(|_| TproxyError::shutdown(TproxyErrorKind::ChannelErrorSender))?;
+                    return result;
                 } else {
-                    error!("Downstream not found for downstream_id: {}", downstream_id);
+                    error!(
+                        "Downstream {} disconnected while channel {} was opening, closing it",
+                        downstream_id, m.channel_id
+                    );
+                    self.close_channel(m.channel_id).await;
                 }
             }
 
             Mining::NewExtendedMiningJob(m) => {
+                let received_at = Instant::now();
                 debug!(
                     "Received NewExtendedMiningJob for channel id: {}",
                     m.channel_id
                 );
-                if let Some(prevhash) = self.prevhashes.get(&m.channel_id) {
-                    let prevhash = prevhash.as_static();
-                    let clean_jobs = m.job_id == prevhash.job_id;
-                    let notify =
-                        build_sv1_notify_from_sv2(prevhash, m.clone().into_static(), clean_jobs)
-                            .map_err(TproxyError::shutdown)?;
-
-                    // Update job storage based on the configured mode
-                    let notify_parsed = notify.clone();
-                    let job_channel_id = if is_non_aggregated() {
-                        m.channel_id
-                    } else {
-                        AGGREGATED_CHANNEL_ID
-                    };
-
-                    let mut channel_jobs = self.valid_sv1_jobs.entry(job_channel_id).or_default();
-                    if clean_jobs {
-                        channel_jobs.clear();
+                let prevhash = self
+                    .prevhashes
+                    .get(&m.channel_id)
+                    .map(|prevhash| prevhash.as_static());
+                match prevhash {
+                    Some(prevhash) => self.notify_job(prevhash, m.into_static(), received_at)?,
+                    // The prevhash of the channel may still be on its way
+                    None => {
+                        debug!(
+                            "No SetNewPrevHash yet for channel id: {}, holding its job",
+                            m.channel_id
+                        );
+                        self.pending_jobs.push(m.into_static(), received_at);
                     }
-                    channel_jobs.push(notify_parsed);
-
-                    let _ = self
-                        .sv1_server_channel_state
-                        .sv1_server_to_downstream_sender
-                        .send((m.channel_id, None, notify.into()));
                 }
             }
 
             Mining::SetNewPrevHash(m) => {
                 debug!("Received SetNewPrevHash for channel id: {}", m.channel_id);
+                // A SetNewPrevHash may still be in flight when its channel closes
+                if !self.is_channel_in_use(m.channel_id) {
+                    debug!(
+                        "Ignoring SetNewPrevHash for closed channel id: {}",
+                        m.channel_id
+                    );
+                    self.pending_jobs.remove_channel(m.channel_id);
+                    return Ok(());
+                }
+                let prevhash = m.into_static();
                 self.prevhashes
-                    .insert(m.channel_id, m.clone().into_static());
+                    .insert(prevhash.channel_id, prevhash.clone());
+                self.enforce_prevhashes_cap();
+                for (job, received_at) in self.pending_jobs.take(prevhash.channel_id) {
+                    self.notify_job(prevhash.clone(), job, received_at)?;
+                }
             }
 
             Mining::SetTarget(m) => {
@@ -689,6 +1384,86 @@ impl Sv1Server {
                     self.handle_set_target_without_vardiff(m).await?;
                 }
             }
+            Mining::SetExtranoncePrefix(m) => {
+                debug!(
+                    "Received SetExtranoncePrefix for channel id: {}",
+                    m.channel_id
+                );
+                let extranonce1 = m
+                    .extranonce_prefix
+                    .to_vec()
+                    .try_into()
+                    .map_err(TproxyError::fallback)?;
+                self.change_extranonce1(m.channel_id, extranonce1).await?;
+            }
+            Mining::OpenMiningChannelError(m) => {
+                let routed = self.upstream_router.take_request(m.request_id);
+                // The downstream was already handled when the request timed out
+                if self
+                    .late_open_channel_requests
+                    .remove(&m.request_id)
+                    .is_some()
+                {
+                    debug!(
+                        "Upstream refused channel request {} after the timeout",
+                        m.request_id
+                    );
+                    return Ok(());
+                }
+                let Some((_, (downstream_id, _))) =
+                    self.request_id_to_downstream_id.remove(&m.request_id)
+                else {
+                    return Err(TproxyError::log(TproxyErrorKind::DownstreamNotFound(
+                        m.request_id,
+                    )));
+                };
+                self.send_queued_open_channel_requests().await?;
+                warn!(
+                    "Upstream refused the channel of downstream {}: {}",
+                    downstream_id,
+                    m.error_code.as_utf8_or_hex()
+                );
+                // Giving up on a channel the primary upstream refused falls back to the next
+                // upstream, a routed upstream only concerning the workers routed to it
+                self.handle_open_channel_failure(downstream_id, !routed)
+                    .await?;
+            }
+            Mining::CloseChannel(m) => {
+                debug!("Received CloseChannel for channel id: {}", m.channel_id);
+                self.prevhashes.remove(&m.channel_id);
+                self.pending_jobs.remove_channel(m.channel_id);
+                self.valid_sv1_jobs.remove(&m.channel_id);
+                self.keepalive_capped_jobs.remove(&m.channel_id);
+                self.release_retained_channel(m.channel_id);
+
+                // Detach the channel from its downstream before disconnecting it, so the
+                // disconnection does not send a CloseChannel back for an already closed channel.
+                let downstream_id = self.downstreams.iter().find_map(|downstream| {
+                    downstream.downstream_data.super_safe_lock(|d| {
+                        (d.channel_id == Some(m.channel_id)).then(|| {
+                            d.channel_id = None;
+                            *downstream.key()
+                        })
+                    })
+                });
+
+                match downstream_id {
+                    Some(downstream_id) => {
+                        info!(
+                            "Channel {} closed by upstream, disconnecting downstream {}",
+                            m.channel_id, downstream_id
+                        );
+                        return Err(TproxyError::disconnect(
+                            TproxyErrorKind::ChannelClosedByUpstream(m.channel_id),
+                            downstream_id,
+                        ));
+                    }
+                    None => warn!(
+                        "No downstream found for channel {} closed by upstream",
+                        m.channel_id
+                    ),
+                }
+            }
             // Guaranteed unreachable: the channel manager only forwards valid,
             // pre-filtered messages, so no other variants can arrive here.
             _ => unreachable!("Invalid message: should have been filtered earlier"),
@@ -697,598 +1472,3697 @@ impl Sv1Server {
         Ok(())
     }
 
-    /// Opens an extended mining channel for a downstream connection.
-    ///
-    /// This method initiates the SV2 channel setup process by:
-    /// - Calculating the initial target based on configuration
-    /// - Generating a unique user identity for the miner
-    /// - Creating an OpenExtendedMiningChannel message
-    /// - Sending the request to the channel manager
-    ///
-    /// # Arguments
-    /// * `downstream` - The downstream connection to set up a channel for
-    ///
-    /// # Returns
-    /// * `Ok(())` - Channel setup request sent successfully
-    /// * `Err(TproxyError)` - Error setting up the channel
-    pub async fn open_extended_mining_channel(
+    // Builds the `mining.notify` of `m` on top of `prevhash`, stores it as a valid job and sends
+    // it to the downstreams of its channel.
+    fn notify_job(
         &self,
-        request_id: RequestId,
-        downstream_id: DownstreamId,
+        prevhash: SetNewPrevHash<'static>,
+        m: NewExtendedMiningJob<'static>,
+        received_at: Instant,
     ) -> TproxyResult<(), error::Sv1Server> {
-        let config = &self.config.downstream_difficulty_config;
-        let downstream = self.downstreams.get(&downstream_id).unwrap();
+        let clean_jobs = m.job_id == prevhash.job_id;
+        let notify = build_sv1_notify_from_sv2(prevhash, m.clone(), clean_jobs)
+            .map_err(TproxyError::shutdown)?;
 
-        let hashrate = config.min_individual_miner_hashrate as f64;
-        let shares_per_min = config.shares_per_minute as f64;
-        let min_extranonce_size = self.config.downstream_extranonce2_size;
-        let vardiff_enabled = config.enable_vardiff;
+        // Update job storage based on the configured mode
+        let notify_parsed = notify.clone();
+        let job_channel_id = self
+            .job_channel_id(Some(m.channel_id))
+            .unwrap_or(m.channel_id);
 
-        let max_target = if vardiff_enabled {
-            hash_rate_to_target(hashrate, shares_per_min).unwrap()
-        } else {
-            // If translator doesn't manage vardiff, we rely on upstream to do that,
-            // so we give it more freedom by setting max_target to maximum possible value
-            Target::from_le_bytes([0xff; 32])
-        };
+        let mut channel_jobs = self.valid_sv1_jobs.entry(job_channel_id).or_default();
+        if clean_jobs {
+            channel_jobs.clear();
+        }
+        self.push_valid_job(&mut channel_jobs, notify_parsed);
 
-        let miner_id = self.miner_counter.fetch_add(1, Ordering::SeqCst) + 1;
-        let user_identity = format!("{}.miner{}", self.config.user_identity, miner_id);
+        // Downstreams with a completed handshake get the notify right away, the others
+        // cache it until the handshake completes
+        let downstreams: HashSet<DownstreamId> = self
+            .downstreams
+            .iter()
+            .filter(|downstream| {
+                downstream.sv1_handshake_complete.load(Ordering::SeqCst)
+                    && downstream.downstream_data.super_safe_lock(|d| {
+                        (d.aggregated_channel_id == m.channel_id
+                            || d.channel_id == Some(m.channel_id))
+                            && !d.rotating_upstream
+                    })
+            })
+            .map(|downstream| *downstream.key())
+            .collect();
+        self.job_propagation.job_received(
+            m.channel_id,
+            notify.job_id.clone(),
+            received_at,
+            downstreams,
+        );
 
-        downstream
+        let _ = self
+            .sv1_server_channel_state
+            .sv1_server_to_downstream_sender
+            .send((m.channel_id, None, notify.into()));
+        Ok(())
+    }
+
+    /// Removes a disconnected downstream and the state kept for its channel.
+    ///
+    /// In non-aggregated mode, the downstream's channel is closed upstream as well, unless it is
+    /// kept open to resume the session of its miner.
+    pub async fn remove_downstream(&self, downstream_id: DownstreamId) {
+        let vardiff = if self.config.downstream_difficulty_config.enable_vardiff {
+            // Only remove from vardiff map if vardiff is enabled
+            self.vardiff.remove(&downstream_id)
+        } else {
+            None
+        };
+        self.connection_permits.remove(&downstream_id);
+        // A channel still opening for the downstream is closed once it opens, as the request is
+        // no longer attributed to any downstream
+        self.request_id_to_downstream_id
+            .retain(|_, (id, _)| *id != downstream_id);
+        self.late_open_channel_requests
+            .retain(|_, id| *id != downstream_id);
+        self.queued_open_channel_requests
+            .super_safe_lock(|queue| queue.retain(|(id, _)| *id != downstream_id));
+        let Some((downstream_id, downstream)) = self.downstreams.remove(&downstream_id) else {
+            return;
+        };
+        if let (Some(vardiff_retention), Some((_, vardiff))) = (&self.vardiff_retention, vardiff) {
+            self.retain_vardiff(vardiff_retention, &downstream, vardiff);
+        }
+        info!(
+            "🔌 Downstream: {downstream_id} disconnected and removed from sv1 server downstreams"
+        );
+        self.job_propagation.downstream_removed(downstream_id);
+        // The channel of a downstream still waiting for one after a rotation was closed along with
+        // the upstream left, while another downstream may be needed to open the aggregated channel
+        if downstream
             .downstream_data
-            .safe_lock(|d| d.user_identity = user_identity.clone())
-            .map_err(TproxyError::shutdown)?;
+            .super_safe_lock(|d| d.rotating_upstream)
+        {
+            if let Err(e) = self.reopen_rotated_channels().await {
+                warn!("Failed to reopen the channels left by a rotation: {:?}", e);
+            }
+            return;
+        }
+        // In aggregated mode, send UpdateChannel so the aggregated hashrate reflects the remaining
+        // downstreams
+        let aggregated_channel_id = downstream
+            .downstream_data
+            .super_safe_lock(|d| d.aggregated_channel_id);
+        self.send_update_channel_on_downstream_state_change(aggregated_channel_id)
+            .await;
 
-        if let Ok(open_channel_msg) = build_sv2_open_extended_mining_channel(
-            request_id,
-            user_identity.clone(),
-            hashrate as Hashrate,
-            max_target,
-            min_extranonce_size,
-        ) {
-            self.sv1_server_channel_state
-                .channel_manager_sender
-                .send((Mining::OpenExtendedMiningChannel(open_channel_msg), None))
-                .await
-                .map_err(|_| TproxyError::shutdown(TproxyErrorKind::ChannelErrorSender))?;
+        if let Some(session_resumption) = &self.session_resumption {
+            if self.retain_session(session_resumption, &downstream) {
+                return;
+            }
+        }
+        if let Some(channel_affinity) = &self.channel_affinity {
+            if self.retain_channel_affinity(channel_affinity, &downstream) {
+                return;
+            }
+        }
+        let channel_id = downstream.downstream_data.super_safe_lock(|d| d.channel_id);
+        if let Some(channel_id) = channel_id {
+            info!("Closing channel {channel_id} of downstream {downstream_id}");
+            self.close_channel(channel_id).await;
+        }
+    }
+
+    // Drops the state kept for a channel no downstream uses anymore.
+    //
+    // In non-aggregated mode, the channel is closed upstream as well. In aggregated mode, it no
+    // longer counts towards the downstreams sharing its aggregated channel.
+    async fn close_channel(&self, channel_id: ChannelId) {
+        self.prevhashes.remove(&channel_id);
+        self.pending_jobs.remove_channel(channel_id);
+        if is_aggregated() {
+            self.aggregated_channel_ids.remove(&channel_id);
         } else {
-            error!("Failed to build OpenExtendedMiningChannel message");
+            self.valid_sv1_jobs.remove(&channel_id);
+            self.keepalive_capped_jobs.remove(&channel_id);
+            info!("Sending CloseChannel message: {channel_id}");
+            let reason_code = Str0255::try_from("downstream disconnected".to_string()).unwrap();
+            _ = self
+                .sv1_server_channel_state
+                .channel_manager_sender
+                .send((
+                    Mining::CloseChannel(CloseChannel {
+                        channel_id,
+                        reason_code,
+                    }),
+                    None,
+                ))
+                .await;
         }
+    }
 
-        Ok(())
+    // Returns whether the aggregated channel stayed open for `idle` without any downstream
+    // connected, tracking since when none is.
+    fn aggregated_channel_idle(&self, idle: Duration) -> bool {
+        self.idle_since.super_safe_lock(|idle_since| {
+            if !self.downstreams.is_empty()
+                || !self.valid_sv1_jobs.contains_key(&AGGREGATED_CHANNEL_ID)
+            {
+                *idle_since = None;
+                return false;
+            }
+            idle_since.get_or_insert_with(Instant::now).elapsed() >= idle
+        })
     }
 
-    /// Retrieves a downstream connection by ID from the provided map.
-    ///
-    /// # Arguments
-    /// * `downstream_id` - The ID of the downstream connection to find
-    /// * `downstream` - HashMap containing downstream connections
-    ///
-    /// # Returns
-    /// * `Some(Downstream)` - If a downstream with the given ID exists
-    /// * `None` - If no downstream with the given ID is found
-    pub fn get_downstream(
-        downstream_id: DownstreamId,
-        downstream: HashMap<DownstreamId, Downstream>,
-    ) -> Option<Downstream> {
-        downstream.get(&downstream_id).cloned()
+    // Closes the aggregated channel upstream while no downstream is connected. The next
+    // downstream connecting opens a new one.
+    async fn close_idle_aggregated_channel(&self) {
+        info!("No downstream connected, closing the aggregated channel");
+        self.idle_since
+            .super_safe_lock(|idle_since| *idle_since = None);
+        // The retained sessions were on the closed channel
+        if let Some(session_resumption) = &self.session_resumption {
+            session_resumption.clear();
+        }
+        if let Some(channel_affinity) = &self.channel_affinity {
+            channel_affinity.clear();
+        }
+        let aggregated_channel_ids: HashSet<ChannelId> = self
+            .valid_sv1_jobs
+            .iter()
+            .map(|jobs| *jobs.key())
+            .chain(self.prevhashes.iter().map(|prevhash| *prevhash.key()))
+            .filter(|channel_id| is_aggregated_channel_id(*channel_id))
+            .collect();
+        for aggregated_channel_id in aggregated_channel_ids {
+            self.prevhashes.remove(&aggregated_channel_id);
+            self.pending_jobs.remove_channel(aggregated_channel_id);
+            self.valid_sv1_jobs.remove(&aggregated_channel_id);
+            self.keepalive_capped_jobs.remove(&aggregated_channel_id);
+        }
+        // Closing AGGREGATED_CHANNEL_ID closes every aggregated channel
+        let reason_code = Str0255::try_from("no downstream connected".to_string()).unwrap();
+        _ = self
+            .sv1_server_channel_state
+            .channel_manager_sender
+            .send((
+                Mining::CloseChannel(CloseChannel {
+                    channel_id: AGGREGATED_CHANNEL_ID,
+                    reason_code,
+                }),
+                None,
+            ))
+            .await;
     }
 
-    /// Handles SetTarget messages when vardiff is disabled.
-    ///
-    /// This method forwards difficulty changes from upstream directly to downstream miners
-    /// without any variable difficulty logic. It respects the aggregated/non-aggregated
-    /// channel configuration.
-    async fn handle_set_target_without_vardiff(
+    // Moves the downstream of `channel_id` onto `extranonce1`, once the upstream changed the
+    // extranonce prefix of the channel.
+    //
+    // A miner subscribed to `mining.set_extranonce` gets the new extranonce1, then the last job
+    // again under a new job id with `clean_jobs` set. The shares of the jobs notified before are
+    // rejected as stale, since they were mined on the old extranonce1. Other miners would keep
+    // mining on the old extranonce1, so they are disconnected.
+    async fn change_extranonce1(
         &self,
-        set_target: SetTarget<'_>,
+        channel_id: ChannelId,
+        extranonce1: Extranonce<'static>,
     ) -> TproxyResult<(), error::Sv1Server> {
-        let new_target =
-            Target::from_le_bytes(set_target.maximum_target.inner_as_ref().try_into().unwrap());
-        debug!(
-            "Forwarding SetTarget to downstreams: channel_id={}, target={:?}",
-            set_target.channel_id, new_target
+        // A retained channel would hand the old extranonce1 out to its miner
+        if self.is_channel_retained(channel_id) {
+            info!(
+                "Releasing the retained channel {} after its extranonce changed",
+                channel_id
+            );
+            self.release_retained_channel(channel_id);
+            self.close_channel(channel_id).await;
+            return Ok(());
+        }
+        let downstream = self.downstreams.iter().find_map(|downstream| {
+            (downstream.downstream_data.super_safe_lock(|d| d.channel_id) == Some(channel_id))
+                .then(|| downstream.clone())
+        });
+        let Some(downstream) = downstream else {
+            warn!(
+                "No downstream found for channel {} whose extranonce changed",
+                channel_id
+            );
+            return Ok(());
+        };
+        let downstream_id = downstream.downstream_id;
+
+        let job_channel_id = self.job_channel_id(Some(channel_id)).unwrap_or(channel_id);
+        let notified_job_ids: Vec<String> = self
+            .valid_sv1_jobs
+            .get(&job_channel_id)
+            .map(|jobs| jobs.iter().map(|job| job.job_id.clone()).collect())
+            .unwrap_or_default();
+        let (extranonce_subscribed, extranonce2_len) =
+            downstream.downstream_data.super_safe_lock(|d| {
+                d.extranonce1 = extranonce1.clone();
+                d.stale_job_ids.extend(notified_job_ids);
+                (d.extranonce_subscribed, d.extranonce2_len)
+            });
+        // Notifications only reach miners that completed their handshake
+        if !extranonce_subscribed || !downstream.sv1_handshake_complete.load(Ordering::SeqCst) {
+            warn!(
+                "Down: Downstream {} cannot be told about the new extranonce of channel {}",
+                downstream_id, channel_id
+            );
+            return Err(TproxyError::disconnect(
+                TproxyErrorKind::ExtranonceChangeNotSupported,
+                downstream_id,
+            ));
+        }
+
+        info!(
+            "Sending mining.set_extranonce to downstream {} for channel {}",
+            downstream_id, channel_id
         );
+        let set_extranonce = server_to_client::SetExtranonce {
+            extra_nonce1: extranonce1,
+            extra_nonce2_size: extranonce2_len,
+        };
+        let _ = self
+            .sv1_server_channel_state
+            .sv1_server_to_downstream_sender
+            .send((channel_id, Some(downstream_id), set_extranonce.into()));
+
+        // The jobs notified so far are stale for the miner, it mines again on the last one
+        if let Some(mut notify) = self.get_last_job(Some(channel_id)) {
+            let original_job_id = Self::extract_original_job_id(&notify.job_id)
+                .unwrap_or_else(|| notify.job_id.clone());
+            notify.job_id = self.next_keepalive_job_id(&original_job_id);
+            notify.clean_jobs = true;
+            if let Some(mut jobs) = self.valid_sv1_jobs.get_mut(&job_channel_id) {
+                self.push_valid_job(&mut jobs, notify.clone());
+            }
+            let _ = self
+                .sv1_server_channel_state
+                .sv1_server_to_downstream_sender
+                .send((channel_id, Some(downstream_id), notify.into()));
+        }
+        Ok(())
+    }
 
-        if is_aggregated() {
-            // Aggregated mode: send set_difficulty to ALL downstreams
-            return self
-                .send_set_difficulty_to_all_downstreams(new_target)
-                .await;
+    // Reopens the channel of `downstream` on the upstream the routing rules send its first
+    // authorized worker to, if it was opened on another one.
+    //
+    // The channel is opened before the worker name is known, on the primary upstream. A miner
+    // subscribed to `mining.set_extranonce` is moved onto the new channel once it opens. Any other
+    // miner cannot change its extranonce1, so it is disconnected, and its next connection from the
+    // same address opens its channel on the upstream of its worker right away.
+    async fn reroute_worker(&self, downstream: &Downstream) -> TproxyResult<(), error::Sv1Server> {
+        if self.config.upstream_routes().is_empty() {
+            return Ok(());
+        }
+        let downstream_id = downstream.downstream_id;
+        let (worker_name, channel_id, extranonce_subscribed, peer_ip) =
+            downstream.downstream_data.super_safe_lock(|d| {
+                (
+                    d.authorized_worker_name.clone(),
+                    d.channel_id,
+                    d.extranonce_subscribed,
+                    d.peer_ip,
+                )
+            });
+        if worker_name.is_empty() {
+            return Ok(());
+        }
+        let (Some(channel_id), Some(upstream)) =
+            (channel_id, self.config.routed_upstream_index(&worker_name))
+        else {
+            return Ok(());
+        };
+        // A channel routed to an upstream that is not connected would be opened on the primary one
+        if self
+            .upstream_router
+            .is_channel_on_upstream(channel_id, upstream)
+            || !self.upstream_router.is_upstream_connected(upstream)
+        {
+            return Ok(());
         }
 
-        // Non-aggregated mode: send set_difficulty to specific downstream for this channel
-        self.send_set_difficulty_to_specific_downstream(set_target.channel_id, new_target)
+        // `mining.set_extranonce` is only forwarded once the Sv1 handshake is complete
+        if !extranonce_subscribed || !downstream.sv1_handshake_complete.load(Ordering::SeqCst) {
+            info!(
+                "Worker {} of downstream {} is routed to upstream {}, disconnecting it to \
+                 reconnect there",
+                worker_name, downstream_id, upstream
+            );
+            if let Some(peer_ip) = peer_ip {
+                self.rerouted_workers.insert(peer_ip, worker_name);
+            }
+            return Err(TproxyError::disconnect(
+                TproxyErrorKind::ExtranonceChangeNotSupported,
+                downstream_id,
+            ));
+        }
+        info!(
+            "Worker {} of downstream {} is routed to upstream {}, reopening its channel there",
+            worker_name, downstream_id, upstream
+        );
+        downstream
+            .downstream_data
+            .super_safe_lock(|d| d.reopening_channel = true);
+        self.handle_open_channel_request(downstream_id, Some(&worker_name))
             .await
     }
 
-    /// Sends set_difficulty to all downstreams (aggregated mode).
-    /// Used only when vardiff is disabled.
-    async fn send_set_difficulty_to_all_downstreams(
+    // Moves `downstream` onto the channel `channel_id` reopened for it on the upstream of its
+    // worker, and closes the channel it leaves. When `rotated`, the channel was opened on the
+    // upstream the translator rotated to, and the one it leaves was closed with the upstream left.
+    //
+    // The miner gets the extranonce1 of the new channel with `mining.set_extranonce`, unless it is
+    // the one it has, and its difficulty again, then the jobs the upstream sends on the new
+    // channel. The shares of the jobs notified before are rejected as stale. A miner that cannot
+    // be sent the new extranonce1 is disconnected.
+    #[allow(clippy::too_many_arguments)]
+    async fn move_to_channel(
         &self,
-        target: Target,
+        downstream: &Downstream,
+        downstream_id: DownstreamId,
+        channel_id: ChannelId,
+        extranonce1: Extranonce<'static>,
+        extranonce2_len: usize,
+        upstream_target: Target,
+        rotated: bool,
     ) -> TproxyResult<(), error::Sv1Server> {
-        for downstream in self.downstreams.iter() {
-            let downstream_id = downstream.key();
-            let downstream = downstream.value();
-            let channel_id = downstream.downstream_data.super_safe_lock(|d| {
-                let channel_id = d.channel_id?;
-
-                d.set_upstream_target(target, *downstream_id);
-                d.set_pending_target(target, *downstream_id);
+        let suggested_extranonce2_size = downstream
+            .downstream_data
+            .super_safe_lock(|d| d.suggested_extranonce2_size);
+        if let Some(suggested) =
+            suggested_extranonce2_size.filter(|suggested| extranonce2_len < *suggested)
+        {
+            // The channel it has is gone with the upstream left
+            if rotated {
+                downstream
+                    .downstream_data
+                    .super_safe_lock(|d| d.channel_id = Some(channel_id));
+                let error = TproxyErrorKind::Extranonce2SizeNotGranted(suggested, extranonce2_len);
+                return Err(TproxyError::disconnect(error, downstream_id));
+            }
+            warn!(
+                "Channel {} reopened for downstream {} has a too small extranonce2, keeping its \
+                 current channel",
+                channel_id, downstream_id
+            );
+            self.close_channel(channel_id).await;
+            return Ok(());
+        }
 
-                Some(channel_id)
+        let left_channel_id = downstream
+            .downstream_data
+            .super_safe_lock(|d| d.channel_id)
+            .unwrap_or(channel_id);
+        let notified_job_ids: Vec<String> = self
+            .valid_sv1_jobs
+            .get(&left_channel_id)
+            .map(|jobs| jobs.iter().map(|job| job.job_id.clone()).collect())
+            .unwrap_or_default();
+        let aggregated_channel_id = self.aggregated_channel_of(channel_id);
+        let (target, extranonce_changed, extranonce_subscribed) =
+            downstream.downstream_data.super_safe_lock(|d| {
+                let extranonce_changed =
+                    d.extranonce1 != extranonce1 || d.extranonce2_len != extranonce2_len;
+                d.channel_id = Some(channel_id);
+                d.aggregated_channel_id = aggregated_channel_id;
+                d.extranonce1 = extranonce1.clone();
+                d.extranonce2_len = extranonce2_len;
+                d.set_upstream_target(upstream_target, downstream_id);
+                d.stale_job_ids.extend(notified_job_ids);
+                (d.target, extranonce_changed, d.extranonce_subscribed)
             });
+        info!(
+            "Moving downstream {} from channel {} to channel {}",
+            downstream_id, left_channel_id, channel_id
+        );
 
-            let Some(channel_id) = channel_id else {
-                trace!(
-                    "Skipping downstream {}: no channel_id set (vardiff disabled)",
-                    downstream_id
+        if extranonce_changed {
+            // `mining.set_extranonce` is only forwarded once the Sv1 handshake is complete
+            if !extranonce_subscribed || !downstream.sv1_handshake_complete.load(Ordering::SeqCst) {
+                warn!(
+                    "Down: Downstream {} cannot be told about the extranonce of channel {}",
+                    downstream_id, channel_id
                 );
-                continue;
+                if !rotated {
+                    self.close_channel(left_channel_id).await;
+                }
+                return Err(TproxyError::disconnect(
+                    TproxyErrorKind::ExtranonceChangeNotSupported,
+                    downstream_id,
+                ));
+            }
+            let set_extranonce = server_to_client::SetExtranonce {
+                extra_nonce1: extranonce1,
+                extra_nonce2_size: extranonce2_len,
             };
-
-            let set_difficulty_msg = match build_sv1_set_difficulty_from_sv2_target(target) {
-                Ok(msg) => msg,
-                Err(e) => {
-                    error!(
-                        "Failed to build SetDifficulty for downstream {}: {:?}",
-                        downstream_id, e
+            let _ = self
+                .sv1_server_channel_state
+                .sv1_server_to_downstream_sender
+                .send((channel_id, Some(downstream_id), set_extranonce.into()));
+        }
+        let set_difficulty = self.build_set_difficulty(target).map_err(|_| {
+            TproxyError::shutdown(TproxyErrorKind::General(
+                "Failed to generate set_difficulty".into(),
+            ))
+        })?;
+        let _ = self
+            .sv1_server_channel_state
+            .sv1_server_to_downstream_sender
+            .send((channel_id, Some(downstream_id), set_difficulty));
+
+        if !rotated {
+            self.close_channel(left_channel_id).await;
+        }
+        Ok(())
+    }
+
+    // Drops the state kept for the channels of the upstream the translator rotates away from,
+    // keeping the downstreams connected.
+    //
+    // The downstreams that had a channel, or waited for one, get one on the next upstream with
+    // `reopen_rotated_channels` once it is connected. The jobs notified so far are no longer
+    // valid, so the shares mined on them are rejected as stale meanwhile.
+    fn leave_rotated_upstream(&self) {
+        if let Some(session_resumption) = &self.session_resumption {
+            session_resumption.clear();
+        }
+        if let Some(channel_affinity) = &self.channel_affinity {
+            channel_affinity.clear();
+        }
+        self.request_id_to_downstream_id.clear();
+        self.queued_open_channel_requests
+            .super_safe_lock(|queue| queue.clear());
+        self.late_open_channel_requests.clear();
+        self.prevhashes.clear();
+        self.pending_jobs.clear();
+        self.keepalive_capped_jobs.clear();
+        self.valid_sv1_jobs.clear();
+        for downstream in self.downstreams.iter() {
+            downstream.downstream_data.super_safe_lock(|d| {
+                d.rotating_upstream =
+                    d.channel_id.is_some() || !d.queued_sv1_handshake_messages.is_empty();
+                d.reopening_channel = false;
+                d.failed_open_channel_attempts = 0;
+                d.stale_job_ids.clear();
+                d.submitted_shares.clear();
+            });
+        }
+    }
+
+    /// Requests a channel on the upstream the translator rotated to for the downstreams that
+    /// had one, or waited for one, on the upstream it left.
+    ///
+    /// The downstreams stay connected and move onto their new channel once it opens. In
+    /// aggregated mode, a single channel is requested until one is open on the new upstream, so
+    /// the other downstreams share its aggregated channel instead of opening their own.
+    pub async fn reopen_rotated_channels(&self) -> TproxyResult<(), error::Sv1Server> {
+        let mut requested: HashSet<DownstreamId> = self
+            .request_id_to_downstream_id
+            .iter()
+            .map(|request| request.value().0)
+            .collect();
+        self.queued_open_channel_requests
+            .super_safe_lock(|queue| requested.extend(queue.iter().map(|(id, _)| *id)));
+        let mut channel_open = false;
+        let mut rotated = Vec::new();
+        for downstream in self.downstreams.iter() {
+            downstream.downstream_data.super_safe_lock(|d| {
+                if !d.rotating_upstream {
+                    channel_open |= d.channel_id.is_some();
+                    return;
+                }
+                let worker_name = match d.channel_id {
+                    Some(_) => Some(d.authorized_worker_name.clone()).filter(|n| !n.is_empty()),
+                    None => d
+                        .queued_sv1_handshake_messages
+                        .iter()
+                        .find_map(|message| authorized_worker_name(message).map(str::to_string)),
+                };
+                rotated.push((*downstream.key(), worker_name));
+            });
+        }
+        if is_aggregated() && !channel_open {
+            if !requested.is_empty() {
+                return Ok(());
+            }
+            rotated.truncate(1);
+        }
+        for (downstream_id, worker_name) in rotated {
+            if requested.contains(&downstream_id) {
+                continue;
+            }
+            // The channel goes to the upstream the worker is routed to
+            let worker_name = worker_name.filter(|_| !self.config.upstream_routes().is_empty());
+            self.handle_open_channel_request(downstream_id, worker_name.as_deref())
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Keeps the channel of a downstream that completed its Sv1 handshake open, so its miner can
+    // resume the session if it reconnects within the retention time. Returns whether it was kept.
+    fn retain_session(
+        &self,
+        session_resumption: &SessionResumption,
+        downstream: &Downstream,
+    ) -> bool {
+        if !downstream.sv1_handshake_complete.load(Ordering::SeqCst) {
+            return false;
+        }
+        let session = downstream.downstream_data.super_safe_lock(|d| {
+            Some(RetainedSession::new(
+                d.channel_id?,
+                d.extranonce1.clone(),
+                d.extranonce2_len,
+                d.upstream_target,
+                d.peer_ip?,
+            ))
+        });
+        let Some(session) = session else {
+            return false;
+        };
+        debug!(
+            "Retaining session {} of downstream {} on channel {}",
+            session_id(&session.extranonce1),
+            downstream.downstream_id,
+            session.channel_id
+        );
+        session_resumption.retain(session);
+        true
+    }
+
+    // Keeps the channel of an authorized downstream in aggregated mode, so its worker can rejoin
+    // it if it reconnects within the retention time. Returns whether it was kept.
+    fn retain_channel_affinity(
+        &self,
+        channel_affinity: &ChannelAffinity,
+        downstream: &Downstream,
+    ) -> bool {
+        if !downstream.sv1_handshake_complete.load(Ordering::SeqCst) {
+            return false;
+        }
+        let retained = downstream.downstream_data.super_safe_lock(|d| {
+            if d.authorized_worker_name.is_empty() {
+                return None;
+            }
+            Some((
+                d.authorized_worker_name.clone(),
+                RetainedSession::new(
+                    d.channel_id?,
+                    d.extranonce1.clone(),
+                    d.extranonce2_len,
+                    d.upstream_target,
+                    d.peer_ip?,
+                ),
+            ))
+        });
+        let Some((worker_name, session)) = retained else {
+            return false;
+        };
+        debug!(
+            "Retaining channel {} of worker {} on downstream {}",
+            session.channel_id, worker_name, downstream.downstream_id
+        );
+        channel_affinity.retain(worker_name, session);
+        true
+    }
+
+    // Whether `channel_id` is kept open for a disconnected miner.
+    fn is_channel_retained(&self, channel_id: ChannelId) -> bool {
+        self.session_resumption
+            .as_ref()
+            .is_some_and(|session_resumption| session_resumption.is_retained(channel_id))
+            || self
+                .channel_affinity
+                .as_ref()
+                .is_some_and(|channel_affinity| channel_affinity.is_retained(channel_id))
+    }
+
+    // Stops keeping `channel_id` open for a disconnected miner.
+    fn release_retained_channel(&self, channel_id: ChannelId) {
+        if let Some(session_resumption) = &self.session_resumption {
+            session_resumption.remove_channel(channel_id);
+        }
+        if let Some(channel_affinity) = &self.channel_affinity {
+            channel_affinity.remove_channel(channel_id);
+        }
+    }
+
+    /// Spawns the loop closing the channels of sessions no miner resumed, and of workers that
+    /// did not rejoin their channel, within the retention time.
+    pub async fn spawn_session_expiry_loop(self: Arc<Self>) {
+        if self.session_resumption.is_none() && self.channel_affinity.is_none() {
+            return;
+        }
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            ticker.tick().await;
+            if let Some(session_resumption) = &self.session_resumption {
+                for session in session_resumption.take_expired() {
+                    info!(
+                        "Session {} was not resumed, closing channel {}",
+                        session_id(&session.extranonce1),
+                        session.channel_id
                     );
-                    return Err(TproxyError::shutdown(e));
+                    self.close_channel(session.channel_id).await;
                 }
-            };
+            }
+            if let Some(channel_affinity) = &self.channel_affinity {
+                for session in channel_affinity.take_expired() {
+                    info!(
+                        "Channel {} was not rejoined by its worker, releasing it",
+                        session.channel_id
+                    );
+                    self.close_channel(session.channel_id).await;
+                }
+            }
+        }
+    }
 
-            if let Err(e) = self
-                .sv1_server_channel_state
-                .sv1_server_to_downstream_sender
-                .send((channel_id, Some(*downstream_id), set_difficulty_msg))
-            {
-                error!(
-                    "Failed to send SetDifficulty to downstream {}: {:?}",
+    // Keeps the vardiff state of an authorized downstream, so its difficulty resumes if it
+    // reconnects within the retention time.
+    fn retain_vardiff(
+        &self,
+        vardiff_retention: &VardiffRetention,
+        downstream: &Downstream,
+        vardiff: Arc<Mutex<VardiffState>>,
+    ) {
+        let (worker_name, peer_ip, hashrate, target) =
+            downstream.downstream_data.super_safe_lock(|d| {
+                (
+                    d.authorized_worker_name.clone(),
+                    d.peer_ip,
+                    d.hashrate,
+                    d.target,
+                )
+            });
+        let (Some(peer_ip), Some(hashrate)) = (peer_ip, hashrate) else {
+            return;
+        };
+        if worker_name.is_empty() {
+            return;
+        }
+        debug!(
+            "Retaining vardiff state of worker {} from {} (hashrate {})",
+            worker_name, peer_ip, hashrate
+        );
+        vardiff_retention.retain(worker_name, peer_ip, vardiff, hashrate, target);
+    }
+
+    /// Resumes the difficulty of a miner whose worker reconnected from the same IP address within
+    /// the vardiff retention time.
+    ///
+    /// Called on the first `mining.authorize` of a connection, before the SV1 handshake completes,
+    /// so the cached `mining.set_difficulty` is the one sent to the miner with its first job.
+    pub(super) fn restore_vardiff(&self, downstream_id: DownstreamId, data: &mut DownstreamData) {
+        let (Some(vardiff_retention), Some(peer_ip)) = (&self.vardiff_retention, data.peer_ip)
+        else {
+            return;
+        };
+        let Some(retained) = vardiff_retention.restore(&data.authorized_worker_name, peer_ip)
+        else {
+            return;
+        };
+        let set_difficulty = match self.build_set_difficulty(retained.target) {
+            Ok(set_difficulty) => set_difficulty,
+            Err(e) => {
+                warn!(
+                    "Failed to restore vardiff state of downstream {}: {:?}",
                     downstream_id, e
                 );
-                return Err(TproxyError::shutdown(TproxyErrorKind::ChannelErrorSender));
-            } else {
-                debug!(
-                    "Sent SetDifficulty to downstream {} (vardiff disabled)",
-                    downstream_id
-                );
+                return;
             }
+        };
+        info!(
+            "Restored vardiff state of worker {} on downstream {} (hashrate {})",
+            data.authorized_worker_name, downstream_id, retained.hashrate
+        );
+        // The shares counted before the disconnection were already accounted for
+        if let Err(e) = retained
+            .vardiff
+            .super_safe_lock(|state| state.reset_counter())
+        {
+            warn!(
+                "Failed to reset vardiff counter of downstream {}: {:?}",
+                downstream_id, e
+            );
         }
-        Ok(())
+        self.vardiff.insert(downstream_id, retained.vardiff);
+        data.hashrate = Some(retained.hashrate);
+        data.target = retained.target;
+        data.pending_hashrate = None;
+        data.pending_target = None;
+        data.cached_set_difficulty = Some(set_difficulty);
     }
 
-    /// Sends set_difficulty to the specific downstream associated with a channel (non-aggregated
-    /// mode).
-    /// Used only when vardiff is disabled.
-    async fn send_set_difficulty_to_specific_downstream(
+    // Answers the queued handshake requests of a downstream that cannot get a usable channel with
+    // `error`, and returns the error disconnecting it.
+    async fn reject_queued_handshake(
         &self,
-        channel_id: ChannelId,
-        target: Target,
+        downstream: &Downstream,
+        downstream_id: DownstreamId,
+        error: TproxyErrorKind,
+    ) -> TproxyError<error::Sv1Server> {
+        warn!("Down: Rejecting downstream {}: {}", downstream_id, error);
+        let queued_messages = downstream
+            .downstream_data
+            .super_safe_lock(|d| std::mem::take(&mut d.queued_sv1_handshake_messages));
+        for message in queued_messages {
+            if let json_rpc::Message::StandardRequest(request) = message {
+                let response = json_rpc::Message::ErrorResponse(json_rpc::Response {
+                    id: request.id,
+                    // Other/Unknown, no stratum error code is dedicated to this
+                    error: Some(json_rpc::JsonRpcError {
+                        code: 20,
+                        message: error.to_string(),
+                        data: None,
+                    }),
+                    result: serde_json::Value::Null,
+                });
+                _ = downstream
+                    .downstream_channel_state
+                    .downstream_sv1_sender
+                    .send(response)
+                    .await;
+            }
+        }
+        TproxyError::disconnect(error, downstream_id)
+    }
+
+    // Returns the aggregated channel the downstream channel `channel_id` was opened on,
+    // `channel_id` itself if it is an aggregated channel, and the first one if it is not known.
+    fn aggregated_channel_of(&self, channel_id: ChannelId) -> ChannelId {
+        if is_aggregated_channel_id(channel_id) {
+            return channel_id;
+        }
+        self.aggregated_channel_ids
+            .get(&channel_id)
+            .map_or(AGGREGATED_CHANNEL_ID, |aggregated_channel_id| {
+                *aggregated_channel_id
+            })
+    }
+
+    // Returns the channel the Sv1 jobs of the downstream channel `channel_id` are stored under:
+    // in aggregated mode its aggregated channel, the first one if no channel is given.
+    fn job_channel_id(&self, channel_id: Option<ChannelId>) -> Option<ChannelId> {
+        if is_non_aggregated() {
+            return channel_id;
+        }
+        Some(channel_id.map_or(AGGREGATED_CHANNEL_ID, |channel_id| {
+            self.aggregated_channel_of(channel_id)
+        }))
+    }
+
+    // Whether `channel_id` is an aggregated channel, the channel of a connected downstream or the
+    // channel of a retained session.
+    fn is_channel_in_use(&self, channel_id: ChannelId) -> bool {
+        is_aggregated_channel_id(channel_id)
+            || self.downstreams.iter().any(|downstream| {
+                downstream.downstream_data.super_safe_lock(|d| d.channel_id) == Some(channel_id)
+            })
+            || self.is_channel_retained(channel_id)
+    }
+
+    // Drops the prevhashes of channels no downstream uses anymore, once the map grew beyond the
+    // configured cap. Entries are removed as channels close, so this should never find any.
+    fn enforce_prevhashes_cap(&self) {
+        let max_retained_prevhashes = self.config.max_retained_prevhashes();
+        let retained = self.prevhashes.len();
+        if retained <= max_retained_prevhashes {
+            return;
+        }
+        let channels_in_use: HashSet<ChannelId> = self
+            .downstreams
+            .iter()
+            .filter_map(|downstream| downstream.downstream_data.super_safe_lock(|d| d.channel_id))
+            .collect();
+        self.prevhashes.retain(|channel_id, _| {
+            is_aggregated_channel_id(*channel_id)
+                || channels_in_use.contains(channel_id)
+                || self.is_channel_retained(*channel_id)
+        });
+        warn!(
+            "Retained prevhashes grew to {} entries, above the cap of {}: pruned {} entries of closed channels",
+            retained,
+            max_retained_prevhashes,
+            retained - self.prevhashes.len()
+        );
+    }
+
+    /// Opens an extended mining channel for a downstream connection.
+    ///
+    /// This method initiates the SV2 channel setup process by:
+    /// - Calculating the initial target based on configuration
+    /// - Rendering the miner's user identity from the configured template
+    /// - Creating an OpenExtendedMiningChannel message
+    /// - Sending the request to the channel manager
+    ///
+    /// # Arguments
+    /// * `downstream` - The downstream connection to set up a channel for
+    /// * `worker_name` - Worker name being authorized, if the channel is opened on its
+    ///   `mining.authorize`
+    ///
+    /// # Returns
+    /// * `Ok(())` - Channel setup request sent successfully
+    /// * `Err(TproxyError)` - Error setting up the channel
+    pub async fn open_extended_mining_channel(
+        &self,
+        request_id: RequestId,
+        downstream_id: DownstreamId,
+        worker_name: Option<&str>,
     ) -> TproxyResult<(), error::Sv1Server> {
-        let affected = self.downstreams.iter().find(|downstream| {
-            downstream
+        let config = &self.config.downstream_difficulty_config;
+        let downstream = self.downstreams.get(&downstream_id).unwrap();
+
+        let hashrate = self.hot_config.min_individual_miner_hashrate() as f64;
+        let shares_per_min = self.hot_config.shares_per_minute() as f64;
+        let suggested_extranonce2_size = downstream
+            .downstream_data
+            .super_safe_lock(|d| d.suggested_extranonce2_size);
+        let min_extranonce_size = match suggested_extranonce2_size {
+            Some(size) if size > self.config.downstream_extranonce2_size as usize => {
+                info!(
+                    "Opening channel for downstream {} with the extranonce2 size of {} bytes it suggested",
+                    downstream_id, size
+                );
+                size as u16
+            }
+            _ => self.config.downstream_extranonce2_size,
+        };
+        let vardiff_enabled = config.enable_vardiff;
+
+        let max_target = if vardiff_enabled {
+            hash_rate_to_target(hashrate, shares_per_min).unwrap()
+        } else {
+            // If translator doesn't manage vardiff, we rely on upstream to do that,
+            // so we give it more freedom by setting max_target to maximum possible value
+            Target::from_le_bytes([0xff; 32])
+        };
+
+        let miner_id = self.miner_counter.fetch_add(1, Ordering::SeqCst) + 1;
+        let authorized_worker_name = match worker_name {
+            Some(worker_name) => worker_name.to_string(),
+            None => downstream
                 .downstream_data
-                .super_safe_lock(|d| d.channel_id == Some(channel_id))
-        });
+                .super_safe_lock(|d| d.authorized_worker_name.clone()),
+        };
+        let user_identity = self
+            .config
+            .render_user_identity(
+                (!authorized_worker_name.is_empty()).then_some(authorized_worker_name.as_str()),
+                miner_id,
+            )
+            .map_err(|e| TproxyError::disconnect(e, downstream_id))?;
 
-        let Some(downstream) = affected else {
-            warn!(
-                "No downstream found for channel {} when vardiff is disabled",
-                channel_id
-            );
-            info!("Sending CloseChannel message: Channel id {channel_id}");
-            let reason_code = Str0255::try_from("downstream disconnected".to_string()).unwrap();
+        downstream
+            .downstream_data
+            .safe_lock(|d| {
+                // A channel reopened for a rerouted worker keeps the identity its shares are
+                // submitted with, set on `mining.authorize`
+                if d.authorized_worker_name.is_empty() {
+                    d.user_identity = user_identity.clone();
+                    d.miner_id = miner_id;
+                }
+            })
+            .map_err(TproxyError::shutdown)?;
+
+        if let Ok(open_channel_msg) = build_sv2_open_extended_mining_channel(
+            request_id,
+            user_identity.clone(),
+            hashrate as Hashrate,
+            max_target,
+            min_extranonce_size,
+        ) {
             self.sv1_server_channel_state
                 .channel_manager_sender
-                .send((
-                    Mining::CloseChannel(CloseChannel {
-                        channel_id,
-                        reason_code,
-                    }),
-                    None,
-                ))
+                .send((Mining::OpenExtendedMiningChannel(open_channel_msg), None))
                 .await
                 .map_err(|_| TproxyError::shutdown(TproxyErrorKind::ChannelErrorSender))?;
-            return Err(TproxyError::log(
-                TproxyErrorKind::DownstreamNotFoundWithChannelId(channel_id),
-            ));
+        } else {
+            error!("Failed to build OpenExtendedMiningChannel message");
+        }
+
+        Ok(())
+    }
+
+    /// Retrieves a downstream connection by ID from the provided map.
+    ///
+    /// # Arguments
+    /// * `downstream_id` - The ID of the downstream connection to find
+    /// * `downstream` - HashMap containing downstream connections
+    ///
+    /// # Returns
+    /// * `Some(Downstream)` - If a downstream with the given ID exists
+    /// * `None` - If no downstream with the given ID is found
+    pub fn get_downstream(
+        downstream_id: DownstreamId,
+        downstream: HashMap<DownstreamId, Downstream>,
+    ) -> Option<Downstream> {
+        downstream.get(&downstream_id).cloned()
+    }
+
+    /// Builds the `mining.set_difficulty` for `target`, snapped to the configured difficulty
+    /// quantization.
+    pub(super) fn build_set_difficulty(
+        &self,
+        target: Target,
+    ) -> Result<json_rpc::Message, StratumTranslationError> {
+        build_sv1_set_difficulty(
+            target,
+            self.config
+                .downstream_difficulty_config
+                .difficulty_quantization,
+        )
+    }
+
+    /// Handles SetTarget messages when vardiff is disabled.
+    ///
+    /// This method forwards difficulty changes from upstream directly to downstream miners
+    /// without any variable difficulty logic. It respects the aggregated/non-aggregated
+    /// channel configuration.
+    async fn handle_set_target_without_vardiff(
+        &self,
+        set_target: SetTarget<'_>,
+    ) -> TproxyResult<(), error::Sv1Server> {
+        let new_target =
+            Target::from_le_bytes(set_target.maximum_target.inner_as_ref().try_into().unwrap());
+        debug!(
+            "Forwarding SetTarget to downstreams: channel_id={}, target={:?}",
+            set_target.channel_id, new_target
+        );
+
+        if is_aggregated() {
+            // Aggregated mode: send set_difficulty to ALL downstreams of the aggregated channel
+            return self
+                .send_set_difficulty_to_all_downstreams(set_target.channel_id, new_target)
+                .await;
+        }
+
+        // Non-aggregated mode: send set_difficulty to specific downstream for this channel
+        self.send_set_difficulty_to_specific_downstream(set_target.channel_id, new_target)
+            .await
+    }
+
+    /// Sends set_difficulty to all downstreams of the aggregated channel `aggregated_channel_id`
+    /// (aggregated mode).
+    /// Used only when vardiff is disabled.
+    async fn send_set_difficulty_to_all_downstreams(
+        &self,
+        aggregated_channel_id: ChannelId,
+        target: Target,
+    ) -> TproxyResult<(), error::Sv1Server> {
+        for downstream in self.downstreams.iter() {
+            let downstream_id = downstream.key();
+            let downstream = downstream.value();
+            if downstream
+                .downstream_data
+                .super_safe_lock(|d| d.aggregated_channel_id != aggregated_channel_id)
+            {
+                continue;
+            }
+            let channel_id = downstream.downstream_data.super_safe_lock(|d| {
+                let channel_id = d.channel_id?;
+
+                d.set_upstream_target(target, *downstream_id);
+                d.set_pending_target(target, *downstream_id);
+
+                Some(channel_id)
+            });
+
+            let Some(channel_id) = channel_id else {
+                trace!(
+                    "Skipping downstream {}: no channel_id set (vardiff disabled)",
+                    downstream_id
+                );
+                continue;
+            };
+
+            let set_difficulty_msg = match self.build_set_difficulty(target) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    error!(
+                        "Failed to build SetDifficulty for downstream {}: {:?}",
+                        downstream_id, e
+                    );
+                    return Err(TproxyError::shutdown(e));
+                }
+            };
+
+            if let Err(e) = self
+                .sv1_server_channel_state
+                .sv1_server_to_downstream_sender
+                .send((channel_id, Some(*downstream_id), set_difficulty_msg))
+            {
+                error!(
+                    "Failed to send SetDifficulty to downstream {}: {:?}",
+                    downstream_id, e
+                );
+                return Err(TproxyError::log(TproxyErrorKind::ChannelErrorSender));
+            } else {
+                debug!(
+                    "Sent SetDifficulty to downstream {} (vardiff disabled)",
+                    downstream_id
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends set_difficulty to the specific downstream associated with a channel (non-aggregated
+    /// mode).
+    /// Used only when vardiff is disabled.
+    async fn send_set_difficulty_to_specific_downstream(
+        &self,
+        channel_id: ChannelId,
+        target: Target,
+    ) -> TproxyResult<(), error::Sv1Server> {
+        let affected = self.downstreams.iter().find(|downstream| {
+            downstream
+                .downstream_data
+                .super_safe_lock(|d| d.channel_id == Some(channel_id))
+        });
+
+        let Some(downstream) = affected else {
+            warn!(
+                "No downstream found for channel {} when vardiff is disabled",
+                channel_id
+            );
+            info!("Sending CloseChannel message: Channel id {channel_id}");
+            let reason_code = Str0255::try_from("downstream disconnected".to_string()).unwrap();
+            self.sv1_server_channel_state
+                .channel_manager_sender
+                .send((
+                    Mining::CloseChannel(CloseChannel {
+                        channel_id,
+                        reason_code,
+                    }),
+                    None,
+                ))
+                .await
+                .map_err(|_| TproxyError::shutdown(TproxyErrorKind::ChannelErrorSender))?;
+            return Err(TproxyError::log(
+                TproxyErrorKind::DownstreamNotFoundWithChannelId(channel_id),
+            ));
+        };
+
+        let downstream_id = downstream.key();
+        let downstream = downstream.value();
+
+        downstream.downstream_data.super_safe_lock(|d| {
+            d.set_upstream_target(target, *downstream_id);
+            d.set_pending_target(target, *downstream_id);
+        });
+
+        let set_difficulty_msg = match self.build_set_difficulty(target) {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!(
+                    "Failed to build SetDifficulty for downstream {}: {:?}",
+                    downstream_id, e
+                );
+                return Err(TproxyError::shutdown(e));
+            }
+        };
+
+        if let Err(e) = self
+            .sv1_server_channel_state
+            .sv1_server_to_downstream_sender
+            .send((channel_id, Some(*downstream_id), set_difficulty_msg))
+        {
+            error!(
+                "Failed to send SetDifficulty to downstream {}: {:?}",
+                downstream_id, e
+            );
+            return Err(TproxyError::log(TproxyErrorKind::ChannelErrorSender));
+        } else {
+            debug!(
+                "Sent SetDifficulty to downstream {} for channel {} (vardiff disabled)",
+                downstream_id, channel_id
+            );
+        }
+        Ok(())
+    }
+
+    /// Spawns the job keepalive loop that sends periodic mining.notify messages.
+    ///
+    /// This prevents SV1 miners from timing out when there are no new jobs received from the
+    /// upstream for a while.
+    ///
+    /// The interval is read again on every check and whenever the configuration is reloaded. An
+    /// interval of 0 pauses the keepalive jobs until a reload sets a non-zero one.
+    pub async fn spawn_job_keepalive_loop(self: Arc<Self>) {
+        info!(
+            "Starting job keepalive loop with interval of {} seconds",
+            self.hot_config.job_keepalive_interval_secs()
+        );
+
+        loop {
+            let keepalive_interval_secs = self.hot_config.job_keepalive_interval_secs();
+            if keepalive_interval_secs == 0 {
+                self.hot_config.reloaded().await;
+                continue;
+            }
+            let interval = Duration::from_secs(keepalive_interval_secs as u64);
+            let check_interval =
+                Duration::from_secs(keepalive_interval_secs as u64 / 2).max(Duration::from_secs(5));
+            tokio::select! {
+                _ = tokio::time::sleep(check_interval) => {}
+                _ = self.hot_config.reloaded() => continue,
+            }
+            let keepalive_targets: Vec<(DownstreamId, Option<ChannelId>)> = self
+                .downstreams
+                .iter()
+                .filter_map(|downstream| {
+                    let downstream_id = downstream.key();
+                    let downstream = downstream.value();
+                    downstream.downstream_data.super_safe_lock(|d| {
+                        // Only send keepalive if:
+                        // 1. Handshake is complete
+                        // 2. Enough time has passed since last job
+                        // 3. The miner is not moving to the upstream the translator rotated to
+                        let handshake_complete =
+                            downstream.sv1_handshake_complete.load(Ordering::SeqCst);
+
+                        if !handshake_complete || d.rotating_upstream {
+                            return None;
+                        }
+
+                        let needs_keepalive = match d.last_job_received_time {
+                            Some(last_time) => last_time.elapsed() >= interval,
+                            None => false, // No job received yet, don't send keepalive
+                        };
+
+                        if needs_keepalive {
+                            Some((*downstream_id, d.channel_id))
+                        } else {
+                            None
+                        }
+                    })
+                })
+                .collect();
+
+            self.send_keepalive_jobs(keepalive_targets, check_interval, keepalive_interval_secs)
+                .await;
+        }
+    }
+
+    // Sends a keepalive job to each of `keepalive_targets`, spread evenly over `check_interval`
+    // and at most `max_keepalive_notifies_per_sec` per second, instead of a burst of
+    // `mining.notify` to every idle downstream at once.
+    async fn send_keepalive_jobs(
+        &self,
+        keepalive_targets: Vec<(DownstreamId, Option<ChannelId>)>,
+        check_interval: Duration,
+        keepalive_interval_secs: u16,
+    ) {
+        let interval = Duration::from_secs(keepalive_interval_secs as u64);
+        let mut spacing = check_interval / keepalive_targets.len().max(1) as u32;
+        let max_notifies_per_sec = self.config.max_keepalive_notifies_per_sec();
+        if max_notifies_per_sec > 0 {
+            spacing = spacing.max(Duration::from_secs(1) / max_notifies_per_sec);
+        }
+        for (i, (downstream_id, channel_id)) in keepalive_targets.into_iter().enumerate() {
+            if i > 0 {
+                tokio::time::sleep(spacing).await;
+            }
+            // The downstream may have received a job from upstream while waiting for its turn
+            let still_idle = self
+                .downstreams
+                .get(&downstream_id)
+                .is_some_and(|downstream| {
+                    downstream.downstream_data.super_safe_lock(|d| {
+                        d.last_job_received_time
+                            .is_some_and(|last_time| last_time.elapsed() >= interval)
+                    })
+                });
+            if !still_idle {
+                continue;
+            }
+            let keepalive_job = self.create_keepalive_job(channel_id, keepalive_interval_secs);
+
+            if let Some(notify) = keepalive_job {
+                debug!(
+                    "Sending keepalive job to downstream {} with job_id: {}, time: {}",
+                    downstream_id, notify.job_id, notify.time.0
+                );
+
+                if let Err(e) = self
+                    .sv1_server_channel_state
+                    .sv1_server_to_downstream_sender
+                    .send((channel_id.unwrap_or(0), Some(downstream_id), notify.into()))
+                {
+                    warn!(
+                        "Failed to send keepalive job to downstream {}: {:?}",
+                        downstream_id, e
+                    );
+                } else if let Some(downstream) = self.downstreams.get(&downstream_id) {
+                    downstream.downstream_data.super_safe_lock(|d| {
+                        d.last_job_received_time = Some(Instant::now());
+                    });
+                }
+            }
+        }
+    }
+
+    /// Creates the next keepalive job for a channel from its last job.
+    ///
+    /// The new job's time is the last job's time plus the keepalive interval, capped at
+    /// `MAX_FUTURE_BLOCK_TIME` from the original upstream job's time and at the configured
+    /// maximum clock skew ahead of the local clock. Returns `None` if there is no job for the
+    /// channel or a cap has been reached; each job reaching a cap is counted once in
+    /// `keepalive_time_capped`.
+    fn create_keepalive_job(
+        &self,
+        channel_id: Option<ChannelId>,
+        keepalive_interval_secs: u16,
+    ) -> Option<server_to_client::Notify<'static>> {
+        let last_job = self.get_last_job(channel_id)?;
+
+        // Extract the original upstream job_id from the last job
+        // If it's already a keepalive job, extract its original; otherwise use as-is
+        let original_job_id = Self::extract_original_job_id(&last_job.job_id)
+            .unwrap_or_else(|| last_job.job_id.clone());
+
+        // Find the original upstream job to get its base time
+        let original_job = self.get_original_job(&original_job_id, channel_id);
+        let base_time = original_job
+            .as_ref()
+            .map(|j| j.time.0)
+            .unwrap_or(last_job.time.0);
+
+        // Increment the time by the keepalive interval, but cap at
+        // MAX_FUTURE_BLOCK_TIME from the original job's time to maintain consensus
+        // validity (see https://github.com/bitcoin/bitcoin/blob/cd6e4c9235f763b8077cece69c2e3b2025cc8d0f/src/chain.h#L29)
+        const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+        // Nor run ahead of the real time by more than the accepted clock skew, in case the
+        // upstream jobs already carry a time ahead of it
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as u32;
+        let max_time = now.saturating_add(self.config.max_clock_skew().as_secs() as u32);
+        let new_time = last_job
+            .time
+            .0
+            .saturating_add(keepalive_interval_secs as u32)
+            .min(base_time.saturating_add(MAX_FUTURE_BLOCK_TIME))
+            .min(max_time);
+
+        let job_channel_id = self.job_channel_id(channel_id);
+
+        // If we've hit a cap, don't send another keepalive for this job
+        if new_time <= last_job.time.0 {
+            // The job stays capped on every later attempt until a new job arrives
+            let newly_capped = job_channel_id.is_none_or(|job_channel_id| {
+                self.keepalive_capped_jobs
+                    .insert(job_channel_id, original_job_id.clone())
+                    .is_none_or(|capped_job_id| capped_job_id != original_job_id)
+            });
+            if newly_capped {
+                self.keepalive_time_capped.fetch_add(1, Ordering::Relaxed);
+                debug!(
+                    channel_id = ?channel_id,
+                    job_id = %original_job_id,
+                    base_time,
+                    "Keepalive job time reached its cap, no new job from upstream"
+                );
+            }
+            return None;
+        }
+        if let Some(job_channel_id) = job_channel_id {
+            self.keepalive_capped_jobs.remove(&job_channel_id);
+        }
+
+        // Generate new keepalive job_id: {original_job_id}#{counter}
+        let new_job_id = self.next_keepalive_job_id(&original_job_id);
+
+        let mut keepalive_notify = last_job;
+        keepalive_notify.job_id = new_job_id;
+        keepalive_notify.time = HexU32Be(new_time);
+
+        // Add the keepalive job to valid jobs so shares can be validated
+        _ = job_channel_id
+            .and_then(|ch_id| self.valid_sv1_jobs.get_mut(&ch_id))
+            .map(|mut jobs| self.push_valid_job(&mut jobs, keepalive_notify.clone()));
+
+        Some(keepalive_notify)
+    }
+
+    // Stores a notified job as valid, evicting the oldest jobs of the channel beyond
+    // `max_valid_jobs_per_channel`. The upstream job the latest job is built on is never evicted:
+    // it is the base of the keepalive jobs built on top of it.
+    fn push_valid_job(
+        &self,
+        jobs: &mut Vec<server_to_client::Notify<'static>>,
+        notify: server_to_client::Notify<'static>,
+    ) {
+        let active_job_id =
+            Self::extract_original_job_id(&notify.job_id).unwrap_or_else(|| notify.job_id.clone());
+        jobs.push(notify);
+        let excess = jobs
+            .len()
+            .saturating_sub(self.config.max_valid_jobs_per_channel());
+        if excess == 0 {
+            return;
+        }
+        let mut evicted = 0;
+        jobs.retain(|job| {
+            if evicted == excess || job.job_id == active_job_id {
+                return true;
+            }
+            evicted += 1;
+            false
+        });
+        trace!("Evicted {evicted} valid jobs past the cap of their channel");
+    }
+
+    /// Generates a keepalive job ID by appending a mutation counter to the original job ID.
+    /// Format: `{original_job_id}#{counter}` where `#` is the delimiter.
+    /// When receiving a share, split on `#` to extract the original job ID.
+    fn next_keepalive_job_id(&self, original_job_id: &str) -> String {
+        let counter = self
+            .keepalive_job_id_counter
+            .fetch_add(1, Ordering::Relaxed);
+        format!("{}#{}", original_job_id, counter)
+    }
+
+    /// Extracts the original upstream job ID from a keepalive job ID.
+    /// Returns None if the job_id doesn't contain the keepalive delimiter.
+    pub(super) fn extract_original_job_id(job_id: &str) -> Option<String> {
+        job_id
+            .split_once(KEEPALIVE_JOB_ID_DELIMITER)
+            .map(|(original, _)| original.to_string())
+    }
+
+    /// Returns true if the job_id is a keepalive job (contains the delimiter).
+    #[inline]
+    fn is_keepalive_job_id(job_id: &str) -> bool {
+        job_id.contains(KEEPALIVE_JOB_ID_DELIMITER)
+    }
+
+    /// Gets the last job from the jobs storage.
+    /// In aggregated mode, returns the last job from the shared job list.
+    /// In non-aggregated mode, returns the last job for the specified channel.
+    pub fn get_last_job(
+        &self,
+        channel_id: Option<u32>,
+    ) -> Option<server_to_client::Notify<'static>> {
+        let channel_id = self.job_channel_id(channel_id)?;
+
+        self.valid_sv1_jobs
+            .get(&channel_id)
+            .and_then(|jobs| jobs.last().cloned())
+    }
+
+    /// Gets the original upstream job by its job_id.
+    /// This is used to find the base time for keepalive time capping.
+    pub fn get_original_job(
+        &self,
+        job_id: &str,
+        channel_id: Option<u32>,
+    ) -> Option<server_to_client::Notify<'static>> {
+        let channel_id = self.job_channel_id(channel_id)?;
+
+        self.valid_sv1_jobs
+            .get(&channel_id)?
+            .iter()
+            .find(|j| j.job_id == job_id)
+            .cloned()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingTargetUpdate {
+    pub downstream_id: DownstreamId,
+    pub new_target: Target,
+    pub new_hashrate: Hashrate,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{
+            DownstreamDifficultyConfig, TranslatorConfig, UpdateChannelErrorAction, Upstream,
+            UpstreamRouteRule,
+        },
+        sv2::ChannelManager,
+    };
+    use async_channel::{bounded, unbounded};
+    use std::{collections::HashMap, str::FromStr};
+    use stratum_apps::{
+        key_utils::Secp256k1PublicKey,
+        stratum_core::{
+            binary_sv2::{Seq0255, Sv2Option},
+            mining_sv2::{
+                NewExtendedMiningJob, OpenExtendedMiningChannelSuccess, OpenMiningChannelError,
+                SetExtranoncePrefix,
+            },
+            parsers_sv2::AnyMessage,
+        },
+        utils::types::Sv2Frame,
+    };
+
+    fn create_test_config() -> TranslatorConfig {
+        let pubkey_str = "9bDuixKmZqAJnrmP746n8zU1wyAQRrus7th9dxnkPg6RzQvCnan";
+        let pubkey = Secp256k1PublicKey::from_str(pubkey_str).unwrap();
+
+        let upstream = Upstream::new("127.0.0.1".to_string(), 4444, pubkey);
+        let difficulty_config = DownstreamDifficultyConfig::new(100.0, 5.0, true, 60);
+
+        TranslatorConfig::new(
+            vec![upstream],
+            "0.0.0.0".to_string(), // downstream_address
+            3333,                  // downstream_port
+            difficulty_config,     // downstream_difficulty_config
+            2,                     // max_supported_version
+            1,                     // min_supported_version
+            4,                     // downstream_extranonce2_size
+            "test_user".to_string(),
+            true,   // aggregate_channels
+            vec![], // supported_extensions
+            vec![], // required_extensions
+        )
+    }
+
+    fn create_test_sv1_server() -> Sv1Server {
+        let (cm_sender, _cm_receiver) = unbounded();
+        let (_downstream_sender, cm_receiver) = unbounded();
+        let config = create_test_config();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+
+        Sv1Server::new(addr, cm_receiver, cm_sender, config)
+    }
+
+    #[test]
+    fn test_sv1_server_creation() {
+        let server = create_test_sv1_server();
+
+        assert_eq!(server.hot_config.shares_per_minute(), 5.0);
+        assert_eq!(server.listener_addr.ip().to_string(), "127.0.0.1");
+        assert_eq!(server.listener_addr.port(), 3333);
+        assert_eq!(server.config.user_identity, "test_user");
+    }
+
+    #[test]
+    fn test_sv1_server_config() {
+        let mut config = create_test_config();
+        config.downstream_difficulty_config.enable_vardiff = true;
+
+        let (cm_sender, _cm_receiver) = unbounded();
+        let (_downstream_sender, cm_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+
+        let server = Sv1Server::new(addr, cm_receiver, cm_sender, config);
+
+        assert!(server.config.downstream_difficulty_config.enable_vardiff);
+    }
+
+    #[test]
+    fn test_get_downstream_basic() {
+        let downstreams = HashMap::new();
+
+        // Test non-existing downstream
+        let not_found = Sv1Server::get_downstream(999, downstreams);
+        assert!(not_found.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_send_set_difficulty_to_all_downstreams_empty() {
+        let server = create_test_sv1_server();
+        let target: Target = hash_rate_to_target(200.0, 5.0).unwrap();
+
+        // Test with empty downstreams
+        _ = server
+            .send_set_difficulty_to_all_downstreams(AGGREGATED_CHANNEL_ID, target)
+            .await;
+
+        // Should not crash with empty downstreams
+    }
+
+    #[tokio::test]
+    async fn test_send_set_difficulty_to_specific_downstream_not_found() {
+        let server = create_test_sv1_server();
+        let target: Target = hash_rate_to_target(200.0, 5.0).unwrap();
+        let channel_id = 1u32;
+
+        // Test with no downstreams
+        _ = server
+            .send_set_difficulty_to_specific_downstream(channel_id, target)
+            .await;
+
+        // Should not crash when no downstreams are found
+    }
+
+    #[tokio::test]
+    async fn test_handle_set_target_without_vardiff_aggregated() {
+        let mut config = create_test_config();
+        config.downstream_difficulty_config.enable_vardiff = false;
+
+        let (cm_sender, _cm_receiver) = unbounded();
+        let (_downstream_sender, cm_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+
+        let server = Sv1Server::new(addr, cm_receiver, cm_sender, config);
+        let target: Target = hash_rate_to_target(200.0, 5.0).unwrap();
+
+        let set_target = SetTarget {
+            channel_id: 1,
+            maximum_target: target.to_le_bytes().into(),
+        };
+
+        // Test should not panic and should handle the message
+        _ = server.handle_set_target_without_vardiff(set_target).await;
+    }
+
+    #[tokio::test]
+    async fn test_handle_set_target_without_vardiff_non_aggregated() {
+        let mut config = create_test_config();
+        config.downstream_difficulty_config.enable_vardiff = false;
+
+        let (cm_sender, _cm_receiver) = unbounded();
+        let (_downstream_sender, cm_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+
+        let server = Sv1Server::new(addr, cm_receiver, cm_sender, config);
+        let target: Target = hash_rate_to_target(200.0, 5.0).unwrap();
+
+        let set_target = SetTarget {
+            channel_id: 1,
+            maximum_target: target.to_le_bytes().into(),
+        };
+
+        // Test should not panic and should handle the message
+        _ = server.handle_set_target_without_vardiff(set_target).await;
+    }
+
+    #[test]
+    fn test_sv1_server_counters() {
+        let server = create_test_sv1_server();
+
+        // Test initial values
+        assert_eq!(server.miner_counter.load(Ordering::SeqCst), 0);
+        assert_eq!(server.sequence_counter.load(Ordering::SeqCst), 1);
+
+        // Test incrementing
+        let miner_id = server.miner_counter.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(miner_id, 0);
+        assert_eq!(server.miner_counter.load(Ordering::SeqCst), 1);
+
+        // sequence_counter starts at 1, so first share gets sequence 1
+        let seq_id = server.sequence_counter.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(seq_id, 1);
+        assert_eq!(server.sequence_counter.load(Ordering::SeqCst), 2);
+    }
+
+    fn create_test_notify(job_id: &str, time: u32) -> server_to_client::Notify<'static> {
+        let notification = json_rpc::Notification {
+            method: "mining.notify".to_string(),
+            params: serde_json::json!([
+                job_id,
+                "00".repeat(32),
+                "01000000",
+                "ffffffff",
+                [],
+                "20000000",
+                "1d00ffff",
+                format!("{time:08x}"),
+                true
+            ]),
+        };
+        server_to_client::Notify::try_from(notification).unwrap()
+    }
+
+    #[test]
+    fn test_keepalive_job_stops_at_future_block_time_cap() {
+        let server = create_test_sv1_server();
+        let base_time = 1_700_000_000;
+        server.valid_sv1_jobs.insert(
+            AGGREGATED_CHANNEL_ID,
+            vec![create_test_notify("1", base_time)],
+        );
+
+        // 120 keepalives of 60 seconds take the job time to the 2 hour cap
+        for _ in 0..120 {
+            assert!(server.create_keepalive_job(None, 60).is_some());
+        }
+        assert_eq!(
+            server.get_last_job(None).unwrap().time.0,
+            base_time + 2 * 60 * 60
+        );
+        assert_eq!(server.keepalive_time_capped.load(Ordering::Relaxed), 0);
+
+        // once capped, no further keepalive is created and the capped job is counted once
+        assert!(server.create_keepalive_job(None, 60).is_none());
+        assert!(server.create_keepalive_job(None, 60).is_none());
+        assert_eq!(server.keepalive_time_capped.load(Ordering::Relaxed), 1);
+        // the keepalives past the valid jobs cap evicted the oldest ones, never the original
+        let jobs = server.valid_sv1_jobs.get(&AGGREGATED_CHANNEL_ID).unwrap();
+        assert_eq!(jobs.len(), 64);
+        assert_eq!(jobs[0].job_id, "1");
+        drop(jobs);
+
+        // a new upstream job is counted again once its own keepalives reach the cap
+        server
+            .valid_sv1_jobs
+            .get_mut(&AGGREGATED_CHANNEL_ID)
+            .unwrap()
+            .push(create_test_notify("2", base_time + 60));
+        let mut keepalives = 0;
+        while server.create_keepalive_job(None, 60).is_some() {
+            keepalives += 1;
+        }
+        assert_eq!(keepalives, 120);
+        assert!(server.create_keepalive_job(None, 60).is_none());
+        assert_eq!(server.keepalive_time_capped.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_valid_jobs_bounded_while_recent_jobs_stay_valid() {
+        let (cm_sender, _cm_receiver) = unbounded();
+        let (_downstream_sender, cm_receiver) = unbounded();
+        let config = create_test_config().with_max_valid_jobs_per_channel(8);
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, cm_receiver, cm_sender, config);
+        let base_time = 1_700_000_000;
+        server.valid_sv1_jobs.insert(
+            AGGREGATED_CHANNEL_ID,
+            vec![create_test_notify("1", base_time)],
+        );
+
+        // non-clean job updates, each followed by keepalives
+        for job_id in 2..=50u32 {
+            let mut jobs = server
+                .valid_sv1_jobs
+                .get_mut(&AGGREGATED_CHANNEL_ID)
+                .unwrap();
+            server.push_valid_job(
+                &mut jobs,
+                create_test_notify(&job_id.to_string(), base_time),
+            );
+            drop(jobs);
+            for _ in 0..3 {
+                assert!(server.create_keepalive_job(None, 1).is_some());
+            }
+            let jobs = server.valid_sv1_jobs.get(&AGGREGATED_CHANNEL_ID).unwrap();
+            assert!(jobs.len() <= 8);
+        }
+
+        let job_ids: Vec<String> = server
+            .valid_sv1_jobs
+            .get(&AGGREGATED_CHANNEL_ID)
+            .unwrap()
+            .iter()
+            .map(|job| job.job_id.clone())
+            .collect();
+        // the latest upstream job, its keepalives and the jobs right before are still valid
+        assert!(job_ids.contains(&"50".to_string()));
+        assert!(job_ids.contains(&"49".to_string()));
+        assert_eq!(job_ids.iter().filter(|id| id.starts_with("50#")).count(), 3);
+        assert!(!job_ids.contains(&"1".to_string()));
+        // keepalives keep building on the time of the latest upstream job
+        assert_eq!(
+            server.get_last_job(None).unwrap().time.0,
+            server.get_original_job("50", None).unwrap().time.0 + 3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_jobs_spread_over_many_idle_downstreams() {
+        let (cm_sender, _cm_receiver) = unbounded();
+        let (_downstream_sender, cm_receiver) = unbounded();
+        let config = create_test_config().with_max_keepalive_notifies_per_sec(20);
+        let server = Sv1Server::new(
+            "127.0.0.1:3333".parse().unwrap(),
+            cm_receiver,
+            cm_sender,
+            config,
+        );
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+        server
+            .valid_sv1_jobs
+            .insert(AGGREGATED_CHANNEL_ID, vec![create_test_notify("1", now)]);
+
+        // ten miners got their last job two keepalive intervals ago
+        let idle_since = Instant::now() - Duration::from_secs(120);
+        let keepalive_targets: Vec<(DownstreamId, Option<ChannelId>)> = (1..=10)
+            .map(|downstream_id| (downstream_id, None))
+            .collect();
+        for &(downstream_id, _) in &keepalive_targets {
+            insert_test_downstream(&server, downstream_id, 100.0);
+            server
+                .downstreams
+                .get(&downstream_id)
+                .unwrap()
+                .downstream_data
+                .super_safe_lock(|d| d.last_job_received_time = Some(idle_since));
+        }
+        let mut sv1_server_receiver = server
+            .sv1_server_channel_state
+            .sv1_server_to_downstream_sender
+            .subscribe();
+
+        let collect = tokio::spawn(async move {
+            let mut sent_at = Vec::new();
+            while sent_at.len() < 10 {
+                let (_, downstream_id, _) = sv1_server_receiver.recv().await.unwrap();
+                assert!(downstream_id.is_some());
+                sent_at.push(Instant::now());
+            }
+            sent_at
+        });
+        // the check interval alone would allow the whole round within 10ms
+        server
+            .send_keepalive_jobs(keepalive_targets, Duration::from_millis(10), 60)
+            .await;
+        let sent_at = collect.await.unwrap();
+
+        // at most 20 notifies per second: one every 50ms instead of a burst
+        for gap in sent_at.windows(2).map(|pair| pair[1] - pair[0]) {
+            assert!(gap >= Duration::from_millis(40), "keepalive gap of {gap:?}");
+        }
+        assert!(sent_at[9] - sent_at[0] >= Duration::from_millis(450));
+
+        // a miner which received a job from upstream meanwhile gets no keepalive
+        server
+            .downstreams
+            .get(&1)
+            .unwrap()
+            .downstream_data
+            .super_safe_lock(|d| d.last_job_received_time = Some(Instant::now()));
+        let mut sv1_server_receiver = server
+            .sv1_server_channel_state
+            .sv1_server_to_downstream_sender
+            .subscribe();
+        server
+            .send_keepalive_jobs(vec![(1, None)], Duration::ZERO, 60)
+            .await;
+        assert!(sv1_server_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_keepalive_job_time_bounded_by_local_clock() {
+        let (cm_sender, _cm_receiver) = unbounded();
+        let (_downstream_sender, cm_receiver) = unbounded();
+        let config = create_test_config().with_max_clock_skew(600, false);
+        let server = Sv1Server::new(
+            "127.0.0.1:3333".parse().unwrap(),
+            cm_receiver,
+            cm_sender,
+            config,
+        );
+        let now = || {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as u32
+        };
+
+        // the upstream clock is 9 minutes ahead: keepalives may only add one more minute
+        let base_time = now() + 9 * 60;
+        server.valid_sv1_jobs.insert(
+            AGGREGATED_CHANNEL_ID,
+            vec![create_test_notify("1", base_time)],
+        );
+        let mut keepalives = 0;
+        while let Some(notify) = server.create_keepalive_job(None, 30) {
+            assert!(notify.time.0 <= now() + 600);
+            keepalives += 1;
+            assert!(keepalives <= 3, "keepalive time is not bounded");
+        }
+        assert!(keepalives >= 2);
+        assert_eq!(server.keepalive_time_capped.load(Ordering::Relaxed), 1);
+
+        // the upstream clock is further ahead than the accepted skew: no keepalive at all
+        let server = create_test_sv1_server();
+        server.valid_sv1_jobs.insert(
+            AGGREGATED_CHANNEL_ID,
+            vec![create_test_notify("1", now() + 60 * 60)],
+        );
+        assert!(server.create_keepalive_job(None, 30).is_none());
+        assert_eq!(server.keepalive_time_capped.load(Ordering::Relaxed), 1);
+    }
+
+    fn insert_test_downstream(server: &Sv1Server, downstream_id: DownstreamId, hashrate: Hashrate) {
+        let (downstream_sv1_sender, downstream_sv1_receiver) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (sv1_server_broadcast, _) = tokio::sync::broadcast::channel(10);
+        let downstream = Downstream::new(
+            downstream_id,
+            downstream_sv1_sender,
+            downstream_sv1_receiver,
+            sv1_server_sender,
+            sv1_server_broadcast,
+            hash_rate_to_target(hashrate as f64, 5.0).unwrap(),
+            Some(hashrate),
+            server.job_propagation.clone(),
+            server.valid_sv1_jobs.clone(),
+        );
+        server.downstreams.insert(downstream_id, downstream);
+    }
+
+    #[tokio::test]
+    async fn test_update_channel_hashrate_reduced_on_downstream_disconnect() {
+        let (cm_sender, cm_receiver) = unbounded();
+        let (_downstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, create_test_config());
+
+        insert_test_downstream(&server, 1, 100.0);
+        insert_test_downstream(&server, 2, 200.0);
+        insert_test_downstream(&server, 3, 300.0);
+        insert_test_downstream(&server, 4, 400.0);
+
+        let mut expected_hashrate = 1000.0;
+        for downstream_id in [2, 4] {
+            let (_, downstream) = server.downstreams.remove(&downstream_id).unwrap();
+            expected_hashrate -= downstream
+                .downstream_data
+                .super_safe_lock(|d| d.hashrate.unwrap());
+            server
+                .send_update_channel_on_downstream_state_change(AGGREGATED_CHANNEL_ID)
+                .await;
+
+            match cm_receiver.try_recv().unwrap() {
+                (Mining::UpdateChannel(msg), _) => {
+                    assert_eq!(msg.nominal_hash_rate, expected_hashrate);
+                }
+                msg => panic!("Expected UpdateChannel, found: {msg:?}"),
+            }
+        }
+        assert_eq!(expected_hashrate, 400.0);
+
+        // a pending vardiff estimate takes precedence over the current hashrate
+        server
+            .downstreams
+            .get(&1)
+            .unwrap()
+            .downstream_data
+            .super_safe_lock(|d| d.pending_hashrate = Some(150.0));
+        assert_eq!(
+            server
+                .aggregated_update_channel(AGGREGATED_CHANNEL_ID)
+                .nominal_hash_rate,
+            450.0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_channel_sent_on_disconnect_without_vardiff() {
+        let mut config = create_test_config();
+        config.downstream_difficulty_config.enable_vardiff = false;
+        let (cm_sender, cm_receiver) = unbounded();
+        let (_downstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, config);
+
+        insert_test_downstream(&server, 1, 100.0);
+        insert_test_downstream(&server, 2, 200.0);
+
+        server.downstreams.remove(&2);
+        server
+            .send_update_channel_on_downstream_state_change(AGGREGATED_CHANNEL_ID)
+            .await;
+
+        match cm_receiver.try_recv().unwrap() {
+            (Mining::UpdateChannel(msg), _) => {
+                assert_eq!(msg.nominal_hash_rate, 100.0);
+                assert_eq!(msg.maximum_target.inner_as_ref(), &[0xFF; 32]);
+            }
+            msg => panic!("Expected UpdateChannel, found: {msg:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_idle_aggregated_channel_closed_without_downstreams() {
+        let (cm_sender, cm_receiver) = unbounded();
+        let (_downstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, create_test_config());
+        let idle = Duration::from_secs(60);
+
+        // no channel open yet
+        assert!(!server.aggregated_channel_idle(Duration::ZERO));
+
+        server.valid_sv1_jobs.insert(
+            AGGREGATED_CHANNEL_ID,
+            vec![create_test_notify("1", now() + 60 * 60)],
+        );
+        insert_test_downstream(&server, 1, 100.0);
+        assert!(!server.aggregated_channel_idle(Duration::ZERO));
+
+        server.downstreams.remove(&1);
+        assert!(!server.aggregated_channel_idle(idle));
+        server
+            .idle_since
+            .super_safe_lock(|idle_since| *idle_since = Some(Instant::now() - idle));
+        assert!(server.aggregated_channel_idle(idle));
+
+        server.close_idle_aggregated_channel().await;
+        match cm_receiver.try_recv().unwrap() {
+            (Mining::CloseChannel(msg), _) => assert_eq!(msg.channel_id, AGGREGATED_CHANNEL_ID),
+            msg => panic!("Expected CloseChannel, found: {msg:?}"),
+        }
+        assert!(!server.valid_sv1_jobs.contains_key(&AGGREGATED_CHANNEL_ID));
+        assert!(!server.aggregated_channel_idle(Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn test_user_identity_template_with_worker_name() {
+        let config =
+            create_test_config().with_user_identity_template("{user}.{worker}".to_string());
+        let (cm_sender, cm_receiver) = unbounded();
+        let (_downstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let mut server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, config);
+
+        let mut downstreams = Vec::new();
+        for downstream_id in 1..=2 {
+            let (downstream_sv1_sender, downstream_sv1_receiver) = unbounded();
+            let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+            let (sv1_server_broadcast, _) = tokio::sync::broadcast::channel(10);
+            let downstream = Downstream::new(
+                downstream_id,
+                downstream_sv1_sender,
+                downstream_sv1_receiver,
+                sv1_server_sender,
+                sv1_server_broadcast,
+                hash_rate_to_target(200.0, 5.0).unwrap(),
+                None,
+                server.job_propagation.clone(),
+                server.valid_sv1_jobs.clone(),
+            );
+            server.downstreams.insert(downstream_id, downstream.clone());
+            downstreams.push(downstream);
+        }
+        let subscribe = json_rpc::Message::StandardRequest(json_rpc::StandardRequest {
+            id: 1,
+            method: "mining.subscribe".to_string(),
+            params: serde_json::json!([]),
+        });
+        let authorize = json_rpc::Message::StandardRequest(json_rpc::StandardRequest {
+            id: 2,
+            method: "mining.authorize".to_string(),
+            params: serde_json::json!(["user.rig01", "x"]),
+        });
+
+        // the channel waits for the miner to authorize
+        for downstream in &downstreams {
+            server
+                .handle_message_from_downstream(
+                    downstream.downstream_id,
+                    downstream.clone(),
+                    subscribe.clone(),
+                )
+                .await
+                .unwrap();
+        }
+        assert!(cm_receiver.try_recv().is_err());
+
+        server
+            .handle_message_from_downstream(1, downstreams[0].clone(), authorize)
+            .await
+            .unwrap();
+        match cm_receiver.try_recv().unwrap() {
+            (Mining::OpenExtendedMiningChannel(msg), _) => {
+                assert_eq!(msg.user_identity.as_utf8_or_hex(), "test_user.rig01");
+            }
+            msg => panic!("Expected OpenExtendedMiningChannel, found: {msg:?}"),
+        }
+        downstreams[0].downstream_data.super_safe_lock(|d| {
+            assert!(d.awaiting_worker_name_since.is_none());
+            assert_eq!(d.queued_sv1_handshake_messages.len(), 2);
+        });
+
+        // a miner not authorizing in time gets its channel with the worker falling back to
+        // miner{id}
+        assert!(server.take_expired_worker_name_waits().is_empty());
+        downstreams[1].downstream_data.super_safe_lock(|d| {
+            d.awaiting_worker_name_since = Some(Instant::now() - WORKER_NAME_WAIT)
+        });
+        assert_eq!(server.take_expired_worker_name_waits(), vec![2]);
+        server.handle_open_channel_request(2, None).await.unwrap();
+        match cm_receiver.try_recv().unwrap() {
+            (Mining::OpenExtendedMiningChannel(msg), _) => {
+                assert_eq!(msg.user_identity.as_utf8_or_hex(), "test_user.miner2");
+            }
+            msg => panic!("Expected OpenExtendedMiningChannel, found: {msg:?}"),
+        }
+
+        server.authorize(Some(2), "user.rig02");
+        let user_identity = downstreams[1]
+            .downstream_data
+            .super_safe_lock(|d| d.user_identity.clone());
+        assert_eq!(user_identity, "test_user.rig02");
+    }
+
+    #[tokio::test]
+    async fn test_job_propagation_alarm_on_slow_downstream() {
+        let config = create_test_config().with_job_propagation_alarm(10);
+        let (cm_sender, _cm_receiver) = unbounded();
+        let (upstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, config);
+        let first_target = hash_rate_to_target(200.0, 5.0).unwrap();
+
+        // the miner does not read, so the queue towards it is already full
+        let (downstream_sv1_sender, downstream_sv1_receiver) = bounded(1);
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let downstream = Downstream::new(
+            1,
+            downstream_sv1_sender.clone(),
+            downstream_sv1_receiver.clone(),
+            sv1_server_sender,
+            server
+                .sv1_server_channel_state
+                .sv1_server_to_downstream_sender
+                .clone(),
+            first_target,
+            None,
+            server.job_propagation.clone(),
+            server.valid_sv1_jobs.clone(),
+        );
+        downstream.downstream_data.super_safe_lock(|d| {
+            d.channel_id = Some(1);
+            d.difficulty_sent = true;
+        });
+        downstream
+            .sv1_handshake_complete
+            .store(true, Ordering::SeqCst);
+        server.downstreams.insert(1, downstream.clone());
+        downstream_sv1_sender
+            .send(create_test_notify("0", 0).into())
+            .await
+            .unwrap();
+        let mut sv1_server_receiver = server
+            .sv1_server_channel_state
+            .sv1_server_to_downstream_sender
+            .subscribe();
+
+        let set_new_prev_hash = SetNewPrevHash {
+            channel_id: 1,
+            job_id: 1,
+            prev_hash: vec![0u8; 32].try_into().unwrap(),
+            min_ntime: 1_700_000_000,
+            nbits: 0x207fffff,
+        };
+        let new_extended_mining_job = NewExtendedMiningJob {
+            channel_id: 1,
+            job_id: 1,
+            min_ntime: Sv2Option::new(None),
+            version: 0x20000000,
+            version_rolling_allowed: true,
+            merkle_path: Seq0255::new(vec![]).unwrap(),
+            coinbase_tx_prefix: vec![0x02; 8].try_into().unwrap(),
+            coinbase_tx_suffix: vec![0xfe; 8].try_into().unwrap(),
+        };
+        for message in [
+            Mining::SetNewPrevHash(set_new_prev_hash),
+            Mining::NewExtendedMiningJob(new_extended_mining_job),
+        ] {
+            upstream_sender.send((message, None)).await.unwrap();
+            server.handle_upstream_message().await.unwrap();
+        }
+
+        let forward_notify = tokio::spawn(async move {
+            downstream
+                .handle_sv1_server_message(&mut sv1_server_receiver)
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(server.job_propagation.alarms_total(), 0);
+
+        // the miner catches up and the notify goes through
+        downstream_sv1_receiver.recv().await.unwrap();
+        forward_notify.await.unwrap().unwrap();
+        assert!(matches!(
+            downstream_sv1_receiver.recv().await.unwrap(),
+            json_rpc::Message::Notification(n) if n.method == "mining.notify"
+        ));
+
+        let latency = server.job_propagation.latency();
+        assert_eq!(latency.count, 1);
+        assert!(latency.sum >= 0.05);
+        assert_eq!(server.job_propagation.alarms_total(), 1);
+        // the latency is handed to the next scrape only
+        let latencies = server.job_propagation.take_latencies();
+        assert_eq!(latencies.len(), 1);
+        assert!(latencies[0] >= 0.05);
+        assert!(server.job_propagation.take_latencies().is_empty());
+    }
+
+    #[test]
+    fn test_slow_consumer_detected_and_disconnected() {
+        use stratum_apps::{
+            monitoring::sv1::Sv1ClientsMonitoring,
+            network_helpers::slow_consumer::SlowConsumerPolicy,
+        };
+
+        let server = create_test_sv1_server();
+        insert_test_downstream(&server, 1, 100.0);
+        let downstream = server.downstreams.get(&1).unwrap().clone();
+        let policy = SlowConsumerPolicy {
+            queue_threshold: 2,
+            duration: Duration::ZERO,
+            disconnect: true,
         };
 
-        let downstream_id = downstream.key();
-        let downstream = downstream.value();
+        // the miner never reads the messages sent to it
+        for job_id in ["1", "2"] {
+            downstream
+                .downstream_channel_state
+                .downstream_sv1_sender
+                .try_send(create_test_notify(job_id, 0).into())
+                .unwrap();
+        }
+        assert!(downstream.check_slow_consumer(&policy).is_ok());
+        assert!(!downstream.slow_consumer.is_slow_consumer());
 
-        downstream.downstream_data.super_safe_lock(|d| {
-            d.set_upstream_target(target, *downstream_id);
-            d.set_pending_target(target, *downstream_id);
-        });
+        downstream
+            .downstream_channel_state
+            .downstream_sv1_sender
+            .try_send(create_test_notify("3", 0).into())
+            .unwrap();
+        let err = downstream.check_slow_consumer(&policy).unwrap_err();
+        assert!(matches!(err.kind, TproxyErrorKind::SlowConsumer(3)));
+        assert!(matches!(err.action, crate::error::Action::Disconnect(1)));
+
+        let client = server.get_sv1_client_by_id(1).unwrap();
+        assert_eq!(client.send_queue_depth, 3);
+        assert!(client.slow_consumer);
+    }
 
-        let set_difficulty_msg = match build_sv1_set_difficulty_from_sv2_target(target) {
-            Ok(msg) => msg,
-            Err(e) => {
-                error!(
-                    "Failed to build SetDifficulty for downstream {}: {:?}",
-                    downstream_id, e
-                );
-                return Err(TproxyError::shutdown(e));
-            }
+    #[tokio::test]
+    async fn test_downstream_not_authorizing_in_grace_period_is_disconnected() {
+        let server = create_test_sv1_server();
+        let grace = Duration::from_millis(100);
+        let request = |id, method: &str| {
+            json_rpc::Message::StandardRequest(json_rpc::StandardRequest {
+                id,
+                method: method.to_string(),
+                params: serde_json::json!([]),
+            })
         };
 
-        if let Err(e) = self
-            .sv1_server_channel_state
-            .sv1_server_to_downstream_sender
-            .send((channel_id, Some(*downstream_id), set_difficulty_msg))
-        {
-            error!(
-                "Failed to send SetDifficulty to downstream {}: {:?}",
-                downstream_id, e
-            );
-            return Err(TproxyError::shutdown(TproxyErrorKind::ChannelErrorSender));
-        } else {
-            debug!(
-                "Sent SetDifficulty to downstream {} for channel {} (vardiff disabled)",
-                downstream_id, channel_id
+        let mut miners = Vec::new();
+        for downstream_id in [1, 2] {
+            let (miner_sender, downstream_sv1_receiver) = unbounded();
+            let (downstream_sv1_sender, _miner_receiver) = unbounded();
+            let downstream = Downstream::new(
+                downstream_id,
+                downstream_sv1_sender,
+                downstream_sv1_receiver,
+                server
+                    .sv1_server_channel_state
+                    .downstream_to_sv1_server_sender
+                    .clone(),
+                server
+                    .sv1_server_channel_state
+                    .sv1_server_to_downstream_sender
+                    .clone(),
+                hash_rate_to_target(100.0, 5.0).unwrap(),
+                None,
+                server.job_propagation.clone(),
+                server.valid_sv1_jobs.clone(),
             );
+            // both miners subscribe, only the second one authorizes
+            miner_sender
+                .send(request(1, "mining.subscribe"))
+                .await
+                .unwrap();
+            downstream.handle_downstream_message().await.unwrap();
+            if downstream_id == 2 {
+                miner_sender
+                    .send(request(2, "mining.authorize"))
+                    .await
+                    .unwrap();
+                downstream.handle_downstream_message().await.unwrap();
+            }
+            miners.push((downstream, miner_sender));
         }
-        Ok(())
+
+        for (downstream, _) in &miners {
+            assert!(downstream.check_authorize_grace(grace).is_ok());
+        }
+
+        tokio::time::sleep(grace).await;
+        let err = miners[0].0.check_authorize_grace(grace).unwrap_err();
+        assert!(matches!(err.kind, TproxyErrorKind::AuthorizeGraceExpired));
+        assert!(matches!(err.action, crate::error::Action::Disconnect(1)));
+        assert!(miners[1].0.check_authorize_grace(grace).is_ok());
     }
 
-    /// Spawns the job keepalive loop that sends periodic mining.notify messages.
-    ///
-    /// This prevents SV1 miners from timing out when there are no new jobs received from the
-    /// upstream for a while.
-    pub async fn spawn_job_keepalive_loop(self: Arc<Self>) {
-        let keepalive_interval_secs = self
-            .config
-            .downstream_difficulty_config
-            .job_keepalive_interval_secs;
+    #[tokio::test]
+    async fn test_lagged_downstream_resyncs_to_latest_job() {
+        let server = create_test_sv1_server();
+        let mut latest_job = create_test_notify("2", 0);
+        latest_job.clean_jobs = false;
+        server.valid_sv1_jobs.insert(
+            AGGREGATED_CHANNEL_ID,
+            vec![create_test_notify("1", 0), latest_job],
+        );
 
-        let interval = Duration::from_secs(keepalive_interval_secs as u64);
-        let check_interval =
-            Duration::from_secs(keepalive_interval_secs as u64 / 2).max(Duration::from_secs(5));
-        info!(
-            "Starting job keepalive loop with interval of {} seconds",
-            keepalive_interval_secs
+        let (downstream_sv1_sender, downstream_sv1_receiver) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (sv1_server_broadcast, _) = tokio::sync::broadcast::channel(2);
+        let downstream = Downstream::new(
+            1,
+            downstream_sv1_sender,
+            downstream_sv1_receiver.clone(),
+            sv1_server_sender,
+            sv1_server_broadcast.clone(),
+            hash_rate_to_target(100.0, 5.0).unwrap(),
+            None,
+            server.job_propagation.clone(),
+            server.valid_sv1_jobs.clone(),
         );
+        downstream
+            .downstream_data
+            .super_safe_lock(|d| d.channel_id = Some(1));
+        downstream
+            .sv1_handshake_complete
+            .store(true, Ordering::SeqCst);
+
+        // overflow the broadcast before the downstream reads from it
+        let mut sv1_server_receiver = sv1_server_broadcast.subscribe();
+        for job_id in ["3", "4", "5"] {
+            sv1_server_broadcast
+                .send((1, None, create_test_notify(job_id, 0).into()))
+                .unwrap();
+        }
 
-        loop {
-            tokio::time::sleep(check_interval).await;
-            let keepalive_targets: Vec<(DownstreamId, Option<ChannelId>)> = self
-                .downstreams
-                .iter()
-                .filter_map(|downstream| {
-                    let downstream_id = downstream.key();
-                    let downstream = downstream.value();
-                    downstream.downstream_data.super_safe_lock(|d| {
-                        // Only send keepalive if:
-                        // 1. Handshake is complete
-                        // 2. Enough time has passed since last job
-                        let handshake_complete =
-                            downstream.sv1_handshake_complete.load(Ordering::SeqCst);
+        downstream
+            .handle_sv1_server_message(&mut sv1_server_receiver)
+            .await
+            .unwrap();
+        assert_eq!(
+            downstream
+                .downstream_data
+                .super_safe_lock(|d| d.lagged_total),
+            1
+        );
+        assert!(matches!(
+            downstream_sv1_receiver.recv().await.unwrap(),
+            json_rpc::Message::Notification(n) if n.method == "mining.set_difficulty"
+        ));
+        let json_rpc::Message::Notification(notification) =
+            downstream_sv1_receiver.recv().await.unwrap()
+        else {
+            panic!("expected the latest job");
+        };
+        let notify = server_to_client::Notify::try_from(notification).unwrap();
+        assert_eq!(notify.job_id, "2");
+        assert!(notify.clean_jobs);
 
-                        if !handshake_complete {
-                            return None;
-                        }
+        // the downstream keeps receiving from the broadcast after re-syncing
+        downstream
+            .handle_sv1_server_message(&mut sv1_server_receiver)
+            .await
+            .unwrap();
+        assert!(matches!(
+            downstream_sv1_receiver.recv().await.unwrap(),
+            json_rpc::Message::Notification(n) if n.method == "mining.notify"
+        ));
+    }
 
-                        let needs_keepalive = match d.last_job_received_time {
-                            Some(last_time) => last_time.elapsed() >= interval,
-                            None => false, // No job received yet, don't send keepalive
-                        };
+    #[tokio::test]
+    async fn test_set_difficulty_sent_before_first_notify_after_handshake() {
+        let server = create_test_sv1_server();
+        let (downstream_sv1_sender, downstream_sv1_receiver) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (sv1_server_broadcast, _) = tokio::sync::broadcast::channel(2);
+        let downstream = Downstream::new(
+            1,
+            downstream_sv1_sender,
+            downstream_sv1_receiver.clone(),
+            sv1_server_sender,
+            sv1_server_broadcast.clone(),
+            hash_rate_to_target(100.0, 5.0).unwrap(),
+            None,
+            server.job_propagation.clone(),
+            server.valid_sv1_jobs.clone(),
+        );
+        downstream
+            .downstream_data
+            .super_safe_lock(|d| d.channel_id = Some(1));
 
-                        if needs_keepalive {
-                            Some((*downstream_id, d.channel_id))
-                        } else {
-                            None
-                        }
-                    })
-                })
-                .collect();
+        // a job reaches the miner while it is still authorizing, with no difficulty cached
+        let mut sv1_server_receiver = sv1_server_broadcast.subscribe();
+        sv1_server_broadcast
+            .send((1, None, create_test_notify("1", 0).into()))
+            .unwrap();
+        downstream
+            .handle_sv1_server_message(&mut sv1_server_receiver)
+            .await
+            .unwrap();
+        assert!(downstream_sv1_receiver.is_empty());
+
+        downstream.handle_sv1_handshake_completion().await.unwrap();
+        assert!(matches!(
+            downstream_sv1_receiver.recv().await.unwrap(),
+            json_rpc::Message::Notification(n) if n.method == "mining.set_difficulty"
+        ));
+        assert!(matches!(
+            downstream_sv1_receiver.recv().await.unwrap(),
+            json_rpc::Message::Notification(n) if n.method == "mining.notify"
+        ));
+
+        // later jobs are not preceded by the difficulty again
+        sv1_server_broadcast
+            .send((1, None, create_test_notify("2", 0).into()))
+            .unwrap();
+        downstream
+            .handle_sv1_server_message(&mut sv1_server_receiver)
+            .await
+            .unwrap();
+        assert!(matches!(
+            downstream_sv1_receiver.recv().await.unwrap(),
+            json_rpc::Message::Notification(n) if n.method == "mining.notify"
+        ));
+        assert!(downstream_sv1_receiver.is_empty());
+    }
 
-            // Send keepalive to each downstream that needs one
-            for (downstream_id, channel_id) in keepalive_targets {
-                // Get the appropriate job for this downstream's channel and create keepalive
-                let keepalive_job = self.get_last_job(channel_id).and_then(|last_job| {
-                    // Extract the original upstream job_id from the last job
-                    // If it's already a keepalive job, extract its original; otherwise use
-                    // as-is
-                    let original_job_id = Self::extract_original_job_id(&last_job.job_id)
-                        .unwrap_or_else(|| last_job.job_id.clone());
-
-                    // Find the original upstream job to get its base time
-                    let original_job = self.get_original_job(&original_job_id, channel_id);
-                    let base_time = original_job
-                        .as_ref()
-                        .map(|j| j.time.0)
-                        .unwrap_or(last_job.time.0);
-
-                    // Increment the time by the keepalive interval, but cap at
-                    // MAX_FUTURE_BLOCK_TIME from the original job's time to maintain consensus
-                    // validity (see https://github.com/bitcoin/bitcoin/blob/cd6e4c9235f763b8077cece69c2e3b2025cc8d0f/src/chain.h#L29)
-                    const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
-                    let new_time = last_job
-                        .time
-                        .0
-                        .saturating_add(keepalive_interval_secs as u32)
-                        .min(base_time.saturating_add(MAX_FUTURE_BLOCK_TIME));
-
-                    // If we've hit the cap, don't send another keepalive for this job
-                    if new_time == last_job.time.0 {
-                        return None;
-                    }
+    #[tokio::test]
+    async fn test_job_before_prevhash_is_notified_once_prevhash_arrives() {
+        let (cm_sender, _cm_receiver) = unbounded();
+        let (upstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, create_test_config());
+        insert_test_downstream(&server, 1, 100.0);
+        server
+            .downstreams
+            .get(&1)
+            .unwrap()
+            .downstream_data
+            .super_safe_lock(|d| d.channel_id = Some(1));
+        let mut sv1_server_receiver = server
+            .sv1_server_channel_state
+            .sv1_server_to_downstream_sender
+            .subscribe();
 
-                    // Generate new keepalive job_id: {original_job_id}#{counter}
-                    let new_job_id = self.next_keepalive_job_id(&original_job_id);
+        let new_extended_mining_job = NewExtendedMiningJob {
+            channel_id: 1,
+            job_id: 1,
+            min_ntime: Sv2Option::new(None),
+            version: 0x20000000,
+            version_rolling_allowed: true,
+            merkle_path: Seq0255::new(vec![]).unwrap(),
+            coinbase_tx_prefix: vec![0x02; 8].try_into().unwrap(),
+            coinbase_tx_suffix: vec![0xfe; 8].try_into().unwrap(),
+        };
+        upstream_sender
+            .send((Mining::NewExtendedMiningJob(new_extended_mining_job), None))
+            .await
+            .unwrap();
+        server.handle_upstream_message().await.unwrap();
+        assert!(sv1_server_receiver.try_recv().is_err());
+        assert_eq!(server.pending_jobs.len(), 1);
 
-                    let mut keepalive_notify = last_job;
-                    keepalive_notify.job_id = new_job_id.clone();
-                    keepalive_notify.time = HexU32Be(new_time);
+        let set_new_prev_hash = SetNewPrevHash {
+            channel_id: 1,
+            job_id: 1,
+            prev_hash: vec![0u8; 32].try_into().unwrap(),
+            min_ntime: 1_700_000_000,
+            nbits: 0x207fffff,
+        };
+        upstream_sender
+            .send((Mining::SetNewPrevHash(set_new_prev_hash), None))
+            .await
+            .unwrap();
+        server.handle_upstream_message().await.unwrap();
+
+        let (channel_id, downstream_id, message) = sv1_server_receiver.try_recv().unwrap();
+        assert_eq!(channel_id, 1);
+        assert!(downstream_id.is_none());
+        assert!(matches!(
+            message,
+            json_rpc::Message::Notification(n) if n.method == "mining.notify"
+        ));
+        assert!(sv1_server_receiver.try_recv().is_err());
+        assert!(server.pending_jobs.is_empty());
+        assert_eq!(server.pending_jobs.dropped_total(), 0);
+        assert_eq!(
+            server
+                .valid_sv1_jobs
+                .iter()
+                .map(|jobs| jobs.len())
+                .sum::<usize>(),
+            1
+        );
+    }
 
-                    // Add the keepalive job to valid jobs so shares can be validated
-                    let job_channel_id = if is_aggregated() {
-                        Some(AGGREGATED_CHANNEL_ID)
-                    } else {
-                        channel_id
-                    };
+    #[tokio::test]
+    async fn test_downstream_logs_carry_connection_span_fields() {
+        use tracing_subscriber::fmt::format::FmtSpan;
+
+        // Collects the formatted log records, prefixed with the fields of their spans
+        #[derive(Clone, Default)]
+        struct Logs(Arc<std::sync::Mutex<Vec<u8>>>);
+        impl std::io::Write for Logs {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_max_level(tracing::Level::DEBUG)
+            .with_span_events(FmtSpan::CLOSE)
+            .finish();
+        let _subscriber = tracing::subscriber::set_default(subscriber);
 
-                    _ = job_channel_id
-                        .and_then(|ch_id| self.valid_sv1_jobs.get_mut(&ch_id))
-                        .map(|mut jobs| jobs.push(keepalive_notify.clone()));
+        let server = create_test_sv1_server();
+        let (downstream_sv1_sender, _downstream_sv1_receiver) = unbounded();
+        let (_miner_sender, downstream_sv1_receiver) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (sv1_server_broadcast, _) = tokio::sync::broadcast::channel(10);
+        let downstream = Downstream::new(
+            1,
+            downstream_sv1_sender,
+            downstream_sv1_receiver,
+            sv1_server_sender,
+            sv1_server_broadcast,
+            hash_rate_to_target(200.0, 5.0).unwrap(),
+            None,
+            server.job_propagation.clone(),
+            server.valid_sv1_jobs.clone(),
+        );
+        downstream
+            .span
+            .record("peer_addr", tracing::field::display("192.0.2.1:4000"));
+        downstream
+            .downstream_data
+            .super_safe_lock(|d| d.channel_id = Some(1));
+        server.downstreams.insert(1, downstream);
 
-                    Some(keepalive_notify)
-                });
+        let authorize = json_rpc::Message::StandardRequest(json_rpc::StandardRequest {
+            id: 1,
+            method: "mining.authorize".to_string(),
+            params: serde_json::json!(["user.rig01", "x"]),
+        });
+        server
+            .sv1_server_channel_state
+            .downstream_to_sv1_server_sender
+            .send((1, authorize))
+            .await
+            .unwrap();
+        server.handle_downstream_message().await.unwrap();
+        // the span is closed once the disconnected downstream is removed
+        server.remove_downstream(1).await;
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let in_span = |line: &&str| {
+            line.contains("downstream{downstream_id=1") && line.contains("peer_addr=192.0.2.1:4000")
+        };
+        let authorize_line = logs
+            .lines()
+            .find(|line| line.contains("Received mining.authorize"))
+            .unwrap();
+        assert!(in_span(&authorize_line), "{logs}");
+        let close_line = logs.lines().filter(in_span).last().unwrap();
+        assert!(close_line.contains("close"), "{logs}");
+        assert!(close_line.contains("worker_name=\"user.rig01\""), "{logs}");
+    }
 
-                if let Some(notify) = keepalive_job {
-                    debug!(
-                        "Sending keepalive job to downstream {} with job_id: {}, time: {}",
-                        downstream_id, notify.job_id, notify.time.0
-                    );
+    #[tokio::test]
+    async fn test_multiple_workers_authorized_on_one_connection_share_its_channel() {
+        let (cm_sender, cm_receiver) = unbounded();
+        let (_downstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, create_test_config());
+
+        // the connection is subscribed and its channel is open
+        let (downstream_sv1_sender, downstream_sv1_receiver) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (sv1_server_broadcast, _) = tokio::sync::broadcast::channel(10);
+        let downstream = Downstream::new(
+            1,
+            downstream_sv1_sender,
+            downstream_sv1_receiver.clone(),
+            sv1_server_sender,
+            sv1_server_broadcast,
+            hash_rate_to_target(200.0, 5.0).unwrap(),
+            None,
+            server.job_propagation.clone(),
+            server.valid_sv1_jobs.clone(),
+        );
+        downstream
+            .downstream_data
+            .super_safe_lock(|d| d.channel_id = Some(1));
+        server.downstreams.insert(1, downstream.clone());
+
+        for (id, worker) in [(1, "user.rig01"), (2, "user.rig02")] {
+            let authorize = json_rpc::Message::StandardRequest(json_rpc::StandardRequest {
+                id,
+                method: "mining.authorize".to_string(),
+                params: serde_json::json!([worker, "x"]),
+            });
+            server
+                .sv1_server_channel_state
+                .downstream_to_sv1_server_sender
+                .send((1, authorize))
+                .await
+                .unwrap();
+            server.handle_downstream_message().await.unwrap();
 
-                    if let Err(e) = self
-                        .sv1_server_channel_state
-                        .sv1_server_to_downstream_sender
-                        .send((channel_id.unwrap_or(0), Some(downstream_id), notify.into()))
-                    {
-                        warn!(
-                            "Failed to send keepalive job to downstream {}: {:?}",
-                            downstream_id, e
-                        );
-                    } else if let Some(downstream) = self.downstreams.get(&downstream_id) {
-                        downstream.downstream_data.super_safe_lock(|d| {
-                            d.last_job_received_time = Some(Instant::now());
-                        });
-                    }
+            match downstream_sv1_receiver.try_recv().unwrap() {
+                json_rpc::Message::OkResponse(response) => {
+                    assert_eq!(response.id, id);
+                    assert_eq!(response.result, serde_json::Value::Bool(true));
                 }
+                msg => panic!("Expected OkResponse, found: {msg:?}"),
             }
         }
-    }
+        assert!(downstream.sv1_handshake_complete.load(Ordering::SeqCst));
 
-    /// Generates a keepalive job ID by appending a mutation counter to the original job ID.
-    /// Format: `{original_job_id}#{counter}` where `#` is the delimiter.
-    /// When receiving a share, split on `#` to extract the original job ID.
-    fn next_keepalive_job_id(&self, original_job_id: &str) -> String {
-        let counter = self
-            .keepalive_job_id_counter
-            .fetch_add(1, Ordering::Relaxed);
-        format!("{}#{}", original_job_id, counter)
+        // both workers submit on the connection's channel, no other channel is opened
+        assert!(cm_receiver.try_recv().is_err());
+        assert!(server.is_authorized(Some(1), "user.rig01"));
+        assert!(server.is_authorized(Some(1), "user.rig02"));
+        assert!(!server.is_authorized(Some(1), "user.rig03"));
+        downstream.downstream_data.super_safe_lock(|d| {
+            assert_eq!(d.authorized_worker_name, "user.rig01");
+            assert_eq!(d.worker_user_identity("user.rig01"), "user.rig01");
+            assert_eq!(d.worker_user_identity("user.rig02"), "user.rig02");
+        });
     }
 
-    /// Extracts the original upstream job ID from a keepalive job ID.
-    /// Returns None if the job_id doesn't contain the keepalive delimiter.
-    fn extract_original_job_id(job_id: &str) -> Option<String> {
-        job_id
-            .split_once(KEEPALIVE_JOB_ID_DELIMITER)
-            .map(|(original, _)| original.to_string())
-    }
+    #[tokio::test]
+    async fn test_rejoined_channel_requires_its_worker_on_authorize() {
+        let (cm_sender, _cm_receiver) = unbounded();
+        let (_downstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, create_test_config());
+
+        // the connection rejoined the channel retained for user.rig01 on subscribe
+        let (downstream_sv1_sender, downstream_sv1_receiver) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (sv1_server_broadcast, _) = tokio::sync::broadcast::channel(10);
+        let downstream = Downstream::new(
+            1,
+            downstream_sv1_sender,
+            downstream_sv1_receiver.clone(),
+            sv1_server_sender,
+            sv1_server_broadcast,
+            hash_rate_to_target(200.0, 5.0).unwrap(),
+            None,
+            server.job_propagation.clone(),
+            server.valid_sv1_jobs.clone(),
+        );
+        downstream.downstream_data.super_safe_lock(|d| {
+            d.channel_id = Some(1);
+            d.rejoined_worker_name = Some("user.rig01".to_string());
+        });
+        server.downstreams.insert(1, downstream.clone());
 
-    /// Returns true if the job_id is a keepalive job (contains the delimiter).
-    #[inline]
-    fn is_keepalive_job_id(job_id: &str) -> bool {
-        job_id.contains(KEEPALIVE_JOB_ID_DELIMITER)
+        let authorize = json_rpc::Message::StandardRequest(json_rpc::StandardRequest {
+            id: 1,
+            method: "mining.authorize".to_string(),
+            params: serde_json::json!(["user.rig02", "x"]),
+        });
+        server
+            .sv1_server_channel_state
+            .downstream_to_sv1_server_sender
+            .send((1, authorize))
+            .await
+            .unwrap();
+        let error = server.handle_downstream_message().await.unwrap_err();
+
+        assert!(matches!(
+            error.kind,
+            TproxyErrorKind::RejoinedWorkerMismatch(ref retained, ref authorized)
+                if retained == "user.rig01" && authorized == "user.rig02"
+        ));
+        assert!(downstream_sv1_receiver.try_recv().is_err());
+        assert!(!server.is_authorized(Some(1), "user.rig02"));
     }
 
-    /// Gets the last job from the jobs storage.
-    /// In aggregated mode, returns the last job from the shared job list.
-    /// In non-aggregated mode, returns the last job for the specified channel.
-    pub fn get_last_job(
-        &self,
-        channel_id: Option<u32>,
-    ) -> Option<server_to_client::Notify<'static>> {
-        let channel_id = if is_aggregated() {
-            AGGREGATED_CHANNEL_ID
-        } else {
-            channel_id?
+    #[tokio::test]
+    async fn test_prevhashes_not_retained_for_closed_channels() {
+        let config = create_test_config().with_max_retained_prevhashes(16);
+        let (cm_sender, _cm_receiver) = unbounded();
+        let (upstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, config);
+        let set_new_prev_hash = |channel_id| {
+            Mining::SetNewPrevHash(SetNewPrevHash {
+                channel_id,
+                job_id: 1,
+                prev_hash: vec![0u8; 32].try_into().unwrap(),
+                min_ntime: 1_700_000_000,
+                nbits: 0x207fffff,
+            })
         };
 
-        self.valid_sv1_jobs
-            .get(&channel_id)
-            .and_then(|jobs| jobs.last().cloned())
-    }
-
-    /// Gets the original upstream job by its job_id.
-    /// This is used to find the base time for keepalive time capping.
-    pub fn get_original_job(
-        &self,
-        job_id: &str,
-        channel_id: Option<u32>,
-    ) -> Option<server_to_client::Notify<'static>> {
-        let channel_id = if is_aggregated() {
-            AGGREGATED_CHANNEL_ID
-        } else {
-            channel_id?
-        };
+        for round in 0..10u32 {
+            let channel_ids: Vec<ChannelId> = (round * 10..round * 10 + 10).collect();
+            for &channel_id in &channel_ids {
+                insert_test_downstream(&server, channel_id as DownstreamId, 100.0);
+                server
+                    .downstreams
+                    .get(&(channel_id as DownstreamId))
+                    .unwrap()
+                    .downstream_data
+                    .super_safe_lock(|d| d.channel_id = Some(channel_id));
+                upstream_sender
+                    .send((set_new_prev_hash(channel_id), None))
+                    .await
+                    .unwrap();
+                server.handle_upstream_message().await.unwrap();
+            }
+            assert_eq!(server.prevhashes.len(), channel_ids.len());
+
+            // half of the channels are closed by the miner disconnecting, the other half by the
+            // upstream
+            for &channel_id in &channel_ids {
+                if channel_id % 2 == 1 {
+                    upstream_sender
+                        .send((
+                            Mining::CloseChannel(CloseChannel {
+                                channel_id,
+                                reason_code: Str0255::try_from("closed".to_string()).unwrap(),
+                            }),
+                            None,
+                        ))
+                        .await
+                        .unwrap();
+                    assert!(server.handle_upstream_message().await.is_err());
+                }
+                server.remove_downstream(channel_id as DownstreamId).await;
+            }
+            assert!(server.prevhashes.is_empty());
+        }
 
-        self.valid_sv1_jobs
-            .get(&channel_id)?
-            .iter()
-            .find(|j| j.job_id == job_id)
-            .cloned()
+        // a prevhash still in flight when its channel closed is not retained
+        upstream_sender
+            .send((set_new_prev_hash(42), None))
+            .await
+            .unwrap();
+        server.handle_upstream_message().await.unwrap();
+        assert!(server.prevhashes.is_empty());
     }
-}
-
-#[derive(Debug, Clone)]
-pub struct PendingTargetUpdate {
-    pub downstream_id: DownstreamId,
-    pub new_target: Target,
-    pub new_hashrate: Hashrate,
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::{DownstreamDifficultyConfig, TranslatorConfig, Upstream};
-    use async_channel::unbounded;
-    use std::{collections::HashMap, str::FromStr};
-    use stratum_apps::key_utils::Secp256k1PublicKey;
 
-    fn create_test_config() -> TranslatorConfig {
-        let pubkey_str = "9bDuixKmZqAJnrmP746n8zU1wyAQRrus7th9dxnkPg6RzQvCnan";
-        let pubkey = Secp256k1PublicKey::from_str(pubkey_str).unwrap();
-
-        let upstream = Upstream::new("127.0.0.1".to_string(), 4444, pubkey);
-        let difficulty_config = DownstreamDifficultyConfig::new(100.0, 5.0, true, 60);
-
-        TranslatorConfig::new(
-            vec![upstream],
-            "0.0.0.0".to_string(), // downstream_address
-            3333,                  // downstream_port
-            difficulty_config,     // downstream_difficulty_config
-            2,                     // max_supported_version
-            1,                     // min_supported_version
-            4,                     // downstream_extranonce2_size
-            "test_user".to_string(),
-            true,   // aggregate_channels
-            vec![], // supported_extensions
-            vec![], // required_extensions
-        )
+    async fn submit_share(
+        server: &Sv1Server,
+        downstream_sv1_receiver: &Receiver<json_rpc::Message>,
+        id: u64,
+        job_id: &str,
+        ntime: u32,
+        nonce: &str,
+    ) -> json_rpc::Message {
+        let submit = json_rpc::Message::StandardRequest(json_rpc::StandardRequest {
+            id,
+            method: "mining.submit".to_string(),
+            params: serde_json::json!([
+                "user.rig01",
+                job_id,
+                "00000000",
+                format!("{ntime:08x}"),
+                nonce
+            ]),
+        });
+        server
+            .sv1_server_channel_state
+            .downstream_to_sv1_server_sender
+            .send((1, submit))
+            .await
+            .unwrap();
+        server.handle_downstream_message().await.unwrap();
+        downstream_sv1_receiver.try_recv().unwrap()
     }
 
-    fn create_test_sv1_server() -> Sv1Server {
-        let (cm_sender, _cm_receiver) = unbounded();
-        let (_downstream_sender, cm_receiver) = unbounded();
-        let config = create_test_config();
+    #[tokio::test]
+    async fn test_rejected_shares_answered_with_stratum_error_codes() {
+        let (cm_sender, cm_receiver) = unbounded();
+        let (_downstream_sender, sv1_server_receiver) = unbounded();
         let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, create_test_config());
+
+        let (downstream_sv1_sender, downstream_sv1_receiver) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (sv1_server_broadcast, _) = tokio::sync::broadcast::channel(10);
+        let downstream = Downstream::new(
+            1,
+            downstream_sv1_sender,
+            downstream_sv1_receiver.clone(),
+            sv1_server_sender,
+            sv1_server_broadcast,
+            Target::from_le_bytes([0xff; 32]),
+            None,
+            server.job_propagation.clone(),
+            server.valid_sv1_jobs.clone(),
+        );
+        downstream.downstream_data.super_safe_lock(|d| {
+            d.channel_id = Some(1);
+            d.authorized_worker_name = "user.rig01".to_string();
+            d.last_job_version_field = Some(0x20000000);
+        });
+        server.downstreams.insert(1, downstream.clone());
+        server
+            .valid_sv1_jobs
+            .insert(AGGREGATED_CHANNEL_ID, vec![create_test_notify("1", 0)]);
+
+        // the first share meets the target and is forwarded upstream
+        match submit_share(&server, &downstream_sv1_receiver, 1, "1", 0, "00000001").await {
+            json_rpc::Message::OkResponse(response) => {
+                assert_eq!(response.id, 1);
+                assert_eq!(response.result, serde_json::Value::Bool(true));
+            }
+            msg => panic!("Expected OkResponse, found: {msg:?}"),
+        }
+        assert!(matches!(
+            cm_receiver.try_recv().unwrap(),
+            (Mining::SubmitSharesExtended(_), _)
+        ));
+
+        let assert_rejected = |message: json_rpc::Message, id: u64, code: i32| match message {
+            json_rpc::Message::ErrorResponse(response) => {
+                assert_eq!(response.id, id);
+                assert_eq!(response.error.unwrap().code, code);
+            }
+            msg => panic!("Expected ErrorResponse, found: {msg:?}"),
+        };
 
-        Sv1Server::new(addr, cm_receiver, cm_sender, config)
+        assert_rejected(
+            submit_share(&server, &downstream_sv1_receiver, 2, "7", 0, "00000002").await,
+            2,
+            21,
+        );
+        assert_rejected(
+            submit_share(&server, &downstream_sv1_receiver, 3, "1", 0, "00000001").await,
+            3,
+            22,
+        );
+
+        downstream
+            .downstream_data
+            .super_safe_lock(|d| d.target = Target::from_le_bytes([0; 32]));
+        assert_rejected(
+            submit_share(&server, &downstream_sv1_receiver, 4, "1", 0, "00000003").await,
+            4,
+            23,
+        );
+
+        // none of the rejected shares reached the upstream
+        assert!(cm_receiver.try_recv().is_err());
     }
 
-    #[test]
-    fn test_sv1_server_creation() {
-        let server = create_test_sv1_server();
+    #[tokio::test]
+    async fn test_share_for_keepalive_of_evicted_job_rejected_locally() {
+        let (cm_sender, cm_receiver) = unbounded();
+        let (_downstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, create_test_config());
+
+        let (downstream_sv1_sender, downstream_sv1_receiver) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (sv1_server_broadcast, _) = tokio::sync::broadcast::channel(10);
+        let downstream = Downstream::new(
+            1,
+            downstream_sv1_sender,
+            downstream_sv1_receiver.clone(),
+            sv1_server_sender,
+            sv1_server_broadcast,
+            Target::from_le_bytes([0xff; 32]),
+            None,
+            server.job_propagation.clone(),
+            server.valid_sv1_jobs.clone(),
+        );
+        downstream.downstream_data.super_safe_lock(|d| {
+            d.channel_id = Some(1);
+            d.authorized_worker_name = "user.rig01".to_string();
+            d.last_job_version_field = Some(0x20000000);
+        });
+        server.downstreams.insert(1, downstream);
+        // the keepalive of job 1 is still valid, but job 1 itself was evicted
+        server.valid_sv1_jobs.insert(
+            AGGREGATED_CHANNEL_ID,
+            vec![create_test_notify("1#0", 0), create_test_notify("2", 0)],
+        );
 
-        assert_eq!(server.shares_per_minute, 5.0);
-        assert_eq!(server.listener_addr.ip().to_string(), "127.0.0.1");
-        assert_eq!(server.listener_addr.port(), 3333);
-        assert_eq!(server.config.user_identity, "test_user");
+        match submit_share(&server, &downstream_sv1_receiver, 1, "1#0", 0, "00000001").await {
+            json_rpc::Message::ErrorResponse(response) => {
+                assert_eq!(response.id, 1);
+                let error = response.error.unwrap();
+                assert_eq!(error.code, 21);
+                assert_eq!(error.message, "Job not found");
+            }
+            msg => panic!("Expected ErrorResponse, found: {msg:?}"),
+        }
+        assert!(cm_receiver.try_recv().is_err());
+        assert_eq!(server.keepalive_shares_orphaned.load(Ordering::Relaxed), 1);
+
+        // the keepalive of a job still valid is forwarded as usual
+        server
+            .valid_sv1_jobs
+            .get_mut(&AGGREGATED_CHANNEL_ID)
+            .unwrap()
+            .push(create_test_notify("2#1", 0));
+        match submit_share(&server, &downstream_sv1_receiver, 2, "2#1", 0, "00000001").await {
+            json_rpc::Message::OkResponse(response) => assert_eq!(response.id, 2),
+            msg => panic!("Expected OkResponse, found: {msg:?}"),
+        }
+        assert!(matches!(
+            cm_receiver.try_recv().unwrap(),
+            (Mining::SubmitSharesExtended(_), _)
+        ));
+        assert_eq!(server.keepalive_shares_orphaned.load(Ordering::Relaxed), 1);
     }
 
-    #[test]
-    fn test_sv1_server_config() {
-        let mut config = create_test_config();
-        config.downstream_difficulty_config.enable_vardiff = true;
+    #[tokio::test]
+    async fn test_shares_outside_ntime_roll_window_rejected() {
+        let config = create_test_config().with_ntime_roll_window_secs(60);
+        let (cm_sender, cm_receiver) = unbounded();
+        let (_downstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, config);
+
+        let (downstream_sv1_sender, downstream_sv1_receiver) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (sv1_server_broadcast, _) = tokio::sync::broadcast::channel(10);
+        let downstream = Downstream::new(
+            1,
+            downstream_sv1_sender,
+            downstream_sv1_receiver.clone(),
+            sv1_server_sender,
+            sv1_server_broadcast,
+            Target::from_le_bytes([0xff; 32]),
+            None,
+            server.job_propagation.clone(),
+            server.valid_sv1_jobs.clone(),
+        );
+        downstream.downstream_data.super_safe_lock(|d| {
+            d.channel_id = Some(1);
+            d.authorized_worker_name = "user.rig01".to_string();
+            d.last_job_version_field = Some(0x20000000);
+        });
+        server.downstreams.insert(1, downstream);
+        server
+            .valid_sv1_jobs
+            .insert(AGGREGATED_CHANNEL_ID, vec![create_test_notify("1", 1000)]);
+
+        for (id, ntime) in [(1, 999), (2, 1061)] {
+            match submit_share(
+                &server,
+                &downstream_sv1_receiver,
+                id,
+                "1",
+                ntime,
+                "00000001",
+            )
+            .await
+            {
+                json_rpc::Message::ErrorResponse(response) => {
+                    assert_eq!(response.id, id);
+                    let error = response.error.unwrap();
+                    assert_eq!(error.code, 20);
+                    assert_eq!(error.message, "Ntime out of range");
+                }
+                msg => panic!("Expected ErrorResponse, found: {msg:?}"),
+            }
+        }
+        assert!(cm_receiver.try_recv().is_err());
 
-        let (cm_sender, _cm_receiver) = unbounded();
-        let (_downstream_sender, cm_receiver) = unbounded();
+        // the last second of the window is still accepted
+        match submit_share(&server, &downstream_sv1_receiver, 3, "1", 1060, "00000001").await {
+            json_rpc::Message::OkResponse(response) => assert_eq!(response.id, 3),
+            msg => panic!("Expected OkResponse, found: {msg:?}"),
+        }
+        assert!(matches!(
+            cm_receiver.try_recv().unwrap(),
+            (Mining::SubmitSharesExtended(_), _)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_miner_suggesting_larger_extranonce2_size() {
+        let config = create_test_config().with_forward_miner_extranonce2_size(true);
+        let (cm_sender, cm_receiver) = unbounded();
+        let (upstream_sender, sv1_server_receiver) = unbounded();
         let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, config);
+        let first_target = hash_rate_to_target(200.0, 5.0).unwrap();
+
+        let (downstream_sv1_sender, downstream_sv1_receiver) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (sv1_server_broadcast, _) = tokio::sync::broadcast::channel(10);
+        let downstream = Downstream::new(
+            1,
+            downstream_sv1_sender,
+            downstream_sv1_receiver.clone(),
+            sv1_server_sender,
+            sv1_server_broadcast,
+            first_target,
+            None,
+            server.job_propagation.clone(),
+            server.valid_sv1_jobs.clone(),
+        );
+        server.downstreams.insert(1, downstream.clone());
 
-        let server = Sv1Server::new(addr, cm_receiver, cm_sender, config);
+        // the miner expects 8 bytes of extranonce2, more than the configured 4
+        let configure = json_rpc::Message::StandardRequest(json_rpc::StandardRequest {
+            id: 1,
+            method: "mining.configure".to_string(),
+            params: serde_json::json!([["extranonce2-size"], {"extranonce2-size.value": 8}]),
+        });
+        server
+            .sv1_server_channel_state
+            .downstream_to_sv1_server_sender
+            .send((1, configure))
+            .await
+            .unwrap();
+        server.handle_downstream_message().await.unwrap();
 
-        assert!(server.config.downstream_difficulty_config.enable_vardiff);
+        let request_id = match cm_receiver.try_recv().unwrap() {
+            (Mining::OpenExtendedMiningChannel(msg), _) => {
+                assert_eq!(msg.min_extranonce_size, 8);
+                msg.request_id
+            }
+            msg => panic!("Expected OpenExtendedMiningChannel, found: {msg:?}"),
+        };
+
+        // the upstream can only grant the configured size, so the miner is rejected
+        let success = OpenExtendedMiningChannelSuccess {
+            request_id,
+            channel_id: 1,
+            target: first_target.to_le_bytes().into(),
+            extranonce_size: 4,
+            extranonce_prefix: vec![0u8; 8].try_into().unwrap(),
+            group_channel_id: 0,
+        };
+        upstream_sender
+            .send((Mining::OpenExtendedMiningChannelSuccess(success), None))
+            .await
+            .unwrap();
+        let error = server.handle_upstream_message().await.unwrap_err();
+        assert!(matches!(
+            error.kind,
+            TproxyErrorKind::Extranonce2SizeNotGranted(8, 4)
+        ));
+
+        match downstream_sv1_receiver.try_recv().unwrap() {
+            json_rpc::Message::ErrorResponse(response) => {
+                assert_eq!(response.id, 1);
+                assert!(response
+                    .error
+                    .unwrap()
+                    .message
+                    .contains("extranonce2 size of 8 bytes"));
+            }
+            msg => panic!("Expected ErrorResponse, found: {msg:?}"),
+        }
     }
 
-    #[test]
-    fn test_get_downstream_basic() {
-        let downstreams = HashMap::new();
+    // The primary upstream refuses the channel, the refusal reaching the SV1 server through the
+    // channel manager. Once no retry is left, the translator falls back to the next upstream.
+    #[tokio::test]
+    async fn test_refused_channel_retried_keeping_queued_messages() {
+        let config = create_test_config()
+            .with_open_channel_failure_action(OpenChannelFailureAction::Retry, 1);
+        let (cm_sender, cm_receiver) = unbounded();
+        let (upstream_sender, sv1_server_receiver) = unbounded();
+        let (to_upstream_sender, to_upstream_receiver) = unbounded();
+        let (from_upstream_sender, from_upstream_receiver) = unbounded();
+        let (status_sender, _status_receiver) = unbounded();
+        let channel_manager = Arc::new(ChannelManager::new(
+            to_upstream_sender,
+            from_upstream_receiver,
+            upstream_sender,
+            cm_receiver,
+            status_sender,
+            vec![],
+            vec![],
+            None,
+            false,
+            4,
+            UpdateChannelErrorAction::RevertAndRetry,
+        ));
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, config);
+        let first_target = hash_rate_to_target(200.0, 5.0).unwrap();
+
+        let (downstream_sv1_sender, downstream_sv1_receiver) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (sv1_server_broadcast, _) = tokio::sync::broadcast::channel(10);
+        let downstream = Downstream::new(
+            1,
+            downstream_sv1_sender,
+            downstream_sv1_receiver.clone(),
+            sv1_server_sender,
+            sv1_server_broadcast,
+            first_target,
+            None,
+            server.job_propagation.clone(),
+            server.valid_sv1_jobs.clone(),
+        );
+        server.downstreams.insert(1, downstream.clone());
 
-        // Test non-existing downstream
-        let not_found = Sv1Server::get_downstream(999, downstreams);
-        assert!(not_found.is_none());
+        let subscribe = json_rpc::Message::StandardRequest(json_rpc::StandardRequest {
+            id: 1,
+            method: "mining.subscribe".to_string(),
+            params: serde_json::json!([]),
+        });
+        server
+            .sv1_server_channel_state
+            .downstream_to_sv1_server_sender
+            .send((1, subscribe))
+            .await
+            .unwrap();
+        server.handle_downstream_message().await.unwrap();
+
+        let mut request_ids = HashSet::new();
+        for attempt in 1..=2 {
+            // the channel manager sends the request to the primary upstream
+            channel_manager
+                .clone()
+                .handle_downstream_message()
+                .await
+                .unwrap();
+            let mut frame = to_upstream_receiver.try_recv().unwrap();
+            let request_id = match AnyMessage::try_from((
+                frame.get_header().unwrap().msg_type(),
+                frame.payload(),
+            ))
+            .unwrap()
+            {
+                AnyMessage::Mining(Mining::OpenExtendedMiningChannel(msg)) => msg.request_id,
+                msg => panic!("Expected OpenExtendedMiningChannel, found: {msg:?}"),
+            };
+            assert!(request_ids.insert(request_id));
+            downstream.downstream_data.super_safe_lock(|d| {
+                assert_eq!(d.queued_sv1_handshake_messages.len(), 1);
+            });
+
+            let error = OpenMiningChannelError {
+                request_id,
+                error_code: "unknown-user".to_string().try_into().unwrap(),
+            };
+            let frame: Sv2Frame = AnyMessage::Mining(Mining::OpenMiningChannelError(error))
+                .try_into()
+                .unwrap();
+            from_upstream_sender.send(frame).await.unwrap();
+            channel_manager
+                .clone()
+                .handle_upstream_frame()
+                .await
+                .unwrap();
+            assert!(channel_manager.pending_channels.is_empty());
+            let result = server.handle_upstream_message().await;
+            if attempt == 1 {
+                // the channel is requested again, the subscribe still waiting for it
+                result.unwrap();
+                assert!(downstream_sv1_receiver.try_recv().is_err());
+            } else {
+                // no retry left, the next upstream gets the subscribe still waiting
+                let error = result.unwrap_err();
+                assert!(matches!(error.action, error::Action::Fallback));
+                assert!(matches!(
+                    error.kind,
+                    TproxyErrorKind::OpenMiningChannelError
+                ));
+            }
+        }
+
+        assert!(channel_manager.channel_state.sv1_server_receiver.is_empty());
+        downstream
+            .downstream_data
+            .super_safe_lock(|d| assert_eq!(d.queued_sv1_handshake_messages.len(), 1));
+        assert!(downstream_sv1_receiver.try_recv().is_err());
     }
 
     #[tokio::test]
-    async fn test_send_set_difficulty_to_all_downstreams_empty() {
-        let server = create_test_sv1_server();
-        let target: Target = hash_rate_to_target(200.0, 5.0).unwrap();
-
-        // Test with empty downstreams
-        _ = server.send_set_difficulty_to_all_downstreams(target).await;
+    async fn test_timed_out_channel_disconnects_downstream() {
+        let config = create_test_config().with_open_channel_timeout(0);
+        let (cm_sender, cm_receiver) = unbounded();
+        let (_upstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, config);
+        let first_target = hash_rate_to_target(200.0, 5.0).unwrap();
+
+        let (downstream_sv1_sender, downstream_sv1_receiver) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (sv1_server_broadcast, _) = tokio::sync::broadcast::channel(10);
+        let downstream = Downstream::new(
+            1,
+            downstream_sv1_sender,
+            downstream_sv1_receiver.clone(),
+            sv1_server_sender,
+            sv1_server_broadcast,
+            first_target,
+            None,
+            server.job_propagation.clone(),
+            server.valid_sv1_jobs.clone(),
+        );
+        server.downstreams.insert(1, downstream.clone());
 
-        // Should not crash with empty downstreams
+        let subscribe = json_rpc::Message::StandardRequest(json_rpc::StandardRequest {
+            id: 1,
+            method: "mining.subscribe".to_string(),
+            params: serde_json::json!([]),
+        });
+        server
+            .sv1_server_channel_state
+            .downstream_to_sv1_server_sender
+            .send((1, subscribe))
+            .await
+            .unwrap();
+        server.handle_downstream_message().await.unwrap();
+        assert!(matches!(
+            cm_receiver.try_recv().unwrap(),
+            (Mining::OpenExtendedMiningChannel(_), _)
+        ));
+
+        // the upstream never answered the request
+        assert_eq!(server.take_timed_out_open_channel_requests(), vec![1]);
+        assert!(server.request_id_to_downstream_id.is_empty());
+        let error = server
+            .handle_open_channel_failure(1, false)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.kind,
+            TproxyErrorKind::DownstreamChannelNotOpened(1)
+        ));
+
+        // the queued subscribe is answered with the error and no channel is requested again
+        assert!(cm_receiver.try_recv().is_err());
+        downstream
+            .downstream_data
+            .super_safe_lock(|d| assert!(d.queued_sv1_handshake_messages.is_empty()));
+        match downstream_sv1_receiver.try_recv().unwrap() {
+            json_rpc::Message::ErrorResponse(response) => assert_eq!(response.id, 1),
+            msg => panic!("Expected ErrorResponse, found: {msg:?}"),
+        }
     }
 
     #[tokio::test]
-    async fn test_send_set_difficulty_to_specific_downstream_not_found() {
-        let server = create_test_sv1_server();
-        let target: Target = hash_rate_to_target(200.0, 5.0).unwrap();
-        let channel_id = 1u32;
+    async fn test_open_channel_requests_are_paced_within_the_limit() {
+        let config = create_test_config().with_max_in_flight_open_channels(2);
+        let (cm_sender, cm_receiver) = unbounded();
+        let (_upstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, config);
+        let sent_request_ids = || {
+            let mut request_ids = Vec::new();
+            while let Ok((message, _)) = cm_receiver.try_recv() {
+                if let Mining::OpenExtendedMiningChannel(m) = message {
+                    request_ids.push(m.request_id);
+                }
+            }
+            request_ids
+        };
 
-        // Test with no downstreams
-        _ = server
-            .send_set_difficulty_to_specific_downstream(channel_id, target)
-            .await;
+        // four miners connect at once, the third one asking for its channel twice
+        for downstream_id in 1..=4 {
+            insert_test_downstream(&server, downstream_id, 100.0);
+            server
+                .handle_open_channel_request(downstream_id, None)
+                .await
+                .unwrap();
+        }
+        server.handle_open_channel_request(3, None).await.unwrap();
+        assert_eq!(sent_request_ids(), vec![1, 2]);
+        assert_eq!(
+            server
+                .queued_open_channel_requests
+                .super_safe_lock(|queue| queue.iter().map(|(id, _)| *id).collect::<Vec<_>>()),
+            vec![3, 4]
+        );
+        // queued requests have not been sent, so their timeout has not started
+        assert_eq!(server.request_id_to_downstream_id.len(), 2);
+
+        // the last miner leaves before its turn, and the first request times out
+        server.remove_downstream(4).await;
+        server.request_id_to_downstream_id.remove(&1);
+        server.send_queued_open_channel_requests().await.unwrap();
+        assert_eq!(sent_request_ids(), vec![3]);
+        assert_eq!(server.request_id_to_downstream_id.get(&3).unwrap().0, 3);
+        assert!(server
+            .queued_open_channel_requests
+            .super_safe_lock(|queue| queue.is_empty()));
+    }
 
-        // Should not crash when no downstreams are found
+    #[tokio::test]
+    async fn test_late_open_channel_success_attached_to_waiting_downstream() {
+        let config = create_test_config().with_open_channel_timeout(1);
+        let (cm_sender, _cm_receiver) = unbounded();
+        let (upstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, config);
+        let target = hash_rate_to_target(100.0, 5.0).unwrap();
+        insert_test_downstream(&server, 2, 100.0);
+
+        // two requests for downstream 2 timed out, the second one being its retry
+        let sent_at = Instant::now() - Duration::from_secs(2);
+        server.request_id_to_downstream_id.insert(1, (2, sent_at));
+        server.request_id_to_downstream_id.insert(2, (2, sent_at));
+        assert_eq!(server.take_timed_out_open_channel_requests(), vec![2, 2]);
+        assert!(server.request_id_to_downstream_id.is_empty());
+
+        // the first late channel goes to the downstream still waiting for one
+        for (request_id, channel_id) in [(1, 1), (2, 2)] {
+            let success = OpenExtendedMiningChannelSuccess {
+                request_id,
+                channel_id,
+                target: target.to_le_bytes().into(),
+                extranonce_size: 4,
+                extranonce_prefix: vec![0u8, 0, 0, 0, 0, 0, 0, channel_id as u8]
+                    .try_into()
+                    .unwrap(),
+                group_channel_id: 0,
+            };
+            upstream_sender
+                .send((Mining::OpenExtendedMiningChannelSuccess(success), None))
+                .await
+                .unwrap();
+            server.handle_upstream_message().await.unwrap();
+        }
+
+        assert!(server.late_open_channel_requests.is_empty());
+        server
+            .downstreams
+            .get(&2)
+            .unwrap()
+            .downstream_data
+            .super_safe_lock(|d| assert_eq!(d.channel_id, Some(1)));
     }
 
     #[tokio::test]
-    async fn test_handle_set_target_without_vardiff_aggregated() {
-        let mut config = create_test_config();
-        config.downstream_difficulty_config.enable_vardiff = false;
+    async fn test_channel_opened_for_a_disconnected_downstream_is_closed() {
+        let config = create_test_config();
+        let (cm_sender, cm_receiver) = unbounded();
+        let (upstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, config);
+        let target = hash_rate_to_target(100.0, 5.0).unwrap();
+        insert_test_downstream(&server, 2, 100.0);
+
+        // the miner subscribed, then disconnected before its channel was opened
+        server.handle_open_channel_request(2, None).await.unwrap();
+        let Ok((Mining::OpenExtendedMiningChannel(request), _)) = cm_receiver.try_recv() else {
+            panic!("Expected OpenExtendedMiningChannel");
+        };
+        assert_eq!(server.request_id_to_downstream_id.len(), 1);
+        server.remove_downstream(2).await;
+        assert!(server.request_id_to_downstream_id.is_empty());
+
+        let channel_id = 7;
+        let success = OpenExtendedMiningChannelSuccess {
+            request_id: request.request_id,
+            channel_id,
+            target: target.to_le_bytes().into(),
+            extranonce_size: 4,
+            extranonce_prefix: vec![0u8; 8].try_into().unwrap(),
+            group_channel_id: 0,
+        };
+        upstream_sender
+            .send((Mining::OpenExtendedMiningChannelSuccess(success), None))
+            .await
+            .unwrap();
+        let error = server.handle_upstream_message().await.unwrap_err();
+        assert!(matches!(error.kind, TproxyErrorKind::DownstreamNotFound(_)));
+        assert!(server.downstreams.is_empty());
+        // the shared channel of aggregated mode stays open, an own channel is closed upstream
+        if is_non_aggregated() {
+            let Ok((Mining::CloseChannel(close), _)) = cm_receiver.try_recv() else {
+                panic!("Expected CloseChannel");
+            };
+            assert_eq!(close.channel_id, channel_id);
+        }
+    }
 
-        let (cm_sender, _cm_receiver) = unbounded();
-        let (_downstream_sender, cm_receiver) = unbounded();
+    #[tokio::test]
+    async fn test_upstream_extranonce_prefix_change_sent_to_miners() {
+        let (cm_sender, cm_receiver) = unbounded();
+        let (upstream_sender, sv1_server_receiver) = unbounded();
         let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, create_test_config());
+        server
+            .valid_sv1_jobs
+            .insert(AGGREGATED_CHANNEL_ID, vec![create_test_notify("1", 0)]);
+
+        // the miner on channel 1 subscribed to `mining.set_extranonce`, the one on channel 2 did
+        // not
+        let (downstream_sv1_sender, downstream_sv1_receiver) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (sv1_server_broadcast, _) = tokio::sync::broadcast::channel(10);
+        let downstream = Downstream::new(
+            1,
+            downstream_sv1_sender,
+            downstream_sv1_receiver.clone(),
+            sv1_server_sender,
+            sv1_server_broadcast,
+            Target::from_le_bytes([0xff; 32]),
+            None,
+            server.job_propagation.clone(),
+            server.valid_sv1_jobs.clone(),
+        );
+        downstream.downstream_data.super_safe_lock(|d| {
+            d.channel_id = Some(1);
+            d.authorized_worker_name = "user.rig01".to_string();
+            d.last_job_version_field = Some(0x20000000);
+            d.extranonce_subscribed = true;
+        });
+        downstream
+            .sv1_handshake_complete
+            .store(true, Ordering::SeqCst);
+        server.downstreams.insert(1, downstream.clone());
+        insert_test_downstream(&server, 2, 100.0);
+        server
+            .downstreams
+            .get(&2)
+            .unwrap()
+            .downstream_data
+            .super_safe_lock(|d| d.channel_id = Some(2));
+        let mut sv1_server_receiver = server
+            .sv1_server_channel_state
+            .sv1_server_to_downstream_sender
+            .subscribe();
 
-        let server = Sv1Server::new(addr, cm_receiver, cm_sender, config);
-        let target: Target = hash_rate_to_target(200.0, 5.0).unwrap();
+        let set_extranonce_prefix = |channel_id| SetExtranoncePrefix {
+            channel_id,
+            extranonce_prefix: vec![0xbb; 8].try_into().unwrap(),
+        };
+        upstream_sender
+            .send((Mining::SetExtranoncePrefix(set_extranonce_prefix(1)), None))
+            .await
+            .unwrap();
+        server.handle_upstream_message().await.unwrap();
 
-        let set_target = SetTarget {
-            channel_id: 1,
-            maximum_target: target.to_le_bytes().into(),
+        // the miner gets its new extranonce1, then the last job under a new job id
+        let (channel_id, downstream_id, message) = sv1_server_receiver.try_recv().unwrap();
+        assert_eq!((channel_id, downstream_id), (1, Some(1)));
+        match message {
+            json_rpc::Message::Notification(n) if n.method == "mining.set_extranonce" => {
+                assert_eq!(n.params, serde_json::json!(["bb".repeat(8), 4]));
+            }
+            msg => panic!("Expected mining.set_extranonce, found: {msg:?}"),
+        }
+        let (_, downstream_id, message) = sv1_server_receiver.try_recv().unwrap();
+        assert_eq!(downstream_id, Some(1));
+        let job_id = match message {
+            json_rpc::Message::Notification(n) if n.method == "mining.notify" => {
+                let notify = server_to_client::Notify::try_from(n).unwrap();
+                assert!(notify.clean_jobs);
+                notify.job_id
+            }
+            msg => panic!("Expected mining.notify, found: {msg:?}"),
         };
+        assert!(job_id.starts_with("1#"));
+        downstream
+            .downstream_data
+            .super_safe_lock(|d| assert_eq!(hex::encode(&d.extranonce1), "bb".repeat(8)));
 
-        // Test should not panic and should handle the message
-        _ = server.handle_set_target_without_vardiff(set_target).await;
+        // shares mined on the old extranonce1 are rejected as stale
+        match submit_share(&server, &downstream_sv1_receiver, 1, "1", 0, "00000001").await {
+            json_rpc::Message::ErrorResponse(response) => {
+                assert_eq!(response.id, 1);
+                assert_eq!(response.error.unwrap().code, 21);
+            }
+            msg => panic!("Expected ErrorResponse, found: {msg:?}"),
+        }
+        assert!(cm_receiver.try_recv().is_err());
+        match submit_share(&server, &downstream_sv1_receiver, 2, &job_id, 0, "00000001").await {
+            json_rpc::Message::OkResponse(response) => assert_eq!(response.id, 2),
+            msg => panic!("Expected OkResponse, found: {msg:?}"),
+        }
+        assert!(matches!(
+            cm_receiver.try_recv().unwrap(),
+            (Mining::SubmitSharesExtended(_), _)
+        ));
+
+        // the miner unable to follow the change is disconnected
+        upstream_sender
+            .send((Mining::SetExtranoncePrefix(set_extranonce_prefix(2)), None))
+            .await
+            .unwrap();
+        let error = server.handle_upstream_message().await.unwrap_err();
+        assert!(matches!(
+            error.kind,
+            TproxyErrorKind::ExtranonceChangeNotSupported
+        ));
+        assert!(matches!(error.action, error::Action::Disconnect(2)));
+        assert!(sv1_server_receiver.try_recv().is_err());
     }
 
     #[tokio::test]
-    async fn test_handle_set_target_without_vardiff_non_aggregated() {
-        let mut config = create_test_config();
-        config.downstream_difficulty_config.enable_vardiff = false;
-
-        let (cm_sender, _cm_receiver) = unbounded();
-        let (_downstream_sender, cm_receiver) = unbounded();
+    async fn test_routed_worker_moved_onto_its_upstream() {
+        let config =
+            create_test_config()
+                .with_upstream_routes(vec![UpstreamRouteRule::new(r"\.pool2$", 1).unwrap()]);
+        let (cm_sender, cm_receiver) = unbounded();
+        let (upstream_sender, sv1_server_receiver) = unbounded();
         let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, config);
+        let (routed_sender, _routed_receiver) = unbounded();
+        server.upstream_router.set_primary_upstream(0);
+        server.upstream_router.connect_upstream(1, routed_sender);
+        let mut sv1_server_receiver = server
+            .sv1_server_channel_state
+            .sv1_server_to_downstream_sender
+            .subscribe();
+
+        // both miners opened their channel on the primary upstream before authorizing
+        for (downstream_id, worker_name) in [(1, "user.pool1"), (2, "user.pool2")] {
+            insert_test_downstream(&server, downstream_id, 100.0);
+            let downstream = server.downstreams.get(&downstream_id).unwrap().clone();
+            downstream.downstream_data.super_safe_lock(|d| {
+                d.channel_id = Some(downstream_id as ChannelId);
+                d.authorized_worker_name = worker_name.to_string();
+                d.user_identity = worker_name.to_string();
+                d.extranonce_subscribed = true;
+            });
+            downstream
+                .sv1_handshake_complete
+                .store(true, Ordering::SeqCst);
+            server.reroute_worker(&downstream).await.unwrap();
+        }
 
-        let server = Sv1Server::new(addr, cm_receiver, cm_sender, config);
-        let target: Target = hash_rate_to_target(200.0, 5.0).unwrap();
+        // only the worker routed to the other upstream gets a channel there
+        let request_id = match cm_receiver.try_recv().unwrap() {
+            (Mining::OpenExtendedMiningChannel(msg), _) => msg.request_id,
+            msg => panic!("Expected OpenExtendedMiningChannel, found: {msg:?}"),
+        };
+        assert!(cm_receiver.try_recv().is_err());
+        assert!(server.upstream_router.route_request(request_id).is_some());
 
-        let set_target = SetTarget {
-            channel_id: 1,
-            maximum_target: target.to_le_bytes().into(),
+        let success = OpenExtendedMiningChannelSuccess {
+            request_id,
+            channel_id: 5,
+            target: hash_rate_to_target(100.0, 5.0)
+                .unwrap()
+                .to_le_bytes()
+                .into(),
+            extranonce_size: 4,
+            extranonce_prefix: vec![0xcc; 8].try_into().unwrap(),
+            group_channel_id: 0,
         };
+        upstream_sender
+            .send((Mining::OpenExtendedMiningChannelSuccess(success), None))
+            .await
+            .unwrap();
+        server.handle_upstream_message().await.unwrap();
 
-        // Test should not panic and should handle the message
-        _ = server.handle_set_target_without_vardiff(set_target).await;
+        // the miner gets the extranonce1 of its new channel, then its difficulty
+        let (channel_id, downstream_id, message) = sv1_server_receiver.try_recv().unwrap();
+        assert_eq!((channel_id, downstream_id), (5, Some(2)));
+        match message {
+            json_rpc::Message::Notification(n) if n.method == "mining.set_extranonce" => {
+                assert_eq!(n.params, serde_json::json!(["cc".repeat(8), 4]));
+            }
+            msg => panic!("Expected mining.set_extranonce, found: {msg:?}"),
+        }
+        match sv1_server_receiver.try_recv().unwrap() {
+            (5, Some(2), json_rpc::Message::Notification(n)) => {
+                assert_eq!(n.method, "mining.set_difficulty")
+            }
+            msg => panic!("Expected mining.set_difficulty, found: {msg:?}"),
+        }
+        let downstream = server.downstreams.get(&2).unwrap().clone();
+        downstream.downstream_data.super_safe_lock(|d| {
+            assert_eq!(d.channel_id, Some(5));
+            assert_eq!(hex::encode(&d.extranonce1), "cc".repeat(8));
+            // its shares keep the identity it authorized with
+            assert_eq!(d.user_identity, "user.pool2");
+            assert!(!d.reopening_channel);
+        });
+        // the channel is already on the upstream of the worker
+        server.reroute_worker(&downstream).await.unwrap();
+        assert!(cm_receiver.try_recv().is_err());
     }
 
-    #[test]
-    fn test_sv1_server_counters() {
-        let server = create_test_sv1_server();
+    #[tokio::test]
+    async fn test_routed_worker_without_extranonce_subscribe_reconnects_onto_its_upstream() {
+        let config =
+            create_test_config()
+                .with_upstream_routes(vec![UpstreamRouteRule::new(r"\.pool2$", 1).unwrap()]);
+        let (cm_sender, cm_receiver) = unbounded();
+        let (_upstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, config);
+        let (routed_sender, _routed_receiver) = unbounded();
+        server.upstream_router.set_primary_upstream(0);
+        server.upstream_router.connect_upstream(1, routed_sender);
+        let peer_ip: IpAddr = "192.168.1.10".parse().unwrap();
+
+        insert_test_downstream(&server, 1, 100.0);
+        let downstream = server.downstreams.get(&1).unwrap().clone();
+        downstream.downstream_data.super_safe_lock(|d| {
+            d.channel_id = Some(1);
+            d.authorized_worker_name = "user.pool2".to_string();
+            d.peer_ip = Some(peer_ip);
+        });
+        downstream
+            .sv1_handshake_complete
+            .store(true, Ordering::SeqCst);
+
+        // the miner cannot be moved onto another extranonce1, so it is disconnected
+        let error = server.reroute_worker(&downstream).await.unwrap_err();
+        assert!(matches!(
+            error.kind,
+            TproxyErrorKind::ExtranonceChangeNotSupported
+        ));
+        assert!(matches!(error.action, error::Action::Disconnect(1)));
+        assert!(cm_receiver.try_recv().is_err());
+
+        // its next connection from the same address opens its channel on the routed upstream
+        insert_test_downstream(&server, 2, 100.0);
+        let subscribe = json_rpc::Message::StandardRequest(json_rpc::StandardRequest {
+            id: 1,
+            method: "mining.subscribe".to_string(),
+            params: serde_json::json!([]),
+        });
+        server
+            .downstreams
+            .get(&2)
+            .unwrap()
+            .downstream_data
+            .super_safe_lock(|d| d.peer_ip = Some(peer_ip));
+        server
+            .sv1_server_channel_state
+            .downstream_to_sv1_server_sender
+            .send((2, subscribe))
+            .await
+            .unwrap();
+        server.handle_downstream_message().await.unwrap();
+        let request_id = match cm_receiver.try_recv().unwrap() {
+            (Mining::OpenExtendedMiningChannel(msg), _) => msg.request_id,
+            msg => panic!("Expected OpenExtendedMiningChannel, found: {msg:?}"),
+        };
+        assert!(server.upstream_router.route_request(request_id).is_some());
+        assert!(server.rerouted_workers.is_empty());
+    }
 
-        // Test initial values
-        assert_eq!(server.miner_counter.load(Ordering::SeqCst), 0);
-        assert_eq!(server.sequence_counter.load(Ordering::SeqCst), 1);
+    #[tokio::test]
+    async fn test_rotated_upstream_moves_downstreams_without_disconnecting_them() {
+        let (cm_sender, cm_receiver) = unbounded();
+        let (upstream_sender, sv1_server_receiver) = unbounded();
+        let addr = "127.0.0.1:3333".parse().unwrap();
+        let server = Sv1Server::new(addr, sv1_server_receiver, cm_sender, create_test_config());
+        let mut sv1_server_receiver = server
+            .sv1_server_channel_state
+            .sv1_server_to_downstream_sender
+            .subscribe();
+
+        let extranonce1 =
+            |downstream_id: DownstreamId| vec![if downstream_id == 1 { 0xaau8 } else { 0xbb }; 8];
+        for downstream_id in [1, 2] {
+            insert_test_downstream(&server, downstream_id, 100.0);
+            let downstream = server.downstreams.get(&downstream_id).unwrap().clone();
+            downstream.downstream_data.super_safe_lock(|d| {
+                d.channel_id = Some(downstream_id as ChannelId);
+                d.extranonce1 = extranonce1(downstream_id).try_into().unwrap();
+                d.extranonce2_len = 4;
+                d.extranonce_subscribed = true;
+            });
+            downstream
+                .sv1_handshake_complete
+                .store(true, Ordering::SeqCst);
+        }
+        server.valid_sv1_jobs.insert(
+            AGGREGATED_CHANNEL_ID,
+            vec![create_test_notify("1", 1_700_000_000)],
+        );
 
-        // Test incrementing
-        let miner_id = server.miner_counter.fetch_add(1, Ordering::SeqCst);
-        assert_eq!(miner_id, 0);
-        assert_eq!(server.miner_counter.load(Ordering::SeqCst), 1);
+        // the downstreams stay, waiting for a channel on the next upstream
+        server.leave_rotated_upstream();
+        assert_eq!(server.downstreams.len(), 2);
+        assert!(server.valid_sv1_jobs.is_empty());
 
-        // sequence_counter starts at 1, so first share gets sequence 1
-        let seq_id = server.sequence_counter.fetch_add(1, Ordering::SeqCst);
-        assert_eq!(seq_id, 1);
-        assert_eq!(server.sequence_counter.load(Ordering::SeqCst), 2);
+        // in aggregated mode, the first channel opens the aggregated channel the other one shares
+        server.reopen_rotated_channels().await.unwrap();
+        let next_request = || match cm_receiver.try_recv().unwrap() {
+            (Mining::OpenExtendedMiningChannel(msg), _) => msg.request_id,
+            msg => panic!("Expected OpenExtendedMiningChannel, found: {msg:?}"),
+        };
+        let mut request_id = next_request();
+        assert!(cm_receiver.try_recv().is_err());
+
+        for channel_id in [1, 2] {
+            let downstream_id = server
+                .request_id_to_downstream_id
+                .get(&request_id)
+                .unwrap()
+                .0;
+            let downstream = server.downstreams.get(&downstream_id).unwrap().clone();
+            // the first one gets the extranonce1 it had, the other one a new one
+            let extranonce_prefix = match channel_id {
+                1 => extranonce1(downstream_id),
+                _ => vec![0xcc; 8],
+            };
+            let success = OpenExtendedMiningChannelSuccess {
+                request_id,
+                channel_id,
+                target: hash_rate_to_target(100.0, 5.0)
+                    .unwrap()
+                    .to_le_bytes()
+                    .into(),
+                extranonce_size: 4,
+                extranonce_prefix: extranonce_prefix.clone().try_into().unwrap(),
+                group_channel_id: 0,
+            };
+            upstream_sender
+                .send((Mining::OpenExtendedMiningChannelSuccess(success), None))
+                .await
+                .unwrap();
+            server.handle_upstream_message().await.unwrap();
+
+            if channel_id == 2 {
+                match sv1_server_receiver.try_recv().unwrap() {
+                    (2, Some(id), json_rpc::Message::Notification(n)) => {
+                        assert_eq!(id, downstream_id);
+                        assert_eq!(n.method, "mining.set_extranonce");
+                        assert_eq!(n.params, serde_json::json!(["cc".repeat(8), 4]));
+                    }
+                    msg => panic!("Expected mining.set_extranonce, found: {msg:?}"),
+                }
+            }
+            match sv1_server_receiver.try_recv().unwrap() {
+                (id, Some(to), json_rpc::Message::Notification(n)) => {
+                    assert_eq!((id, to), (channel_id, downstream_id));
+                    assert_eq!(n.method, "mining.set_difficulty");
+                }
+                msg => panic!("Expected mining.set_difficulty, found: {msg:?}"),
+            }
+            downstream.downstream_data.super_safe_lock(|d| {
+                assert_eq!(d.channel_id, Some(channel_id));
+                assert_eq!(hex::encode(&d.extranonce1), hex::encode(&extranonce_prefix));
+                assert!(!d.rotating_upstream);
+            });
+
+            // the channels left were closed along with the upstream, so no CloseChannel is sent
+            if channel_id == 1 {
+                request_id = next_request();
+            }
+            assert!(cm_receiver.try_recv().is_err());
+        }
     }
 }
@@ -0,0 +1,124 @@
+//! Vardiff state retained across SV1 reconnections.
+//!
+//! A miner reconnecting after a short outage would otherwise restart from the minimum difficulty
+//! and take several vardiff cycles to converge again. The state of a disconnected miner is kept
+//! for a while, keyed by its worker name and IP address, and handed back if the same worker
+//! authorizes again from the same address in time.
+use std::{
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use stratum_apps::{
+    custom_mutex::Mutex,
+    stratum_core::{bitcoin::Target, channels_sv2::VardiffState},
+    utils::types::Hashrate,
+};
+
+/// Vardiff state of a disconnected miner.
+#[derive(Debug)]
+pub struct RetainedVardiff {
+    pub vardiff: Arc<Mutex<VardiffState>>,
+    pub hashrate: Hashrate,
+    pub target: Target,
+    retained_at: Instant,
+}
+
+/// Vardiff states of disconnected miners, kept for a fixed time after they disconnect.
+#[derive(Debug)]
+pub struct VardiffRetention {
+    ttl: Duration,
+    retained: DashMap<(String, IpAddr), RetainedVardiff>,
+}
+
+impl VardiffRetention {
+    /// Creates a store keeping each state for `ttl` after its miner disconnected.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            retained: DashMap::new(),
+        }
+    }
+
+    /// Keeps the vardiff state of `worker_name` disconnecting from `ip`, replacing any older one.
+    ///
+    /// Expired states are dropped on the way, so the store does not grow with miners that never
+    /// come back.
+    pub fn retain(
+        &self,
+        worker_name: String,
+        ip: IpAddr,
+        vardiff: Arc<Mutex<VardiffState>>,
+        hashrate: Hashrate,
+        target: Target,
+    ) {
+        let now = Instant::now();
+        self.retained
+            .retain(|_, retained| now.duration_since(retained.retained_at) < self.ttl);
+        self.retained.insert(
+            (worker_name, ip),
+            RetainedVardiff {
+                vardiff,
+                hashrate,
+                target,
+                retained_at: now,
+            },
+        );
+    }
+
+    /// Takes the vardiff state of `worker_name` reconnecting from `ip`, if it disconnected less
+    /// than the retention time ago.
+    pub fn restore(&self, worker_name: &str, ip: IpAddr) -> Option<RetainedVardiff> {
+        let (_, retained) = self.retained.remove(&(worker_name.to_string(), ip))?;
+        (retained.retained_at.elapsed() < self.ttl).then_some(retained)
+    }
+
+    /// Drops every retained state.
+    pub fn clear(&self) {
+        self.retained.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn vardiff() -> Arc<Mutex<VardiffState>> {
+        Arc::new(Mutex::new(VardiffState::new().unwrap()))
+    }
+
+    #[test]
+    fn restores_state_of_same_worker_and_ip_once() {
+        let retention = VardiffRetention::new(Duration::from_secs(60));
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let target = Target::from_le_bytes([0x0f; 32]);
+        retention.retain("worker.1".to_string(), ip, vardiff(), 1e12, target);
+
+        assert!(retention
+            .restore("worker.1", IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+            .is_none());
+        assert!(retention.restore("worker.2", ip).is_none());
+        let restored = retention.restore("worker.1", ip).unwrap();
+        assert_eq!(restored.hashrate, 1e12);
+        assert_eq!(restored.target, target);
+        assert!(retention.restore("worker.1", ip).is_none());
+    }
+
+    #[test]
+    fn expired_state_is_not_restored() {
+        let retention = VardiffRetention::new(Duration::from_millis(10));
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        retention.retain(
+            "worker.1".to_string(),
+            ip,
+            vardiff(),
+            1e12,
+            Target::from_le_bytes([0x0f; 32]),
+        );
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(retention.restore("worker.1", ip).is_none());
+    }
+}
@@ -1,7 +1,8 @@
 //! SV1 client monitoring integration for Sv1Server
 //!
 //! This module implements the Sv1ClientsMonitoring trait on `Sv1Server`.
-use stratum_apps::monitoring::sv1::{Sv1ClientInfo, Sv1ClientsMonitoring};
+use std::sync::atomic::Ordering;
+use stratum_apps::monitoring::sv1::{LatencyHistogram, Sv1ClientInfo, Sv1ClientsMonitoring};
 
 use crate::{
     sv1::{downstream::downstream::Downstream, sv1_server::sv1_server::Sv1Server},
@@ -18,8 +19,11 @@ fn downstream_to_sv1_client_info(downstream: &Downstream) -> Option<Sv1ClientInf
             channel_id: dd.channel_id,
             authorized_worker_name: dd.authorized_worker_name.clone(),
             user_identity: dd.user_identity.clone(),
+            group_label: dd.group_label.clone(),
             target_hex: hex::encode(dd.target.to_be_bytes()),
             hashrate: if report_hashrate { dd.hashrate } else { None },
+            achieved_difficulty: dd.achieved_difficulty.difficulty(),
+            achieved_hashrate: dd.achieved_difficulty.hashrate(),
             extranonce1_hex: hex::encode(&dd.extranonce1),
             extranonce2_len: dd.extranonce2_len,
             version_rolling_mask: dd
@@ -30,6 +34,12 @@ fn downstream_to_sv1_client_info(downstream: &Downstream) -> Option<Sv1ClientInf
                 .version_rolling_min_bit
                 .as_ref()
                 .map(|bit| format!("{:08x}", bit.0)),
+            lagged_total: dd.lagged_total,
+            send_queue_depth: downstream
+                .downstream_channel_state
+                .downstream_sv1_sender
+                .len(),
+            slow_consumer: downstream.slow_consumer.is_slow_consumer(),
         })
         .ok()
 }
@@ -47,4 +57,28 @@ impl Sv1ClientsMonitoring for Sv1Server {
             .get(&client_id)
             .and_then(|downstream| downstream_to_sv1_client_info(downstream.value()))
     }
+
+    fn get_keepalive_time_capped_total(&self) -> u64 {
+        self.keepalive_time_capped.load(Ordering::Relaxed)
+    }
+
+    fn get_keepalive_shares_orphaned_total(&self) -> u64 {
+        self.keepalive_shares_orphaned.load(Ordering::Relaxed)
+    }
+
+    fn get_job_propagation_latency(&self) -> LatencyHistogram {
+        self.job_propagation.latency()
+    }
+
+    fn take_job_propagation_latencies(&self) -> Vec<f64> {
+        self.job_propagation.take_latencies()
+    }
+
+    fn get_job_propagation_alarms_total(&self) -> u64 {
+        self.job_propagation.alarms_total()
+    }
+
+    fn get_jobs_dropped_without_prevhash_total(&self) -> u64 {
+        self.pending_jobs.dropped_total()
+    }
 }
@@ -1,26 +1,46 @@
 use crate::{
+    config::UpdateChannelErrorAction,
     error::{self, TproxyError, TproxyErrorKind, TproxyResult},
     is_aggregated,
     status::{handle_error, Status, StatusSender},
-    sv2::channel_manager::channel::ChannelState,
-    utils::{ShutdownMessage, AGGREGATED_CHANNEL_ID},
+    sv2::channel_manager::{
+        channel::ChannelState,
+        extranonce_factory::{
+            TrackedExtranonceFactory, DEFAULT_EXTRANONCE_USAGE_WARNING_THRESHOLD,
+        },
+        upstream_router::UpstreamRouter,
+    },
+    utils::{
+        aggregated_channel_id, is_aggregated_channel_id, ShutdownMessage, AGGREGATED_CHANNEL_ID,
+    },
 };
 use async_channel::{Receiver, Sender};
 use dashmap::DashMap;
-use std::sync::Arc;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 use stratum_apps::{
     custom_mutex::Mutex,
+    monitoring::{FailoverEvent, FailoverReason},
     stratum_core::{
         channels_sv2::client::{extended::ExtendedChannel, group::GroupChannel},
         codec_sv2::StandardSv2Frame,
         extensions_sv2::{EXTENSION_TYPE_WORKER_HASHRATE_TRACKING, TLV_FIELD_TYPE_USER_IDENTITY},
         framing_sv2,
         handlers_sv2::{HandleExtensionsFromServerAsync, HandleMiningMessagesFromServerAsync},
-        mining_sv2::{ExtendedExtranonce, OpenExtendedMiningChannelSuccess},
-        parsers_sv2::{AnyMessage, Mining, Tlv, TlvList},
+        mining_sv2::{
+            CloseChannel, OpenExtendedMiningChannel, OpenExtendedMiningChannelSuccess,
+            UpdateChannel,
+        },
+        parsers_sv2::{AnyMessage, CommonMessages, Mining, Tlv, TlvList},
     },
     task_manager::TaskManager,
     utils::{
+        log_throttle::LogThrottle,
         protocol_message_type::{protocol_message_type, MessageType},
         types::{ChannelId, DownstreamId, Hashrate, Sv2Frame},
     },
@@ -33,6 +53,57 @@ use tracing::{debug, error, info, warn};
 /// by allocating unique extranonce prefixes to each downstream.
 const AGGREGATED_MODE_TRANSLATOR_SEARCH_SPACE_BYTES: usize = 4;
 
+// Extranonce size requested upstream for a downstream extranonce of `downstream_extranonce_size`.
+// In aggregated mode, extra bytes are added for translator search space allocation.
+fn upstream_min_extranonce_size(downstream_extranonce_size: usize) -> usize {
+    if is_aggregated() {
+        downstream_extranonce_size + AGGREGATED_MODE_TRANSLATOR_SEARCH_SPACE_BYTES
+    } else {
+        downstream_extranonce_size
+    }
+}
+
+/// How often upstream channels are checked for stale jobs when the watchdog is enabled.
+const JOB_STALENESS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `UpdateChannelError` codes the translator can correct by re-sending the update.
+const INVALID_NOMINAL_HASHRATE: &str = "invalid-nominal-hashrate";
+const MAX_TARGET_OUT_OF_RANGE: &str = "max-target-out-of-range";
+const REQUESTED_MAX_TARGET_OUT_OF_RANGE: &str = "requested-max-target-out-of-range";
+
+/// Last `UpdateChannel` sent upstream for a channel, kept to correct it if the upstream rejects
+/// it.
+#[derive(Debug, Clone)]
+pub struct SentChannelUpdate {
+    pub update: UpdateChannel<'static>,
+    /// Nominal hashrate of the channel before the update was applied.
+    pub previous_nominal_hashrate: Hashrate,
+    /// Whether the update is itself the correction of a rejected one, which is not retried.
+    pub is_correction: bool,
+}
+
+/// Upstream channels removed in response to a `CloseChannel` message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClosedChannels {
+    /// A group channel was closed, together with every extended channel it contained.
+    Group {
+        group_channel_id: ChannelId,
+        channel_ids: Vec<ChannelId>,
+    },
+    /// A single extended channel was closed.
+    Extended(ChannelId),
+}
+
+impl ClosedChannels {
+    /// Returns the IDs of the extended channels that were closed.
+    pub fn channel_ids(&self) -> &[ChannelId] {
+        match self {
+            ClosedChannels::Group { channel_ids, .. } => channel_ids,
+            ClosedChannels::Extended(channel_id) => std::slice::from_ref(channel_id),
+        }
+    }
+}
+
 /// Manages SV2 channels and message routing between upstream and downstream.
 ///
 /// The ChannelManager serves as the central component that bridges SV2 upstream
@@ -44,7 +115,8 @@ const AGGREGATED_MODE_TRANSLATOR_SEARCH_SPACE_BYTES: usize = 4;
 /// - Job distribution to downstream connections
 ///
 /// The manager supports two operational modes:
-/// - Aggregated: All downstream connections share a single extended channel
+/// - Aggregated: Downstream connections share an extended channel, another one being opened once
+///   `max_downstreams_per_aggregated_channel` share it
 /// - Non-aggregated: Each downstream connection gets its own extended channel
 ///
 /// This design allows the translator to efficiently manage multiple mining
@@ -59,8 +131,13 @@ pub struct ChannelManager {
     /// Store pending channel info by downstream_id: (user_identity, hashrate,
     /// downstream_extranonce_len)
     pub pending_channels: Arc<DashMap<DownstreamId, (String, Hashrate, usize)>>,
+    /// `OpenExtendedMiningChannel` last sent upstream for each pending channel, re-sent with a
+    /// smaller extranonce size when the upstream rejects the requested one.
+    pub pending_open_channel_requests:
+        Arc<DashMap<DownstreamId, OpenExtendedMiningChannel<'static>>>,
     /// Map of active extended channels by channel ID.
-    /// In aggregated mode, the shared upstream channel is stored under AGGREGATED_CHANNEL_ID.
+    /// In aggregated mode, the shared upstream channels are stored under AGGREGATED_CHANNEL_ID
+    /// and the IDs below it, see `aggregated_channel_id`.
     /// In non-aggregated mode, each downstream has its own channel with its assigned ID.
     pub extended_channels: Arc<DashMap<ChannelId, ExtendedChannel<'static>>>,
     /// Map of active group channels by group channel ID
@@ -72,7 +149,47 @@ pub struct ChannelManager {
     /// Extensions that have been successfully negotiated with the upstream server
     pub negotiated_extensions: Arc<Mutex<Vec<u16>>>,
     /// Extranonce factories containing per channel extranonces
-    pub extranonce_factories: Arc<DashMap<ChannelId, ExtendedExtranonce>>,
+    pub extranonce_factories: Arc<DashMap<ChannelId, TrackedExtranonceFactory>>,
+    /// Fraction of the extranonce prefix space of a channel above which a warning is logged.
+    pub(crate) extranonce_usage_warning_threshold: f64,
+    /// Time of the last `NewExtendedMiningJob`/`SetNewPrevHash` per upstream channel ID,
+    /// watched by the job-staleness watchdog.
+    pub last_job_activity: Arc<DashMap<ChannelId, Instant>>,
+    /// Number of times the job-staleness watchdog found a stale upstream channel.
+    pub stale_job_events: Arc<AtomicU64>,
+    /// Label of the upstream currently connected, reported as the `upstream` monitoring label.
+    pub active_upstream: Arc<Mutex<Option<String>>>,
+    /// Most recent fallback to another upstream, reported by monitoring.
+    pub last_failover: Arc<Mutex<Option<FailoverEvent>>>,
+    /// Last `UpdateChannel` sent upstream, by upstream channel ID.
+    pub sent_channel_updates: Arc<DashMap<ChannelId, SentChannelUpdate>>,
+    /// Staleness timeout after which an upstream channel without new jobs is reported.
+    job_staleness_timeout: Option<Duration>,
+    /// Whether a stale upstream channel triggers fallback instead of only being logged.
+    job_staleness_fallback: bool,
+    /// Smallest downstream extranonce size to retry with when the upstream rejects the
+    /// requested one as too large.
+    downstream_extranonce2_size_floor: usize,
+    /// What to do when the upstream rejects an `UpdateChannel`.
+    update_channel_error_action: UpdateChannelErrorAction,
+    /// Largest accepted skew between upstream job times and the local clock, `None` disables the
+    /// check.
+    max_clock_skew: Option<Duration>,
+    /// Whether a clock skew above `max_clock_skew` shuts the translator down.
+    refuse_clock_skew: bool,
+    /// Whether the clock skew was already checked against the current upstream.
+    pub clock_skew_checked: Arc<AtomicBool>,
+    /// Routed upstreams the channels of some downstreams are opened on instead of the primary
+    /// upstream.
+    pub upstream_router: Arc<UpstreamRouter>,
+    /// Throttle of the warnings logged for every rejected share.
+    pub log_throttle: Arc<LogThrottle>,
+    /// In aggregated mode, the aggregated channel each downstream channel was opened on, shared
+    /// with the SV1 server, which drops the channels no downstream uses anymore.
+    pub aggregated_channel_ids: Arc<DashMap<ChannelId, ChannelId>>,
+    /// Number of downstream channels sharing an aggregated channel above which another one is
+    /// opened, `None` shares a single one.
+    max_downstreams_per_aggregated_channel: Option<usize>,
 }
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -89,6 +206,11 @@ impl ChannelManager {
     ///   by server)
     /// * `required_extensions` - Extensions that the translator requires (must be supported by
     ///   server)
+    /// * `job_staleness_timeout` - Timeout of the job-staleness watchdog, `None` disables it
+    /// * `job_staleness_fallback` - Whether a stale upstream channel triggers fallback
+    /// * `downstream_extranonce2_size_floor` - Smallest downstream extranonce size to retry with
+    ///   when the upstream rejects the requested one as too large
+    /// * `update_channel_error_action` - What to do when the upstream rejects an `UpdateChannel`
     ///
     /// # Returns
     /// A new ChannelManager instance ready to handle message routing
@@ -101,6 +223,10 @@ impl ChannelManager {
         status_sender: Sender<Status>,
         supported_extensions: Vec<u16>,
         required_extensions: Vec<u16>,
+        job_staleness_timeout: Option<Duration>,
+        job_staleness_fallback: bool,
+        downstream_extranonce2_size_floor: usize,
+        update_channel_error_action: UpdateChannelErrorAction,
     ) -> Self {
         let channel_state = ChannelState::new(
             upstream_sender,
@@ -115,14 +241,74 @@ impl ChannelManager {
             supported_extensions,
             required_extensions,
             pending_channels: Arc::new(DashMap::new()),
+            pending_open_channel_requests: Arc::new(DashMap::new()),
             extended_channels: Arc::new(DashMap::new()),
             group_channels: Arc::new(DashMap::new()),
             share_sequence_counters: Arc::new(DashMap::new()),
             negotiated_extensions: Arc::new(Mutex::new(Vec::new())),
             extranonce_factories: Arc::new(DashMap::new()),
+            extranonce_usage_warning_threshold: DEFAULT_EXTRANONCE_USAGE_WARNING_THRESHOLD,
+            last_job_activity: Arc::new(DashMap::new()),
+            stale_job_events: Arc::new(AtomicU64::new(0)),
+            active_upstream: Arc::new(Mutex::new(None)),
+            last_failover: Arc::new(Mutex::new(None)),
+            sent_channel_updates: Arc::new(DashMap::new()),
+            job_staleness_timeout,
+            job_staleness_fallback,
+            downstream_extranonce2_size_floor,
+            update_channel_error_action,
+            max_clock_skew: None,
+            refuse_clock_skew: false,
+            clock_skew_checked: Arc::new(AtomicBool::new(false)),
+            upstream_router: Arc::new(UpstreamRouter::default()),
+            log_throttle: Arc::new(LogThrottle::new(None)),
+            aggregated_channel_ids: Arc::new(DashMap::new()),
+            max_downstreams_per_aggregated_channel: None,
         }
     }
 
+    /// Enables the check of the first job time of each upstream against the local clock.
+    ///
+    /// A skew above `max_clock_skew` is logged, or shuts the translator down if `refuse` is set.
+    pub fn with_clock_skew_check(mut self, max_clock_skew: Duration, refuse: bool) -> Self {
+        self.max_clock_skew = Some(max_clock_skew);
+        self.refuse_clock_skew = refuse;
+        self
+    }
+
+    /// Sets the fraction of the extranonce prefix space of a channel above which a warning is
+    /// logged.
+    pub fn with_extranonce_usage_warning_threshold(mut self, threshold: f64) -> Self {
+        self.extranonce_usage_warning_threshold = threshold;
+        self
+    }
+
+    /// Sets the router sending the channels of routed downstreams to their upstream, shared with
+    /// the SV1 server.
+    pub fn with_upstream_router(mut self, upstream_router: Arc<UpstreamRouter>) -> Self {
+        self.upstream_router = upstream_router;
+        self
+    }
+
+    /// Throttles the warnings logged for every rejected share with `log_throttle`.
+    pub fn with_log_throttle(mut self, log_throttle: Arc<LogThrottle>) -> Self {
+        self.log_throttle = log_throttle;
+        self
+    }
+
+    /// Records the aggregated channel each downstream channel is opened on in
+    /// `aggregated_channel_ids`, shared with the SV1 server, and opens another aggregated
+    /// channel upstream once `max_downstreams` downstream channels share each of the open ones.
+    pub fn with_aggregated_channels(
+        mut self,
+        aggregated_channel_ids: Arc<DashMap<ChannelId, ChannelId>>,
+        max_downstreams: Option<usize>,
+    ) -> Self {
+        self.aggregated_channel_ids = aggregated_channel_ids;
+        self.max_downstreams_per_aggregated_channel = max_downstreams;
+        self
+    }
+
     /// Spawns and runs the main channel manager task loop.
     ///
     /// This method creates an async task that handles all message routing for the
@@ -149,6 +335,7 @@ impl ChannelManager {
     ) {
         let mut shutdown_rx = notify_shutdown.subscribe();
         let status_sender = StatusSender::ChannelManager(status_sender);
+        let mut staleness_ticker = tokio::time::interval(JOB_STALENESS_CHECK_INTERVAL);
         task_manager.spawn(async move {
             loop {
                 tokio::select! {
@@ -158,13 +345,21 @@ impl ChannelManager {
                                 info!("ChannelManager: received shutdown signal.");
                                 break;
                             }
-                            Ok(ShutdownMessage::UpstreamFallback{tx}) => {
+                            Ok(ShutdownMessage::UpstreamFallback{tx}
+                                | ShutdownMessage::UpstreamRotation{tx}) => {
                                 self.pending_channels.clear();
+                                self.pending_open_channel_requests.clear();
                                 self.extended_channels.clear();
+                                self.aggregated_channel_ids.clear();
                                 self.group_channels.clear();
                                 self.share_sequence_counters.clear();
                                 self.negotiated_extensions.super_safe_lock(|data| data.clear());
                                 self.extranonce_factories.clear();
+                                self.last_job_activity.clear();
+                                self.active_upstream.super_safe_lock(|data| *data = None);
+                                self.sent_channel_updates.clear();
+                                self.clock_skew_checked.store(false, Ordering::Relaxed);
+                                self.upstream_router.clear();
                                 drop(tx);
                             }
                             Ok(_) => {
@@ -195,6 +390,13 @@ impl ChannelManager {
                             }
                         }
                     },
+                    _ = staleness_ticker.tick(), if self.job_staleness_timeout.is_some() => {
+                        if let Err(e) = self.check_job_staleness() {
+                            if handle_error(&status_sender, e).await {
+                                break;
+                            }
+                        }
+                    },
                     else => {
                         warn!("All channel manager message streams closed. Exiting...");
                         break;
@@ -246,6 +448,27 @@ impl ChannelManager {
                     .handle_extensions_message_frame_from_server(None, header, sv2_frame.payload())
                     .await?;
             }
+            // The upstream forwards a `ChannelEndpointChanged`, after which the shares of the
+            // channel are numbered from scratch.
+            MessageType::Common => {
+                match CommonMessages::try_from((header.msg_type(), sv2_frame.payload()))
+                    .map_err(TproxyError::fallback)?
+                {
+                    CommonMessages::ChannelEndpointChanged(m) => {
+                        info!(
+                            "Channel {} changed its endpoint, resetting its share sequence number",
+                            m.channel_id
+                        );
+                        self.share_sequence_counters.remove(&m.channel_id);
+                    }
+                    _ => {
+                        return Err(TproxyError::fallback(TproxyErrorKind::UnexpectedMessage(
+                            header.ext_type(),
+                            header.msg_type(),
+                        )));
+                    }
+                }
+            }
             _ => {
                 error!(
                     extension_type = header.ext_type(),
@@ -295,38 +518,43 @@ impl ChannelManager {
                 let min_extranonce_size = m.min_extranonce_size as usize;
 
                 if is_aggregated() {
-                    if self.extended_channels.contains_key(&AGGREGATED_CHANNEL_ID) {
-                        // We already have the unique channel open and so we create a new
-                        // extranonce prefix and we send the
+                    if let Some(aggregated_channel_id) = self.aggregated_channel_with_room() {
+                        // We already have a shared channel open with room left and so we create
+                        // a new extranonce prefix and we send the
                         // OpenExtendedMiningChannelSuccess message directly to the sv1
                         // server
                         let target = self
                             .extended_channels
-                            .get(&AGGREGATED_CHANNEL_ID)
+                            .get(&aggregated_channel_id)
                             .map(|ch| *ch.get_target())
                             .unwrap();
+                        // The shared channel may have settled on a smaller downstream extranonce
+                        // than requested, after the upstream rejected the configured size
+                        let range2_len = self
+                            .extranonce_factories
+                            .get(&aggregated_channel_id)
+                            .unwrap()
+                            .get_range2_len();
+                        if range2_len < open_channel_msg.min_extranonce_size as usize
+                            && range2_len >= self.downstream_extranonce2_size_floor
+                        {
+                            open_channel_msg.min_extranonce_size = range2_len as u16;
+                        }
                         let new_extranonce_prefix = self
                             .extranonce_factories
-                            .get_mut(&AGGREGATED_CHANNEL_ID)
+                            .get_mut(&aggregated_channel_id)
                             .unwrap()
                             .next_prefix_extended(open_channel_msg.min_extranonce_size.into())
                             .ok();
                         let new_extranonce_size = self
                             .extranonce_factories
-                            .get_mut(&AGGREGATED_CHANNEL_ID)
+                            .get_mut(&aggregated_channel_id)
                             .unwrap()
                             .get_range2_len();
                         if let Some(new_extranonce_prefix) = new_extranonce_prefix {
                             if new_extranonce_size >= open_channel_msg.min_extranonce_size as usize
                             {
-                                // Find max channel ID, excluding AGGREGATED_CHANNEL_ID (u32::MAX)
-                                // which would cause overflow when adding 1
-                                let channel_id = self
-                                    .extended_channels
-                                    .iter()
-                                    .filter(|x| *x.key() != AGGREGATED_CHANNEL_ID)
-                                    .fold(0, |acc, x| std::cmp::max(acc, *x.key()));
-                                let next_channel_id = channel_id + 1;
+                                let next_channel_id = self.next_downstream_channel_id();
                                 let new_downstream_extended_channel = ExtendedChannel::new(
                                     next_channel_id,
                                     user_identity.clone(),
@@ -342,6 +570,8 @@ impl ChannelManager {
                                 );
                                 self.extended_channels
                                     .insert(next_channel_id, new_downstream_extended_channel);
+                                self.aggregated_channel_ids
+                                    .insert(next_channel_id, aggregated_channel_id);
                                 let success_message = Mining::OpenExtendedMiningChannelSuccess(
                                     OpenExtendedMiningChannelSuccess {
                                         request_id: open_channel_msg.request_id,
@@ -372,7 +602,7 @@ impl ChannelManager {
                                     // to release the borrow before accessing other channels
                                     let (last_active_job, future_jobs, last_chain_tip) = {
                                         let aggregated_channel =
-                                            self.extended_channels.get(&AGGREGATED_CHANNEL_ID)?;
+                                            self.extended_channels.get(&aggregated_channel_id)?;
                                         (
                                             aggregated_channel
                                                 .get_active_job()
@@ -412,7 +642,7 @@ impl ChannelManager {
                                     // set the channel id to the aggregated channel id
                                     // before sending the message to the Sv1Server
                                     last_active_job.map(|mut job| {
-                                        job.channel_id = AGGREGATED_CHANNEL_ID;
+                                        job.channel_id = aggregated_channel_id;
                                         job
                                     })
                                 };
@@ -434,8 +664,14 @@ impl ChannelManager {
                         }
                         return Ok(());
                     } else {
-                        // We don't have the unique channel open yet and so we send the
+                        // We don't have a shared channel with room left yet and so we send the
                         // OpenExtendedMiningChannel message to the upstream
+                        if !self.aggregated_channels().is_empty() {
+                            info!(
+                                "All aggregated channels are shared by {} downstreams, opening another one",
+                                self.max_downstreams_per_aggregated_channel.unwrap_or_default()
+                            );
+                        }
                         // Before doing that we need to truncate the user identity at the
                         // first dot and append .translator-proxy
                         // Truncate at the first dot and append .translator-proxy
@@ -449,15 +685,9 @@ impl ChannelManager {
                             user_identity.as_bytes().to_vec().try_into().unwrap();
                     }
                 }
-                // In aggregated mode, add extra bytes for translator search space allocation
-                let upstream_min_extranonce_size = if is_aggregated() {
-                    min_extranonce_size + AGGREGATED_MODE_TRANSLATOR_SEARCH_SPACE_BYTES
-                } else {
-                    min_extranonce_size
-                };
-
                 // Update the message with the adjusted extranonce size for upstream
-                open_channel_msg.min_extranonce_size = upstream_min_extranonce_size as u16;
+                open_channel_msg.min_extranonce_size =
+                    upstream_min_extranonce_size(min_extranonce_size) as u16;
 
                 // Store the user identity, hashrate, and original downstream extranonce size
                 self.pending_channels.insert(
@@ -465,23 +695,8 @@ impl ChannelManager {
                     (user_identity, hashrate, min_extranonce_size),
                 );
 
-                info!(
-                    "Sending OpenExtendedMiningChannel message to upstream: {:?}",
-                    open_channel_msg
-                );
-
-                let message = Mining::OpenExtendedMiningChannel(open_channel_msg);
-                let sv2_frame: Sv2Frame = AnyMessage::Mining(message)
-                    .try_into()
-                    .map_err(TproxyError::shutdown)?;
-                self.channel_state
-                    .upstream_sender
-                    .send(sv2_frame)
-                    .await
-                    .map_err(|e| {
-                        error!("Failed to send open channel message to upstream: {:?}", e);
-                        TproxyError::fallback(TproxyErrorKind::ChannelErrorSender)
-                    })?;
+                self.send_open_channel_to_upstream(open_channel_msg.into_static())
+                    .await?;
             }
             Mining::SubmitSharesExtended(mut m) => {
                 let value =
@@ -499,12 +714,13 @@ impl ChannelManager {
                         m.channel_id, m.sequence_number
                     );
 
+                    let aggregated_channel_id = self.aggregated_channel_of(m.channel_id);
                     if is_aggregated()
-                        && self.extended_channels.contains_key(&AGGREGATED_CHANNEL_ID)
+                        && self.extended_channels.contains_key(&aggregated_channel_id)
                     {
                         let upstream_extended_channel_id = self
                             .extended_channels
-                            .get(&AGGREGATED_CHANNEL_ID)
+                            .get(&aggregated_channel_id)
                             .map(|ch| ch.get_channel_id())
                             .unwrap();
 
@@ -520,7 +736,7 @@ impl ChannelManager {
                         // Get the length of the upstream prefix (range0)
                         let range0_len = self
                             .extranonce_factories
-                            .get(&AGGREGATED_CHANNEL_ID)
+                            .get(&aggregated_channel_id)
                             .unwrap()
                             .get_range0_len();
                         if let Some(downstream_extranonce_prefix) = downstream_extranonce_prefix {
@@ -572,10 +788,16 @@ impl ChannelManager {
                     }
 
                     // Send the share upstream (common for both aggregated and non-aggregated modes)
-                    let contains_type_in_negotiated_extension =
-                        self.negotiated_extensions.super_safe_lock(|data| {
-                            data.contains(&EXTENSION_TYPE_WORKER_HASHRATE_TRACKING)
+                    // Routed channels use the extensions negotiated with their own upstream
+                    let contains_type_in_negotiated_extension = self
+                        .upstream_router
+                        .has_routed_extension(m.channel_id, EXTENSION_TYPE_WORKER_HASHRATE_TRACKING)
+                        .unwrap_or_else(|| {
+                            self.negotiated_extensions.super_safe_lock(|data| {
+                                data.contains(&EXTENSION_TYPE_WORKER_HASHRATE_TRACKING)
+                            })
                         });
+                    let routed_upstream = self.upstream_router.route_channel(&mut m.channel_id);
 
                     // Check if we should try to include TLV fields
                     let should_send_with_tlv =
@@ -620,10 +842,12 @@ impl ChannelManager {
                                     );
                                     TproxyError::shutdown(framing_sv2::Error::ExpectedSv2Frame)
                                 })?;
-                            self.channel_state.upstream_sender.send(sv2_frame).await.map_err(|e| {
-                                error!("Failed to send submit shares extended message to upstream: {:?}", e);
-                                TproxyError::fallback(TproxyErrorKind::ChannelErrorSender)
-                            })?;
+                            self.send_to_upstream(
+                                sv2_frame,
+                                routed_upstream.clone(),
+                                "submit shares extended",
+                            )
+                            .await?;
                             sent = true;
                         }
                     }
@@ -633,82 +857,89 @@ impl ChannelManager {
                         let sv2_frame: Sv2Frame = AnyMessage::Mining(message)
                             .try_into()
                             .map_err(TproxyError::shutdown)?;
-                        self.channel_state.upstream_sender.send(sv2_frame).await.map_err(|e| {
-                            error!("Failed to send submit shares extended message to upstream: {:?}", e);
-                            TproxyError::fallback(TproxyErrorKind::ChannelErrorSender)
-                        })?;
+                        self.send_to_upstream(sv2_frame, routed_upstream, "submit shares extended")
+                            .await?;
                     }
                 }
             }
             Mining::UpdateChannel(mut m) => {
                 debug!("Received UpdateChannel from SV1Server: {:?}", m);
 
+                let mut previous_nominal_hashrate = m.nominal_hash_rate;
                 if is_aggregated() {
                     // Update the aggregated channel's nominal hashrate so
                     // that monitoring reports a value consistent with the
                     // downstream vardiff estimate.
-                    if let Some(mut aggregated_extended_channel) =
-                        self.extended_channels.get_mut(&AGGREGATED_CHANNEL_ID)
+                    if let Some(mut aggregated_extended_channel) = self
+                        .extended_channels
+                        .get_mut(&self.aggregated_channel_of(m.channel_id))
                     {
+                        previous_nominal_hashrate =
+                            aggregated_extended_channel.get_nominal_hashrate();
                         aggregated_extended_channel.set_nominal_hashrate(m.nominal_hash_rate);
                         m.channel_id = aggregated_extended_channel.get_channel_id();
                     }
                 } else {
                     // Non-aggregated: update the specific channel's nominal hashrate
                     if let Some(mut channel) = self.extended_channels.get_mut(&m.channel_id) {
+                        previous_nominal_hashrate = channel.get_nominal_hashrate();
                         channel.set_nominal_hashrate(m.nominal_hash_rate);
                     }
                 }
 
-                info!(
-                    "Sending UpdateChannel message to upstream for channel_id: {:?}",
-                    m.channel_id
-                );
-                // Forward UpdateChannel message to upstream
-                let message = Mining::UpdateChannel(m);
-                let sv2_frame: Sv2Frame = AnyMessage::Mining(message)
-                    .try_into()
-                    .map_err(TproxyError::shutdown)?;
-
-                self.channel_state
-                    .upstream_sender
-                    .send(sv2_frame)
-                    .await
-                    .map_err(|e| {
-                        error!("Failed to send UpdateChannel message to upstream: {:?}", e);
-                        TproxyError::fallback(TproxyErrorKind::ChannelErrorSender)
-                    })?;
+                self.send_update_channel_to_upstream(SentChannelUpdate {
+                    update: m,
+                    previous_nominal_hashrate,
+                    is_correction: false,
+                })
+                .await?;
             }
             Mining::CloseChannel(m) => {
                 debug!("Received CloseChannel from Sv1Server: {m}");
 
-                // Remove from extended_channels
-                if self.extended_channels.remove(&m.channel_id).is_some() {
-                    debug!("Removed channel {} from extended_channels before sending CloseChannel to upstream", m.channel_id);
+                let channel_ids = if is_aggregated() && m.channel_id == AGGREGATED_CHANNEL_ID {
+                    let upstream_channel_ids = self.remove_aggregated_channels();
+                    if upstream_channel_ids.is_empty() {
+                        warn!("Attempted to close the aggregated channel but it is not open");
+                        return Ok(());
+                    }
+                    for upstream_channel_id in &upstream_channel_ids {
+                        info!("Closing aggregated channel {}", upstream_channel_id);
+                    }
+                    upstream_channel_ids
                 } else {
-                    warn!("Attempted to remove channel {} from extended_channels but it was not found", m.channel_id);
-                }
-                // Remove from any group channels that contain it
-                for mut group_channel in self.group_channels.iter_mut() {
-                    if group_channel.get_channel_ids().contains(&m.channel_id) {
-                        group_channel.remove_channel_id(m.channel_id);
-                        debug!("Removed channel {} from group channel before sending CloseChannel to upstream", m.channel_id);
+                    if self.extended_channels.remove(&m.channel_id).is_some() {
+                        self.sent_channel_updates.remove(&m.channel_id);
+                        debug!("Removed channel {} from extended_channels before sending CloseChannel to upstream", m.channel_id);
+                    } else {
+                        warn!("Attempted to remove channel {} from extended_channels but it was not found", m.channel_id);
                     }
-                }
+                    vec![m.channel_id]
+                };
+                for channel_id in channel_ids {
+                    // Remove from any group channels that contain it
+                    for mut group_channel in self.group_channels.iter_mut() {
+                        if group_channel.get_channel_ids().contains(&channel_id) {
+                            group_channel.remove_channel_id(channel_id);
+                            debug!("Removed channel {} from group channel before sending CloseChannel to upstream", channel_id);
+                        }
+                    }
+
+                    let mut close_channel = CloseChannel {
+                        channel_id,
+                        reason_code: m.reason_code.clone(),
+                    };
+                    let routed_upstream = self
+                        .upstream_router
+                        .release_channel(&mut close_channel.channel_id);
+                    let message = Mining::CloseChannel(close_channel);
+                    let sv2_frame: Sv2Frame = AnyMessage::Mining(message)
+                        .try_into()
+                        .map_err(TproxyError::shutdown)?;
 
-                let message = Mining::CloseChannel(m);
-                let sv2_frame: Sv2Frame = AnyMessage::Mining(message)
-                    .try_into()
-                    .map_err(TproxyError::shutdown)?;
-
-                self.channel_state
-                    .upstream_sender
-                    .send(sv2_frame)
-                    .await
-                    .map_err(|e| {
-                        error!("Failed to send CloseChannel message to upstream: {:?}", e);
-                        TproxyError::fallback(TproxyErrorKind::ChannelErrorSender)
-                    })?;
+                    self.send_to_upstream(sv2_frame, routed_upstream, "CloseChannel")
+                        .await?;
+                }
             }
             _ => {
                 warn!("Unhandled downstream message: {:?}", message);
@@ -718,6 +949,311 @@ impl ChannelManager {
         Ok(())
     }
 
+    /// Removes the aggregated upstream channels, along with the channels of the SV1 miners that
+    /// were on them, so the next miner opens a new one.
+    ///
+    /// Returns the upstream IDs of the removed channels, empty if none was open.
+    fn remove_aggregated_channels(&self) -> Vec<ChannelId> {
+        let mut upstream_channel_ids = Vec::new();
+        for aggregated_channel_id in self.aggregated_channels() {
+            let Some((_, aggregated_channel)) =
+                self.extended_channels.remove(&aggregated_channel_id)
+            else {
+                continue;
+            };
+            let upstream_channel_id = aggregated_channel.get_channel_id();
+            self.extranonce_factories.remove(&aggregated_channel_id);
+            self.last_job_activity.remove(&upstream_channel_id);
+            self.sent_channel_updates.remove(&upstream_channel_id);
+            upstream_channel_ids.push(upstream_channel_id);
+        }
+        if !upstream_channel_ids.is_empty() {
+            self.extended_channels.clear();
+            self.aggregated_channel_ids.clear();
+        }
+        upstream_channel_ids
+    }
+
+    /// Returns the IDs the open aggregated channels are stored under, in the order they were
+    /// opened.
+    pub fn aggregated_channels(&self) -> Vec<ChannelId> {
+        let mut aggregated_channels: Vec<ChannelId> = self
+            .extended_channels
+            .iter()
+            .map(|channel| *channel.key())
+            .filter(|channel_id| is_aggregated_channel_id(*channel_id))
+            .collect();
+        aggregated_channels.sort_unstable_by(|a, b| b.cmp(a));
+        aggregated_channels
+    }
+
+    /// Returns the ID of the aggregated channel the downstream channel `channel_id` was opened
+    /// on, `channel_id` itself if it is an aggregated channel.
+    ///
+    /// Downstream channels of no known aggregated channel fall back to the first one.
+    pub fn aggregated_channel_of(&self, channel_id: ChannelId) -> ChannelId {
+        if is_aggregated_channel_id(channel_id) {
+            return channel_id;
+        }
+        self.aggregated_channel_ids
+            .get(&channel_id)
+            .map_or(AGGREGATED_CHANNEL_ID, |aggregated_channel_id| {
+                *aggregated_channel_id
+            })
+    }
+
+    /// Returns the ID of the aggregated channel the upstream opened as `upstream_channel_id`.
+    pub fn aggregated_channel_of_upstream(
+        &self,
+        upstream_channel_id: ChannelId,
+    ) -> Option<ChannelId> {
+        self.aggregated_channels()
+            .into_iter()
+            .find(|aggregated_channel_id| {
+                self.extended_channels
+                    .get(aggregated_channel_id)
+                    .is_some_and(|channel| channel.get_channel_id() == upstream_channel_id)
+            })
+    }
+
+    /// Returns the downstream channels opened on the aggregated channel `aggregated_channel_id`.
+    pub fn aggregated_channel_members(&self, aggregated_channel_id: ChannelId) -> Vec<ChannelId> {
+        self.aggregated_channel_ids
+            .iter()
+            .filter(|entry| *entry.value() == aggregated_channel_id)
+            .map(|entry| *entry.key())
+            .collect()
+    }
+
+    /// Returns the ID the next aggregated channel opened upstream is stored under.
+    pub fn next_aggregated_channel_id(&self) -> ChannelId {
+        aggregated_channel_id(self.aggregated_channels().len() as u32)
+    }
+
+    /// Returns the lowest channel ID above all the downstream channels, which excludes the
+    /// aggregated channels stored at the top of the ID space.
+    pub fn next_downstream_channel_id(&self) -> ChannelId {
+        self.extended_channels
+            .iter()
+            .map(|channel| *channel.key())
+            .filter(|channel_id| !is_aggregated_channel_id(*channel_id))
+            .fold(0, std::cmp::max)
+            + 1
+    }
+
+    /// Returns the aggregated channels a message the upstream sent to `channel_id` is for: the one
+    /// it opened as `channel_id`, or all of them if `channel_id` is their group channel.
+    ///
+    /// Fails over if no aggregated channel is open, or if they belong to no group channel.
+    pub fn addressed_aggregated_channels(
+        &self,
+        channel_id: ChannelId,
+    ) -> TproxyResult<Vec<ChannelId>, error::ChannelManager> {
+        let aggregated_channels = self.aggregated_channels();
+        if aggregated_channels.is_empty() {
+            return Err(TproxyError::fallback(TproxyErrorKind::ChannelNotFound));
+        }
+        // here, we are assuming that since we are in aggregated mode, there should be only one
+        // single group channel and the aggregated channels must belong to it
+        let Some(group_channel_id) = self
+            .group_channels
+            .iter()
+            .next()
+            .map(|group_channel| group_channel.get_group_channel_id())
+        else {
+            error!("Aggregated channel does not belong to any group channel");
+            return Err(TproxyError::fallback(TproxyErrorKind::ChannelNotFound));
+        };
+        if group_channel_id == channel_id {
+            return Ok(aggregated_channels);
+        }
+        Ok(self
+            .aggregated_channel_of_upstream(channel_id)
+            .into_iter()
+            .collect())
+    }
+
+    // Returns the first aggregated channel shared by fewer than
+    // `max_downstreams_per_aggregated_channel` downstream channels.
+    fn aggregated_channel_with_room(&self) -> Option<ChannelId> {
+        self.aggregated_channels()
+            .into_iter()
+            .find(|aggregated_channel_id| {
+                self.max_downstreams_per_aggregated_channel
+                    .is_none_or(|max_downstreams| {
+                        self.aggregated_channel_members(*aggregated_channel_id)
+                            .len()
+                            < max_downstreams
+                    })
+            })
+    }
+
+    /// Removes the upstream channels affected by a `CloseChannel` addressed to `channel_id`.
+    ///
+    /// Closing a group channel removes the group and exactly its member extended channels.
+    /// Closing an extended channel removes only that channel, including its group membership.
+    /// Returns `None` if `channel_id` is neither a known group nor extended channel.
+    pub fn remove_closed_channels(&self, channel_id: ChannelId) -> Option<ClosedChannels> {
+        if let Some((_, group_channel)) = self.group_channels.remove(&channel_id) {
+            let mut channel_ids: Vec<ChannelId> =
+                group_channel.get_channel_ids().iter().copied().collect();
+            channel_ids.sort_unstable();
+            for member_channel_id in &channel_ids {
+                self.extended_channels.remove(member_channel_id);
+                self.last_job_activity.remove(member_channel_id);
+                self.sent_channel_updates.remove(member_channel_id);
+            }
+            return Some(ClosedChannels::Group {
+                group_channel_id: channel_id,
+                channel_ids,
+            });
+        }
+
+        self.extended_channels.remove(&channel_id)?;
+        self.last_job_activity.remove(&channel_id);
+        self.sent_channel_updates.remove(&channel_id);
+        for mut group_channel in self.group_channels.iter_mut() {
+            if group_channel.get_channel_ids().contains(&channel_id) {
+                group_channel.remove_channel_id(channel_id);
+            }
+        }
+        Some(ClosedChannels::Extended(channel_id))
+    }
+
+    /// Sets the label of the upstream currently connected.
+    /// Called when connecting to an upstream or during failover.
+    pub fn set_active_upstream(&self, label: String) {
+        info!("Active upstream: {label}");
+        self.active_upstream
+            .super_safe_lock(|data| *data = Some(label));
+    }
+
+    /// Records a fallback away from the active upstream caused by `reason`.
+    /// Called before the fallback resets the active upstream.
+    pub fn record_failover(&self, reason: FailoverReason, detail: String) {
+        let upstream = self.active_upstream.super_safe_lock(|data| data.clone());
+        self.last_failover.super_safe_lock(|data| {
+            *data = Some(FailoverEvent::new(reason, detail, upstream));
+        });
+    }
+
+    /// Records that a `NewExtendedMiningJob` or `SetNewPrevHash` arrived for `channel_id`.
+    ///
+    /// Messages addressed to a group channel refresh every upstream channel of the group.
+    /// Only channels already watched (registered when the channel was opened) are updated.
+    pub fn record_job_activity(&self, channel_id: ChannelId) {
+        let now = Instant::now();
+        if let Some(group_channel) = self.group_channels.get(&channel_id) {
+            for member_channel_id in group_channel.get_channel_ids() {
+                if let Some(mut last_activity) = self.last_job_activity.get_mut(member_channel_id) {
+                    *last_activity = now;
+                }
+            }
+        }
+        if let Some(mut last_activity) = self.last_job_activity.get_mut(&channel_id) {
+            *last_activity = now;
+        }
+    }
+
+    /// Checks every watched upstream channel for missing jobs.
+    ///
+    /// A channel that received no `NewExtendedMiningJob`/`SetNewPrevHash` within the staleness
+    /// timeout is logged and counted in [`Self::stale_job_events`]. Its timer is then restarted,
+    /// so a silent channel is reported once per timeout period. When `job_staleness_fallback` is
+    /// set, the first stale channel triggers fallback.
+    pub fn check_job_staleness(&self) -> TproxyResult<(), error::ChannelManager> {
+        let Some(timeout) = self.job_staleness_timeout else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        let mut stale_channel = None;
+        for mut entry in self.last_job_activity.iter_mut() {
+            let silent_for = now.duration_since(*entry.value());
+            if silent_for < timeout {
+                continue;
+            }
+            warn!(
+                "No new job received from upstream on channel {} for {:?}",
+                entry.key(),
+                silent_for
+            );
+            self.stale_job_events.fetch_add(1, Ordering::Relaxed);
+            *entry.value_mut() = now;
+            stale_channel.get_or_insert(*entry.key());
+        }
+
+        match stale_channel {
+            Some(channel_id) if self.job_staleness_fallback => Err(TproxyError::fallback(
+                TproxyErrorKind::StaleUpstreamJobs(channel_id),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that `job_time`, the `min_ntime` of a prevhash received from upstream, is not
+    /// ahead of the local clock by more than the accepted skew.
+    ///
+    /// Only the first prevhash of each upstream connection is checked: the template provider
+    /// behind the upstream sets it from its own clock, so it reveals a skew between the two hosts
+    /// before any keepalive job is derived from it. A job time in the past is always accepted, as
+    /// templates built on an older block legitimately carry one.
+    pub fn check_clock_skew(&self, job_time: u32) -> TproxyResult<(), error::ChannelManager> {
+        let Some(max_clock_skew) = self.max_clock_skew else {
+            return Ok(());
+        };
+        if self.clock_skew_checked.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let skew = job_time as i64 - now;
+        if skew <= max_clock_skew.as_secs() as i64 {
+            debug!("Upstream job time is {}s ahead of the local clock", skew);
+            return Ok(());
+        }
+        if self.refuse_clock_skew {
+            error!(
+                "Upstream job time is {}s ahead of the local clock, above the {:?} limit: check the clock of this host",
+                skew, max_clock_skew
+            );
+            return Err(TproxyError::shutdown(TproxyErrorKind::ClockSkew(skew)));
+        }
+        warn!(
+            "Upstream job time is {}s ahead of the local clock, above the {:?} limit: check the clock of this host",
+            skew, max_clock_skew
+        );
+        Ok(())
+    }
+
+    /// Fails with `RequiredExtensionsNotSupported` if the upstream did not agree to all of the
+    /// `required_extensions`.
+    ///
+    /// The upstream answers `RequestExtensions` before opening any channel, so a channel opening
+    /// with some of them missing means the upstream ignored the request.
+    pub fn check_required_extensions(&self) -> TproxyResult<(), error::ChannelManager> {
+        let missing_required = self.negotiated_extensions.super_safe_lock(|negotiated| {
+            self.required_extensions
+                .iter()
+                .filter(|ext| !negotiated.contains(ext))
+                .copied()
+                .collect::<Vec<u16>>()
+        });
+        if missing_required.is_empty() {
+            return Ok(());
+        }
+        error!(
+            "Upstream does not support our required extensions {:?}, falling back",
+            missing_required
+        );
+        Err(TproxyError::fallback(
+            TproxyErrorKind::RequiredExtensionsNotSupported(missing_required),
+        ))
+    }
+
     /// Gets the next sequence number for a valid share and increments the counter.
     ///
     /// The counter_key determines which counter to use:
@@ -730,14 +1266,211 @@ impl ChannelManager {
         *counter += 1;
         *counter
     }
+
+    /// Re-sends the pending `OpenExtendedMiningChannel` of `request_id` with a downstream
+    /// extranonce one byte smaller, after the upstream rejected the previous size as too large.
+    ///
+    /// Returns `false` if no such request is pending or its size already is at the configured
+    /// floor, in which case the caller should give up on the upstream.
+    pub async fn retry_open_channel_with_smaller_extranonce(
+        &self,
+        request_id: u32,
+    ) -> TproxyResult<bool, error::ChannelManager> {
+        let downstream_id = request_id as DownstreamId;
+        let Some(mut open_channel_msg) = self
+            .pending_open_channel_requests
+            .get(&downstream_id)
+            .map(|msg| msg.clone())
+        else {
+            return Ok(false);
+        };
+        let reduced_extranonce_size = {
+            let Some(mut pending_channel) = self.pending_channels.get_mut(&downstream_id) else {
+                return Ok(false);
+            };
+            if pending_channel.2 <= self.downstream_extranonce2_size_floor {
+                return Ok(false);
+            }
+            pending_channel.2 -= 1;
+            pending_channel.2
+        };
+
+        info!(
+            "Upstream rejected the extranonce size of request {}, retrying with a downstream extranonce size of {}",
+            request_id, reduced_extranonce_size
+        );
+        open_channel_msg.min_extranonce_size =
+            upstream_min_extranonce_size(reduced_extranonce_size) as u16;
+        self.send_open_channel_to_upstream(open_channel_msg).await?;
+        Ok(true)
+    }
+
+    /// Reacts to the upstream rejecting the last `UpdateChannel` sent for `channel_id` with
+    /// `error_code`, according to the configured [`UpdateChannelErrorAction`].
+    ///
+    /// Returns whether a corrected update was sent. A correction is never retried itself, so a
+    /// misbehaving upstream cannot make the translator loop.
+    pub async fn correct_rejected_channel_update(
+        &self,
+        channel_id: ChannelId,
+        error_code: &str,
+    ) -> TproxyResult<bool, error::ChannelManager> {
+        let Some((_, sent)) = self.sent_channel_updates.remove(&channel_id) else {
+            warn!(
+                "Upstream rejected an unknown UpdateChannel for channel {}: {}",
+                channel_id, error_code
+            );
+            return Ok(false);
+        };
+        let rejected = sent.update;
+
+        if self.update_channel_error_action == UpdateChannelErrorAction::Ignore {
+            error!(
+                "Upstream rejected UpdateChannel for channel {} (nominal hashrate {} h/s): {}; the channel keeps its previous target",
+                channel_id, rejected.nominal_hash_rate, error_code
+            );
+            return Ok(false);
+        }
+        if sent.is_correction {
+            error!(
+                "Upstream also rejected the corrected UpdateChannel for channel {} (nominal hashrate {} h/s): {}; giving up",
+                channel_id, rejected.nominal_hash_rate, error_code
+            );
+            return Ok(false);
+        }
+
+        let mut update = rejected.clone();
+        match error_code {
+            INVALID_NOMINAL_HASHRATE => {
+                update.nominal_hash_rate = sent.previous_nominal_hashrate;
+            }
+            MAX_TARGET_OUT_OF_RANGE | REQUESTED_MAX_TARGET_OUT_OF_RANGE => {
+                update.maximum_target = [0xff; 32].into();
+            }
+            _ => {
+                error!(
+                    "Upstream rejected UpdateChannel for channel {}: {}; no correction possible",
+                    channel_id, error_code
+                );
+                return Ok(false);
+            }
+        }
+
+        let local_channel_id = if is_aggregated() {
+            self.aggregated_channel_of_upstream(channel_id)
+                .unwrap_or(AGGREGATED_CHANNEL_ID)
+        } else {
+            channel_id
+        };
+        if let Some(mut channel) = self.extended_channels.get_mut(&local_channel_id) {
+            channel.set_nominal_hashrate(update.nominal_hash_rate);
+        }
+        warn!(
+            "Upstream rejected UpdateChannel for channel {} (nominal hashrate {} h/s): {}; retrying with nominal hashrate {} h/s",
+            channel_id, rejected.nominal_hash_rate, error_code, update.nominal_hash_rate
+        );
+        self.send_update_channel_to_upstream(SentChannelUpdate {
+            update,
+            previous_nominal_hashrate: sent.previous_nominal_hashrate,
+            is_correction: true,
+        })
+        .await?;
+        Ok(true)
+    }
+
+    // Sends an `UpdateChannel` upstream, keeping it around to correct it if the upstream
+    // rejects it.
+    async fn send_update_channel_to_upstream(
+        &self,
+        sent: SentChannelUpdate,
+    ) -> TproxyResult<(), error::ChannelManager> {
+        info!(
+            "Sending UpdateChannel message to upstream for channel_id: {:?}",
+            sent.update.channel_id
+        );
+        let mut update = sent.update.clone();
+        let routed_upstream = self.upstream_router.route_channel(&mut update.channel_id);
+        self.sent_channel_updates
+            .insert(sent.update.channel_id, sent);
+
+        let sv2_frame: Sv2Frame = AnyMessage::Mining(Mining::UpdateChannel(update))
+            .try_into()
+            .map_err(TproxyError::shutdown)?;
+        self.send_to_upstream(sv2_frame, routed_upstream, "UpdateChannel")
+            .await
+    }
+
+    // Sends an `OpenExtendedMiningChannel` upstream, keeping it around for retries until the
+    // upstream answers.
+    async fn send_open_channel_to_upstream(
+        &self,
+        open_channel_msg: OpenExtendedMiningChannel<'static>,
+    ) -> TproxyResult<(), error::ChannelManager> {
+        info!(
+            "Sending OpenExtendedMiningChannel message to upstream: {:?}",
+            open_channel_msg
+        );
+        self.pending_open_channel_requests.insert(
+            open_channel_msg.request_id as DownstreamId,
+            open_channel_msg.clone(),
+        );
+
+        let routed_upstream = self
+            .upstream_router
+            .route_request(open_channel_msg.request_id);
+        let message = Mining::OpenExtendedMiningChannel(open_channel_msg);
+        let sv2_frame: Sv2Frame = AnyMessage::Mining(message)
+            .try_into()
+            .map_err(TproxyError::shutdown)?;
+        self.send_to_upstream(sv2_frame, routed_upstream, "open channel")
+            .await
+    }
+
+    // Sends a frame to `routed_upstream`, or to the primary upstream if it is `None`. A routed
+    // upstream that went away only takes its own channels down, so failing to reach it does not
+    // trigger fallback.
+    async fn send_to_upstream(
+        &self,
+        sv2_frame: Sv2Frame,
+        routed_upstream: Option<Sender<Sv2Frame>>,
+        message_name: &str,
+    ) -> TproxyResult<(), error::ChannelManager> {
+        match routed_upstream {
+            Some(sender) => sender.send(sv2_frame).await.map_err(|e| {
+                warn!(
+                    "Failed to send {} message to routed upstream: {:?}",
+                    message_name, e
+                );
+                TproxyError::log(TproxyErrorKind::ChannelErrorSender)
+            }),
+            None => self
+                .channel_state
+                .upstream_sender
+                .send(sv2_frame)
+                .await
+                .map_err(|e| {
+                    error!(
+                        "Failed to send {} message to upstream: {:?}",
+                        message_name, e
+                    );
+                    TproxyError::fallback(TproxyErrorKind::ChannelErrorSender)
+                }),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use async_channel::unbounded;
-    use stratum_apps::stratum_core::mining_sv2::{
-        OpenExtendedMiningChannel, SubmitSharesExtended, UpdateChannel,
+    use stratum_apps::stratum_core::{
+        binary_sv2::Seq064K,
+        bitcoin::Target,
+        extensions_sv2::RequestExtensionsSuccess,
+        handlers_sv2::HandleExtensionsFromServerAsync,
+        mining_sv2::{
+            OpenExtendedMiningChannel, SetGroupChannel, SubmitSharesExtended, UpdateChannelError,
+        },
     };
 
     fn create_test_channel_manager() -> ChannelManager {
@@ -755,9 +1488,249 @@ mod tests {
             status_sender,
             vec![],
             vec![],
+            None,
+            false,
+            2,
+            UpdateChannelErrorAction::RevertAndRetry,
         )
     }
 
+    fn create_test_channel_manager_with_watchdog(fallback: bool) -> ChannelManager {
+        let (upstream_sender, _upstream_receiver) = unbounded();
+        let (_upstream_sender2, upstream_receiver) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (_sv1_server_sender2, sv1_server_receiver) = unbounded();
+        let (status_sender, _) = unbounded();
+
+        ChannelManager::new(
+            upstream_sender,
+            upstream_receiver,
+            sv1_server_sender,
+            sv1_server_receiver,
+            status_sender,
+            vec![],
+            vec![],
+            Some(Duration::from_secs(30)),
+            fallback,
+            2,
+            UpdateChannelErrorAction::RevertAndRetry,
+        )
+    }
+
+    // Registers extended channels 1..=3 in group channel 10 and channel 4 in group channel 20.
+    fn add_test_group_channels(manager: &ChannelManager) {
+        for (group_channel_id, channel_ids) in [(10, vec![1, 2, 3]), (20, vec![4])] {
+            let mut group_channel = GroupChannel::new(group_channel_id);
+            for channel_id in channel_ids {
+                group_channel.add_channel_id(channel_id, 8).unwrap();
+                manager.extended_channels.insert(
+                    channel_id,
+                    ExtendedChannel::new(
+                        channel_id,
+                        format!("miner{channel_id}"),
+                        vec![0, 0, 0, channel_id as u8],
+                        Target::from_le_bytes([0xff; 32]),
+                        1000.0,
+                        true,
+                        4,
+                    ),
+                );
+                manager.last_job_activity.insert(channel_id, Instant::now());
+                manager.sent_channel_updates.insert(
+                    channel_id,
+                    SentChannelUpdate {
+                        update: UpdateChannel {
+                            channel_id,
+                            nominal_hash_rate: 2000.0,
+                            maximum_target: [0xff; 32].into(),
+                        },
+                        previous_nominal_hashrate: 1000.0,
+                        is_correction: false,
+                    },
+                );
+            }
+            manager
+                .group_channels
+                .insert(group_channel_id, group_channel);
+        }
+    }
+
+    #[test]
+    fn test_remove_closed_channels_group_close_removes_only_members() {
+        let manager = create_test_channel_manager();
+        add_test_group_channels(&manager);
+
+        let closed = manager.remove_closed_channels(10).unwrap();
+
+        assert_eq!(
+            closed,
+            ClosedChannels::Group {
+                group_channel_id: 10,
+                channel_ids: vec![1, 2, 3],
+            }
+        );
+        assert_eq!(closed.channel_ids(), &[1, 2, 3]);
+        assert!(!manager.group_channels.contains_key(&10));
+        for channel_id in [1, 2, 3] {
+            assert!(!manager.extended_channels.contains_key(&channel_id));
+            assert!(!manager.last_job_activity.contains_key(&channel_id));
+            assert!(!manager.sent_channel_updates.contains_key(&channel_id));
+        }
+        // members of other groups are untouched
+        assert!(manager.extended_channels.contains_key(&4));
+        assert!(manager.group_channels.contains_key(&20));
+    }
+
+    #[test]
+    fn test_remove_closed_channels_single_close_removes_only_that_channel() {
+        let manager = create_test_channel_manager();
+        add_test_group_channels(&manager);
+
+        let closed = manager.remove_closed_channels(2).unwrap();
+
+        assert_eq!(closed, ClosedChannels::Extended(2));
+        assert_eq!(closed.channel_ids(), &[2]);
+        assert!(!manager.extended_channels.contains_key(&2));
+        assert!(!manager.last_job_activity.contains_key(&2));
+        assert!(!manager.sent_channel_updates.contains_key(&2));
+        for channel_id in [1, 3, 4] {
+            assert!(manager.extended_channels.contains_key(&channel_id));
+            assert!(manager.sent_channel_updates.contains_key(&channel_id));
+        }
+        let group_channel = manager.group_channels.get(&10).unwrap();
+        assert!(!group_channel.get_channel_ids().contains(&2));
+        assert!(group_channel.get_channel_ids().contains(&1));
+        assert!(group_channel.get_channel_ids().contains(&3));
+    }
+
+    #[tokio::test]
+    async fn test_set_group_channel_moves_only_the_listed_channels() {
+        let manager = create_test_channel_manager();
+        add_test_group_channels(&manager);
+        let group_members = |group_channel_id| {
+            manager.group_channels.get(&group_channel_id).map(|group| {
+                let mut channel_ids: Vec<_> = group.get_channel_ids().iter().copied().collect();
+                channel_ids.sort();
+                channel_ids
+            })
+        };
+
+        // channel 2 is reassigned from group 10 to group 20
+        let set_group_channel = SetGroupChannel {
+            group_channel_id: 20,
+            channel_ids: Seq064K::new(vec![2]).unwrap(),
+        };
+        manager
+            .clone()
+            .handle_set_group_channel(None, set_group_channel, None)
+            .await
+            .unwrap();
+        // the group-directed jobs of group 20 are now routed to channel 2, and those of group 10
+        // no longer are
+        assert_eq!(group_members(10), Some(vec![1, 3]));
+        assert_eq!(group_members(20), Some(vec![2, 4]));
+
+        // channels 1 and 3 move to a new group, leaving group 10 empty
+        let set_group_channel = SetGroupChannel {
+            group_channel_id: 30,
+            channel_ids: Seq064K::new(vec![1, 3]).unwrap(),
+        };
+        manager
+            .clone()
+            .handle_set_group_channel(None, set_group_channel, None)
+            .await
+            .unwrap();
+        assert_eq!(group_members(10), None);
+        assert_eq!(group_members(20), Some(vec![2, 4]));
+        assert_eq!(group_members(30), Some(vec![1, 3]));
+    }
+
+    #[test]
+    fn test_aggregated_channel_with_room() {
+        let manager = create_test_channel_manager()
+            .with_aggregated_channels(Arc::new(DashMap::new()), Some(2));
+        let add_channel = |channel_id: ChannelId, upstream_channel_id: ChannelId| {
+            manager.extended_channels.insert(
+                channel_id,
+                ExtendedChannel::new(
+                    upstream_channel_id,
+                    "miner".to_string(),
+                    vec![0, 0, 0, 0],
+                    Target::from_le_bytes([0xff; 32]),
+                    1000.0,
+                    true,
+                    4,
+                ),
+            );
+        };
+        assert_eq!(manager.aggregated_channel_with_room(), None);
+        assert_eq!(manager.next_aggregated_channel_id(), AGGREGATED_CHANNEL_ID);
+
+        // upstream channel 7 is shared by downstream channels 1 and 2
+        add_channel(AGGREGATED_CHANNEL_ID, 7);
+        for channel_id in [1, 2] {
+            add_channel(channel_id, channel_id);
+            manager
+                .aggregated_channel_ids
+                .insert(channel_id, AGGREGATED_CHANNEL_ID);
+        }
+        assert_eq!(manager.aggregated_channel_with_room(), None);
+        assert_eq!(manager.next_downstream_channel_id(), 3);
+
+        // upstream channel 9 is opened for downstream channel 3
+        let second_channel_id = manager.next_aggregated_channel_id();
+        assert_eq!(second_channel_id, aggregated_channel_id(1));
+        add_channel(second_channel_id, 9);
+        add_channel(3, 3);
+        manager.aggregated_channel_ids.insert(3, second_channel_id);
+        assert_eq!(
+            manager.aggregated_channels(),
+            vec![AGGREGATED_CHANNEL_ID, second_channel_id]
+        );
+        assert_eq!(
+            manager.aggregated_channel_with_room(),
+            Some(second_channel_id)
+        );
+        assert_eq!(manager.next_downstream_channel_id(), 4);
+        assert_eq!(manager.aggregated_channel_of(2), AGGREGATED_CHANNEL_ID);
+        assert_eq!(manager.aggregated_channel_of(3), second_channel_id);
+        assert_eq!(
+            manager.aggregated_channel_of(second_channel_id),
+            second_channel_id
+        );
+        assert_eq!(
+            manager.aggregated_channel_of_upstream(9),
+            Some(second_channel_id)
+        );
+        assert_eq!(
+            manager.aggregated_channel_members(second_channel_id),
+            vec![3]
+        );
+
+        // a downstream channel dropped by the SV1 server frees its room
+        manager.aggregated_channel_ids.remove(&1);
+        assert_eq!(
+            manager.aggregated_channel_with_room(),
+            Some(AGGREGATED_CHANNEL_ID)
+        );
+
+        let mut closed = manager.remove_aggregated_channels();
+        closed.sort_unstable();
+        assert_eq!(closed, vec![7, 9]);
+        assert!(manager.extended_channels.is_empty());
+        assert!(manager.aggregated_channel_ids.is_empty());
+    }
+
+    #[test]
+    fn test_remove_closed_channels_unknown_channel() {
+        let manager = create_test_channel_manager();
+        add_test_group_channels(&manager);
+
+        assert!(manager.remove_closed_channels(99).is_none());
+        assert_eq!(manager.extended_channels.len(), 4);
+        assert_eq!(manager.group_channels.len(), 2);
+    }
+
     #[tokio::test]
     async fn test_handle_downstream_open_channel_message() {
         let manager = create_test_channel_manager();
@@ -846,6 +1819,230 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_retry_open_channel_with_smaller_extranonce() {
+        let (upstream_sender, upstream_receiver) = unbounded();
+        let (_upstream_sender2, upstream_receiver2) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (_sv1_server_sender2, sv1_server_receiver) = unbounded();
+        let (status_sender, _) = unbounded();
+        let manager = ChannelManager::new(
+            upstream_sender,
+            upstream_receiver2,
+            sv1_server_sender,
+            sv1_server_receiver,
+            status_sender,
+            vec![],
+            vec![],
+            None,
+            false,
+            3,
+            UpdateChannelErrorAction::RevertAndRetry,
+        );
+
+        manager
+            .pending_channels
+            .insert(1, ("test_user".to_string(), 1000.0, 4));
+        let open_channel = OpenExtendedMiningChannel {
+            request_id: 1,
+            user_identity: "test_user".as_bytes().to_vec().try_into().unwrap(),
+            nominal_hash_rate: 1000.0,
+            max_target: vec![0xFFu8; 32].try_into().unwrap(),
+            min_extranonce_size: upstream_min_extranonce_size(4) as u16,
+        };
+        manager
+            .send_open_channel_to_upstream(open_channel)
+            .await
+            .unwrap();
+        assert!(upstream_receiver.try_recv().is_ok());
+
+        // the upstream rejected the first size: the request is re-sent one byte smaller
+        assert!(manager
+            .retry_open_channel_with_smaller_extranonce(1)
+            .await
+            .unwrap());
+        assert!(upstream_receiver.try_recv().is_ok());
+        assert_eq!(manager.pending_channels.get(&1).unwrap().2, 3);
+        assert_eq!(
+            manager
+                .pending_open_channel_requests
+                .get(&1)
+                .unwrap()
+                .min_extranonce_size as usize,
+            upstream_min_extranonce_size(3)
+        );
+
+        // the floor is reached, so there is nothing left to retry with
+        assert!(!manager
+            .retry_open_channel_with_smaller_extranonce(1)
+            .await
+            .unwrap());
+        assert!(upstream_receiver.try_recv().is_err());
+
+        // unknown requests are not retried
+        assert!(!manager
+            .retry_open_channel_with_smaller_extranonce(2)
+            .await
+            .unwrap());
+    }
+
+    // Forwards an `UpdateChannel` from the SV1 server to the upstream, which rejects it with
+    // `error_code`, and returns how many `UpdateChannel` the translator sent in total.
+    async fn reject_update_channel(
+        action: UpdateChannelErrorAction,
+        error_code: &str,
+    ) -> (Arc<ChannelManager>, usize) {
+        let (upstream_sender, upstream_receiver) = unbounded();
+        let (_upstream_sender2, upstream_receiver2) = unbounded();
+        let (sv1_server_sender, _sv1_server_receiver) = unbounded();
+        let (sv1_server_sender2, sv1_server_receiver) = unbounded();
+        let (status_sender, _) = unbounded();
+        let manager = Arc::new(ChannelManager::new(
+            upstream_sender,
+            upstream_receiver2,
+            sv1_server_sender,
+            sv1_server_receiver,
+            status_sender,
+            vec![],
+            vec![],
+            None,
+            false,
+            2,
+            action,
+        ));
+        manager.extended_channels.insert(
+            AGGREGATED_CHANNEL_ID,
+            ExtendedChannel::new(
+                1,
+                "test_user".to_string(),
+                vec![0; 4],
+                Target::from_le_bytes([0xff; 32]),
+                1000.0,
+                true,
+                8,
+            ),
+        );
+
+        let update_channel = UpdateChannel {
+            channel_id: AGGREGATED_CHANNEL_ID,
+            nominal_hash_rate: 1e15,
+            maximum_target: [0xaa; 32].into(),
+        };
+        sv1_server_sender2
+            .send((Mining::UpdateChannel(update_channel), None))
+            .await
+            .unwrap();
+        manager.clone().handle_downstream_message().await.unwrap();
+        assert!(upstream_receiver.try_recv().is_ok());
+
+        let update_channel_error = UpdateChannelError {
+            channel_id: 1,
+            error_code: error_code.to_string().try_into().unwrap(),
+        };
+        (*manager)
+            .clone()
+            .handle_update_channel_error(None, update_channel_error, None)
+            .await
+            .unwrap();
+
+        let mut sent = 1;
+        while upstream_receiver.try_recv().is_ok() {
+            sent += 1;
+        }
+        (manager, sent)
+    }
+
+    #[tokio::test]
+    async fn test_rejected_update_channel_is_reverted_and_retried() {
+        let (manager, sent) = reject_update_channel(
+            UpdateChannelErrorAction::RevertAndRetry,
+            INVALID_NOMINAL_HASHRATE,
+        )
+        .await;
+
+        // the update is re-sent with the hashrate the upstream last accepted
+        assert_eq!(sent, 2);
+        let correction = manager.sent_channel_updates.get(&1).unwrap().clone();
+        assert!(correction.is_correction);
+        assert_eq!(correction.update.nominal_hash_rate, 1000.0);
+        assert_eq!(
+            manager
+                .extended_channels
+                .get(&AGGREGATED_CHANNEL_ID)
+                .unwrap()
+                .get_nominal_hashrate(),
+            1000.0
+        );
+
+        // a rejected correction is not retried again
+        let update_channel_error = UpdateChannelError {
+            channel_id: 1,
+            error_code: INVALID_NOMINAL_HASHRATE.to_string().try_into().unwrap(),
+        };
+        (*manager)
+            .clone()
+            .handle_update_channel_error(None, update_channel_error, None)
+            .await
+            .unwrap();
+        assert!(manager.sent_channel_updates.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rejected_update_channel_max_target_is_relaxed() {
+        let (manager, sent) = reject_update_channel(
+            UpdateChannelErrorAction::RevertAndRetry,
+            REQUESTED_MAX_TARGET_OUT_OF_RANGE,
+        )
+        .await;
+
+        // the hashrate was fine, only the maximum target restriction is dropped
+        assert_eq!(sent, 2);
+        let correction = manager.sent_channel_updates.get(&1).unwrap().clone();
+        assert_eq!(correction.update.nominal_hash_rate, 1e15);
+        assert_eq!(correction.update.maximum_target.inner_as_ref(), &[0xff; 32]);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_update_channel_is_ignored() {
+        let (manager, sent) =
+            reject_update_channel(UpdateChannelErrorAction::Ignore, INVALID_NOMINAL_HASHRATE).await;
+
+        assert_eq!(sent, 1);
+        assert!(manager.sent_channel_updates.is_empty());
+    }
+
+    #[test]
+    fn test_clock_skew_check() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+
+        // a template provider 2 hours ahead of the local clock only raises a warning...
+        let manager =
+            create_test_channel_manager().with_clock_skew_check(Duration::from_secs(600), false);
+        assert!(manager.check_clock_skew(now + 2 * 60 * 60).is_ok());
+        assert!(manager.clock_skew_checked.load(Ordering::Relaxed));
+
+        // ...unless refusing is configured
+        let manager =
+            create_test_channel_manager().with_clock_skew_check(Duration::from_secs(600), true);
+        let error = manager.check_clock_skew(now + 2 * 60 * 60).unwrap_err();
+        assert!(matches!(error.kind, TproxyErrorKind::ClockSkew(skew) if skew >= 7200));
+        // only the first prevhash of an upstream is checked
+        assert!(manager.check_clock_skew(now + 2 * 60 * 60).is_ok());
+
+        // a job time in the past, as of a template built on an older block, is accepted
+        let manager =
+            create_test_channel_manager().with_clock_skew_check(Duration::from_secs(600), true);
+        assert!(manager.check_clock_skew(now - 2 * 60 * 60).is_ok());
+
+        // a skew within the limit is accepted
+        let manager =
+            create_test_channel_manager().with_clock_skew_check(Duration::from_secs(600), true);
+        assert!(manager.check_clock_skew(now + 60).is_ok());
+    }
+
     #[test]
     fn test_channel_manager_debug() {
         let manager = create_test_channel_manager();
@@ -866,4 +2063,87 @@ mod tests {
 
         assert!(has_pending);
     }
+
+    #[test]
+    fn test_job_staleness_watchdog_fires_on_silent_channel() {
+        let manager = create_test_channel_manager_with_watchdog(false);
+        manager
+            .last_job_activity
+            .insert(1, Instant::now() - Duration::from_secs(60));
+        manager.last_job_activity.insert(2, Instant::now());
+
+        assert!(manager.check_job_staleness().is_ok());
+        assert_eq!(manager.stale_job_events.load(Ordering::Relaxed), 1);
+
+        // the timer of the stale channel restarts, so it is not reported again right away
+        assert!(manager.check_job_staleness().is_ok());
+        assert_eq!(manager.stale_job_events.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_job_staleness_watchdog_triggers_fallback() {
+        let manager = create_test_channel_manager_with_watchdog(true);
+        manager
+            .last_job_activity
+            .insert(1, Instant::now() - Duration::from_secs(60));
+
+        let err = manager.check_job_staleness().unwrap_err();
+        assert!(matches!(err.action, crate::error::Action::Fallback));
+        assert!(matches!(err.kind, TproxyErrorKind::StaleUpstreamJobs(1)));
+    }
+
+    #[tokio::test]
+    async fn test_required_extension_unsupported_by_upstream_fails_over() {
+        let mut manager = create_test_channel_manager();
+        manager.required_extensions = vec![0x0002];
+
+        // an upstream ignoring `RequestExtensions` opens channels without negotiating
+        let err = manager.check_required_extensions().unwrap_err();
+        assert!(matches!(err.action, crate::error::Action::Fallback));
+        assert!(matches!(
+            err.kind,
+            TproxyErrorKind::RequiredExtensionsNotSupported(ref missing) if missing == &[0x0002]
+        ));
+
+        // an upstream answering without the required extension
+        let success = RequestExtensionsSuccess {
+            request_id: 0,
+            supported_extensions: Seq064K::new(vec![0x0003]).unwrap(),
+        };
+        let err = manager
+            .handle_request_extensions_success(None, success, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err.action, crate::error::Action::Fallback));
+        assert!(matches!(
+            err.kind,
+            TproxyErrorKind::RequiredExtensionsNotSupported(ref missing) if missing == &[0x0002]
+        ));
+
+        let success = RequestExtensionsSuccess {
+            request_id: 0,
+            supported_extensions: Seq064K::new(vec![0x0002]).unwrap(),
+        };
+        manager
+            .handle_request_extensions_success(None, success, None)
+            .await
+            .unwrap();
+        assert!(manager.check_required_extensions().is_ok());
+    }
+
+    #[test]
+    fn test_record_job_activity_refreshes_watched_channel() {
+        let manager = create_test_channel_manager_with_watchdog(false);
+        manager
+            .last_job_activity
+            .insert(1, Instant::now() - Duration::from_secs(60));
+
+        manager.record_job_activity(1);
+        // unwatched channels are not added
+        manager.record_job_activity(2);
+
+        assert!(manager.check_job_staleness().is_ok());
+        assert_eq!(manager.stale_job_events.load(Ordering::Relaxed), 0);
+        assert!(!manager.last_job_activity.contains_key(&2));
+    }
 }
@@ -36,30 +36,14 @@ impl HandleExtensionsFromServerAsync for ChannelManager {
 
         info!("Extension negotiation success: supported={:?}", supported);
 
-        // Check if all of the proxy's required extensions are supported by the server
-        let missing_required: Vec<u16> = self
-            .required_extensions
-            .iter()
-            .filter(|ext| !supported.contains(ext))
-            .copied()
-            .collect();
-
-        if !missing_required.is_empty() {
-            error!(
-                "Server does not support our required extensions {:?}. Connection should fail over to another upstream.",
-                missing_required
-            );
-            return Err(TproxyError::fallback(TproxyErrorKind::General(format!(
-                "Server does not support required extensions: {:?}",
-                missing_required
-            ))));
-        }
-
         // Store the negotiated extensions in the shared channel manager data
         self.negotiated_extensions.super_safe_lock(|data| {
             *data = supported;
         });
 
+        // Check if all of the proxy's required extensions are supported by the server
+        self.check_required_extensions()?;
+
         info!("Successfully negotiated extensions");
 
         Ok(())
@@ -0,0 +1,167 @@
+//! Extranonce prefix factory of an upstream channel, tracking how much of its prefix space the
+//! downstream channels use.
+//!
+//! Every downstream channel derived from an upstream channel takes one value of the range 1
+//! bytes the translator reserves in the extranonce. Once they are all taken, no further miner
+//! can share the channel, so a warning is logged when the allocated fraction crosses a
+//! configurable threshold, leaving operators time to open more channels.
+use std::ops::{Deref, DerefMut};
+
+use stratum_apps::stratum_core::mining_sv2::{
+    ExtendedExtranonce, ExtendedExtranonceError, Extranonce,
+};
+use tracing::warn;
+
+/// Default fraction of the prefix space of a channel above which a warning is logged.
+pub const DEFAULT_EXTRANONCE_USAGE_WARNING_THRESHOLD: f64 = 0.8;
+
+/// [`ExtendedExtranonce`] counting the prefixes it allocated out of its range 1.
+#[derive(Debug, Clone)]
+pub struct TrackedExtranonceFactory {
+    factory: ExtendedExtranonce,
+    range1_len: usize,
+    // Number of distinct prefixes range 1 can hold
+    capacity: f64,
+    allocated: u64,
+    warning_threshold: f64,
+    warned: bool,
+}
+
+impl TrackedExtranonceFactory {
+    /// Wraps `factory`, whose range 1 is `range1_len` bytes long, warning once the allocated
+    /// fraction of its prefixes reaches `warning_threshold`.
+    pub fn new(factory: ExtendedExtranonce, range1_len: usize, warning_threshold: f64) -> Self {
+        Self {
+            factory,
+            range1_len,
+            capacity: 256f64.powi(range1_len as i32),
+            allocated: 0,
+            warning_threshold,
+            warned: false,
+        }
+    }
+
+    /// Allocates the next prefix, see [`ExtendedExtranonce::next_prefix_extended`].
+    pub fn next_prefix_extended(
+        &mut self,
+        required_extranonce_size: usize,
+    ) -> Result<Extranonce, ExtendedExtranonceError> {
+        let prefix = self
+            .factory
+            .next_prefix_extended(required_extranonce_size)?;
+        self.allocated += 1;
+        let usage = self.usage();
+        if !self.warned && usage >= self.warning_threshold {
+            self.warned = true;
+            warn!(
+                allocated = self.allocated,
+                capacity = self.capacity,
+                "{:.1}% of the extranonce prefixes of the channel are allocated, open more upstream channels before it is exhausted",
+                usage * 100.0
+            );
+        }
+        Ok(prefix)
+    }
+
+    /// Moves the factory onto `upstream_prefix`, the new prefix of its upstream channel, once the
+    /// upstream changed it with `SetExtranoncePrefix`.
+    ///
+    /// The prefixes allocated so far are allocated again on the new prefix, so the next one does
+    /// not collide with those the downstream channels already use.
+    pub fn rebase(&mut self, upstream_prefix: Extranonce) -> Result<(), ExtendedExtranonceError> {
+        let range0_len = self.factory.get_range0_len();
+        let range2_len = self.factory.get_range2_len();
+        let range0 = 0..range0_len;
+        let range1 = range0.end..range0.end + self.range1_len;
+        let range2 = range1.end..range1.end + range2_len;
+        let mut factory =
+            ExtendedExtranonce::from_upstream_extranonce(upstream_prefix, range0, range1, range2)?;
+        for _ in 0..self.allocated {
+            factory.next_prefix_extended(range2_len)?;
+        }
+        self.factory = factory;
+        Ok(())
+    }
+
+    /// Returns the allocated fraction of the prefix space.
+    pub fn usage(&self) -> f64 {
+        self.allocated as f64 / self.capacity
+    }
+
+    /// Returns whether the allocated fraction reached the warning threshold.
+    pub fn above_warning_threshold(&self) -> bool {
+        self.warned
+    }
+}
+
+impl Deref for TrackedExtranonceFactory {
+    type Target = ExtendedExtranonce;
+
+    fn deref(&self) -> &Self::Target {
+        &self.factory
+    }
+}
+
+impl DerefMut for TrackedExtranonceFactory {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.factory
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stratum_apps::stratum_core::binary_sv2::B032;
+
+    use super::*;
+
+    #[test]
+    fn warns_once_the_usage_reaches_the_threshold() {
+        // one byte of range 1 holds 256 prefixes
+        let factory = ExtendedExtranonce::new(0..0, 0..1, 1..5, None).unwrap();
+        let mut factory = TrackedExtranonceFactory::new(factory, 1, 0.5);
+
+        for _ in 0..127 {
+            factory.next_prefix_extended(4).unwrap();
+        }
+        assert!(factory.usage() < 0.5);
+        assert!(!factory.above_warning_threshold());
+
+        factory.next_prefix_extended(4).unwrap();
+        assert_eq!(factory.usage(), 0.5);
+        assert!(factory.above_warning_threshold());
+    }
+
+    fn upstream_prefix(byte: u8) -> Extranonce {
+        let prefix: B032 = vec![byte; 4].try_into().unwrap();
+        prefix.into()
+    }
+
+    #[test]
+    fn rebase_keeps_the_allocated_prefixes() {
+        let factory =
+            ExtendedExtranonce::from_upstream_extranonce(upstream_prefix(0xaa), 0..4, 4..6, 6..10)
+                .unwrap();
+        let mut factory = TrackedExtranonceFactory::new(factory, 2, 0.8);
+        let mut next_prefix = || {
+            factory
+                .next_prefix_extended(4)
+                .unwrap()
+                .into_b032()
+                .to_vec()
+        };
+        let allocated: Vec<Vec<u8>> = (0..3).map(|_| next_prefix()).collect();
+
+        factory.rebase(upstream_prefix(0xbb)).unwrap();
+        let next = factory
+            .next_prefix_extended(4)
+            .unwrap()
+            .into_b032()
+            .to_vec();
+        assert_eq!(next[..4], [0xbb; 4]);
+        // the next prefix follows the ones allocated before the upstream prefix changed
+        assert!(allocated
+            .iter()
+            .all(|prefix| prefix[..4] == [0xaa; 4] && prefix[4..] != next[4..]));
+        assert_eq!(factory.usage(), 4.0 / 65536.0);
+    }
+}
@@ -1,9 +1,15 @@
 use crate::{
     error::{self, TproxyError, TproxyErrorKind},
     is_aggregated,
-    sv2::ChannelManager,
-    utils::{proxy_extranonce_prefix_len, AGGREGATED_CHANNEL_ID},
+    sv2::{
+        channel_manager::{
+            channel_manager::ClosedChannels, extranonce_factory::TrackedExtranonceFactory,
+        },
+        ChannelManager,
+    },
+    utils::proxy_extranonce_prefix_len,
 };
+use std::time::Instant;
 use stratum_apps::{
     stratum_core::{
         bitcoin::Target,
@@ -20,10 +26,13 @@ use stratum_apps::{
         },
         parsers_sv2::{Mining, Tlv},
     },
-    utils::types::DownstreamId,
+    utils::{coinbase_split::validate_coinbase_split, types::DownstreamId},
 };
 use tracing::{debug, error, info, warn};
 
+/// `OpenMiningChannelError` code of an upstream unable to provide the requested extranonce size.
+const MIN_EXTRANONCE_SIZE_TOO_LARGE: &str = "min-extranonce-size-too-large";
+
 #[cfg_attr(not(test), hotpath::measure_all)]
 impl HandleMiningMessagesFromServerAsync for ChannelManager {
     type Error = TproxyError<error::ChannelManager>;
@@ -64,6 +73,8 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
         m: OpenExtendedMiningChannelSuccess<'_>,
         _tlv_fields: Option<&[Tlv]>,
     ) -> Result<(), Self::Error> {
+        self.check_required_extensions()?;
+
         // Check if we have the pending channel data, return error if not
         let (user_identity, nominal_hashrate, downstream_extranonce_len) = self
             .pending_channels
@@ -73,6 +84,11 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
                 TproxyError::log(TproxyErrorKind::PendingChannelNotFound(m.request_id))
             })?
             .1;
+        self.pending_open_channel_requests
+            .remove(&(m.request_id as DownstreamId));
+
+        // start watching the new upstream channel for stale jobs
+        self.last_job_activity.insert(m.channel_id, Instant::now());
 
         let success = {
             info!(
@@ -125,9 +141,12 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
             // If we are in aggregated mode, we need to create a new extranonce prefix and
             // insert the extended channel into the map
             if is_aggregated() {
-                // Store the upstream extended channel under AGGREGATED_CHANNEL_ID
+                // Store the upstream extended channel under AGGREGATED_CHANNEL_ID, or the ID
+                // below the last aggregated channel once those are shared by
+                // max_downstreams_per_aggregated_channel downstreams
+                let aggregated_channel_id = self.next_aggregated_channel_id();
                 self.extended_channels
-                    .insert(AGGREGATED_CHANNEL_ID, extended_channel.clone());
+                    .insert(aggregated_channel_id, extended_channel.clone());
 
                 let upstream_extranonce_prefix: Extranonce = m.extranonce_prefix.clone().into();
                 let translator_proxy_extranonce_prefix_len = proxy_extranonce_prefix_len(
@@ -152,20 +171,36 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
                     range2,
                 )
                 .expect("Failed to create ExtendedExtranonce from upstream extranonce");
-                self.extranonce_factories
-                    .insert(AGGREGATED_CHANNEL_ID, extended_extranonce_factory);
-
-                let mut factory = self
-                    .extranonce_factories
-                    .get_mut(&AGGREGATED_CHANNEL_ID)
-                    .expect("extranonce_prefix_factory should be set after creation");
-                let new_extranonce_size = factory.get_range2_len() as u16;
-                let new_extranonce_prefix = factory
-                    .next_prefix_extended(new_extranonce_size as usize)
-                    .expect("next_prefix_extended should return a value for valid input")
-                    .into_b032();
+                self.extranonce_factories.insert(
+                    aggregated_channel_id,
+                    TrackedExtranonceFactory::new(
+                        extended_extranonce_factory,
+                        translator_proxy_extranonce_prefix_len,
+                        self.extranonce_usage_warning_threshold,
+                    ),
+                );
+
+                let (new_extranonce_size, new_extranonce_prefix) = {
+                    let mut factory = self
+                        .extranonce_factories
+                        .get_mut(&aggregated_channel_id)
+                        .expect("extranonce_prefix_factory should be set after creation");
+                    let new_extranonce_size = factory.get_range2_len() as u16;
+                    let new_extranonce_prefix = factory
+                        .next_prefix_extended(new_extranonce_size as usize)
+                        .expect("next_prefix_extended should return a value for valid input")
+                        .into_b032();
+                    (new_extranonce_size, new_extranonce_prefix)
+                };
+                // The upstream channel ID may already be taken by a downstream channel of
+                // another aggregated channel
+                let downstream_channel_id = if self.extended_channels.contains_key(&m.channel_id) {
+                    self.next_downstream_channel_id()
+                } else {
+                    m.channel_id
+                };
                 let new_downstream_extended_channel = ExtendedChannel::new(
-                    m.channel_id,
+                    downstream_channel_id,
                     user_identity.clone(),
                     new_extranonce_prefix.clone().into_static().to_vec(),
                     target,
@@ -174,10 +209,12 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
                     new_extranonce_size,
                 );
                 self.extended_channels
-                    .insert(m.channel_id, new_downstream_extended_channel);
+                    .insert(downstream_channel_id, new_downstream_extended_channel);
+                self.aggregated_channel_ids
+                    .insert(downstream_channel_id, aggregated_channel_id);
                 let new_open_extended_mining_channel_success = OpenExtendedMiningChannelSuccess {
                     request_id: m.request_id,
-                    channel_id: m.channel_id,
+                    channel_id: downstream_channel_id,
                     extranonce_prefix: new_extranonce_prefix,
                     extranonce_size: new_extranonce_size,
                     target: m.target.clone(),
@@ -215,7 +252,11 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
                         )
                         .expect("Failed to create ExtendedExtranonce factory - likely extranonce size configuration issue");
                     // Store the factory for this specific channel
-                    let mut factory = extended_extranonce_factory;
+                    let mut factory = TrackedExtranonceFactory::new(
+                        extended_extranonce_factory,
+                        translator_proxy_extranonce_prefix_len,
+                        self.extranonce_usage_warning_threshold,
+                    );
                     let new_extranonce_prefix = factory
                         .next_prefix_extended(downstream_extranonce_len)
                         .expect("Failed to generate extranonce prefix")
@@ -278,9 +319,28 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
         _tlv_fields: Option<&[Tlv]>,
     ) -> Result<(), Self::Error> {
         warn!("Received: {}", m);
-        Err(TproxyError::fallback(
-            TproxyErrorKind::OpenMiningChannelError,
-        ))
+        if m.error_code.as_utf8_or_hex() == MIN_EXTRANONCE_SIZE_TOO_LARGE
+            && self
+                .retry_open_channel_with_smaller_extranonce(m.request_id)
+                .await?
+        {
+            return Ok(());
+        }
+        // The SV1 server retries the channel or gives up on it, as configured, whether the
+        // upstream that refused it is routed or the primary one
+        self.pending_channels
+            .remove(&(m.request_id as DownstreamId));
+        self.pending_open_channel_requests
+            .remove(&(m.request_id as DownstreamId));
+        self.channel_state
+            .sv1_server_sender
+            .send((Mining::OpenMiningChannelError(m.into_static()), None))
+            .await
+            .map_err(|e| {
+                error!("Failed to send OpenMiningChannelError: {:?}", e);
+                TproxyError::shutdown(TproxyErrorKind::ChannelErrorSender)
+            })?;
+        Ok(())
     }
 
     async fn handle_update_channel_error(
@@ -290,6 +350,8 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
         _tlv_fields: Option<&[Tlv]>,
     ) -> Result<(), Self::Error> {
         warn!("Received: {}", m);
+        self.correct_rejected_channel_update(m.channel_id, &m.error_code.as_utf8_or_hex())
+            .await?;
         Ok(())
     }
 
@@ -310,32 +372,45 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
             ));
         }
 
-        let group_channel = self.group_channels.remove(&m.channel_id);
-
-        // we're not in aggregated mode
-        // was the message sent to a group channel?
-        if let Some((_, group_channel)) = group_channel {
-            for channel_id in group_channel.get_channel_ids() {
-                self.extended_channels.remove(channel_id);
-            }
-        // if the message was not sent to a group channel, and we're not working in
-        // aggregated mode,
-        } else if self.extended_channels.contains_key(&m.channel_id) {
-            // remove the channel from the extended channels map
-            self.extended_channels.remove(&m.channel_id);
-
-            // remove the channel from any group channels that contain it
-            for mut group_channel in self.group_channels.iter_mut() {
-                if group_channel.get_channel_ids().contains(&m.channel_id) {
-                    group_channel.remove_channel_id(m.channel_id);
-                }
-            }
-        } else {
+        // we're not in aggregated mode, so a group channel close disconnects every miner of
+        // the group, while an extended channel close disconnects only the miner behind it
+        let Some(closed_channels) = self.remove_closed_channels(m.channel_id) else {
             error!(
                 "Channel Id not found: {}, ignoring CloseChannel message",
                 m.channel_id
             );
             return Err(TproxyError::log(TproxyErrorKind::ChannelNotFound));
+        };
+
+        match &closed_channels {
+            ClosedChannels::Group {
+                group_channel_id,
+                channel_ids,
+            } => info!(
+                "Upstream closed group channel {}: disconnecting {} miner(s)",
+                group_channel_id,
+                channel_ids.len()
+            ),
+            ClosedChannels::Extended(channel_id) => info!(
+                "Upstream closed extended channel {}: disconnecting 1 miner",
+                channel_id
+            ),
+        }
+
+        for channel_id in closed_channels.channel_ids() {
+            let close_channel = CloseChannel {
+                channel_id: *channel_id,
+                ..m.clone()
+            }
+            .into_static();
+            self.channel_state
+                .sv1_server_sender
+                .send((Mining::CloseChannel(close_channel), None))
+                .await
+                .map_err(|e| {
+                    error!("Failed to send CloseChannel to Sv1Server: {:?}", e);
+                    TproxyError::shutdown(TproxyErrorKind::ChannelErrorSender)
+                })?;
         }
 
         Ok(())
@@ -347,8 +422,83 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
         m: SetExtranoncePrefix<'_>,
         _tlv_fields: Option<&[Tlv]>,
     ) -> Result<(), Self::Error> {
-        warn!("Received: {}", m);
-        warn!("⚠️ Cannot process SetExtranoncePrefix since set_extranonce is not supported for majority of sv1 clients. Ignoring.");
+        info!("Received: {}", m);
+        let channel_id = m.channel_id;
+        let upstream_prefix = m.extranonce_prefix.inner_as_ref().to_vec();
+
+        // The downstream channels derived from the upstream channel keep the bytes the translator
+        // appended to its prefix, only the upstream part of their prefix changes
+        let aggregated_channel_id = is_aggregated()
+            .then(|| self.aggregated_channel_of_upstream(channel_id))
+            .flatten();
+        let factory_id = aggregated_channel_id.unwrap_or(channel_id);
+        let downstream_channel_ids: Vec<_> = match aggregated_channel_id {
+            Some(aggregated_channel_id) => self.aggregated_channel_members(aggregated_channel_id),
+            None => vec![channel_id],
+        };
+        let invalid_prefix = || {
+            error!("Cannot use the extranonce prefix of channel {}", channel_id);
+            TproxyError::fallback(TproxyErrorKind::InvalidExtranoncePrefix(channel_id))
+        };
+
+        // The extranonce sizes negotiated with the miners cannot change
+        let old_upstream_prefix_len = match self.extranonce_factories.get(&factory_id) {
+            Some(factory) => factory.get_range0_len(),
+            None => self
+                .extended_channels
+                .get(&channel_id)
+                .ok_or_else(invalid_prefix)?
+                .get_extranonce_prefix()
+                .len(),
+        };
+        if upstream_prefix.len() != old_upstream_prefix_len {
+            return Err(invalid_prefix());
+        }
+        if let Some(aggregated_channel_id) = aggregated_channel_id {
+            self.extended_channels
+                .get_mut(&aggregated_channel_id)
+                .ok_or_else(invalid_prefix)?
+                .set_extranonce_prefix(upstream_prefix.clone())
+                .map_err(|_| invalid_prefix())?;
+        }
+        if let Some(mut factory) = self.extranonce_factories.get_mut(&factory_id) {
+            factory
+                .rebase(m.extranonce_prefix.clone().into())
+                .map_err(|_| invalid_prefix())?;
+        }
+
+        for downstream_channel_id in downstream_channel_ids {
+            let extranonce_prefix = {
+                let Some(mut channel) = self.extended_channels.get_mut(&downstream_channel_id)
+                else {
+                    continue;
+                };
+                let mut extranonce_prefix = upstream_prefix.clone();
+                extranonce_prefix
+                    .extend_from_slice(&channel.get_extranonce_prefix()[old_upstream_prefix_len..]);
+                channel
+                    .set_extranonce_prefix(extranonce_prefix.clone())
+                    .map_err(|_| invalid_prefix())?;
+                extranonce_prefix
+            };
+            debug!(
+                "Extranonce prefix of channel {} changed to {}",
+                downstream_channel_id,
+                hex::encode(&extranonce_prefix)
+            );
+            let set_extranonce_prefix = SetExtranoncePrefix {
+                channel_id: downstream_channel_id,
+                extranonce_prefix: extranonce_prefix.try_into().map_err(|_| invalid_prefix())?,
+            };
+            self.channel_state
+                .sv1_server_sender
+                .send((Mining::SetExtranoncePrefix(set_extranonce_prefix), None))
+                .await
+                .map_err(|e| {
+                    error!("Failed to send SetExtranoncePrefix to Sv1Server: {:?}", e);
+                    TproxyError::shutdown(TproxyErrorKind::ChannelErrorSender)
+                })?;
+        }
         Ok(())
     }
 
@@ -368,7 +518,12 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
         m: SubmitSharesError<'_>,
         _tlv_fields: Option<&[Tlv]>,
     ) -> Result<(), Self::Error> {
-        warn!("Received: {} ❌", m);
+        if let Some(suppressed) = self
+            .log_throttle
+            .admit("submit_shares_error", &m.error_code.as_utf8_or_hex())
+        {
+            warn!("Received: {} ❌{suppressed}", m);
+        }
         Ok(())
     }
 
@@ -390,6 +545,7 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
         _tlv_fields: Option<&[Tlv]>,
     ) -> Result<(), Self::Error> {
         info!("Received: {}", m);
+        self.record_job_activity(m.channel_id);
         let m_static = m.clone().into_static();
 
         // we update the channel states and keep track of the messages that need to be sent to the
@@ -399,30 +555,31 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
 
             // are we in aggregated mode?
             if is_aggregated() {
-                // Validate that the message is for the aggregated channel or its group
-                let aggregated_channel_id = self
-                    .extended_channels
-                    .get(&AGGREGATED_CHANNEL_ID)
-                    .ok_or(TproxyError::fallback(TproxyErrorKind::ChannelNotFound))?
-                    .get_channel_id();
-
-                // here, we are assuming that since we are in aggregated mode, there should
-                // be only one single group channel and the
-                // aggregated channel must belong to it
-                let group_channel = self.group_channels.iter().next();
-                let Some(group_channel) = group_channel else {
-                    error!("Aggregated channel does not belong to any group channel");
-                    return Err(TproxyError::fallback(TproxyErrorKind::ChannelNotFound));
-                };
-
-                let group_channel_id = group_channel.get_group_channel_id();
+                // Validate that the message is for an aggregated channel or their group
+                let aggregated_channel_ids =
+                    self.addressed_aggregated_channels(m_static.channel_id)?;
+                if aggregated_channel_ids.is_empty() {
+                    // we got a nonsense channel id, we should log an error and ignore the
+                    // message
+                    error!(
+                        "Channel not found: {}, ignoring NewExtendedMiningJob message",
+                        m_static.channel_id
+                    );
+                    return Err(TproxyError::log(TproxyErrorKind::ChannelNotFound));
+                }
 
-                // was the message sent to the aggregated channel?
-                if aggregated_channel_id == m_static.channel_id
-                    || group_channel_id == m_static.channel_id
-                {
-                    // update all extended channel states
-                    for mut extended_channel in self.extended_channels.iter_mut() {
+                for aggregated_channel_id in aggregated_channel_ids {
+                    // update the states of the aggregated channel and of the downstream channels
+                    // opened on it
+                    let channel_ids = std::iter::once(aggregated_channel_id)
+                        .chain(self.aggregated_channel_members(aggregated_channel_id));
+                    for channel_id in channel_ids {
+                        let Some(mut extended_channel) =
+                            self.extended_channels.get_mut(&channel_id)
+                        else {
+                            continue;
+                        };
+                        validate_job_coinbase(&m_static, &extended_channel)?;
                         extended_channel
                             .on_new_extended_mining_job(m_static.clone())
                             .map_err(|e| {
@@ -435,19 +592,12 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
 
                     // only send this message to the SV1Server if it's not a future job
                     if !m_static.is_future() {
+                        // this is done so that every downstream of the aggregated channel will
+                        // receive the NewExtendedMiningJob message
                         let mut new_extended_mining_job_message = m_static.clone();
-                        new_extended_mining_job_message.channel_id = AGGREGATED_CHANNEL_ID; // this is done so that every aggregated downstream
-                                                                                            // will receive the NewExtendedMiningJob message
+                        new_extended_mining_job_message.channel_id = aggregated_channel_id;
                         new_extended_mining_job_messages.push(new_extended_mining_job_message);
                     }
-                } else {
-                    // we got a nonsense channel id, we should log an error and ignore the
-                    // message
-                    error!(
-                        "Channel not found: {}, ignoring NewExtendedMiningJob message",
-                        m_static.channel_id
-                    );
-                    return Err(TproxyError::log(TproxyErrorKind::ChannelNotFound));
                 }
             // we're not in aggregated mode
             // was the message sent to a group channel?
@@ -464,6 +614,7 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
 
                     let mut job = m_static.clone();
                     job.channel_id = *channel_id;
+                    validate_job_coinbase(&job, &channel)?;
 
                     // update each channel state
                     channel
@@ -494,6 +645,7 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
                 };
 
                 // update channel state
+                validate_job_coinbase(&m_static, &channel)?;
                 channel
                     .on_new_extended_mining_job(m_static.clone())
                     .map_err(|e| {
@@ -531,7 +683,9 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
         _tlv_fields: Option<&[Tlv]>,
     ) -> Result<(), Self::Error> {
         info!("Received: {}", m);
-        let mut m_static = m.clone().into_static();
+        self.record_job_activity(m.channel_id);
+        self.check_clock_skew(m.min_ntime)?;
+        let m_static = m.clone().into_static();
 
         // we update the channel states and keep track of the messages that need to be sent to the
         // SV1Server
@@ -541,29 +695,30 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
                 let mut new_extended_mining_job_messages = Vec::new();
 
                 if is_aggregated() {
-                    // Validate that the message is for the aggregated channel or its group
-                    let aggregated_channel_id = self
-                        .extended_channels
-                        .get(&AGGREGATED_CHANNEL_ID)
-                        .ok_or(TproxyError::fallback(TproxyErrorKind::ChannelNotFound))?
-                        .get_channel_id();
-
-                    // does aggregated channel belong to some group channel?
-                    // here, we are assuming that since we are in aggregated mode, there
-                    // should be only one single group channel
-                    // and the aggregated channel must belong to it
-                    let group_channel = self.group_channels.iter().next();
-                    let Some(group_channel) = group_channel else {
-                        error!("Aggregated channel does not belong to any group channel");
-                        return Err(TproxyError::fallback(TproxyErrorKind::ChannelNotFound));
-                    };
-
-                    let group_channel_id = group_channel.get_group_channel_id();
+                    // Validate that the message is for an aggregated channel or their group
+                    let aggregated_channel_ids =
+                        self.addressed_aggregated_channels(m.channel_id)?;
+                    if aggregated_channel_ids.is_empty() {
+                        // we got a nonsense channel id, we should log an error and ignore
+                        // the message
+                        warn!(
+                            "Channel not found: {}, ignoring SetNewPrevHash message",
+                            m_static.channel_id
+                        );
+                        return Err(TproxyError::log(TproxyErrorKind::ChannelNotFound));
+                    }
 
-                    // was the message sent to the aggregated channel?
-                    if aggregated_channel_id == m.channel_id || group_channel_id == m.channel_id {
-                        // update all extended channel states
-                        for mut extended_channel in self.extended_channels.iter_mut() {
+                    for aggregated_channel_id in aggregated_channel_ids {
+                        // update the states of the aggregated channel and of the downstream
+                        // channels opened on it
+                        let channel_ids = std::iter::once(aggregated_channel_id)
+                            .chain(self.aggregated_channel_members(aggregated_channel_id));
+                        for channel_id in channel_ids {
+                            let Some(mut extended_channel) =
+                                self.extended_channels.get_mut(&channel_id)
+                            else {
+                                continue;
+                            };
                             extended_channel
                                 .on_set_new_prev_hash(m_static.clone())
                                 .map_err(|e| {
@@ -576,28 +731,21 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
 
                         // make sure the SetNewPrevHash message is sent to the aggregated
                         // channel
-                        m_static.channel_id = AGGREGATED_CHANNEL_ID;
-                        set_new_prev_hash_messages.push(m_static.clone());
+                        let mut set_new_prev_hash_message = m_static.clone();
+                        set_new_prev_hash_message.channel_id = aggregated_channel_id;
+                        set_new_prev_hash_messages.push(set_new_prev_hash_message);
 
                         // for the aggregated channel, send one NewExtendedMiningJob message
                         // to the SV1Server (get active job after updating all channels)
                         let mut new_extended_mining_job_message = self
                             .extended_channels
-                            .get(&AGGREGATED_CHANNEL_ID)
+                            .get(&aggregated_channel_id)
                             .expect("aggregated channel must exist")
                             .get_active_job()
                             .expect("active job must exist")
                             .clone();
-                        new_extended_mining_job_message.0.channel_id = AGGREGATED_CHANNEL_ID;
+                        new_extended_mining_job_message.0.channel_id = aggregated_channel_id;
                         new_extended_mining_job_messages.push(new_extended_mining_job_message.0);
-                    } else {
-                        // we got a nonsense channel id, we should log an error and ignore
-                        // the message
-                        warn!(
-                            "Channel not found: {}, ignoring SetNewPrevHash message",
-                            m_static.channel_id
-                        );
-                        return Err(TproxyError::log(TproxyErrorKind::ChannelNotFound));
                     }
                 // we are not in aggregated mode.. was the message sent to a group channel?
                 } else if let Some(mut group_channel) = self.group_channels.get_mut(&m.channel_id) {
@@ -752,40 +900,10 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
 
             // are in aggregated mode?
             if is_aggregated() {
-                let aggregated_channel_id = self
-                    .extended_channels
-                    .get(&AGGREGATED_CHANNEL_ID)
-                    .ok_or(TproxyError::fallback(TproxyErrorKind::ChannelNotFound))?
-                    .get_channel_id();
-
-                // does aggregated channel belong to some group channel?
-                // here, we are assuming that since we are in aggregated mode, there should
-                // be only one single group channel and the
-                // aggregated channel must belong to it
-                let group_channel = self.group_channels.iter().next();
-                let Some(group_channel) = group_channel else {
-                    error!("Aggregated channel does not belong to any group channel");
-                    return Err(TproxyError::fallback(TproxyErrorKind::ChannelNotFound));
-                };
-
-                let group_channel_id = group_channel.get_group_channel_id();
-
-                // was the message sent to the aggregated channel?
-                if aggregated_channel_id == m.channel_id || group_channel_id == m.channel_id {
-                    // Update target for all extended channels (including AGGREGATED_CHANNEL_ID)
-                    self.extended_channels.iter_mut().for_each(|mut channel| {
-                        channel.set_target(Target::from_le_bytes(
-                            m.maximum_target
-                                .inner_as_ref()
-                                .try_into()
-                                .expect("target deserialization should never fail"),
-                        ));
-                    });
-
-                    let mut message = m_static.clone();
-                    message.channel_id = AGGREGATED_CHANNEL_ID;
-                    set_target_messages.push(message);
-                } else {
+                // was the message sent to an aggregated channel or to their group channel? either
+                // way it applies to every downstream sharing the aggregated channel(s)
+                let aggregated_channel_ids = self.addressed_aggregated_channels(m.channel_id)?;
+                if aggregated_channel_ids.is_empty() {
                     // we got a nonsense channel id, we should log an error and ignore the
                     // message
                     warn!(
@@ -795,6 +913,27 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
                     return Err(TproxyError::log(TproxyErrorKind::ChannelNotFound));
                 }
 
+                for aggregated_channel_id in aggregated_channel_ids {
+                    // Update target for the aggregated channel and the downstream channels
+                    // opened on it
+                    let channel_ids = std::iter::once(aggregated_channel_id)
+                        .chain(self.aggregated_channel_members(aggregated_channel_id));
+                    for channel_id in channel_ids {
+                        if let Some(mut channel) = self.extended_channels.get_mut(&channel_id) {
+                            channel.set_target(Target::from_le_bytes(
+                                m.maximum_target
+                                    .inner_as_ref()
+                                    .try_into()
+                                    .expect("target deserialization should never fail"),
+                            ));
+                        }
+                    }
+
+                    let mut message = m_static.clone();
+                    message.channel_id = aggregated_channel_id;
+                    set_target_messages.push(message);
+                }
+
             // we are not in aggregated mode... was the message sent to a group channel?
             } else if let Some(group_channel) = self.group_channels.get(&m.channel_id) {
                 // process the message for each individual channel on the group
@@ -864,90 +1003,72 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
     ) -> Result<(), Self::Error> {
         info!("Received: {}", m);
 
-        // remove every channel from any group channels that end up empty
+        // `SetGroupChannel` redefines the group of the listed channels only: they move out of the
+        // group they were in, the other members of the target group stay in it. The group
+        // channels are the only record of the membership, group-directed jobs are routed
+        // through them.
+        let channel_ids = m.channel_ids.clone().into_inner();
         let mut group_channels_to_remove = Vec::new();
-
-        // check every group channel if it contains any of the channels in the new group
-        // channel
         for mut channel in self.group_channels.iter_mut() {
             let group_channel_id = *channel.key();
+            if group_channel_id == m.group_channel_id {
+                continue;
+            }
             let group_channel = channel.value_mut();
-
-            let channel_ids_to_remove = m.channel_ids.clone().into_inner();
-            for channel_id in channel_ids_to_remove {
-                group_channel.remove_channel_id(channel_id);
+            for channel_id in &channel_ids {
+                group_channel.remove_channel_id(*channel_id);
             }
-
             if group_channel.get_channel_ids().is_empty() {
                 group_channels_to_remove.push(group_channel_id);
             }
         }
 
-        // Now remove the empty group channels
+        // Now remove the group channels left empty
         for group_channel_id in group_channels_to_remove {
+            debug!("Group channel {group_channel_id} left empty, removing it");
             self.group_channels.remove(&group_channel_id);
         }
 
-        // does the group channel already exist?
-        match self.group_channels.get_mut(&m.group_channel_id) {
-            // if yes, clean up any channels that are no longer in the new group channel
-            Some(mut group_channel) => {
-                let current_channel_ids = group_channel.get_channel_ids().clone();
-                let new_channel_ids = m.channel_ids.clone().into_inner();
-
-                // Remove channels that are no longer in the new list
-                for channel_id in &current_channel_ids {
-                    if !new_channel_ids.contains(channel_id) {
-                        group_channel.remove_channel_id(*channel_id);
-                    }
-                }
-
-                // Add all channels from the message (inner HashSet ingores duplicates)
-                for channel_id in new_channel_ids {
-                    let extended_channel = self
-                        .extended_channels
-                        .get(&channel_id)
-                        .ok_or_else(|| TproxyError::fallback(TproxyErrorKind::ChannelNotFound))?;
-
-                    let full_extranonce_size = extended_channel.get_full_extranonce_size();
-                    group_channel
-                        .add_channel_id(channel_id, full_extranonce_size)
-                        .map_err(|e| {
-                            error!("Failed to add channel id to group channel: {:?}", e);
-                            TproxyError::fallback(
-                                TproxyErrorKind::FailedToAddChannelIdToGroupChannel(e),
-                            )
-                        })?;
-                }
-            }
-            // if no, create a new group channel, and add all the channels to it
-            None => {
-                let mut group_channel = GroupChannel::new(m.group_channel_id);
-
-                // Add all channels to the newly created group channel
-                for channel_id in m.channel_ids.clone().into_inner() {
-                    let extended_channel = self
-                        .extended_channels
-                        .get(&channel_id)
-                        .ok_or_else(|| TproxyError::fallback(TproxyErrorKind::ChannelNotFound))?;
-
-                    let full_extranonce_size = extended_channel.get_full_extranonce_size();
-
-                    group_channel
-                        .add_channel_id(channel_id, full_extranonce_size)
-                        .map_err(|e| {
-                            error!("Failed to add channel id to group channel: {:?}", e);
-                            TproxyError::fallback(
-                                TproxyErrorKind::FailedToAddChannelIdToGroupChannel(e),
-                            )
-                        })?;
-                }
-
-                self.group_channels
-                    .insert(m.group_channel_id, group_channel);
-            }
+        // add the channels to the group channel, creating it if it does not exist yet
+        let mut group_channel = self
+            .group_channels
+            .entry(m.group_channel_id)
+            .or_insert_with(|| GroupChannel::new(m.group_channel_id));
+        for channel_id in channel_ids {
+            let full_extranonce_size = self
+                .extended_channels
+                .get(&channel_id)
+                .ok_or_else(|| TproxyError::fallback(TproxyErrorKind::ChannelNotFound))?
+                .get_full_extranonce_size();
+            // the inner HashSet ignores channels already in the group
+            group_channel
+                .add_channel_id(channel_id, full_extranonce_size)
+                .map_err(|e| {
+                    error!("Failed to add channel id to group channel: {:?}", e);
+                    TproxyError::fallback(TproxyErrorKind::FailedToAddChannelIdToGroupChannel(e))
+                })?;
         }
 
         Ok(())
     }
 }
+
+// Rejects a job whose coinbase is not split where the extranonce of `channel` goes, as every
+// share on it would be invalid.
+fn validate_job_coinbase(
+    job: &NewExtendedMiningJob<'_>,
+    channel: &ExtendedChannel<'_>,
+) -> Result<(), TproxyError<error::ChannelManager>> {
+    validate_coinbase_split(
+        job.coinbase_tx_prefix.inner_as_ref(),
+        job.coinbase_tx_suffix.inner_as_ref(),
+        channel.get_full_extranonce_size(),
+    )
+    .map_err(|e| {
+        error!(
+            "Invalid coinbase of job {} on channel {}: {e}",
+            job.job_id, job.channel_id
+        );
+        TproxyError::fallback(TproxyErrorKind::InvalidCoinbaseSplit(e))
+    })
+}
@@ -1,5 +1,7 @@
 pub mod channel_manager;
 pub mod extensions_message_handler;
+pub mod extranonce_factory;
 pub mod mining_message_handler;
+pub mod upstream_router;
 pub use channel_manager::ChannelManager;
 pub(super) mod channel;
@@ -0,0 +1,563 @@
+//! Routing of downstream channels to the upstreams selected by the `upstream_routes` rules.
+//!
+//! Routed upstreams are connected alongside the primary upstream, each over its own connection.
+//! Their frames reach the channel manager through the queue of the primary upstream, once the
+//! channel IDs they assigned are replaced with local ones allocated from
+//! [`ROUTED_CHANNEL_ID_BASE`], so channels of different upstreams never share an ID. Frames sent
+//! on a routed channel get the upstream channel ID back and go to the upstream it was opened on.
+//!
+//! Routed upstreams are asked for the same `required_extensions` as the primary upstream, and the
+//! router completes that negotiation itself, since the channel manager only tracks the extensions
+//! of the primary upstream.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use async_channel::Sender;
+use dashmap::DashMap;
+use stratum_apps::{
+    custom_mutex::Mutex,
+    stratum_core::{
+        binary_sv2::{Seq064K, Str0255},
+        extensions_sv2::RequestExtensions,
+        mining_sv2::CloseChannel,
+        parsers_sv2::{AnyMessage, ExtensionsNegotiation, Mining},
+    },
+    utils::{
+        protocol_message_type::{protocol_message_type, MessageType},
+        types::{ChannelId, RequestId, Sv2Frame},
+    },
+};
+use tracing::{debug, info, warn};
+
+use crate::error::TproxyErrorKind;
+
+/// First local channel ID given to the channels of routed upstreams. Upstreams assign their
+/// channel IDs from the bottom of the range, so the primary upstream is not expected to reach it.
+pub const ROUTED_CHANNEL_ID_BASE: ChannelId = 1 << 31;
+
+/// State shared by the SV1 server and the channel manager to route channels to upstreams.
+#[derive(Debug)]
+pub struct UpstreamRouter {
+    // Senders to the routed upstreams currently connected, by index in the `upstreams` list.
+    upstreams: DashMap<usize, Sender<Sv2Frame>>,
+    // Index of the upstream the primary connection currently uses.
+    primary: Mutex<Option<usize>>,
+    // Routed upstream of each `OpenExtendedMiningChannel` waiting for an answer.
+    requests: DashMap<RequestId, usize>,
+    // Upstream index and upstream channel ID of each local channel ID, and the reverse.
+    local_to_upstream: DashMap<ChannelId, (usize, ChannelId)>,
+    upstream_to_local: DashMap<(usize, ChannelId), ChannelId>,
+    next_channel_id: AtomicU32,
+    // Extensions the translator supports and requires, as configured.
+    supported_extensions: Vec<u16>,
+    required_extensions: Vec<u16>,
+    // Extensions negotiated with each routed upstream.
+    negotiated_extensions: DashMap<usize, Vec<u16>>,
+}
+
+impl Default for UpstreamRouter {
+    fn default() -> Self {
+        Self {
+            upstreams: DashMap::new(),
+            primary: Mutex::new(None),
+            requests: DashMap::new(),
+            local_to_upstream: DashMap::new(),
+            upstream_to_local: DashMap::new(),
+            next_channel_id: AtomicU32::new(ROUTED_CHANNEL_ID_BASE),
+            supported_extensions: Vec::new(),
+            required_extensions: Vec::new(),
+            negotiated_extensions: DashMap::new(),
+        }
+    }
+}
+
+impl UpstreamRouter {
+    /// Sets the extensions negotiated with the routed upstreams, like with the primary upstream.
+    pub fn with_extensions(
+        mut self,
+        supported_extensions: Vec<u16>,
+        required_extensions: Vec<u16>,
+    ) -> Self {
+        self.supported_extensions = supported_extensions;
+        self.required_extensions = required_extensions;
+        self
+    }
+
+    /// Records the index of the upstream the primary connection uses. Channels routed to it are
+    /// opened on the primary connection.
+    pub fn set_primary_upstream(&self, upstream: usize) {
+        self.primary
+            .super_safe_lock(|primary| *primary = Some(upstream));
+    }
+
+    /// Returns whether the primary connection uses the upstream at index `upstream`.
+    pub fn is_primary_upstream(&self, upstream: usize) -> bool {
+        self.primary
+            .super_safe_lock(|primary| *primary == Some(upstream))
+    }
+
+    /// Registers the routed upstream at index `upstream`, reachable through `sender`.
+    pub fn connect_upstream(&self, upstream: usize, sender: Sender<Sv2Frame>) {
+        self.upstreams.insert(upstream, sender);
+    }
+
+    /// Returns whether channels can be opened on the upstream at index `upstream`, either on the
+    /// primary connection or on its own.
+    pub fn is_upstream_connected(&self, upstream: usize) -> bool {
+        self.is_primary_upstream(upstream) || self.upstreams.contains_key(&upstream)
+    }
+
+    /// Returns whether `channel_id` was opened on the upstream at index `upstream`.
+    pub fn is_channel_on_upstream(&self, channel_id: ChannelId, upstream: usize) -> bool {
+        match self.local_to_upstream.get(&channel_id) {
+            Some(entry) => entry.0 == upstream,
+            None => self.is_primary_upstream(upstream),
+        }
+    }
+
+    /// Returns whether `extension` was negotiated with the routed upstream `channel_id` was
+    /// opened on, or `None` for the channels of the primary upstream.
+    pub fn has_routed_extension(&self, channel_id: ChannelId, extension: u16) -> Option<bool> {
+        let (upstream, _) = *self.local_to_upstream.get(&channel_id)?;
+        Some(
+            self.negotiated_extensions
+                .get(&upstream)
+                .is_some_and(|negotiated| negotiated.contains(&extension)),
+        )
+    }
+
+    /// Forgets the routed upstream at index `upstream` after its connection ended.
+    ///
+    /// Returns the local IDs of the channels that were open on it.
+    pub fn disconnect_upstream(&self, upstream: usize) -> Vec<ChannelId> {
+        self.upstreams.remove(&upstream);
+        self.negotiated_extensions.remove(&upstream);
+        self.requests.retain(|_, routed| *routed != upstream);
+        self.upstream_to_local
+            .retain(|(routed, _), _| *routed != upstream);
+        let mut channel_ids = Vec::new();
+        self.local_to_upstream.retain(|local, (routed, _)| {
+            if *routed == upstream {
+                channel_ids.push(*local);
+            }
+            *routed != upstream
+        });
+        channel_ids
+    }
+
+    /// Routes the `OpenExtendedMiningChannel` of `request_id` to the upstream at index `upstream`.
+    pub fn set_request_upstream(&self, request_id: RequestId, upstream: usize) {
+        self.requests.insert(request_id, upstream);
+    }
+
+    /// Returns the sender of the routed upstream the `OpenExtendedMiningChannel` of `request_id`
+    /// goes to, or `None` if it goes to the primary upstream.
+    ///
+    /// A request routed to an upstream that is not connected falls back to the primary one.
+    pub fn route_request(&self, request_id: RequestId) -> Option<Sender<Sv2Frame>> {
+        let upstream = *self.requests.get(&request_id)?;
+        let sender = if self.is_primary_upstream(upstream) {
+            None
+        } else {
+            let sender = self.upstreams.get(&upstream).map(|sender| sender.clone());
+            if sender.is_none() {
+                warn!(
+                    "Upstream {} is not connected, opening the channel of request {} on the primary upstream",
+                    upstream, request_id
+                );
+            }
+            sender
+        };
+        if sender.is_none() {
+            self.requests.remove(&request_id);
+        }
+        sender
+    }
+
+    /// Returns whether the `OpenExtendedMiningChannel` of `request_id` was sent to a routed
+    /// upstream, forgetting it.
+    pub fn take_request(&self, request_id: RequestId) -> bool {
+        self.requests.remove(&request_id).is_some()
+    }
+
+    /// Returns the sender of the routed upstream `channel_id` was opened on, replacing
+    /// `channel_id` with the ID the upstream assigned. Returns `None`, leaving `channel_id`
+    /// untouched, for the channels of the primary upstream.
+    pub fn route_channel(&self, channel_id: &mut ChannelId) -> Option<Sender<Sv2Frame>> {
+        let (upstream, upstream_channel_id) = *self.local_to_upstream.get(channel_id)?;
+        let sender = self.upstreams.get(&upstream)?.clone();
+        *channel_id = upstream_channel_id;
+        Some(sender)
+    }
+
+    /// Like [`Self::route_channel`], forgetting the channel since it is being closed.
+    pub fn release_channel(&self, channel_id: &mut ChannelId) -> Option<Sender<Sv2Frame>> {
+        let local = *channel_id;
+        let sender = self.route_channel(channel_id)?;
+        if let Some((_, upstream_channel)) = self.local_to_upstream.remove(&local) {
+            self.upstream_to_local.remove(&upstream_channel);
+        }
+        Some(sender)
+    }
+
+    /// Rewrites a frame received from the routed upstream at index `upstream` with local channel
+    /// IDs, ready for the channel manager.
+    ///
+    /// The answers to `RequestExtensions` are handled here, so `None` is returned for them, as
+    /// for the other frames that are not mining messages and those that cannot be parsed. Fails
+    /// if the upstream does not agree to the extensions the translator requires, or requires some
+    /// it does not support, in which case it has to be disconnected.
+    pub fn remap_inbound(
+        &self,
+        upstream: usize,
+        mut sv2_frame: Sv2Frame,
+    ) -> Result<Option<Sv2Frame>, TproxyErrorKind> {
+        let Some(header) = sv2_frame.get_header() else {
+            return Ok(None);
+        };
+        match protocol_message_type(header.ext_type(), header.msg_type()) {
+            MessageType::Mining => {}
+            MessageType::Extensions => {
+                match ExtensionsNegotiation::try_from((header.msg_type(), sv2_frame.payload())) {
+                    Ok(message) => self.handle_extensions_message(upstream, message)?,
+                    Err(e) => warn!(
+                        "Failed to parse extensions message from routed upstream {upstream}: {e:?}"
+                    ),
+                }
+                return Ok(None);
+            }
+            _ => {
+                debug!(
+                    "Dropping non-mining message {:#04x} from routed upstream {}",
+                    header.msg_type(),
+                    upstream
+                );
+                return Ok(None);
+            }
+        }
+        let mut message = match Mining::try_from((header.msg_type(), sv2_frame.payload())) {
+            Ok(message) => message.into_static(),
+            Err(e) => {
+                warn!("Failed to parse message from routed upstream {upstream}: {e:?}");
+                return Ok(None);
+            }
+        };
+        self.remap_inbound_message(upstream, &mut message);
+        Ok(AnyMessage::Mining(message).try_into().ok())
+    }
+
+    // Records the extensions the routed upstream at index `upstream` agreed to, asking again with
+    // the ones it requires if the translator supports them.
+    fn handle_extensions_message(
+        &self,
+        upstream: usize,
+        message: ExtensionsNegotiation<'_>,
+    ) -> Result<(), TproxyErrorKind> {
+        match message {
+            ExtensionsNegotiation::RequestExtensionsSuccess(m) => {
+                let supported: Vec<u16> = m.supported_extensions.into_inner();
+                info!(
+                    "Extension negotiation with routed upstream {} succeeded: supported={:?}",
+                    upstream, supported
+                );
+                let missing_required: Vec<u16> = self
+                    .required_extensions
+                    .iter()
+                    .filter(|ext| !supported.contains(ext))
+                    .copied()
+                    .collect();
+                self.negotiated_extensions.insert(upstream, supported);
+                if !missing_required.is_empty() {
+                    return Err(TproxyErrorKind::RequiredExtensionsNotSupported(
+                        missing_required,
+                    ));
+                }
+            }
+            ExtensionsNegotiation::RequestExtensionsError(m) => {
+                let unsupported: Vec<u16> = m.unsupported_extensions.into_inner();
+                let required_by_server: Vec<u16> = m.required_extensions.into_inner();
+                warn!(
+                    "Extension negotiation with routed upstream {} failed: unsupported={:?}, \
+                     required_by_server={:?}",
+                    upstream, unsupported, required_by_server
+                );
+                let missing_required: Vec<u16> = self
+                    .required_extensions
+                    .iter()
+                    .filter(|ext| unsupported.contains(ext))
+                    .copied()
+                    .collect();
+                if !missing_required.is_empty() {
+                    return Err(TproxyErrorKind::RequiredExtensionsNotSupported(
+                        missing_required,
+                    ));
+                }
+                let cannot_support: Vec<u16> = required_by_server
+                    .iter()
+                    .filter(|ext| !self.supported_extensions.contains(ext))
+                    .copied()
+                    .collect();
+                if !cannot_support.is_empty() {
+                    return Err(TproxyErrorKind::ServerRequiresUnsupportedExtensions(
+                        cannot_support,
+                    ));
+                }
+                if !required_by_server.is_empty() {
+                    self.request_extensions(upstream, m.request_id + 1, required_by_server);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Sends `RequestExtensions` for `extensions` to the routed upstream at index `upstream`.
+    fn request_extensions(&self, upstream: usize, request_id: u16, extensions: Vec<u16>) {
+        let Some(sender) = self.upstreams.get(&upstream).map(|sender| sender.clone()) else {
+            return;
+        };
+        let Ok(requested_extensions) = Seq064K::new(extensions) else {
+            return;
+        };
+        let request_extensions = RequestExtensions {
+            request_id,
+            requested_extensions,
+        };
+        let Ok(sv2_frame) = Sv2Frame::try_from(AnyMessage::Extensions(
+            request_extensions.into_static().into(),
+        )) else {
+            return;
+        };
+        if sender.try_send(sv2_frame).is_err() {
+            warn!("Failed to send RequestExtensions to routed upstream {upstream}");
+        }
+    }
+
+    fn remap_inbound_message(&self, upstream: usize, message: &mut Mining<'static>) {
+        let local =
+            |channel_id: &mut ChannelId| *channel_id = self.local_channel_id(upstream, *channel_id);
+        match message {
+            Mining::OpenExtendedMiningChannelSuccess(m) => {
+                self.requests.remove(&m.request_id);
+                local(&mut m.channel_id);
+                local(&mut m.group_channel_id);
+            }
+            Mining::OpenStandardMiningChannelSuccess(m) => {
+                self.requests.remove(&m.request_id);
+                local(&mut m.channel_id);
+                local(&mut m.group_channel_id);
+            }
+            Mining::SetGroupChannel(m) => {
+                local(&mut m.group_channel_id);
+                let channel_ids: Vec<ChannelId> = m
+                    .channel_ids
+                    .clone()
+                    .into_inner()
+                    .into_iter()
+                    .map(|channel_id| self.local_channel_id(upstream, channel_id))
+                    .collect();
+                if let Ok(channel_ids) = Seq064K::new(channel_ids) {
+                    m.channel_ids = channel_ids;
+                }
+            }
+            Mining::CloseChannel(CloseChannel { channel_id, .. }) => {
+                local(channel_id);
+                if let Some((_, upstream_channel)) = self.local_to_upstream.remove(channel_id) {
+                    self.upstream_to_local.remove(&upstream_channel);
+                }
+            }
+            Mining::NewExtendedMiningJob(m) => local(&mut m.channel_id),
+            Mining::NewMiningJob(m) => local(&mut m.channel_id),
+            Mining::SetNewPrevHash(m) => local(&mut m.channel_id),
+            Mining::SetTarget(m) => local(&mut m.channel_id),
+            Mining::SetExtranoncePrefix(m) => local(&mut m.channel_id),
+            Mining::SubmitSharesSuccess(m) => local(&mut m.channel_id),
+            Mining::SubmitSharesError(m) => local(&mut m.channel_id),
+            Mining::UpdateChannelError(m) => local(&mut m.channel_id),
+            Mining::SetCustomMiningJobSuccess(m) => local(&mut m.channel_id),
+            Mining::SetCustomMiningJobError(m) => local(&mut m.channel_id),
+            _ => {}
+        }
+    }
+
+    // Returns the local ID of the channel `channel_id` of the upstream at index `upstream`,
+    // allocating one the first time the channel is seen.
+    fn local_channel_id(&self, upstream: usize, channel_id: ChannelId) -> ChannelId {
+        *self
+            .upstream_to_local
+            .entry((upstream, channel_id))
+            .or_insert_with(|| {
+                let local = self.next_channel_id.fetch_add(1, Ordering::Relaxed);
+                self.local_to_upstream.insert(local, (upstream, channel_id));
+                local
+            })
+    }
+
+    /// Forgets the requests and channels of every upstream, keeping the routed upstreams that are
+    /// connected. The channels open on the routed upstreams are closed, since their downstreams
+    /// are disconnected along with those of the primary upstream.
+    pub fn clear(&self) {
+        for entry in self.local_to_upstream.iter() {
+            let (upstream, channel_id) = *entry.value();
+            let Some(sender) = self.upstreams.get(&upstream).map(|sender| sender.clone()) else {
+                continue;
+            };
+            let close_channel = AnyMessage::Mining(Mining::CloseChannel(CloseChannel {
+                channel_id,
+                reason_code: Str0255::try_from("upstream fallback".to_string()).unwrap(),
+            }));
+            if let Ok(sv2_frame) = Sv2Frame::try_from(close_channel) {
+                let _ = sender.try_send(sv2_frame);
+            }
+        }
+        self.primary.super_safe_lock(|primary| *primary = None);
+        self.requests.clear();
+        self.local_to_upstream.clear();
+        self.upstream_to_local.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_channel::unbounded;
+    use stratum_apps::stratum_core::{
+        extensions_sv2::RequestExtensionsSuccess,
+        mining_sv2::{SetNewPrevHash, SubmitSharesSuccess},
+    };
+
+    use super::*;
+
+    fn frame(message: Mining<'static>) -> Sv2Frame {
+        AnyMessage::Mining(message).try_into().unwrap()
+    }
+
+    fn parse(mut sv2_frame: Sv2Frame) -> Mining<'static> {
+        let header = sv2_frame.get_header().unwrap();
+        Mining::try_from((header.msg_type(), sv2_frame.payload()))
+            .unwrap()
+            .into_static()
+    }
+
+    fn prev_hash(channel_id: ChannelId) -> Mining<'static> {
+        Mining::SetNewPrevHash(SetNewPrevHash {
+            channel_id,
+            job_id: 1,
+            prev_hash: vec![0u8; 32].try_into().unwrap(),
+            min_ntime: 0,
+            nbits: 0,
+        })
+    }
+
+    #[test]
+    fn routed_channels_get_distinct_local_ids() {
+        let router = UpstreamRouter::default();
+        let (sender, _receiver) = unbounded();
+        router.connect_upstream(1, sender);
+
+        // two upstreams may assign the same channel ID, the routed one gets a local ID
+        let Mining::SetNewPrevHash(m) = parse(
+            router
+                .remap_inbound(1, frame(prev_hash(1)))
+                .unwrap()
+                .unwrap(),
+        ) else {
+            panic!("Expected SetNewPrevHash");
+        };
+        let local_channel_id = m.channel_id;
+        assert_eq!(local_channel_id, ROUTED_CHANNEL_ID_BASE);
+
+        let mut channel_id = local_channel_id;
+        assert!(router.route_channel(&mut channel_id).is_some());
+        assert_eq!(channel_id, 1);
+        // the channels of the primary upstream are left alone
+        let mut channel_id = 1;
+        assert!(router.route_channel(&mut channel_id).is_none());
+        assert_eq!(channel_id, 1);
+
+        let success = Mining::SubmitSharesSuccess(SubmitSharesSuccess {
+            channel_id: 1,
+            last_sequence_number: 1,
+            new_submits_accepted_count: 1,
+            new_shares_sum: 1,
+        });
+        let Mining::SubmitSharesSuccess(m) =
+            parse(router.remap_inbound(1, frame(success)).unwrap().unwrap())
+        else {
+            panic!("Expected SubmitSharesSuccess");
+        };
+        assert_eq!(m.channel_id, local_channel_id);
+
+        assert_eq!(router.disconnect_upstream(1), vec![local_channel_id]);
+        let mut channel_id = local_channel_id;
+        assert!(router.route_channel(&mut channel_id).is_none());
+    }
+
+    #[test]
+    fn requests_routed_to_the_primary_upstream_stay_on_it() {
+        let router = UpstreamRouter::default();
+        let (sender, _receiver) = unbounded();
+        router.connect_upstream(1, sender);
+        router.set_primary_upstream(0);
+
+        router.set_request_upstream(10, 1);
+        router.set_request_upstream(11, 0);
+        router.set_request_upstream(12, 2);
+        assert!(router.route_request(10).is_some());
+        assert!(router.route_request(11).is_none());
+        // upstream 2 is not connected, so the channel is opened on the primary upstream
+        assert!(router.route_request(12).is_none());
+
+        assert!(router.take_request(10));
+        assert!(!router.take_request(11));
+        assert!(!router.take_request(12));
+    }
+
+    #[test]
+    fn extensions_are_negotiated_with_routed_upstreams() {
+        let router = UpstreamRouter::default().with_extensions(vec![2], vec![2]);
+        let (sender, _receiver) = unbounded();
+        router.connect_upstream(1, sender);
+        let local_channel_id = router.local_channel_id(1, 1);
+        assert_eq!(
+            router.has_routed_extension(local_channel_id, 2),
+            Some(false)
+        );
+        // the channels of the primary upstream use its own extensions
+        assert_eq!(router.has_routed_extension(1, 2), None);
+
+        let success = |supported_extensions: Vec<u16>| {
+            let success = RequestExtensionsSuccess {
+                request_id: 1,
+                supported_extensions: Seq064K::new(supported_extensions).unwrap(),
+            };
+            Sv2Frame::try_from(AnyMessage::Extensions(success.into_static().into())).unwrap()
+        };
+        assert!(router.remap_inbound(1, success(vec![2])).unwrap().is_none());
+        assert_eq!(router.has_routed_extension(local_channel_id, 2), Some(true));
+
+        // a routed upstream without the required extensions has to be disconnected
+        assert!(matches!(
+            router.remap_inbound(1, success(vec![])),
+            Err(TproxyErrorKind::RequiredExtensionsNotSupported(missing)) if missing == vec![2]
+        ));
+    }
+
+    #[test]
+    fn clearing_closes_the_channels_of_routed_upstreams() {
+        let router = UpstreamRouter::default();
+        let (sender, receiver) = unbounded();
+        router.connect_upstream(1, sender);
+        let local_channel_id = router.local_channel_id(1, 7);
+        assert!(router.is_channel_on_upstream(local_channel_id, 1));
+
+        router.clear();
+
+        let Mining::CloseChannel(m) = parse(receiver.try_recv().unwrap()) else {
+            panic!("Expected CloseChannel");
+        };
+        assert_eq!(m.channel_id, 7);
+        assert!(!router.is_channel_on_upstream(local_channel_id, 1));
+        // the routed upstream itself stays connected
+        assert!(router.is_upstream_connected(1));
+    }
+}
@@ -1,15 +1,20 @@
 use crate::{
     error::{self, TproxyError, TproxyErrorKind},
     sv2::Upstream,
+    utils::check_setup_connection_success_flags,
 };
-use stratum_apps::stratum_core::{
-    common_messages_sv2::{
-        ChannelEndpointChanged, Reconnect, SetupConnectionError, SetupConnectionSuccess,
+use stratum_apps::{
+    network_helpers::reconnect::resolve_reconnect,
+    stratum_core::{
+        common_messages_sv2::{
+            ChannelEndpointChanged, Reconnect, SetupConnectionError, SetupConnectionSuccess,
+        },
+        handlers_sv2::HandleCommonMessagesFromServerAsync,
+        parsers_sv2::{AnyMessage, CommonMessages, Tlv},
     },
-    handlers_sv2::HandleCommonMessagesFromServerAsync,
-    parsers_sv2::Tlv,
+    utils::types::Sv2Frame,
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 #[cfg_attr(not(test), hotpath::measure_all)]
 impl HandleCommonMessagesFromServerAsync for Upstream {
@@ -39,6 +44,24 @@ impl HandleCommonMessagesFromServerAsync for Upstream {
         _tlv_fields: Option<&[Tlv]>,
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
+        if !self.supports_version(msg.used_version) {
+            error!(
+                "Upstream selected unsupported protocol version {}",
+                msg.used_version
+            );
+            return Err(TproxyError::fallback(
+                TproxyErrorKind::UnsupportedProtocolVersion(msg.used_version),
+            ));
+        }
+        check_setup_connection_success_flags(
+            self.setup_connection_flags(),
+            msg.flags,
+            self.setup_connection_flags_policy(),
+        )
+        .map_err(|e| {
+            error!("Upstream {}: {e}", self.label());
+            TproxyError::fallback(e)
+        })?;
         Ok(())
     }
 
@@ -49,9 +72,21 @@ impl HandleCommonMessagesFromServerAsync for Upstream {
         _tlv_fields: Option<&[Tlv]>,
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
-        todo!()
+        // The channel manager owns the share sequence numbers, which restart on the new endpoint.
+        let sv2_frame: Sv2Frame = AnyMessage::Common(CommonMessages::ChannelEndpointChanged(msg))
+            .try_into()
+            .map_err(TproxyError::shutdown)?;
+        self.upstream_channel_state
+            .channel_manager_sender
+            .send(sv2_frame)
+            .await
+            .map_err(|_| TproxyError::shutdown(TproxyErrorKind::ChannelErrorSender))?;
+        Ok(())
     }
 
+    // The upstream connection is dropped with a fallback to the endpoint of the `Reconnect`,
+    // which the translator connects to before any other upstream. A `Reconnect` to an endpoint
+    // the allowlist of the upstream does not list is ignored.
     async fn handle_reconnect(
         &mut self,
         _server_id: Option<usize>,
@@ -59,6 +94,20 @@ impl HandleCommonMessagesFromServerAsync for Upstream {
         _tlv_fields: Option<&[Tlv]>,
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
-        todo!()
+        match resolve_reconnect(&msg, self.address(), self.reconnect_allowlist()).await {
+            Ok(addr) => {
+                info!(
+                    "Upstream {} redirects to {addr}, reconnecting",
+                    self.label()
+                );
+                Err(TproxyError::fallback(TproxyErrorKind::UpstreamReconnect(
+                    addr,
+                )))
+            }
+            Err(e) => {
+                warn!("Ignoring Reconnect from upstream {}: {e}", self.label());
+                Ok(())
+            }
+        }
     }
 }
@@ -1,14 +1,21 @@
 use crate::{
+    config::SetupConnectionFlagsPolicy,
     error::{self, TproxyError, TproxyErrorKind, TproxyResult},
-    io_task::spawn_io_tasks,
+    io_task::{spawn_io_tasks, IoTimeouts},
     status::{handle_error, Status, StatusSender},
     sv2::upstream::channel::UpstreamChannelState,
-    utils::{ShutdownMessage, UpstreamEntry},
+    utils::{mining_setup_connection_flags, ShutdownMessage, UpstreamEntry},
 };
 use async_channel::{unbounded, Receiver, Sender};
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 use stratum_apps::{
-    network_helpers::noise_stream::NoiseTcpStream,
+    network_helpers::{message_counters::MessageCounters, noise_stream::NoiseTcpStream},
     stratum_core::{
         binary_sv2::Seq064K,
         codec_sv2::HandshakeRole,
@@ -48,7 +55,21 @@ pub struct Upstream {
     pub upstream_channel_state: UpstreamChannelState,
     /// Extensions that the translator requires (must be supported by server)
     pub required_extensions: Vec<u16>,
+    /// Lowest SV2 protocol version the translator accepts from the upstream
+    min_supported_version: u16,
+    /// Highest SV2 protocol version the translator accepts from the upstream
+    max_supported_version: u16,
     address: SocketAddr,
+    /// Label identifying this upstream in monitoring metrics
+    label: String,
+    /// Set by the I/O tasks once a read or a write on the connection timed out
+    io_timed_out: Arc<AtomicBool>,
+    /// Endpoints the upstream may redirect the translator to with `Reconnect`
+    reconnect_allowlist: Vec<String>,
+    /// How strictly the flags of the `SetupConnectionSuccess` of the upstream are checked
+    setup_connection_flags_policy: SetupConnectionFlagsPolicy,
+    /// Whether the connection is kept when the primary upstream fails over or is rotated
+    keep_on_fallback: bool,
 }
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -69,6 +90,12 @@ impl Upstream {
     /// * `channel_manager_receiver` - Channel to receive messages from the channel manager
     /// * `notify_shutdown` - Broadcast channel for shutdown coordination
     /// * `shutdown_complete_tx` - Channel to signal shutdown completion
+    /// * `min_supported_version` / `max_supported_version` - Protocol version range requested in
+    ///   `SetupConnection` and required from the version used by the upstream
+    /// * `max_frame_size` - Largest SV2 frame, in bytes, accepted from the upstream
+    /// * `frame_trace` - Whether to log the frames exchanged with the upstream at trace level
+    /// * `message_counters` - Counts of the messages exchanged, shared by every connection
+    /// * `io_timeouts` - Read and write timeouts of the connection
     ///
     /// # Returns
     /// * `Ok(Upstream)` - Successfully connected to an upstream server
@@ -82,6 +109,12 @@ impl Upstream {
         shutdown_complete_tx: mpsc::Sender<()>,
         task_manager: Arc<TaskManager>,
         required_extensions: Vec<u16>,
+        min_supported_version: u16,
+        max_supported_version: u16,
+        max_frame_size: usize,
+        frame_trace: bool,
+        message_counters: Arc<MessageCounters>,
+        io_timeouts: IoTimeouts,
     ) -> TproxyResult<Self, error::Upstream> {
         let mut shutdown_rx = notify_shutdown.subscribe();
 
@@ -108,12 +141,16 @@ impl Upstream {
                         let (outbound_tx, outbound_rx) = unbounded();
                         let (inbound_tx, inbound_rx) = unbounded();
 
-                        spawn_io_tasks(
+                        let io_timed_out = spawn_io_tasks(
                             task_manager,
                             reader,
                             writer,
                             outbound_rx,
                             inbound_tx,
+                            max_frame_size,
+                            frame_trace,
+                            message_counters,
+                            io_timeouts,
                             notify_shutdown,
                         );
 
@@ -131,7 +168,14 @@ impl Upstream {
                         return Ok(Self {
                             upstream_channel_state,
                             required_extensions: required_extensions.clone(),
+                            min_supported_version,
+                            max_supported_version,
                             address: upstream.addr,
+                            label: upstream.label.clone(),
+                            io_timed_out,
+                            reconnect_allowlist: upstream.reconnect_allowlist.clone(),
+                            setup_connection_flags_policy: SetupConnectionFlagsPolicy::default(),
+                            keep_on_fallback: false,
                         });
                     }
                     Err(e) => {
@@ -188,7 +232,8 @@ impl Upstream {
                         drop(shutdown_complete_tx);
                         return Ok(());
                     }
-                    Ok(ShutdownMessage::UpstreamFallback{tx}) => {
+                    Ok(ShutdownMessage::UpstreamFallback{tx}
+                        | ShutdownMessage::UpstreamRotation{tx}) => {
                         info!("Upstream: shutdown signal received during connection setup.");
                         drop(shutdown_complete_tx);
                         drop(tx);
@@ -218,6 +263,84 @@ impl Upstream {
         Ok(())
     }
 
+    /// Starts message processing on an upstream whose SV2 setup already completed.
+    ///
+    /// Used to promote a warm-standby upstream during failover: the connection was
+    /// established and [`Self::setup_connection`] performed ahead of time, so only the
+    /// upstream task has to be spawned. Channels are opened once it runs.
+    pub fn activate(
+        self,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+        shutdown_complete_tx: mpsc::Sender<()>,
+        status_sender: Sender<Status>,
+        task_manager: Arc<TaskManager>,
+    ) -> TproxyResult<(), error::Upstream> {
+        info!("Upstream: activating warm standby at {}", self.address);
+        self.run_upstream_task(
+            notify_shutdown,
+            shutdown_complete_tx,
+            StatusSender::Upstream(status_sender),
+            task_manager,
+        )
+    }
+
+    /// Sets how strictly the flags of the `SetupConnectionSuccess` of the upstream are checked.
+    pub fn with_setup_connection_flags_policy(
+        mut self,
+        policy: SetupConnectionFlagsPolicy,
+    ) -> Self {
+        self.setup_connection_flags_policy = policy;
+        self
+    }
+
+    /// Returns how strictly the flags of the `SetupConnectionSuccess` of the upstream are checked.
+    pub fn setup_connection_flags_policy(&self) -> SetupConnectionFlagsPolicy {
+        self.setup_connection_flags_policy
+    }
+
+    /// Keeps the connection open when the primary upstream fails over or is rotated, as routed
+    /// upstreams do. The fallback is still acknowledged.
+    pub fn with_keep_on_fallback(mut self, keep_on_fallback: bool) -> Self {
+        self.keep_on_fallback = keep_on_fallback;
+        self
+    }
+
+    /// Returns the flags of the `SetupConnection` sent to the upstream, which never asks for work
+    /// selection.
+    pub fn setup_connection_flags(&self) -> u32 {
+        mining_setup_connection_flags(false)
+    }
+
+    /// Returns whether `version` lies within the protocol version range requested from upstream.
+    pub fn supports_version(&self, version: u16) -> bool {
+        (self.min_supported_version..=self.max_supported_version).contains(&version)
+    }
+
+    /// Returns the address of the upstream server.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    // Returns the error the connection closed by the I/O tasks is reported with, instead of
+    // `error`, when they closed it after a read or write timed out.
+    fn connection_closed_error(&self, error: impl Into<TproxyErrorKind>) -> TproxyErrorKind {
+        if self.io_timed_out.load(Ordering::SeqCst) {
+            TproxyErrorKind::UpstreamIoTimeout
+        } else {
+            error.into()
+        }
+    }
+
+    /// Returns the label identifying this upstream in monitoring metrics.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Returns the endpoints the upstream may redirect the translator to with `Reconnect`.
+    pub fn reconnect_allowlist(&self) -> &[String] {
+        &self.reconnect_allowlist
+    }
+
     /// Performs the SV2 handshake setup with the upstream server.
     ///
     /// This method handles the initial SV2 protocol handshake by:
@@ -230,8 +353,13 @@ impl Upstream {
     pub async fn setup_connection(&mut self) -> TproxyResult<(), error::Upstream> {
         debug!("Upstream: initiating SV2 handshake...");
         // Build SetupConnection message
-        let setup_conn_msg = Self::get_setup_connection_message(2, 2, &self.address, false)
-            .map_err(TproxyError::shutdown)?;
+        let setup_conn_msg = Self::get_setup_connection_message(
+            self.min_supported_version,
+            self.max_supported_version,
+            &self.address,
+            false,
+        )
+        .map_err(TproxyError::shutdown)?;
         let sv2_frame: Sv2Frame =
             Message::Common(setup_conn_msg.into())
                 .try_into()
@@ -258,7 +386,7 @@ impl Upstream {
                 }
                 Err(e) => {
                     error!("Failed to receive handshake response from upstream: {}", e);
-                    return Err(TproxyError::fallback(e));
+                    return Err(TproxyError::fallback(self.connection_closed_error(e)));
                 }
             };
 
@@ -378,9 +506,14 @@ impl Upstream {
                                 info!("Upstream: received ShutdownAll signal. Exiting loop.");
                                 break;
                             }
-                            Ok(ShutdownMessage::UpstreamFallback{tx}) => {
-                                info!("Upstream: fallback initiated");
+                            Ok(ShutdownMessage::UpstreamFallback{tx}
+                                | ShutdownMessage::UpstreamRotation{tx}) => {
                                 drop(tx);
+                                if self.keep_on_fallback {
+                                    debug!("Upstream: fallback initiated, keeping the connection");
+                                    continue;
+                                }
+                                info!("Upstream: fallback initiated");
                                 break;
                             }
                             Ok(_) => {
@@ -405,7 +538,8 @@ impl Upstream {
                             }
                             Err(e) => {
                                 error!("Upstream: receiver channel closed unexpectedly: {e}");
-                                handle_error(&status_sender, TproxyError::<error::Upstream>::fallback(e)).await;
+                                let error = self.connection_closed_error(e);
+                                handle_error(&status_sender, TproxyError::<error::Upstream>::fallback(error)).await;
                                 break;
                             }
                         }
@@ -423,7 +557,8 @@ impl Upstream {
                                     .await
                                     .map_err(|e| {
                                         error!("Upstream: failed to send sv2 frame: {e:?}");
-                                        TproxyError::<error::Upstream>::fallback(TproxyErrorKind::ChannelErrorSender)
+                                        let error = self.connection_closed_error(TproxyErrorKind::ChannelErrorSender);
+                                        TproxyError::<error::Upstream>::fallback(error)
                                     })
                                 {
                                     handle_error(&status_sender, e).await;
@@ -492,11 +627,7 @@ impl Upstream {
         let hardware_version = "Translator Proxy".to_string().try_into()?;
         let firmware = String::new().try_into()?;
         let device_id = String::new().try_into()?;
-        let flags = if is_work_selection_enabled {
-            0b110
-        } else {
-            0b100
-        };
+        let flags = mining_setup_connection_flags(is_work_selection_enabled);
 
         Ok(SetupConnection {
             protocol: Protocol::MiningProtocol,
@@ -512,3 +643,146 @@ impl Upstream {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::State;
+    use std::time::Duration;
+    use stratum_apps::{
+        key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
+        network_helpers::noise_stream::{
+            NoiseTcpReadHalf, NoiseTcpWriteHalf, DEFAULT_MAX_FRAME_SIZE,
+        },
+        stratum_core::{
+            binary_sv2::B016M,
+            common_messages_sv2::SetupConnectionSuccess,
+            noise_sv2::Responder,
+            parsers_sv2::TemplateDistribution,
+            template_distribution_sv2::{
+                RequestTransactionDataSuccess, MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_SUCCESS,
+            },
+        },
+    };
+    use tokio::net::TcpListener;
+
+    const AUTHORITY_PUBLIC_KEY: &str = "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72";
+    const AUTHORITY_SECRET_KEY: &str = "mkDLTBBRxdBv998612qipDYoTK3YUrqLe8uWw7gu3iXbSrn2n";
+
+    // Accepts a connection, completes the SV2 setup, then never reads from it again.
+    async fn stalled_upstream(
+        listener: TcpListener,
+    ) -> (NoiseTcpReadHalf<Message>, NoiseTcpWriteHalf<Message>) {
+        let public_key = AUTHORITY_PUBLIC_KEY.parse::<Secp256k1PublicKey>().unwrap();
+        let secret_key = AUTHORITY_SECRET_KEY.parse::<Secp256k1SecretKey>().unwrap();
+        let responder = Responder::from_authority_kp(
+            &public_key.into_bytes(),
+            &secret_key.into_bytes(),
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let stream = NoiseTcpStream::<Message>::new(socket, HandshakeRole::Responder(responder))
+            .await
+            .unwrap();
+        let (mut reader, mut writer) = stream.into_split();
+        reader.read_frame().await.unwrap();
+        let success: Sv2Frame = Message::Common(
+            SetupConnectionSuccess {
+                used_version: 2,
+                flags: 0,
+            }
+            .into(),
+        )
+        .try_into()
+        .unwrap();
+        writer.write_frame(success.into()).await.unwrap();
+        (reader, writer)
+    }
+
+    fn large_frame() -> Sv2Frame {
+        let message = Message::TemplateDistribution(
+            TemplateDistribution::RequestTransactionDataSuccess(RequestTransactionDataSuccess {
+                template_id: 1,
+                excess_data: Vec::new().try_into().unwrap(),
+                transaction_list: Seq064K::new(
+                    vec![B016M::try_from(vec![0u8; 1_000_000]).unwrap()],
+                )
+                .unwrap(),
+            }),
+        );
+        Sv2Frame::from_message(
+            message,
+            MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_SUCCESS,
+            0,
+            false,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn write_timeout_on_stalled_upstream_triggers_fallback() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let entry = UpstreamEntry {
+            addr: listener.local_addr().unwrap(),
+            authority_pubkey: AUTHORITY_PUBLIC_KEY.parse().unwrap(),
+            tried_or_flagged: false,
+            label: "stalled".to_string(),
+            negotiated_extensions: None,
+            reconnect_allowlist: Vec::new(),
+        };
+        let stalled_upstream = tokio::spawn(stalled_upstream(listener));
+
+        let (notify_shutdown, _) = broadcast::channel(10);
+        let (shutdown_complete_tx, _shutdown_complete_rx) = mpsc::channel(1);
+        let (status_tx, status_rx) = unbounded();
+        let (to_channel_manager, _from_upstream) = unbounded();
+        let (to_upstream, from_channel_manager) = unbounded();
+        let task_manager = Arc::new(TaskManager::new());
+        let upstream = Upstream::new(
+            &entry,
+            to_channel_manager,
+            from_channel_manager,
+            notify_shutdown.clone(),
+            shutdown_complete_tx.clone(),
+            task_manager.clone(),
+            Vec::new(),
+            2,
+            2,
+            DEFAULT_MAX_FRAME_SIZE,
+            false,
+            Arc::new(MessageCounters::new()),
+            IoTimeouts {
+                read: None,
+                write: Some(Duration::from_millis(200)),
+            },
+        )
+        .await
+        .unwrap();
+        upstream
+            .start(
+                notify_shutdown,
+                shutdown_complete_tx,
+                status_tx,
+                task_manager,
+            )
+            .await
+            .unwrap();
+        // kept open, so the writes only stall
+        let _stream = stalled_upstream.await.unwrap();
+
+        // far more than the socket buffers hold
+        for _ in 0..32 {
+            to_upstream.send(large_frame()).await.unwrap();
+        }
+
+        let status = tokio::time::timeout(Duration::from_secs(10), status_rx.recv())
+            .await
+            .expect("write timeout did not fire")
+            .unwrap();
+        assert!(matches!(
+            status.state,
+            State::UpstreamShutdown(TproxyErrorKind::UpstreamIoTimeout)
+        ));
+    }
+}
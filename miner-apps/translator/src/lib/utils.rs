@@ -13,20 +13,57 @@ use stratum_apps::{
             merkle_root::merkle_root_from_path,
             target::{bytes_to_hex, u256_to_block_hash},
         },
-        sv1_api::{client_to_server, server_to_client::Notify, utils::HexU32Be},
+        stratum_translation::{
+            error::StratumTranslationError, sv2_to_sv1::build_sv1_set_difficulty_from_sv2_target,
+        },
+        sv1_api::{client_to_server, json_rpc, server_to_client::Notify, utils::HexU32Be},
     },
     utils::types::{ChannelId, DownstreamId},
 };
 
 use tokio::sync::mpsc;
-use tracing::debug;
+use tracing::{debug, warn};
 
-use crate::error::TproxyErrorKind;
+use crate::{
+    config::{DifficultyQuantization, SetupConnectionFlagsPolicy},
+    error::TproxyErrorKind,
+};
 
 /// Channel ID used to broadcast messages to all downstreams in aggregated mode.
 /// This sentinel value distinguishes broadcast from a legitimate channel 0.
 pub const AGGREGATED_CHANNEL_ID: ChannelId = u32::MAX;
 
+/// Number of aggregated channels that can be open at once, each stored under its own ID below
+/// [`AGGREGATED_CHANNEL_ID`].
+pub const MAX_AGGREGATED_CHANNELS: u32 = u16::MAX as u32;
+
+/// Returns the ID the `index`-th aggregated channel is stored under, the first one being
+/// [`AGGREGATED_CHANNEL_ID`].
+pub fn aggregated_channel_id(index: u32) -> ChannelId {
+    AGGREGATED_CHANNEL_ID - index
+}
+
+/// Returns whether `channel_id` is the ID an aggregated channel is stored under.
+pub fn is_aggregated_channel_id(channel_id: ChannelId) -> bool {
+    channel_id > AGGREGATED_CHANNEL_ID - MAX_AGGREGATED_CHANNELS
+}
+
+/// Builds the SV1 `mining.set_difficulty` for `target`, with the difficulty snapped to the grid
+/// of `quantization`.
+pub fn build_sv1_set_difficulty(
+    target: Target,
+    quantization: DifficultyQuantization,
+) -> Result<json_rpc::Message, StratumTranslationError> {
+    if quantization == DifficultyQuantization::None {
+        return build_sv1_set_difficulty_from_sv2_target(target);
+    }
+    let difficulty = quantization.quantize(target.difficulty_float());
+    Ok(json_rpc::Message::Notification(json_rpc::Notification {
+        method: "mining.set_difficulty".to_string(),
+        params: serde_json::json!([difficulty]),
+    }))
+}
+
 /// Validates an SV1 share against the target difficulty and job parameters.
 ///
 /// This function performs complete share validation by:
@@ -55,6 +92,19 @@ pub fn validate_sv1_share(
     version_rolling_mask: Option<HexU32Be>,
     job: Notify<'static>,
 ) -> Result<bool, TproxyErrorKind> {
+    let hash_as_target = sv1_share_hash(share, extranonce1, version_rolling_mask, job)?;
+    Ok(share_meets_target(hash_as_target, target))
+}
+
+/// Computes the hash of the block header of an SV1 share, as a target.
+///
+/// The difficulty the share achieved is the `difficulty_float` of the returned target.
+pub fn sv1_share_hash(
+    share: &client_to_server::Submit<'static>,
+    extranonce1: Vec<u8>,
+    version_rolling_mask: Option<HexU32Be>,
+    job: Notify<'static>,
+) -> Result<Target, TproxyErrorKind> {
     let mut full_extranonce = vec![];
     full_extranonce.extend_from_slice(extranonce1.as_slice());
     full_extranonce.extend_from_slice(share.extra_nonce2.0.as_ref());
@@ -98,8 +148,11 @@ pub fn validate_sv1_share(
     // convert the header hash to a target type for easy comparison
     let hash = header.block_hash();
     let raw_hash: [u8; 32] = *hash.to_raw_hash().as_ref();
-    let hash_as_target = Target::from_le_bytes(raw_hash);
+    Ok(Target::from_le_bytes(raw_hash))
+}
 
+/// Returns whether a share hashing to `hash_as_target` meets the downstream `target`.
+pub fn share_meets_target(hash_as_target: Target, target: Target) -> bool {
     // print hash_as_target and self.target as human readable hex
     let hash_bytes = hash_as_target.to_be_bytes();
     let target_bytes = target.to_be_bytes();
@@ -110,15 +163,7 @@ pub fn validate_sv1_share(
         bytes_to_hex(&target_bytes),
     );
     // check if the share hash meets the downstream target
-    if hash_as_target < target {
-        /*if self.share_accounting.is_share_seen(hash.to_raw_hash()) {
-            return Err(ShareValidationError::DuplicateShare);
-        }*/
-
-        return Ok(true);
-    }
-
-    Ok(false)
+    hash_as_target < target
 }
 
 /// Calculates the required length of the proxy's extranonce prefix.
@@ -140,6 +185,107 @@ pub fn proxy_extranonce_prefix_len(
     channel_rollable_extranonce_size - downstream_rollable_extranonce_size
 }
 
+/// `mining.configure` extension through which a SV1 miner suggests the extranonce2 size it
+/// expects, e.g. `[["extranonce2-size"], {"extranonce2-size.value": 8}]`.
+pub const EXTRANONCE2_SIZE_EXTENSION: &str = "extranonce2-size";
+
+/// Removes the [`EXTRANONCE2_SIZE_EXTENSION`] from a `mining.configure` request and returns the
+/// extranonce2 size it suggests.
+///
+/// The extension is removed so the rest of the request is handled as if it was never sent, since
+/// it is not a standard SV1 extension.
+pub fn take_suggested_extranonce2_size(message: &mut json_rpc::Message) -> Option<usize> {
+    let json_rpc::Message::StandardRequest(request) = message else {
+        return None;
+    };
+    if request.method != "mining.configure" {
+        return None;
+    }
+    let params = request.params.as_array_mut()?;
+    let extensions = params.first_mut()?.as_array_mut()?;
+    let position = extensions
+        .iter()
+        .position(|extension| extension.as_str() == Some(EXTRANONCE2_SIZE_EXTENSION))?;
+    extensions.remove(position);
+    params
+        .get_mut(1)?
+        .as_object_mut()?
+        .remove(&format!("{EXTRANONCE2_SIZE_EXTENSION}.value"))?
+        .as_u64()
+        .map(|size| size as usize)
+}
+
+/// Returns the worker name a `mining.authorize` request authorizes.
+pub fn authorized_worker_name(message: &json_rpc::Message) -> Option<&str> {
+    let json_rpc::Message::StandardRequest(request) = message else {
+        return None;
+    };
+    if request.method != "mining.authorize" {
+        return None;
+    }
+    request.params.as_array()?.first()?.as_str()
+}
+
+/// `SetupConnection` flag of the mining protocol asking the pool for work selection.
+const REQUIRES_WORK_SELECTION: u32 = 1 << 1;
+/// `SetupConnection` flag of the mining protocol announcing that the version field is rolled.
+const REQUIRES_VERSION_ROLLING: u32 = 1 << 2;
+/// `SetupConnection.Success` flag of the mining protocol: the upstream does not accept any change
+/// of the version field.
+const REQUIRES_FIXED_VERSION: u32 = 1 << 0;
+/// `SetupConnection.Success` flag of the mining protocol: the upstream does not accept standard
+/// channels.
+const REQUIRES_EXTENDED_CHANNELS: u32 = 1 << 1;
+
+/// Returns the flags of the `SetupConnection` sent to the upstream, asking for work selection
+/// only if `work_selection` is set.
+pub fn mining_setup_connection_flags(work_selection: bool) -> u32 {
+    if work_selection {
+        REQUIRES_VERSION_ROLLING | REQUIRES_WORK_SELECTION
+    } else {
+        REQUIRES_VERSION_ROLLING
+    }
+}
+
+/// Checks the flags of the `SetupConnection.Success` of the upstream against the
+/// `requested_flags` of the `SetupConnection` sent to it, failing only if `policy` asks to fail
+/// over on flags that may not suit the translator.
+pub fn check_setup_connection_success_flags(
+    requested_flags: u32,
+    success_flags: u32,
+    policy: SetupConnectionFlagsPolicy,
+) -> Result<(), TproxyErrorKind> {
+    // SV1 miners roll the version field of the jobs they are sent
+    let fixed_version = requested_flags & REQUIRES_VERSION_ROLLING != 0
+        && success_flags & REQUIRES_FIXED_VERSION != 0;
+    let unknown_flags = success_flags & !(REQUIRES_FIXED_VERSION | REQUIRES_EXTENDED_CHANNELS);
+    if !fixed_version && unknown_flags == 0 {
+        return Ok(());
+    }
+    match policy {
+        SetupConnectionFlagsPolicy::Ignore => {
+            debug!("Ignoring SetupConnection.Success flags {success_flags:#b}");
+        }
+        SetupConnectionFlagsPolicy::Warn => {
+            if fixed_version {
+                warn!(
+                    "Upstream refuses version rolling, shares rolling the version will be rejected"
+                );
+            }
+            if unknown_flags != 0 {
+                warn!("Upstream set unknown SetupConnection.Success flags {unknown_flags:#b}");
+            }
+        }
+        SetupConnectionFlagsPolicy::FailOver => {
+            return Err(TproxyErrorKind::IncompatibleSetupConnectionFlags {
+                requested: requested_flags,
+                success: success_flags,
+            });
+        }
+    }
+    Ok(())
+}
+
 /// Messages used for coordinating shutdown across different components.
 ///
 /// This enum defines the different types of shutdown signals that can be sent
@@ -152,6 +298,9 @@ pub enum ShutdownMessage {
     DownstreamShutdown(DownstreamId),
     /// Reset channel manager state and shutdown downstreams due to upstream reconnection
     UpstreamFallback { tx: mpsc::Sender<()> },
+    /// Reset channel manager state and stop the upstream, keeping the downstreams connected, as
+    /// the translator moves them onto the upstream it rotates to
+    UpstreamRotation { tx: mpsc::Sender<()> },
 }
 
 #[derive(Debug)]
@@ -159,14 +308,117 @@ pub struct UpstreamEntry {
     pub addr: SocketAddr,
     pub authority_pubkey: Secp256k1PublicKey,
     pub tried_or_flagged: bool,
+    /// Label identifying this upstream in monitoring metrics.
+    pub label: String,
+    /// Extensions negotiated on the last connection to this upstream, if any.
+    pub negotiated_extensions: Option<Vec<u16>>,
+    /// Endpoints this upstream may redirect the translator to with `Reconnect`.
+    pub reconnect_allowlist: Vec<String>,
+}
+
+impl UpstreamEntry {
+    /// Remembers the extensions negotiated on the connection to this upstream being dropped.
+    ///
+    /// Nothing is remembered when the negotiation did not complete.
+    pub fn remember_negotiated_extensions(&mut self, negotiated_extensions: Vec<u16>) {
+        if !negotiated_extensions.is_empty() {
+            self.negotiated_extensions = Some(negotiated_extensions);
+        }
+    }
+
+    /// Returns the extensions to request when connecting to this upstream.
+    ///
+    /// With `reuse_negotiated` set, an upstream connected before is asked for the extensions it
+    /// negotiated last time, which already include the ones it requires, saving the
+    /// `RequestExtensionsError` round trip. Otherwise `required_extensions` are requested.
+    pub fn extensions_to_request(
+        &self,
+        required_extensions: &[u16],
+        reuse_negotiated: bool,
+    ) -> Vec<u16> {
+        match &self.negotiated_extensions {
+            Some(negotiated) if reuse_negotiated => {
+                debug!(
+                    "Requesting extensions {:?} negotiated on the last connection to {}",
+                    negotiated, self.label
+                );
+                negotiated.clone()
+            }
+            _ => required_extensions.to_vec(),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::str::FromStr;
     use tokio::sync::mpsc;
 
     use super::*;
 
+    fn upstream_entry() -> UpstreamEntry {
+        UpstreamEntry {
+            addr: "127.0.0.1:34254".parse().unwrap(),
+            authority_pubkey: Secp256k1PublicKey::from_str(
+                "9bDuixKmZqAJnrmP746n8zU1wyAQRrus7th9dxnkPg6RzQvCnan",
+            )
+            .unwrap(),
+            tried_or_flagged: false,
+            label: "primary".to_string(),
+            negotiated_extensions: None,
+            reconnect_allowlist: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_reconnect_requests_negotiated_extensions() {
+        let mut entry = upstream_entry();
+        assert_eq!(entry.extensions_to_request(&[], true), Vec::<u16>::new());
+
+        // the upstream required extension 2 on the first connection
+        entry.remember_negotiated_extensions(vec![2]);
+        assert_eq!(entry.extensions_to_request(&[], true), vec![2]);
+        assert_eq!(entry.extensions_to_request(&[], false), Vec::<u16>::new());
+
+        // a connection dropped before the negotiation completed keeps the last known set
+        entry.remember_negotiated_extensions(vec![]);
+        assert_eq!(entry.extensions_to_request(&[], true), vec![2]);
+    }
+
+    #[test]
+    fn test_setup_connection_success_flags_checked_per_policy() {
+        let requested = mining_setup_connection_flags(false);
+        let unknown_flag = 1 << 3;
+        for policy in [
+            SetupConnectionFlagsPolicy::Ignore,
+            SetupConnectionFlagsPolicy::Warn,
+            SetupConnectionFlagsPolicy::FailOver,
+        ] {
+            for success in [0, REQUIRES_EXTENDED_CHANNELS] {
+                assert!(check_setup_connection_success_flags(requested, success, policy).is_ok());
+            }
+            let fixed_version =
+                check_setup_connection_success_flags(requested, REQUIRES_FIXED_VERSION, policy);
+            let unknown = check_setup_connection_success_flags(requested, unknown_flag, policy);
+            match policy {
+                SetupConnectionFlagsPolicy::Ignore | SetupConnectionFlagsPolicy::Warn => {
+                    assert!(fixed_version.is_ok());
+                    assert!(unknown.is_ok());
+                }
+                SetupConnectionFlagsPolicy::FailOver => {
+                    assert!(fixed_version.is_err());
+                    assert!(matches!(
+                        unknown,
+                        Err(TproxyErrorKind::IncompatibleSetupConnectionFlags {
+                            requested: 0b100,
+                            success: 0b1000,
+                        })
+                    ));
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_proxy_extranonce_prefix_len() {
         assert_eq!(proxy_extranonce_prefix_len(8, 4), 4);
@@ -174,6 +426,33 @@ mod tests {
         assert_eq!(proxy_extranonce_prefix_len(4, 4), 0);
     }
 
+    #[test]
+    fn test_take_suggested_extranonce2_size() {
+        let mut configure = json_rpc::Message::StandardRequest(json_rpc::StandardRequest {
+            id: 1,
+            method: "mining.configure".to_string(),
+            params: serde_json::json!([
+                ["version-rolling", "extranonce2-size"],
+                {"version-rolling.mask": "1fffe000", "extranonce2-size.value": 8}
+            ]),
+        });
+        assert_eq!(take_suggested_extranonce2_size(&mut configure), Some(8));
+        match configure {
+            json_rpc::Message::StandardRequest(request) => assert_eq!(
+                request.params,
+                serde_json::json!([["version-rolling"], {"version-rolling.mask": "1fffe000"}])
+            ),
+            msg => panic!("Expected StandardRequest, found: {msg:?}"),
+        }
+
+        let mut subscribe = json_rpc::Message::StandardRequest(json_rpc::StandardRequest {
+            id: 2,
+            method: "mining.subscribe".to_string(),
+            params: serde_json::json!(["miner/1.0"]),
+        });
+        assert_eq!(take_suggested_extranonce2_size(&mut subscribe), None);
+    }
+
     #[test]
     fn test_shutdown_message_debug() {
         let msg1 = ShutdownMessage::ShutdownAll;
@@ -188,6 +467,22 @@ mod tests {
         assert!(format!("{:?}", msg3).contains("UpstreamFallback"));
     }
 
+    #[test]
+    fn test_quantized_set_difficulty() {
+        let set_difficulty = |quantization| {
+            let json_rpc::Message::Notification(notification) =
+                build_sv1_set_difficulty(Target::MAX_ATTAINABLE_MAINNET, quantization).unwrap()
+            else {
+                panic!("expected a mining.set_difficulty notification");
+            };
+            assert_eq!(notification.method, "mining.set_difficulty");
+            notification.params[0].as_f64().unwrap()
+        };
+
+        assert_eq!(set_difficulty(DifficultyQuantization::PowerOfTwo), 1.0);
+        assert_eq!(set_difficulty(DifficultyQuantization::Step(8.0)), 8.0);
+    }
+
     #[test]
     fn test_shutdown_message_clone() {
         let msg = ShutdownMessage::DownstreamShutdown(456);
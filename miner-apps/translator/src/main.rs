@@ -1,8 +1,10 @@
 mod args;
+use std::path::PathBuf;
 use stratum_apps::config_helpers::logging::init_logging;
+use tracing::{error, info};
 pub use translator_sv2::{config, error, status, sv1, sv2, TranslatorSv2};
 
-use crate::args::process_cli_args;
+use crate::args::{load_config, process_cli_args};
 
 #[cfg(all(feature = "hotpath-alloc", not(test)))]
 #[tokio::main(flavor = "current_thread")]
@@ -20,14 +22,47 @@ async fn main() {
 ///
 /// Loads the configuration from TOML and initializes the main runtime
 /// defined in `translator_sv2::TranslatorSv2`. Errors during startup are logged.
+///
+/// On SIGHUP, the configuration file is loaded again and its hot-reloadable fields are applied
+/// to the running translator.
 #[cfg_attr(not(test), hotpath::main)]
 async fn inner_main() {
-    let proxy_config = process_cli_args().unwrap_or_else(|e| {
+    let (proxy_config, config_path) = process_cli_args().unwrap_or_else(|e| {
         eprintln!("Translator proxy config error: {e}");
         std::process::exit(1);
     });
 
     init_logging(proxy_config.log_dir());
 
-    TranslatorSv2::new(proxy_config).start().await;
+    let translator = TranslatorSv2::new(proxy_config);
+    tokio::spawn(reload_on_sighup(translator.clone(), config_path));
+    translator.start().await;
 }
+
+/// Reloads the configuration at `config_path` into `translator` on every SIGHUP.
+#[cfg(unix)]
+async fn reload_on_sighup(translator: TranslatorSv2, config_path: PathBuf) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(sighup) => sighup,
+        Err(e) => {
+            error!("Failed to listen for SIGHUP, configuration reload disabled: {e}");
+            return;
+        }
+    };
+    while sighup.recv().await.is_some() {
+        info!("SIGHUP received, reloading {}", config_path.display());
+        match load_config(&config_path) {
+            Ok(config) => {
+                if let Err(e) = translator.reload_config(&config) {
+                    error!("Failed to reload configuration, keeping the current one: {e}");
+                }
+            }
+            Err(e) => error!("Failed to reload configuration, keeping the current one: {e}"),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn reload_on_sighup(_translator: TranslatorSv2, _config_path: PathBuf) {}
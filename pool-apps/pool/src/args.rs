@@ -36,6 +36,9 @@ pub fn process_cli_args() -> PoolConfig {
         .build()
         .and_then(|settings| settings.try_deserialize::<PoolConfig>())
         .expect("Failed to load or deserialize config");
+    config
+        .validate_coinbase_reward_script()
+        .expect("Invalid coinbase_reward_script");
 
     config.set_log_dir(args.log_file);
 
@@ -0,0 +1,35 @@
+//! Authentication of downstream connections.
+//!
+//! Every downstream completing `SetupConnection` is accepted by default. A private pool can gate
+//! its downstreams by handing a [`DownstreamAuthenticator`] to
+//! [`PoolSv2::with_downstream_authenticator`](crate::PoolSv2::with_downstream_authenticator).
+//! A rejected downstream is answered with a `SetupConnectionError` carrying the configured
+//! `authentication_error_code`, then disconnected.
+use std::fmt;
+
+use stratum_apps::stratum_core::common_messages_sv2::SetupConnection;
+
+/// Decides which downstreams may use the pool, from the `SetupConnection` they sent.
+///
+/// The fields of `SetupConnection` are set by the downstream, so an authenticator can match its
+/// credential, such as a shared secret in `device_id`, against the ones the operator knows.
+pub trait DownstreamAuthenticator: Send + Sync {
+    /// Returns whether the downstream that sent `setup_connection` is accepted.
+    fn authenticate(&self, setup_connection: &SetupConnection<'_>) -> bool;
+}
+
+impl fmt::Debug for dyn DownstreamAuthenticator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DownstreamAuthenticator")
+    }
+}
+
+/// Authenticator accepting every downstream, used unless another one is set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AcceptAll;
+
+impl DownstreamAuthenticator for AcceptAll {
+    fn authenticate(&self, _setup_connection: &SetupConnection<'_>) -> bool {
+        true
+    }
+}
@@ -99,6 +99,9 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                 channel_manager_data
                     .vardiff
                     .remove(&(downstream_id, msg.channel_id).into());
+                channel_manager_data
+                    .share_sequences
+                    .remove_channel(downstream_id, msg.channel_id);
                 Ok(())
             })
     }
@@ -116,6 +119,24 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
 
         info!("Received OpenStandardMiningChannel: {}", msg);
 
+        let Some(nominal_hash_rate) = self.bounded_nominal_hashrate(msg.nominal_hash_rate) else {
+            error!("OpenMiningChannelError: invalid-hashrate");
+            let open_standard_mining_channel_error = OpenMiningChannelError {
+                request_id,
+                error_code: "invalid-hashrate"
+                    .to_string()
+                    .try_into()
+                    .expect("error code must be valid string"),
+            };
+            RouteMessageTo::from((
+                downstream_id,
+                Mining::OpenMiningChannelError(open_standard_mining_channel_error),
+            ))
+            .forward(&self.channel_manager_channel)
+            .await;
+            return Ok(());
+        };
+
         let messages = self.channel_manager_data.super_safe_lock(|channel_manager_data| {
             let Some(downstream) = channel_manager_data.downstream.get_mut(&downstream_id) else {
                 return Err(PoolError::disconnect(PoolErrorKind::DownstreamIdNotFound, downstream_id));
@@ -148,7 +169,14 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
             };
 
             downstream.downstream_data.super_safe_lock(|downstream_data| {
-                let nominal_hash_rate = msg.nominal_hash_rate;
+                if let Some(error) = self.channel_limit_error(
+                    downstream_id,
+                    request_id,
+                    downstream_data.open_channels(),
+                ) {
+                    return Ok(vec![error]);
+                }
+
                 let requested_max_target = Target::from_le_bytes(msg.max_target.inner_as_ref().try_into().unwrap());
                 let extranonce_prefix = channel_manager_data.extranonce_prefix_factory_standard.next_prefix_standard().map_err(PoolError::shutdown)?;
 
@@ -265,7 +293,23 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
             client_id.expect("client_id must be present for downstream_id extraction");
         info!("Received OpenExtendedMiningChannel: {}", msg);
 
-        let nominal_hash_rate = msg.nominal_hash_rate;
+        let Some(nominal_hash_rate) = self.bounded_nominal_hashrate(msg.nominal_hash_rate) else {
+            error!("OpenMiningChannelError: invalid-hashrate");
+            let open_extended_mining_channel_error = OpenMiningChannelError {
+                request_id,
+                error_code: "invalid-hashrate"
+                    .to_string()
+                    .try_into()
+                    .expect("error code must be valid string"),
+            };
+            RouteMessageTo::from((
+                downstream_id,
+                Mining::OpenMiningChannelError(open_extended_mining_channel_error),
+            ))
+            .forward(&self.channel_manager_channel)
+            .await;
+            return Ok(());
+        };
         let requested_max_target =
             Target::from_le_bytes(msg.max_target.inner_as_ref().try_into().unwrap());
         let requested_min_rollable_extranonce_size = msg.min_extranonce_size;
@@ -282,6 +326,14 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                     .super_safe_lock(|downstream_data| {
                         let mut messages: Vec<RouteMessageTo> = Vec::new();
 
+                        if let Some(error) = self.channel_limit_error(
+                            downstream_id,
+                            request_id,
+                            downstream_data.open_channels(),
+                        ) {
+                            return Ok(vec![error]);
+                        }
+
                         let extranonce_prefix = match channel_manager_data
                             .extranonce_prefix_factory_extended
                             .next_prefix_extended(requested_min_rollable_extranonce_size.into())
@@ -548,6 +600,13 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                     return Ok(vec![(downstream_id, Mining::SubmitSharesError(submit_shares_error)).into()]);
                 };
 
+                if let Some(error) = self.check_share_sequence(&mut channel_manager_data.share_sequences, downstream_id, channel_id, msg.sequence_number) {
+                    if let Some(share_ledger) = channel_manager_data.share_ledger.as_mut() {
+                        share_ledger.record_rejected(standard_channel.get_user_identity());
+                    }
+                    return Ok(vec![(downstream_id, Mining::SubmitSharesError(error)).into()]);
+                }
+
                 let Some(vardiff) = channel_manager_data.vardiff.get_mut(&(downstream_id, channel_id).into()) else {
                     return Ok(vec![(downstream_id, Mining::CloseChannel(create_close_channel_msg(channel_id, "invalid-channel-id"))).into()]);
                 };
@@ -578,11 +637,12 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
 
                     }
                     Ok(ShareValidationResult::BlockFound(share_hash, template_id, coinbase)) => {
-                        info!("SubmitSharesStandard: 💰 Block Found!!! 💰{share_hash}");
+                        info!("SubmitSharesStandard: 💰 Block Found!!! 💰{share_hash} | downstream_id: {}, channel_id: {}, template_id: {:?}", downstream_id, channel_id, template_id);
                         // if we have a template id (i.e.: this was not a custom job)
                         // we can propagate the solution to the TP
                         if let Some(template_id) = template_id {
-                            info!("SubmitSharesStandard: Propagating solution to the Template Provider.");
+                            info!("SubmitSharesStandard: 💰 Submitting block {share_hash} to the Template Provider, accepted once it becomes the chain tip.");
+                            self.found_blocks.record_submitted(share_hash.to_string(), Some(template_id));
                             let solution = SubmitSolution {
                                 template_id,
                                 version: msg.version,
@@ -639,11 +699,12 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                         messages.push((downstream_id, Mining::SubmitSharesError(error)).into());
                     }
                     Err(ShareValidationError::DoesNotMeetTarget) => {
-                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: difficulty-too-low ❌", downstream_id, channel_id, msg.sequence_number);
+                        let error_code = if self.strict_share_validation { "above-target" } else { "difficulty-too-low" };
+                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: {} ❌", downstream_id, channel_id, msg.sequence_number, error_code);
                         let error = SubmitSharesError {
                             channel_id: msg.channel_id,
                             sequence_number: msg.sequence_number,
-                            error_code: "difficulty-too-low"
+                            error_code: error_code
                                 .to_string()
                                 .try_into()
                                 .expect("error code must be valid string"),
@@ -667,6 +728,15 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                     }
                 }
 
+                if let Some(share_ledger) = channel_manager_data.share_ledger.as_mut() {
+                    let user_identity = standard_channel.get_user_identity();
+                    if messages.iter().any(RouteMessageTo::is_submit_shares_error) {
+                        share_ledger.record_rejected(user_identity);
+                    } else {
+                        share_ledger.record_accepted(user_identity, standard_channel.get_target().difficulty_float());
+                    }
+                }
+
                 Ok(messages)
             })
         })?;
@@ -731,6 +801,13 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                     // here we have the UserIdentity TLV, so we can use it to enhance monitoring of individual miners in the future
                 }
 
+                if let Some(error) = self.check_share_sequence(&mut channel_manager_data.share_sequences, downstream_id, channel_id, msg.sequence_number) {
+                    if let Some(share_ledger) = channel_manager_data.share_ledger.as_mut() {
+                        share_ledger.record_rejected(extended_channel.get_user_identity());
+                    }
+                    return Ok(vec![(downstream_id, Mining::SubmitSharesError(error)).into()]);
+                }
+
                 let Some(vardiff) = channel_manager_data.vardiff.get_mut(&(downstream_id, channel_id).into()) else {
                     return Ok(vec![(downstream_id, Mining::CloseChannel(create_close_channel_msg(channel_id, "invalid-channel-id"))).into()]);
                 };
@@ -759,11 +836,12 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                         }
                     }
                     Ok(ShareValidationResult::BlockFound(share_hash, template_id, coinbase)) => {
-                        info!("SubmitSharesExtended: 💰 Block Found!!! 💰{share_hash}");
+                        info!("SubmitSharesExtended: 💰 Block Found!!! 💰{share_hash} | downstream_id: {}, channel_id: {}, template_id: {:?}", downstream_id, channel_id, template_id);
                         // if we have a template id (i.e.: this was not a custom job)
                         // we can propagate the solution to the TP
                         if let Some(template_id) = template_id {
-                            info!("SubmitSharesExtended: Propagating solution to the Template Provider.");
+                            info!("SubmitSharesExtended: 💰 Submitting block {share_hash} to the Template Provider, accepted once it becomes the chain tip.");
+                            self.found_blocks.record_submitted(share_hash.to_string(), Some(template_id));
                             let solution = SubmitSolution {
                                 template_id,
                                 version: msg.version,
@@ -819,11 +897,12 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                         messages.push((downstream_id, Mining::SubmitSharesError(error)).into());
                     }
                     Err(ShareValidationError::DoesNotMeetTarget) => {
-                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: difficulty-too-low ❌", downstream_id, channel_id, msg.sequence_number);
+                        let error_code = if self.strict_share_validation { "above-target" } else { "difficulty-too-low" };
+                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: {} ❌", downstream_id, channel_id, msg.sequence_number, error_code);
                         let error = SubmitSharesError {
                             channel_id: msg.channel_id,
                             sequence_number: msg.sequence_number,
-                            error_code: "difficulty-too-low"
+                            error_code: error_code
                                 .to_string()
                                 .try_into()
                                 .expect("error code must be valid string"),
@@ -859,6 +938,15 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                     }
                 }
 
+                if let Some(share_ledger) = channel_manager_data.share_ledger.as_mut() {
+                    let user_identity = extended_channel.get_user_identity();
+                    if messages.iter().any(RouteMessageTo::is_submit_shares_error) {
+                        share_ledger.record_rejected(user_identity);
+                    } else {
+                        share_ledger.record_accepted(user_identity, extended_channel.get_target().difficulty_float());
+                    }
+                }
+
                 Ok(messages)
             })
         })?;
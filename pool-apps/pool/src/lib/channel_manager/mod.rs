@@ -2,9 +2,10 @@ use std::{
     collections::HashMap,
     net::SocketAddr,
     sync::{
-        atomic::{AtomicU32, AtomicUsize},
+        atomic::{AtomicBool, AtomicU32, AtomicUsize},
         Arc,
     },
+    time::SystemTime,
 };
 
 use async_channel::{Receiver, Sender};
@@ -14,7 +15,14 @@ use stratum_apps::{
     config_helpers::CoinbaseRewardScript,
     custom_mutex::Mutex,
     key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
-    network_helpers::noise_stream::NoiseTcpStream,
+    monitoring::FoundBlocks,
+    network_helpers::{
+        broadcast_pacing::BroadcastPacing,
+        connection_limiter::{ConnectionLimiter, ConnectionPermit},
+        message_counters::MessageCounters,
+        noise_stream::NoiseTcpStream,
+        slow_consumer::SlowConsumerPolicy,
+    },
     stratum_core::{
         bitcoin::{Amount, TxOut},
         channels_sv2::{
@@ -30,26 +38,34 @@ use stratum_apps::{
         handlers_sv2::{
             HandleMiningMessagesFromClientAsync, HandleTemplateDistributionMessagesFromServerAsync,
         },
-        mining_sv2::{ExtendedExtranonce, SetTarget},
+        mining_sv2::{ExtendedExtranonce, OpenMiningChannelError, SetTarget, SubmitSharesError},
         noise_sv2::Responder,
         parsers_sv2::{Mining, TemplateDistribution, Tlv},
         template_distribution_sv2::{NewTemplate, SetNewPrevHash},
     },
     task_manager::TaskManager,
-    utils::types::{ChannelId, DownstreamId, Message, SharesPerMinute, VardiffKey},
+    utils::{
+        bind_retry::{bind_with_retry, BindRetry},
+        types::{ChannelId, DownstreamId, Message, SharesPerMinute, VardiffKey},
+        vardiff::clamp_vardiff_step,
+    },
 };
-use tokio::{net::TcpListener, select, sync::broadcast};
+use tokio::{select, sync::broadcast};
 use tracing::{debug, error, info, warn};
 
 use crate::{
+    authentication::DownstreamAuthenticator,
+    channel_manager::share_sequence::{SequenceViolation, ShareSequenceTracker},
     config::PoolConfig,
     downstream::Downstream,
     error::{self, PoolError, PoolErrorKind, PoolResult},
+    share_export::{write_dump, ShareExport, ShareLedger},
     status::{handle_error, Status, StatusSender},
     utils::ShutdownMessage,
 };
 
 mod mining_message_handler;
+pub(crate) mod share_sequence;
 mod template_distribution_message_handler;
 
 const POOL_ALLOCATION_BYTES: usize = 4;
@@ -60,6 +76,9 @@ pub struct ChannelManagerData {
     // Mapping of `downstream_id` → `Downstream` object,
     // used by the channel manager to locate and interact with downstream clients.
     pub(crate) downstream: HashMap<DownstreamId, Downstream>,
+    // Mapping of `downstream_id` → connection slot held by that downstream,
+    // released when the downstream is removed.
+    connection_permits: HashMap<DownstreamId, ConnectionPermit>,
     // Extranonce prefix factory for **extended downstream channels**.
     // Each new extended downstream receives a unique extranonce prefix.
     extranonce_prefix_factory_extended: ExtendedExtranonce,
@@ -71,6 +90,11 @@ pub struct ChannelManagerData {
     // Mapping of `(downstream_id, channel_id)` → vardiff controller.
     // Each entry manages variable difficulty for a specific downstream channel.
     vardiff: HashMap<VardiffKey, VardiffState>,
+    // Last share sequence number of each downstream channel, with the out-of-sequence shares
+    // detected so far.
+    pub(crate) share_sequences: ShareSequenceTracker,
+    // Shares of each worker since the last share accounting dump, when the export is enabled.
+    pub(crate) share_ledger: Option<ShareLedger>,
     // Coinbase outputs
     coinbase_outputs: Vec<u8>,
     // Last new prevhash
@@ -102,6 +126,46 @@ pub struct ChannelManager {
     supported_extensions: Vec<u16>,
     /// Protocol extensions that the pool requires (clients must support these).
     required_extensions: Vec<u16>,
+    /// Lowest nominal hashrate accepted when opening a channel.
+    min_nominal_hashrate: Option<f32>,
+    /// Highest nominal hashrate accepted when opening a channel.
+    max_nominal_hashrate: Option<f32>,
+    /// Whether out-of-range nominal hashrates are clamped instead of rejected.
+    clamp_hashrate: bool,
+    /// Maximum number of channels a single downstream connection may have open at once.
+    max_channels_per_downstream: Option<usize>,
+    /// Whether shares repeating or going below the last sequence number of their channel are
+    /// rejected instead of only logged.
+    reject_out_of_sequence_shares: bool,
+    /// Whether shares below the target of their channel are answered with `above-target` instead
+    /// of `difficulty-too-low`.
+    strict_share_validation: bool,
+    /// Largest factor by which a single vardiff adjustment changes the difficulty of a channel.
+    vardiff_max_step_ratio: Option<f32>,
+    /// Largest SV2 frame, in bytes, accepted from downstreams.
+    max_frame_size: usize,
+    /// When a downstream falling behind the messages sent to it is flagged as a slow consumer.
+    slow_consumer_policy: Option<SlowConsumerPolicy>,
+    /// How job broadcasts to downstreams are spread over time.
+    job_broadcast_pacing: Option<BroadcastPacing>,
+    /// Where and how often the shares of each worker are dumped.
+    share_export: Option<ShareExport>,
+    /// Per-IP connection cap and accept rate limiter of the downstream server.
+    pub(crate) connection_limiter: Arc<ConnectionLimiter>,
+    /// Counts of the messages exchanged with downstreams and the template provider.
+    pub(crate) message_counters: Arc<MessageCounters>,
+    /// Blocks submitted to the template provider, and whether it accepted them.
+    pub(crate) found_blocks: Arc<FoundBlocks>,
+    /// Decides which downstreams may use the pool after their `SetupConnection`.
+    downstream_authenticator: Arc<dyn DownstreamAuthenticator>,
+    /// Error code of the `SetupConnectionError` sent to rejected downstreams.
+    authentication_error_code: String,
+    /// Cleared while the connection to the template provider is being re-established.
+    pub(crate) template_provider_connected: Arc<AtomicBool>,
+    /// Whether downstreams connecting while the template provider is reconnecting are refused.
+    defer_downstream_until_upstream_ready: bool,
+    /// How binding the downstream server is retried while its address is in use.
+    bind_retry: BindRetry,
 }
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -115,6 +179,7 @@ impl ChannelManager {
         downstream_sender: broadcast::Sender<(DownstreamId, Mining<'static>, Option<Vec<Tlv>>)>,
         downstream_receiver: Receiver<(DownstreamId, Mining<'static>, Option<Vec<Tlv>>)>,
         coinbase_outputs: Vec<u8>,
+        downstream_authenticator: Arc<dyn DownstreamAuthenticator>,
     ) -> PoolResult<Self, error::ChannelManager> {
         let range_0 = 0..0;
         let range_1 = 0..POOL_ALLOCATION_BYTES;
@@ -140,10 +205,13 @@ impl ChannelManager {
 
         let channel_manager_data = Arc::new(Mutex::new(ChannelManagerData {
             downstream: HashMap::new(),
+            connection_permits: HashMap::new(),
             extranonce_prefix_factory_extended,
             extranonce_prefix_factory_standard,
             downstream_id_factory: AtomicUsize::new(1),
             vardiff: HashMap::new(),
+            share_sequences: ShareSequenceTracker::default(),
+            share_ledger: config.share_export().map(|_| ShareLedger::default()),
             coinbase_outputs,
             last_future_template: None,
             last_new_prev_hash: None,
@@ -165,11 +233,122 @@ impl ChannelManager {
             coinbase_reward_script: config.coinbase_reward_script().clone(),
             supported_extensions: config.supported_extensions().to_vec(),
             required_extensions: config.required_extensions().to_vec(),
+            min_nominal_hashrate: config.min_nominal_hashrate(),
+            max_nominal_hashrate: config.max_nominal_hashrate(),
+            clamp_hashrate: config.clamp_hashrate(),
+            max_channels_per_downstream: config.max_channels_per_downstream(),
+            reject_out_of_sequence_shares: config.reject_out_of_sequence_shares(),
+            strict_share_validation: config.strict_share_validation(),
+            vardiff_max_step_ratio: config.vardiff_max_step_ratio(),
+            max_frame_size: config.max_frame_size(),
+            slow_consumer_policy: config.slow_consumer_policy(),
+            job_broadcast_pacing: config.job_broadcast_pacing(),
+            share_export: config.share_export(),
+            connection_limiter: Arc::new(
+                ConnectionLimiter::new(
+                    config.max_connections_per_ip(),
+                    config.max_accepts_per_sec(),
+                )
+                .with_churn_warning(config.churn_warning_per_minute()),
+            ),
+            message_counters: Arc::new(MessageCounters::new()),
+            found_blocks: Arc::new(FoundBlocks::new()),
+            downstream_authenticator,
+            authentication_error_code: config.authentication_error_code().to_string(),
+            template_provider_connected: Arc::new(AtomicBool::new(true)),
+            defer_downstream_until_upstream_ready: config.defer_downstream_until_upstream_ready(),
+            bind_retry: config.bind_retry(),
         };
 
         Ok(channel_manager)
     }
 
+    // Checks the nominal hashrate requested by a downstream against the configured bounds.
+    // Returns the hashrate to open the channel with (clamped into the bounds when
+    // `clamp_hashrate` is set), or `None` if the channel must be rejected. Without bounds, every
+    // hashrate is passed through as requested.
+    fn bounded_nominal_hashrate(&self, nominal_hash_rate: f32) -> Option<f32> {
+        if self.min_nominal_hashrate.is_none() && self.max_nominal_hashrate.is_none() {
+            return Some(nominal_hash_rate);
+        }
+        let min = self.min_nominal_hashrate.unwrap_or(f32::MIN);
+        let max = self.max_nominal_hashrate.unwrap_or(f32::MAX);
+        if (min..=max).contains(&nominal_hash_rate) {
+            return Some(nominal_hash_rate);
+        }
+        if self.clamp_hashrate && !nominal_hash_rate.is_nan() {
+            let clamped = nominal_hash_rate.max(min).min(max);
+            warn!("Nominal hashrate {nominal_hash_rate} out of range, clamped to {clamped}");
+            return Some(clamped);
+        }
+        None
+    }
+
+    // Returns the `too-many-channels` error answering the channel request `request_id` of
+    // `downstream_id` when, with `open_channels` channels open, it may not open another one.
+    fn channel_limit_error(
+        &self,
+        downstream_id: DownstreamId,
+        request_id: u32,
+        open_channels: usize,
+    ) -> Option<RouteMessageTo<'static>> {
+        let max_channels = self.max_channels_per_downstream?;
+        if open_channels < max_channels {
+            return None;
+        }
+        warn!(
+            "Downstream {downstream_id} has {open_channels} channels open, rejecting channel request {request_id}: too-many-channels"
+        );
+        let open_mining_channel_error = OpenMiningChannelError {
+            request_id,
+            error_code: "too-many-channels"
+                .to_string()
+                .try_into()
+                .expect("error code must be valid string"),
+        };
+        Some(RouteMessageTo::from((
+            downstream_id,
+            Mining::OpenMiningChannelError(open_mining_channel_error),
+        )))
+    }
+
+    // Records the sequence number of a share, logging it if out of sequence. Returns the
+    // `SubmitSharesError` to answer with when the share repeats or goes below the last sequence
+    // number of its channel and `reject_out_of_sequence_shares` is set. Gaps are only logged.
+    fn check_share_sequence(
+        &self,
+        share_sequences: &mut ShareSequenceTracker,
+        downstream_id: DownstreamId,
+        channel_id: ChannelId,
+        sequence_number: u32,
+    ) -> Option<SubmitSharesError<'static>> {
+        match share_sequences.record(downstream_id, channel_id, sequence_number)? {
+            SequenceViolation::Gap { missing } => {
+                warn!(
+                    "Share sequence gap: downstream_id: {}, channel_id: {}, sequence_number: {}, {} sequence number(s) skipped",
+                    downstream_id, channel_id, sequence_number, missing
+                );
+                return None;
+            }
+            violation => warn!(
+                "Share sequence {:?}: downstream_id: {}, channel_id: {}, sequence_number: {}",
+                violation, downstream_id, channel_id, sequence_number
+            ),
+        }
+        if !self.reject_out_of_sequence_shares {
+            return None;
+        }
+        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: invalid-sequence-number ❌", downstream_id, channel_id, sequence_number);
+        Some(SubmitSharesError {
+            channel_id,
+            sequence_number,
+            error_code: "invalid-sequence-number"
+                .to_string()
+                .try_into()
+                .expect("error code must be valid string"),
+        })
+    }
+
     // Bootstraps a group channel with the given parameters.
     // Returns a `GroupChannel` if successful, otherwise returns `None`.
     //
@@ -271,7 +450,7 @@ impl ChannelManager {
         }
 
         info!("Starting downstream server at {listening_address}");
-        let server = TcpListener::bind(listening_address)
+        let server = bind_with_retry(listening_address, self.bind_retry)
             .await
             .map_err(|e| {
                 error!(error = ?e, "Failed to bind downstream server at {listening_address}");
@@ -300,6 +479,13 @@ impl ChannelManager {
                     res = server.accept() => {
                         match res {
                             Ok((stream, socket_address)) => {
+                                let connection_permit = match self.connection_limiter.try_acquire(socket_address.ip()) {
+                                    Ok(permit) => permit,
+                                    Err(reason) => {
+                                        warn!(%socket_address, %reason, "Rejecting downstream connection");
+                                        continue;
+                                    }
+                                };
                                 info!(%socket_address, "New downstream connection");
                                 let responder = match Responder::from_authority_kp(
                                     &authority_public_key.into_bytes(),
@@ -353,10 +539,18 @@ impl ChannelManager {
                                     status_sender.clone(),
                                     self.supported_extensions.clone(),
                                     self.required_extensions.clone(),
+                                    self.max_frame_size,
+                                    self.message_counters.clone(),
+                                    self.slow_consumer_policy,
+                                    self.downstream_authenticator.clone(),
+                                    self.authentication_error_code.clone(),
+                                    self.defer_downstream_until_upstream_ready
+                                        .then(|| self.template_provider_connected.clone()),
                                 );
 
                                 self.channel_manager_data.super_safe_lock(|data| {
                                     data.downstream.insert(downstream_id, downstream.clone());
+                                    data.connection_permits.insert(downstream_id, connection_permit);
                                 });
 
                                 downstream
@@ -397,6 +591,13 @@ impl ChannelManager {
 
         self.coinbase_output_constraints(coinbase_outputs).await?;
 
+        if let Some(share_export) = self.share_export.clone() {
+            task_manager.spawn(
+                self.clone()
+                    .run_share_export(share_export, notify_shutdown.subscribe()),
+            );
+        }
+
         task_manager.spawn(async move {
             let cm = self.clone();
             let vardiff_future = self.run_vardiff_loop();
@@ -453,7 +654,9 @@ impl ChannelManager {
     //
     // Given a `downstream_id`, this method:
     // 1. Removes the corresponding Downstream from the `downstream` map.
-    // 2. Removes the channels of the corresponding Downstream from `vardiff` map.
+    // 2. Removes the channels of the corresponding Downstream from the `vardiff` and
+    //    `share_sequences` maps.
+    // 3. Releases the connection slot held by the Downstream.
     #[allow(clippy::result_large_err)]
     fn remove_downstream(
         &self,
@@ -461,9 +664,11 @@ impl ChannelManager {
     ) -> PoolResult<(), error::ChannelManager> {
         self.channel_manager_data.super_safe_lock(|cm_data| {
             cm_data.downstream.remove(&downstream_id);
+            cm_data.connection_permits.remove(&downstream_id);
             cm_data
                 .vardiff
                 .retain(|key, _| key.downstream_id != downstream_id);
+            cm_data.share_sequences.remove_downstream(downstream_id);
         });
         Ok(())
     }
@@ -503,6 +708,7 @@ impl ChannelManager {
         channel_id: ChannelId,
         channel_state: &mut ExtendedChannel<'static, DefaultJobStore<ExtendedJob<'static>>>,
         vardiff_state: &mut VardiffState,
+        max_step_ratio: Option<f32>,
         updates: &mut Vec<RouteMessageTo>,
     ) {
         let (hashrate, target, shares_per_minute) = (
@@ -520,6 +726,9 @@ impl ChannelManager {
         let Some(new_hashrate) = new_hashrate_opt else {
             return;
         };
+        let new_hashrate = max_step_ratio.map_or(new_hashrate, |max_ratio| {
+            clamp_vardiff_step(hashrate, new_hashrate, max_ratio)
+        });
 
         match channel_state.update_channel(new_hashrate, None) {
             Ok(()) => {
@@ -548,6 +757,7 @@ impl ChannelManager {
         channel_id: ChannelId,
         channel: &mut StandardChannel<'static, DefaultJobStore<StandardJob<'static>>>,
         vardiff_state: &mut VardiffState,
+        max_step_ratio: Option<f32>,
         updates: &mut Vec<RouteMessageTo>,
     ) {
         let hashrate = channel.get_nominal_hashrate();
@@ -561,6 +771,9 @@ impl ChannelManager {
         };
 
         if let Some(new_hashrate) = new_hashrate_opt {
+            let new_hashrate = max_step_ratio.map_or(new_hashrate, |max_ratio| {
+                clamp_vardiff_step(hashrate, new_hashrate, max_ratio)
+            });
             match channel.update_channel(new_hashrate, None) {
                 Ok(()) => {
                     let updated_target = channel.get_target();
@@ -602,6 +815,73 @@ impl ChannelManager {
         }
     }
 
+    // Dumps the shares recorded since the previous dump every export interval, and tries a last
+    // dump on shutdown so the shares of the ongoing period are not lost. Shares whose dump failed
+    // go back to the ledger, to be part of the next dump.
+    async fn run_share_export(
+        self,
+        share_export: ShareExport,
+        mut shutdown_rx: broadcast::Receiver<ShutdownMessage>,
+    ) {
+        let mut ticker = tokio::time::interval(share_export.interval);
+        // the first tick completes right away
+        ticker.tick().await;
+        let mut period_start = SystemTime::now();
+        loop {
+            let shutdown = select! {
+                _ = ticker.tick() => false,
+                message = shutdown_rx.recv() => match message {
+                    Ok(ShutdownMessage::ShutdownAll)
+                    | Err(broadcast::error::RecvError::Closed) => true,
+                    _ => continue,
+                },
+            };
+            let workers = self.channel_manager_data.super_safe_lock(|data| {
+                data.share_ledger
+                    .as_mut()
+                    .map(ShareLedger::take)
+                    .unwrap_or_default()
+            });
+            let period_end = SystemTime::now();
+            // the dump is written with blocking file I/O
+            let dump = {
+                let share_export = share_export.clone();
+                let workers = workers.clone();
+                tokio::task::spawn_blocking(move || {
+                    write_dump(&share_export, period_start, period_end, &workers)
+                })
+            };
+            match dump
+                .await
+                .map_err(std::io::Error::other)
+                .and_then(|res| res)
+            {
+                Ok(()) => {
+                    debug!(
+                        "Wrote the shares of {} workers to {}",
+                        workers.len(),
+                        share_export.path.display()
+                    );
+                    period_start = period_end;
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to write the share accounting dump to {}, keeping its shares for the next dump: {e}",
+                        share_export.path.display()
+                    );
+                    self.channel_manager_data.super_safe_lock(|data| {
+                        if let Some(share_ledger) = data.share_ledger.as_mut() {
+                            share_ledger.restore(workers);
+                        }
+                    });
+                }
+            }
+            if shutdown {
+                break;
+            }
+        }
+    }
+
     // Runs vardiff across **all channels** and generates updates.
     //
     // # Purpose
@@ -628,6 +908,7 @@ impl ChannelManager {
                                 *channel_id,
                                 standard_channel,
                                 vardiff_state,
+                                self.vardiff_max_step_ratio,
                                 &mut messages,
                             );
                         }
@@ -637,6 +918,7 @@ impl ChannelManager {
                                 *channel_id,
                                 extended_channel,
                                 vardiff_state,
+                                self.vardiff_max_step_ratio,
                                 &mut messages,
                             );
                         }
@@ -701,6 +983,14 @@ impl<'a> From<(DownstreamId, Mining<'a>)> for RouteMessageTo<'a> {
 }
 
 impl RouteMessageTo<'_> {
+    /// Whether the message rejects a share.
+    pub fn is_submit_shares_error(&self) -> bool {
+        matches!(
+            self,
+            RouteMessageTo::Downstream((_, Mining::SubmitSharesError(_)))
+        )
+    }
+
     pub async fn forward(self, channel_manager_channel: &ChannelManagerChannel) {
         match self {
             RouteMessageTo::Downstream((downstream_id, message)) => {
@@ -0,0 +1,84 @@
+//! Tracking of the `sequence_number` of the shares submitted on each channel.
+//!
+//! Clients number the shares of a channel sequentially. A skipped number is worth logging but may
+//! be legitimate, while a repeated or older number points to a replayed share or a buggy client.
+use std::collections::HashMap;
+
+use stratum_apps::{
+    monitoring::client::ShareSequenceViolations,
+    utils::types::{ChannelId, DownstreamId, VardiffKey},
+};
+
+/// How the sequence number of a share deviates from the next one of its channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceViolation {
+    /// `missing` sequence numbers were skipped.
+    Gap { missing: u32 },
+    /// The sequence number of the last share was repeated.
+    Duplicate,
+    /// The sequence number is below the one of the last share.
+    Regression,
+}
+
+/// Last sequence number seen on each downstream channel, with violation counters.
+#[derive(Debug, Default)]
+pub struct ShareSequenceTracker {
+    last_sequence_numbers: HashMap<VardiffKey, u32>,
+    violations: ShareSequenceViolations,
+}
+
+impl ShareSequenceTracker {
+    /// Records a share of `channel_id` and returns how its sequence number deviates from the
+    /// expected one, if it does.
+    ///
+    /// The first share of a channel sets its baseline. Numbers are compared with wrapping
+    /// arithmetic, so a counter rolling over `u32::MAX` is not a regression. Duplicates and
+    /// regressions leave the last sequence number unchanged.
+    pub fn record(
+        &mut self,
+        downstream_id: DownstreamId,
+        channel_id: ChannelId,
+        sequence_number: u32,
+    ) -> Option<SequenceViolation> {
+        let key = (downstream_id, channel_id).into();
+        let Some(last) = self.last_sequence_numbers.get_mut(&key) else {
+            self.last_sequence_numbers.insert(key, sequence_number);
+            return None;
+        };
+
+        let distance = sequence_number.wrapping_sub(*last);
+        let violation = match distance {
+            0 => Some(SequenceViolation::Duplicate),
+            1 => None,
+            d if d <= u32::MAX / 2 => Some(SequenceViolation::Gap { missing: d - 1 }),
+            _ => Some(SequenceViolation::Regression),
+        };
+        match violation {
+            Some(SequenceViolation::Duplicate) => self.violations.duplicates += 1,
+            Some(SequenceViolation::Regression) => self.violations.regressions += 1,
+            Some(SequenceViolation::Gap { .. }) => {
+                self.violations.gaps += 1;
+                *last = sequence_number;
+            }
+            None => *last = sequence_number,
+        }
+        violation
+    }
+
+    /// Forgets the sequence number of a closed channel.
+    pub fn remove_channel(&mut self, downstream_id: DownstreamId, channel_id: ChannelId) {
+        self.last_sequence_numbers
+            .remove(&(downstream_id, channel_id).into());
+    }
+
+    /// Forgets the sequence numbers of every channel of a disconnected downstream.
+    pub fn remove_downstream(&mut self, downstream_id: DownstreamId) {
+        self.last_sequence_numbers
+            .retain(|key, _| key.downstream_id != downstream_id);
+    }
+
+    /// Returns the number of violations detected so far.
+    pub fn violations(&self) -> ShareSequenceViolations {
+        self.violations
+    }
+}
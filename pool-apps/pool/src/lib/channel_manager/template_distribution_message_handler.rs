@@ -1,12 +1,15 @@
 use std::sync::atomic::Ordering;
 
-use stratum_apps::stratum_core::{
-    bitcoin::Amount,
-    channels_sv2::outputs::deserialize_outputs,
-    handlers_sv2::HandleTemplateDistributionMessagesFromServerAsync,
-    mining_sv2::SetNewPrevHash as SetNewPrevHashMp,
-    parsers_sv2::{Mining, Tlv},
-    template_distribution_sv2::*,
+use stratum_apps::{
+    network_helpers::broadcast_pacing::BroadcastPacer,
+    stratum_core::{
+        bitcoin::Amount,
+        channels_sv2::outputs::deserialize_outputs,
+        handlers_sv2::HandleTemplateDistributionMessagesFromServerAsync,
+        mining_sv2::SetNewPrevHash as SetNewPrevHashMp,
+        parsers_sv2::{Mining, Tlv},
+        template_distribution_sv2::*,
+    },
 };
 use tracing::{info, warn};
 
@@ -127,7 +130,9 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
             Ok::<Vec<RouteMessageTo<'_>>, Self::Error>(messages)
         })?;
 
+        let mut pacer = BroadcastPacer::new(self.job_broadcast_pacing, messages.len());
         for message in messages {
+            pacer.pace().await;
             message.forward(&self.channel_manager_channel).await;
         }
 
@@ -162,6 +167,10 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
 
+        if let Some(block_hash) = self.found_blocks.record_chain_tip(&msg.prev_hash.to_vec()) {
+            info!("💰 Block {block_hash} accepted: it is the new chain tip 💰");
+        }
+
         let messages = self.channel_manager_data.super_safe_lock(|data| {
             data.last_new_prev_hash = Some(msg.clone().into_static());
 
@@ -244,7 +253,9 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
             Ok::<Vec<RouteMessageTo<'_>>, Self::Error>(messages)
         })?;
 
+        let mut pacer = BroadcastPacer::new(self.job_broadcast_pacing, messages.len());
         for message in messages {
+            pacer.pace().await;
             message.forward(&self.channel_manager_channel).await;
         }
 
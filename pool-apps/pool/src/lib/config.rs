@@ -11,14 +11,30 @@
 use std::{
     net::SocketAddr,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use stratum_apps::{
-    config_helpers::{opt_path_from_toml, CoinbaseRewardScript},
+    config_helpers::{
+        authority_secret_key_from_toml, opt_path_from_toml, CoinbaseOutputError,
+        CoinbaseRewardScript,
+    },
     key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
+    network_helpers::{
+        broadcast_pacing::BroadcastPacing, noise_stream::DEFAULT_MAX_FRAME_SIZE,
+        slow_consumer::SlowConsumerPolicy,
+    },
     stratum_core::bitcoin::{Amount, TxOut},
     tp_type::TemplateProviderType,
-    utils::types::{SharesBatchSize, SharesPerMinute},
+    utils::{
+        bind_retry::BindRetry,
+        types::{SharesBatchSize, SharesPerMinute},
+    },
+};
+
+use crate::{
+    error::PoolErrorKind,
+    share_export::{ShareExport, ShareExportFormat},
 };
 
 /// Configuration for the Pool, including connection, authority, and coinbase settings.
@@ -27,6 +43,7 @@ pub struct PoolConfig {
     listen_address: SocketAddr,
     template_provider_type: TemplateProviderType,
     authority_public_key: Secp256k1PublicKey,
+    #[serde(flatten, deserialize_with = "authority_secret_key_from_toml")]
     authority_secret_key: Secp256k1SecretKey,
     cert_validity_sec: u64,
     coinbase_reward_script: CoinbaseRewardScript,
@@ -42,12 +59,159 @@ pub struct PoolConfig {
     monitoring_address: Option<SocketAddr>,
     #[serde(default = "default_monitoring_cache_refresh_secs")]
     monitoring_cache_refresh_secs: u64,
+    /// Age, in seconds, past which a monitoring request rebuilds the snapshot itself, bounding
+    /// the served staleness if the periodic refresh stalls. Unset disables it.
+    #[serde(default)]
+    monitoring_on_demand_refresh_secs: Option<u64>,
+    /// Log the coinbase outputs paid at startup, and report them on the monitoring server.
+    #[serde(default)]
+    report_coinbase_outputs: bool,
+    /// Lowest `nominal_hash_rate` (in h/s) accepted when opening a channel.
+    #[serde(default)]
+    min_nominal_hashrate: Option<f32>,
+    /// Highest `nominal_hash_rate` (in h/s) accepted when opening a channel.
+    #[serde(default)]
+    max_nominal_hashrate: Option<f32>,
+    /// Clamp out-of-range `nominal_hash_rate` into the configured bounds instead of rejecting
+    /// the channel.
+    #[serde(default)]
+    clamp_hashrate: bool,
+    /// Maximum number of channels a single downstream connection may have open at once, at least
+    /// 1. Unset disables the limit.
+    #[serde(default)]
+    max_channels_per_downstream: Option<usize>,
+    /// Answer shares repeating or going below the last sequence number of their channel with a
+    /// `SubmitSharesError` instead of only logging them.
+    #[serde(default)]
+    reject_out_of_sequence_shares: bool,
+    /// Answer shares whose hash does not meet the current target of their channel with the
+    /// `above-target` error code, instead of `difficulty-too-low`.
+    #[serde(default)]
+    strict_share_validation: bool,
+    /// Largest factor by which a single vardiff adjustment multiplies or divides the difficulty
+    /// of a channel. Unset lets vardiff move the difficulty in one step.
+    #[serde(default)]
+    vardiff_max_step_ratio: Option<f32>,
+    /// Maximum number of concurrent downstream connections from a single IP address. `0`
+    /// disables the limit.
+    #[serde(default = "default_max_connections_per_ip")]
+    max_connections_per_ip: u32,
+    /// Maximum number of new downstream connections accepted per second, across all IP
+    /// addresses. `0` disables the limit.
+    #[serde(default = "default_max_accepts_per_sec")]
+    max_accepts_per_sec: u32,
+    /// Number of downstream connections closed within a minute above which a warning about
+    /// flapping miners is logged. `0` disables the warning.
+    #[serde(default)]
+    churn_warning_per_minute: u32,
+    /// Number of running tasks above which a warning is logged. Tasks are still spawned past it.
+    /// Unset disables the soft cap.
+    #[serde(default)]
+    max_tasks: Option<usize>,
+    /// Number of attempts to reconnect to a `Sv2Tp` template provider after the connection to it
+    /// drops, while the last known job keeps being served. `0` shuts the pool down right away.
+    #[serde(default)]
+    template_provider_reconnect_attempts: u32,
+    /// Seconds to wait before the first reconnection to the template provider, doubled before
+    /// every next attempt.
+    #[serde(default = "default_template_provider_reconnect_backoff_secs")]
+    template_provider_reconnect_backoff_secs: u64,
+    /// Whether downstreams connecting while the connection to the template provider is being
+    /// re-established are refused with a `temporarily-unavailable` `SetupConnectionError`. The
+    /// listener only opens once the first template arrived in any case.
+    #[serde(default)]
+    defer_downstream_until_upstream_ready: bool,
+    /// Largest SV2 frame, in bytes, accepted from the template provider and downstreams. A peer
+    /// announcing a larger frame is disconnected before its payload is read.
+    #[serde(default = "default_max_frame_size")]
+    max_frame_size: usize,
+    /// Number of messages waiting to be sent to a downstream above which it is falling behind.
+    /// Unset disables slow consumer detection.
+    #[serde(default)]
+    slow_consumer_queue_threshold: Option<usize>,
+    /// Seconds the queue towards a downstream must stay above `slow_consumer_queue_threshold`
+    /// for the downstream to be flagged as a slow consumer.
+    #[serde(default = "default_slow_consumer_secs")]
+    slow_consumer_secs: u64,
+    /// Whether a downstream flagged as a slow consumer is disconnected instead of only being
+    /// reported.
+    #[serde(default)]
+    disconnect_slow_consumers: bool,
+    /// Number of downstream messages sent back to back when broadcasting a new job or chain tip.
+    /// Unset sends the whole broadcast at once.
+    #[serde(default)]
+    job_broadcast_batch_size: Option<usize>,
+    /// Milliseconds over which the batches of a broadcast are spread. No downstream gets the
+    /// broadcast later than this after the first one.
+    #[serde(default = "default_job_broadcast_max_spread_ms")]
+    job_broadcast_max_spread_ms: u64,
+    /// File the accepted and rejected shares of each worker are periodically written to. Unset
+    /// disables the export.
+    #[serde(default, deserialize_with = "opt_path_from_toml")]
+    share_export_path: Option<PathBuf>,
+    /// Format of the share accounting dumps.
+    #[serde(default)]
+    share_export_format: ShareExportFormat,
+    /// Seconds between two share accounting dumps.
+    #[serde(default = "default_share_export_interval_secs")]
+    share_export_interval_secs: u64,
+    /// Error code of the `SetupConnectionError` sent to a downstream rejected by the
+    /// authenticator of the pool, at most 255 bytes long.
+    #[serde(default = "default_authentication_error_code")]
+    authentication_error_code: String,
+    /// Number of times binding the downstream or monitoring listener is retried while its
+    /// address is still in use, e.g. by the previous process on a fast restart.
+    #[serde(default = "default_bind_retries")]
+    bind_retries: u32,
+    /// Milliseconds between two binds of a listener whose address is in use.
+    #[serde(default = "default_bind_retry_delay_ms")]
+    bind_retry_delay_ms: u64,
 }
 
 fn default_monitoring_cache_refresh_secs() -> u64 {
     15
 }
 
+fn default_max_connections_per_ip() -> u32 {
+    100
+}
+
+fn default_max_accepts_per_sec() -> u32 {
+    100
+}
+
+fn default_template_provider_reconnect_backoff_secs() -> u64 {
+    2
+}
+
+fn default_max_frame_size() -> usize {
+    DEFAULT_MAX_FRAME_SIZE
+}
+
+fn default_slow_consumer_secs() -> u64 {
+    10
+}
+
+fn default_job_broadcast_max_spread_ms() -> u64 {
+    5
+}
+
+fn default_share_export_interval_secs() -> u64 {
+    600
+}
+
+fn default_authentication_error_code() -> String {
+    "unauthorized".to_string()
+}
+
+fn default_bind_retries() -> u32 {
+    5
+}
+
+fn default_bind_retry_delay_ms() -> u64 {
+    500
+}
+
 impl PoolConfig {
     /// Creates a new instance of the [`PoolConfig`].
     ///
@@ -82,14 +246,116 @@ impl PoolConfig {
             required_extensions,
             monitoring_address: None,
             monitoring_cache_refresh_secs: 15,
+            monitoring_on_demand_refresh_secs: None,
+            report_coinbase_outputs: false,
+            min_nominal_hashrate: None,
+            max_nominal_hashrate: None,
+            clamp_hashrate: false,
+            max_channels_per_downstream: None,
+            reject_out_of_sequence_shares: false,
+            strict_share_validation: false,
+            vardiff_max_step_ratio: None,
+            max_connections_per_ip: default_max_connections_per_ip(),
+            max_accepts_per_sec: default_max_accepts_per_sec(),
+            churn_warning_per_minute: 0,
+            max_tasks: None,
+            template_provider_reconnect_attempts: 0,
+            template_provider_reconnect_backoff_secs:
+                default_template_provider_reconnect_backoff_secs(),
+            defer_downstream_until_upstream_ready: false,
+            max_frame_size: default_max_frame_size(),
+            slow_consumer_queue_threshold: None,
+            slow_consumer_secs: default_slow_consumer_secs(),
+            disconnect_slow_consumers: false,
+            job_broadcast_batch_size: None,
+            job_broadcast_max_spread_ms: default_job_broadcast_max_spread_ms(),
+            share_export_path: None,
+            share_export_format: ShareExportFormat::default(),
+            share_export_interval_secs: default_share_export_interval_secs(),
+            authentication_error_code: default_authentication_error_code(),
+            bind_retries: default_bind_retries(),
+            bind_retry_delay_ms: default_bind_retry_delay_ms(),
         }
     }
 
+    /// Sets the accepted `nominal_hash_rate` range for new channels.
+    ///
+    /// With `clamp_hashrate` set, out-of-range values are clamped into the range instead of the
+    /// channel being rejected.
+    pub fn with_nominal_hashrate_bounds(
+        mut self,
+        min_nominal_hashrate: Option<f32>,
+        max_nominal_hashrate: Option<f32>,
+        clamp_hashrate: bool,
+    ) -> Self {
+        self.min_nominal_hashrate = min_nominal_hashrate;
+        self.max_nominal_hashrate = max_nominal_hashrate;
+        self.clamp_hashrate = clamp_hashrate;
+        self
+    }
+
+    /// Sets the per-IP connection cap and the accept rate limit of the downstream server. `0`
+    /// disables the corresponding limit.
+    pub fn with_connection_limits(
+        mut self,
+        max_connections_per_ip: u32,
+        max_accepts_per_sec: u32,
+    ) -> Self {
+        self.max_connections_per_ip = max_connections_per_ip;
+        self.max_accepts_per_sec = max_accepts_per_sec;
+        self
+    }
+
     /// Returns the coinbase output.
     pub fn coinbase_reward_script(&self) -> &CoinbaseRewardScript {
         &self.coinbase_reward_script
     }
 
+    /// Checks the settings that cannot be checked while deserializing the config.
+    pub fn validate(&self) -> Result<(), PoolErrorKind> {
+        if let (Some(min), Some(max)) = (self.min_nominal_hashrate, self.max_nominal_hashrate) {
+            if min > max {
+                return Err(PoolErrorKind::Configuration(format!(
+                    "min_nominal_hashrate ({min}) is above max_nominal_hashrate ({max})"
+                )));
+            }
+        }
+        if let Some(max_staleness) = self.monitoring_on_demand_refresh_secs {
+            if max_staleness <= self.monitoring_cache_refresh_secs {
+                return Err(PoolErrorKind::Configuration(format!(
+                    "monitoring_on_demand_refresh_secs ({max_staleness}) must be above monitoring_cache_refresh_secs ({})",
+                    self.monitoring_cache_refresh_secs
+                )));
+            }
+        }
+        // a limit of 0 would reject every channel
+        if self.max_channels_per_downstream == Some(0) {
+            return Err(PoolErrorKind::Configuration(
+                "max_channels_per_downstream must be at least 1, leave it unset for no limit"
+                    .to_string(),
+            ));
+        }
+        // sent as the `Str0255` error code of a `SetupConnectionError`
+        if self.authentication_error_code.len() > 255 {
+            return Err(PoolErrorKind::Configuration(format!(
+                "authentication_error_code is {} bytes long, at most 255 are allowed",
+                self.authentication_error_code.len()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that the address of the coinbase output belongs to the network of the Bitcoin Core
+    /// template provider, if one is configured.
+    pub fn validate_coinbase_reward_script(&self) -> Result<(), CoinbaseOutputError> {
+        match &self.template_provider_type {
+            TemplateProviderType::BitcoinCoreIpc { network, .. } => {
+                self.coinbase_reward_script.check_network(network.network())
+            }
+            TemplateProviderType::Sv2Tp { .. } => Ok(()),
+        }
+    }
+
     /// Returns Pool listenining address.
     pub fn listen_address(&self) -> &SocketAddr {
         &self.listen_address
@@ -173,10 +439,276 @@ impl PoolConfig {
         self.monitoring_address
     }
 
+    /// Enables the monitoring server on `monitoring_address`.
+    pub fn with_monitoring(
+        mut self,
+        monitoring_address: SocketAddr,
+        monitoring_cache_refresh_secs: u64,
+    ) -> Self {
+        self.monitoring_address = Some(monitoring_address);
+        self.monitoring_cache_refresh_secs = monitoring_cache_refresh_secs;
+        self
+    }
+
     /// Returns the monitoring cache refresh interval in seconds.
     pub fn monitoring_cache_refresh_secs(&self) -> u64 {
         self.monitoring_cache_refresh_secs
     }
+
+    /// Makes monitoring requests rebuild a snapshot older than `max_staleness_secs` seconds.
+    pub fn with_monitoring_on_demand_refresh(mut self, max_staleness_secs: u64) -> Self {
+        self.monitoring_on_demand_refresh_secs = Some(max_staleness_secs);
+        self
+    }
+
+    /// Returns the snapshot age past which monitoring requests rebuild it, if enabled.
+    pub fn monitoring_on_demand_refresh(&self) -> Option<Duration> {
+        self.monitoring_on_demand_refresh_secs
+            .map(Duration::from_secs)
+    }
+
+    /// Sets whether the coinbase outputs paid are logged at startup and reported on the
+    /// monitoring server.
+    pub fn with_report_coinbase_outputs(mut self, report_coinbase_outputs: bool) -> Self {
+        self.report_coinbase_outputs = report_coinbase_outputs;
+        self
+    }
+
+    /// Returns whether the coinbase outputs paid are logged at startup and reported on the
+    /// monitoring server.
+    pub fn report_coinbase_outputs(&self) -> bool {
+        self.report_coinbase_outputs
+    }
+
+    /// Returns the lowest accepted nominal hashrate (optional).
+    pub fn min_nominal_hashrate(&self) -> Option<f32> {
+        self.min_nominal_hashrate
+    }
+
+    /// Returns the highest accepted nominal hashrate (optional).
+    pub fn max_nominal_hashrate(&self) -> Option<f32> {
+        self.max_nominal_hashrate
+    }
+
+    /// Returns whether out-of-range nominal hashrates are clamped instead of rejected.
+    pub fn clamp_hashrate(&self) -> bool {
+        self.clamp_hashrate
+    }
+
+    /// Sets the maximum number of channels a single downstream connection may have open at once.
+    pub fn with_max_channels_per_downstream(mut self, max_channels: usize) -> Self {
+        self.max_channels_per_downstream = Some(max_channels);
+        self
+    }
+
+    /// Returns the maximum number of channels per downstream connection, if limited.
+    pub fn max_channels_per_downstream(&self) -> Option<usize> {
+        self.max_channels_per_downstream
+    }
+
+    /// Sets whether shares repeating or going below the last sequence number of their channel are
+    /// rejected instead of only logged.
+    pub fn with_reject_out_of_sequence_shares(mut self, reject: bool) -> Self {
+        self.reject_out_of_sequence_shares = reject;
+        self
+    }
+
+    /// Returns whether out-of-sequence shares are rejected.
+    pub fn reject_out_of_sequence_shares(&self) -> bool {
+        self.reject_out_of_sequence_shares
+    }
+
+    /// Sets whether shares below the target of their channel are answered with `above-target`.
+    pub fn with_strict_share_validation(mut self, strict: bool) -> Self {
+        self.strict_share_validation = strict;
+        self
+    }
+
+    /// Returns whether strict share validation is enabled.
+    pub fn strict_share_validation(&self) -> bool {
+        self.strict_share_validation
+    }
+
+    /// Sets the largest factor by which a single vardiff adjustment changes the difficulty of a
+    /// channel, in either direction.
+    pub fn with_vardiff_max_step_ratio(mut self, max_step_ratio: f32) -> Self {
+        self.vardiff_max_step_ratio = Some(max_step_ratio);
+        self
+    }
+
+    /// Returns the largest factor by which a single vardiff adjustment changes the difficulty of
+    /// a channel, if bounded.
+    pub fn vardiff_max_step_ratio(&self) -> Option<f32> {
+        self.vardiff_max_step_ratio
+    }
+
+    /// Returns the maximum number of concurrent downstream connections per IP address.
+    pub fn max_connections_per_ip(&self) -> u32 {
+        self.max_connections_per_ip
+    }
+
+    /// Returns the maximum number of new downstream connections accepted per second.
+    pub fn max_accepts_per_sec(&self) -> u32 {
+        self.max_accepts_per_sec
+    }
+
+    /// Sets the number of downstream connections closed within a minute above which a warning is
+    /// logged. `0` disables the warning.
+    pub fn with_churn_warning_per_minute(mut self, churn_warning_per_minute: u32) -> Self {
+        self.churn_warning_per_minute = churn_warning_per_minute;
+        self
+    }
+
+    /// Returns the number of downstream connections closed within a minute above which a warning is
+    /// logged.
+    pub fn churn_warning_per_minute(&self) -> u32 {
+        self.churn_warning_per_minute
+    }
+
+    /// Sets the number of running tasks above which a warning is logged.
+    pub fn with_max_tasks(mut self, max_tasks: usize) -> Self {
+        self.max_tasks = Some(max_tasks);
+        self
+    }
+
+    /// Returns the soft cap on running tasks, if any.
+    pub fn max_tasks(&self) -> Option<usize> {
+        self.max_tasks
+    }
+
+    /// Sets how many times, and after how many seconds doubling between attempts, the pool
+    /// reconnects to a `Sv2Tp` template provider whose connection dropped. `0` attempts shut the
+    /// pool down right away.
+    pub fn with_template_provider_reconnect(mut self, attempts: u32, backoff_secs: u64) -> Self {
+        self.template_provider_reconnect_attempts = attempts;
+        self.template_provider_reconnect_backoff_secs = backoff_secs;
+        self
+    }
+
+    /// Returns the number of attempts to reconnect to the template provider.
+    pub fn template_provider_reconnect_attempts(&self) -> u32 {
+        self.template_provider_reconnect_attempts
+    }
+
+    /// Returns the wait before the first reconnection to the template provider.
+    pub fn template_provider_reconnect_backoff(&self) -> Duration {
+        Duration::from_secs(self.template_provider_reconnect_backoff_secs)
+    }
+
+    /// Sets whether downstreams connecting while the template provider is reconnecting are
+    /// refused.
+    pub fn with_defer_downstream_until_upstream_ready(mut self, defer: bool) -> Self {
+        self.defer_downstream_until_upstream_ready = defer;
+        self
+    }
+
+    /// Returns whether downstreams connecting while the template provider is reconnecting are
+    /// refused.
+    pub fn defer_downstream_until_upstream_ready(&self) -> bool {
+        self.defer_downstream_until_upstream_ready
+    }
+
+    /// Sets the largest SV2 frame accepted from peers, in bytes.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Returns the largest SV2 frame accepted from peers, in bytes.
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Enables slow consumer detection, flagging downstreams whose queue stays above
+    /// `queue_threshold` messages for `secs` seconds, and whether flagged downstreams are
+    /// disconnected.
+    pub fn with_slow_consumer_detection(
+        mut self,
+        queue_threshold: usize,
+        secs: u64,
+        disconnect: bool,
+    ) -> Self {
+        self.slow_consumer_queue_threshold = Some(queue_threshold);
+        self.slow_consumer_secs = secs;
+        self.disconnect_slow_consumers = disconnect;
+        self
+    }
+
+    /// Returns when a downstream is flagged as a slow consumer, if the detection is enabled.
+    pub fn slow_consumer_policy(&self) -> Option<SlowConsumerPolicy> {
+        self.slow_consumer_queue_threshold
+            .map(|queue_threshold| SlowConsumerPolicy {
+                queue_threshold,
+                duration: Duration::from_secs(self.slow_consumer_secs),
+                disconnect: self.disconnect_slow_consumers,
+            })
+    }
+
+    /// Sends job broadcasts to downstreams in batches of `batch_size` messages, spread over at
+    /// most `max_spread_ms` milliseconds.
+    pub fn with_job_broadcast_pacing(mut self, batch_size: usize, max_spread_ms: u64) -> Self {
+        self.job_broadcast_batch_size = Some(batch_size);
+        self.job_broadcast_max_spread_ms = max_spread_ms;
+        self
+    }
+
+    /// Returns how job broadcasts to downstreams are paced, if they are.
+    pub fn job_broadcast_pacing(&self) -> Option<BroadcastPacing> {
+        self.job_broadcast_batch_size
+            .map(|batch_size| BroadcastPacing {
+                batch_size,
+                max_spread: Duration::from_millis(self.job_broadcast_max_spread_ms),
+            })
+    }
+
+    /// Writes the shares of each worker to `path`, in `format`, every `interval_secs` seconds.
+    pub fn with_share_export(
+        mut self,
+        path: PathBuf,
+        format: ShareExportFormat,
+        interval_secs: u64,
+    ) -> Self {
+        self.share_export_path = Some(path);
+        self.share_export_format = format;
+        self.share_export_interval_secs = interval_secs;
+        self
+    }
+
+    /// Returns where and how often the share accounting is dumped, if it is.
+    pub fn share_export(&self) -> Option<ShareExport> {
+        self.share_export_path.clone().map(|path| ShareExport {
+            path,
+            format: self.share_export_format,
+            interval: Duration::from_secs(self.share_export_interval_secs.max(1)),
+        })
+    }
+
+    /// Sets the error code of the `SetupConnectionError` sent to rejected downstreams.
+    pub fn with_authentication_error_code(mut self, authentication_error_code: String) -> Self {
+        self.authentication_error_code = authentication_error_code;
+        self
+    }
+
+    /// Returns the error code of the `SetupConnectionError` sent to rejected downstreams.
+    pub fn authentication_error_code(&self) -> &str {
+        &self.authentication_error_code
+    }
+
+    /// Retries binding a listener whose address is in use `retries` times, `delay_ms`
+    /// milliseconds apart. No retries fail startup on the first bind failing.
+    pub fn with_bind_retry(mut self, retries: u32, delay_ms: u64) -> Self {
+        self.bind_retries = retries;
+        self.bind_retry_delay_ms = delay_ms;
+        self
+    }
+
+    /// Returns how binding a listener whose address is in use is retried.
+    pub fn bind_retry(&self) -> BindRetry {
+        BindRetry {
+            retries: self.bind_retries,
+            delay: Duration::from_millis(self.bind_retry_delay_ms),
+        }
+    }
 }
 
 /// Pool's authority public and secret keys.
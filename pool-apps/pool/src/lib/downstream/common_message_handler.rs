@@ -7,7 +7,7 @@ use stratum_apps::{
     stratum_core::{
         common_messages_sv2::{
             has_requires_std_job, has_work_selection, Protocol, SetupConnection,
-            SetupConnectionError, SetupConnectionSuccess,
+            SetupConnectionSuccess,
         },
         handlers_sv2::HandleCommonMessagesFromClientAsync,
         parsers_sv2::{AnyMessage, Tlv},
@@ -44,30 +44,40 @@ impl HandleCommonMessagesFromClientAsync for Downstream {
 
         if msg.protocol != Protocol::MiningProtocol {
             info!("Rejecting connection from {downstream_id}: SetupConnection asking for other protocols than mining protocol.");
-            let response = SetupConnectionError {
-                flags: 0,
-                error_code: "unsupported-protocol"
-                    .to_string()
-                    .try_into()
-                    .expect("error code must be valid string"),
-            };
-            let frame: Sv2Frame = AnyMessage::Common(response.into_static().into())
-                .try_into()
-                .map_err(PoolError::shutdown)?;
-            self.downstream_channel
-                .downstream_sender
-                .send(frame)
-                .await
-                .map_err(|_| {
-                    PoolError::disconnect(PoolErrorKind::ChannelErrorSender, downstream_id)
-                })?;
-
+            self.send_setup_connection_error("unsupported-protocol")
+                .await?;
             return Err(PoolError::disconnect(
                 PoolErrorKind::UnsupportedProtocol,
                 downstream_id,
             ));
         }
 
+        if self
+            .template_provider_connected
+            .as_ref()
+            .is_some_and(|connected| !connected.load(Ordering::SeqCst))
+        {
+            info!(
+                "Rejecting connection from {downstream_id}: the template provider is reconnecting"
+            );
+            self.send_setup_connection_error("temporarily-unavailable")
+                .await?;
+            return Err(PoolError::disconnect(
+                PoolErrorKind::UpstreamNotReady,
+                downstream_id,
+            ));
+        }
+
+        if !self.authenticator.authenticate(&msg) {
+            info!("Rejecting connection from {downstream_id}: not accepted by the authenticator");
+            self.send_setup_connection_error(&self.authentication_error_code)
+                .await?;
+            return Err(PoolError::disconnect(
+                PoolErrorKind::Unauthenticated,
+                downstream_id,
+            ));
+        }
+
         self.requires_custom_work
             .store(has_work_selection(msg.flags), Ordering::SeqCst);
         self.requires_standard_jobs
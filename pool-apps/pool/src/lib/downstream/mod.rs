@@ -4,12 +4,17 @@ use std::{
         atomic::{AtomicBool, AtomicU32},
         Arc,
     },
+    time::Instant,
 };
 
 use async_channel::{unbounded, Receiver, Sender};
 use stratum_apps::{
     custom_mutex::Mutex,
-    network_helpers::noise_stream::NoiseTcpStream,
+    network_helpers::{
+        message_counters::MessageCounters,
+        noise_stream::NoiseTcpStream,
+        slow_consumer::{SlowConsumerDetector, SlowConsumerPolicy, SLOW_CONSUMER_CHECK_INTERVAL},
+    },
     stratum_core::{
         channels_sv2::server::{
             extended::ExtendedChannel,
@@ -17,7 +22,7 @@ use stratum_apps::{
             jobs::{extended::ExtendedJob, job_store::DefaultJobStore, standard::StandardJob},
             standard::StandardChannel,
         },
-        common_messages_sv2::MESSAGE_TYPE_SETUP_CONNECTION,
+        common_messages_sv2::{SetupConnectionError, MESSAGE_TYPE_SETUP_CONNECTION},
         framing_sv2,
         handlers_sv2::{HandleCommonMessagesFromClientAsync, HandleExtensionsFromClientAsync},
         parsers_sv2::{parse_message_frame_with_tlvs, AnyMessage, Mining, Tlv},
@@ -32,6 +37,7 @@ use tokio::sync::broadcast;
 use tracing::{debug, error, warn};
 
 use crate::{
+    authentication::DownstreamAuthenticator,
     error::{self, PoolError, PoolErrorKind, PoolResult},
     io_task::spawn_io_tasks,
     status::{handle_error, Status, StatusSender},
@@ -60,6 +66,13 @@ pub struct DownstreamData {
     pub negotiated_extensions: Vec<u16>,
 }
 
+impl DownstreamData {
+    /// Returns the number of standard and extended channels open on the connection.
+    pub fn open_channels(&self) -> usize {
+        self.extended_channels.len() + self.standard_channels.len()
+    }
+}
+
 /// Communication layer for a downstream connection.
 ///
 /// Provides the messaging primitives for interacting with the
@@ -88,6 +101,17 @@ pub struct Downstream {
     pub supported_extensions: Vec<u16>,
     /// Extensions that the pool requires
     pub required_extensions: Vec<u16>,
+    /// When this downstream is flagged as a slow consumer, if the detection is enabled
+    slow_consumer_policy: Option<SlowConsumerPolicy>,
+    /// Flags this downstream as a slow consumer when the frames waiting to be sent to it pile up
+    pub slow_consumer: Arc<SlowConsumerDetector>,
+    /// Decides whether this downstream may use the pool after its `SetupConnection`
+    authenticator: Arc<dyn DownstreamAuthenticator>,
+    /// Error code of the `SetupConnectionError` sent when the authenticator rejects it
+    authentication_error_code: String,
+    /// Flag cleared while the template provider is reconnecting, set when a `SetupConnection`
+    /// arriving meanwhile is refused
+    template_provider_connected: Option<Arc<AtomicBool>>,
 }
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -110,6 +134,12 @@ impl Downstream {
         status_sender: Sender<Status>,
         supported_extensions: Vec<u16>,
         required_extensions: Vec<u16>,
+        max_frame_size: usize,
+        message_counters: Arc<MessageCounters>,
+        slow_consumer_policy: Option<SlowConsumerPolicy>,
+        authenticator: Arc<dyn DownstreamAuthenticator>,
+        authentication_error_code: String,
+        template_provider_connected: Option<Arc<AtomicBool>>,
     ) -> Self {
         let (noise_stream_reader, noise_stream_writer) = noise_stream.into_split();
         let status_sender = StatusSender::Downstream {
@@ -124,6 +154,8 @@ impl Downstream {
             noise_stream_writer,
             outbound_rx,
             inbound_tx,
+            max_frame_size,
+            message_counters,
             notify_shutdown,
             status_sender,
         );
@@ -151,6 +183,11 @@ impl Downstream {
             requires_custom_work: Arc::new(AtomicBool::new(false)),
             supported_extensions,
             required_extensions,
+            slow_consumer_policy,
+            slow_consumer: Arc::new(SlowConsumerDetector::new()),
+            authenticator,
+            authentication_error_code,
+            template_provider_connected,
         }
     }
 
@@ -187,6 +224,7 @@ impl Downstream {
 
         let mut receiver = self.downstream_channel.channel_manager_receiver.subscribe();
         task_manager.spawn(async move {
+            let mut slow_consumer_check = tokio::time::interval(SLOW_CONSUMER_CHECK_INTERVAL);
             loop {
                 let mut self_clone_1 = self.clone();
                 let downstream_id = self_clone_1.downstream_id;
@@ -221,6 +259,13 @@ impl Downstream {
                             }
                         }
                     }
+                    _ = slow_consumer_check.tick(), if self.slow_consumer_policy.is_some() => {
+                        if let Err(e) = self.check_slow_consumer() {
+                            if handle_error(&status_sender, e).await {
+                                break;
+                            }
+                        }
+                    }
 
                 }
             }
@@ -228,6 +273,39 @@ impl Downstream {
         });
     }
 
+    /// Samples the number of frames waiting to be sent to the downstream, flagging it as a slow
+    /// consumer once the queue stayed above the configured threshold for long enough.
+    ///
+    /// A flagged downstream is disconnected if the policy says so.
+    pub fn check_slow_consumer(&self) -> PoolResult<(), error::Downstream> {
+        let Some(policy) = &self.slow_consumer_policy else {
+            return Ok(());
+        };
+        let queue_depth = self.send_queue_depth();
+        if !self
+            .slow_consumer
+            .observe(queue_depth, policy, Instant::now())
+        {
+            return Ok(());
+        }
+        warn!(
+            downstream_id = self.downstream_id,
+            queue_depth, "Slow consumer: frames queued for more than {:?}", policy.duration
+        );
+        if policy.disconnect {
+            return Err(PoolError::disconnect(
+                PoolErrorKind::SlowConsumer(queue_depth),
+                self.downstream_id,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the number of frames waiting to be sent to the downstream.
+    pub fn send_queue_depth(&self) -> usize {
+        self.downstream_channel.downstream_sender.len()
+    }
+
     // Performs the initial handshake with a downstream peer.
     async fn setup_connection_with_downstream(&mut self) -> PoolResult<(), error::Downstream> {
         let mut frame = self
@@ -260,6 +338,31 @@ impl Downstream {
         ))
     }
 
+    // Answers the `SetupConnection` of the downstream with a `SetupConnectionError` carrying
+    // `error_code`.
+    async fn send_setup_connection_error(
+        &self,
+        error_code: &str,
+    ) -> PoolResult<(), error::Downstream> {
+        let response = SetupConnectionError {
+            flags: 0,
+            error_code: error_code
+                .to_string()
+                .try_into()
+                .map_err(PoolError::shutdown)?,
+        };
+        let frame: Sv2Frame = AnyMessage::Common(response.into_static().into())
+            .try_into()
+            .map_err(PoolError::shutdown)?;
+        self.downstream_channel
+            .downstream_sender
+            .send(frame)
+            .await
+            .map_err(|_| {
+                PoolError::disconnect(PoolErrorKind::ChannelErrorSender, self.downstream_id)
+            })
+    }
+
     // Handles messages sent from the channel manager to this downstream.
     async fn handle_channel_manager_message(
         self,
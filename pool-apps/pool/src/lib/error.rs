@@ -179,6 +179,10 @@ pub enum PoolErrorKind {
     BitcoinCoreSv2CancellationTokenActivated,
     /// Unsupported Protocol
     UnsupportedProtocol,
+    /// Downstream rejected by the authenticator after its SetupConnection
+    Unauthenticated,
+    /// Downstream connected while the template provider is reconnecting
+    UpstreamNotReady,
     /// Setup connection error
     SetupConnectionError,
     /// endpoint change error
@@ -189,6 +193,9 @@ pub enum PoolErrorKind {
     Configuration(String),
     /// Job not found
     JobNotFound,
+    /// Messages queued towards a downstream stayed above the slow consumer threshold (queue
+    /// depth)
+    SlowConsumer(usize),
 }
 
 impl std::fmt::Display for PoolErrorKind {
@@ -269,6 +276,8 @@ impl std::fmt::Display for PoolErrorKind {
                 write!(f, "BitcoinCoreSv2 cancellation token activated")
             },
             UnsupportedProtocol => write!(f, "Protocol not supported"),
+            Unauthenticated => write!(f, "Downstream not accepted by the authenticator"),
+            UpstreamNotReady => write!(f, "Template provider is reconnecting"),
             SetupConnectionError => {
                 write!(f, "Failed to Setup connection")
             }
@@ -278,6 +287,9 @@ impl std::fmt::Display for PoolErrorKind {
             CouldNotInitiateSystem => write!(f, "Could not initiate subsystem"),
             Configuration(e) => write!(f, "Configuration error: {e}"),
             JobNotFound => write!(f, "Job not found"),
+            SlowConsumer(queue_depth) => {
+                write!(f, "Downstream is not keeping up, {queue_depth} messages queued")
+            }
         }
     }
 }
@@ -2,7 +2,11 @@ use std::sync::Arc;
 
 use async_channel::{Receiver, Sender};
 use stratum_apps::{
-    network_helpers::noise_stream::{NoiseTcpReadHalf, NoiseTcpWriteHalf},
+    network_helpers::{
+        message_counters::MessageCounters,
+        noise_stream::{NoiseTcpReadHalf, NoiseTcpWriteHalf},
+        Error,
+    },
     stratum_core::framing_sv2::framing::Frame,
     task_manager::TaskManager,
     utils::types::{Message, Sv2Frame},
@@ -25,14 +29,18 @@ pub fn spawn_io_tasks(
     mut writer: NoiseTcpWriteHalf<Message>,
     outbound_rx: Receiver<Sv2Frame>,
     inbound_tx: Sender<Sv2Frame>,
+    max_frame_size: usize,
+    message_counters: Arc<MessageCounters>,
     notify_shutdown: broadcast::Sender<ShutdownMessage>,
     status_sender: StatusSender,
 ) {
     let caller = std::panic::Location::caller();
+    reader.set_max_frame_size(max_frame_size);
     let inbound_tx_clone = inbound_tx.clone();
     let outbound_rx_clone = outbound_rx.clone();
     {
         let mut shutdown_rx = notify_shutdown.subscribe();
+        let message_counters = message_counters.clone();
         let status_sender = status_sender.clone();
         let status_type: StatusType = StatusType::from(&status_sender);
 
@@ -68,6 +76,7 @@ pub fn spawn_io_tasks(
                                     },
                                     Frame::Sv2(sv2_frame) => {
                                         trace!("Received inbound frame");
+                                        message_counters.count_inbound(&sv2_frame);
                                         if let Err(e) = inbound_tx.send(sv2_frame).await {
                                             inbound_tx.close();
                                             error!(error=?e, "Failed to forward inbound frame");
@@ -76,6 +85,11 @@ pub fn spawn_io_tasks(
                                     },
                                 }
                             }
+                            Err(Error::FrameTooLarge { size, max }) => {
+                                error!(size, max, "Frame above the maximum frame size, closing the connection");
+                                inbound_tx.close();
+                                break;
+                            }
                             Err(e) => {
                                 error!(error=?e, "Reader error");
                                 inbound_tx.close();
@@ -125,6 +139,7 @@ pub fn spawn_io_tasks(
                         match res {
                             Ok(frame) => {
                                 trace!("Sending outbound frame");
+                                message_counters.count_outbound(&frame);
                                 if let Err(e) = writer.write_frame(frame.into()).await {
                                     error!(error=?e, "Writer error");
                                     outbound_rx.close();
@@ -1,16 +1,22 @@
-use std::{sync::Arc, thread::JoinHandle};
+use std::{future::Future, sync::Arc, thread::JoinHandle};
 
 use async_channel::unbounded;
 
 use bitcoin_core_sv2::CancellationToken;
 use stratum_apps::{
-    stratum_core::bitcoin::consensus::Encodable, task_manager::TaskManager,
-    tp_type::TemplateProviderType, SHUTDOWN_BROADCAST_CAPACITY,
+    monitoring::{CoinbaseOutputInfo, CoinbaseOutputsInfo, TemplateFees},
+    ready_signal::ReadySignal,
+    stratum_core::bitcoin::consensus::Encodable,
+    task_manager::TaskManager,
+    tp_type::TemplateProviderType,
+    utils::status_channel::next_status,
+    SHUTDOWN_BROADCAST_CAPACITY,
 };
 use tokio::sync::broadcast;
 use tracing::{debug, error, info, warn};
 
 use crate::{
+    authentication::{AcceptAll, DownstreamAuthenticator},
     channel_manager::ChannelManager,
     config::PoolConfig,
     error::PoolErrorKind,
@@ -22,12 +28,14 @@ use crate::{
     utils::ShutdownMessage,
 };
 
+pub mod authentication;
 pub mod channel_manager;
 pub mod config;
 pub mod downstream;
 pub mod error;
 mod io_task;
 mod monitoring;
+pub mod share_export;
 pub mod status;
 pub mod template_receiver;
 pub mod utils;
@@ -36,6 +44,8 @@ pub mod utils;
 pub struct PoolSv2 {
     config: PoolConfig,
     notify_shutdown: broadcast::Sender<ShutdownMessage>,
+    ready: ReadySignal,
+    downstream_authenticator: Arc<dyn DownstreamAuthenticator>,
 }
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -46,11 +56,31 @@ impl PoolSv2 {
         Self {
             config,
             notify_shutdown,
+            ready: ReadySignal::new(),
+            downstream_authenticator: Arc::new(AcceptAll),
         }
     }
 
+    /// Sets the authenticator deciding which downstreams may use the pool after their
+    /// `SetupConnection`. Every downstream is accepted by default.
+    pub fn with_downstream_authenticator(
+        mut self,
+        downstream_authenticator: Arc<dyn DownstreamAuthenticator>,
+    ) -> Self {
+        self.downstream_authenticator = downstream_authenticator;
+        self
+    }
+
+    /// Returns a future resolving once the pool is serving, with its downstream listener bound
+    /// after the first template was received.
+    pub fn ready(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.ready.wait()
+    }
+
     /// Starts the Pool main loop.
     pub async fn start(&self) -> Result<(), PoolErrorKind> {
+        self.config.validate()?;
+
         let coinbase_outputs = vec![self.config.get_txout()];
         let mut encoded_outputs = vec![];
 
@@ -58,9 +88,24 @@ impl PoolSv2 {
             .consensus_encode(&mut encoded_outputs)
             .expect("Invalid coinbase output in config");
 
+        // reported to let operators check the payout addresses before any block is found
+        let coinbase_outputs_info = self.config.report_coinbase_outputs().then(|| {
+            let network = self.config.template_provider_type().network();
+            let address = self.config.coinbase_reward_script().address(network);
+            CoinbaseOutputsInfo {
+                outputs: coinbase_outputs
+                    .iter()
+                    .map(|output| CoinbaseOutputInfo::new(output, address.clone()))
+                    .collect(),
+            }
+        });
+        for output in coinbase_outputs_info.iter().flat_map(|info| &info.outputs) {
+            info!("Coinbase output: {output}");
+        }
+
         let notify_shutdown = self.notify_shutdown.clone();
 
-        let task_manager = Arc::new(TaskManager::new());
+        let task_manager = Arc::new(TaskManager::new().with_max_tasks(self.config.max_tasks()));
 
         let (status_sender, status_receiver) = unbounded();
 
@@ -81,9 +126,20 @@ impl PoolSv2 {
             channel_manager_to_downstream_sender.clone(),
             downstream_to_channel_manager_receiver,
             encoded_outputs.clone(),
+            self.downstream_authenticator.clone(),
         )
         .await?;
 
+        // fees of the latest template, when templates come from Bitcoin Core IPC
+        let template_fees = match self.config.template_provider_type() {
+            TemplateProviderType::BitcoinCoreIpc {
+                network,
+                fee_threshold,
+                ..
+            } => Some(Arc::new(TemplateFees::new(network, *fee_threshold))),
+            TemplateProviderType::Sv2Tp { .. } => None,
+        };
+
         // Start monitoring server if configured
         if let Some(monitoring_addr) = self.config.monitoring_address() {
             info!(
@@ -91,13 +147,32 @@ impl PoolSv2 {
                 monitoring_addr
             );
 
-            let monitoring_server = stratum_apps::monitoring::MonitoringServer::new(
+            let mut monitoring_server = stratum_apps::monitoring::MonitoringServer::new(
                 monitoring_addr,
                 None, // Pool doesn't have channels opened with servers
                 Some(Arc::new(channel_manager.clone())), // channels opened with clients
                 std::time::Duration::from_secs(self.config.monitoring_cache_refresh_secs()),
             )
-            .expect("Failed to initialize monitoring server");
+            .expect("Failed to initialize monitoring server")
+            .with_connections_monitoring(channel_manager.connection_limiter.clone())
+            .expect("Failed to add connections monitoring")
+            .with_tasks_monitoring(task_manager.clone())
+            .expect("Failed to add tasks monitoring")
+            .with_messages_monitoring(channel_manager.message_counters.clone())
+            .expect("Failed to add messages monitoring")
+            .with_found_blocks_monitoring(channel_manager.found_blocks.clone())
+            .with_bind_retry(self.config.bind_retry());
+            if let Some(max_staleness) = self.config.monitoring_on_demand_refresh() {
+                monitoring_server = monitoring_server.with_on_demand_refresh(max_staleness);
+            }
+            if let Some(template_fees) = &template_fees {
+                monitoring_server =
+                    monitoring_server.with_template_fees_monitoring(template_fees.clone());
+            }
+            if let Some(coinbase_outputs_info) = coinbase_outputs_info {
+                monitoring_server = monitoring_server
+                    .with_coinbase_outputs_monitoring(Arc::new(coinbase_outputs_info));
+            }
 
             // Create shutdown signal that waits for ShutdownAll
             let mut notify_shutdown_monitoring = notify_shutdown.subscribe();
@@ -134,8 +209,15 @@ impl PoolSv2 {
                     notify_shutdown.clone(),
                     task_manager.clone(),
                     status_sender.clone(),
+                    self.config.max_frame_size(),
+                    channel_manager.message_counters.clone(),
+                )
+                .await?
+                .with_reconnect(
+                    self.config.template_provider_reconnect_attempts(),
+                    self.config.template_provider_reconnect_backoff(),
                 )
-                .await?;
+                .with_connection_flag(channel_manager.template_provider_connected.clone());
 
                 sv2_tp
                     .start(
@@ -176,6 +258,9 @@ impl PoolSv2 {
                     incoming_tdp_receiver,
                     outgoing_tdp_sender,
                     cancellation_token: CancellationToken::new(),
+                    template_fees: template_fees
+                        .clone()
+                        .expect("template fees are recorded with Bitcoin Core IPC"),
                 };
 
                 bitcoin_core_sv2_join_handle = Some(
@@ -212,6 +297,7 @@ impl PoolSv2 {
                 channel_manager_to_downstream_sender,
             )
             .await?;
+        self.ready.set_ready();
 
         info!("Spawning status listener task...");
         loop {
@@ -221,8 +307,8 @@ impl PoolSv2 {
                     let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
                     break;
                 }
-                message = status_receiver.recv() => {
-                    if let Ok(status) = message {
+                message = next_status(&status_receiver) => {
+                    if let Some(status) = message {
                         match status.state {
                             State::DownstreamShutdown{downstream_id,..} => {
                                 warn!("Downstream {downstream_id:?} disconnected — Channel manager.");
@@ -239,6 +325,10 @@ impl PoolSv2 {
                                 break;
                             }
                         }
+                    } else {
+                        warn!("Status channel closed — initiating graceful shutdown...");
+                        let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
+                        break;
                     }
                 }
             }
@@ -4,7 +4,8 @@
 //! Pool only has clients (miners connecting to it), no upstream server.
 
 use stratum_apps::monitoring::client::{
-    ClientInfo, ClientsMonitoring, ExtendedChannelInfo, StandardChannelInfo,
+    ClientInfo, ClientsMonitoring, ExtendedChannelInfo, ShareSequenceViolations,
+    StandardChannelInfo,
 };
 
 use crate::{channel_manager::ChannelManager, downstream::Downstream};
@@ -17,6 +18,12 @@ fn downstream_to_client_info(client: &Downstream) -> Option<ClientInfo> {
         .safe_lock(|dd| {
             let mut extended_channels = Vec::new();
             let mut standard_channels = Vec::new();
+            let group_channel_id = |channel_id| {
+                dd.group_channel
+                    .get_channel_ids()
+                    .contains(&channel_id)
+                    .then(|| dd.group_channel.get_group_channel_id())
+            };
 
             for (_channel_id, extended_channel) in dd.extended_channels.iter() {
                 let channel_id = extended_channel.get_channel_id();
@@ -27,11 +34,15 @@ fn downstream_to_client_info(client: &Downstream) -> Option<ClientInfo> {
 
                 extended_channels.push(ExtendedChannelInfo {
                     channel_id,
+                    group_channel_id: group_channel_id(channel_id),
                     user_identity: user_identity.clone(),
                     nominal_hashrate: extended_channel.get_nominal_hashrate(),
                     target_hex: hex::encode(target.to_be_bytes()),
                     requested_max_target_hex: hex::encode(requested_max_target.to_be_bytes()),
                     extranonce_prefix_hex: hex::encode(extended_channel.get_extranonce_prefix()),
+                    current_job_id: extended_channel
+                        .get_active_job()
+                        .map(|job| job.get_job_id()),
                     full_extranonce_size: extended_channel.get_full_extranonce_size(),
                     rollable_extranonce_size: extended_channel.get_rollable_extranonce_size(),
                     expected_shares_per_minute: extended_channel.get_shares_per_minute(),
@@ -54,11 +65,15 @@ fn downstream_to_client_info(client: &Downstream) -> Option<ClientInfo> {
 
                 standard_channels.push(StandardChannelInfo {
                     channel_id,
+                    group_channel_id: group_channel_id(channel_id),
                     user_identity: user_identity.clone(),
                     nominal_hashrate: standard_channel.get_nominal_hashrate(),
                     target_hex: hex::encode(target.to_be_bytes()),
                     requested_max_target_hex: hex::encode(requested_max_target.to_be_bytes()),
                     extranonce_prefix_hex: hex::encode(standard_channel.get_extranonce_prefix()),
+                    current_job_id: standard_channel
+                        .get_active_job()
+                        .map(|job| job.get_job_id()),
                     expected_shares_per_minute: standard_channel.get_shares_per_minute(),
                     shares_accepted: share_accounting.get_shares_accepted(),
                     share_work_sum: share_accounting.get_share_work_sum(),
@@ -74,6 +89,9 @@ fn downstream_to_client_info(client: &Downstream) -> Option<ClientInfo> {
                 client_id: client.downstream_id,
                 extended_channels,
                 standard_channels,
+                send_queue_depth: client.send_queue_depth(),
+                slow_consumer: client.slow_consumer.is_slow_consumer(),
+                lagged_total: 0,
             }
         })
         .ok()
@@ -103,4 +121,10 @@ impl ClientsMonitoring for ChannelManager {
             })
             .unwrap_or(None)
     }
+
+    fn get_share_sequence_violations(&self) -> ShareSequenceViolations {
+        self.channel_manager_data
+            .safe_lock(|d| d.share_sequences.violations())
+            .unwrap_or_default()
+    }
 }
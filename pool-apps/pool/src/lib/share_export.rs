@@ -0,0 +1,192 @@
+//! Periodic export of the shares of each worker, for external payout systems.
+//!
+//! The channel manager records every share answered on a channel in a [`ShareLedger`], keyed by
+//! the user identity of the channel. Every export interval, the shares recorded since the last
+//! dump are taken out of the ledger and written to the configured file, as CSV or JSON. The file
+//! is written next to its destination first and renamed over it, so a payout system never reads
+//! a partial dump.
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Format of the share accounting dumps.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShareExportFormat {
+    /// One row per worker, after a header row.
+    #[default]
+    Csv,
+    /// A single object holding the dump period and an array of workers.
+    Json,
+}
+
+/// Where and how often the share accounting is dumped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShareExport {
+    /// File the dumps are written to, replaced by every dump.
+    pub path: PathBuf,
+    /// Format of the dumps.
+    pub format: ShareExportFormat,
+    /// Time between two dumps.
+    pub interval: Duration,
+}
+
+/// Shares of a worker since the last dump.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorkerShares {
+    /// Number of shares accepted.
+    pub accepted: u64,
+    /// Number of shares answered with a `SubmitSharesError`.
+    pub rejected: u64,
+    /// Sum of the difficulty of the channel target of each accepted share.
+    pub work_sum: f64,
+}
+
+/// Shares of every worker since the last dump, keyed by user identity.
+#[derive(Debug, Default)]
+pub struct ShareLedger {
+    workers: BTreeMap<String, WorkerShares>,
+}
+
+impl ShareLedger {
+    /// Records a share of `user_identity` accepted on a channel whose target difficulty is
+    /// `work`.
+    pub fn record_accepted(&mut self, user_identity: &str, work: f64) {
+        let worker = self.worker(user_identity);
+        worker.accepted += 1;
+        worker.work_sum += work;
+    }
+
+    /// Records a share of `user_identity` answered with a `SubmitSharesError`.
+    pub fn record_rejected(&mut self, user_identity: &str) {
+        self.worker(user_identity).rejected += 1;
+    }
+
+    /// Returns the shares recorded since the last call, leaving the ledger empty.
+    pub fn take(&mut self) -> BTreeMap<String, WorkerShares> {
+        std::mem::take(&mut self.workers)
+    }
+
+    /// Puts back the shares of a [`Self::take`] that could not be dumped, adding them to the
+    /// shares recorded since.
+    pub fn restore(&mut self, workers: BTreeMap<String, WorkerShares>) {
+        for (user_identity, shares) in workers {
+            let worker = self.worker(&user_identity);
+            worker.accepted += shares.accepted;
+            worker.rejected += shares.rejected;
+            worker.work_sum += shares.work_sum;
+        }
+    }
+
+    fn worker(&mut self, user_identity: &str) -> &mut WorkerShares {
+        if !self.workers.contains_key(user_identity) {
+            self.workers
+                .insert(user_identity.to_string(), WorkerShares::default());
+        }
+        self.workers
+            .get_mut(user_identity)
+            .expect("worker was just inserted")
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Dump<'a> {
+    period_start: u64,
+    period_end: u64,
+    workers: Vec<WorkerRow<'a>>,
+}
+
+#[derive(serde::Serialize)]
+struct WorkerRow<'a> {
+    user_identity: &'a str,
+    shares_accepted: u64,
+    shares_rejected: u64,
+    share_work_sum: f64,
+    estimated_hashrate: f64,
+}
+
+/// Writes the shares of `workers`, recorded between `period_start` and `period_end`, to
+/// `export.path`.
+///
+/// The hashrate of a worker is estimated from the work of its accepted shares over the period.
+pub fn write_dump(
+    export: &ShareExport,
+    period_start: SystemTime,
+    period_end: SystemTime,
+    workers: &BTreeMap<String, WorkerShares>,
+) -> io::Result<()> {
+    let period_secs = period_end
+        .duration_since(period_start)
+        .unwrap_or_default()
+        .as_secs_f64();
+    let dump = Dump {
+        period_start: unix_secs(period_start),
+        period_end: unix_secs(period_end),
+        workers: workers
+            .iter()
+            .map(|(user_identity, shares)| WorkerRow {
+                user_identity,
+                shares_accepted: shares.accepted,
+                shares_rejected: shares.rejected,
+                share_work_sum: shares.work_sum,
+                // a share of difficulty 1 takes 2^32 hashes on average
+                estimated_hashrate: if period_secs > 0.0 {
+                    shares.work_sum * 2f64.powi(32) / period_secs
+                } else {
+                    0.0
+                },
+            })
+            .collect(),
+    };
+    let contents = match export.format {
+        ShareExportFormat::Csv => to_csv(&dump),
+        ShareExportFormat::Json => serde_json::to_string_pretty(&dump)?,
+    };
+    write_atomically(&export.path, contents.as_bytes())
+}
+
+fn to_csv(dump: &Dump) -> String {
+    let mut csv = String::from(
+        "user_identity,period_start,period_end,shares_accepted,shares_rejected,share_work_sum,\
+         estimated_hashrate\n",
+    );
+    for worker in &dump.workers {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            csv_field(worker.user_identity),
+            dump.period_start,
+            dump.period_end,
+            worker.shares_accepted,
+            worker.shares_rejected,
+            worker.share_work_sum,
+            worker.estimated_hashrate
+        ));
+    }
+    csv
+}
+
+// User identities are set by the miners, so they are quoted whenever they could break the row.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// Writes to a temporary file first, so the destination always holds a complete dump.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp_file = path.as_os_str().to_owned();
+    tmp_file.push(".tmp");
+    fs::write(&tmp_file, contents)?;
+    fs::rename(&tmp_file, path)
+}
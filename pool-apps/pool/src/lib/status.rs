@@ -94,60 +94,135 @@ pub struct Status {
     pub state: State,
 }
 
-#[cfg_attr(not(test), hotpath::measure)]
-async fn send_status<O>(sender: &StatusSender, error: PoolError<O>) -> bool {
-    use Action::*;
+/// Maps an error reported by `sender` to the [`State`] sent to the main loop, if any, and to
+/// whether the task of `sender` stops.
+///
+/// | Action       | Reported by        | State                      | Main loop   | Stops |
+/// |--------------|--------------------|----------------------------|-------------|-------|
+/// | `Log`        | any                | none                       | -           | no    |
+/// | `Disconnect` | `Downstream`       | `DownstreamShutdown`       | disconnects | yes   |
+/// | `Disconnect` | others             | `DownstreamShutdown`       | disconnects | no    |
+/// | `Shutdown`   | `TemplateReceiver` | `TemplateReceiverShutdown` | shuts down  | yes   |
+/// | `Shutdown`   | others             | `ChannelManagerShutdown`   | shuts down  | yes   |
+///
+/// The action is picked where the error is raised, after what failed:
+///
+/// - `Log`: a benign race, such as a message for a downstream already gone
+///   (`DownstreamIdNotFound`).
+/// - `Disconnect`: a single downstream misbehaved or cannot be served (`UnsupportedProtocol`,
+///   `Unauthenticated`, `UpstreamNotReady`, `ClientDoesNotSupportRequiredExtensions`,
+///   `SlowConsumer`, `UnexpectedMessage`, `DownstreamNotFound`, `FutureTemplateNotPresent`,
+///   `LastNewPrevhashNotFound`, or a failed send to it).
+/// - `Shutdown`: the pool itself cannot go on (`CouldNotInitiateSystem`, `JobNotFound` for a
+///   template the channels were given, a template provider refusing the connection or changing its
+///   endpoint, `BitcoinCoreSv2CancellationTokenActivated`, or a closed channel between its own
+///   tasks).
+fn error_to_state(
+    sender: &StatusSender,
+    action: Action,
+    kind: PoolErrorKind,
+) -> (Option<State>, bool) {
+    use StatusSender::*;
 
-    match error.action {
-        Log => {
-            warn!("Log-only error from {:?}: {:?}", sender, error.kind);
-            false
-        }
-
-        Disconnect(downstream_id) => {
+    match action {
+        Action::Log => (None, false),
+        Action::Disconnect(downstream_id) => {
             let state = State::DownstreamShutdown {
                 downstream_id,
-                reason: error.kind,
+                reason: kind,
             };
-
-            if let Err(e) = sender.send(Status { state }).await {
-                tracing::error!(
-                    "Failed to send downstream shutdown status from {:?}: {:?}",
-                    sender,
-                    e
-                );
-                std::process::abort();
-            }
-            matches!(sender, StatusSender::Downstream { .. })
+            let stop = match sender {
+                Downstream { .. } => true,
+                TemplateReceiver(_) | ChannelManager(_) => false,
+            };
+            (Some(state), stop)
         }
-        Shutdown => {
+        Action::Shutdown => {
             let state = match sender {
-                StatusSender::ChannelManager(_) => {
-                    warn!(
-                        "Channel Manager shutdown requested due to error: {:?}",
-                        error.kind
-                    );
-                    State::ChannelManagerShutdown(error.kind)
-                }
-                StatusSender::TemplateReceiver(_) => {
-                    warn!(
-                        "Template Receiver shutdown requested due to error: {:?}",
-                        error.kind
-                    );
-                    State::TemplateReceiverShutdown(error.kind)
-                }
-                _ => State::ChannelManagerShutdown(error.kind),
+                TemplateReceiver(_) => State::TemplateReceiverShutdown(kind),
+                Downstream { .. } | ChannelManager(_) => State::ChannelManagerShutdown(kind),
             };
+            (Some(state), true)
+        }
+    }
+}
 
-            if let Err(e) = sender.send(Status { state }).await {
-                tracing::error!("Failed to send shutdown status from {:?}: {:?}", sender, e);
-                std::process::abort();
-            }
-            true
+#[cfg_attr(not(test), hotpath::measure)]
+async fn send_status<O>(sender: &StatusSender, error: PoolError<O>) -> bool {
+    match error.action {
+        Action::Log => warn!("Log-only error from {:?}: {:?}", sender, error.kind),
+        Action::Shutdown => warn!(
+            "Shutdown requested by {:?} due to error: {:?}",
+            sender, error.kind
+        ),
+        Action::Disconnect(_) => {}
+    }
+
+    let (state, stop) = error_to_state(sender, error.action, error.kind);
+    if let Some(state) = state {
+        if let Err(e) = sender.send(Status { state }).await {
+            tracing::error!("Failed to send status from {:?}: {:?}", sender, e);
+            std::process::abort();
         }
     }
+    stop
 }
 
 pub async fn handle_error<O>(sender: &StatusSender, e: PoolError<O>) -> bool {
     send_status(sender, e).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error;
+
+    #[tokio::test]
+    async fn test_handle_error_reports_state_to_main_loop() {
+        let (tx, rx) = async_channel::unbounded();
+
+        // a downstream asking for another protocol is disconnected, and its task stops
+        let downstream = StatusSender::Downstream {
+            downstream_id: 5,
+            tx: tx.clone(),
+        };
+        let error =
+            PoolError::<error::Downstream>::disconnect(PoolErrorKind::UnsupportedProtocol, 5);
+        assert!(handle_error(&downstream, error).await);
+        assert!(matches!(
+            rx.try_recv().unwrap().state,
+            State::DownstreamShutdown {
+                downstream_id: 5,
+                reason: PoolErrorKind::UnsupportedProtocol,
+            }
+        ));
+
+        // a downstream the channel manager fails to serve is disconnected, the channel manager
+        // keeps running
+        let channel_manager = StatusSender::ChannelManager(tx.clone());
+        let error =
+            PoolError::<error::ChannelManager>::disconnect(PoolErrorKind::DownstreamNotFound(5), 5);
+        assert!(!handle_error(&channel_manager, error).await);
+        assert!(matches!(
+            rx.try_recv().unwrap().state,
+            State::DownstreamShutdown {
+                downstream_id: 5,
+                reason: PoolErrorKind::DownstreamNotFound(5),
+            }
+        ));
+
+        // log-only errors do not reach the main loop
+        let error = PoolError::<error::ChannelManager>::log(PoolErrorKind::DownstreamIdNotFound);
+        assert!(!handle_error(&channel_manager, error).await);
+        assert!(rx.is_empty());
+
+        // a template provider changing its endpoint shuts the pool down
+        let template_receiver = StatusSender::TemplateReceiver(tx);
+        let error = PoolError::<error::TemplateProvider>::shutdown(PoolErrorKind::ChangeEndpoint);
+        assert!(handle_error(&template_receiver, error).await);
+        assert!(matches!(
+            rx.try_recv().unwrap().state,
+            State::TemplateReceiverShutdown(PoolErrorKind::ChangeEndpoint)
+        ));
+    }
+}
@@ -6,7 +6,10 @@ use crate::{
 use async_channel::{Receiver, Sender};
 use bitcoin_core_sv2::{BitcoinCoreSv2, CancellationToken};
 use std::{path::PathBuf, sync::Arc, thread::JoinHandle};
-use stratum_apps::{stratum_core::parsers_sv2::TemplateDistribution, task_manager::TaskManager};
+use stratum_apps::{
+    monitoring::TemplateFees, stratum_core::parsers_sv2::TemplateDistribution,
+    task_manager::TaskManager,
+};
 use tokio::sync::broadcast;
 
 #[derive(Clone)]
@@ -17,11 +20,13 @@ pub struct BitcoinCoreSv2Config {
     pub incoming_tdp_receiver: Receiver<TemplateDistribution<'static>>,
     pub outgoing_tdp_sender: Sender<TemplateDistribution<'static>>,
     pub cancellation_token: CancellationToken,
+    /// Fees of the latest template, updated as templates are sent to the channel manager.
+    pub template_fees: Arc<TemplateFees>,
 }
 
 #[cfg_attr(not(test), hotpath::measure)]
 pub async fn connect_to_bitcoin_core(
-    bitcoin_core_config: BitcoinCoreSv2Config,
+    mut bitcoin_core_config: BitcoinCoreSv2Config,
     notify_shutdown: broadcast::Sender<ShutdownMessage>,
     task_manager: Arc<TaskManager>,
     status_sender: Sender<Status>,
@@ -55,6 +60,28 @@ pub async fn connect_to_bitcoin_core(
         }
     });
 
+    // record the fees of every template on its way to the channel manager
+    let (template_sender, template_receiver) = async_channel::unbounded();
+    let outgoing_tdp_sender = std::mem::replace(
+        &mut bitcoin_core_config.outgoing_tdp_sender,
+        template_sender,
+    );
+    let template_fees = bitcoin_core_config.template_fees.clone();
+    task_manager.spawn(async move {
+        while let Ok(message) = template_receiver.recv().await {
+            if let TemplateDistribution::NewTemplate(template) = &message {
+                template_fees.record(
+                    template.template_id,
+                    template.coinbase_prefix.inner_as_ref(),
+                    template.coinbase_tx_value_remaining,
+                );
+            }
+            if outgoing_tdp_sender.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
     let status_sender_clone = status_sender.clone();
 
     // spawn a dedicated thread to run the BitcoinCoreSv2 instance
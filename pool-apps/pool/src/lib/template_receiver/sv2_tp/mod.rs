@@ -1,9 +1,18 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 mod common_message_handler;
 use async_channel::{unbounded, Receiver, Sender};
 use stratum_apps::{
+    custom_mutex::Mutex,
     key_utils::Secp256k1PublicKey,
-    network_helpers::noise_stream::NoiseTcpStream,
+    network_helpers::{message_counters::MessageCounters, noise_stream::NoiseTcpStream},
     stratum_core::{
         codec_sv2::HandshakeRole,
         framing_sv2,
@@ -33,11 +42,93 @@ pub struct Sv2TpChannel {
     channel_manager_receiver: Receiver<TemplateDistribution<'static>>,
     tp_sender: Sender<Sv2Frame>,
     tp_receiver: Receiver<Sv2Frame>,
+    // Last `CoinbaseOutputConstraints` of the channel manager, replayed after a reconnection
+    coinbase_output_constraints: Arc<Mutex<Option<TemplateDistribution<'static>>>>,
+}
+
+// Where and how to connect to the template provider, kept to reconnect to it.
+#[derive(Clone)]
+struct Sv2TpEndpoint {
+    address: String,
+    public_key: Option<Secp256k1PublicKey>,
+    max_frame_size: usize,
+    message_counters: Arc<MessageCounters>,
+}
+
+impl Sv2TpEndpoint {
+    // Opens the TCP connection, performs the Noise handshake and spawns the IO tasks, returning
+    // the channels receiving frames from and sending frames to the template provider. Returns
+    // `None` if the template provider could not be reached.
+    async fn connect(
+        &self,
+        attempt: usize,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+        task_manager: Arc<TaskManager>,
+        status_sender: StatusSender,
+    ) -> PoolResult<Option<(Receiver<Sv2Frame>, Sender<Sv2Frame>)>, error::TemplateProvider> {
+        let initiator = match self.public_key {
+            Some(pub_key) => {
+                debug!(attempt, "Using public key for initiator handshake");
+                Initiator::from_raw_k(pub_key.into_bytes())
+            }
+            None => {
+                debug!(attempt, "Using anonymous initiator (no public key)");
+                Initiator::without_pk()
+            }
+        }
+        .map_err(PoolError::shutdown)?;
+
+        let stream = match TcpStream::connect(self.address.as_str()).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!(attempt, error = ?e, "Failed to connect to template provider");
+                return Ok(None);
+            }
+        };
+        info!(
+            attempt,
+            "TCP connection established, starting Noise handshake"
+        );
+
+        let noise_stream =
+            match NoiseTcpStream::<Message>::new(stream, HandshakeRole::Initiator(initiator)).await
+            {
+                Ok(noise_stream) => noise_stream,
+                Err(e) => {
+                    error!(attempt, error = ?e, "Noise handshake failed");
+                    return Ok(None);
+                }
+            };
+        info!(attempt, "Noise handshake completed successfully");
+
+        let (noise_stream_reader, noise_stream_writer) = noise_stream.into_split();
+        let (inbound_tx, inbound_rx) = unbounded::<Sv2Frame>();
+        let (outbound_tx, outbound_rx) = unbounded::<Sv2Frame>();
+
+        info!(attempt, "Spawning IO tasks for template receiver");
+        spawn_io_tasks(
+            task_manager,
+            noise_stream_reader,
+            noise_stream_writer,
+            outbound_rx,
+            inbound_tx,
+            self.max_frame_size,
+            self.message_counters.clone(),
+            notify_shutdown,
+            status_sender,
+        );
+        Ok(Some((inbound_rx, outbound_tx)))
+    }
 }
 
 #[derive(Clone)]
 pub struct Sv2Tp {
     sv2_tp_channel: Sv2TpChannel,
+    endpoint: Sv2TpEndpoint,
+    reconnect_attempts: u32,
+    reconnect_backoff: Duration,
+    // Cleared while the connection to the template provider is being re-established
+    connected: Arc<AtomicBool>,
 }
 
 #[cfg_attr(not(test), hotpath::measure_all)]
@@ -49,6 +140,7 @@ impl Sv2Tp {
     /// - Spawns IO tasks for inbound/outbound frames
     ///
     /// Retries up to 3 times before returning [`PoolError::Shutdown`].
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         tp_address: String,
         public_key: Option<Secp256k1PublicKey>,
@@ -57,78 +149,46 @@ impl Sv2Tp {
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
         task_manager: Arc<TaskManager>,
         status_sender: Sender<Status>,
+        max_frame_size: usize,
+        message_counters: Arc<MessageCounters>,
     ) -> PoolResult<Sv2Tp, error::TemplateProvider> {
         const MAX_RETRIES: usize = 3;
 
+        let endpoint = Sv2TpEndpoint {
+            address: tp_address,
+            public_key,
+            max_frame_size,
+            message_counters,
+        };
+        let status_sender = StatusSender::TemplateReceiver(status_sender);
         for attempt in 1..=MAX_RETRIES {
             info!(attempt, MAX_RETRIES, "Connecting to template provider");
 
-            let initiator = match public_key {
-                Some(pub_key) => {
-                    debug!(attempt, "Using public key for initiator handshake");
-                    Initiator::from_raw_k(pub_key.into_bytes())
-                }
-                None => {
-                    debug!(attempt, "Using anonymous initiator (no public key)");
-                    Initiator::without_pk()
-                }
-            }
-            .map_err(PoolError::shutdown)?;
+            if let Some((tp_receiver, tp_sender)) = endpoint
+                .connect(
+                    attempt,
+                    notify_shutdown.clone(),
+                    task_manager.clone(),
+                    status_sender.clone(),
+                )
+                .await?
+            {
+                let template_receiver_channel = Sv2TpChannel {
+                    channel_manager_receiver,
+                    channel_manager_sender,
+                    tp_receiver,
+                    tp_sender,
+                    coinbase_output_constraints: Arc::new(Mutex::new(None)),
+                };
 
-            match TcpStream::connect(tp_address.as_str()).await {
-                Ok(stream) => {
-                    info!(
-                        attempt,
-                        "TCP connection established, starting Noise handshake"
-                    );
-
-                    match NoiseTcpStream::<Message>::new(
-                        stream,
-                        HandshakeRole::Initiator(initiator),
-                    )
-                    .await
-                    {
-                        Ok(noise_stream) => {
-                            info!(attempt, "Noise handshake completed successfully");
-
-                            let (noise_stream_reader, noise_stream_writer) =
-                                noise_stream.into_split();
-
-                            let status_sender = StatusSender::TemplateReceiver(status_sender);
-                            let (inbound_tx, inbound_rx) = unbounded::<Sv2Frame>();
-                            let (outbound_tx, outbound_rx) = unbounded::<Sv2Frame>();
-
-                            info!(attempt, "Spawning IO tasks for template receiver");
-                            spawn_io_tasks(
-                                task_manager.clone(),
-                                noise_stream_reader,
-                                noise_stream_writer,
-                                outbound_rx,
-                                inbound_tx,
-                                notify_shutdown,
-                                status_sender,
-                            );
-
-                            let template_receiver_channel = Sv2TpChannel {
-                                channel_manager_receiver,
-                                channel_manager_sender,
-                                tp_receiver: inbound_rx,
-                                tp_sender: outbound_tx,
-                            };
-
-                            info!(attempt, "TemplateReceiver initialized successfully");
-                            return Ok(Sv2Tp {
-                                sv2_tp_channel: template_receiver_channel,
-                            });
-                        }
-                        Err(e) => {
-                            error!(attempt, error = ?e, "Noise handshake failed");
-                        }
-                    }
-                }
-                Err(e) => {
-                    warn!(attempt, MAX_RETRIES, error = ?e, "Failed to connect to template provider");
-                }
+                info!(attempt, "TemplateReceiver initialized successfully");
+                return Ok(Sv2Tp {
+                    sv2_tp_channel: template_receiver_channel,
+                    endpoint,
+                    reconnect_attempts: 0,
+                    reconnect_backoff: Duration::ZERO,
+                    connected: Arc::new(AtomicBool::new(true)),
+                });
             }
 
             if attempt < MAX_RETRIES {
@@ -141,6 +201,23 @@ impl Sv2Tp {
         Err(PoolError::shutdown(PoolErrorKind::CouldNotInitiateSystem))
     }
 
+    /// Reconnects up to `attempts` times when the connection to the template provider drops,
+    /// waiting `backoff` before the first attempt and twice as long before every next one. The
+    /// last known job keeps being served to downstreams meanwhile. `0` shuts the pool down as
+    /// soon as the connection drops.
+    pub fn with_reconnect(mut self, attempts: u32, backoff: Duration) -> Self {
+        self.reconnect_attempts = attempts;
+        self.reconnect_backoff = backoff;
+        self
+    }
+
+    /// Shares the flag cleared while the connection to the template provider is being
+    /// re-established, and set again once it is.
+    pub fn with_connection_flag(mut self, connected: Arc<AtomicBool>) -> Self {
+        self.connected = connected;
+        self
+    }
+
     /// Start unified message loop for Sv2Tp.
     ///
     /// Responsibilities:
@@ -163,6 +240,7 @@ impl Sv2Tp {
         self.setup_connection(socket_address).await?;
 
         info!("Setup Connection done. connection with template receiver is now done");
+        let reconnect_task_manager = task_manager.clone();
         task_manager.spawn(
             async move {
                 loop {
@@ -185,16 +263,28 @@ impl Sv2Tp {
                         res = self_clone_1.handle_template_provider_message() => {
                             if let Err(e) = res {
                                 error!("TemplateReceiver template provider handler failed: {e:?}");
-                                if handle_error(&status_sender, e).await {
-                                    break;
+                                match self.reconnect_if_dropped(e, &mut shutdown_rx, &notify_shutdown, &status_sender, &reconnect_task_manager).await {
+                                    ControlFlow::Continue(()) => {}
+                                    ControlFlow::Break(None) => break,
+                                    ControlFlow::Break(Some(e)) => {
+                                        if handle_error(&status_sender, e).await {
+                                            break;
+                                        }
+                                    }
                                 }
                             }
                         }
                         res = self_clone_2.handle_channel_manager_message() => {
                             if let Err(e) = res {
                                 error!("TemplateReceiver channel manager handler failed: {e:?}");
-                                if handle_error(&status_sender, e).await {
-                                    break;
+                                match self.reconnect_if_dropped(e, &mut shutdown_rx, &notify_shutdown, &status_sender, &reconnect_task_manager).await {
+                                    ControlFlow::Continue(()) => {}
+                                    ControlFlow::Break(None) => break,
+                                    ControlFlow::Break(Some(e)) => {
+                                        if handle_error(&status_sender, e).await {
+                                            break;
+                                        }
+                                    }
                                 }
                             }
                         },
@@ -274,6 +364,11 @@ impl Sv2Tp {
             .recv()
             .await
             .map_err(PoolError::shutdown)?;
+        if matches!(msg, TemplateDistribution::CoinbaseOutputConstraints(_)) {
+            self.sv2_tp_channel
+                .coinbase_output_constraints
+                .super_safe_lock(|constraints| *constraints = Some(msg.clone()));
+        }
         let message = AnyMessage::TemplateDistribution(msg).into_static();
         let frame: Sv2Frame = message.try_into().map_err(PoolError::shutdown)?;
 
@@ -287,6 +382,97 @@ impl Sv2Tp {
         Ok(())
     }
 
+    // Reconnects to the template provider if `e` comes from the connection to it being
+    // dropped and reconnecting is enabled. Breaks with the error to escalate if any, or with
+    // `None` if a shutdown was requested while reconnecting.
+    async fn reconnect_if_dropped(
+        &mut self,
+        e: PoolError<error::TemplateProvider>,
+        shutdown_rx: &mut broadcast::Receiver<ShutdownMessage>,
+        notify_shutdown: &broadcast::Sender<ShutdownMessage>,
+        status_sender: &StatusSender,
+        task_manager: &Arc<TaskManager>,
+    ) -> ControlFlow<Option<PoolError<error::TemplateProvider>>> {
+        if self.reconnect_attempts == 0 || !self.sv2_tp_channel.tp_receiver.is_closed() {
+            return ControlFlow::Break(Some(e));
+        }
+        match self
+            .reconnect(shutdown_rx, notify_shutdown, status_sender, task_manager)
+            .await
+        {
+            Ok(flow) => flow.map_break(|()| None),
+            Err(e) => ControlFlow::Break(Some(e)),
+        }
+    }
+
+    // Opens a new connection to the template provider, with a backoff doubling after every
+    // failed attempt, and replays the `CoinbaseOutputConstraints` of the channel manager so the
+    // template provider sends templates again. Breaks without reconnecting if a shutdown is
+    // requested during the backoff or while connecting.
+    async fn reconnect(
+        &mut self,
+        shutdown_rx: &mut broadcast::Receiver<ShutdownMessage>,
+        notify_shutdown: &broadcast::Sender<ShutdownMessage>,
+        status_sender: &StatusSender,
+        task_manager: &Arc<TaskManager>,
+    ) -> PoolResult<ControlFlow<()>, error::TemplateProvider> {
+        self.connected.store(false, Ordering::SeqCst);
+        let mut backoff = self.reconnect_backoff;
+        for attempt in 1..=self.reconnect_attempts as usize {
+            warn!(
+                attempt,
+                max_attempts = self.reconnect_attempts,
+                ?backoff,
+                "Connection to the template provider lost, reconnecting"
+            );
+            tokio::select! {
+                _ = shutdown_requested(shutdown_rx) => return Ok(ControlFlow::Break(())),
+                _ = tokio::time::sleep(backoff) => {}
+            }
+            backoff = backoff.saturating_mul(2);
+
+            let connection = tokio::select! {
+                _ = shutdown_requested(shutdown_rx) => return Ok(ControlFlow::Break(())),
+                connection = self.endpoint.connect(
+                    attempt,
+                    notify_shutdown.clone(),
+                    task_manager.clone(),
+                    status_sender.clone(),
+                ) => connection?,
+            };
+            let Some((tp_receiver, tp_sender)) = connection else {
+                continue;
+            };
+            self.sv2_tp_channel.tp_receiver = tp_receiver;
+            self.sv2_tp_channel.tp_sender = tp_sender;
+            if let Err(e) = self.setup_connection(self.endpoint.address.clone()).await {
+                warn!(attempt, error = ?e, "Setup connection with the template provider failed");
+                continue;
+            }
+
+            let constraints = self
+                .sv2_tp_channel
+                .coinbase_output_constraints
+                .super_safe_lock(|constraints| constraints.clone());
+            if let Some(constraints) = constraints {
+                let frame: Sv2Frame = AnyMessage::TemplateDistribution(constraints)
+                    .try_into()
+                    .map_err(PoolError::shutdown)?;
+                self.sv2_tp_channel
+                    .tp_sender
+                    .send(frame)
+                    .await
+                    .map_err(|_| PoolError::shutdown(PoolErrorKind::ChannelErrorSender))?;
+            }
+            info!(attempt, "Reconnected to the template provider");
+            self.connected.store(true, Ordering::SeqCst);
+            return Ok(ControlFlow::Continue(()));
+        }
+
+        error!("Could not reconnect to the template provider, shutting down TemplateReceiver");
+        Err(PoolError::shutdown(PoolErrorKind::CouldNotInitiateSystem))
+    }
+
     // Performs the initial handshake with Template Provider.
     pub async fn setup_connection(
         &mut self,
@@ -335,3 +521,13 @@ impl Sv2Tp {
         Ok(())
     }
 }
+
+// Resolves once a full shutdown is requested, or the shutdown channel closed.
+async fn shutdown_requested(shutdown_rx: &mut broadcast::Receiver<ShutdownMessage>) {
+    loop {
+        match shutdown_rx.recv().await {
+            Ok(ShutdownMessage::ShutdownAll) | Err(_) => return,
+            Ok(_) => {}
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use core::fmt;
 
-use miniscript::bitcoin::{address, hex};
+use miniscript::bitcoin::{address, hex, Network};
 
 /// Error enum
 #[derive(Debug)]
@@ -15,6 +15,12 @@ pub enum Error {
     UnknownOutputScriptType,
     /// Error from the `miniscript` crate.
     Miniscript(miniscript::Error),
+    /// Descriptor has no wildcard to derive output scripts from
+    NotRangedDescriptor,
+    /// Error deriving an output script from a ranged descriptor
+    Derivation(String),
+    /// Address that is not valid on the network mined on
+    WrongNetwork(String, Network),
 }
 
 impl fmt::Display for Error {
@@ -26,6 +32,11 @@ impl fmt::Display for Error {
             UnknownOutputScriptType => write!(f, "Unknown script type in config"),
             InvalidOutputScript => write!(f, "Invalid output_script_value for your script type. It must be a valid public key/script"),
             Miniscript(ref e) => write!(f, "Miniscript: {e}"),
+            NotRangedDescriptor => write!(f, "Descriptor has no wildcard to derive scripts from"),
+            Derivation(ref e) => write!(f, "Deriving script from descriptor: {e}"),
+            WrongNetwork(ref address, network) => {
+                write!(f, "Address {address} is not valid on {network}")
+            }
         }
     }
 }
@@ -3,7 +3,7 @@ mod serde_types;
 
 use miniscript::{
     bitcoin::{address::NetworkUnchecked, Address, Network, ScriptBuf},
-    DefiniteDescriptorKey, Descriptor,
+    DefiniteDescriptorKey, Descriptor, DescriptorPublicKey,
 };
 
 pub use errors::Error;
@@ -16,6 +16,8 @@ pub use errors::Error;
 pub struct CoinbaseRewardScript {
     script_pubkey: ScriptBuf,
     ok_for_mainnet: bool,
+    // The address of an addr() descriptor, kept to check it against the network mined on
+    address: Option<Address<NetworkUnchecked>>,
 }
 
 impl CoinbaseRewardScript {
@@ -31,6 +33,7 @@ impl CoinbaseRewardScript {
                 // Descriptors don't have a way to specify a network, so we assume
                 // they are OK to be used on mainnet.
                 ok_for_mainnet: true,
+                address: None,
             });
         }
 
@@ -45,6 +48,7 @@ impl CoinbaseRewardScript {
                 Ok(Self {
                     script_pubkey: addr.assume_checked_ref().script_pubkey(),
                     ok_for_mainnet: addr.is_valid_for_network(Network::Bitcoin),
+                    address: Some(addr),
                 })
             }
             "raw" => {
@@ -59,6 +63,7 @@ impl CoinbaseRewardScript {
                     script_pubkey: ScriptBuf::from_hex(&script_hex)?,
                     // Users of hex scriptpubkeys are on their own.
                     ok_for_mainnet: true,
+                    address: None,
                 })
             }
             _ => {
@@ -70,6 +75,7 @@ impl CoinbaseRewardScript {
                     // Descriptors don't have a way to specify a network, so we assume
                     // they are OK to be used on mainnet.
                     ok_for_mainnet: true,
+                    address: None,
                 })
             }
         }
@@ -84,10 +90,73 @@ impl CoinbaseRewardScript {
         self.ok_for_mainnet
     }
 
+    /// Checks that this coinbase output can be used on `network`.
+    ///
+    /// Only addr() descriptors carry a network: a mainnet address is rejected on test networks
+    /// and the other way round. Other descriptors and scripts are accepted on any network.
+    pub fn check_network(&self, network: Network) -> Result<(), Error> {
+        match &self.address {
+            Some(address) if !address.is_valid_for_network(network) => Err(Error::WrongNetwork(
+                address.clone().assume_checked().to_string(),
+                network,
+            )),
+            _ => Ok(()),
+        }
+    }
+
     /// The `scriptPubKey` associated with the coinbase output
     pub fn script_pubkey(&self) -> ScriptBuf {
         self.script_pubkey.clone()
     }
+
+    /// The address paid by the coinbase output: the one of an addr() descriptor, or else the
+    /// standard address of its `scriptPubKey` on `network`, when known.
+    pub fn address(&self, network: Option<Network>) -> Option<String> {
+        match (&self.address, network) {
+            (Some(address), _) => Some(address.clone().assume_checked().to_string()),
+            (None, Some(network)) => Address::from_script(&self.script_pubkey, network)
+                .ok()
+                .map(|address| address.to_string()),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Ranged output descriptor, such as `wpkh(xpub.../0/*)`, from which a distinct coinbase
+/// output script can be derived for every block.
+#[derive(Debug, serde::Deserialize, Clone)]
+#[serde(try_from = "String")]
+pub struct CoinbaseRewardDescriptor {
+    descriptor: Descriptor<DescriptorPublicKey>,
+}
+
+impl CoinbaseRewardDescriptor {
+    /// Creates a new [`CoinbaseRewardDescriptor`] from a descriptor string.
+    ///
+    /// The descriptor must contain a wildcard (`*`) step to derive scripts from.
+    pub fn from_descriptor(s: &str) -> Result<Self, Error> {
+        let descriptor = s.parse::<Descriptor<DescriptorPublicKey>>()?;
+        if !descriptor.has_wildcard() {
+            return Err(Error::NotRangedDescriptor);
+        }
+        Ok(Self { descriptor })
+    }
+
+    /// The `scriptPubKey` derived at `index`.
+    pub fn script_pubkey_at(&self, index: u32) -> Result<ScriptBuf, Error> {
+        let descriptor = self
+            .descriptor
+            .at_derivation_index(index)
+            .map_err(|e| Error::Derivation(e.to_string()))?;
+        Ok(descriptor.script_pubkey())
+    }
+}
+
+impl TryFrom<String> for CoinbaseRewardDescriptor {
+    type Error = Error;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::from_descriptor(&value)
+    }
 }
 
 #[cfg(test)]
@@ -214,6 +283,95 @@ mod tests {
         );
     }
 
+    #[test]
+    fn addresses_of_every_type_are_checked_against_the_network() {
+        for (address, script_pubkey) in [
+            // legacy P2PKH and P2SH
+            (
+                "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+                "76a91477bff20c60e522dfaa3350c39b030a5d004e839a88ac",
+            ),
+            (
+                "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+                "a914b472a266d0bd89c13706a4132ccfb16f7c3b9fcb87",
+            ),
+            // bech32 P2WPKH
+            (
+                "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+                "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+            ),
+            // bech32m P2TR
+            (
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+                "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            ),
+        ] {
+            let output =
+                CoinbaseRewardScript::from_descriptor(&format!("addr({address})")).unwrap();
+            assert_eq!(output.script_pubkey().to_hex_string(), script_pubkey);
+            output.check_network(Network::Bitcoin).unwrap();
+            for network in [Network::Testnet, Network::Signet, Network::Regtest] {
+                assert!(matches!(
+                    output.check_network(network),
+                    Err(Error::WrongNetwork(ref a, n)) if a == address && n == network
+                ));
+            }
+        }
+
+        // a testnet address is valid on signet, but not on mainnet
+        let output = CoinbaseRewardScript::from_descriptor(
+            "addr(tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx)",
+        )
+        .unwrap();
+        output.check_network(Network::Testnet).unwrap();
+        output.check_network(Network::Signet).unwrap();
+        assert_eq!(
+            output
+                .check_network(Network::Bitcoin)
+                .unwrap_err()
+                .to_string(),
+            "Address tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx is not valid on bitcoin",
+        );
+
+        // scripts other than addresses carry no network
+        let output = CoinbaseRewardScript::from_descriptor(
+            "wpkh(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+        )
+        .unwrap();
+        output.check_network(Network::Regtest).unwrap();
+    }
+
+    #[test]
+    fn address_comes_from_descriptor_or_network() {
+        let output = CoinbaseRewardScript::from_descriptor(
+            "addr(tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx)",
+        )
+        .unwrap();
+        for network in [None, Some(Network::Signet)] {
+            assert_eq!(
+                output.address(network).unwrap(),
+                "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"
+            );
+        }
+
+        // other scripts only have an address once the network is known
+        let output = CoinbaseRewardScript::from_descriptor(
+            "wpkh(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+        )
+        .unwrap();
+        assert_eq!(output.address(None), None);
+        assert_eq!(
+            output.address(Some(Network::Bitcoin)).unwrap(),
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+        );
+        assert_eq!(
+            output.address(Some(Network::Testnet)).unwrap(),
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"
+        );
+        let output = CoinbaseRewardScript::from_descriptor("raw(6a)").unwrap();
+        assert_eq!(output.address(Some(Network::Bitcoin)), None);
+    }
+
     #[test]
     fn fixed_vector_combo() {
         // We do not support combo descriptors. Nobody should.
@@ -352,4 +510,27 @@ mod tests {
             "Miniscript: public keys must be 64, 66 or 130 characters in size",
         );
     }
+
+    #[test]
+    fn ranged_descriptor_derives_distinct_scripts() {
+        let descriptor = CoinbaseRewardDescriptor::from_descriptor(
+            "wpkh(xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8/0/*)",
+        )
+        .unwrap();
+        let first = descriptor.script_pubkey_at(0).unwrap();
+        let second = descriptor.script_pubkey_at(1).unwrap();
+        assert!(first.is_p2wpkh());
+        assert_ne!(first, second);
+        assert_eq!(first, descriptor.script_pubkey_at(0).unwrap());
+    }
+
+    #[test]
+    fn descriptor_without_wildcard_is_not_ranged() {
+        assert!(matches!(
+            CoinbaseRewardDescriptor::from_descriptor(
+                "wpkh(xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8/0/0)",
+            ),
+            Err(Error::NotRangedDescriptor)
+        ));
+    }
 }
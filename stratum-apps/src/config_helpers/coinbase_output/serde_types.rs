@@ -99,6 +99,7 @@ impl TryFrom<LegacyCoinbaseOutput> for super::CoinbaseRewardScript {
             script_pubkey,
             // legacy encoding gives no way to specify testnet or mainnet
             ok_for_mainnet: true,
+            address: None,
         })
     }
 }
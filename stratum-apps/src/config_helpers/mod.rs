@@ -3,14 +3,24 @@
 //! This module provides utilities for:
 //! - Parsing configuration files (TOML, etc.)
 //! - Handling coinbase output specifications
+//! - Resolving authority secret keys stored outside the configuration file
+//! - Choosing what to do once every upstream failed
 //! - Setting up logging and tracing
 //!
 //! Originally from the `config_helpers_sv2` crate.
 
 mod coinbase_output;
-pub use coinbase_output::{CoinbaseRewardScript, Error as CoinbaseOutputError};
+pub use coinbase_output::{
+    CoinbaseRewardDescriptor, CoinbaseRewardScript, Error as CoinbaseOutputError,
+};
 
 pub mod logging;
 
+mod secret_key;
+pub use secret_key::{authority_secret_key_from_toml, SecretKeyError};
+
 mod toml;
 pub use toml::{duration_from_toml, opt_path_from_toml};
+
+mod upstream_policy;
+pub use upstream_policy::AllUpstreamsFailedPolicy;
@@ -0,0 +1,254 @@
+//! Authority secret keys read from the configuration file, a key file or an environment variable.
+//!
+//! Storing a secret key inline in a TOML file is discouraged, so configurations may instead
+//! reference it with `authority_secret_key_file` or `authority_secret_key_env`. The reference is
+//! resolved while the configuration is loaded, so a missing or unreadable key stops the
+//! application before it starts.
+use serde::{de, Deserialize, Deserializer};
+use std::{fmt, fs, path::PathBuf};
+
+use super::opt_path_from_toml;
+use crate::key_utils::Secp256k1SecretKey;
+
+/// Error resolving the authority secret key of a configuration.
+#[derive(Debug)]
+pub enum SecretKeyError {
+    /// None or several of the secret key options are set.
+    Sources,
+    /// The key file could not be read.
+    File(PathBuf, std::io::Error),
+    /// The key file can be accessed by other users.
+    Permissions(PathBuf, u32),
+    /// The environment variable is not set or not valid unicode.
+    Env(String),
+    /// The key is not a valid base58check encoded secret key.
+    Key(String),
+}
+
+impl fmt::Display for SecretKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretKeyError::Sources => write!(
+                f,
+                "exactly one of authority_secret_key, authority_secret_key_file and authority_secret_key_env must be set"
+            ),
+            SecretKeyError::File(path, e) => {
+                write!(f, "cannot read secret key file {}: {e}", path.display())
+            }
+            SecretKeyError::Permissions(path, mode) => write!(
+                f,
+                "secret key file {} is accessible by other users (mode {mode:o}), restrict it with `chmod o-rwx`",
+                path.display()
+            ),
+            SecretKeyError::Env(var) => {
+                write!(
+                    f,
+                    "secret key environment variable {var} is not set or not valid unicode"
+                )
+            }
+            SecretKeyError::Key(e) => write!(f, "invalid authority secret key: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SecretKeyError {}
+
+#[derive(Deserialize)]
+struct SecretKeySources {
+    #[serde(default)]
+    authority_secret_key: Option<String>,
+    #[serde(default, deserialize_with = "opt_path_from_toml")]
+    authority_secret_key_file: Option<PathBuf>,
+    #[serde(default)]
+    authority_secret_key_env: Option<String>,
+}
+
+impl SecretKeySources {
+    fn resolve(self) -> Result<Secp256k1SecretKey, SecretKeyError> {
+        let encoded = match (
+            self.authority_secret_key,
+            self.authority_secret_key_file,
+            self.authority_secret_key_env,
+        ) {
+            (Some(inline), None, None) => inline,
+            (None, Some(path), None) => read_secret_key_file(path)?,
+            (None, None, Some(var)) => std::env::var(&var).map_err(|_| SecretKeyError::Env(var))?,
+            _ => return Err(SecretKeyError::Sources),
+        };
+        encoded
+            .trim()
+            .parse()
+            .map_err(|e: crate::key_utils::Error| SecretKeyError::Key(e.to_string()))
+    }
+}
+
+fn read_secret_key_file(path: PathBuf) -> Result<String, SecretKeyError> {
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(e) => return Err(SecretKeyError::File(path, e)),
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & 0o007 != 0 {
+            return Err(SecretKeyError::Permissions(path, mode));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = metadata;
+    fs::read_to_string(&path).map_err(|e| SecretKeyError::File(path, e))
+}
+
+/// Deserialize the authority secret key from `authority_secret_key` (inline),
+/// `authority_secret_key_file` (path to a file holding the key, `~` and environment variables
+/// are expanded) or `authority_secret_key_env` (name of an environment variable holding the key).
+///
+/// Use this on the secret key field of a configuration with:
+/// `#[serde(flatten, deserialize_with = "authority_secret_key_from_toml")]`.
+///
+/// On unix, key files accessible by other users are rejected.
+pub fn authority_secret_key_from_toml<'de, D>(
+    deserializer: D,
+) -> Result<Secp256k1SecretKey, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    SecretKeySources::deserialize(deserializer)?
+        .resolve()
+        .map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ext_config::{Config, File, FileFormat};
+    use std::env;
+
+    const SECRET_KEY: &str = "mkDLTBBRxdBv998612qipDYoTK3YUrqLe8uWw7gu3iXbSrn2n";
+
+    #[derive(Debug, Deserialize)]
+    struct TestConfig {
+        #[serde(flatten, deserialize_with = "authority_secret_key_from_toml")]
+        authority_secret_key: Secp256k1SecretKey,
+        cert_validity_sec: u64,
+    }
+
+    fn load(toml: &str) -> Result<TestConfig, ext_config::ConfigError> {
+        Config::builder()
+            .add_source(File::from_str(toml, FileFormat::Toml))
+            .build()
+            .and_then(|settings| settings.try_deserialize::<TestConfig>())
+    }
+
+    fn key_file(name: &str, mode: u32) -> PathBuf {
+        let path = env::temp_dir().join(format!("sv2-{name}-{}", std::process::id()));
+        fs::write(&path, format!("{SECRET_KEY}\n")).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+        path
+    }
+
+    #[test]
+    fn inline_secret_key() {
+        let cfg = load(&format!(
+            r#"
+            authority_secret_key = "{SECRET_KEY}"
+            cert_validity_sec = 3600
+        "#
+        ))
+        .expect("failed to load a valid toml");
+        assert_eq!(cfg.authority_secret_key.to_string(), SECRET_KEY);
+        assert_eq!(cfg.cert_validity_sec, 3600);
+    }
+
+    #[test]
+    fn secret_key_from_file() {
+        let path = key_file("secret-key-file", 0o600);
+        let cfg = load(&format!(
+            r#"
+            authority_secret_key_file = "{}"
+            cert_validity_sec = 3600
+        "#,
+            path.display()
+        ))
+        .expect("failed to load a valid toml");
+        assert_eq!(cfg.authority_secret_key.to_string(), SECRET_KEY);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn secret_key_from_env() {
+        env::set_var("TEST_AUTHORITY_SECRET_KEY", SECRET_KEY);
+        let cfg = load(
+            r#"
+            authority_secret_key_env = "TEST_AUTHORITY_SECRET_KEY"
+            cert_validity_sec = 3600
+        "#,
+        )
+        .expect("failed to load a valid toml");
+        assert_eq!(cfg.authority_secret_key.to_string(), SECRET_KEY);
+    }
+
+    #[test]
+    fn missing_secret_key_file_fails() {
+        let err = load(
+            r#"
+            authority_secret_key_file = "/this/file/does/not/exist.key"
+            cert_validity_sec = 3600
+        "#,
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("cannot read secret key file /this/file/does/not/exist.key"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn world_readable_secret_key_file_fails() {
+        let path = key_file("secret-key-open", 0o644);
+        let err = load(&format!(
+            r#"
+            authority_secret_key_file = "{}"
+            cert_validity_sec = 3600
+        "#,
+            path.display()
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("accessible by other users"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unset_secret_key_env_fails() {
+        let err = load(
+            r#"
+            authority_secret_key_env = "THIS_VAR_DOES_NOT_EXIST"
+            cert_validity_sec = 3600
+        "#,
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("environment variable THIS_VAR_DOES_NOT_EXIST is not set"));
+    }
+
+    #[test]
+    fn several_secret_key_sources_fail() {
+        let err = load(&format!(
+            r#"
+            authority_secret_key = "{SECRET_KEY}"
+            authority_secret_key_env = "TEST_AUTHORITY_SECRET_KEY"
+            cert_validity_sec = 3600
+        "#
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("exactly one of"));
+    }
+}
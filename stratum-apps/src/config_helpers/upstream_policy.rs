@@ -0,0 +1,29 @@
+//! Policy applied by miner-side applications once every configured upstream failed.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What an application does after exhausting the retries of every configured upstream.
+///
+/// Configured with `on_all_upstreams_failed = "shutdown" | "wait_and_retry" | "solo"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AllUpstreamsFailedPolicy {
+    /// Shut the application down.
+    Shutdown,
+    /// Wait for the configured retry interval, then try every upstream again, until one
+    /// connects.
+    WaitAndRetry,
+    /// Keep serving downstreams without upstream, mining solo. Only supported by applications
+    /// able to build their own jobs.
+    Solo,
+}
+
+impl fmt::Display for AllUpstreamsFailedPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllUpstreamsFailedPolicy::Shutdown => write!(f, "shutdown"),
+            AllUpstreamsFailedPolicy::WaitAndRetry => write!(f, "wait_and_retry"),
+            AllUpstreamsFailedPolicy::Solo => write!(f, "solo"),
+        }
+    }
+}
@@ -66,6 +66,11 @@ pub mod utils;
 #[cfg(feature = "monitoring")]
 pub mod monitoring;
 
+/// Readiness signalling of the apps
+///
+/// Lets embedders wait until an app is fully operational instead of sleeping.
+pub mod ready_signal;
+
 // Task orchestrator used in SRI apps.
 pub mod task_manager;
 /// Template provider type
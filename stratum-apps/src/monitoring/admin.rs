@@ -0,0 +1,10 @@
+//! Admin actions triggered through the monitoring server
+
+/// Trait for applications that can leave their upstream on demand
+pub trait FailoverControl: Send + Sync {
+    /// Request a failover from the upstream currently connected to the next eligible one
+    ///
+    /// The failover itself happens asynchronously. Returns why the request was refused, e.g.
+    /// when no upstream is connected.
+    fn request_failover(&self) -> Result<(), String>;
+}
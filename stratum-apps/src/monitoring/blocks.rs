@@ -0,0 +1,174 @@
+//! Found block monitoring types
+//!
+//! When a share meets the network target, the app submits the block to the template provider
+//! with `SubmitSolution`. Template Distribution has no reply to it, so a block is only reported
+//! as accepted once the template provider announces it as the new chain tip in `SetNewPrevHash`.
+
+use std::{
+    collections::VecDeque,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::custom_mutex::Mutex;
+
+/// Number of found blocks kept, older ones only counting in the totals
+const MAX_RECENT_BLOCKS: usize = 100;
+
+/// Whether the template provider confirmed a found block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FoundBlockStatus {
+    /// Submitted to the template provider, not yet seen as the chain tip
+    Submitted,
+    /// Announced by the template provider as the new chain tip
+    Accepted,
+}
+
+/// A block found by the downstreams of the app
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FoundBlockInfo {
+    /// Hash of the block, in the byte order block explorers display
+    pub block_hash: String,
+    /// Id of the template the block was built on, None for custom jobs
+    pub template_id: Option<u64>,
+    /// Whether the template provider confirmed the block
+    pub status: FoundBlockStatus,
+    /// Unix timestamp (seconds) at which the block was found
+    pub found_at: u64,
+}
+
+/// Blocks found since startup
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FoundBlocksInfo {
+    /// Blocks submitted to the template provider since startup
+    pub submitted_total: u64,
+    /// Blocks the template provider announced as the new chain tip since startup
+    pub accepted_total: u64,
+    /// Most recent blocks, newest first
+    pub blocks: Vec<FoundBlockInfo>,
+}
+
+/// Trait for monitoring the blocks found by the downstreams of the app
+pub trait FoundBlocksMonitoring: Send + Sync {
+    /// Get the blocks found since startup
+    fn get_found_blocks(&self) -> FoundBlocksInfo;
+}
+
+#[derive(Debug, Default)]
+struct FoundBlocksState {
+    submitted_total: u64,
+    accepted_total: u64,
+    blocks: VecDeque<FoundBlockInfo>,
+}
+
+/// Blocks found since startup, updated by the app as solutions go out and new tips come in
+#[derive(Debug, Default)]
+pub struct FoundBlocks {
+    state: Mutex<FoundBlocksState>,
+}
+
+impl FoundBlocks {
+    /// Creates an empty record.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a block submitted to the template provider. `block_hash` is in display order,
+    /// as formatted by the hash types of the `bitcoin` crate.
+    pub fn record_submitted(&self, block_hash: String, template_id: Option<u64>) {
+        let found_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.state.super_safe_lock(|state| {
+            state.submitted_total += 1;
+            state.blocks.push_front(FoundBlockInfo {
+                block_hash,
+                template_id,
+                status: FoundBlockStatus::Submitted,
+                found_at,
+            });
+            state.blocks.truncate(MAX_RECENT_BLOCKS);
+        });
+    }
+
+    /// Records the chain tip announced by the template provider, `prev_hash` being the hash of
+    /// the tip in internal byte order, as carried by `SetNewPrevHash`. Returns the hash of the
+    /// submitted block it accepts, if any.
+    pub fn record_chain_tip(&self, prev_hash: &[u8]) -> Option<String> {
+        let tip: String = prev_hash.iter().rev().map(|b| format!("{b:02x}")).collect();
+        self.state.super_safe_lock(|state| {
+            let block = state.blocks.iter_mut().find(|block| {
+                block.status == FoundBlockStatus::Submitted && block.block_hash == tip
+            })?;
+            block.status = FoundBlockStatus::Accepted;
+            state.accepted_total += 1;
+            Some(tip)
+        })
+    }
+}
+
+impl FoundBlocksMonitoring for FoundBlocks {
+    fn get_found_blocks(&self) -> FoundBlocksInfo {
+        self.state.super_safe_lock(|state| FoundBlocksInfo {
+            submitted_total: state.submitted_total,
+            accepted_total: state.accepted_total,
+            blocks: state.blocks.iter().cloned().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn submitted_block_is_accepted_once_it_becomes_the_chain_tip() {
+        let found_blocks = FoundBlocks::new();
+        assert_eq!(found_blocks.get_found_blocks(), FoundBlocksInfo::default());
+
+        let mut prev_hash = [0u8; 32];
+        prev_hash[0] = 0xab;
+        prev_hash[31] = 0x01;
+        let block_hash = format!("01{}ab", "00".repeat(30));
+        found_blocks.record_submitted(block_hash.clone(), Some(7));
+
+        let info = found_blocks.get_found_blocks();
+        assert_eq!(info.submitted_total, 1);
+        assert_eq!(info.accepted_total, 0);
+        assert_eq!(info.blocks[0].block_hash, block_hash);
+        assert_eq!(info.blocks[0].template_id, Some(7));
+        assert_eq!(info.blocks[0].status, FoundBlockStatus::Submitted);
+
+        // a tip found elsewhere leaves the block submitted
+        assert_eq!(found_blocks.record_chain_tip(&[0x11; 32]), None);
+        assert_eq!(found_blocks.get_found_blocks().accepted_total, 0);
+
+        assert_eq!(found_blocks.record_chain_tip(&prev_hash), Some(block_hash));
+        let info = found_blocks.get_found_blocks();
+        assert_eq!(info.accepted_total, 1);
+        assert_eq!(info.blocks[0].status, FoundBlockStatus::Accepted);
+
+        // the same tip announced again is not counted twice
+        assert_eq!(found_blocks.record_chain_tip(&prev_hash), None);
+        assert_eq!(found_blocks.get_found_blocks().accepted_total, 1);
+    }
+
+    #[test]
+    fn only_the_most_recent_blocks_are_kept() {
+        let found_blocks = FoundBlocks::new();
+        for i in 0..MAX_RECENT_BLOCKS + 5 {
+            found_blocks.record_submitted(format!("{i:064x}"), None);
+        }
+        let info = found_blocks.get_found_blocks();
+        assert_eq!(info.submitted_total, (MAX_RECENT_BLOCKS + 5) as u64);
+        assert_eq!(info.blocks.len(), MAX_RECENT_BLOCKS);
+        assert_eq!(
+            info.blocks[0].block_hash,
+            format!("{:064x}", MAX_RECENT_BLOCKS + 4)
+        );
+    }
+}
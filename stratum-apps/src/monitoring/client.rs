@@ -10,11 +10,15 @@ use utoipa::ToSchema;
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ExtendedChannelInfo {
     pub channel_id: u32,
+    /// Group channel this channel belongs to, if any
+    pub group_channel_id: Option<u32>,
     pub user_identity: String,
     pub nominal_hashrate: f32,
     pub target_hex: String,
     pub requested_max_target_hex: String,
     pub extranonce_prefix_hex: String,
+    /// Id of the job the channel is currently mining on
+    pub current_job_id: Option<u32>,
     pub full_extranonce_size: usize,
     pub rollable_extranonce_size: u16,
     pub expected_shares_per_minute: f32,
@@ -31,11 +35,15 @@ pub struct ExtendedChannelInfo {
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct StandardChannelInfo {
     pub channel_id: u32,
+    /// Group channel this channel belongs to, if any
+    pub group_channel_id: Option<u32>,
     pub user_identity: String,
     pub nominal_hashrate: f32,
     pub target_hex: String,
     pub requested_max_target_hex: String,
     pub extranonce_prefix_hex: String,
+    /// Id of the job the channel is currently mining on
+    pub current_job_id: Option<u32>,
     pub expected_shares_per_minute: f32,
     pub shares_accepted: u32,
     pub share_work_sum: f64,
@@ -52,6 +60,15 @@ pub struct ClientInfo {
     pub client_id: usize,
     pub extended_channels: Vec<ExtendedChannelInfo>,
     pub standard_channels: Vec<StandardChannelInfo>,
+    /// Messages waiting to be sent to this client
+    #[serde(default)]
+    pub send_queue_depth: usize,
+    /// Whether the send queue of this client stayed above the slow consumer threshold
+    #[serde(default)]
+    pub slow_consumer: bool,
+    /// Times this client lagged behind the job broadcast and was re-synced to the latest job
+    #[serde(default)]
+    pub lagged_total: u64,
 }
 
 impl ClientInfo {
@@ -93,6 +110,17 @@ pub struct ClientMetadata {
     pub total_hashrate: f32,
 }
 
+/// Number of shares whose sequence number was not the next one of their channel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ShareSequenceViolations {
+    /// Shares numbered past the next sequence number, i.e. after skipped numbers
+    pub gaps: u64,
+    /// Shares repeating the last sequence number of their channel
+    pub duplicates: u64,
+    /// Shares numbered below the last sequence number of their channel
+    pub regressions: u64,
+}
+
 /// Aggregate information about all clients
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ClientsSummary {
@@ -101,6 +129,9 @@ pub struct ClientsSummary {
     pub extended_channels: usize,
     pub standard_channels: usize,
     pub total_hashrate: f32,
+    /// Shares submitted out of sequence, across all client channels
+    #[serde(default)]
+    pub share_sequence_violations: ShareSequenceViolations,
 }
 
 /// Trait for monitoring clients (downstream connections)
@@ -118,6 +149,14 @@ pub trait ClientsMonitoring: Send + Sync {
             .find(|c| c.client_id == client_id)
     }
 
+    /// Get the number of shares submitted out of sequence
+    ///
+    /// Default implementation returns no violations, for implementations that don't track share
+    /// sequence numbers.
+    fn get_share_sequence_violations(&self) -> ShareSequenceViolations {
+        ShareSequenceViolations::default()
+    }
+
     /// Get summary of all clients
     fn get_clients_summary(&self) -> ClientsSummary {
         let clients = self.get_clients();
@@ -130,6 +169,7 @@ pub trait ClientsMonitoring: Send + Sync {
             extended_channels: extended,
             standard_channels: standard,
             total_hashrate: clients.iter().map(|c| c.total_hashrate()).sum(),
+            share_sequence_violations: self.get_share_sequence_violations(),
         }
     }
 }
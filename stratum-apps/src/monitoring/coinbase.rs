@@ -0,0 +1,66 @@
+//! Coinbase output monitoring types
+//!
+//! Reports the outputs the app pays in the coinbase of its blocks, so operators can check the
+//! payout addresses before any block is found. Apps whose outputs change at runtime, such as the
+//! JDC paying the outputs of its Job Declarator Server, report the ones currently in use.
+
+use std::fmt;
+
+use miniscript::bitcoin::TxOut;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// An output of the coinbase of the blocks built by the app
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CoinbaseOutputInfo {
+    /// Hex encoded `scriptPubKey` of the output
+    pub script_pubkey: String,
+    /// Address paid by the output, None when its script has no standard address or the network
+    /// is unknown
+    pub address: Option<String>,
+    /// Value (satoshis) of the output, 0 when it takes the value left by the template
+    pub value: u64,
+}
+
+impl CoinbaseOutputInfo {
+    /// Creates the report of `output`, paying `address` if known.
+    pub fn new(output: &TxOut, address: Option<String>) -> Self {
+        Self {
+            script_pubkey: output.script_pubkey.to_hex_string(),
+            address,
+            value: output.value.to_sat(),
+        }
+    }
+}
+
+impl fmt::Display for CoinbaseOutputInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.address {
+            Some(address) => write!(f, "{address} (scriptPubKey {})", self.script_pubkey)?,
+            None => write!(f, "scriptPubKey {}", self.script_pubkey)?,
+        }
+        match self.value {
+            0 => write!(f, ", paid the value left by the template"),
+            value => write!(f, ", paid {value} sats"),
+        }
+    }
+}
+
+/// Coinbase outputs the app pays
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CoinbaseOutputsInfo {
+    pub outputs: Vec<CoinbaseOutputInfo>,
+}
+
+/// Trait for monitoring the coinbase outputs the app pays
+pub trait CoinbaseOutputsMonitoring: Send + Sync {
+    /// Get the coinbase outputs currently paid
+    fn get_coinbase_outputs(&self) -> CoinbaseOutputsInfo;
+}
+
+/// Outputs fixed by the config, which never change once parsed
+impl CoinbaseOutputsMonitoring for CoinbaseOutputsInfo {
+    fn get_coinbase_outputs(&self) -> CoinbaseOutputsInfo {
+        self.clone()
+    }
+}
@@ -0,0 +1,16 @@
+//! Connection admission monitoring types
+
+/// Trait for monitoring the connections admitted and refused by an accept loop
+pub trait ConnectionsMonitoring: Send + Sync {
+    /// Get the number of rejected connections, labeled by rejection reason
+    fn get_connections_rejected(&self) -> Vec<(&'static str, u64)>;
+
+    /// Get the number of downstream connections admitted since startup
+    fn get_connects_total(&self) -> u64;
+
+    /// Get the number of admitted downstream connections closed since startup
+    fn get_disconnects_total(&self) -> u64;
+
+    /// Get the number of downstream connections closed within the last minute
+    fn get_churn_per_minute(&self) -> u64;
+}
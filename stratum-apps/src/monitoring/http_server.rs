@@ -1,23 +1,35 @@
 //! HTTP server for exposing monitoring data using Axum
 
 use super::{
+    admin::FailoverControl,
+    blocks::{FoundBlockInfo, FoundBlockStatus, FoundBlocksInfo, FoundBlocksMonitoring},
     client::{
         ClientInfo, ClientMetadata, ClientsMonitoring, ClientsSummary, ExtendedChannelInfo,
-        StandardChannelInfo,
+        ShareSequenceViolations, StandardChannelInfo,
     },
+    coinbase::{CoinbaseOutputInfo, CoinbaseOutputsInfo, CoinbaseOutputsMonitoring},
+    connections::ConnectionsMonitoring,
+    messages::MessagesMonitoring,
     prometheus_metrics::PrometheusMetrics,
     server::{
-        ServerExtendedChannelInfo, ServerMonitoring, ServerStandardChannelInfo, ServerSummary,
+        FailoverEvent, FailoverReason, ServerExtendedChannelInfo, ServerMonitoring,
+        ServerStandardChannelInfo, ServerSummary,
     },
     snapshot_cache::SnapshotCache,
-    sv1::{Sv1ClientInfo, Sv1ClientsMonitoring, Sv1ClientsSummary},
+    sv1::{
+        LatencyBucket, LatencyHistogram, Sv1ClientInfo, Sv1ClientsMonitoring, Sv1ClientsSummary,
+        Sv1GroupSummary,
+    },
+    tasks::TasksMonitoring,
+    template_fees::{TemplateFeesInfo, TemplateFeesMonitoring},
     GlobalInfo,
 };
+use crate::utils::bind_retry::{bind_with_retry, BindRetry};
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
-    response::{IntoResponse, Json, Response},
-    routing::get,
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Json, Response},
+    routing::{get, post},
     Router,
 };
 use prometheus::{Encoder, TextEncoder};
@@ -25,10 +37,9 @@ use serde::Deserialize;
 use std::{
     future::Future,
     net::SocketAddr,
-    sync::Arc,
+    sync::{mpsc::RecvTimeoutError, Arc},
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tokio::net::TcpListener;
 use tracing::info;
 use utoipa::{IntoParams, OpenApi, ToSchema};
 use utoipa_swagger_ui::SwaggerUi;
@@ -48,13 +59,21 @@ use utoipa_swagger_ui::SwaggerUi;
         handle_clients,
         handle_client_by_id,
         handle_client_channels,
+        handle_channels,
         handle_sv1_clients,
         handle_sv1_client_by_id,
+        handle_failover,
+        handle_template_fees,
+        handle_found_blocks,
+        handle_coinbase_outputs,
     ),
     components(schemas(
         GlobalInfo,
         ServerSummary,
+        FailoverEvent,
+        FailoverReason,
         ClientsSummary,
+        ShareSequenceViolations,
         ServerExtendedChannelInfo,
         ServerStandardChannelInfo,
         ClientInfo,
@@ -63,13 +82,25 @@ use utoipa_swagger_ui::SwaggerUi;
         StandardChannelInfo,
         Sv1ClientInfo,
         Sv1ClientsSummary,
+        Sv1GroupSummary,
+        LatencyHistogram,
+        LatencyBucket,
+        TemplateFeesInfo,
+        FoundBlocksInfo,
+        FoundBlockInfo,
+        FoundBlockStatus,
+        CoinbaseOutputsInfo,
+        CoinbaseOutputInfo,
         HealthResponse,
         ErrorResponse,
+        FailoverResponse,
         ServerResponse,
         ServerChannelsResponse,
         ClientsResponse,
         ClientResponse,
         ClientChannelsResponse,
+        ChannelDetails,
+        ChannelsResponse,
         Sv1ClientsResponse,
     )),
     tags(
@@ -77,7 +108,12 @@ use utoipa_swagger_ui::SwaggerUi;
         (name = "global", description = "Global statistics"),
         (name = "server", description = "Server (upstream) monitoring"),
         (name = "clients", description = "Clients (downstream) monitoring"),
-        (name = "sv1", description = "Sv1 clients monitoring (Translator Proxy only)")
+        (name = "channels", description = "Active channels with both server and clients"),
+        (name = "sv1", description = "Sv1 clients monitoring (Translator Proxy only)"),
+        (name = "template", description = "Template provider monitoring (Bitcoin Core IPC only)"),
+        (name = "blocks", description = "Blocks found by the downstreams"),
+        (name = "config", description = "Configuration in effect"),
+        (name = "admin", description = "Admin actions (only when enabled by the application)")
     )
 )]
 struct ApiDoc;
@@ -88,11 +124,32 @@ struct ServerState {
     cache: Arc<SnapshotCache>,
     start_time: u64,
     metrics: PrometheusMetrics,
+    // Read directly on scrape: rejection counters are atomics, not behind business logic locks
+    connections: Option<Arc<dyn ConnectionsMonitoring + Send + Sync + 'static>>,
+    // Read directly on scrape as well: task counters are atomics
+    tasks: Option<Arc<dyn TasksMonitoring + Send + Sync + 'static>>,
+    // Read directly on scrape as well: message counters are atomics
+    messages: Option<Arc<dyn MessagesMonitoring + Send + Sync + 'static>>,
+    // Drained on scrape: the job propagation latencies are observed into their histogram once
+    sv1_clients: Option<Arc<dyn Sv1ClientsMonitoring + Send + Sync + 'static>>,
+    // Handles `POST /api/v1/failover`, unavailable unless the application provides it
+    failover: Option<Arc<dyn FailoverControl + Send + Sync + 'static>>,
+    // Bearer token required by the admin actions
+    admin_token: Option<Arc<str>>,
+    // Read directly on request: only the latest template is kept, behind its own lock
+    template_fees: Option<Arc<dyn TemplateFeesMonitoring + Send + Sync + 'static>>,
+    // Read directly on request: found blocks are rare and kept behind their own lock
+    found_blocks: Option<Arc<dyn FoundBlocksMonitoring + Send + Sync + 'static>>,
+    // Read directly on request: the outputs are few, and may change with the upstream
+    coinbase_outputs: Option<Arc<dyn CoinbaseOutputsMonitoring + Send + Sync + 'static>>,
 }
 
 const DEFAULT_LIMIT: usize = 25;
 const MAX_LIMIT: usize = 100;
 
+/// Status dashboard served at `/dashboard`
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+
 #[derive(Deserialize, IntoParams)]
 struct Pagination {
     /// Offset for pagination (default: 0)
@@ -127,8 +184,8 @@ fn paginate<T: Clone>(items: &[T], params: &Pagination) -> (usize, Vec<T>) {
 /// HTTP server that exposes monitoring data as JSON
 pub struct MonitoringServer {
     bind_address: SocketAddr,
+    bind_retry: BindRetry,
     state: ServerState,
-    refresh_interval: Duration,
 }
 
 impl MonitoringServer {
@@ -171,15 +228,24 @@ impl MonitoringServer {
         // Do initial refresh
         cache.refresh();
 
-        let metrics = PrometheusMetrics::new(has_server, has_clients, false)?;
+        let metrics = PrometheusMetrics::new(has_server, has_clients, false, false, false, false)?;
 
         Ok(Self {
             bind_address,
-            refresh_interval,
+            bind_retry: BindRetry::NONE,
             state: ServerState {
                 cache,
                 start_time,
                 metrics,
+                connections: None,
+                tasks: None,
+                messages: None,
+                sv1_clients: None,
+                failover: None,
+                admin_token: None,
+                template_fees: None,
+                found_blocks: None,
+                coinbase_outputs: None,
             },
         })
     }
@@ -200,19 +266,185 @@ impl MonitoringServer {
         let cache = Arc::new(
             Arc::try_unwrap(self.state.cache)
                 .unwrap_or_else(|arc| (*arc).clone())
-                .with_sv1_clients_source(sv1_monitoring),
+                .with_sv1_clients_source(sv1_monitoring.clone()),
         );
 
         // Refresh cache with new SV1 data
         cache.refresh();
 
         // Re-create metrics with SV1 enabled
-        self.state.metrics = PrometheusMetrics::new(has_server, has_clients, true)?;
+        let has_connections = self.state.connections.is_some();
+        let has_tasks = self.state.tasks.is_some();
+        let has_messages = self.state.messages.is_some();
+        self.state.metrics = PrometheusMetrics::new(
+            has_server,
+            has_clients,
+            true,
+            has_connections,
+            has_tasks,
+            has_messages,
+        )?;
         self.state.cache = cache;
+        self.state.sv1_clients = Some(sv1_monitoring);
+
+        Ok(self)
+    }
+
+    /// Add monitoring of the connections refused by the accept loop (optional)
+    ///
+    /// This must be called before `run()` to expose `sv2_connections_rejected_total` and the
+    /// downstream connection churn metrics.
+    pub fn with_connections_monitoring(
+        mut self,
+        connections_monitoring: Arc<dyn ConnectionsMonitoring + Send + Sync + 'static>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let snapshot = self.state.cache.get_snapshot();
+        let has_server = snapshot.server_info.is_some();
+        let has_clients = snapshot.clients_summary.is_some();
+        let has_sv1 = snapshot.sv1_summary.is_some();
+
+        let has_tasks = self.state.tasks.is_some();
+        let has_messages = self.state.messages.is_some();
+
+        // Re-create metrics with connection metrics enabled
+        self.state.metrics = PrometheusMetrics::new(
+            has_server,
+            has_clients,
+            has_sv1,
+            true,
+            has_tasks,
+            has_messages,
+        )?;
+        self.state.connections = Some(connections_monitoring);
+
+        Ok(self)
+    }
+
+    /// Add monitoring of the tasks spawned by the application (optional)
+    ///
+    /// This must be called before `run()` to expose the `sv2_tasks_*` metrics.
+    pub fn with_tasks_monitoring(
+        mut self,
+        tasks_monitoring: Arc<dyn TasksMonitoring + Send + Sync + 'static>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let snapshot = self.state.cache.get_snapshot();
+        let has_server = snapshot.server_info.is_some();
+        let has_clients = snapshot.clients_summary.is_some();
+        let has_sv1 = snapshot.sv1_summary.is_some();
+        let has_connections = self.state.connections.is_some();
+        let has_messages = self.state.messages.is_some();
+
+        // Re-create metrics with task metrics enabled
+        self.state.metrics = PrometheusMetrics::new(
+            has_server,
+            has_clients,
+            has_sv1,
+            has_connections,
+            true,
+            has_messages,
+        )?;
+        self.state.tasks = Some(tasks_monitoring);
+
+        Ok(self)
+    }
+
+    /// Add monitoring of the SV2 messages exchanged by the application (optional)
+    ///
+    /// This must be called before `run()` to expose `sv2_messages_total`.
+    pub fn with_messages_monitoring(
+        mut self,
+        messages_monitoring: Arc<dyn MessagesMonitoring + Send + Sync + 'static>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let snapshot = self.state.cache.get_snapshot();
+        let has_server = snapshot.server_info.is_some();
+        let has_clients = snapshot.clients_summary.is_some();
+        let has_sv1 = snapshot.sv1_summary.is_some();
+        let has_connections = self.state.connections.is_some();
+        let has_tasks = self.state.tasks.is_some();
+
+        // Re-create metrics with message metrics enabled
+        self.state.metrics = PrometheusMetrics::new(
+            has_server,
+            has_clients,
+            has_sv1,
+            has_connections,
+            has_tasks,
+            true,
+        )?;
+        self.state.messages = Some(messages_monitoring);
 
         Ok(self)
     }
 
+    /// Enable the `POST /api/v1/failover` admin action (optional)
+    ///
+    /// Requests must carry `admin_token` as an `Authorization: Bearer` header, others are
+    /// answered `401`. Without it, the endpoint answers `404`.
+    pub fn with_failover_control(
+        mut self,
+        failover_control: Arc<dyn FailoverControl + Send + Sync + 'static>,
+        admin_token: String,
+    ) -> Self {
+        self.state.failover = Some(failover_control);
+        self.state.admin_token = Some(admin_token.into());
+        self
+    }
+
+    /// Add monitoring of the fees of the templates from Bitcoin Core IPC (optional)
+    ///
+    /// Without it, `GET /api/v1/template/fees` answers `404`.
+    pub fn with_template_fees_monitoring(
+        mut self,
+        template_fees_monitoring: Arc<dyn TemplateFeesMonitoring + Send + Sync + 'static>,
+    ) -> Self {
+        self.state.template_fees = Some(template_fees_monitoring);
+        self
+    }
+
+    /// Add monitoring of the blocks found by the downstreams (optional)
+    ///
+    /// Without it, `GET /api/v1/blocks` answers `404`.
+    pub fn with_found_blocks_monitoring(
+        mut self,
+        found_blocks_monitoring: Arc<dyn FoundBlocksMonitoring + Send + Sync + 'static>,
+    ) -> Self {
+        self.state.found_blocks = Some(found_blocks_monitoring);
+        self
+    }
+
+    /// Add monitoring of the coinbase outputs the application pays (optional)
+    ///
+    /// Without it, `GET /api/v1/config/coinbase` answers `404`.
+    pub fn with_coinbase_outputs_monitoring(
+        mut self,
+        coinbase_outputs_monitoring: Arc<dyn CoinbaseOutputsMonitoring + Send + Sync + 'static>,
+    ) -> Self {
+        self.state.coinbase_outputs = Some(coinbase_outputs_monitoring);
+        self
+    }
+
+    /// Retry binding the server address while it is in use, e.g. on a fast restart (optional)
+    pub fn with_bind_retry(mut self, bind_retry: BindRetry) -> Self {
+        self.bind_retry = bind_retry;
+        self
+    }
+
+    /// Rebuild the snapshot when a request finds it older than `max_staleness` (optional)
+    ///
+    /// Bounds the staleness of the served data if the periodic refresh stalls. Call it after the
+    /// `with_*` builders that replace the cache.
+    pub fn with_on_demand_refresh(self, max_staleness: Duration) -> Self {
+        self.state.cache.set_on_demand_refresh(Some(max_staleness));
+        self
+    }
+
+    /// Snapshot cache served by this server, e.g. to change its refresh interval while running
+    ///
+    /// Call it after the `with_*` builders, which replace the cache.
+    pub fn snapshot_cache(&self) -> Arc<SnapshotCache> {
+        self.state.cache.clone()
+    }
+
     /// Run the monitoring server until the shutdown signal completes
     ///
     /// Starts an HTTP server that exposes monitoring data as JSON.
@@ -228,18 +460,41 @@ impl MonitoringServer {
         shutdown_signal: impl Future<Output = ()> + Send + 'static,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         info!("Starting monitoring server on http://{}", self.bind_address);
-        info!("Cache refresh interval: {:?}", self.refresh_interval);
+        info!(
+            "Cache refresh interval: {:?}",
+            self.state.cache.refresh_interval()
+        );
 
-        // Spawn background task to refresh cache periodically
+        // Refresh the cache periodically on a dedicated thread, so a saturated runtime cannot
+        // starve it. The interval is read again after every refresh, so changes made through the
+        // cache apply without restarting the server. Dropping `stop_refresh` stops the thread.
         let cache_for_refresh = self.state.cache.clone();
-        let refresh_interval = self.refresh_interval;
-        let refresh_handle = tokio::spawn(async move {
-            let mut interval = tokio::time::interval(refresh_interval);
-            loop {
-                interval.tick().await;
+        let (stop_refresh, refresh_stopped) = std::sync::mpsc::channel::<()>();
+        std::thread::Builder::new()
+            .name("monitoring-refresh".to_string())
+            .spawn(move || loop {
                 cache_for_refresh.refresh();
-            }
-        });
+                match refresh_stopped.recv_timeout(cache_for_refresh.refresh_interval()) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            })?;
+
+        // Watchdog reporting a refresh that is overdue, e.g. stuck on a business logic lock. It
+        // runs on its own thread too, so it still reports when the runtime itself is stalled.
+        // Dropping `stop_watchdog` stops the thread.
+        let cache_for_watchdog = self.state.cache.clone();
+        let (stop_watchdog, watchdog_stopped) = std::sync::mpsc::channel::<()>();
+        std::thread::Builder::new()
+            .name("monitoring-watchdog".to_string())
+            .spawn(move || loop {
+                match watchdog_stopped.recv_timeout(cache_for_watchdog.refresh_interval()) {
+                    Err(RecvTimeoutError::Timeout) => {
+                        cache_for_watchdog.check_overdue_refresh();
+                    }
+                    _ => break,
+                }
+            })?;
 
         // Versioned JSON API under /api/v1
         let api_v1 = Router::new()
@@ -250,17 +505,23 @@ impl MonitoringServer {
             .route("/clients", get(handle_clients))
             .route("/clients/{client_id}", get(handle_client_by_id))
             .route("/clients/{client_id}/channels", get(handle_client_channels))
+            .route("/channels", get(handle_channels))
             .route("/sv1/clients", get(handle_sv1_clients))
-            .route("/sv1/clients/{client_id}", get(handle_sv1_client_by_id));
+            .route("/sv1/clients/{client_id}", get(handle_sv1_client_by_id))
+            .route("/failover", post(handle_failover))
+            .route("/template/fees", get(handle_template_fees))
+            .route("/blocks", get(handle_found_blocks))
+            .route("/config/coinbase", get(handle_coinbase_outputs));
 
         let app = Router::new()
             .route("/", get(handle_root))
+            .route("/dashboard", get(handle_dashboard))
             .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()))
             .nest("/api/v1", api_v1)
             .route("/metrics", get(handle_prometheus_metrics))
             .with_state(self.state);
 
-        let listener = TcpListener::bind(self.bind_address).await?;
+        let listener = bind_with_retry(self.bind_address, self.bind_retry).await?;
 
         info!(
             "Swagger UI available at http://{}/swagger-ui",
@@ -270,6 +531,10 @@ impl MonitoringServer {
             "Prometheus metrics available at http://{}/metrics",
             self.bind_address
         );
+        info!(
+            "Status dashboard available at http://{}/dashboard",
+            self.bind_address
+        );
 
         let server_handle = axum::serve(listener, app).with_graceful_shutdown(async move {
             shutdown_signal.await;
@@ -279,8 +544,9 @@ impl MonitoringServer {
         // Run server and wait for shutdown
         let result = server_handle.await;
 
-        // Stop the refresh task
-        refresh_handle.abort();
+        // Stop the refresh thread and its watchdog
+        drop(stop_refresh);
+        drop(stop_watchdog);
 
         info!("Monitoring server stopped");
         result.map_err(|e| e.into())
@@ -290,8 +556,10 @@ impl MonitoringServer {
 // Response types - used for both actual responses and OpenAPI documentation
 #[derive(serde::Serialize, ToSchema)]
 struct HealthResponse {
+    /// `ok`, or `stale` when the snapshot is older than twice the refresh interval
     status: String,
     timestamp: u64,
+    snapshot_age_secs: Option<u64>,
 }
 
 #[derive(serde::Serialize, ToSchema)]
@@ -299,11 +567,19 @@ struct ErrorResponse {
     error: String,
 }
 
+#[derive(serde::Serialize, ToSchema)]
+struct FailoverResponse {
+    status: String,
+}
+
 #[derive(serde::Serialize, ToSchema)]
 struct ServerResponse {
+    upstream: Option<String>,
     extended_channels_count: usize,
     standard_channels_count: usize,
     total_hashrate: f32,
+    /// Most recent fallback to another upstream, if any happened
+    last_failover: Option<FailoverEvent>,
 }
 
 #[derive(serde::Serialize, ToSchema)]
@@ -343,6 +619,30 @@ struct ClientChannelsResponse {
     standard_channels: Vec<StandardChannelInfo>,
 }
 
+/// An active channel, opened either with the server or by a client
+#[derive(Clone, serde::Serialize, ToSchema)]
+struct ChannelDetails {
+    /// `server` for channels opened with the upstream, `client` for channels opened by downstreams
+    source: String,
+    /// `extended` or `standard`
+    channel_type: String,
+    channel_id: u32,
+    group_channel_id: Option<u32>,
+    target_hex: String,
+    current_job_id: Option<u32>,
+    extranonce_prefix_hex: String,
+    /// Downstreams mining on the channel
+    downstream_ids: Vec<usize>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+struct ChannelsResponse {
+    offset: usize,
+    limit: usize,
+    total: usize,
+    items: Vec<ChannelDetails>,
+}
+
 #[derive(serde::Serialize, ToSchema)]
 struct Sv1ClientsResponse {
     offset: usize,
@@ -358,6 +658,7 @@ async fn handle_root() -> Json<serde_json::Value> {
         "version": "0.1.0",
         "endpoints": {
             "/": "This endpoint - API listing",
+            "/dashboard": "Status dashboard (HTML)",
             "/swagger-ui": "Swagger UI (interactive API documentation)",
             "/api-docs/openapi.json": "OpenAPI specification",
             "/api/v1/health": "Health check",
@@ -367,30 +668,49 @@ async fn handle_root() -> Json<serde_json::Value> {
             "/api/v1/clients": "All Sv2 clients metadata (paginated)",
             "/api/v1/clients/{id}": "Single Sv2 client metadata",
             "/api/v1/clients/{id}/channels": "Sv2 client channels (paginated)",
+            "/api/v1/channels": "All server and client channels (paginated)",
             "/api/v1/sv1/clients": "Sv1 clients (Translator Proxy only, paginated)",
             "/api/v1/sv1/clients/{id}": "Single Sv1 client (Translator Proxy only)",
+            "/api/v1/template/fees": "Fees of the latest template (Bitcoin Core IPC only)",
+            "/api/v1/blocks": "Blocks found since startup, submitted or accepted",
+            "/api/v1/config/coinbase": "Coinbase outputs currently paid",
             "/metrics": "Prometheus metrics"
         }
     }))
 }
 
+/// Dashboard endpoint - status page polling `/api/v1/global`, with no external assets
+async fn handle_dashboard() -> Html<&'static str> {
+    Html(DASHBOARD_HTML)
+}
+
 /// Health check endpoint
 #[utoipa::path(
     get,
     path = "/api/v1/health",
     tag = "health",
     responses(
-        (status = 200, description = "Service is healthy", body = HealthResponse)
+        (status = 200, description = "Service is healthy", body = HealthResponse),
+        (status = 503, description = "Monitoring snapshot is stale", body = HealthResponse)
     )
 )]
-async fn handle_health() -> Json<HealthResponse> {
-    Json(HealthResponse {
-        status: "ok".to_string(),
-        timestamp: SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs(),
-    })
+async fn handle_health(State(state): State<ServerState>) -> (StatusCode, Json<HealthResponse>) {
+    let (status_code, status) = if state.cache.is_stale() {
+        (StatusCode::SERVICE_UNAVAILABLE, "stale")
+    } else {
+        (StatusCode::OK, "ok")
+    };
+    (
+        status_code,
+        Json(HealthResponse {
+            status: status.to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            snapshot_age_secs: state.cache.snapshot_age().map(|age| age.as_secs()),
+        }),
+    )
 }
 
 /// Get global statistics
@@ -410,6 +730,8 @@ async fn handle_global(State(state): State<ServerState>) -> Json<GlobalInfo> {
         - state.start_time;
 
     let snapshot = state.cache.get_snapshot();
+    let snapshot_age_secs = snapshot.age_secs().unwrap_or_default();
+    let generated_at = snapshot.generated_at.unwrap_or_default();
 
     let clients = snapshot.clients_summary.unwrap_or(ClientsSummary {
         total_clients: 0,
@@ -417,19 +739,24 @@ async fn handle_global(State(state): State<ServerState>) -> Json<GlobalInfo> {
         extended_channels: 0,
         standard_channels: 0,
         total_hashrate: 0.0,
+        share_sequence_violations: ShareSequenceViolations::default(),
     });
 
     let server = snapshot.server_summary.unwrap_or(ServerSummary {
+        upstream: None,
         total_channels: 0,
         extended_channels: 0,
         standard_channels: 0,
         total_hashrate: 0.0,
+        last_failover: None,
     });
 
     Json(GlobalInfo {
         server,
         clients,
         uptime_secs,
+        snapshot_age_secs,
+        generated_at,
     })
 }
 
@@ -448,9 +775,11 @@ async fn handle_server(State(state): State<ServerState>) -> Response {
 
     match snapshot.server_summary {
         Some(summary) => Json(ServerResponse {
+            upstream: summary.upstream,
             extended_channels_count: summary.extended_channels,
             standard_channels_count: summary.standard_channels,
             total_hashrate: summary.total_hashrate,
+            last_failover: summary.last_failover,
         })
         .into_response(),
         None => (
@@ -463,6 +792,140 @@ async fn handle_server(State(state): State<ServerState>) -> Response {
     }
 }
 
+/// Fail over from the current upstream to the next eligible one
+///
+/// The application lets the work already queued for the upstream go out before leaving it.
+#[utoipa::path(
+    post,
+    path = "/api/v1/failover",
+    tag = "admin",
+    responses(
+        (status = 202, description = "Failover requested", body = FailoverResponse),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorResponse),
+        (status = 404, description = "Failover not available", body = ErrorResponse),
+        (status = 409, description = "Failover refused", body = ErrorResponse)
+    )
+)]
+async fn handle_failover(State(state): State<ServerState>, headers: HeaderMap) -> Response {
+    let (Some(failover), Some(admin_token)) = (state.failover, state.admin_token) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Failover not available".to_string(),
+            }),
+        )
+            .into_response();
+    };
+    let authorized = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token_matches(token, &admin_token));
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "Missing or invalid admin token".to_string(),
+            }),
+        )
+            .into_response();
+    }
+
+    match failover.request_failover() {
+        Ok(()) => {
+            info!("Failover requested through the monitoring server");
+            (
+                StatusCode::ACCEPTED,
+                Json(FailoverResponse {
+                    status: "failover requested".to_string(),
+                }),
+            )
+                .into_response()
+        }
+        Err(error) => (StatusCode::CONFLICT, Json(ErrorResponse { error })).into_response(),
+    }
+}
+
+// Compares the bytes of `token` with the ones of `expected` without stopping at the first
+// difference, so response times do not reveal how much of the admin token was guessed
+fn token_matches(token: &str, expected: &str) -> bool {
+    let (token, expected) = (token.as_bytes(), expected.as_bytes());
+    let mut difference = token.len() ^ expected.len();
+    for (i, byte) in expected.iter().enumerate() {
+        difference |= usize::from(byte ^ token.get(i).copied().unwrap_or_default());
+    }
+    std::hint::black_box(difference) == 0
+}
+
+/// Get the fees of the latest template and the configured fee threshold
+#[utoipa::path(
+    get,
+    path = "/api/v1/template/fees",
+    tag = "template",
+    responses(
+        (status = 200, description = "Fees of the latest template", body = TemplateFeesInfo),
+        (status = 404, description = "Template fee monitoring not available", body = ErrorResponse)
+    )
+)]
+async fn handle_template_fees(State(state): State<ServerState>) -> Response {
+    match state.template_fees {
+        Some(template_fees) => Json(template_fees.get_template_fees()).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Template fee monitoring not available".to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+/// Get the blocks found since startup, submitted or accepted by the template provider
+#[utoipa::path(
+    get,
+    path = "/api/v1/blocks",
+    tag = "blocks",
+    responses(
+        (status = 200, description = "Blocks found since startup", body = FoundBlocksInfo),
+        (status = 404, description = "Found block monitoring not available", body = ErrorResponse)
+    )
+)]
+async fn handle_found_blocks(State(state): State<ServerState>) -> Response {
+    match state.found_blocks {
+        Some(found_blocks) => Json(found_blocks.get_found_blocks()).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Found block monitoring not available".to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+/// Get the coinbase outputs the application currently pays
+#[utoipa::path(
+    get,
+    path = "/api/v1/config/coinbase",
+    tag = "config",
+    responses(
+        (status = 200, description = "Coinbase outputs paid", body = CoinbaseOutputsInfo),
+        (status = 404, description = "Coinbase output reporting not enabled", body = ErrorResponse)
+    )
+)]
+async fn handle_coinbase_outputs(State(state): State<ServerState>) -> Response {
+    match state.coinbase_outputs {
+        Some(coinbase_outputs) => Json(coinbase_outputs.get_coinbase_outputs()).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Coinbase output reporting not enabled".to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
 /// Get server channels (paginated)
 #[utoipa::path(
     get,
@@ -655,6 +1118,102 @@ async fn handle_client_channels(
     }
 }
 
+/// Get all active channels, with the server and with clients (paginated)
+///
+/// Server channels are listed first. Their downstreams are the Sv1 clients mining on them, if
+/// Sv1 monitoring is available.
+#[utoipa::path(
+    get,
+    path = "/api/v1/channels",
+    tag = "channels",
+    params(Pagination),
+    responses(
+        (status = 200, description = "Active channels (paginated)", body = ChannelsResponse),
+        (status = 404, description = "Channels monitoring not available", body = ErrorResponse)
+    )
+)]
+async fn handle_channels(
+    Query(params): Query<Pagination>,
+    State(state): State<ServerState>,
+) -> Response {
+    let snapshot = state.cache.get_snapshot();
+
+    if snapshot.server_info.is_none() && snapshot.clients.is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Channels monitoring not available".to_string(),
+            }),
+        )
+            .into_response();
+    }
+
+    let sv1_downstream_ids = |channel_id: u32| -> Vec<usize> {
+        snapshot
+            .sv1_clients
+            .iter()
+            .flatten()
+            .filter(|c| c.channel_id == Some(channel_id))
+            .map(|c| c.client_id)
+            .collect()
+    };
+
+    let mut channels = Vec::new();
+    if let Some(ref server) = snapshot.server_info {
+        channels.extend(server.extended_channels.iter().map(|c| ChannelDetails {
+            source: "server".to_string(),
+            channel_type: "extended".to_string(),
+            channel_id: c.channel_id,
+            group_channel_id: c.group_channel_id,
+            target_hex: c.target_hex.clone(),
+            current_job_id: c.current_job_id,
+            extranonce_prefix_hex: c.extranonce_prefix_hex.clone(),
+            downstream_ids: sv1_downstream_ids(c.channel_id),
+        }));
+        channels.extend(server.standard_channels.iter().map(|c| ChannelDetails {
+            source: "server".to_string(),
+            channel_type: "standard".to_string(),
+            channel_id: c.channel_id,
+            group_channel_id: c.group_channel_id,
+            target_hex: c.target_hex.clone(),
+            current_job_id: c.current_job_id,
+            extranonce_prefix_hex: c.extranonce_prefix_hex.clone(),
+            downstream_ids: sv1_downstream_ids(c.channel_id),
+        }));
+    }
+    for client in snapshot.clients.iter().flatten() {
+        channels.extend(client.extended_channels.iter().map(|c| ChannelDetails {
+            source: "client".to_string(),
+            channel_type: "extended".to_string(),
+            channel_id: c.channel_id,
+            group_channel_id: c.group_channel_id,
+            target_hex: c.target_hex.clone(),
+            current_job_id: c.current_job_id,
+            extranonce_prefix_hex: c.extranonce_prefix_hex.clone(),
+            downstream_ids: vec![client.client_id],
+        }));
+        channels.extend(client.standard_channels.iter().map(|c| ChannelDetails {
+            source: "client".to_string(),
+            channel_type: "standard".to_string(),
+            channel_id: c.channel_id,
+            group_channel_id: c.group_channel_id,
+            target_hex: c.target_hex.clone(),
+            current_job_id: c.current_job_id,
+            extranonce_prefix_hex: c.extranonce_prefix_hex.clone(),
+            downstream_ids: vec![client.client_id],
+        }));
+    }
+
+    let (total, items) = paginate(&channels, &params);
+    Json(ChannelsResponse {
+        offset: params.offset,
+        limit: params.effective_limit(),
+        total,
+        items,
+    })
+    .into_response()
+}
+
 /// Get Sv1 clients (Translator Proxy only)
 #[utoipa::path(
     get,
@@ -748,6 +1307,10 @@ async fn handle_prometheus_metrics(State(state): State<ServerState>) -> Response
         .as_secs()
         - state.start_time;
     state.metrics.sv2_uptime_seconds.set(uptime_secs as f64);
+    state
+        .metrics
+        .sv2_monitoring_snapshot_overdue_total
+        .set(state.cache.overdue_refreshes() as f64);
 
     // Reset per-channel metrics before repopulating
     if let Some(ref metric) = state.metrics.sv2_client_channel_hashrate {
@@ -756,36 +1319,102 @@ async fn handle_prometheus_metrics(State(state): State<ServerState>) -> Response
     if let Some(ref metric) = state.metrics.sv2_client_shares_accepted_total {
         metric.reset();
     }
+    if let Some(ref metric) = state.metrics.sv2_client_send_queue_depth {
+        metric.reset();
+    }
+    if let Some(ref metric) = state.metrics.sv2_client_slow_consumer {
+        metric.reset();
+    }
+    if let Some(ref metric) = state.metrics.sv2_client_open_channels {
+        metric.reset();
+    }
     if let Some(ref metric) = state.metrics.sv2_server_channel_hashrate {
         metric.reset();
     }
     if let Some(ref metric) = state.metrics.sv2_server_shares_accepted_total {
         metric.reset();
     }
+    if let Some(ref metric) = state.metrics.sv2_server_channel_extranonce_prefix_usage {
+        metric.reset();
+    }
+    // Server metrics are labeled by upstream, reset them so a failover drops the old label
+    if let Some(ref metric) = state.metrics.sv2_server_active {
+        metric.reset();
+    }
+    if let Some(ref metric) = state.metrics.sv2_server_channels {
+        metric.reset();
+    }
+    if let Some(ref metric) = state.metrics.sv2_server_hashrate_total {
+        metric.reset();
+    }
+    if let Some(ref metric) = state.metrics.sv2_server_pending_declared_jobs {
+        metric.reset();
+    }
+    // Groups are labeled by the group label, reset them so groups left by every client disappear
+    if let Some(ref metric) = state.metrics.sv1_group_clients {
+        metric.reset();
+    }
+    if let Some(ref metric) = state.metrics.sv1_group_hashrate {
+        metric.reset();
+    }
+    // Labeled by downstream, reset so disconnected downstreams disappear
+    if let Some(ref metric) = state.metrics.sv2_downstream_lagged_total {
+        metric.reset();
+    }
+    if let Some(ref metric) = state.metrics.sv1_client_send_queue_depth {
+        metric.reset();
+    }
+    if let Some(ref metric) = state.metrics.sv1_client_slow_consumer {
+        metric.reset();
+    }
+    if let Some(ref metric) = state.metrics.sv1_client_achieved_hashrate {
+        metric.reset();
+    }
 
     // Collect server metrics
     if let Some(ref summary) = snapshot.server_summary {
+        let upstream = summary.upstream.as_deref().unwrap_or_default();
+        if let (Some(ref metric), Some(active)) =
+            (&state.metrics.sv2_server_active, &summary.upstream)
+        {
+            metric.with_label_values(&[active]).set(1.0);
+        }
         if let Some(ref metric) = state.metrics.sv2_server_channels {
             metric
-                .with_label_values(&["extended"])
+                .with_label_values(&[upstream, "extended"])
                 .set(summary.extended_channels as f64);
             metric
-                .with_label_values(&["standard"])
+                .with_label_values(&[upstream, "standard"])
                 .set(summary.standard_channels as f64);
         }
         if let Some(ref metric) = state.metrics.sv2_server_hashrate_total {
-            metric.set(summary.total_hashrate as f64);
+            metric
+                .with_label_values(&[upstream])
+                .set(summary.total_hashrate as f64);
         }
     }
 
     if let Some(ref server) = snapshot.server_info {
+        let upstream = server.upstream.as_deref().unwrap_or_default();
+        if let (Some(ref metric), Some(pending)) = (
+            &state.metrics.sv2_server_pending_declared_jobs,
+            server.pending_declared_jobs,
+        ) {
+            metric.with_label_values(&[upstream]).set(pending as f64);
+        }
+        if let (Some(ref metric), Some(stale_job_events)) = (
+            &state.metrics.sv2_server_stale_job_events_total,
+            server.stale_job_events,
+        ) {
+            metric.set(stale_job_events as f64);
+        }
         for channel in &server.extended_channels {
             let channel_id = channel.channel_id.to_string();
             let user = &channel.user_identity;
 
             if let Some(ref metric) = state.metrics.sv2_server_shares_accepted_total {
                 metric
-                    .with_label_values(&[&channel_id, user])
+                    .with_label_values(&[upstream, &channel_id, user])
                     .set(channel.shares_accepted as f64);
             }
             if let (Some(ref metric), Some(hashrate)) = (
@@ -793,9 +1422,17 @@ async fn handle_prometheus_metrics(State(state): State<ServerState>) -> Response
                 channel.nominal_hashrate,
             ) {
                 metric
-                    .with_label_values(&[&channel_id, user])
+                    .with_label_values(&[upstream, &channel_id, user])
                     .set(hashrate as f64);
             }
+            if let (Some(ref metric), Some(usage)) = (
+                &state.metrics.sv2_server_channel_extranonce_prefix_usage,
+                channel.extranonce_prefix_usage,
+            ) {
+                metric
+                    .with_label_values(&[upstream, &channel_id, user])
+                    .set(usage);
+            }
         }
 
         for channel in &server.standard_channels {
@@ -804,7 +1441,7 @@ async fn handle_prometheus_metrics(State(state): State<ServerState>) -> Response
 
             if let Some(ref metric) = state.metrics.sv2_server_shares_accepted_total {
                 metric
-                    .with_label_values(&[&channel_id, user])
+                    .with_label_values(&[upstream, &channel_id, user])
                     .set(channel.shares_accepted as f64);
             }
             if let (Some(ref metric), Some(hashrate)) = (
@@ -812,7 +1449,7 @@ async fn handle_prometheus_metrics(State(state): State<ServerState>) -> Response
                 channel.nominal_hashrate,
             ) {
                 metric
-                    .with_label_values(&[&channel_id, user])
+                    .with_label_values(&[upstream, &channel_id, user])
                     .set(hashrate as f64);
             }
         }
@@ -834,10 +1471,44 @@ async fn handle_prometheus_metrics(State(state): State<ServerState>) -> Response
         if let Some(ref metric) = state.metrics.sv2_client_hashrate_total {
             metric.set(summary.total_hashrate as f64);
         }
+        if let Some(ref metric) = state.metrics.sv2_client_share_sequence_violations_total {
+            let violations = &summary.share_sequence_violations;
+            metric
+                .with_label_values(&["gap"])
+                .set(violations.gaps as f64);
+            metric
+                .with_label_values(&["duplicate"])
+                .set(violations.duplicates as f64);
+            metric
+                .with_label_values(&["regression"])
+                .set(violations.regressions as f64);
+        }
 
         for client in snapshot.clients.as_deref().unwrap_or(&[]) {
             let client_id = client.client_id.to_string();
 
+            if let Some(ref metric) = state.metrics.sv2_downstream_lagged_total {
+                metric
+                    .with_label_values(&[&client_id])
+                    .set(client.lagged_total as f64);
+            }
+
+            if let Some(ref metric) = state.metrics.sv2_client_send_queue_depth {
+                metric
+                    .with_label_values(&[&client_id])
+                    .set(client.send_queue_depth as f64);
+            }
+            if let Some(ref metric) = state.metrics.sv2_client_slow_consumer {
+                metric
+                    .with_label_values(&[&client_id])
+                    .set(if client.slow_consumer { 1.0 } else { 0.0 });
+            }
+            if let Some(ref metric) = state.metrics.sv2_client_open_channels {
+                metric
+                    .with_label_values(&[&client_id])
+                    .set(client.total_channels() as f64);
+            }
+
             for channel in &client.extended_channels {
                 let channel_id = channel.channel_id.to_string();
                 let user = &channel.user_identity;
@@ -880,6 +1551,107 @@ async fn handle_prometheus_metrics(State(state): State<ServerState>) -> Response
         if let Some(ref metric) = state.metrics.sv1_hashrate_total {
             metric.set(summary.total_hashrate as f64);
         }
+        for group in &summary.groups {
+            if let Some(ref metric) = state.metrics.sv1_group_clients {
+                metric
+                    .with_label_values(&[&group.group_label])
+                    .set(group.total_clients as f64);
+            }
+            if let Some(ref metric) = state.metrics.sv1_group_hashrate {
+                metric
+                    .with_label_values(&[&group.group_label])
+                    .set(group.total_hashrate as f64);
+            }
+        }
+        if let Some(ref metric) = state.metrics.sv2_keepalive_time_capped_total {
+            metric.set(summary.keepalive_time_capped_total as f64);
+        }
+
+        if let Some(ref metric) = state.metrics.sv1_job_propagation_alarms_total {
+            metric.set(summary.job_propagation_alarms_total as f64);
+        }
+        if let Some(ref metric) = state.metrics.sv1_jobs_dropped_without_prevhash_total {
+            metric.set(summary.jobs_dropped_without_prevhash_total as f64);
+        }
+        if let Some(ref metric) = state.metrics.sv1_keepalive_shares_orphaned_total {
+            metric.set(summary.keepalive_shares_orphaned_total as f64);
+        }
+    }
+    if let (Some(metric), Some(sv1_clients)) = (
+        &state.metrics.sv1_job_propagation_latency_seconds,
+        &state.sv1_clients,
+    ) {
+        for seconds in sv1_clients.take_job_propagation_latencies() {
+            metric.observe(seconds);
+        }
+    }
+    for client in snapshot.sv1_clients.as_deref().unwrap_or(&[]) {
+        let client_id = client.client_id.to_string();
+        if let Some(ref metric) = state.metrics.sv2_downstream_lagged_total {
+            metric
+                .with_label_values(&[&client_id])
+                .set(client.lagged_total as f64);
+        }
+        if let Some(ref metric) = state.metrics.sv1_client_send_queue_depth {
+            metric
+                .with_label_values(&[&client_id])
+                .set(client.send_queue_depth as f64);
+        }
+        if let Some(ref metric) = state.metrics.sv1_client_slow_consumer {
+            metric
+                .with_label_values(&[&client_id])
+                .set(if client.slow_consumer { 1.0 } else { 0.0 });
+        }
+        if let (Some(ref metric), Some(achieved_hashrate)) = (
+            &state.metrics.sv1_client_achieved_hashrate,
+            client.achieved_hashrate,
+        ) {
+            metric
+                .with_label_values(&[&client_id])
+                .set(achieved_hashrate as f64);
+        }
+    }
+
+    // Collect connection admission metrics
+    if let Some(ref connections) = state.connections {
+        if let Some(ref metric) = state.metrics.sv2_connections_rejected_total {
+            for (reason, total) in connections.get_connections_rejected() {
+                metric.with_label_values(&[reason]).set(total as f64);
+            }
+        }
+        if let Some(ref metric) = state.metrics.sv2_downstream_connects_total {
+            metric.set(connections.get_connects_total() as f64);
+        }
+        if let Some(ref metric) = state.metrics.sv2_downstream_disconnects_total {
+            metric.set(connections.get_disconnects_total() as f64);
+        }
+        if let Some(ref metric) = state.metrics.sv2_downstream_churn_per_minute {
+            metric.set(connections.get_churn_per_minute() as f64);
+        }
+    }
+
+    // Collect task metrics
+    if let Some(ref tasks) = state.tasks {
+        if let Some(ref metric) = state.metrics.sv2_tasks_active {
+            metric.set(tasks.get_tasks_active() as f64);
+        }
+        if let Some(ref metric) = state.metrics.sv2_tasks_spawned_total {
+            metric.set(tasks.get_tasks_spawned() as f64);
+        }
+        if let Some(ref metric) = state.metrics.sv2_tasks_cap_exceeded_total {
+            metric.set(tasks.get_tasks_cap_exceeded() as f64);
+        }
+    }
+
+    // Collect message metrics
+    if let Some(ref messages) = state.messages {
+        if let Some(ref metric) = state.metrics.sv2_messages_total {
+            for (direction, message_type, total) in messages.get_messages_total() {
+                metric
+                    .with_label_values(&[direction, message_type])
+                    .set(total as f64);
+            }
+        }
     }
 
     // Encode and return metrics
@@ -907,3 +1679,328 @@ async fn handle_prometheus_metrics(State(state): State<ServerState>) -> Response
             .into_response(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config_helpers::CoinbaseRewardScript,
+        monitoring::{FoundBlocks, TemplateFees},
+        tp_type::BitcoinNetwork,
+    };
+    use axum::http::header::CONTENT_TYPE;
+    use miniscript::bitcoin::{Amount, TxOut};
+
+    #[tokio::test]
+    async fn dashboard_returns_html_with_placeholders() {
+        let response = handle_dashboard().await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let content_type = response.headers()[CONTENT_TYPE].to_str().unwrap();
+        assert!(content_type.starts_with("text/html"));
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        for placeholder in [
+            "id=\"connection-status\"",
+            "id=\"downstream-count\"",
+            "id=\"hashrate\"",
+            "id=\"events\"",
+            "/api/v1/global",
+        ] {
+            assert!(body.contains(placeholder), "missing {placeholder}");
+        }
+        // Everything must be inline, without external scripts or stylesheets
+        assert!(!body.contains("<script src"));
+        assert!(!body.contains("<link"));
+    }
+
+    #[tokio::test]
+    async fn health_reports_stale_snapshot() {
+        let refresh_interval = Duration::from_millis(50);
+        let server =
+            MonitoringServer::new("127.0.0.1:0".parse().unwrap(), None, None, refresh_interval)
+                .unwrap();
+
+        let (status_code, Json(health)) = handle_health(State(server.state.clone())).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(health.status, "ok");
+        assert_eq!(health.snapshot_age_secs, Some(0));
+
+        // Without the refresh loop of `run()`, the snapshot ages past twice the interval
+        tokio::time::sleep(refresh_interval * 3).await;
+        let (status_code, Json(health)) = handle_health(State(server.state.clone())).await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(health.status, "stale");
+    }
+
+    struct SingleChannelClient;
+
+    impl ClientsMonitoring for SingleChannelClient {
+        fn get_clients(&self) -> Vec<ClientInfo> {
+            vec![ClientInfo {
+                client_id: 7,
+                extended_channels: vec![ExtendedChannelInfo {
+                    channel_id: 3,
+                    group_channel_id: Some(1),
+                    user_identity: "miner".to_string(),
+                    nominal_hashrate: 1_000.0,
+                    target_hex: "00ff".repeat(16),
+                    requested_max_target_hex: "ff".repeat(32),
+                    extranonce_prefix_hex: "0000000000000003".to_string(),
+                    current_job_id: Some(42),
+                    full_extranonce_size: 16,
+                    rollable_extranonce_size: 8,
+                    expected_shares_per_minute: 6.0,
+                    shares_accepted: 0,
+                    share_work_sum: 0.0,
+                    last_share_sequence_number: 0,
+                    best_diff: 0.0,
+                    last_batch_accepted: 0,
+                    last_batch_work_sum: 0.0,
+                    share_batch_size: 10,
+                }],
+                standard_channels: vec![],
+                send_queue_depth: 0,
+                slow_consumer: false,
+                lagged_total: 0,
+            }]
+        }
+    }
+
+    #[tokio::test]
+    async fn channels_lists_open_client_channel() {
+        let server = MonitoringServer::new(
+            "127.0.0.1:0".parse().unwrap(),
+            None,
+            Some(Arc::new(SingleChannelClient)),
+            Duration::from_secs(15),
+        )
+        .unwrap();
+
+        let response = handle_channels(
+            Query(Pagination {
+                offset: 0,
+                limit: None,
+            }),
+            State(server.state.clone()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let channels: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(channels["total"], 1);
+        let channel = &channels["items"][0];
+        assert_eq!(channel["source"], "client");
+        assert_eq!(channel["channel_type"], "extended");
+        assert_eq!(channel["channel_id"], 3);
+        assert_eq!(channel["group_channel_id"], 1);
+        assert_eq!(channel["target_hex"], "00ff".repeat(16));
+        assert_eq!(channel["current_job_id"], 42);
+        assert_eq!(channel["extranonce_prefix_hex"], "0000000000000003");
+        assert_eq!(channel["downstream_ids"], serde_json::json!([7]));
+    }
+
+    struct MeasuredLatencies(std::sync::Mutex<Vec<f64>>);
+
+    impl Sv1ClientsMonitoring for MeasuredLatencies {
+        fn get_sv1_clients(&self) -> Vec<Sv1ClientInfo> {
+            vec![]
+        }
+
+        fn take_job_propagation_latencies(&self) -> Vec<f64> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn job_propagation_latencies_are_observed_once() {
+        let latencies = Arc::new(MeasuredLatencies(std::sync::Mutex::new(vec![0.002, 0.3])));
+        let server = MonitoringServer::new(
+            "127.0.0.1:0".parse().unwrap(),
+            None,
+            None,
+            Duration::from_secs(15),
+        )
+        .unwrap()
+        .with_sv1_monitoring(latencies.clone())
+        .unwrap();
+
+        for _ in 0..2 {
+            let response = handle_prometheus_metrics(State(server.state.clone())).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body = std::str::from_utf8(&body).unwrap();
+            for line in [
+                "sv1_job_propagation_latency_seconds_bucket{le=\"0.001\"} 0",
+                "sv1_job_propagation_latency_seconds_bucket{le=\"0.005\"} 1",
+                "sv1_job_propagation_latency_seconds_bucket{le=\"0.5\"} 2",
+                "sv1_job_propagation_latency_seconds_bucket{le=\"+Inf\"} 2",
+                "sv1_job_propagation_latency_seconds_count 2",
+            ] {
+                assert!(body.lines().any(|l| l == line), "missing {line}");
+            }
+        }
+
+        latencies.0.lock().unwrap().push(1.5);
+        let response = handle_prometheus_metrics(State(server.state.clone())).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body
+            .lines()
+            .any(|l| l == "sv1_job_propagation_latency_seconds_count 3"));
+    }
+
+    struct RefusingFailover;
+
+    impl FailoverControl for RefusingFailover {
+        fn request_failover(&self) -> Result<(), String> {
+            Err("No upstream connected".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn failover_requires_failover_control_and_admin_token() {
+        let server = MonitoringServer::new(
+            "127.0.0.1:0".parse().unwrap(),
+            None,
+            None,
+            Duration::from_secs(15),
+        )
+        .unwrap();
+        let response = handle_failover(State(server.state.clone()), HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let server = server.with_failover_control(Arc::new(RefusingFailover), "secret".to_string());
+        let response = handle_failover(State(server.state.clone()), HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        for token in ["wrong", "secreT", "secret2", "secre"] {
+            let mut headers = HeaderMap::new();
+            headers.insert(AUTHORIZATION, format!("Bearer {token}").parse().unwrap());
+            let response = handle_failover(State(server.state.clone()), headers).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer secret".parse().unwrap());
+        let response = handle_failover(State(server.state.clone()), headers).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["error"], "No upstream connected");
+    }
+
+    #[tokio::test]
+    async fn template_fees_reports_the_latest_template() {
+        let server = MonitoringServer::new(
+            "127.0.0.1:0".parse().unwrap(),
+            None,
+            None,
+            Duration::from_secs(15),
+        )
+        .unwrap();
+        let response = handle_template_fees(State(server.state.clone())).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let template_fees = Arc::new(TemplateFees::new(&BitcoinNetwork::Regtest, 500));
+        let server = server.with_template_fees_monitoring(template_fees.clone());
+        for (template_id, fees) in [(1, 1_000), (2, 30_000)] {
+            // OP_1: block height 1, paying the initial subsidy
+            template_fees.record(template_id, &[0x51], 5_000_000_000 + fees);
+            let response = handle_template_fees(State(server.state.clone())).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(info["template_id"], template_id);
+            assert_eq!(info["total_fees"], fees);
+            assert_eq!(info["fee_threshold"], 500);
+        }
+    }
+
+    #[tokio::test]
+    async fn found_blocks_reports_submitted_and_accepted_blocks() {
+        let server = MonitoringServer::new(
+            "127.0.0.1:0".parse().unwrap(),
+            None,
+            None,
+            Duration::from_secs(15),
+        )
+        .unwrap();
+        let response = handle_found_blocks(State(server.state.clone())).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let found_blocks = Arc::new(FoundBlocks::new());
+        let server = server.with_found_blocks_monitoring(found_blocks.clone());
+        found_blocks.record_submitted("22".repeat(32), Some(3));
+        found_blocks.record_submitted("33".repeat(32), Some(4));
+        found_blocks.record_chain_tip(&[0x33; 32]);
+
+        let response = handle_found_blocks(State(server.state.clone())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info["submitted_total"], 2);
+        assert_eq!(info["accepted_total"], 1);
+        assert_eq!(info["blocks"][0]["status"], "accepted");
+        assert_eq!(info["blocks"][0]["template_id"], 4);
+        assert_eq!(info["blocks"][1]["status"], "submitted");
+    }
+
+    #[tokio::test]
+    async fn coinbase_outputs_report_the_configured_addresses() {
+        let server = MonitoringServer::new(
+            "127.0.0.1:0".parse().unwrap(),
+            None,
+            None,
+            Duration::from_secs(15),
+        )
+        .unwrap();
+        let response = handle_coinbase_outputs(State(server.state.clone())).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let coinbase_reward_script = CoinbaseRewardScript::from_descriptor(
+            "addr(bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4)",
+        )
+        .unwrap();
+        let server = server.with_coinbase_outputs_monitoring(Arc::new(CoinbaseOutputsInfo {
+            outputs: vec![CoinbaseOutputInfo::new(
+                &TxOut {
+                    value: Amount::from_sat(0),
+                    script_pubkey: coinbase_reward_script.script_pubkey(),
+                },
+                coinbase_reward_script.address(None),
+            )],
+        }));
+
+        let response = handle_coinbase_outputs(State(server.state.clone())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            info["outputs"][0]["address"],
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+        );
+        assert_eq!(
+            info["outputs"][0]["script_pubkey"],
+            "0014751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+        assert_eq!(info["outputs"][0]["value"], 0);
+    }
+}
@@ -0,0 +1,8 @@
+//! Exchanged message monitoring types
+
+/// Trait for monitoring the SV2 messages exchanged by an application
+pub trait MessagesMonitoring: Send + Sync {
+    /// Get the number of messages exchanged, labeled by direction (`inbound`/`outbound`) and
+    /// message type
+    fn get_messages_total(&self) -> Vec<(&'static str, &'static str, u64)>;
+}
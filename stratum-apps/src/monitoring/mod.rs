@@ -1,32 +1,58 @@
 //! Monitoring system for SV2 applications.
 //!
 //! Provides HTTP JSON API and Prometheus metrics for monitoring.
-//! Read-only - does not modify any state.
+//! Read-only, except for the admin actions an application opts into.
 //!
 //! ## Architecture
 //!
 //! - **Server**: The upstream connection (pool, JDS) - typically one per app
 //! - **Clients**: Downstream connections (miners) - multiple per app
 //! - **SV1 clients**: Legacy SV1 connections (Translator only)
+//! - **Connections**: Connections admitted and refused by the accept loop (optional)
+//! - **Tasks**: Tasks spawned by the application (optional)
+//! - **Messages**: SV2 messages exchanged, by direction and type (optional)
+//! - **Failover**: On-demand failover to the next upstream (optional admin action)
+//! - **Template fees**: Fees of the latest template from Bitcoin Core IPC (optional)
+//! - **Found blocks**: Blocks submitted to the template provider and their acceptance (optional)
+//! - **Coinbase outputs**: Outputs currently paid in the coinbase (optional)
 
+pub mod admin;
+pub mod blocks;
 pub mod client;
+pub mod coinbase;
+pub mod connections;
 pub mod http_server;
+pub mod messages;
 pub mod prometheus_metrics;
 pub mod server;
 pub mod snapshot_cache;
 pub mod sv1;
+pub mod tasks;
+pub mod template_fees;
 
+pub use admin::FailoverControl;
+pub use blocks::{
+    FoundBlockInfo, FoundBlockStatus, FoundBlocks, FoundBlocksInfo, FoundBlocksMonitoring,
+};
 pub use client::{
     ClientInfo, ClientMetadata, ClientsMonitoring, ClientsSummary, ExtendedChannelInfo,
-    StandardChannelInfo,
+    ShareSequenceViolations, StandardChannelInfo,
 };
+pub use coinbase::{CoinbaseOutputInfo, CoinbaseOutputsInfo, CoinbaseOutputsMonitoring};
+pub use connections::ConnectionsMonitoring;
 pub use http_server::MonitoringServer;
+pub use messages::MessagesMonitoring;
 pub use server::{
-    ServerExtendedChannelInfo, ServerInfo, ServerMonitoring, ServerStandardChannelInfo,
-    ServerSummary,
+    FailoverEvent, FailoverReason, ServerExtendedChannelInfo, ServerInfo, ServerMonitoring,
+    ServerStandardChannelInfo, ServerSummary,
 };
 pub use snapshot_cache::{MonitoringSnapshot, SnapshotCache};
-pub use sv1::{Sv1ClientInfo, Sv1ClientsMonitoring, Sv1ClientsSummary};
+pub use sv1::{
+    LatencyBucket, LatencyHistogram, Sv1ClientInfo, Sv1ClientsMonitoring, Sv1ClientsSummary,
+    Sv1GroupSummary,
+};
+pub use tasks::TasksMonitoring;
+pub use template_fees::{TemplateFees, TemplateFeesInfo, TemplateFeesMonitoring};
 
 use utoipa::ToSchema;
 
@@ -36,4 +62,8 @@ pub struct GlobalInfo {
     pub server: ServerSummary,
     pub clients: ClientsSummary,
     pub uptime_secs: u64,
+    /// Seconds since the snapshot the statistics come from was taken
+    pub snapshot_age_secs: u64,
+    /// Unix timestamp (seconds) at which the snapshot was taken
+    pub generated_at: u64,
 }
@@ -1,6 +1,7 @@
 //! Prometheus metrics definitions for SV2 monitoring
 
-use prometheus::{Gauge, GaugeVec, Opts, Registry};
+use super::sv1::JOB_PROPAGATION_LATENCY_BUCKETS;
+use prometheus::{Gauge, GaugeVec, Histogram, HistogramOpts, Opts, Registry};
 
 /// Prometheus metrics for the monitoring server.
 /// Metrics are optional - only registered when the corresponding monitoring type is enabled.
@@ -9,20 +10,51 @@ pub struct PrometheusMetrics {
     pub registry: Registry,
     // System metrics
     pub sv2_uptime_seconds: Gauge,
-    // Server metrics (upstream connection)
+    pub sv2_monitoring_snapshot_overdue_total: Gauge,
+    // Server metrics (upstream connection), labeled by upstream
+    pub sv2_server_active: Option<GaugeVec>,
     pub sv2_server_channels: Option<GaugeVec>,
-    pub sv2_server_hashrate_total: Option<Gauge>,
+    pub sv2_server_hashrate_total: Option<GaugeVec>,
     pub sv2_server_channel_hashrate: Option<GaugeVec>,
     pub sv2_server_shares_accepted_total: Option<GaugeVec>,
+    pub sv2_server_channel_extranonce_prefix_usage: Option<GaugeVec>,
+    pub sv2_server_pending_declared_jobs: Option<GaugeVec>,
+    pub sv2_server_stale_job_events_total: Option<Gauge>,
     // Clients metrics (downstream connections)
     pub sv2_clients_total: Option<Gauge>,
     pub sv2_client_channels: Option<GaugeVec>,
     pub sv2_client_hashrate_total: Option<Gauge>,
     pub sv2_client_channel_hashrate: Option<GaugeVec>,
     pub sv2_client_shares_accepted_total: Option<GaugeVec>,
+    pub sv2_client_share_sequence_violations_total: Option<GaugeVec>,
+    pub sv2_client_send_queue_depth: Option<GaugeVec>,
+    pub sv2_client_slow_consumer: Option<GaugeVec>,
+    pub sv2_client_open_channels: Option<GaugeVec>,
     // SV1 metrics
     pub sv1_clients_total: Option<Gauge>,
     pub sv1_hashrate_total: Option<Gauge>,
+    pub sv1_group_clients: Option<GaugeVec>,
+    pub sv1_group_hashrate: Option<GaugeVec>,
+    pub sv2_keepalive_time_capped_total: Option<Gauge>,
+    pub sv1_job_propagation_latency_seconds: Option<Histogram>,
+    pub sv1_job_propagation_alarms_total: Option<Gauge>,
+    pub sv1_jobs_dropped_without_prevhash_total: Option<Gauge>,
+    pub sv1_keepalive_shares_orphaned_total: Option<Gauge>,
+    pub sv2_downstream_lagged_total: Option<GaugeVec>,
+    pub sv1_client_send_queue_depth: Option<GaugeVec>,
+    pub sv1_client_slow_consumer: Option<GaugeVec>,
+    pub sv1_client_achieved_hashrate: Option<GaugeVec>,
+    // Connection admission metrics
+    pub sv2_connections_rejected_total: Option<GaugeVec>,
+    pub sv2_downstream_connects_total: Option<Gauge>,
+    pub sv2_downstream_disconnects_total: Option<Gauge>,
+    pub sv2_downstream_churn_per_minute: Option<Gauge>,
+    // Task metrics
+    pub sv2_tasks_active: Option<Gauge>,
+    pub sv2_tasks_spawned_total: Option<Gauge>,
+    pub sv2_tasks_cap_exceeded_total: Option<Gauge>,
+    // Message metrics
+    pub sv2_messages_total: Option<GaugeVec>,
 }
 
 impl PrometheusMetrics {
@@ -30,29 +62,53 @@ impl PrometheusMetrics {
         enable_server_metrics: bool,
         enable_clients_metrics: bool,
         enable_sv1_metrics: bool,
+        enable_connections_metrics: bool,
+        enable_tasks_metrics: bool,
+        enable_messages_metrics: bool,
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         let registry = Registry::new();
 
         // System metrics (always enabled)
         let sv2_uptime_seconds = Gauge::new("sv2_uptime_seconds", "Server uptime in seconds")?;
         registry.register(Box::new(sv2_uptime_seconds.clone()))?;
+        let sv2_monitoring_snapshot_overdue_total = Gauge::new(
+            "sv2_monitoring_snapshot_overdue_total",
+            "Number of times the monitoring snapshot refresh was found overdue",
+        )?;
+        registry.register(Box::new(sv2_monitoring_snapshot_overdue_total.clone()))?;
 
         // Server metrics (upstream connection)
         let (
+            sv2_server_active,
             sv2_server_channels,
             sv2_server_hashrate_total,
             sv2_server_channel_hashrate,
             sv2_server_shares_accepted_total,
+            sv2_server_channel_extranonce_prefix_usage,
+            sv2_server_pending_declared_jobs,
+            sv2_server_stale_job_events_total,
         ) = if enable_server_metrics {
+            let active = GaugeVec::new(
+                Opts::new(
+                    "sv2_server_active",
+                    "Set to 1 for the upstream the server connection is currently established with",
+                ),
+                &["upstream"],
+            )?;
+            registry.register(Box::new(active.clone()))?;
+
             let channels = GaugeVec::new(
                 Opts::new("sv2_server_channels", "Number of server channels by type"),
-                &["channel_type"],
+                &["upstream", "channel_type"],
             )?;
             registry.register(Box::new(channels.clone()))?;
 
-            let hashrate = Gauge::new(
-                "sv2_server_hashrate_total",
-                "Total hashrate for channels opened with the server",
+            let hashrate = GaugeVec::new(
+                Opts::new(
+                    "sv2_server_hashrate_total",
+                    "Total hashrate for channels opened with the server",
+                ),
+                &["upstream"],
             )?;
             registry.register(Box::new(hashrate.clone()))?;
 
@@ -61,7 +117,7 @@ impl PrometheusMetrics {
                     "sv2_server_channel_hashrate",
                     "Hashrate for individual server channels",
                 ),
-                &["channel_id", "user_identity"],
+                &["upstream", "channel_id", "user_identity"],
             )?;
             registry.register(Box::new(channel_hashrate.clone()))?;
 
@@ -70,18 +126,46 @@ impl PrometheusMetrics {
                     "sv2_server_shares_accepted_total",
                     "Total shares accepted per server channel",
                 ),
-                &["channel_id", "user_identity"],
+                &["upstream", "channel_id", "user_identity"],
             )?;
             registry.register(Box::new(shares_accepted.clone()))?;
 
+            let extranonce_prefix_usage = GaugeVec::new(
+                Opts::new(
+                    "sv2_server_channel_extranonce_prefix_usage",
+                    "Fraction of the extranonce prefixes of a server channel allocated to downstream channels",
+                ),
+                &["upstream", "channel_id", "user_identity"],
+            )?;
+            registry.register(Box::new(extranonce_prefix_usage.clone()))?;
+
+            let pending_declared_jobs = GaugeVec::new(
+                Opts::new(
+                    "sv2_server_pending_declared_jobs",
+                    "Number of jobs declared to the server and not acknowledged yet",
+                ),
+                &["upstream"],
+            )?;
+            registry.register(Box::new(pending_declared_jobs.clone()))?;
+
+            let stale_job_events = Gauge::new(
+                "sv2_server_stale_job_events_total",
+                "Number of times a server channel received no new job or prevhash within the job-staleness timeout",
+            )?;
+            registry.register(Box::new(stale_job_events.clone()))?;
+
             (
+                Some(active),
                 Some(channels),
                 Some(hashrate),
                 Some(channel_hashrate),
                 Some(shares_accepted),
+                Some(extranonce_prefix_usage),
+                Some(pending_declared_jobs),
+                Some(stale_job_events),
             )
         } else {
-            (None, None, None, None)
+            (None, None, None, None, None, None, None, None)
         };
 
         // Clients metrics (downstream connections)
@@ -91,6 +175,10 @@ impl PrometheusMetrics {
             sv2_client_hashrate_total,
             sv2_client_channel_hashrate,
             sv2_client_shares_accepted_total,
+            sv2_client_share_sequence_violations_total,
+            sv2_client_send_queue_depth,
+            sv2_client_slow_consumer,
+            sv2_client_open_channels,
         ) = if enable_clients_metrics {
             let clients_total =
                 Gauge::new("sv2_clients_total", "Total number of connected clients")?;
@@ -126,44 +214,312 @@ impl PrometheusMetrics {
             )?;
             registry.register(Box::new(shares_accepted.clone()))?;
 
+            let share_sequence_violations = GaugeVec::new(
+                Opts::new(
+                    "sv2_client_share_sequence_violations_total",
+                    "Total shares submitted by clients out of sequence, by kind",
+                ),
+                &["kind"],
+            )?;
+            registry.register(Box::new(share_sequence_violations.clone()))?;
+
+            let send_queue_depth = GaugeVec::new(
+                Opts::new(
+                    "sv2_client_send_queue_depth",
+                    "Messages waiting to be sent to each client",
+                ),
+                &["client_id"],
+            )?;
+            registry.register(Box::new(send_queue_depth.clone()))?;
+
+            let slow_consumer = GaugeVec::new(
+                Opts::new(
+                    "sv2_client_slow_consumer",
+                    "Whether the send queue of each client stayed above the slow consumer threshold",
+                ),
+                &["client_id"],
+            )?;
+            registry.register(Box::new(slow_consumer.clone()))?;
+
+            let open_channels = GaugeVec::new(
+                Opts::new(
+                    "sv2_client_open_channels",
+                    "Standard and extended channels open with each client",
+                ),
+                &["client_id"],
+            )?;
+            registry.register(Box::new(open_channels.clone()))?;
+
             (
                 Some(clients_total),
                 Some(channels),
                 Some(hashrate),
                 Some(channel_hashrate),
                 Some(shares_accepted),
+                Some(share_sequence_violations),
+                Some(send_queue_depth),
+                Some(slow_consumer),
+                Some(open_channels),
             )
         } else {
-            (None, None, None, None, None)
+            (None, None, None, None, None, None, None, None, None)
         };
 
         // SV1 metrics
-        let (sv1_clients_total, sv1_hashrate_total) = if enable_sv1_metrics {
+        let (
+            sv1_clients_total,
+            sv1_hashrate_total,
+            sv1_group_clients,
+            sv1_group_hashrate,
+            sv2_keepalive_time_capped_total,
+            sv1_job_propagation_latency_seconds,
+            sv1_job_propagation_alarms_total,
+            sv1_jobs_dropped_without_prevhash_total,
+            sv1_keepalive_shares_orphaned_total,
+            sv1_client_send_queue_depth,
+            sv1_client_slow_consumer,
+            sv1_client_achieved_hashrate,
+        ) = if enable_sv1_metrics {
             let clients = Gauge::new("sv1_clients_total", "Total number of SV1 clients")?;
             registry.register(Box::new(clients.clone()))?;
 
             let hashrate = Gauge::new("sv1_hashrate_total", "Total hashrate from SV1 clients")?;
             registry.register(Box::new(hashrate.clone()))?;
 
-            (Some(clients), Some(hashrate))
+            let group_clients = GaugeVec::new(
+                Opts::new("sv1_group_clients", "Number of SV1 clients per group label"),
+                &["group"],
+            )?;
+            registry.register(Box::new(group_clients.clone()))?;
+
+            let group_hashrate = GaugeVec::new(
+                Opts::new(
+                    "sv1_group_hashrate",
+                    "Hashrate of the SV1 clients per group label",
+                ),
+                &["group"],
+            )?;
+            registry.register(Box::new(group_hashrate.clone()))?;
+
+            let keepalive_time_capped = Gauge::new(
+                "sv2_keepalive_time_capped_total",
+                "Total jobs whose keepalive time reached the future block time cap",
+            )?;
+            registry.register(Box::new(keepalive_time_capped.clone()))?;
+
+            let latency = Histogram::with_opts(
+                HistogramOpts::new(
+                    "sv1_job_propagation_latency_seconds",
+                    "Time from receiving a job from upstream until all SV1 clients were sent it",
+                )
+                .buckets(JOB_PROPAGATION_LATENCY_BUCKETS.to_vec()),
+            )?;
+            registry.register(Box::new(latency.clone()))?;
+
+            let alarms = Gauge::new(
+                "sv1_job_propagation_alarms_total",
+                "Jobs whose propagation latency to SV1 clients exceeded the alarm threshold",
+            )?;
+            registry.register(Box::new(alarms.clone()))?;
+
+            let jobs_dropped = Gauge::new(
+                "sv1_jobs_dropped_without_prevhash_total",
+                "Jobs dropped because the prevhash of their channel never arrived",
+            )?;
+            registry.register(Box::new(jobs_dropped.clone()))?;
+
+            let keepalive_shares_orphaned = Gauge::new(
+                "sv1_keepalive_shares_orphaned_total",
+                "Shares for a keepalive job rejected because the job it was derived from was evicted",
+            )?;
+            registry.register(Box::new(keepalive_shares_orphaned.clone()))?;
+
+            let send_queue_depth = GaugeVec::new(
+                Opts::new(
+                    "sv1_client_send_queue_depth",
+                    "Messages waiting to be sent to each SV1 client",
+                ),
+                &["client_id"],
+            )?;
+            registry.register(Box::new(send_queue_depth.clone()))?;
+
+            let slow_consumer = GaugeVec::new(
+                Opts::new(
+                    "sv1_client_slow_consumer",
+                    "Whether the send queue of each SV1 client stayed above the slow consumer threshold",
+                ),
+                &["client_id"],
+            )?;
+            registry.register(Box::new(slow_consumer.clone()))?;
+
+            let achieved_hashrate = GaugeVec::new(
+                Opts::new(
+                    "sv1_client_achieved_hashrate",
+                    "Hashrate of each SV1 client derived from the difficulty achieved by its shares",
+                ),
+                &["client_id"],
+            )?;
+            registry.register(Box::new(achieved_hashrate.clone()))?;
+
+            (
+                Some(clients),
+                Some(hashrate),
+                Some(group_clients),
+                Some(group_hashrate),
+                Some(keepalive_time_capped),
+                Some(latency),
+                Some(alarms),
+                Some(jobs_dropped),
+                Some(keepalive_shares_orphaned),
+                Some(send_queue_depth),
+                Some(slow_consumer),
+                Some(achieved_hashrate),
+            )
+        } else {
+            (
+                None, None, None, None, None, None, None, None, None, None, None, None,
+            )
+        };
+
+        // Lag metrics, shared by SV2 and SV1 clients
+        let sv2_downstream_lagged_total = if enable_clients_metrics || enable_sv1_metrics {
+            let lagged = GaugeVec::new(
+                Opts::new(
+                    "sv2_downstream_lagged_total",
+                    "Times a downstream lagged behind the job broadcast and was re-synced",
+                ),
+                &["downstream_id"],
+            )?;
+            registry.register(Box::new(lagged.clone()))?;
+            Some(lagged)
+        } else {
+            None
+        };
+
+        // Connection admission metrics
+        let (
+            sv2_connections_rejected_total,
+            sv2_downstream_connects_total,
+            sv2_downstream_disconnects_total,
+            sv2_downstream_churn_per_minute,
+        ) = if enable_connections_metrics {
+            let rejected = GaugeVec::new(
+                Opts::new(
+                    "sv2_connections_rejected_total",
+                    "Total connections refused by the accept loop, by reason",
+                ),
+                &["reason"],
+            )?;
+            registry.register(Box::new(rejected.clone()))?;
+
+            let connects = Gauge::new(
+                "sv2_downstream_connects_total",
+                "Total downstream connections admitted by the accept loop",
+            )?;
+            registry.register(Box::new(connects.clone()))?;
+
+            let disconnects = Gauge::new(
+                "sv2_downstream_disconnects_total",
+                "Total admitted downstream connections that were closed",
+            )?;
+            registry.register(Box::new(disconnects.clone()))?;
+
+            let churn = Gauge::new(
+                "sv2_downstream_churn_per_minute",
+                "Downstream connections closed within the last minute",
+            )?;
+            registry.register(Box::new(churn.clone()))?;
+
+            (
+                Some(rejected),
+                Some(connects),
+                Some(disconnects),
+                Some(churn),
+            )
+        } else {
+            (None, None, None, None)
+        };
+
+        // Task metrics
+        let (sv2_tasks_active, sv2_tasks_spawned_total, sv2_tasks_cap_exceeded_total) =
+            if enable_tasks_metrics {
+                let active = Gauge::new("sv2_tasks_active", "Number of tasks currently running")?;
+                registry.register(Box::new(active.clone()))?;
+
+                let spawned = Gauge::new(
+                    "sv2_tasks_spawned_total",
+                    "Total tasks spawned since startup",
+                )?;
+                registry.register(Box::new(spawned.clone()))?;
+
+                let cap_exceeded = Gauge::new(
+                    "sv2_tasks_cap_exceeded_total",
+                    "Total spawns that went above the soft cap on running tasks",
+                )?;
+                registry.register(Box::new(cap_exceeded.clone()))?;
+
+                (Some(active), Some(spawned), Some(cap_exceeded))
+            } else {
+                (None, None, None)
+            };
+
+        // Message metrics
+        let sv2_messages_total = if enable_messages_metrics {
+            let messages = GaugeVec::new(
+                Opts::new(
+                    "sv2_messages_total",
+                    "Total SV2 messages exchanged, by direction and message type",
+                ),
+                &["direction", "type"],
+            )?;
+            registry.register(Box::new(messages.clone()))?;
+            Some(messages)
         } else {
-            (None, None)
+            None
         };
 
         Ok(Self {
             registry,
             sv2_uptime_seconds,
+            sv2_monitoring_snapshot_overdue_total,
+            sv2_server_active,
             sv2_server_channels,
             sv2_server_hashrate_total,
             sv2_server_channel_hashrate,
             sv2_server_shares_accepted_total,
+            sv2_server_channel_extranonce_prefix_usage,
+            sv2_server_pending_declared_jobs,
+            sv2_server_stale_job_events_total,
             sv2_clients_total,
             sv2_client_channels,
             sv2_client_hashrate_total,
             sv2_client_channel_hashrate,
             sv2_client_shares_accepted_total,
+            sv2_client_share_sequence_violations_total,
+            sv2_client_send_queue_depth,
+            sv2_client_slow_consumer,
+            sv2_client_open_channels,
             sv1_clients_total,
             sv1_hashrate_total,
+            sv1_group_clients,
+            sv1_group_hashrate,
+            sv2_keepalive_time_capped_total,
+            sv1_job_propagation_latency_seconds,
+            sv1_job_propagation_alarms_total,
+            sv1_jobs_dropped_without_prevhash_total,
+            sv1_keepalive_shares_orphaned_total,
+            sv2_downstream_lagged_total,
+            sv1_client_send_queue_depth,
+            sv1_client_slow_consumer,
+            sv1_client_achieved_hashrate,
+            sv2_connections_rejected_total,
+            sv2_downstream_connects_total,
+            sv2_downstream_disconnects_total,
+            sv2_downstream_churn_per_minute,
+            sv2_tasks_active,
+            sv2_tasks_spawned_total,
+            sv2_tasks_cap_exceeded_total,
+            sv2_messages_total,
         })
     }
 }
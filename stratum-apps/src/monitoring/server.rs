@@ -3,6 +3,8 @@
 //! These types are for monitoring the **server** (upstream connection).
 //! An app typically has one server connection with one or more channels.
 
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
@@ -10,11 +12,15 @@ use utoipa::ToSchema;
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ServerExtendedChannelInfo {
     pub channel_id: u32,
+    /// Group channel this channel belongs to, if any
+    pub group_channel_id: Option<u32>,
     pub user_identity: String,
     /// None when vardiff is disabled and hashrate cannot be reliably tracked
     pub nominal_hashrate: Option<f32>,
     pub target_hex: String,
     pub extranonce_prefix_hex: String,
+    /// Id of the job the channel is currently mining on
+    pub current_job_id: Option<u32>,
     pub full_extranonce_size: usize,
     pub rollable_extranonce_size: u16,
     pub version_rolling: bool,
@@ -22,28 +28,97 @@ pub struct ServerExtendedChannelInfo {
     pub share_work_sum: f64,
     pub shares_submitted: u32,
     pub best_diff: f64,
+    /// Fraction of the extranonce prefixes derived from this channel for downstream channels
+    /// that are allocated. None when the app does not split the channel
+    pub extranonce_prefix_usage: Option<f64>,
 }
 
 /// Information about a standard channel opened with the server
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ServerStandardChannelInfo {
     pub channel_id: u32,
+    /// Group channel this channel belongs to, if any
+    pub group_channel_id: Option<u32>,
     pub user_identity: String,
     /// None when vardiff is disabled and hashrate cannot be reliably tracked
     pub nominal_hashrate: Option<f32>,
     pub target_hex: String,
     pub extranonce_prefix_hex: String,
+    /// Id of the job the channel is currently mining on
+    pub current_job_id: Option<u32>,
     pub shares_accepted: u32,
     pub share_work_sum: f64,
     pub shares_submitted: u32,
     pub best_diff: f64,
 }
 
+/// Cause of a fallback from the server to the next configured upstream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FailoverReason {
+    /// The server rejected the connection setup
+    SetupConnectionError,
+    /// The server rejected the opening of a channel
+    OpenMiningChannelError,
+    /// The server closed a channel the app relies on
+    CloseChannel,
+    /// The server stopped answering or sending work in time
+    Timeout,
+    /// The connection with the server was lost
+    ConnectionLost,
+    /// An operator requested the failover through the monitoring server
+    Manual,
+    /// The server redirected the app to another endpoint with `Reconnect`
+    Reconnect,
+    /// The connection reached its configured maximum lifetime and was rotated to the next upstream
+    Rotation,
+    /// Any other error on the server connection
+    Other,
+}
+
+/// Most recent fallback from the server to the next configured upstream
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FailoverEvent {
+    pub reason: FailoverReason,
+    /// Description of the error that triggered the fallback
+    pub detail: String,
+    /// Label of the upstream that was left, if it was connected
+    pub upstream: Option<String>,
+    /// Unix timestamp (seconds) of the fallback
+    pub timestamp: u64,
+}
+
+impl FailoverEvent {
+    /// Records a fallback from `upstream` happening now.
+    pub fn new(reason: FailoverReason, detail: String, upstream: Option<String>) -> Self {
+        Self {
+            reason,
+            detail,
+            upstream,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        }
+    }
+}
+
 /// Information about the server (upstream connection)
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ServerInfo {
+    /// Label of the upstream currently connected (configured name or address)
+    pub upstream: Option<String>,
     pub extended_channels: Vec<ServerExtendedChannelInfo>,
     pub standard_channels: Vec<ServerStandardChannelInfo>,
+    /// Most recent fallback to another upstream, if any happened
+    pub last_failover: Option<FailoverEvent>,
+    /// Jobs declared to the upstream and not acknowledged yet (JD Client only)
+    #[serde(default)]
+    pub pending_declared_jobs: Option<usize>,
+    /// Times a server channel went without a new job or prevhash for the job-staleness timeout
+    /// (Translator only)
+    #[serde(default)]
+    pub stale_job_events: Option<u64>,
 }
 
 impl ServerInfo {
@@ -69,10 +144,14 @@ impl ServerInfo {
 /// Aggregate information about the server connection
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ServerSummary {
+    /// Label of the upstream currently connected (configured name or address)
+    pub upstream: Option<String>,
     pub total_channels: usize,
     pub extended_channels: usize,
     pub standard_channels: usize,
     pub total_hashrate: f32,
+    /// Most recent fallback to another upstream, if any happened
+    pub last_failover: Option<FailoverEvent>,
 }
 
 /// Trait for monitoring the server (upstream connection)
@@ -85,10 +164,12 @@ pub trait ServerMonitoring: Send + Sync {
         let server = self.get_server();
 
         ServerSummary {
+            upstream: server.upstream.clone(),
             total_channels: server.total_channels(),
             extended_channels: server.extended_channels.len(),
             standard_channels: server.standard_channels.len(),
             total_hashrate: server.total_hashrate(),
+            last_failover: server.last_failover,
         }
     }
 }
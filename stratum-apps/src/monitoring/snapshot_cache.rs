@@ -36,12 +36,21 @@
 //!              └───────────┘       └───────────┘       └───────────┘
 //! ```
 
-use std::sync::{Arc, RwLock};
-use std::time::{Duration, Instant};
-
-use super::client::{ClientInfo, ClientsMonitoring, ClientsSummary};
-use super::server::{ServerInfo, ServerMonitoring, ServerSummary};
-use super::sv1::{Sv1ClientInfo, Sv1ClientsMonitoring, Sv1ClientsSummary};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{debug, warn};
+
+use super::{
+    client::{ClientInfo, ClientsMonitoring, ClientsSummary},
+    server::{ServerInfo, ServerMonitoring, ServerSummary},
+    sv1::{Sv1ClientInfo, Sv1ClientsMonitoring, Sv1ClientsSummary},
+};
 
 /// Cached snapshot of monitoring data.
 ///
@@ -50,6 +59,8 @@ use super::sv1::{Sv1ClientInfo, Sv1ClientsMonitoring, Sv1ClientsSummary};
 #[derive(Debug, Clone, Default)]
 pub struct MonitoringSnapshot {
     pub timestamp: Option<Instant>,
+    /// Unix timestamp (seconds) of the refresh that produced this snapshot
+    pub generated_at: Option<u64>,
     pub server_info: Option<ServerInfo>,
     pub server_summary: Option<ServerSummary>,
     pub clients: Option<Vec<ClientInfo>>,
@@ -71,12 +82,21 @@ impl MonitoringSnapshot {
     pub fn age(&self) -> Option<Duration> {
         self.timestamp.map(|ts| ts.elapsed())
     }
+
+    /// Get the age of this snapshot in whole seconds
+    pub fn age_secs(&self) -> Option<u64> {
+        self.age().map(|age| age.as_secs())
+    }
 }
 
 /// A cache that holds monitoring snapshots and refreshes them periodically.
 pub struct SnapshotCache {
     snapshot: RwLock<MonitoringSnapshot>,
-    refresh_interval: Duration,
+    refresh_interval_ms: AtomicU64,
+    // Age in milliseconds past which reading the snapshot rebuilds it, 0 when disabled
+    on_demand_refresh_after_ms: AtomicU64,
+    on_demand_refreshing: AtomicBool,
+    overdue_refreshes: AtomicU64,
     server_source: Option<Arc<dyn ServerMonitoring + Send + Sync>>,
     sv2_clients_source: Option<Arc<dyn ClientsMonitoring + Send + Sync>>,
     sv1_clients_source: Option<Arc<dyn Sv1ClientsMonitoring + Send + Sync>>,
@@ -88,7 +108,12 @@ impl Clone for SnapshotCache {
         let current_snapshot = self.snapshot.read().unwrap().clone();
         Self {
             snapshot: RwLock::new(current_snapshot),
-            refresh_interval: self.refresh_interval,
+            refresh_interval_ms: AtomicU64::new(self.refresh_interval_ms.load(Ordering::Relaxed)),
+            on_demand_refresh_after_ms: AtomicU64::new(
+                self.on_demand_refresh_after_ms.load(Ordering::Relaxed),
+            ),
+            on_demand_refreshing: AtomicBool::new(false),
+            overdue_refreshes: AtomicU64::new(self.overdue_refreshes.load(Ordering::Relaxed)),
             server_source: self.server_source.clone(),
             sv2_clients_source: self.sv2_clients_source.clone(),
             sv1_clients_source: self.sv1_clients_source.clone(),
@@ -111,7 +136,10 @@ impl SnapshotCache {
     ) -> Self {
         Self {
             snapshot: RwLock::new(MonitoringSnapshot::default()),
-            refresh_interval,
+            refresh_interval_ms: AtomicU64::new(refresh_interval.as_millis() as u64),
+            on_demand_refresh_after_ms: AtomicU64::new(0),
+            on_demand_refreshing: AtomicBool::new(false),
+            overdue_refreshes: AtomicU64::new(0),
             server_source,
             sv2_clients_source: clients_source,
             sv1_clients_source: None,
@@ -130,11 +158,67 @@ impl SnapshotCache {
     /// Get the current snapshot.
     ///
     /// This is a fast read that does NOT acquire any business logic locks.
-    /// The returned snapshot may be up to `refresh_interval` old.
+    /// The returned snapshot may be up to `refresh_interval` old, unless on-demand refresh is
+    /// enabled and the snapshot is older than its bound: it is then rebuilt first.
     pub fn get_snapshot(&self) -> MonitoringSnapshot {
+        if let Some(max_staleness) = self.on_demand_refresh_after() {
+            if self.snapshot.read().unwrap().is_stale(max_staleness) {
+                self.refresh_on_demand();
+            }
+        }
         self.snapshot.read().unwrap().clone()
     }
 
+    // Rebuilds a stale snapshot for a request. Only one request rebuilds it at a time, the
+    // others keep reading the stale snapshot meanwhile.
+    fn refresh_on_demand(&self) {
+        if self
+            .on_demand_refreshing
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+        {
+            debug!("Rebuilding stale monitoring snapshot on demand");
+            self.refresh();
+            self.on_demand_refreshing.store(false, Ordering::Release);
+        }
+    }
+
+    /// Get the age of the current snapshot without cloning it
+    pub fn snapshot_age(&self) -> Option<Duration> {
+        self.snapshot.read().unwrap().age()
+    }
+
+    /// Check if the current snapshot is older than twice the refresh interval,
+    /// i.e. the refresh loop missed at least one refresh.
+    pub fn is_stale(&self) -> bool {
+        self.snapshot
+            .read()
+            .unwrap()
+            .is_stale(self.refresh_interval() * 2)
+    }
+
+    /// Check whether the periodic refresh is overdue, i.e. the snapshot is stale.
+    ///
+    /// Called periodically by the watchdog of the monitoring server. Every overdue check is
+    /// logged and counted in [`Self::overdue_refreshes`].
+    pub fn check_overdue_refresh(&self) -> bool {
+        if !self.is_stale() {
+            return false;
+        }
+        self.overdue_refreshes.fetch_add(1, Ordering::Relaxed);
+        warn!(
+            snapshot_age = ?self.snapshot_age(),
+            refresh_interval = ?self.refresh_interval(),
+            "Monitoring snapshot refresh is overdue"
+        );
+        true
+    }
+
+    /// Number of times the watchdog found the periodic refresh overdue
+    pub fn overdue_refreshes(&self) -> u64 {
+        self.overdue_refreshes.load(Ordering::Relaxed)
+    }
+
     /// Refresh the cache by reading from the data sources.
     ///
     /// This method DOES acquire the business logic locks (via the trait methods),
@@ -142,6 +226,12 @@ impl SnapshotCache {
     pub fn refresh(&self) {
         let mut new_snapshot = MonitoringSnapshot {
             timestamp: Some(Instant::now()),
+            generated_at: Some(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            ),
             ..Default::default()
         };
 
@@ -169,7 +259,31 @@ impl SnapshotCache {
 
     /// Get the refresh interval
     pub fn refresh_interval(&self) -> Duration {
-        self.refresh_interval
+        Duration::from_millis(self.refresh_interval_ms.load(Ordering::Relaxed))
+    }
+
+    /// Change the refresh interval, taking effect from the next refresh on
+    pub fn set_refresh_interval(&self, refresh_interval: Duration) {
+        self.refresh_interval_ms
+            .store(refresh_interval.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Get the age past which reading the snapshot rebuilds it, if on-demand refresh is enabled
+    pub fn on_demand_refresh_after(&self) -> Option<Duration> {
+        match self.on_demand_refresh_after_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// Rebuild the snapshot when a request finds it older than `max_staleness`, or never with
+    /// `None`.
+    ///
+    /// Rebuilding acquires the business logic locks from the request, so keep `max_staleness`
+    /// well above the refresh interval.
+    pub fn set_on_demand_refresh(&self, max_staleness: Option<Duration>) {
+        let ms = max_staleness.map_or(0, |max_staleness| max_staleness.as_millis().max(1) as u64);
+        self.on_demand_refresh_after_ms.store(ms, Ordering::Relaxed);
     }
 }
 
@@ -181,8 +295,12 @@ mod tests {
     impl ServerMonitoring for MockServerMonitoring {
         fn get_server(&self) -> ServerInfo {
             ServerInfo {
+                upstream: None,
                 extended_channels: vec![],
                 standard_channels: vec![],
+                last_failover: None,
+                pending_declared_jobs: None,
+                stale_job_events: None,
             }
         }
     }
@@ -206,6 +324,9 @@ mod tests {
         let snapshot = cache.get_snapshot();
         assert!(snapshot.timestamp.is_none());
         assert_eq!(cache.refresh_interval(), Duration::from_secs(5));
+
+        cache.set_refresh_interval(Duration::from_secs(30));
+        assert_eq!(cache.refresh_interval(), Duration::from_secs(30));
     }
 
     #[test]
@@ -229,6 +350,105 @@ mod tests {
         assert!(snapshot.server_info.is_some());
         assert!(snapshot.clients.is_some());
         assert!(snapshot.clients_summary.is_some());
+        assert!(snapshot.generated_at.is_some());
+    }
+
+    #[test]
+    fn test_snapshot_age_stays_bounded_while_refreshing() {
+        let refresh_interval = Duration::from_millis(100);
+        let cache = Arc::new(SnapshotCache::new(
+            refresh_interval,
+            Some(Arc::new(MockServerMonitoring)),
+            Some(Arc::new(MockClientsMonitoring)),
+        ));
+        cache.refresh();
+
+        // Refresh loop, as run by the monitoring server
+        let refresher = Arc::clone(&cache);
+        let running = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let refresher_running = Arc::clone(&running);
+        let refresh_handle = std::thread::spawn(move || {
+            while refresher_running.load(std::sync::atomic::Ordering::SeqCst) {
+                std::thread::sleep(refresh_interval);
+                refresher.refresh();
+            }
+        });
+
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(1) {
+            let age = cache.get_snapshot().age().unwrap();
+            assert!(
+                age <= refresh_interval * 2,
+                "Snapshot age {:?} exceeded twice the refresh interval",
+                age
+            );
+            assert!(!cache.is_stale());
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        // Once the refresh loop stops, the snapshot turns stale
+        running.store(false, std::sync::atomic::Ordering::SeqCst);
+        refresh_handle.join().unwrap();
+        std::thread::sleep(refresh_interval * 3);
+        assert!(cache.is_stale());
+    }
+
+    /// Server monitoring whose refresh blocks while the business logic holds its lock.
+    struct StalledMonitoring {
+        business_lock: std::sync::Mutex<()>,
+    }
+
+    impl ServerMonitoring for StalledMonitoring {
+        fn get_server(&self) -> ServerInfo {
+            let _guard = self.business_lock.lock().unwrap();
+            MockServerMonitoring.get_server()
+        }
+    }
+
+    #[test]
+    fn test_watchdog_fires_on_stalled_refresh() {
+        let refresh_interval = Duration::from_millis(50);
+        let monitoring = Arc::new(StalledMonitoring {
+            business_lock: std::sync::Mutex::new(()),
+        });
+        let cache = Arc::new(SnapshotCache::new(
+            refresh_interval,
+            Some(monitoring.clone() as Arc<dyn ServerMonitoring + Send + Sync>),
+            None,
+        ));
+        cache.refresh();
+        assert!(!cache.check_overdue_refresh());
+
+        // The business logic holds its lock, so the next refresh is stuck
+        let guard = monitoring.business_lock.lock().unwrap();
+        let refresher = Arc::clone(&cache);
+        let refresh_handle = std::thread::spawn(move || refresher.refresh());
+        std::thread::sleep(refresh_interval * 3);
+
+        assert!(cache.check_overdue_refresh());
+        assert!(cache.check_overdue_refresh());
+        assert_eq!(cache.overdue_refreshes(), 2);
+
+        drop(guard);
+        refresh_handle.join().unwrap();
+        assert!(!cache.check_overdue_refresh());
+        assert_eq!(cache.overdue_refreshes(), 2);
+    }
+
+    #[test]
+    fn test_stale_snapshot_rebuilt_on_demand() {
+        let refresh_interval = Duration::from_millis(50);
+        let cache =
+            SnapshotCache::new(refresh_interval, Some(Arc::new(MockServerMonitoring)), None);
+        cache.refresh();
+        std::thread::sleep(refresh_interval * 3);
+
+        // Disabled by default, the stale snapshot is served as is
+        assert!(cache.get_snapshot().age().unwrap() > refresh_interval * 2);
+
+        cache.set_on_demand_refresh(Some(refresh_interval * 2));
+        assert!(cache.get_snapshot().age().unwrap() < refresh_interval);
+        assert!(!cache.is_stale());
     }
 
     /// Mock monitoring that simulates lock contention with business logic.
@@ -280,8 +500,12 @@ mod tests {
             // Minimal sleep to simulate lock acquisition overhead
             std::thread::sleep(Duration::from_micros(10));
             ServerInfo {
+                upstream: None,
                 extended_channels: vec![],
                 standard_channels: vec![],
+                last_failover: None,
+                pending_declared_jobs: None,
+                stale_job_events: None,
             }
         }
     }
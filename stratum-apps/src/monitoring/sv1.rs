@@ -4,6 +4,7 @@
 //! Used by Translator Proxy (tProxy) that accepts SV1 miner connections.
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use utoipa::ToSchema;
 
 /// Information about a single SV1 client connection
@@ -13,12 +14,83 @@ pub struct Sv1ClientInfo {
     pub channel_id: Option<u32>,
     pub authorized_worker_name: String,
     pub user_identity: String,
+    /// Label grouping this client with others in monitoring, derived from its worker name
+    #[serde(default)]
+    pub group_label: Option<String>,
     pub target_hex: String,
+    /// Hashrate derived from the target assigned to this client
     pub hashrate: Option<f32>,
+    /// Moving average of the difficulty achieved by the accepted shares of this client
+    #[serde(default)]
+    pub achieved_difficulty: Option<f64>,
+    /// Hashrate derived from the achieved difficulty and the rate of accepted shares, which stays
+    /// accurate when the client submits shares above its target
+    #[serde(default)]
+    pub achieved_hashrate: Option<f32>,
     pub extranonce1_hex: String,
     pub extranonce2_len: usize,
     pub version_rolling_mask: Option<String>,
     pub version_rolling_min_bit: Option<String>,
+    /// Times this client lagged behind the job broadcast and was re-synced to the latest job
+    #[serde(default)]
+    pub lagged_total: u64,
+    /// Messages waiting to be sent to this client
+    #[serde(default)]
+    pub send_queue_depth: usize,
+    /// Whether the send queue of this client stayed above the slow consumer threshold
+    #[serde(default)]
+    pub slow_consumer: bool,
+}
+
+/// Upper bounds, in seconds, of the job propagation latency histogram buckets
+pub const JOB_PROPAGATION_LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Cumulative number of observations at or below `le` seconds
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LatencyBucket {
+    pub le: f64,
+    pub count: u64,
+}
+
+/// Latency histogram in seconds, with cumulative buckets as exposed by Prometheus
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct LatencyHistogram {
+    pub buckets: Vec<LatencyBucket>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl LatencyHistogram {
+    /// Create an empty histogram with the given bucket upper bounds, in seconds
+    pub fn new(bounds: &[f64]) -> Self {
+        Self {
+            buckets: bounds
+                .iter()
+                .map(|&le| LatencyBucket { le, count: 0 })
+                .collect(),
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    /// Record a single observation, in seconds
+    pub fn observe(&mut self, seconds: f64) {
+        for bucket in self.buckets.iter_mut().filter(|b| seconds <= b.le) {
+            bucket.count += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// Aggregate information about the SV1 clients sharing a group label
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Sv1GroupSummary {
+    pub group_label: String,
+    pub total_clients: usize,
+    pub total_hashrate: f32,
 }
 
 /// Aggregate information about SV1 client connections
@@ -26,6 +98,25 @@ pub struct Sv1ClientInfo {
 pub struct Sv1ClientsSummary {
     pub total_clients: usize,
     pub total_hashrate: f32,
+    /// Number of jobs whose keepalive time reached the future block time cap
+    #[serde(default)]
+    pub keepalive_time_capped_total: u64,
+    /// Time from receiving a job from upstream until all SV1 clients were sent its `mining.notify`
+    #[serde(default)]
+    pub job_propagation_latency: LatencyHistogram,
+    /// Number of jobs whose propagation latency exceeded the configured alarm threshold
+    #[serde(default)]
+    pub job_propagation_alarms_total: u64,
+    /// Number of jobs dropped because the prevhash of their channel never arrived
+    #[serde(default)]
+    pub jobs_dropped_without_prevhash_total: u64,
+    /// Number of shares for a keepalive job rejected because the job it was derived from was no
+    /// longer valid
+    #[serde(default)]
+    pub keepalive_shares_orphaned_total: u64,
+    /// Clients and hashrate per group label, sorted by label. Empty when no client has a label.
+    #[serde(default)]
+    pub groups: Vec<Sv1GroupSummary>,
 }
 
 /// Trait for monitoring SV1 client connections
@@ -43,13 +134,77 @@ pub trait Sv1ClientsMonitoring: Send + Sync {
             .find(|c| c.client_id == client_id)
     }
 
+    /// Get the number of keepalive jobs that could not be sent because the job time reached
+    /// the future block time cap
+    ///
+    /// Default implementation returns 0, for implementations that don't send keepalive jobs.
+    fn get_keepalive_time_capped_total(&self) -> u64 {
+        0
+    }
+
+    /// Get the histogram of job propagation latencies to SV1 clients
+    ///
+    /// Default implementation returns an empty histogram, for implementations that don't track it.
+    fn get_job_propagation_latency(&self) -> LatencyHistogram {
+        LatencyHistogram::default()
+    }
+
+    /// Take the job propagation latencies to SV1 clients measured since the last call, in seconds
+    ///
+    /// Called on every Prometheus scrape, which observes each of them into the latency histogram.
+    /// Default implementation returns none, for implementations that don't track them.
+    fn take_job_propagation_latencies(&self) -> Vec<f64> {
+        Vec::new()
+    }
+
+    /// Get the number of jobs whose propagation latency exceeded the alarm threshold
+    ///
+    /// Default implementation returns 0, for implementations that don't track it.
+    fn get_job_propagation_alarms_total(&self) -> u64 {
+        0
+    }
+
+    /// Get the number of jobs dropped because the prevhash of their channel never arrived
+    ///
+    /// Default implementation returns 0, for implementations that don't hold such jobs.
+    fn get_jobs_dropped_without_prevhash_total(&self) -> u64 {
+        0
+    }
+
+    /// Get the number of shares for a keepalive job rejected because the job it was derived from
+    /// was no longer valid
+    ///
+    /// Default implementation returns 0, for implementations that don't send keepalive jobs.
+    fn get_keepalive_shares_orphaned_total(&self) -> u64 {
+        0
+    }
+
     /// Get summary of SV1 clients
     fn get_sv1_clients_summary(&self) -> Sv1ClientsSummary {
         let clients = self.get_sv1_clients();
 
+        let mut groups: BTreeMap<&str, Sv1GroupSummary> = BTreeMap::new();
+        for client in &clients {
+            if let Some(label) = client.group_label.as_deref() {
+                let group = groups.entry(label).or_insert_with(|| Sv1GroupSummary {
+                    group_label: label.to_string(),
+                    total_clients: 0,
+                    total_hashrate: 0.0,
+                });
+                group.total_clients += 1;
+                group.total_hashrate += client.hashrate.unwrap_or(0.0);
+            }
+        }
+
         Sv1ClientsSummary {
             total_clients: clients.len(),
             total_hashrate: clients.iter().filter_map(|c| c.hashrate).sum(),
+            keepalive_time_capped_total: self.get_keepalive_time_capped_total(),
+            job_propagation_latency: self.get_job_propagation_latency(),
+            job_propagation_alarms_total: self.get_job_propagation_alarms_total(),
+            jobs_dropped_without_prevhash_total: self.get_jobs_dropped_without_prevhash_total(),
+            keepalive_shares_orphaned_total: self.get_keepalive_shares_orphaned_total(),
+            groups: groups.into_values().collect(),
         }
     }
 }
@@ -0,0 +1,13 @@
+//! Spawned task monitoring types
+
+/// Trait for monitoring the tasks spawned by an application
+pub trait TasksMonitoring: Send + Sync {
+    /// Get the number of tasks currently running
+    fn get_tasks_active(&self) -> u64;
+
+    /// Get the number of tasks spawned since startup
+    fn get_tasks_spawned(&self) -> u64;
+
+    /// Get the number of spawns that went above the soft cap on running tasks
+    fn get_tasks_cap_exceeded(&self) -> u64;
+}
@@ -0,0 +1,158 @@
+//! Template fee monitoring types
+//!
+//! With the Bitcoin Core IPC template provider, a new template is sent whenever the fees of the
+//! mempool grow by more than the configured `fee_threshold`. Reporting the fees of the latest
+//! template next to that threshold lets operators correlate job churn with mempool conditions.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{custom_mutex::Mutex, tp_type::BitcoinNetwork};
+
+const OP_1: u8 = 0x51;
+const OP_16: u8 = 0x60;
+const INITIAL_SUBSIDY_SATS: u64 = 50 * 100_000_000;
+
+/// Fees of the latest template received from the template provider
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TemplateFeesInfo {
+    /// Fee increase (satoshis) of the mempool above which a new template is sent
+    pub fee_threshold: u64,
+    /// Id of the latest template, None before the first one
+    pub template_id: Option<u64>,
+    /// Value (satoshis) the coinbase outputs of the latest template may pay: subsidy plus fees
+    pub coinbase_tx_value_remaining: Option<u64>,
+    /// Fees (satoshis) of the transactions of the latest template. None when the block height
+    /// cannot be read from its coinbase prefix
+    pub total_fees: Option<u64>,
+    /// Unix timestamp (seconds) at which the latest template arrived
+    pub updated_at: Option<u64>,
+}
+
+/// Trait for monitoring the fees of the templates received from the template provider
+pub trait TemplateFeesMonitoring: Send + Sync {
+    /// Get the fees of the latest template
+    fn get_template_fees(&self) -> TemplateFeesInfo;
+}
+
+/// Fees of the latest template, updated by the app as templates arrive
+#[derive(Debug)]
+pub struct TemplateFees {
+    halving_interval: u64,
+    latest: Mutex<TemplateFeesInfo>,
+}
+
+impl TemplateFees {
+    /// Creates the record of a template provider on `network` configured with `fee_threshold`.
+    pub fn new(network: &BitcoinNetwork, fee_threshold: u64) -> Self {
+        let halving_interval = match network {
+            BitcoinNetwork::Regtest => 150,
+            _ => 210_000,
+        };
+        Self {
+            halving_interval,
+            latest: Mutex::new(TemplateFeesInfo {
+                fee_threshold,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Records a new template. Its fees are what `coinbase_tx_value_remaining` leaves once the
+    /// subsidy of the block height, read from `coinbase_prefix`, is deducted.
+    pub fn record(
+        &self,
+        template_id: u64,
+        coinbase_prefix: &[u8],
+        coinbase_tx_value_remaining: u64,
+    ) {
+        let total_fees = block_height(coinbase_prefix)
+            .map(|height| coinbase_tx_value_remaining.saturating_sub(self.block_subsidy(height)));
+        let updated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.latest.super_safe_lock(|latest| {
+            latest.template_id = Some(template_id);
+            latest.coinbase_tx_value_remaining = Some(coinbase_tx_value_remaining);
+            latest.total_fees = total_fees;
+            latest.updated_at = Some(updated_at);
+        });
+    }
+
+    fn block_subsidy(&self, height: u64) -> u64 {
+        INITIAL_SUBSIDY_SATS
+            .checked_shr((height / self.halving_interval) as u32)
+            .unwrap_or(0)
+    }
+}
+
+impl TemplateFeesMonitoring for TemplateFees {
+    fn get_template_fees(&self) -> TemplateFeesInfo {
+        self.latest.super_safe_lock(|latest| latest.clone())
+    }
+}
+
+// The coinbase prefix of a template starts with the BIP34 push of the block height.
+fn block_height(coinbase_prefix: &[u8]) -> Option<u64> {
+    match coinbase_prefix.split_first()? {
+        (&op, _) if (OP_1..=OP_16).contains(&op) => Some(u64::from(op - OP_1 + 1)),
+        (&len @ 1..=8, rest) => Some(
+            rest.get(..len as usize)?
+                .iter()
+                .rev()
+                .fold(0, |height, byte| (height << 8) | u64::from(*byte)),
+        ),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reported_fees_track_the_latest_template() {
+        let template_fees = TemplateFees::new(&BitcoinNetwork::Mainnet, 1_000);
+        assert_eq!(
+            template_fees.get_template_fees(),
+            TemplateFeesInfo {
+                fee_threshold: 1_000,
+                ..Default::default()
+            }
+        );
+
+        // height 840_000 (0x0cd140) is in the fifth subsidy era, paying 3.125 BTC
+        let coinbase_prefix = [0x03, 0x40, 0xd1, 0x0c, 0x00];
+        for (template_id, fees) in [(1, 25_000), (2, 1_250_000), (3, 400)] {
+            template_fees.record(template_id, &coinbase_prefix, 312_500_000 + fees);
+            let info = template_fees.get_template_fees();
+            assert_eq!(info.template_id, Some(template_id));
+            assert_eq!(info.total_fees, Some(fees));
+            assert_eq!(info.coinbase_tx_value_remaining, Some(312_500_000 + fees));
+            assert_eq!(info.fee_threshold, 1_000);
+            assert!(info.updated_at.is_some());
+        }
+
+        // without a height, only the coinbase value is known
+        template_fees.record(4, &[], 312_500_000);
+        let info = template_fees.get_template_fees();
+        assert_eq!(info.template_id, Some(4));
+        assert_eq!(info.total_fees, None);
+    }
+
+    #[test]
+    fn regtest_subsidy_halves_every_150_blocks() {
+        let template_fees = TemplateFees::new(&BitcoinNetwork::Regtest, 0);
+        // OP_16, then the push of 151 with its sign byte
+        for (coinbase_prefix, subsidy) in [
+            (vec![0x60], 5_000_000_000),
+            (vec![0x02, 0x97, 0x00], 2_500_000_000),
+        ] {
+            template_fees.record(1, &coinbase_prefix, subsidy + 7);
+            assert_eq!(template_fees.get_template_fees().total_fees, Some(7));
+        }
+    }
+}
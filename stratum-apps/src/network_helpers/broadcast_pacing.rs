@@ -0,0 +1,124 @@
+//! Spreading of the messages broadcast to many downstreams over a short window.
+//!
+//! A new job is sent to every downstream at once, which on servers with a large number of
+//! downstreams shows up as a CPU spike and a long tail of latency for the last ones served. With a
+//! [`BroadcastPacing`], the messages of a broadcast are sent in batches spread evenly over at most
+//! `max_spread`, so no downstream gets the job later than `max_spread` after the first one.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// How the messages of a broadcast are split into batches and spread over time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BroadcastPacing {
+    /// Number of messages sent back to back before pausing.
+    pub batch_size: usize,
+    /// Longest delay between the first and the last batch of a broadcast. With no delay, the
+    /// broadcast only yields to the runtime between batches.
+    pub max_spread: Duration,
+}
+
+/// Paces the messages of a single broadcast.
+#[derive(Debug)]
+pub struct BroadcastPacer {
+    pacing: Option<BroadcastPacing>,
+    start: Instant,
+    batches: usize,
+    sent: usize,
+}
+
+impl BroadcastPacer {
+    /// Starts pacing a broadcast of `messages` messages. Without `pacing`, or with a batch size of
+    /// `0`, every message is sent right away.
+    pub fn new(pacing: Option<BroadcastPacing>, messages: usize) -> Self {
+        let pacing = pacing.filter(|pacing| pacing.batch_size > 0);
+        Self {
+            pacing,
+            start: Instant::now(),
+            batches: pacing.map_or(1, |pacing| messages.div_ceil(pacing.batch_size)),
+            sent: 0,
+        }
+    }
+
+    /// Waits until the next message is due. Called before sending each message.
+    pub async fn pace(&mut self) {
+        let sent = self.sent;
+        self.sent += 1;
+        let Some(pacing) = self.pacing else {
+            return;
+        };
+        if sent == 0 || sent % pacing.batch_size != 0 {
+            return;
+        }
+        let batch = sent / pacing.batch_size;
+        if pacing.max_spread.is_zero() || batch >= self.batches {
+            tokio::task::yield_now().await;
+            return;
+        }
+        // the last batch is due `max_spread` after the first one
+        let due = self.start + pacing.max_spread * batch as u32 / (self.batches - 1) as u32;
+        tokio::time::sleep_until(due).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    // Timers fire on a millisecond granularity, and the test runtime may be busy.
+    const TIMER_SLACK: Duration = Duration::from_millis(30);
+
+    #[tokio::test]
+    async fn every_downstream_gets_the_job_within_max_spread() {
+        let pacing = BroadcastPacing {
+            batch_size: 100,
+            max_spread: Duration::from_millis(45),
+        };
+        let downstreams: Vec<_> = (0..1000).map(|_| mpsc::unbounded_channel()).collect();
+
+        let start = Instant::now();
+        let mut pacer = BroadcastPacer::new(Some(pacing), downstreams.len());
+        let mut received = Vec::new();
+        for (sender, mut receiver) in downstreams {
+            pacer.pace().await;
+            sender.send("job").unwrap();
+            received.push(tokio::spawn(async move {
+                receiver.recv().await.unwrap();
+                start.elapsed()
+            }));
+        }
+
+        let mut latest = Duration::ZERO;
+        for delay in received {
+            latest = latest.max(delay.await.unwrap());
+        }
+        // the last batch is sent once `max_spread` has elapsed, and not much later
+        assert!(latest >= pacing.max_spread);
+        assert!(latest <= pacing.max_spread + TIMER_SLACK);
+    }
+
+    #[tokio::test]
+    async fn broadcast_without_pacing_is_not_delayed() {
+        let start = Instant::now();
+        for pacing in [
+            None,
+            Some(BroadcastPacing {
+                batch_size: 0,
+                max_spread: Duration::from_secs(1),
+            }),
+            Some(BroadcastPacing {
+                batch_size: 10,
+                max_spread: Duration::ZERO,
+            }),
+        ] {
+            let mut pacer = BroadcastPacer::new(pacing, 1000);
+            for _ in 0..1000 {
+                pacer.pace().await;
+            }
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
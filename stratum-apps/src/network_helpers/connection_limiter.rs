@@ -0,0 +1,370 @@
+//! Admission control for accept loops.
+//!
+//! [`ConnectionLimiter`] caps the number of concurrent connections per source IP and throttles
+//! the global accept rate with a token bucket, so a single host flooding a listener cannot
+//! exhaust the resources of the application. A connection admitted by the limiter holds a
+//! [`ConnectionPermit`] for its whole lifetime; dropping the permit frees its per-IP slot.
+//!
+//! The limiter also counts the connections admitted and closed, and the disconnects of the last
+//! minute. Miners flapping between connected and disconnected waste the resources of the
+//! channel setup on every cycle, so a churn above the configured rate is logged as a warning.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+use crate::custom_mutex::Mutex;
+
+// Period over which the churn is measured, and the warning about it repeated at most once.
+const CHURN_WINDOW: Duration = Duration::from_secs(60);
+
+/// Reason a connection was refused by a [`ConnectionLimiter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The source IP already holds the maximum number of connections.
+    PerIpLimit,
+    /// The global accept rate was exceeded.
+    RateLimit,
+}
+
+impl RejectReason {
+    /// Returns the reason as reported in the `reason` metric label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::PerIpLimit => "per_ip_limit",
+            RejectReason::RateLimit => "rate_limit",
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::PerIpLimit => write!(f, "too many connections from this IP"),
+            RejectReason::RateLimit => write!(f, "accept rate limit exceeded"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    connections_per_ip: HashMap<IpAddr, u32>,
+    accept_tokens: f64,
+    last_refill: Instant,
+    recent_disconnects: VecDeque<Instant>,
+    churn_warned_at: Option<Instant>,
+}
+
+/// Per-IP connection cap and global accept-rate limiter shared by an accept loop.
+///
+/// Both limits are disabled when set to `0`.
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    max_connections_per_ip: u32,
+    max_accepts_per_sec: u32,
+    churn_warning_per_minute: u32,
+    state: Mutex<LimiterState>,
+    rejected_per_ip_limit: AtomicU64,
+    rejected_rate_limit: AtomicU64,
+    connects: AtomicU64,
+    disconnects: AtomicU64,
+}
+
+impl ConnectionLimiter {
+    /// Creates a limiter allowing `max_connections_per_ip` concurrent connections per source IP
+    /// and `max_accepts_per_sec` new connections per second, with bursts of up to one second
+    /// worth of connections.
+    pub fn new(max_connections_per_ip: u32, max_accepts_per_sec: u32) -> Self {
+        Self {
+            max_connections_per_ip,
+            max_accepts_per_sec,
+            churn_warning_per_minute: 0,
+            state: Mutex::new(LimiterState {
+                connections_per_ip: HashMap::new(),
+                accept_tokens: max_accepts_per_sec as f64,
+                last_refill: Instant::now(),
+                recent_disconnects: VecDeque::new(),
+                churn_warned_at: None,
+            }),
+            rejected_per_ip_limit: AtomicU64::new(0),
+            rejected_rate_limit: AtomicU64::new(0),
+            connects: AtomicU64::new(0),
+            disconnects: AtomicU64::new(0),
+        }
+    }
+
+    /// Logs a warning when more than `churn_warning_per_minute` connections close within a
+    /// minute. Disabled when set to `0`.
+    pub fn with_churn_warning(mut self, churn_warning_per_minute: u32) -> Self {
+        self.churn_warning_per_minute = churn_warning_per_minute;
+        self
+    }
+
+    /// Admits a new connection from `ip`, returning the permit to hold while it is open.
+    ///
+    /// The per-IP cap is checked first, so a host over its cap does not drain the accept rate
+    /// shared with everyone else.
+    pub fn try_acquire(self: &Arc<Self>, ip: IpAddr) -> Result<ConnectionPermit, RejectReason> {
+        let result = self.state.super_safe_lock(|state| {
+            let connections = state.connections_per_ip.get(&ip).copied().unwrap_or(0);
+            if self.max_connections_per_ip > 0 && connections >= self.max_connections_per_ip {
+                return Err(RejectReason::PerIpLimit);
+            }
+
+            if self.max_accepts_per_sec > 0 {
+                let now = Instant::now();
+                let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+                let capacity = self.max_accepts_per_sec as f64;
+                state.accept_tokens = (state.accept_tokens + elapsed * capacity).min(capacity);
+                state.last_refill = now;
+                if state.accept_tokens < 1.0 {
+                    return Err(RejectReason::RateLimit);
+                }
+                state.accept_tokens -= 1.0;
+            }
+
+            state.connections_per_ip.insert(ip, connections + 1);
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => {
+                self.connects.fetch_add(1, Ordering::Relaxed);
+                Ok(ConnectionPermit {
+                    limiter: self.clone(),
+                    ip,
+                })
+            }
+            Err(reason) => {
+                match reason {
+                    RejectReason::PerIpLimit => &self.rejected_per_ip_limit,
+                    RejectReason::RateLimit => &self.rejected_rate_limit,
+                }
+                .fetch_add(1, Ordering::Relaxed);
+                Err(reason)
+            }
+        }
+    }
+
+    /// Returns the number of connections currently open from `ip`.
+    pub fn connections(&self, ip: IpAddr) -> u32 {
+        self.state
+            .super_safe_lock(|state| state.connections_per_ip.get(&ip).copied().unwrap_or(0))
+    }
+
+    /// Returns the number of rejected connections for each [`RejectReason`].
+    pub fn rejected_total(&self) -> Vec<(RejectReason, u64)> {
+        vec![
+            (
+                RejectReason::PerIpLimit,
+                self.rejected_per_ip_limit.load(Ordering::Relaxed),
+            ),
+            (
+                RejectReason::RateLimit,
+                self.rejected_rate_limit.load(Ordering::Relaxed),
+            ),
+        ]
+    }
+
+    /// Returns the number of connections admitted since startup.
+    pub fn connects_total(&self) -> u64 {
+        self.connects.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of admitted connections closed since startup.
+    pub fn disconnects_total(&self) -> u64 {
+        self.disconnects.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of connections closed within the last minute.
+    pub fn churn_per_minute(&self) -> u64 {
+        let now = Instant::now();
+        self.state.super_safe_lock(|state| {
+            prune_disconnects(&mut state.recent_disconnects, now);
+            state.recent_disconnects.len() as u64
+        })
+    }
+
+    fn release(&self, ip: IpAddr) {
+        self.release_at(ip, Instant::now());
+    }
+
+    // Frees the slot of a connection from `ip` closed at `now`. Returns whether the churn
+    // warning was logged.
+    fn release_at(&self, ip: IpAddr, now: Instant) -> bool {
+        self.disconnects.fetch_add(1, Ordering::Relaxed);
+        let churn = self.state.super_safe_lock(|state| {
+            if let Some(connections) = state.connections_per_ip.get_mut(&ip) {
+                *connections -= 1;
+                if *connections == 0 {
+                    state.connections_per_ip.remove(&ip);
+                }
+            }
+
+            state.recent_disconnects.push_back(now);
+            prune_disconnects(&mut state.recent_disconnects, now);
+            let churn = state.recent_disconnects.len() as u64;
+            let threshold = u64::from(self.churn_warning_per_minute);
+            let warned_recently = state
+                .churn_warned_at
+                .is_some_and(|warned_at| now.duration_since(warned_at) < CHURN_WINDOW);
+            if threshold == 0 || churn <= threshold || warned_recently {
+                return None;
+            }
+            state.churn_warned_at = Some(now);
+            Some(churn)
+        });
+
+        match churn {
+            Some(churn) => {
+                warn!(
+                    "{churn} downstream connections closed within the last minute, above the \
+                     warning rate of {}: miners may be flapping",
+                    self.churn_warning_per_minute
+                );
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn prune_disconnects(recent_disconnects: &mut VecDeque<Instant>, now: Instant) {
+    while recent_disconnects
+        .front()
+        .is_some_and(|closed_at| now.duration_since(*closed_at) >= CHURN_WINDOW)
+    {
+        recent_disconnects.pop_front();
+    }
+}
+
+#[cfg(feature = "monitoring")]
+impl crate::monitoring::ConnectionsMonitoring for ConnectionLimiter {
+    fn get_connections_rejected(&self) -> Vec<(&'static str, u64)> {
+        self.rejected_total()
+            .into_iter()
+            .map(|(reason, total)| (reason.as_str(), total))
+            .collect()
+    }
+
+    fn get_connects_total(&self) -> u64 {
+        self.connects_total()
+    }
+
+    fn get_disconnects_total(&self) -> u64 {
+        self.disconnects_total()
+    }
+
+    fn get_churn_per_minute(&self) -> u64 {
+        self.churn_per_minute()
+    }
+}
+
+/// Slot held by an admitted connection, released when dropped.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn rejects_connections_beyond_the_per_ip_cap() {
+        let limiter = Arc::new(ConnectionLimiter::new(5, 0));
+        let flooder = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+
+        let mut permits = Vec::new();
+        for _ in 0..50 {
+            if let Ok(permit) = limiter.try_acquire(flooder) {
+                permits.push(permit);
+            }
+        }
+        assert_eq!(permits.len(), 5);
+        assert_eq!(limiter.connections(flooder), 5);
+        assert_eq!(
+            limiter.rejected_total(),
+            vec![(RejectReason::PerIpLimit, 45), (RejectReason::RateLimit, 0)]
+        );
+
+        // Other sources are unaffected by the flood
+        let other = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 8));
+        assert!(limiter.try_acquire(other).is_ok());
+
+        // Closing a connection frees its slot
+        permits.pop();
+        assert_eq!(limiter.connections(flooder), 4);
+        assert!(limiter.try_acquire(flooder).is_ok());
+    }
+
+    #[test]
+    fn throttles_accept_bursts() {
+        let limiter = Arc::new(ConnectionLimiter::new(0, 10));
+
+        let mut permits = Vec::new();
+        let mut rejected = 0;
+        for i in 0..30u8 {
+            match limiter.try_acquire(IpAddr::V4(Ipv4Addr::new(198, 51, 100, i))) {
+                Ok(permit) => permits.push(permit),
+                Err(reason) => {
+                    assert_eq!(reason, RejectReason::RateLimit);
+                    rejected += 1;
+                }
+            }
+        }
+        // The burst capacity is one second worth of accepts; refills during the loop are
+        // negligible
+        assert!(permits.len() >= 10 && permits.len() < 12);
+        assert_eq!(rejected, 30 - permits.len());
+    }
+
+    #[test]
+    fn flapping_connections_are_counted_and_warned_about() {
+        let limiter = Arc::new(ConnectionLimiter::new(0, 0).with_churn_warning(10));
+        let miner = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let start = Instant::now();
+
+        let mut warnings = 0;
+        for i in 0..25 {
+            // the disconnect is replayed at a chosen time instead of when the permit drops
+            std::mem::forget(limiter.try_acquire(miner).unwrap());
+            if limiter.release_at(miner, start + Duration::from_millis(i * 100)) {
+                warnings += 1;
+            }
+        }
+        assert_eq!(limiter.connects_total(), 25);
+        assert_eq!(limiter.disconnects_total(), 25);
+        assert_eq!(limiter.connections(miner), 0);
+        // the warning fired on the 11th disconnect, then stayed quiet for the rest of the minute
+        assert_eq!(warnings, 1);
+
+        // churn of the last minute only: the flapping above is over by then
+        let later = start + Duration::from_secs(120);
+        std::mem::forget(limiter.try_acquire(miner).unwrap());
+        assert!(!limiter.release_at(miner, later));
+        assert_eq!(
+            limiter
+                .state
+                .super_safe_lock(|state| state.recent_disconnects.len()),
+            1
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! Logging of the raw SV2 frames exchanged on a connection, for troubleshooting interop issues.
+//!
+//! Applications enabling it log, at trace level, the message type and payload hex of every frame
+//! read from or written to a connection. Payloads are truncated to [`FRAME_TRACE_MAX_BYTES`], and
+//! the payloads of messages carrying user identities or job tokens are not logged at all.
+
+use std::fmt::Write as _;
+
+use stratum_core::{
+    job_declaration_sv2::{
+        MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN, MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN_SUCCESS,
+        MESSAGE_TYPE_DECLARE_MINING_JOB,
+    },
+    mining_sv2::{
+        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL, MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL,
+        MESSAGE_TYPE_SET_CUSTOM_MINING_JOB,
+    },
+};
+use tracing::{enabled, trace, Level};
+
+use crate::utils::{
+    protocol_message_type::{
+        is_common_message, is_extensions_message, is_job_declaration_message, is_mining_message,
+        is_template_distribution_message,
+    },
+    types::Sv2Frame,
+};
+
+/// Largest number of payload bytes logged per frame.
+pub const FRAME_TRACE_MAX_BYTES: usize = 256;
+
+// The most significant bit of the extension type flags channel messages.
+const CHANNEL_MSG_BIT: u16 = 0x8000;
+
+#[derive(Debug, Clone, Copy)]
+enum FrameDirection {
+    Inbound,
+    Outbound,
+}
+
+impl FrameDirection {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FrameDirection::Inbound => "inbound",
+            FrameDirection::Outbound => "outbound",
+        }
+    }
+}
+
+/// Logs a frame read from the connection.
+pub fn trace_inbound_frame(frame: &mut Sv2Frame) {
+    if enabled!(Level::TRACE) {
+        log_frame(FrameDirection::Inbound, frame);
+    }
+}
+
+/// Logs a frame about to be written to the connection.
+///
+/// Frames built from a message are only serialized when written, so the frame is serialized here
+/// to log its payload and returned in its serialized form. Returns `None` if it cannot be
+/// serialized.
+pub fn trace_outbound_frame(frame: Sv2Frame) -> Option<Sv2Frame> {
+    if !enabled!(Level::TRACE) {
+        return Some(frame);
+    }
+    let mut bytes = vec![0; frame.encoded_length()];
+    frame.serialize(&mut bytes).ok()?;
+    let mut frame = Sv2Frame::from_bytes(bytes.into()).ok()?;
+    log_frame(FrameDirection::Outbound, &mut frame);
+    Some(frame)
+}
+
+fn log_frame(direction: FrameDirection, frame: &mut Sv2Frame) {
+    let Some(header) = frame.get_header() else {
+        trace!(direction = %direction.as_str(), "SV2 frame without header");
+        return;
+    };
+    let extension_type = header.ext_type() & !CHANNEL_MSG_BIT;
+    let msg_type = header.msg_type();
+    let payload = if is_redacted(extension_type, msg_type) {
+        "<redacted>".to_string()
+    } else {
+        payload_hex(frame.payload())
+    };
+    trace!(
+        direction = %direction.as_str(),
+        protocol = %protocol_name(extension_type, msg_type),
+        extension_type,
+        msg_type = %format_args!("{msg_type:#04x}"),
+        len = header.len(),
+        payload = %payload,
+        "SV2 frame"
+    );
+}
+
+// Messages whose payload carries a user identity or a mining job token.
+fn is_redacted(extension_type: u16, msg_type: u8) -> bool {
+    extension_type == 0
+        && matches!(
+            msg_type,
+            MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL
+                | MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL
+                | MESSAGE_TYPE_SET_CUSTOM_MINING_JOB
+                | MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN
+                | MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN_SUCCESS
+                | MESSAGE_TYPE_DECLARE_MINING_JOB
+        )
+}
+
+fn protocol_name(extension_type: u16, msg_type: u8) -> &'static str {
+    if is_common_message(extension_type, msg_type) {
+        "common"
+    } else if is_mining_message(extension_type, msg_type) {
+        "mining"
+    } else if is_job_declaration_message(extension_type, msg_type) {
+        "job_declaration"
+    } else if is_template_distribution_message(extension_type, msg_type) {
+        "template_distribution"
+    } else if is_extensions_message(extension_type, msg_type) {
+        "extensions"
+    } else {
+        "unknown"
+    }
+}
+
+fn payload_hex(payload: &[u8]) -> String {
+    let shown = &payload[..payload.len().min(FRAME_TRACE_MAX_BYTES)];
+    let mut hex = String::with_capacity(shown.len() * 2);
+    for byte in shown {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    if payload.len() > shown.len() {
+        let _ = write!(hex, "... ({} more bytes)", payload.len() - shown.len());
+    }
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use stratum_core::{
+        common_messages_sv2::SetupConnectionSuccess,
+        parsers_sv2::{AnyMessage, CommonMessages},
+    };
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame_bytes(msg_type: u8, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0, 0, msg_type];
+        bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes()[..3]);
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn frames_are_logged_at_trace_level() {
+        let capture = Capture::default();
+        let writer = capture.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(Level::TRACE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let success = AnyMessage::Common(CommonMessages::SetupConnectionSuccess(
+                SetupConnectionSuccess {
+                    used_version: 2,
+                    flags: 0,
+                },
+            ));
+            let frame: Sv2Frame = success.try_into().unwrap();
+            assert!(trace_outbound_frame(frame).is_some());
+
+            let payload = vec![0xab; FRAME_TRACE_MAX_BYTES + 10];
+            let mut frame = Sv2Frame::from_bytes(frame_bytes(0x01, &payload).into()).unwrap();
+            trace_inbound_frame(&mut frame);
+
+            let mut frame = Sv2Frame::from_bytes(
+                frame_bytes(MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL, b"secret").into(),
+            )
+            .unwrap();
+            trace_inbound_frame(&mut frame);
+        });
+
+        let logs = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = logs.lines().collect();
+        assert_eq!(lines.len(), 3);
+
+        assert!(lines[0].contains("direction=outbound"));
+        assert!(lines[0].contains("protocol=common"));
+        assert!(lines[0].contains("payload=020000000000"));
+
+        assert!(lines[1].contains("direction=inbound"));
+        assert!(lines[1].contains(&"ab".repeat(FRAME_TRACE_MAX_BYTES)));
+        assert!(lines[1].contains("... (10 more bytes)"));
+
+        assert!(lines[2].contains("protocol=mining"));
+        assert!(lines[2].contains("<redacted>"));
+        // "secret" in hex
+        assert!(!lines[2].contains("736563726574"));
+    }
+}
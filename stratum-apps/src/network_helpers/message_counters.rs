@@ -0,0 +1,155 @@
+//! Counts of the SV2 messages exchanged by an application, by direction and message type.
+//!
+//! Applications share one [`MessageCounters`] between the I/O tasks of all their connections,
+//! which count every frame read or written with a single atomic increment. The counts are
+//! exported as `sv2_messages_total{direction,type}` by the monitoring server.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use stratum_core::extensions_sv2::EXTENSION_TYPE_EXTENSIONS_NEGOTIATION;
+
+use crate::utils::{protocol_message_type::message_type_name, types::Sv2Frame};
+
+// The most significant bit of the extension type flags channel messages.
+const CHANNEL_MSG_BIT: u16 = 0x8000;
+
+// One slot per message type of the core protocols, one per message type of the extensions
+// negotiation, and a last one for the messages of other extensions.
+const SLOTS: usize = 2 * 256 + 1;
+
+/// Number of SV2 messages received and sent, by message type.
+#[derive(Debug)]
+pub struct MessageCounters {
+    inbound: Box<[AtomicU64]>,
+    outbound: Box<[AtomicU64]>,
+}
+
+impl Default for MessageCounters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MessageCounters {
+    /// Creates counters starting at zero.
+    pub fn new() -> Self {
+        Self {
+            inbound: (0..SLOTS).map(|_| AtomicU64::new(0)).collect(),
+            outbound: (0..SLOTS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Counts a frame read from a connection.
+    pub fn count_inbound(&self, frame: &Sv2Frame) {
+        Self::count(&self.inbound, frame);
+    }
+
+    /// Counts a frame written to a connection.
+    pub fn count_outbound(&self, frame: &Sv2Frame) {
+        Self::count(&self.outbound, frame);
+    }
+
+    /// Returns the number of messages per direction (`inbound`/`outbound`) and message type,
+    /// leaving out the message types never exchanged.
+    pub fn totals(&self) -> Vec<(&'static str, &'static str, u64)> {
+        let mut totals = Vec::new();
+        for (direction, counters) in [("inbound", &self.inbound), ("outbound", &self.outbound)] {
+            let start = totals.len();
+            for (slot, counter) in counters.iter().enumerate() {
+                let total = counter.load(Ordering::Relaxed);
+                if total == 0 {
+                    continue;
+                }
+                let name = slot_name(slot);
+                // several slots map to `unknown`
+                match totals[start..]
+                    .iter_mut()
+                    .find(|(_, message_type, _)| *message_type == name)
+                {
+                    Some((_, _, sum)) => *sum += total,
+                    None => totals.push((direction, name, total)),
+                }
+            }
+        }
+        totals
+    }
+
+    fn count(counters: &[AtomicU64], frame: &Sv2Frame) {
+        if let Some(header) = frame.get_header() {
+            counters[slot(header.ext_type(), header.msg_type())].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn slot(extension_type: u16, message_type: u8) -> usize {
+    match extension_type & !CHANNEL_MSG_BIT {
+        0 => message_type as usize,
+        EXTENSION_TYPE_EXTENSIONS_NEGOTIATION => 256 + message_type as usize,
+        _ => SLOTS - 1,
+    }
+}
+
+fn slot_name(slot: usize) -> &'static str {
+    match slot {
+        0..=255 => message_type_name(0, slot as u8),
+        256..=511 => message_type_name(EXTENSION_TYPE_EXTENSIONS_NEGOTIATION, (slot - 256) as u8),
+        _ => "unknown",
+    }
+}
+
+#[cfg(feature = "monitoring")]
+impl crate::monitoring::MessagesMonitoring for MessageCounters {
+    fn get_messages_total(&self) -> Vec<(&'static str, &'static str, u64)> {
+        self.totals()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stratum_core::{
+        common_messages_sv2::{Protocol, SetupConnection, SetupConnectionSuccess},
+        parsers_sv2::{AnyMessage, CommonMessages},
+    };
+
+    use super::*;
+
+    fn frame(message: CommonMessages<'static>) -> Sv2Frame {
+        AnyMessage::Common(message).try_into().unwrap()
+    }
+
+    #[test]
+    fn setup_exchange_is_counted_by_direction_and_type() {
+        let counters = MessageCounters::new();
+        let setup_connection = SetupConnection {
+            protocol: Protocol::MiningProtocol,
+            min_version: 2,
+            max_version: 2,
+            flags: 0,
+            endpoint_host: b"0.0.0.0".to_vec().try_into().unwrap(),
+            endpoint_port: 0,
+            vendor: b"vendor".to_vec().try_into().unwrap(),
+            hardware_version: b"hw".to_vec().try_into().unwrap(),
+            firmware: b"fw".to_vec().try_into().unwrap(),
+            device_id: b"device".to_vec().try_into().unwrap(),
+        };
+
+        counters.count_outbound(&frame(CommonMessages::SetupConnection(setup_connection)));
+        for _ in 0..2 {
+            let success = SetupConnectionSuccess {
+                used_version: 2,
+                flags: 0,
+            };
+            counters.count_inbound(&frame(CommonMessages::SetupConnectionSuccess(success)));
+        }
+
+        let mut totals = counters.totals();
+        totals.sort();
+        assert_eq!(
+            totals,
+            vec![
+                ("inbound", "SetupConnectionSuccess", 2),
+                ("outbound", "SetupConnection", 1),
+            ]
+        );
+    }
+}
@@ -5,11 +5,23 @@
 //!
 //! - Noise-encrypted connections ([`noise_connection`], [`noise_stream`])
 //! - SV1 protocol connections ([`sv1_connection`]) - when `sv1` feature is enabled
+//! - Per-IP and accept-rate connection limits ([`connection_limiter`])
+//! - Detection of downstreams falling behind their outbound queue ([`slow_consumer`])
+//! - Spreading of the messages broadcast to many downstreams over time ([`broadcast_pacing`])
+//! - Trace logging of the raw frames exchanged on a connection ([`frame_trace`])
+//! - Counts of the messages exchanged, by direction and message type ([`message_counters`])
+//! - Validation of the endpoints upstreams redirect their clients to ([`reconnect`])
 //!
 //! Originally from the `network_helpers_sv2` crate.
 
+pub mod broadcast_pacing;
+pub mod connection_limiter;
+pub mod frame_trace;
+pub mod message_counters;
 pub mod noise_connection;
 pub mod noise_stream;
+pub mod reconnect;
+pub mod slow_consumer;
 
 #[cfg(feature = "sv1")]
 pub mod sv1_connection;
@@ -31,6 +43,8 @@ pub enum Error {
     SendError,
     /// Socket was closed, likely by the peer
     SocketClosed,
+    /// The peer announced a frame larger than the configured maximum frame size
+    FrameTooLarge { size: usize, max: usize },
 }
 
 impl fmt::Display for Error {
@@ -47,6 +61,12 @@ impl fmt::Display for Error {
             Error::SendError => write!(f, "Error sending to async channel"),
 
             Error::SocketClosed => write!(f, "Socket was closed (likely by the peer)"),
+
+            Error::FrameTooLarge { size, max } => write!(
+                f,
+                "Frame of {} bytes exceeds the maximum frame size of {} bytes",
+                size, max
+            ),
         }
     }
 }
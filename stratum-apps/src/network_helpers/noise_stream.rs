@@ -26,6 +26,10 @@ use stratum_core::{
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tracing::{debug, error};
 
+/// Maximum frame size applications use unless configured otherwise: large enough for the
+/// transaction data of a full block, small enough to bound what a peer can make us allocate.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 8 * 1024 * 1024;
+
 /// A Noise-secured duplex stream over TCP that wraps a `TcpStream`
 /// and provides secure read/write capabilities using the Noise protocol.
 ///
@@ -51,6 +55,7 @@ pub struct NoiseTcpReadHalf<Message: Serialize + Deserialize<'static> + GetSize
     state: State,
     current_frame_buf: Vec<u8>,
     bytes_read: usize,
+    max_frame_size: usize,
 }
 
 /// The writing half of a `NoiseTcpStream`.
@@ -150,6 +155,7 @@ where
                 state: state.clone(),
                 current_frame_buf: vec![],
                 bytes_read: 0,
+                max_frame_size: usize::MAX,
             },
             writer: NoiseTcpWriteHalf {
                 writer,
@@ -215,6 +221,15 @@ impl<Message> NoiseTcpReadHalf<Message>
 where
     Message: Serialize + Deserialize<'static> + GetSize + Send + 'static,
 {
+    /// Sets the largest encrypted frame payload, in bytes, the peer may announce.
+    ///
+    /// Once the header of a larger frame is decoded, reads fail with [`Error::FrameTooLarge`]
+    /// before any buffer is allocated for its payload. Unlimited by default, besides the 24-bit
+    /// length of SV2 frames.
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
     /// Reads and decodes a complete frame from the socket.
     ///
     /// This method blocks until a full frame is read and decoded,
@@ -224,6 +239,7 @@ where
     pub async fn read_frame(&mut self) -> Result<StandardEitherFrame<Message>, Error> {
         loop {
             let expected = self.decoder.writable_len();
+            self.check_frame_size(expected)?;
 
             if self.current_frame_buf.len() != expected {
                 self.current_frame_buf.resize(expected, 0);
@@ -269,6 +285,7 @@ where
     /// - `Err(_)` on socket or decoding errors.
     pub fn try_read_frame(&mut self) -> Result<Option<StandardEitherFrame<Message>>, Error> {
         let expected = self.decoder.writable_len();
+        self.check_frame_size(expected)?;
 
         if self.current_frame_buf.len() != expected {
             self.current_frame_buf.resize(expected, 0);
@@ -301,6 +318,18 @@ where
             Err(e) => Err(Error::CodecError(e)),
         }
     }
+
+    // The decoder only asks for the payload of a frame once its header was decoded, so this
+    // rejects a frame from its announced length alone.
+    fn check_frame_size(&self, expected: usize) -> Result<(), Error> {
+        if expected > self.max_frame_size {
+            return Err(Error::FrameTooLarge {
+                size: expected,
+                max: self.max_frame_size,
+            });
+        }
+        Ok(())
+    }
 }
 
 async fn send_message<Message: Serialize + Deserialize<'static> + GetSize + Send + 'static>(
@@ -330,3 +359,86 @@ async fn receive_message<Message: Serialize + Deserialize<'static> + GetSize + S
     decoder.writable().copy_from_slice(&buffer);
     decoder.next_frame(state).map_err(Error::CodecError)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
+        utils::types::{Message, Sv2Frame},
+    };
+    use std::time::Duration;
+    use stratum_core::{
+        binary_sv2::{Seq064K, B016M},
+        noise_sv2::{Initiator, Responder},
+        parsers_sv2::{AnyMessage, TemplateDistribution},
+        template_distribution_sv2::{
+            RequestTransactionDataSuccess, MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_SUCCESS,
+        },
+    };
+    use tokio::net::TcpListener;
+
+    async fn connected_pair() -> (NoiseTcpStream<Message>, NoiseTcpStream<Message>) {
+        let pub_key = "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72"
+            .parse::<Secp256k1PublicKey>()
+            .unwrap()
+            .into_bytes();
+        let prv_key = "mkDLTBBRxdBv998612qipDYoTK3YUrqLe8uWw7gu3iXbSrn2n"
+            .parse::<Secp256k1SecretKey>()
+            .unwrap()
+            .into_bytes();
+        let responder =
+            Responder::from_authority_kp(&pub_key, &prv_key, Duration::from_secs(3600)).unwrap();
+        let initiator = Initiator::from_raw_k(pub_key).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::join!(
+            async {
+                let stream = TcpStream::connect(addr).await.unwrap();
+                NoiseTcpStream::new(stream, HandshakeRole::Initiator(initiator))
+                    .await
+                    .unwrap()
+            },
+            async {
+                let (stream, _) = listener.accept().await.unwrap();
+                NoiseTcpStream::new(stream, HandshakeRole::Responder(responder))
+                    .await
+                    .unwrap()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn oversized_frame_is_rejected_before_reading_its_payload() {
+        let (client, server) = connected_pair().await;
+        let (_, mut writer) = client.into_split();
+        let (mut reader, _) = server.into_split();
+        reader.set_max_frame_size(1024);
+
+        let message = AnyMessage::TemplateDistribution(
+            TemplateDistribution::RequestTransactionDataSuccess(RequestTransactionDataSuccess {
+                template_id: 1,
+                excess_data: Vec::new().try_into().unwrap(),
+                transaction_list: Seq064K::new(vec![B016M::try_from(vec![0u8; 100_000]).unwrap()])
+                    .unwrap(),
+            }),
+        );
+        let frame = Sv2Frame::from_message(
+            message,
+            MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_SUCCESS,
+            0,
+            false,
+        )
+        .unwrap();
+        // the payload is never read, so writing it may not complete
+        tokio::spawn(async move { writer.write_frame(frame.into()).await });
+
+        let error = reader.read_frame().await.unwrap_err();
+        assert!(
+            matches!(error, Error::FrameTooLarge { size, max: 1024 } if size > 100_000),
+            "unexpected error: {error}"
+        );
+        assert!(reader.current_frame_buf.len() <= 1024);
+    }
+}
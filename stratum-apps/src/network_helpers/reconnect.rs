@@ -0,0 +1,165 @@
+//! Validation of the endpoints upstreams redirect their clients to with `Reconnect`.
+//!
+//! An upstream sends `Reconnect` to move its clients to another endpoint, e.g. before a
+//! maintenance. Following it blindly would let an upstream send the client, and the work of the
+//! miners behind it, anywhere, so the new endpoint is only followed when the allowlist configured
+//! for the upstream lists it.
+
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+};
+
+use stratum_core::common_messages_sv2::Reconnect;
+
+/// Reason the endpoint of a `Reconnect` is not followed.
+#[derive(Debug)]
+pub enum ReconnectError {
+    /// The endpoint (`host:port`) is not in the allowlist of the upstream.
+    NotAllowed(String),
+    /// The host of the endpoint (`host:port`) could not be resolved.
+    Resolve(String, std::io::Error),
+}
+
+impl fmt::Display for ReconnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReconnectError::NotAllowed(endpoint) => {
+                write!(f, "reconnect endpoint {endpoint} is not allowed")
+            }
+            ReconnectError::Resolve(endpoint, e) => {
+                write!(f, "failed to resolve reconnect endpoint {endpoint}: {e}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReconnectError {}
+
+/// Returns the address `reconnect` redirects the connection to `current` to, if `allowlist`
+/// allows it.
+///
+/// An empty `new_host` keeps the host of `current` and a `new_port` of `0` keeps its port.
+/// Entries of `allowlist` are either `host:port` endpoints, or a `host` alone allowing any port
+/// of it, IPv6 addresses being written `[ip]:port` and `ip` or `[ip]`. IP addresses are compared
+/// as addresses, not as text. Reconnecting to `current` itself is always allowed.
+pub async fn resolve_reconnect(
+    reconnect: &Reconnect<'_>,
+    current: SocketAddr,
+    allowlist: &[String],
+) -> Result<SocketAddr, ReconnectError> {
+    let host = match reconnect.new_host.as_utf8_or_hex() {
+        host if host.is_empty() => current.ip().to_string(),
+        host => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+    };
+    let port = match reconnect.new_port {
+        0 => current.port(),
+        port => port,
+    };
+    let ip = host.parse::<IpAddr>().ok();
+    let endpoint = match ip {
+        Some(ip) => SocketAddr::new(ip, port).to_string(),
+        None => format!("{host}:{port}"),
+    };
+    let is_current = ip == Some(current.ip()) && port == current.port();
+    let allowed = allowlist
+        .iter()
+        .any(|entry| allowlist_entry_matches(entry, &host, ip, port));
+    // hosts that are not allowed are not even resolved
+    if !is_current && !allowed {
+        return Err(ReconnectError::NotAllowed(endpoint));
+    }
+    tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .map_err(|e| ReconnectError::Resolve(endpoint.clone(), e))?
+        .next()
+        .ok_or_else(|| ReconnectError::Resolve(endpoint, std::io::ErrorKind::NotFound.into()))
+}
+
+// Whether the allowlist `entry` allows the endpoint `host`:`port`, `ip` being the address `host`
+// spells, if it is one.
+fn allowlist_entry_matches(entry: &str, host: &str, ip: Option<IpAddr>, port: u16) -> bool {
+    if let Some(ip) = ip {
+        if let Ok(allowed) = entry.parse::<SocketAddr>() {
+            return allowed == SocketAddr::new(ip, port);
+        }
+        let entry_ip = entry.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(allowed) = entry_ip.parse::<IpAddr>() {
+            return allowed == ip;
+        }
+    }
+    entry == format!("{host}:{port}") || entry == host
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reconnect(new_host: &str, new_port: u16) -> Reconnect<'static> {
+        Reconnect {
+            new_host: new_host.to_string().try_into().unwrap(),
+            new_port,
+        }
+    }
+
+    #[tokio::test]
+    async fn only_allowed_endpoints_are_followed() {
+        let current: SocketAddr = "10.0.0.1:3333".parse().unwrap();
+        let allowlist = vec!["10.0.0.2:3334".to_string(), "10.0.0.3".to_string()];
+
+        let addr = resolve_reconnect(&reconnect("10.0.0.2", 3334), current, &allowlist)
+            .await
+            .unwrap();
+        assert_eq!(addr, "10.0.0.2:3334".parse().unwrap());
+        // a host listed alone allows any of its ports
+        let addr = resolve_reconnect(&reconnect("10.0.0.3", 0), current, &allowlist)
+            .await
+            .unwrap();
+        assert_eq!(addr, "10.0.0.3:3333".parse().unwrap());
+
+        for (host, port) in [("10.0.0.2", 3333), ("10.0.0.4", 3334)] {
+            assert!(matches!(
+                resolve_reconnect(&reconnect(host, port), current, &allowlist).await,
+                Err(ReconnectError::NotAllowed(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn ipv6_endpoints_are_compared_as_addresses() {
+        let current: SocketAddr = "[2001:db8::1]:3333".parse().unwrap();
+        let allowlist = vec!["[2001:db8::2]:3334".to_string(), "2001:db8::3".to_string()];
+
+        let addr = resolve_reconnect(&reconnect("2001:db8::2", 3334), current, &allowlist)
+            .await
+            .unwrap();
+        assert_eq!(addr, "[2001:db8::2]:3334".parse().unwrap());
+        // the same address written differently, and in brackets
+        let addr = resolve_reconnect(&reconnect("[2001:0db8::0003]", 0), current, &allowlist)
+            .await
+            .unwrap();
+        assert_eq!(addr, "[2001:db8::3]:3333".parse().unwrap());
+
+        assert!(matches!(
+            resolve_reconnect(&reconnect("2001:db8::2", 3333), current, &allowlist).await,
+            Err(ReconnectError::NotAllowed(endpoint)) if endpoint == "[2001:db8::2]:3333"
+        ));
+    }
+
+    #[tokio::test]
+    async fn empty_host_and_port_keep_the_current_endpoint() {
+        let current: SocketAddr = "10.0.0.1:3333".parse().unwrap();
+
+        let addr = resolve_reconnect(&reconnect("", 0), current, &[])
+            .await
+            .unwrap();
+        assert_eq!(addr, current);
+        assert!(matches!(
+            resolve_reconnect(&reconnect("", 3334), current, &[]).await,
+            Err(ReconnectError::NotAllowed(endpoint)) if endpoint == "10.0.0.1:3334"
+        ));
+    }
+}
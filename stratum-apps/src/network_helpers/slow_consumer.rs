@@ -0,0 +1,118 @@
+//! Detection of downstreams that cannot keep up with the messages sent to them.
+//!
+//! The messages sent to a downstream wait in its outbound queue until they are written to the
+//! connection. A downstream whose queue stays above the threshold of the [`SlowConsumerPolicy`]
+//! for the configured duration is flagged as a slow consumer by its [`SlowConsumerDetector`],
+//! until the queue drains back to the threshold.
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::custom_mutex::Mutex;
+
+/// Interval at which applications sample the outbound queue of their downstreams.
+pub const SLOW_CONSUMER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When a downstream is flagged as a slow consumer, and what to do about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlowConsumerPolicy {
+    /// Number of queued messages above which the downstream is falling behind.
+    pub queue_threshold: usize,
+    /// How long the queue must stay above `queue_threshold` for the downstream to be flagged.
+    pub duration: Duration,
+    /// Whether a flagged downstream is disconnected instead of only being reported.
+    pub disconnect: bool,
+}
+
+/// Tracks how long the outbound queue of a single downstream has been above the threshold.
+#[derive(Debug)]
+pub struct SlowConsumerDetector {
+    above_threshold_since: Mutex<Option<Instant>>,
+    slow_consumer: AtomicBool,
+}
+
+impl Default for SlowConsumerDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SlowConsumerDetector {
+    /// Creates a detector for a downstream that is not falling behind.
+    pub fn new() -> Self {
+        Self {
+            above_threshold_since: Mutex::new(None),
+            slow_consumer: AtomicBool::new(false),
+        }
+    }
+
+    /// Records a sample of the outbound queue depth taken at `now`.
+    ///
+    /// Returns `true` when this sample flags the downstream as a slow consumer, and only for the
+    /// first sample after it fell behind.
+    pub fn observe(&self, queue_depth: usize, policy: &SlowConsumerPolicy, now: Instant) -> bool {
+        let slow = self.above_threshold_since.super_safe_lock(|since| {
+            if queue_depth <= policy.queue_threshold {
+                *since = None;
+                return false;
+            }
+            let since = since.get_or_insert(now);
+            now.duration_since(*since) >= policy.duration
+        });
+        let was_slow = self.slow_consumer.swap(slow, Ordering::Relaxed);
+        slow && !was_slow
+    }
+
+    /// Returns whether the downstream is currently flagged as a slow consumer.
+    pub fn is_slow_consumer(&self) -> bool {
+        self.slow_consumer.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> SlowConsumerPolicy {
+        SlowConsumerPolicy {
+            queue_threshold: 10,
+            duration: Duration::from_secs(5),
+            disconnect: false,
+        }
+    }
+
+    #[test]
+    fn flags_queue_above_threshold_for_duration() {
+        let detector = SlowConsumerDetector::default();
+        let start = Instant::now();
+
+        assert!(!detector.observe(11, &policy(), start));
+        assert!(!detector.observe(50, &policy(), start + Duration::from_secs(4)));
+        assert!(!detector.is_slow_consumer());
+
+        assert!(detector.observe(50, &policy(), start + Duration::from_secs(5)));
+        assert!(detector.is_slow_consumer());
+        // only the first sample past the duration reports the downstream
+        assert!(!detector.observe(50, &policy(), start + Duration::from_secs(6)));
+        assert!(detector.is_slow_consumer());
+    }
+
+    #[test]
+    fn draining_queue_clears_the_flag() {
+        let detector = SlowConsumerDetector::default();
+        let start = Instant::now();
+
+        detector.observe(11, &policy(), start);
+        detector.observe(11, &policy(), start + Duration::from_secs(5));
+        assert!(detector.is_slow_consumer());
+
+        assert!(!detector.observe(10, &policy(), start + Duration::from_secs(6)));
+        assert!(!detector.is_slow_consumer());
+        // the duration starts over once the queue grows again
+        assert!(!detector.observe(11, &policy(), start + Duration::from_secs(7)));
+        assert!(!detector.observe(11, &policy(), start + Duration::from_secs(11)));
+        assert!(detector.observe(11, &policy(), start + Duration::from_secs(12)));
+    }
+}
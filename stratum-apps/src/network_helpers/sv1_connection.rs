@@ -1,19 +1,32 @@
 use async_channel::{unbounded, Receiver, Sender};
 use futures::StreamExt;
+use std::{collections::VecDeque, sync::Arc};
 use stratum_core::sv1_api::json_rpc;
 use tokio::{
     io::{AsyncWriteExt, BufReader, BufWriter},
     net::TcpStream,
+    sync::Notify,
 };
-use tokio_util::codec::{FramedRead, LinesCodec};
+use tokio_util::codec::{FramedRead, LinesCodec, LinesCodecError};
 use tracing::{error, trace, warn};
 
+use crate::custom_mutex::Mutex;
+
 /// Represents a connection between two roles communicating using SV1 protocol.
 ///
 /// This struct can be used to read and write messages to the other side of the connection.  The
 /// channel is unidirectional, i.e., each [`ConnectionSV1`] instance handles the connection either
 /// from the upstream perspective or the downstream perspective. In order to communicate in both
 /// directions, you will need two instances of this struct.
+///
+/// JSON-RPC batches (arrays) received from the other side are split into their elements, which
+/// are delivered in order, and the responses to them are sent back as a single batch response as
+/// soon as the last one is sent. Sending any other message while a batch is still waiting fails
+/// its unanswered requests, so the batch response always goes out first and nothing is held back.
+///
+/// Messages are newline-delimited. A line split across several reads is buffered until its
+/// newline arrives, and each message is written out in full before the next one. A line longer
+/// than [`MAX_LINE_LENGTH`] bytes closes the connection.
 #[derive(Debug)]
 pub struct ConnectionSV1 {
     receiver: Receiver<json_rpc::Message>,
@@ -50,7 +63,105 @@ impl ConnectionState {
     }
 }
 
-const MAX_LINE_LENGTH: usize = 1 << 16;
+/// Longest line, in bytes and without its newline, accepted from the other side.
+pub const MAX_LINE_LENGTH: usize = 1 << 16;
+
+/// JSON-RPC error code for an element that is not a valid request object.
+const INVALID_REQUEST_CODE: i64 = -32600;
+
+/// JSON-RPC error code for a batched request left unanswered when something else is sent.
+const UNANSWERED_REQUEST_CODE: i64 = -32603;
+
+/// A JSON-RPC batch received from the other side whose responses are still being collected.
+struct PendingBatch {
+    /// One slot per batch element expecting a response, in element order. Requests carry their
+    /// id, malformed elements are filled with an error response up front.
+    slots: Vec<(Option<u64>, Option<serde_json::Value>)>,
+}
+
+impl PendingBatch {
+    fn is_complete(&self) -> bool {
+        self.slots.iter().all(|(_, response)| response.is_some())
+    }
+
+    /// Fills every unanswered slot with an error response.
+    fn fail_unanswered(&mut self) {
+        for (id, response) in self.slots.iter_mut().filter(|(_, r)| r.is_none()) {
+            *response = Some(serde_json::json!({
+                "id": id,
+                "result": null,
+                "error": [UNANSWERED_REQUEST_CODE, "Request not answered", null],
+            }));
+        }
+    }
+
+    fn into_response(self) -> serde_json::Value {
+        serde_json::Value::Array(
+            self.slots
+                .into_iter()
+                .filter_map(|(_, response)| response)
+                .collect(),
+        )
+    }
+}
+
+/// Tracks the JSON-RPC batches received by the reader so the writer can answer each of them with
+/// a single batch response.
+#[derive(Default)]
+struct BatchTracker {
+    pending: Mutex<VecDeque<PendingBatch>>,
+    ready: Notify,
+}
+
+impl BatchTracker {
+    fn push(&self, batch: PendingBatch) {
+        self.pending
+            .super_safe_lock(|pending| pending.push_back(batch));
+        self.ready.notify_one();
+    }
+
+    /// Stores `msg` in the pending batch waiting for it. Returns the message back if it is not
+    /// a response to a batched request.
+    fn absorb(&self, msg: json_rpc::Message) -> Option<json_rpc::Message> {
+        let id = match &msg {
+            json_rpc::Message::OkResponse(response)
+            | json_rpc::Message::ErrorResponse(response) => response.id,
+            _ => return Some(msg),
+        };
+        let Ok(value) = serde_json::to_value(&msg) else {
+            return Some(msg);
+        };
+        let absorbed = self.pending.super_safe_lock(|pending| {
+            let slot = pending.iter_mut().find_map(|batch| {
+                batch
+                    .slots
+                    .iter_mut()
+                    .find(|(slot_id, response)| *slot_id == Some(id) && response.is_none())
+            });
+            slot.map(|(_, response)| *response = Some(value)).is_some()
+        });
+        (!absorbed).then_some(msg)
+    }
+
+    /// Removes the batches at the front of the queue that are complete, preserving the order in
+    /// which they were received. With `fail_unanswered`, every pending batch is completed first
+    /// by failing the requests still unanswered.
+    fn take_ready(&self, fail_unanswered: bool) -> Vec<PendingBatch> {
+        self.pending.super_safe_lock(|pending| {
+            if fail_unanswered {
+                for batch in pending.iter_mut().filter(|batch| !batch.is_complete()) {
+                    warn!("Sending a message before a batch was fully answered, failing the rest");
+                    batch.fail_unanswered();
+                }
+            }
+            let mut ready = Vec::new();
+            while pending.front().is_some_and(PendingBatch::is_complete) {
+                ready.extend(pending.pop_front());
+            }
+            ready
+        })
+    }
+}
 
 impl ConnectionSV1 {
     pub async fn new(stream: TcpStream) -> Self {
@@ -67,14 +178,15 @@ impl ConnectionSV1 {
             receiver_incoming.clone(),
             sender_incoming.clone(),
         );
+        let batches = Arc::new(BatchTracker::default());
 
         tokio::spawn(async move {
             tokio::select! {
-                _ = Self::run_reader(buffer_read_half, sender_incoming.clone()) => {
+                _ = Self::run_reader(buffer_read_half, sender_incoming.clone(), batches.clone()) => {
                     trace!("Reader task exited. Closing writer sender.");
                     connection_state.close();
                 }
-                _ = Self::run_writer(buffer_write_half, receiver_outgoing.clone()) => {
+                _ = Self::run_writer(buffer_write_half, receiver_outgoing.clone(), batches) => {
                     trace!("Writer task exited. Closing reader sender.");
                     connection_state.close();
                 }
@@ -90,10 +202,27 @@ impl ConnectionSV1 {
     async fn run_reader(
         reader: BufReader<tokio::net::tcp::OwnedReadHalf>,
         sender: Sender<json_rpc::Message>,
+        batches: Arc<BatchTracker>,
     ) {
         let mut lines = FramedRead::new(reader, LinesCodec::new_with_max_length(MAX_LINE_LENGTH));
         while let Some(result) = lines.next().await {
             match result {
+                Ok(line) if line.trim_start().starts_with('[') => {
+                    let elements = match serde_json::from_str::<Vec<serde_json::Value>>(&line) {
+                        Ok(elements) => elements,
+                        Err(e) => {
+                            error!("Failed to deserialize batch: {e:?}");
+                            continue;
+                        }
+                    };
+                    let messages = Self::register_batch(elements, &batches);
+                    for msg in messages {
+                        if sender.send(msg).await.is_err() {
+                            warn!("Receiver dropped, stopping reader");
+                            return;
+                        }
+                    }
+                }
                 Ok(line) => match serde_json::from_str::<json_rpc::Message>(&line) {
                     Ok(msg) => {
                         if sender.send(msg).await.is_err() {
@@ -105,6 +234,13 @@ impl ConnectionSV1 {
                         error!("Failed to deserialize message: {e:?}");
                     }
                 },
+                Err(LinesCodecError::MaxLineLengthExceeded) => {
+                    // The reader yields nothing after a decoding error, so the connection ends
+                    warn!(
+                        "Received a line longer than {MAX_LINE_LENGTH} bytes, closing connection"
+                    );
+                    break;
+                }
                 Err(e) => {
                     error!("Error reading from stream: {e:?}");
                     break;
@@ -113,27 +249,101 @@ impl ConnectionSV1 {
         }
     }
 
+    /// Parses the elements of a JSON-RPC batch and registers the batch so its responses are sent
+    /// back together. Malformed elements get an invalid request error in their slot instead of
+    /// dropping the whole batch. Returns the valid messages in element order.
+    fn register_batch(
+        elements: Vec<serde_json::Value>,
+        batches: &BatchTracker,
+    ) -> Vec<json_rpc::Message> {
+        let mut messages = Vec::with_capacity(elements.len());
+        let mut slots = Vec::new();
+        if elements.is_empty() {
+            slots.push((None, Some(Self::invalid_request(serde_json::Value::Null))));
+        }
+        for element in elements {
+            match serde_json::from_value::<json_rpc::Message>(element.clone()) {
+                Ok(msg) => {
+                    if let json_rpc::Message::StandardRequest(request) = &msg {
+                        slots.push((Some(request.id), None));
+                    }
+                    messages.push(msg);
+                }
+                Err(e) => {
+                    error!("Failed to deserialize batch element: {e:?}");
+                    let id = element
+                        .get("id")
+                        .cloned()
+                        .unwrap_or(serde_json::Value::Null);
+                    slots.push((None, Some(Self::invalid_request(id))));
+                }
+            }
+        }
+        if !slots.is_empty() {
+            batches.push(PendingBatch { slots });
+        }
+        messages
+    }
+
+    fn invalid_request(id: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "result": null,
+            "error": [INVALID_REQUEST_CODE, "Invalid request", null],
+        })
+    }
+
     async fn run_writer(
         mut writer: BufWriter<tokio::net::tcp::OwnedWriteHalf>,
         receiver: Receiver<json_rpc::Message>,
+        batches: Arc<BatchTracker>,
     ) {
-        while let Ok(msg) = receiver.recv().await {
-            match serde_json::to_string(&msg) {
-                Ok(line) => {
-                    let data = format!("{line}\n");
-                    if writer.write_all(data.as_bytes()).await.is_err() {
-                        error!("Failed to write to stream");
+        loop {
+            // A message that is not a response to a batched request means the batches still
+            // waiting are not getting the rest of their responses before it
+            let msg = tokio::select! {
+                msg = receiver.recv() => {
+                    let Ok(msg) = msg else {
                         break;
-                    }
-                    if writer.flush().await.is_err() {
-                        error!("Failed to flush writer.");
-                        break;
-                    }
+                    };
+                    batches.absorb(msg)
                 }
-                Err(e) => {
-                    error!("Failed to serialize message: {e:?}");
-                    break;
+                _ = batches.ready.notified() => None,
+            };
+
+            for batch in batches.take_ready(msg.is_some()) {
+                if !Self::write_line(&mut writer, &batch.into_response()).await {
+                    return;
+                }
+            }
+            if let Some(msg) = msg {
+                if !Self::write_line(&mut writer, &msg).await {
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn write_line<T: serde::Serialize>(
+        writer: &mut BufWriter<tokio::net::tcp::OwnedWriteHalf>,
+        msg: &T,
+    ) -> bool {
+        match serde_json::to_string(msg) {
+            Ok(line) => {
+                let data = format!("{line}\n");
+                if writer.write_all(data.as_bytes()).await.is_err() {
+                    error!("Failed to write to stream");
+                    return false;
+                }
+                if writer.flush().await.is_err() {
+                    error!("Failed to flush writer.");
+                    return false;
                 }
+                true
+            }
+            Err(e) => {
+                error!("Failed to serialize message: {e:?}");
+                false
             }
         }
     }
@@ -161,7 +371,8 @@ impl ConnectionSV1 {
 
 #[cfg(test)]
 mod tests {
-    use tokio::net::TcpListener;
+    use std::time::Duration;
+    use tokio::{io::AsyncBufReadExt, net::TcpListener};
 
     use super::*;
 
@@ -211,4 +422,167 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn test_sv1_connection_batch() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let miner_stream = TcpStream::connect(addr).await.unwrap();
+        let (server_stream, _) = listener.accept().await.unwrap();
+
+        let server_connection = ConnectionSV1::new(server_stream).await;
+        let (miner_read, mut miner_write) = miner_stream.into_split();
+        let mut miner_lines = BufReader::new(miner_read).lines();
+
+        let batch = r#"[{"id":1,"method":"mining.subscribe","params":["miner/1.0"]},"garbage",{"id":2,"method":"mining.authorize","params":["user","password"]}]"#;
+        miner_write
+            .write_all(format!("{batch}\n").as_bytes())
+            .await
+            .unwrap();
+
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            match server_connection.receive().await.unwrap() {
+                json_rpc::Message::StandardRequest(request) => {
+                    received.push((request.id, request.method))
+                }
+                _ => panic!("Unexpected message type"),
+            }
+        }
+        assert_eq!(
+            received,
+            vec![
+                (1, "mining.subscribe".to_string()),
+                (2, "mining.authorize".to_string())
+            ]
+        );
+
+        // Answer out of order: the batch response must follow the element order
+        for id in [2, 1] {
+            assert!(server_connection.send(ok_response(id)).await);
+        }
+
+        let line = miner_lines.next_line().await.unwrap().unwrap();
+        let response: Vec<serde_json::Value> = serde_json::from_str(&line).unwrap();
+        assert_eq!(response.len(), 3);
+        assert_eq!(response[0]["id"], 1);
+        assert_eq!(response[0]["result"], true);
+        assert_eq!(response[1]["id"], serde_json::Value::Null);
+        assert_eq!(response[1]["error"][0], INVALID_REQUEST_CODE);
+        assert_eq!(response[2]["id"], 2);
+        assert_eq!(response[2]["result"], true);
+    }
+
+    #[tokio::test]
+    async fn test_sv1_connection_batch_fails_unanswered_requests() {
+        let (server_connection, miner_stream) = connect_raw_miner().await;
+        let (miner_read, mut miner_write) = miner_stream.into_split();
+        let mut miner_lines = BufReader::new(miner_read).lines();
+
+        let batch = r#"[{"id":1,"method":"mining.subscribe","params":["miner/1.0"]},{"id":2,"method":"mining.authorize","params":["user","password"]}]"#;
+        miner_write
+            .write_all(format!("{batch}\n").as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(request_id(server_connection.receive().await.unwrap()), 1);
+        assert_eq!(request_id(server_connection.receive().await.unwrap()), 2);
+
+        // Only the subscribe is answered before a notification goes out: the batch response is
+        // sent right away with the authorize failed, followed by the notification.
+        assert!(server_connection.send(ok_response(1)).await);
+        assert!(
+            server_connection
+                .send(json_rpc::Message::Notification(json_rpc::Notification {
+                    method: "mining.set_difficulty".to_string(),
+                    params: serde_json::json!([1]),
+                }))
+                .await
+        );
+
+        let line = tokio::time::timeout(Duration::from_secs(1), miner_lines.next_line())
+            .await
+            .expect("batch response was held back")
+            .unwrap()
+            .unwrap();
+        let response: Vec<serde_json::Value> = serde_json::from_str(&line).unwrap();
+        assert_eq!(response.len(), 2);
+        assert_eq!(response[0]["id"], 1);
+        assert_eq!(response[0]["result"], true);
+        assert_eq!(response[1]["id"], 2);
+        assert_eq!(response[1]["error"][0], UNANSWERED_REQUEST_CODE);
+
+        let line = miner_lines.next_line().await.unwrap().unwrap();
+        let notification: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(notification["method"], "mining.set_difficulty");
+    }
+
+    fn ok_response(id: u64) -> json_rpc::Message {
+        json_rpc::Message::OkResponse(json_rpc::Response {
+            id,
+            result: serde_json::Value::Bool(true),
+            error: None,
+        })
+    }
+
+    async fn connect_raw_miner() -> (ConnectionSV1, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let miner_stream = TcpStream::connect(addr).await.unwrap();
+        miner_stream.set_nodelay(true).unwrap();
+        let (server_stream, _) = listener.accept().await.unwrap();
+        (ConnectionSV1::new(server_stream).await, miner_stream)
+    }
+
+    fn request_id(msg: json_rpc::Message) -> u64 {
+        match msg {
+            json_rpc::Message::StandardRequest(request) => request.id,
+            _ => panic!("Unexpected message type"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sv1_connection_fragmented_lines() {
+        let (server_connection, mut miner_stream) = connect_raw_miner().await;
+
+        let data = concat!(
+            r#"{"id":1,"method":"mining.subscribe","params":["miner/1.0"]}"#,
+            "\n",
+            r#"{"id":2,"method":"mining.authorize","params":["user","password"]}"#,
+            "\r\n",
+        );
+        // The first chunk ends inside the first message, the second one spans both messages
+        for chunk in [&data[..10], &data[10..70], &data[70..]] {
+            for bytes in chunk.as_bytes().chunks(7) {
+                miner_stream.write_all(bytes).await.unwrap();
+                miner_stream.flush().await.unwrap();
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+
+        assert_eq!(request_id(server_connection.receive().await.unwrap()), 1);
+        assert_eq!(request_id(server_connection.receive().await.unwrap()), 2);
+    }
+
+    #[tokio::test]
+    async fn test_sv1_connection_rejects_oversized_line() {
+        let (server_connection, mut miner_stream) = connect_raw_miner().await;
+
+        let request = r#"{"id":1,"method":"mining.subscribe","params":["miner/1.0"]}"#;
+        miner_stream
+            .write_all(format!("{request}\n").as_bytes())
+            .await
+            .unwrap();
+        let oversized = format!(
+            r#"{{"id":2,"method":"mining.subscribe","params":["{}"]}}"#,
+            "a".repeat(MAX_LINE_LENGTH)
+        );
+        miner_stream
+            .write_all(format!("{oversized}\n{request}\n").as_bytes())
+            .await
+            .unwrap();
+
+        assert_eq!(request_id(server_connection.receive().await.unwrap()), 1);
+        // The connection is closed instead of delivering anything after the oversized line
+        assert!(server_connection.receive().await.is_none());
+    }
 }
@@ -0,0 +1,76 @@
+//! Readiness of an app, for embedders and orchestration scripts waiting until it is serving.
+//!
+//! An app marks its [`ReadySignal`] ready once it is fully operational, and the futures returned
+//! by [`ReadySignal::wait`] resolve from then on. A signal never goes back to not ready.
+
+use std::{future::Future, sync::Arc};
+
+use tokio::sync::watch;
+
+/// Whether an app is fully operational. Clones share the same signal.
+#[derive(Debug, Clone)]
+pub struct ReadySignal(Arc<watch::Sender<bool>>);
+
+impl Default for ReadySignal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReadySignal {
+    /// Creates a signal that is not ready yet.
+    pub fn new() -> Self {
+        Self(Arc::new(watch::Sender::new(false)))
+    }
+
+    /// Marks the app as ready, resolving the pending and future [`Self::wait`] futures.
+    pub fn set_ready(&self) {
+        self.0.send_replace(true);
+    }
+
+    /// Returns whether the app is ready.
+    pub fn is_ready(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Returns a future resolving once the app is ready.
+    ///
+    /// The future does not borrow the signal. If every handle to the signal is dropped before it
+    /// is ready, the future never resolves.
+    pub fn wait(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut ready = self.0.subscribe();
+        async move {
+            if ready.wait_for(|ready| *ready).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn wait_resolves_once_ready() {
+        let signal = ReadySignal::new();
+        let wait = tokio::spawn(signal.wait());
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!wait.is_finished());
+        assert!(!signal.is_ready());
+
+        signal.clone().set_ready();
+        tokio::time::timeout(Duration::from_secs(1), wait)
+            .await
+            .unwrap()
+            .unwrap();
+        // waiting after the app became ready resolves right away
+        tokio::time::timeout(Duration::from_secs(1), signal.wait())
+            .await
+            .unwrap();
+        assert!(signal.is_ready());
+    }
+}
@@ -1,13 +1,26 @@
-use std::sync::Mutex as StdMutex;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Mutex as StdMutex,
+};
 use tokio::task::JoinHandle;
+use tracing::warn;
 
 /// Manages a collection of spawned tokio tasks.
 ///
 /// This struct provides a centralized way to spawn, track, and manage the lifecycle
 /// of async tasks in the translator. It maintains a list of join handles that can
 /// be used to wait for all tasks to complete or abort them during shutdown.
+///
+/// An optional soft cap on the number of running tasks can be set with
+/// [`TaskManager::with_max_tasks`]: tasks are still spawned past the cap, but a warning is
+/// logged, which helps spotting task leaks before they exhaust the process.
 pub struct TaskManager {
     tasks: StdMutex<Vec<JoinHandle<()>>>,
+    max_tasks: Option<usize>,
+    spawned_total: AtomicU64,
+    cap_exceeded_total: AtomicU64,
+    // Set while the cap is exceeded, so the warning is logged once per crossing
+    over_cap: AtomicBool,
 }
 
 impl Default for TaskManager {
@@ -23,9 +36,22 @@ impl TaskManager {
     pub fn new() -> Self {
         Self {
             tasks: StdMutex::new(Vec::new()),
+            max_tasks: None,
+            spawned_total: AtomicU64::new(0),
+            cap_exceeded_total: AtomicU64::new(0),
+            over_cap: AtomicBool::new(false),
         }
     }
 
+    /// Sets a soft cap on the number of running tasks.
+    ///
+    /// Spawning past the cap is still allowed, but logs a warning the first time the cap is
+    /// exceeded after being respected.
+    pub fn with_max_tasks(mut self, max_tasks: Option<usize>) -> Self {
+        self.max_tasks = max_tasks;
+        self
+    }
+
     /// Spawns a new async task and adds it to the managed collection.
     ///
     /// The task will be tracked by this manager and can be waited for or aborted
@@ -48,7 +74,48 @@ impl TaskManager {
         );
 
         let handle = tokio::spawn(fut.instrument(span));
-        self.tasks.lock().unwrap().push(handle);
+        let active = {
+            let mut tasks = self.tasks.lock().unwrap();
+            // Forget finished tasks, so the list does not grow with the app uptime
+            tasks.retain(|task| !task.is_finished());
+            tasks.push(handle);
+            tasks.len()
+        };
+        self.spawned_total.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(max_tasks) = self.max_tasks {
+            if active > max_tasks {
+                self.cap_exceeded_total.fetch_add(1, Ordering::Relaxed);
+                if !self.over_cap.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        "{} tasks running, above the soft cap of {} (spawned from {})",
+                        active, max_tasks, location
+                    );
+                }
+            } else {
+                self.over_cap.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Returns the number of tracked tasks that have not finished yet.
+    pub fn active_tasks(&self) -> usize {
+        self.tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|task| !task.is_finished())
+            .count()
+    }
+
+    /// Returns the number of tasks spawned since the manager was created.
+    pub fn spawned_total(&self) -> u64 {
+        self.spawned_total.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of spawns that brought the running tasks above the soft cap.
+    pub fn cap_exceeded_total(&self) -> u64 {
+        self.cap_exceeded_total.load(Ordering::Relaxed)
     }
 
     /// Waits for all managed tasks to complete.
@@ -78,3 +145,66 @@ impl TaskManager {
         }
     }
 }
+
+#[cfg(feature = "monitoring")]
+impl crate::monitoring::TasksMonitoring for TaskManager {
+    fn get_tasks_active(&self) -> u64 {
+        self.active_tasks() as u64
+    }
+
+    fn get_tasks_spawned(&self) -> u64 {
+        self.spawned_total()
+    }
+
+    fn get_tasks_cap_exceeded(&self) -> u64 {
+        self.cap_exceeded_total()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn counts_spawned_tasks_and_flags_the_soft_cap() {
+        let task_manager = TaskManager::new().with_max_tasks(Some(10));
+
+        let mut stops = Vec::new();
+        for _ in 0..25 {
+            let (stop, stopped) = oneshot::channel::<()>();
+            stops.push(stop);
+            task_manager.spawn(async move {
+                let _ = stopped.await;
+            });
+        }
+        assert_eq!(task_manager.spawned_total(), 25);
+        assert_eq!(task_manager.active_tasks(), 25);
+        // Every spawn after the 10th one went above the cap
+        assert_eq!(task_manager.cap_exceeded_total(), 15);
+
+        drop(stops);
+        task_manager.join_all().await;
+        assert_eq!(task_manager.active_tasks(), 0);
+        assert_eq!(task_manager.spawned_total(), 25);
+    }
+
+    #[tokio::test]
+    async fn finished_tasks_do_not_count_towards_the_soft_cap() {
+        let task_manager = TaskManager::new().with_max_tasks(Some(2));
+
+        for _ in 0..10 {
+            let (done, finished) = oneshot::channel::<()>();
+            task_manager.spawn(async move {
+                let _ = done.send(());
+            });
+            let _ = finished.await;
+            // The task may still be running briefly after its last statement
+            while task_manager.active_tasks() > 0 {
+                tokio::task::yield_now().await;
+            }
+        }
+        assert_eq!(task_manager.spawned_total(), 10);
+        assert_eq!(task_manager.cap_exceeded_total(), 0);
+    }
+}
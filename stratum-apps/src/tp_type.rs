@@ -1,4 +1,5 @@
 use crate::{config_helpers::opt_path_from_toml, key_utils::Secp256k1PublicKey};
+use miniscript::bitcoin::Network;
 use std::path::PathBuf;
 
 /// Bitcoin network for determining node.sock location
@@ -22,6 +23,17 @@ impl BitcoinNetwork {
             BitcoinNetwork::Regtest => Some("regtest"),
         }
     }
+
+    /// Returns the network addresses are validated against. Testnet4 shares the address format
+    /// of the other test networks.
+    pub fn network(&self) -> Network {
+        match self {
+            BitcoinNetwork::Mainnet => Network::Bitcoin,
+            BitcoinNetwork::Testnet4 => Network::Testnet,
+            BitcoinNetwork::Signet => Network::Signet,
+            BitcoinNetwork::Regtest => Network::Regtest,
+        }
+    }
 }
 
 /// Returns the default Bitcoin Core data directory for the current OS.
@@ -75,6 +87,16 @@ pub enum TemplateProviderType {
     },
 }
 
+impl TemplateProviderType {
+    /// Returns the network mined on, only known with Bitcoin Core IPC.
+    pub fn network(&self) -> Option<Network> {
+        match self {
+            TemplateProviderType::BitcoinCoreIpc { network, .. } => Some(network.network()),
+            TemplateProviderType::Sv2Tp { .. } => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
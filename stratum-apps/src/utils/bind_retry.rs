@@ -0,0 +1,93 @@
+//! Retrying of listener binds while their address is still in use.
+//!
+//! When an app restarts quickly, the listening socket of the previous process can hold its
+//! address for a moment after it exited, and binding it fails with "address in use". Tokio
+//! listeners set `SO_REUSEADDR`, so sockets left in `TIME_WAIT` don't get in the way, but a socket
+//! still being torn down does. With a [`BindRetry`], the bind is tried again a few times before
+//! startup fails.
+
+use std::{io, net::SocketAddr, time::Duration};
+
+use tokio::net::TcpListener;
+use tracing::warn;
+
+/// How many times, and how far apart, a bind failing with "address in use" is retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BindRetry {
+    /// Number of binds tried after the first one failed.
+    pub retries: u32,
+    /// Delay between two binds.
+    pub delay: Duration,
+}
+
+impl BindRetry {
+    /// Fails on the first bind failing.
+    pub const NONE: Self = Self {
+        retries: 0,
+        delay: Duration::ZERO,
+    };
+}
+
+impl Default for BindRetry {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Binds a TCP listener to `address`, retrying as set by `retry` while the address is in use.
+///
+/// Any other error, or the address still being in use after the last retry, is returned.
+pub async fn bind_with_retry(address: SocketAddr, retry: BindRetry) -> io::Result<TcpListener> {
+    let mut retries_left = retry.retries;
+    loop {
+        match TcpListener::bind(address).await {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && retries_left > 0 => {
+                warn!(
+                    "Address {address} in use, retrying in {:?} ({retries_left} left)",
+                    retry.delay
+                );
+                retries_left -= 1;
+                tokio::time::sleep(retry.delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn binds_once_the_address_is_released() {
+        let held = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = held.local_addr().unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            drop(held);
+        });
+
+        let retry = BindRetry {
+            retries: 20,
+            delay: Duration::from_millis(50),
+        };
+        let listener = bind_with_retry(address, retry).await.unwrap();
+        assert_eq!(listener.local_addr().unwrap(), address);
+    }
+
+    #[tokio::test]
+    async fn gives_up_once_the_retries_are_exhausted() {
+        let held = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = held.local_addr().unwrap();
+
+        let err = bind_with_retry(address, BindRetry::NONE).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        let retry = BindRetry {
+            retries: 2,
+            delay: Duration::from_millis(10),
+        };
+        let err = bind_with_retry(address, retry).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    }
+}
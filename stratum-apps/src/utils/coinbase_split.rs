@@ -0,0 +1,192 @@
+//! Validation of the split of a coinbase transaction around its extranonce.
+//!
+//! Extended jobs carry the coinbase transaction as `coinbase_tx_prefix` and `coinbase_tx_suffix`,
+//! and the extranonce of the channel is inserted between them. The extranonce has to land inside
+//! the scriptSig of the coinbase input: a prefix cut at the wrong place, or an extranonce of
+//! another size than the one reserved in the scriptSig, yields a coinbase that no longer
+//! deserializes as the transaction the upstream built, and every share on the job is invalid.
+
+use std::fmt;
+
+// version (4 bytes), then the marker and flag of a transaction serialized with its witness
+const VERSION_SIZE: usize = 4;
+const SEGWIT_MARKER_AND_FLAG: [u8; 2] = [0x00, 0x01];
+const PREVIOUS_OUTPUT_SIZE: usize = 36;
+const SEQUENCE_SIZE: usize = 4;
+
+/// Reason the split of a coinbase transaction does not match its extranonce.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CoinbaseSplitError {
+    /// The prefix does not hold the coinbase input up to the start of its scriptSig.
+    MalformedPrefix,
+    /// The extranonce, starting where the prefix ends, does not fit in the scriptSig (scriptSig
+    /// length, end of the extranonce within the scriptSig).
+    ExtranonceOutsideScriptSig {
+        script_sig_len: usize,
+        extranonce_end: usize,
+    },
+    /// The suffix is too short to hold the rest of the scriptSig and the sequence of the input
+    /// (expected minimum length, actual length).
+    SuffixTooShort { expected: usize, actual: usize },
+}
+
+impl fmt::Display for CoinbaseSplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoinbaseSplitError::MalformedPrefix => {
+                write!(f, "coinbase prefix does not end inside the scriptSig")
+            }
+            CoinbaseSplitError::ExtranonceOutsideScriptSig {
+                script_sig_len,
+                extranonce_end,
+            } => write!(
+                f,
+                "extranonce ends at byte {extranonce_end} of a {script_sig_len} bytes scriptSig"
+            ),
+            CoinbaseSplitError::SuffixTooShort { expected, actual } => write!(
+                f,
+                "coinbase suffix is {actual} bytes, at least {expected} expected"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CoinbaseSplitError {}
+
+/// Checks that `prefix` ends inside the scriptSig of the coinbase input, that an extranonce of
+/// `extranonce_size` bytes inserted there fits in the scriptSig, and that `suffix` resumes right
+/// after it with the rest of the scriptSig and the sequence of the input.
+pub fn validate_coinbase_split(
+    prefix: &[u8],
+    suffix: &[u8],
+    extranonce_size: usize,
+) -> Result<(), CoinbaseSplitError> {
+    let mut offset = VERSION_SIZE;
+    if prefix.get(offset..offset + 2) == Some(&SEGWIT_MARKER_AND_FLAG[..]) {
+        offset += 2;
+    }
+    // a coinbase has a single input
+    let (input_count, size) =
+        read_compact_size(prefix.get(offset..)).ok_or(CoinbaseSplitError::MalformedPrefix)?;
+    if input_count != 1 {
+        return Err(CoinbaseSplitError::MalformedPrefix);
+    }
+    offset += size + PREVIOUS_OUTPUT_SIZE;
+    let (script_sig_len, size) =
+        read_compact_size(prefix.get(offset..)).ok_or(CoinbaseSplitError::MalformedPrefix)?;
+    offset += size;
+    let script_sig_len =
+        usize::try_from(script_sig_len).map_err(|_| CoinbaseSplitError::MalformedPrefix)?;
+
+    let before_extranonce = prefix.len() - offset;
+    let extranonce_end = before_extranonce + extranonce_size;
+    if extranonce_end > script_sig_len {
+        return Err(CoinbaseSplitError::ExtranonceOutsideScriptSig {
+            script_sig_len,
+            extranonce_end,
+        });
+    }
+    let expected = script_sig_len - extranonce_end + SEQUENCE_SIZE;
+    if suffix.len() < expected {
+        return Err(CoinbaseSplitError::SuffixTooShort {
+            expected,
+            actual: suffix.len(),
+        });
+    }
+    Ok(())
+}
+
+// Reads a Bitcoin CompactSize, returning its value and the number of bytes it takes. None when
+// `bytes` is too short to hold it.
+fn read_compact_size(bytes: Option<&[u8]>) -> Option<(u64, usize)> {
+    let (&first, rest) = bytes?.split_first()?;
+    let size = match first {
+        0xfd => 2,
+        0xfe => 4,
+        0xff => 8,
+        value => return Some((u64::from(value), 1)),
+    };
+    let value = rest
+        .get(..size)?
+        .iter()
+        .rev()
+        .fold(0, |value, byte| (value << 8) | u64::from(*byte));
+    Some((value, 1 + size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTRANONCE_SIZE: usize = 8;
+
+    // A coinbase whose scriptSig is the BIP34 push of height 840_000 followed by the extranonce,
+    // split around the extranonce.
+    fn split_coinbase() -> (Vec<u8>, Vec<u8>) {
+        let height_push = [0x03, 0x40, 0xd1, 0x0c];
+        let mut prefix = vec![0x02, 0x00, 0x00, 0x00, 0x01];
+        prefix.extend([0x00; 32]);
+        prefix.extend([0xff; 4]);
+        prefix.push((height_push.len() + EXTRANONCE_SIZE) as u8);
+        prefix.extend(height_push);
+
+        let mut suffix = vec![0xff; 4];
+        // a single output paying 3.125 BTC to an empty script, then the locktime
+        suffix.push(0x01);
+        suffix.extend(312_500_000u64.to_le_bytes());
+        suffix.push(0x00);
+        suffix.extend([0x00; 4]);
+        (prefix, suffix)
+    }
+
+    #[test]
+    fn extranonce_placed_in_the_script_sig_is_accepted() {
+        let (prefix, suffix) = split_coinbase();
+        assert_eq!(
+            validate_coinbase_split(&prefix, &suffix, EXTRANONCE_SIZE),
+            Ok(())
+        );
+        // a smaller extranonce leaves the rest of the scriptSig to the suffix
+        assert_eq!(
+            validate_coinbase_split(&prefix, &suffix, EXTRANONCE_SIZE - 4),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn mis_split_coinbase_is_rejected() {
+        let (mut prefix, mut suffix) = split_coinbase();
+
+        // the prefix ends one byte too late, inside the sequence of the input
+        prefix.push(suffix.remove(0));
+        assert_eq!(
+            validate_coinbase_split(&prefix, &suffix, EXTRANONCE_SIZE),
+            Err(CoinbaseSplitError::ExtranonceOutsideScriptSig {
+                script_sig_len: 12,
+                extranonce_end: 13,
+            })
+        );
+
+        // the extranonce is larger than the room reserved for it
+        let (prefix, suffix) = split_coinbase();
+        assert!(matches!(
+            validate_coinbase_split(&prefix, &suffix, EXTRANONCE_SIZE + 1),
+            Err(CoinbaseSplitError::ExtranonceOutsideScriptSig { .. })
+        ));
+
+        // the prefix stops before the scriptSig
+        assert_eq!(
+            validate_coinbase_split(&prefix[..20], &suffix, EXTRANONCE_SIZE),
+            Err(CoinbaseSplitError::MalformedPrefix)
+        );
+
+        // a smaller extranonce leaves scriptSig bytes the suffix does not hold
+        assert_eq!(
+            validate_coinbase_split(&prefix, &suffix[..4], EXTRANONCE_SIZE - 4),
+            Err(CoinbaseSplitError::SuffixTooShort {
+                expected: 8,
+                actual: 4,
+            })
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Throttling of log lines repeated during error storms.
+//!
+//! While an upstream is down, the same warning can be logged for every connection attempt or
+//! every rejected share, burying anything else in the logs. A [`LogThrottle`] lets the first
+//! occurrence of a message through and counts the ones following it within the throttle window.
+//! The next occurrence after the window is logged with a summary of how many were suppressed.
+//! Messages are throttled per key and per code, e.g. the error code of a rejected share, so a
+//! storm of one error does not hide another.
+//!
+//! A burst that stops before the window is over has no next occurrence to report it, so
+//! [`LogThrottle::run`] logs the summaries of the windows that are over on a timer.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+use crate::custom_mutex::Mutex;
+
+/// Number of occurrences of a message suppressed since it was last logged.
+///
+/// Displays as a summary to append to the logged message, or as nothing when none were
+/// suppressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Suppressed(pub u64);
+
+impl fmt::Display for Suppressed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            0 => Ok(()),
+            count => write!(f, " ({count} similar messages suppressed)"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    logged_at: Instant,
+    suppressed: u64,
+}
+
+/// Decides which occurrences of high-frequency log messages are logged.
+#[derive(Debug)]
+pub struct LogThrottle {
+    window: Option<Duration>,
+    entries: Mutex<HashMap<(&'static str, String), Entry>>,
+}
+
+impl LogThrottle {
+    /// Creates a throttle logging each message at most once per `window`. With `None`, every
+    /// occurrence is logged.
+    pub fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns whether the message identified by `key` and `code` is logged now, with the number
+    /// of its occurrences suppressed since it was last logged. `None` when this occurrence is
+    /// suppressed.
+    pub fn admit(&self, key: &'static str, code: &str) -> Option<Suppressed> {
+        self.admit_at(key, code, Instant::now())
+    }
+
+    /// Logs, every window until `shutdown` resolves, the number of occurrences suppressed during
+    /// each window that is over, for the messages that did not occur again since. Returns right
+    /// away without a window.
+    pub async fn run(&self, shutdown: impl Future<Output = ()>) {
+        let Some(window) = self.window else {
+            return;
+        };
+        let mut ticker = tokio::time::interval(window.max(Duration::from_secs(1)));
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                _ = ticker.tick() => {
+                    for (key, code, suppressed) in self.take_summaries_at(Instant::now()) {
+                        warn!("Throttled {key} [{code}]{suppressed}");
+                    }
+                }
+            }
+        }
+    }
+
+    fn admit_at(&self, key: &'static str, code: &str, now: Instant) -> Option<Suppressed> {
+        let Some(window) = self.window else {
+            return Some(Suppressed(0));
+        };
+        self.entries
+            .super_safe_lock(|entries| match entries.get_mut(&(key, code.to_string())) {
+                Some(entry) if now.duration_since(entry.logged_at) < window => {
+                    entry.suppressed += 1;
+                    None
+                }
+                Some(entry) => {
+                    let suppressed = std::mem::take(&mut entry.suppressed);
+                    entry.logged_at = now;
+                    Some(Suppressed(suppressed))
+                }
+                None => {
+                    entries.insert(
+                        (key, code.to_string()),
+                        Entry {
+                            logged_at: now,
+                            suppressed: 0,
+                        },
+                    );
+                    Some(Suppressed(0))
+                }
+            })
+    }
+
+    // Takes the suppressed occurrences of the messages whose window is over, restarting their
+    // window. Messages with none suppressed are forgotten, so codes that stopped occurring do
+    // not pile up.
+    fn take_summaries_at(&self, now: Instant) -> Vec<(&'static str, String, Suppressed)> {
+        let Some(window) = self.window else {
+            return Vec::new();
+        };
+        let mut summaries = Vec::new();
+        self.entries.super_safe_lock(|entries| {
+            entries.retain(|(key, code), entry| {
+                if now.duration_since(entry.logged_at) < window {
+                    return true;
+                }
+                if entry.suppressed == 0 {
+                    return false;
+                }
+                summaries.push((*key, code.clone(), Suppressed(entry.suppressed)));
+                entry.suppressed = 0;
+                entry.logged_at = now;
+                true
+            })
+        });
+        summaries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_messages_are_collapsed_into_a_summary() {
+        let throttle = LogThrottle::new(Some(Duration::from_secs(60)));
+        let start = Instant::now();
+
+        assert_eq!(
+            throttle.admit_at("share", "stale-share", start),
+            Some(Suppressed(0))
+        );
+        for secs in 1..=10 {
+            assert_eq!(
+                throttle.admit_at("share", "stale-share", start + Duration::from_secs(secs)),
+                None
+            );
+        }
+        // other messages, and other codes of the same message, are throttled on their own
+        assert_eq!(
+            throttle.admit_at("share", "invalid-job-id", start + Duration::from_secs(10)),
+            Some(Suppressed(0))
+        );
+        assert_eq!(
+            throttle.admit_at("attempt", "10.0.0.1:3333", start + Duration::from_secs(10)),
+            Some(Suppressed(0))
+        );
+
+        let next = throttle.admit_at("share", "stale-share", start + Duration::from_secs(60));
+        assert_eq!(next, Some(Suppressed(10)));
+        assert_eq!(
+            format!("Share rejected{}", next.unwrap()),
+            "Share rejected (10 similar messages suppressed)"
+        );
+        // the window restarts from the summary
+        assert_eq!(
+            throttle.admit_at("share", "stale-share", start + Duration::from_secs(61)),
+            None
+        );
+    }
+
+    #[test]
+    fn burst_that_stops_is_summarised_once_its_window_is_over() {
+        let throttle = LogThrottle::new(Some(Duration::from_secs(60)));
+        let start = Instant::now();
+
+        for secs in 0..5 {
+            throttle.admit_at("share", "stale-share", start + Duration::from_secs(secs));
+        }
+        throttle.admit_at("attempt", "10.0.0.1:3333", start);
+        assert!(throttle
+            .take_summaries_at(start + Duration::from_secs(30))
+            .is_empty());
+
+        // the burst stopped, nothing else would ever report its suppressed occurrences
+        assert_eq!(
+            throttle.take_summaries_at(start + Duration::from_secs(60)),
+            vec![("share", "stale-share".to_string(), Suppressed(4))]
+        );
+        // reported once, and messages with nothing suppressed are forgotten
+        assert!(throttle
+            .take_summaries_at(start + Duration::from_secs(120))
+            .is_empty());
+        assert!(throttle
+            .entries
+            .super_safe_lock(|entries| entries.is_empty()));
+    }
+
+    #[test]
+    fn every_message_is_logged_without_a_window() {
+        let throttle = LogThrottle::new(None);
+        for _ in 0..3 {
+            assert_eq!(throttle.admit("share", "stale-share"), Some(Suppressed(0)));
+        }
+        assert_eq!(Suppressed(0).to_string(), "");
+    }
+}
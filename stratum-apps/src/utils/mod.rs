@@ -1,2 +1,7 @@
+pub mod bind_retry;
+pub mod coinbase_split;
+pub mod log_throttle;
 pub mod protocol_message_type;
+pub mod status_channel;
 pub mod types;
+pub mod vardiff;
@@ -146,3 +146,64 @@ pub fn protocol_message_type(extension_type: u16, message_type: u8) -> MessageTy
         MessageType::Unknown
     }
 }
+
+/// Returns the name of a message, such as `SubmitSharesExtended`, or `unknown`.
+pub fn message_type_name(extension_type: u16, message_type: u8) -> &'static str {
+    const CHANNEL_MSG_MASK: u16 = 0b1000_0000_0000_0000;
+    let extension_type = extension_type & !CHANNEL_MSG_MASK;
+    if extension_type == EXTENSION_TYPE_EXTENSIONS_NEGOTIATION {
+        return match message_type {
+            MESSAGE_TYPE_REQUEST_EXTENSIONS => "RequestExtensions",
+            MESSAGE_TYPE_REQUEST_EXTENSIONS_SUCCESS => "RequestExtensionsSuccess",
+            MESSAGE_TYPE_REQUEST_EXTENSIONS_ERROR => "RequestExtensionsError",
+            _ => "unknown",
+        };
+    }
+    if extension_type != 0 {
+        return "unknown";
+    }
+    match message_type {
+        MESSAGE_TYPE_SETUP_CONNECTION => "SetupConnection",
+        MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS => "SetupConnectionSuccess",
+        MESSAGE_TYPE_SETUP_CONNECTION_ERROR => "SetupConnectionError",
+        MESSAGE_TYPE_CHANNEL_ENDPOINT_CHANGED => "ChannelEndpointChanged",
+        MESSAGE_TYPE_RECONNECT => "Reconnect",
+        MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL => "OpenStandardMiningChannel",
+        MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL_SUCCESS => "OpenStandardMiningChannelSuccess",
+        MESSAGE_TYPE_OPEN_MINING_CHANNEL_ERROR => "OpenMiningChannelError",
+        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL => "OpenExtendedMiningChannel",
+        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS => "OpenExtendedMiningChannelSuccess",
+        MESSAGE_TYPE_NEW_MINING_JOB => "NewMiningJob",
+        MESSAGE_TYPE_UPDATE_CHANNEL => "UpdateChannel",
+        MESSAGE_TYPE_UPDATE_CHANNEL_ERROR => "UpdateChannelError",
+        MESSAGE_TYPE_CLOSE_CHANNEL => "CloseChannel",
+        MESSAGE_TYPE_SET_EXTRANONCE_PREFIX => "SetExtranoncePrefix",
+        MESSAGE_TYPE_SUBMIT_SHARES_STANDARD => "SubmitSharesStandard",
+        MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED => "SubmitSharesExtended",
+        MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS => "SubmitSharesSuccess",
+        MESSAGE_TYPE_SUBMIT_SHARES_ERROR => "SubmitSharesError",
+        MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB => "NewExtendedMiningJob",
+        MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH => "SetNewPrevHash",
+        MESSAGE_TYPE_SET_TARGET => "SetTarget",
+        MESSAGE_TYPE_SET_CUSTOM_MINING_JOB => "SetCustomMiningJob",
+        MESSAGE_TYPE_SET_CUSTOM_MINING_JOB_SUCCESS => "SetCustomMiningJobSuccess",
+        MESSAGE_TYPE_SET_CUSTOM_MINING_JOB_ERROR => "SetCustomMiningJobError",
+        MESSAGE_TYPE_SET_GROUP_CHANNEL => "SetGroupChannel",
+        MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN => "AllocateMiningJobToken",
+        MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN_SUCCESS => "AllocateMiningJobTokenSuccess",
+        MESSAGE_TYPE_PROVIDE_MISSING_TRANSACTIONS => "ProvideMissingTransactions",
+        MESSAGE_TYPE_PROVIDE_MISSING_TRANSACTIONS_SUCCESS => "ProvideMissingTransactionsSuccess",
+        MESSAGE_TYPE_DECLARE_MINING_JOB => "DeclareMiningJob",
+        MESSAGE_TYPE_DECLARE_MINING_JOB_SUCCESS => "DeclareMiningJobSuccess",
+        MESSAGE_TYPE_DECLARE_MINING_JOB_ERROR => "DeclareMiningJobError",
+        MESSAGE_TYPE_PUSH_SOLUTION => "PushSolution",
+        MESSAGE_TYPE_COINBASE_OUTPUT_CONSTRAINTS => "CoinbaseOutputConstraints",
+        MESSAGE_TYPE_NEW_TEMPLATE => "NewTemplate",
+        MESSAGE_TYPE_SET_NEW_PREV_HASH => "SetNewPrevHashTdp",
+        MESSAGE_TYPE_REQUEST_TRANSACTION_DATA => "RequestTransactionData",
+        MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_SUCCESS => "RequestTransactionDataSuccess",
+        MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_ERROR => "RequestTransactionDataError",
+        MESSAGE_TYPE_SUBMIT_SOLUTION => "SubmitSolution",
+        _ => "unknown",
+    }
+}
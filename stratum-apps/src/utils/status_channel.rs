@@ -0,0 +1,96 @@
+//! Status channel between the subsystems of an app and its main loop.
+//!
+//! Subsystems report to the main loop over an [`async_channel`] of status updates. Once every
+//! sender is dropped, `recv` fails right away on every call, so a main loop selecting on it would
+//! spin. [`next_status`] turns the closed channel into a `None` the main loop shuts down on.
+//!
+//! Status updates are shutdown, disconnect and fallback requests, so none may be lost. Subsystems
+//! report with `send().await`, which waits for room in a bounded channel for as long as the main
+//! loop is alive. A status is only undelivered once the receiver is dropped, and the main loop is
+//! then gone anyway.
+
+use async_channel::Receiver;
+use tracing::error;
+
+/// Waits for the next status update. Returns `None` once every sender of the channel is dropped,
+/// as no subsystem can report anymore.
+pub async fn next_status<T>(status_receiver: &Receiver<T>) -> Option<T> {
+    match status_receiver.recv().await {
+        Ok(status) => Some(status),
+        Err(_) => {
+            error!("Every status sender was dropped, no subsystem can report anymore");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn main_loop_exits_once_every_status_sender_is_dropped() {
+        let (status_sender, status_receiver) = async_channel::unbounded::<u32>();
+        let subsystem = {
+            let status_sender = status_sender.clone();
+            tokio::spawn(async move {
+                status_sender.send(1).await.unwrap();
+            })
+        };
+        drop(status_sender);
+        subsystem.await.unwrap();
+
+        // the main loops of the apps select on the status channel and on a shutdown signal
+        let mut iterations = 0;
+        let main_loop = async {
+            loop {
+                iterations += 1;
+                tokio::select! {
+                    _ = std::future::pending::<()>() => break,
+                    status = next_status(&status_receiver) => {
+                        let Some(status) = status else {
+                            break;
+                        };
+                        assert_eq!(status, 1);
+                    }
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), main_loop)
+            .await
+            .expect("main loop kept running on a closed status channel");
+        // the pending status was handled, then the loop stopped on the closed channel
+        assert_eq!(iterations, 2);
+    }
+
+    #[tokio::test]
+    async fn shutdown_status_is_delivered_to_a_stalled_main_loop() {
+        let (status_sender, status_receiver) = async_channel::bounded::<&str>(1);
+        // the only slot is taken by a status the stalled main loop did not handle yet
+        status_sender.send("fallback").await.unwrap();
+
+        let subsystem = {
+            let status_sender = status_sender.clone();
+            tokio::spawn(async move { status_sender.send("shutdown").await })
+        };
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!subsystem.is_finished());
+
+        // the main loop catches up
+        assert_eq!(next_status(&status_receiver).await, Some("fallback"));
+        let status = tokio::time::timeout(Duration::from_secs(1), next_status(&status_receiver))
+            .await
+            .expect("shutdown status was lost");
+        assert_eq!(status, Some("shutdown"));
+        subsystem.await.unwrap().unwrap();
+
+        // only a dropped receiver makes delivery fail
+        drop(status_receiver);
+        assert_eq!(
+            status_sender.send("shutdown").await,
+            Err(async_channel::SendError("shutdown"))
+        );
+    }
+}
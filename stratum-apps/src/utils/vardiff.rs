@@ -0,0 +1,66 @@
+//! Bounds on the difficulty change made by a single vardiff adjustment.
+//!
+//! A sudden drop in share rate, such as a hashboard failing, makes vardiff bring the difficulty
+//! down to the new rate in one step, and a miner recovering right after floods the server with
+//! easy shares. Clamping every adjustment to a maximum ratio moves the difficulty in steps
+//! instead, converging over a few vardiff cycles.
+
+use super::types::Hashrate;
+
+/// Clamps the hashrate proposed by a vardiff adjustment so that the channel difficulty, which is
+/// proportional to it, is at most multiplied or divided by `max_ratio`.
+///
+/// A `max_ratio` that is not above `1.0` leaves the proposed hashrate untouched.
+pub fn clamp_vardiff_step(current: Hashrate, proposed: Hashrate, max_ratio: f32) -> Hashrate {
+    let bounded = max_ratio > 1.0 && current > 0.0;
+    if !bounded {
+        return proposed;
+    }
+    proposed.clamp(current / max_ratio, current * max_ratio)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs vardiff cycles while the share rate stays at `actual`, returning the hashrate after
+    // each adjustment.
+    fn adjust_towards(mut current: Hashrate, actual: Hashrate, max_ratio: f32) -> Vec<Hashrate> {
+        let mut steps = Vec::new();
+        while current != actual {
+            current = clamp_vardiff_step(current, actual, max_ratio);
+            steps.push(current);
+            assert!(steps.len() <= 100, "vardiff does not converge");
+        }
+        steps
+    }
+
+    #[test]
+    fn sudden_hashrate_drop_descends_in_bounded_steps() {
+        let max_ratio = 2.0;
+        let steps = adjust_towards(100e12, 1e12, max_ratio);
+
+        // 50, 25, 12.5, 6.25, 3.125 and 1.5625 TH/s before reaching 1 TH/s
+        assert_eq!(steps.len(), 7);
+        let mut previous = 100e12;
+        for step in steps {
+            assert!(step < previous);
+            assert!(previous / step <= max_ratio);
+            previous = step;
+        }
+    }
+
+    #[test]
+    fn recovery_rises_in_bounded_steps() {
+        let steps = adjust_towards(1e12, 10e12, 4.0);
+        assert_eq!(steps, vec![4e12, 10e12]);
+    }
+
+    #[test]
+    fn unbounded_ratio_leaves_the_adjustment_untouched() {
+        for max_ratio in [0.0, 1.0, f32::NAN] {
+            assert_eq!(clamp_vardiff_step(100e12, 1e12, max_ratio), 1e12);
+        }
+        assert_eq!(clamp_vardiff_step(0.0, 1e12, 2.0), 1e12);
+    }
+}